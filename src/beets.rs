@@ -0,0 +1,328 @@
+// This module converts between taggu metadata and the item listings used by beets, in the layout produced by `beet export`.
+
+use std::path::{Path, PathBuf};
+
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
+
+use library::{Library, WalkStep};
+use lookup::LookupContext;
+use lookup::options::LookupOptions;
+use metadata::{MetaBlock, MetaValue};
+use writer::plan_edit_item_block;
+use plan::WritePlan;
+use yaml::{yaml_as_meta_block, meta_value_as_yaml};
+use helpers::normalize;
+use progress::Progress;
+use redact::Redaction;
+use error::*;
+
+const BEETS_PATH_FIELD: &str = "path";
+
+/// Separator used to join list values, since most beets fields hold a single string.
+const BEETS_SEQ_SEPARATOR: &str = "; ";
+
+/// Pairs of taggu field names and the beets fields that they correspond to.
+/// Beets fields not listed here are flexible attributes, and keep their names on both sides.
+const BEETS_FIELD_PAIRS: &[(&str, &str)] = &[
+    ("album_artist", "albumartist"),
+    ("date", "year"),
+    ("track_num", "track"),
+    ("disc_num", "disc"),
+    ("replaygain_track_gain", "rg_track_gain"),
+    ("replaygain_track_peak", "rg_track_peak"),
+    ("replaygain_album_gain", "rg_album_gain"),
+    ("replaygain_album_peak", "rg_album_peak"),
+];
+
+/// Fields exported for every item, in addition to the item's own fields.
+const EXPORTED_FIELDS: &[&str] = &[
+    "title",
+    "artist",
+    "album",
+    "album_artist",
+    "date",
+    "genre",
+    "track_num",
+    "disc_num",
+];
+
+/// Beets fields that describe the file or the beets database rather than the music, and are never imported.
+const BEETS_SKIPPED_FIELDS: &[&str] = &[
+    "id",
+    "album_id",
+    "added",
+    "mtime",
+    "format",
+    "bitrate",
+    "bitdepth",
+    "samplerate",
+    "channels",
+    "length",
+    "filesize",
+];
+
+fn taggu_field_name(beets_field_name: &str) -> &str {
+    BEETS_FIELD_PAIRS.iter()
+        .find(|&&(_, b)| b == beets_field_name)
+        .map_or(beets_field_name, |&(t, _)| t)
+}
+
+fn beets_field_name(taggu_field_name: &str) -> &str {
+    BEETS_FIELD_PAIRS.iter()
+        .find(|&&(t, _)| t == taggu_field_name)
+        .map_or(taggu_field_name, |&(_, b)| b)
+}
+
+/// Beets fills in every fixed field, using empty strings and zeros for values that are not set.
+fn is_unset_beets_value(mv: &MetaValue) -> bool {
+    match *mv {
+        MetaValue::Nil => true,
+        MetaValue::Str(ref s) => s.is_empty() || s == "0",
+        _ => false,
+    }
+}
+
+/// A single item read from a beets listing, with fields already renamed to their taggu names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeetsItem {
+    pub item_path: PathBuf,
+    pub fields: MetaBlock,
+}
+
+/// Reads the items in a beets listing, which is a sequence of mappings that each contain a path.
+pub fn read_beets_items(y: &Yaml) -> Result<Vec<BeetsItem>> {
+    let items_y = match *y {
+        Yaml::Array(ref arr) => arr,
+        _ => bail!("beets listing must be a sequence of items"),
+    };
+
+    let mut items = vec![];
+
+    for item_y in items_y {
+        let mut mb = yaml_as_meta_block(item_y).ok_or("beets item must be a mapping of fields")?;
+
+        let item_path = match mb.remove(BEETS_PATH_FIELD) {
+            Some(MetaValue::Str(s)) => PathBuf::from(s),
+            _ => bail!("beets item is missing its path"),
+        };
+
+        let fields = mb.into_iter()
+            .filter(|&(ref k, ref v)| !BEETS_SKIPPED_FIELDS.contains(&k.as_str()) && !is_unset_beets_value(v))
+            .map(|(k, v)| (taggu_field_name(&k).to_string(), v))
+            .collect();
+
+        items.push(BeetsItem { item_path, fields });
+    }
+
+    Ok(items)
+}
+
+/// Plans writing the fields of beets items into the blocks of the matching library items.
+/// Items that are not in the library are skipped.
+/// Returns the paths of the imported items.
+pub fn import_beets_items(media_lib: &Library, items: &[BeetsItem], plan: &mut WritePlan) -> Result<Vec<PathBuf>> {
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    let mut imported = vec![];
+
+    for item in items {
+        let abs_item_path = match media_lib.safe_sub_path(&item.item_path) {
+            Ok(ref p) if p.exists() => p.clone(),
+            _ => {
+                warn!("skipping beets item that is not in the library: '{}'", item.item_path.to_string_lossy());
+                continue;
+            },
+        };
+
+        let fields = item.fields.clone();
+        plan_edit_item_block(media_lib, plan, &abs_item_path, |mb| mb.extend(fields))?;
+
+        imported.push(abs_item_path);
+    }
+
+    Ok(imported)
+}
+
+/// Describes a single item as a beets listing entry.
+/// The common fields include values inherited from ancestors; any other fields of the item itself are exported as flexible attributes.
+/// Redactions apply by taggu field name, and never to the path.
+fn beets_item_yaml(lookup_ctx: &mut LookupContext, abs_item_path: &Path, redaction: &Redaction) -> Result<Yaml> {
+    let mut hsh = Hash::new();
+    hsh.insert(Yaml::String(BEETS_PATH_FIELD.to_string()), Yaml::String(abs_item_path.to_string_lossy().to_string()));
+
+    for field_name in EXPORTED_FIELDS {
+        let options = LookupOptions::exact(*field_name).join_seq(BEETS_SEQ_SEPARATOR);
+
+        if let Some(val) = lookup_ctx.lookup(abs_item_path, &options)?.and_then(|val| redaction.apply(field_name, val)) {
+            hsh.insert(Yaml::String(beets_field_name(field_name).to_string()), meta_value_as_yaml(&val));
+        }
+    }
+
+    for (field_name, val) in lookup_ctx.lookup_matching_fields(abs_item_path, "*")? {
+        let key_y = Yaml::String(beets_field_name(&field_name).to_string());
+
+        if hsh.contains_key(&key_y) {
+            continue;
+        }
+
+        if let Some(val) = redaction.apply(&field_name, val) {
+            hsh.insert(key_y, meta_value_as_yaml(&val));
+        }
+    }
+
+    Ok(Yaml::Hash(hsh))
+}
+
+/// Describes every non-directory item in a directory and its selected subdirectories as a beets listing, in walk order.
+/// Items marked as ignored are left out.
+pub fn export_beets_items<P: AsRef<Path>, G: Progress>(media_lib: &Library, abs_dir_path: P, redaction: &Redaction, progress: &mut G) -> Result<Yaml> {
+    let abs_dir_path = normalize(abs_dir_path.as_ref());
+
+    // Rule: dir path must be a directory.
+    ensure!(media_lib.vfs().is_dir(&abs_dir_path), ErrorKind::NotADirectory(abs_dir_path.clone()));
+
+    let mut lookup_ctx = LookupContext::new(media_lib);
+    let mut items_y = vec![];
+
+    media_lib.walk_items(&abs_dir_path, |step| {
+        match step {
+            WalkStep::EnterDir(dir_path) => lookup_ctx.cache_item_file_with_progress(dir_path, progress)?,
+            WalkStep::Item { path, is_dir } => {
+                // Ignored items are left out, along with everything inside them.
+                if lookup_ctx.is_ignored(path)? {
+                    return Ok(false);
+                }
+
+                if !is_dir {
+                    lookup_ctx.cache_item_file_with_progress(path, progress)?;
+                    progress.item_scanned(path);
+
+                    items_y.push(beets_item_yaml(&mut lookup_ctx, path, redaction)?);
+                }
+            },
+            WalkStep::LeaveDir(_) => {},
+        }
+
+        Ok(true)
+    })?;
+
+    Ok(Yaml::Array(items_y))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use yaml_rust::{Yaml, YamlLoader};
+
+    use lookup::LookupContext;
+    use metadata::MetaValue;
+    use fixtures::default_setup;
+    use progress::NoProgress;
+    use plan::WritePlan;
+    use redact::{Redaction, RedactAction};
+
+    use super::{BeetsItem, read_beets_items, import_beets_items, export_beets_items};
+
+    #[test]
+    fn test_read_beets_items() {
+        let text = "
+- path: /music/a.flac
+  id: 12
+  title: Title A
+  albumartist: Artist
+  track: 3
+  disc: 0
+  genre: ''
+  mood: calm
+- path: /music/b.flac
+  title: Title B
+";
+        let y = &YamlLoader::load_from_str(text).unwrap()[0];
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let expected = vec![
+            BeetsItem {
+                item_path: "/music/a.flac".into(),
+                fields: btreemap![
+                    String::from("title") => str_val("Title A"),
+                    String::from("album_artist") => str_val("Artist"),
+                    String::from("track_num") => str_val("3"),
+                    String::from("mood") => str_val("calm"),
+                ],
+            },
+            BeetsItem {
+                item_path: "/music/b.flac".into(),
+                fields: btreemap![
+                    String::from("title") => str_val("Title B"),
+                ],
+            },
+        ];
+        assert_eq!(expected, read_beets_items(y).unwrap());
+
+        let y = &YamlLoader::load_from_str("- title: No Path").unwrap()[0];
+        assert!(read_beets_items(y).is_err());
+    }
+
+    #[test]
+    fn test_import_export_beets_items() {
+        let (temp_media_root, media_lib) = default_setup("test_import_export_beets_items");
+        let tp = temp_media_root.path();
+
+        let mut f = File::create(tp.join("ALBUM_01").join("self.yml")).unwrap();
+        writeln!(f, "album: Album\nartist:\n  - Artist A\n  - Artist B").unwrap();
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let track_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+        let items = vec![
+            BeetsItem {
+                item_path: track_fp.clone(),
+                fields: btreemap![String::from("title") => str_val("Title"), String::from("mood") => str_val("calm")],
+            },
+            BeetsItem {
+                item_path: tp.join("..").join("ELSEWHERE.flac"),
+                fields: btreemap![String::from("title") => str_val("Elsewhere")],
+            },
+        ];
+
+        // Planning does not change anything.
+        let mut plan = WritePlan::new();
+        let imported = import_beets_items(&media_lib, &items, &mut plan).expect("Unable to import");
+        assert_eq!(vec![track_fp.clone()], imported);
+        assert_eq!(None, LookupContext::new(&media_lib).lookup_origin(&track_fp, "title").unwrap());
+
+        plan.execute().expect("Unable to execute plan");
+        assert_eq!(Some(str_val("Title")), LookupContext::new(&media_lib).lookup_origin(&track_fp, "title").unwrap());
+
+        let mut f = File::create(tp.join("ALBUM_01").join("DISC_02").join("self.yml")).unwrap();
+        writeln!(f, "__ignore: true").unwrap();
+        let exported = export_beets_items(&media_lib, tp.join("ALBUM_01"), &Redaction::new(), &mut NoProgress).expect("Unable to export");
+        let exported = exported.as_vec().expect("Export is not a sequence");
+        assert_eq!(3, exported.len());
+
+        let first = &exported[0];
+        assert_eq!(Some(&*track_fp.to_string_lossy()), first["path"].as_str());
+        assert_eq!(Some("Title"), first["title"].as_str());
+        assert_eq!(Some("Album"), first["album"].as_str());
+        assert_eq!(Some("Artist A; Artist B"), first["artist"].as_str());
+        assert_eq!(Some("calm"), first["mood"].as_str());
+        assert_eq!(Some("TRACK_01_item_val"), first["TRACK_01_item_key"].as_str());
+        assert_eq!(Yaml::BadValue, first["genre"]);
+
+        let mut redaction = Redaction::new();
+        redaction.rule("mood", RedactAction::Remove).unwrap().rule("TRACK_*", RedactAction::Hash).unwrap();
+        let exported = export_beets_items(&media_lib, tp.join("ALBUM_01"), &redaction, &mut NoProgress).expect("Unable to export");
+        let first = &exported[0];
+        assert_eq!(Some(&*track_fp.to_string_lossy()), first["path"].as_str());
+        assert_eq!(Some("Title"), first["title"].as_str());
+        assert_eq!(Yaml::BadValue, first["mood"]);
+        assert!(first["TRACK_01_item_key"].as_str().unwrap().starts_with("hash:"));
+
+        assert!(export_beets_items(&media_lib, &track_fp, &Redaction::new(), &mut NoProgress).is_err());
+    }
+}
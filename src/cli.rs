@@ -0,0 +1,2611 @@
+// This module provides the command line interface for the `taggu` executable.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use glob;
+use regex::Regex;
+use yaml_rust::YamlEmitter;
+
+use library::{Library, LibraryBuilder};
+use library::selection::Selection;
+use library::assets::{AssetRule, AssetScope};
+use library::metrics::Metrics;
+#[cfg(feature = "cue")] use metadata::source::cue::CueMetaSource;
+#[cfg(feature = "replaygain")] use metadata::source::replaygain::ReplayGainSource;
+use lookup::LookupContext;
+use lookup::options::LookupOptionsBuilder;
+use lookup::persist::PersistentCache;
+use lookup::resolved::FieldSource;
+use lookup::trace::LookupTrace;
+use lookup::query::{Query, read_saved_queries, run_query as run_library_query};
+use metadata::{MetaTarget, MetaValue};
+use metadata::pretty::pretty_entry;
+use metadata::keys::PLAY_COUNT_KEY;
+use schema::{Schema, read_schema_file};
+use scaffold::scaffold_dir;
+use sync::{FieldMapping, SyncDirection, sync_dir};
+use multivalue::SplitRules;
+use sort_name::SortNameRules;
+use naming::{NamingRules, check_names};
+use retag::{read_retag_csv, plan_retag};
+use tracklist::{TracklistReport, TrackIssue, check_tracklists};
+use typos::{SimilarValues, DEFAULT_TYPO_FIELDS, find_similar_values};
+use beets::{read_beets_items, import_beets_items, export_beets_items};
+use redact::Redaction;
+use ontology::{Ontology, read_ontology_file};
+#[cfg(feature = "discogs")] use discogs::{fetch_release, plan_release_import, release_id as discogs_release_id};
+use itunes::{read_itunes_library, import_itunes_tracks, file_url_path};
+#[cfg(feature = "lastfm")] use lastfm::{read_scrobbles, import_scrobbles};
+#[cfg(feature = "tui")] use tui;
+use mpd::{collect_stickers, stickers_as_sql};
+use yaml::read_yaml_file;
+use progress::{Progress, ProgressCounts};
+use plan::{WritePlan, WriteOp};
+use completion::{Shell, completion_script, field_names};
+use playlist::{PlaylistFormat, render_playlist};
+use compare::{MatchBy, compare_libraries};
+use refactor::{rename_field, replace_values, split_values, split_into_sidecars};
+use writer::plan_move_item_meta;
+use undo::UndoJournal;
+use health::HealthReport;
+use plexer::{Alignment, PlexCheckReport};
+use diagnostics::{Diagnostic, check_meta_file};
+use watch::{MetaFileWatcher, PollingWatcher, WatchEvent};
+use git;
+use json::{Json, OUTPUT_FORMAT_VERSION};
+use helpers::{FileStamp, normalize};
+use error::*;
+
+const DEFAULT_SELF_META_FILE_NAME: &str = "taggu_self.yml";
+const DEFAULT_ITEM_META_FILE_NAME: &str = "taggu_item.yml";
+const DEFAULT_SIDECAR_SUFFIX: &str = "taggu.yml";
+const DEFAULT_SCHEMA_FILE_NAME: &str = "taggu_schema.yml";
+const DEFAULT_TAG_MAP_FILE_NAME: &str = "taggu_tag_map.yml";
+const DEFAULT_QUERIES_FILE_NAME: &str = "taggu_queries.yml";
+const DEFAULT_REDACT_FILE_NAME: &str = "taggu_redact.yml";
+const DEFAULT_ONTOLOGY_FILE_NAME: &str = "taggu_ontology.yml";
+const DEFAULT_SPLIT_FILE_NAME: &str = "taggu_split.yml";
+const DEFAULT_SORT_NAME_FILE_NAME: &str = "taggu_sort.yml";
+const DEFAULT_NAMING_FILE_NAME: &str = "taggu_naming.yml";
+const DEFAULT_WATCH_INTERVAL_MS: u64 = 500;
+
+const USAGE: &str = "\
+usage: taggu [--root <dir>] [--overlay <dir>] [--read-only] [--lenient] [--progress] [--timing] [--output <format>] [--paths-from <file>] [--changed-since <ref>] <command> [<args>]
+
+commands:
+    dump [--trace] [--show-sources] [--subst] [--join <sep>] <item> <field>...
+                                        print the values of fields for an item, where
+                                        nested fields can be given as dotted paths, and
+                                        glob patterns match fields of the item itself;
+                                        lists of strings are joined with <sep> if given,
+                                        and ${lib.root} and ${ENV_VAR} are replaced if
+                                        --subst is given
+    init [--dry-run] <dir>              create skeleton meta files for untagged items
+    sync --direction <direction> [--dry-run] [--map <field>=<tag>]... <dir>
+                                        reconcile metadata with embedded tags, where
+                                        <direction> is yaml-to-tags, tags-to-yaml or merge
+    beets-import [--dry-run] <file>     copy fields from a `beet export` listing into meta files
+    beets-export [--redact] <dir>       print the items in a directory as a beets listing;
+                                        with --redact, fields are removed or hashed as
+                                        configured in taggu_redact.yml at the library root,
+                                        e.g. 'purchase_*: remove' or 'rating: hash'
+    itunes-import [--dry-run] [--music-folder <path>] <file>
+                                        copy fields (including ratings, play counts and sort
+                                        names) from an iTunes Library.xml export into meta files,
+                                        matching each track by its path under the music folder
+                                        (taken from the export if not given) to the same path
+                                        under the library root
+    discogs-import [--dry-run] [--yes] <dir>
+                                        fill in the titles, artists and credits of the items in a
+                                        directory from the tracks of the Discogs release named by
+                                        its discogs:release_id field, in order, asking first if
+                                        the counts differ (unless --yes is given), and keep the
+                                        track titles as its __tracklist; needs the discogs
+                                        feature and curl, and sends DISCOGS_TOKEN if set
+    lastfm-import [--dry-run] <file>    add the plays in a Last.fm scrobble log or CSV export to the
+                                        playcount and lastplayed fields of the items that match
+                                        by artist and title, and list the plays that match none;
+                                        needs the lastfm feature
+    mpd-stickers [--field <field>]... <dir>
+                                        print an SQL script that stores fields of the items
+                                        in a directory in an MPD sticker database
+    cache <action>                      manage the cache of parsed meta files that dump uses,
+                                        where <action> is build (parse every meta file and
+                                        save the cache, along with when each item was first
+                                        seen), verify (list meta files that changed
+                                        since the cache was built), clear (remove the cache)
+                                        or stats (show cache size and hit rate)
+    refactor rename-field [--dry-run] <old> <new>
+                                        rename a field in every meta file in the library,
+                                        keeping comments and formatting where possible
+    refactor replace [--dry-run] --field <field> --from <regex> --to <text>
+                                        replace matches of a regex in the values of a field
+                                        in every meta file in the library, where <text> can
+                                        refer to capture groups as $1, $2 and so on
+    refactor split-values [--dry-run] [--field <field>]
+                                        split values that join several values with a delimiter,
+                                        e.g. 'A feat. B', into sequences in every meta file in the
+                                        library, for every field with a split rule or only <field>
+    refactor split-sidecars [--dry-run] <meta file>
+                                        split a sibling meta file into one sidecar meta file
+                                        per item (e.g. TRACK_01.flac.taggu.yml), and remove it
+    apply --csv <file> [--dry-run]      apply the field changes listed in a CSV file, with a header
+                                        naming its path and/or query, field and value columns;
+                                        each row sets a field of the item at path, or of every
+                                        item matching query, or removes it if value is empty;
+                                        nothing is written if a new value breaks the schema
+    move [--dry-run] <item> <dest>      move or rename an item, and move its blocks in the meta files
+                                        next to it and its sidecar meta file along with it
+    played <item>...                    add one to the playcount of each item, e.g. from a player's
+                                        end of track hook
+    rate <item> <rating>                set the rating of an item, from 0 to 5, or remove it if
+                                        <rating> is none; neither this nor played is recorded
+                                        for undo
+    query <query>                       print the items whose metadata matches <query>, e.g.
+                                        'genre ~ \"psy*\" && year >= 2020 ORDER BY year DESC SELECT title',
+                                        where conditions compare fields with ==, !=, <, <=, >, >=,
+                                        or ~ and !~ for glob patterns, and combine with &&, || and !;
+                                        ORDER BY sorts by fields, and SELECT prints fields after each path
+    playlist --query <name> [--format <format>] [--added-within <days>] [--modified-within <days>] [--out <file>] [--dry-run]
+                                        print the items matching a saved query as a playlist, where
+                                        <format> is m3u (the default) or paths; with --out, write the
+                                        playlist to <file> instead, with the paths of items below
+                                        its directory written relative to it; --added-within keeps
+                                        only items first seen by 'cache build' in the last <days>
+                                        days (or created then, if it has not seen them yet), and
+                                        --modified-within only items whose files or metadata
+                                        changed in the last <days> days
+    compare [--match <method>] <root a> <root b>
+                                        compare the items and metadata of two libraries, e.g. a
+                                        mirror or a migrated copy, and print the items missing from
+                                        <root b>, the extra items in it, and the fields that differ,
+                                        including inherited ones; <method> is path (the default),
+                                        or hash to also pair up renamed files by their contents;
+                                        exits with an error status if there are any differences
+    lint [--fix-names] [--dry-run] [--typo-field <field>]...
+                                        check metadata against the schema, and print the violations,
+                                        along with meta files whose blocks do not line up with
+                                        their items, directories whose items are missing, extra or
+                                        out of order compared to the track titles in their
+                                        __tracklist field, and items whose file names do not match
+                                        their name template; exits with an error status if there
+                                        are any; --fix-names renames the items to match their
+                                        templates; also prints values of a field that differ only
+                                        in case or by a character or two, e.g. psytrance and
+                                        psy-trance, with the meta files of the less used ones, for
+                                        each <field> or else artist, album_artist, composer and
+                                        genre, without counting them as errors
+    plex-check [<dir>]                  show how the blocks of the meta files in <dir> line up with
+                                        its items, marking blocks without an item with '-' and items
+                                        without a block with '+'; without <dir>, show every meta
+                                        file in the library where they do not line up
+    check [--watch] [--interval <ms>]   check each meta file for syntax errors and schema violations,
+                                        and print them with their positions in the file; --watch
+                                        keeps running and checks meta files again as they or the
+                                        items next to them change, polling every <ms> milliseconds
+                                        (500 by default), which also works on network mounts; with
+                                        --output json-lines it suits editors
+    health [--tags] [--html]            check the whole library and print a report with a score
+                                        from 0 to 100, covering schema violations and items
+                                        without metadata, plus conflicts between metadata and
+                                        embedded tags if --tags is given; --html prints the
+                                        report as an HTML page
+    stats --field <field>               print how many items use each value of a field, most used
+                                        first, counting only the values in their own metadata,
+                                        e.g. to spot typos like psytrance and psy-trance
+    install-hooks [--dry-run] [--force]
+                                        write a git pre-commit hook that lints the items described
+                                        by staged meta files, and blocks commits with violations;
+                                        an existing hook is only replaced if --force is given
+    tui                                 browse the library in the terminal, with the resolved fields
+                                        of the selected item, where inherited ones show the meta
+                                        file they come from, and its schema violations; fields can
+                                        be edited (e), added (a) and removed (d), and each edit can
+                                        be undone with undo; needs the tui feature
+    undo [--list]                       undo the changes made by the last command that wrote files,
+                                        or list the commands that can be undone, newest first
+    completions <shell>                 print a script that sets up tab completion of commands,
+                                        options and the fields of items, where <shell> is
+                                        bash, zsh or fish
+
+commands that write files accept --dry-run, which prints the changes that would be made instead,
+and fail without writing anything if --read-only is given; the files they change are first copied
+to .taggu_undo in the library root (or the overlay root), which keeps the last 20 commands for undo
+
+--lenient warns about each block of a meta file that cannot be parsed, instead of silently dropping it;
+a bad block in a sequence still takes up its place, so that the blocks after it line up with their items
+
+--timing prints counts of the meta files parsed, directories read and cache hits to stderr after the
+command, along with how long reading them took, to help find what makes a command slow
+
+--overlay <dir> reads meta files from <dir> as well, where they mirror the directories of the library
+and take precedence over the library's own meta files; edits to items are written to <dir>, so that
+metadata can be kept for a collection that is read-only
+
+an ontology of terms is read from taggu_ontology.yml in the library root, if there is one, which maps
+fields to terms and their parents, e.g. `genre: {psytrance: trance, trance: electronic}`; queries
+then match narrower terms with == and !=, so that `genre == electronic` matches psytrance, and lint
+and check flag terms that the ontology does not know
+
+split rules are read from taggu_split.yml in the library root, on top of the built-in ones for artist,
+album_artist, composer and genre, which map fields to the delimiters that join several values, e.g.
+`artist: [\"; \", \" feat. \"]`; they are also applied to tag values read by sync
+
+sort fields that are not set are derived from the fields they sort when dumped or queried, e.g.
+artist_sort from artist, with leading articles moved to the end (\"The Beatles\" becomes
+\"Beatles, The\"); taggu_sort.yml in the library root can set the articles, characters to
+transliterate first and the fields to derive, e.g. `{articles: [The, Die], transliterate: {ß: ss},
+fields: {title_sort: title}}`
+
+name templates are read from taggu_naming.yml in the library root, if there is one, which maps
+patterns of file names to templates filled in from the metadata of items, e.g.
+`\"*.flac\": \"{track_num:02}. {title}.flac\"`; the first matching pattern applies, and {field:3}
+pads a value with spaces to 3 characters, or with zeros if the width starts with 0
+
+saved queries are read from taggu_queries.yml in the library root, which maps names to queries, e.g.
+`recent_psy: genre ~ \"psy*\" && year >= 2020`; fields selected by a query become the titles of
+entries in M3U playlists
+
+every command accepts --paths-from <file>, which limits the items it walks over to the paths listed
+in <file>, one per line, along with the items below them and the items described by listed meta
+files; <file> can be - to read the list from stdin, e.g. `git diff --name-only | taggu lint --paths-from -`
+
+every command accepts --changed-since <ref>, which limits the items it walks over to the directories
+holding meta files that differ from the git ref <ref>, including untracked meta files, e.g.
+`taggu lint --changed-since HEAD` in a pre-commit hook; this needs the git feature
+
+every command accepts --output <format>, where <format> is text (the default), json or json-lines;
+json output is a single object with a \"version\" key, which changes only when existing
+keys are removed or change meaning, and errors are reported as an object with an \"error\" key;
+json-lines output is the same, except that commands that keep running print an object per line";
+
+/// Options that apply to every subcommand.
+struct GlobalOpts {
+    root_dir: PathBuf,
+    overlay_root: Option<PathBuf>,
+    read_only: bool,
+    lenient: bool,
+    show_progress: bool,
+    timing: bool,
+    /// Shared by every library that the command opens, so that `--timing` covers all of them.
+    metrics: Arc<Metrics>,
+    output: OutputFormat,
+    paths_from: Option<String>,
+    changed_since: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    /// Prints a JSON object per line as results come in, for commands that keep running; otherwise the same as JSON.
+    JsonLines,
+}
+
+impl OutputFormat {
+    fn parse(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "json-lines" => Ok(OutputFormat::JsonLines),
+            _ => bail!("unknown output format: '{}', expected text, json or json-lines", s),
+        }
+    }
+
+    fn is_json(self) -> bool {
+        self != OutputFormat::Text
+    }
+}
+
+/// Prints the JSON output of a command, which always starts with the output format version and the command name.
+fn print_json(command: &str, fields: Vec<(&str, Json)>) {
+    let mut pairs = vec![
+        ("version", Json::Int(OUTPUT_FORMAT_VERSION)),
+        ("command", Json::str(command)),
+    ];
+    pairs.extend(fields);
+
+    println!("{}", Json::object(pairs));
+}
+
+fn error_json(e: &Error) -> Json {
+    Json::object(vec![
+        ("version", Json::Int(OUTPUT_FORMAT_VERSION)),
+        ("error", Json::str(e.to_string())),
+        ("causes", Json::Array(e.iter().skip(1).map(|c| Json::str(c.to_string())).collect())),
+    ])
+}
+
+fn meta_value_json(opt_mv: Option<&MetaValue>) -> Json {
+    opt_mv.map_or(Json::Null, Json::from_meta_value)
+}
+
+fn write_op_json(op: &WriteOp) -> Json {
+    let kind = match *op {
+        WriteOp::Yaml(..) | WriteOp::Text(..) => "meta_file",
+        WriteOp::Tags(..) => "tags",
+        WriteOp::Remove(..) => "removed_meta_file",
+    };
+
+    Json::object(vec![
+        ("kind", Json::str(kind)),
+        ("path", Json::path(op.path())),
+    ])
+}
+
+fn source_json(opt_source: Option<&FieldSource>) -> Json {
+    match opt_source {
+        Some(source) => Json::object(vec![
+            ("meta_path", Json::path(&source.meta_path)),
+            ("meta_target", Json::str(format!("{:?}", source.meta_target))),
+            ("depth", Json::Int(source.depth as i64)),
+        ]),
+        None => Json::Null,
+    }
+}
+
+fn trace_json(trace: &LookupTrace) -> Json {
+    Json::Array(trace.steps().iter().map(|step| {
+        Json::object(vec![
+            ("item_path", Json::path(&step.item_path)),
+            ("meta_path", Json::path(&step.meta_path)),
+            ("field", Json::str(step.field_name.as_str())),
+            ("decision", Json::str(step.decision.to_string())),
+        ])
+    }).collect())
+}
+
+/// Shows a running status line on stderr, if enabled.
+struct StatusLine {
+    enabled: bool,
+    counts: ProgressCounts,
+}
+
+impl StatusLine {
+    fn new(enabled: bool) -> Self {
+        StatusLine { enabled, counts: ProgressCounts::default() }
+    }
+
+    fn render(&self) {
+        if !self.enabled {
+            return;
+        }
+
+        let current = self.counts.current_path.as_ref().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+
+        // Clear the rest of the line, since the current path may be shorter than the previous one.
+        eprint!("\r{} items, {} meta files: {}\x1b[K", self.counts.items_scanned, self.counts.meta_files_parsed, current);
+        let _ = io::stderr().flush();
+    }
+
+    fn finish(&self) {
+        if self.enabled {
+            eprintln!();
+        }
+    }
+}
+
+impl Progress for StatusLine {
+    fn item_scanned(&mut self, abs_item_path: &Path) {
+        self.counts.item_scanned(abs_item_path);
+        self.render();
+    }
+
+    fn meta_file_parsed(&mut self, abs_meta_path: &Path) {
+        self.counts.meta_file_parsed(abs_meta_path);
+        self.render();
+    }
+}
+
+pub fn run<I: IntoIterator<Item = String>>(args: I) -> Result<()> {
+    let mut args: Vec<String> = args.into_iter().collect();
+
+    let mut global_opts = GlobalOpts {
+        root_dir: env::current_dir()?,
+        overlay_root: None,
+        read_only: false,
+        lenient: false,
+        show_progress: false,
+        timing: false,
+        metrics: Arc::new(Metrics::new()),
+        output: OutputFormat::Text,
+        paths_from: None,
+        changed_since: None,
+    };
+
+    // Consume global options, which must come before the subcommand.
+    while args.first().map_or(false, |a| a.starts_with("--")) {
+        let flag = args.remove(0);
+
+        match flag.as_str() {
+            "--root" => {
+                ensure!(!args.is_empty(), "missing value for '--root'");
+                global_opts.root_dir = PathBuf::from(args.remove(0));
+            },
+            "--overlay" => {
+                ensure!(!args.is_empty(), "missing value for '--overlay'");
+                global_opts.overlay_root = Some(PathBuf::from(args.remove(0)));
+            },
+            "--read-only" => { global_opts.read_only = true; },
+            "--lenient" => { global_opts.lenient = true; },
+            "--progress" => { global_opts.show_progress = true; },
+            "--timing" => { global_opts.timing = true; },
+            "--output" => {
+                ensure!(!args.is_empty(), "missing value for '--output'");
+                global_opts.output = OutputFormat::parse(&args.remove(0))?;
+            },
+            "--paths-from" => {
+                ensure!(!args.is_empty(), "missing value for '--paths-from'");
+                global_opts.paths_from = Some(args.remove(0));
+            },
+            "--changed-since" => {
+                ensure!(!args.is_empty(), "missing value for '--changed-since'");
+                global_opts.changed_since = Some(args.remove(0));
+            },
+            "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            },
+            _ => bail!("unknown option: '{}'\n{}", flag, USAGE),
+        }
+    }
+
+    ensure!(!args.is_empty(), "no command given\n{}", USAGE);
+    let command = args.remove(0);
+
+    // The output format may also be given after the subcommand, so that it can be added to any existing invocation.
+    while let Some(i) = args.iter().position(|a| a == "--output") {
+        ensure!(i + 1 < args.len(), "missing value for '--output'");
+        global_opts.output = OutputFormat::parse(&args[i + 1])?;
+        args.drain(i..i + 2);
+    }
+
+    // The same goes for the paths to work on, so that a list of changed files can be piped into any command.
+    while let Some(i) = args.iter().position(|a| a == "--paths-from") {
+        ensure!(i + 1 < args.len(), "missing value for '--paths-from'");
+        global_opts.paths_from = Some(args[i + 1].clone());
+        args.drain(i..i + 2);
+    }
+
+    while let Some(i) = args.iter().position(|a| a == "--changed-since") {
+        ensure!(i + 1 < args.len(), "missing value for '--changed-since'");
+        global_opts.changed_since = Some(args[i + 1].clone());
+        args.drain(i..i + 2);
+    }
+
+    ensure!(global_opts.paths_from.is_none() || global_opts.changed_since.is_none(), "'--paths-from' and '--changed-since' cannot be used together");
+
+    let start = Instant::now();
+    let result = run_command(&global_opts, &command, args);
+
+    if global_opts.timing {
+        global_opts.metrics.record_time(&command, start.elapsed());
+        eprintln!("{}", global_opts.metrics.snapshot());
+    }
+
+    if global_opts.output.is_json() {
+        if let Err(ref e) = result {
+            println!("{}", error_json(e));
+        }
+    }
+
+    result
+}
+
+fn run_command(global_opts: &GlobalOpts, command: &str, args: Vec<String>) -> Result<()> {
+    match command {
+        "dump" => run_dump(global_opts, args),
+        "init" => run_init(global_opts, args),
+        "sync" => run_sync(global_opts, args),
+        "beets-import" => run_beets_import(global_opts, args),
+        "beets-export" => run_beets_export(global_opts, args),
+        "itunes-import" => run_itunes_import(global_opts, args),
+        #[cfg(feature = "discogs")]
+        "discogs-import" => run_discogs_import(global_opts, args),
+        #[cfg(not(feature = "discogs"))]
+        "discogs-import" => bail!("'discogs-import' needs the discogs feature"),
+        #[cfg(feature = "lastfm")]
+        "lastfm-import" => run_lastfm_import(global_opts, args),
+        #[cfg(not(feature = "lastfm"))]
+        "lastfm-import" => bail!("'lastfm-import' needs the lastfm feature"),
+        "mpd-stickers" => run_mpd_stickers(global_opts, args),
+        "cache" => run_cache(global_opts, args),
+        "refactor" => run_refactor(global_opts, args),
+        "apply" => run_apply(global_opts, args),
+        "move" => run_move(global_opts, args),
+        "played" => run_played(global_opts, args),
+        "rate" => run_rate(global_opts, args),
+        "query" => run_query(global_opts, args),
+        "playlist" => run_playlist(global_opts, args),
+        "compare" => run_compare(global_opts, args),
+        "lint" => run_lint(global_opts, args),
+        "plex-check" => run_plex_check(global_opts, args),
+        "check" => run_check(global_opts, args),
+        "health" => run_health(global_opts, args),
+        "stats" => run_stats(global_opts, args),
+        "install-hooks" => run_install_hooks(global_opts, args),
+        #[cfg(feature = "tui")]
+        "tui" => run_tui(global_opts, args),
+        #[cfg(not(feature = "tui"))]
+        "tui" => bail!("'tui' needs the tui feature"),
+        "undo" => run_undo(global_opts, args),
+        "completions" => run_completions(global_opts, args),
+        // Used by the completion scripts, so it is left out of the usage text.
+        "complete-fields" => run_complete_fields(global_opts, args),
+        // Used by editors, so it is left out of the usage text too.
+        "complete-values" => run_complete_values(global_opts, args),
+        _ => bail!("unknown command: '{}'\n{}", command, USAGE),
+    }
+}
+
+fn default_library(global_opts: &GlobalOpts) -> Result<Library> {
+    library_at(global_opts, &global_opts.root_dir)
+}
+
+/// Opens a library with the default settings over any root directory, e.g. the second library of a comparison.
+fn library_at(global_opts: &GlobalOpts, root_dir: &Path) -> Result<Library> {
+    let meta_target_specs = vec![
+        (String::from(DEFAULT_SELF_META_FILE_NAME), MetaTarget::Contains),
+        (String::from(DEFAULT_ITEM_META_FILE_NAME), MetaTarget::Siblings),
+        (String::from("taggu_sidecar"), MetaTarget::Sidecar { suffix: String::from(DEFAULT_SIDECAR_SUFFIX) }),
+    ];
+
+    // Meta files themselves should never be considered items, and neither should the undo journal.
+    let meta_file_regex = Regex::new(r"^(taggu_.*\.yml|\.taggu_undo)$").chain_err(|| "unable to compile meta file pattern")?;
+
+    let cover_regex = Regex::new(r"(?i)^(cover|folder|front)\.(jpe?g|png)$").chain_err(|| "unable to compile asset pattern")?;
+    let cover_rule = AssetRule::new("cover", Selection::Regex(cover_regex), AssetScope::Dir);
+    let booklet_rule = AssetRule::new("booklet", Selection::Ext(String::from("pdf")), AssetScope::Dir);
+
+    // Neither should assets.
+    let selection = Selection::Not(Box::new(Selection::Or(
+        Box::new(Selection::Regex(meta_file_regex)),
+        Box::new(Selection::Or(
+            Box::new(cover_rule.selection().clone()),
+            Box::new(booklet_rule.selection().clone()),
+        )),
+    )));
+
+    let mut builder = LibraryBuilder::new(root_dir, meta_target_specs);
+    builder
+        .read_only(global_opts.read_only)
+        .lenient(global_opts.lenient)
+        .metrics(Arc::clone(&global_opts.metrics))
+        .selection(selection)
+        .asset_rule(cover_rule)
+        .asset_rule(booklet_rule);
+
+    #[cfg(feature = "cue")]
+    builder.meta_source(CueMetaSource);
+
+    #[cfg(feature = "replaygain")]
+    builder.meta_source(ReplayGainSource::new());
+
+    #[cfg(feature = "archives")]
+    builder.archives(true);
+
+    // Fields marked as local in the schema are never inherited.
+    let schema = default_schema(root_dir)?;
+
+    for field_name in schema.local_fields() {
+        builder.local_field(glob::Pattern::escape(field_name));
+    }
+
+    builder.create()
+}
+
+/// Opens the default library, scoped to the paths listed with `--paths-from` or the directories changed since `--changed-since` if given.
+/// Relative paths are taken to be relative to the current directory, and paths outside of the library are skipped.
+fn open_library(global_opts: &GlobalOpts) -> Result<Library> {
+    let media_lib = default_library(global_opts)?;
+
+    let media_lib = match global_opts.overlay_root {
+        Some(ref overlay_root) => Library::overlay(&media_lib, overlay_root)?,
+        None => media_lib,
+    };
+
+    if let Some(ref since_ref) = global_opts.changed_since {
+        let changed_meta_fps = git::changed_meta_fps(&media_lib, since_ref)?;
+
+        return media_lib.scoped_to(git::affected_dirs(&media_lib, changed_meta_fps));
+    }
+
+    let paths_from = match global_opts.paths_from {
+        Some(ref paths_from) => paths_from,
+        None => return Ok(media_lib),
+    };
+
+    let mut text = String::new();
+
+    if paths_from == "-" {
+        io::stdin().read_to_string(&mut text).chain_err(|| "unable to read paths from stdin")?;
+    }
+    else {
+        File::open(paths_from)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .chain_err(|| format!("unable to read paths from: '{}'", paths_from))?;
+    }
+
+    let current_dir = env::current_dir()?.canonicalize()?;
+    let mut abs_paths = vec![];
+
+    for line in text.lines().map(|l| l.trim_end_matches('\r')).filter(|l| !l.is_empty()) {
+        let abs_path = normalize(&current_dir.join(line));
+
+        if media_lib.is_proper_sub_path(&abs_path) {
+            abs_paths.push(abs_path);
+        }
+        else {
+            warn!("skipping path outside of the library: '{}'", line);
+        }
+    }
+
+    media_lib.scoped_to(abs_paths)
+}
+
+/// Reads the schema file in the library root, if there is one, on top of the built-in field specs.
+/// Fields with a hierarchy in the ontology are also constrained to its terms.
+fn default_schema<P: AsRef<Path>>(root_dir: P) -> Result<Schema> {
+    let schema_fp = root_dir.as_ref().join(DEFAULT_SCHEMA_FILE_NAME);
+    let mut schema = Schema::builtin();
+
+    if schema_fp.is_file() {
+        schema.extend(read_schema_file(&schema_fp).chain_err(|| format!("unable to read schema file: '{}'", schema_fp.to_string_lossy()))?);
+    }
+
+    schema.constrain_to_ontology(&default_ontology(root_dir)?);
+
+    Ok(schema)
+}
+
+/// Reads the sort name rules file in the library root, if there is one, on top of the built-in sort name rules.
+fn default_sort_name_rules<P: AsRef<Path>>(root_dir: P) -> Result<SortNameRules> {
+    let mut sort_name_rules = SortNameRules::default();
+    let sort_name_fp = root_dir.as_ref().join(DEFAULT_SORT_NAME_FILE_NAME);
+
+    if sort_name_fp.is_file() {
+        let yaml = read_yaml_file(&sort_name_fp)?;
+        sort_name_rules.extend_from_yaml(&yaml).chain_err(|| format!("unable to read sort name rules file: '{}'", sort_name_fp.to_string_lossy()))?;
+    }
+
+    Ok(sort_name_rules)
+}
+
+/// Reads the naming rules file in the library root, if there is one.
+fn default_naming_rules<P: AsRef<Path>>(root_dir: P) -> Result<NamingRules> {
+    let mut naming_rules = NamingRules::new();
+    let naming_fp = root_dir.as_ref().join(DEFAULT_NAMING_FILE_NAME);
+
+    if naming_fp.is_file() {
+        let yaml = read_yaml_file(&naming_fp)?;
+        naming_rules.extend_from_yaml(&yaml).chain_err(|| format!("unable to read naming rules file: '{}'", naming_fp.to_string_lossy()))?;
+    }
+
+    Ok(naming_rules)
+}
+
+/// Reads the ontology file in the library root, if there is one.
+fn default_ontology<P: AsRef<Path>>(root_dir: P) -> Result<Ontology> {
+    let ontology_fp = root_dir.as_ref().join(DEFAULT_ONTOLOGY_FILE_NAME);
+
+    if !ontology_fp.is_file() {
+        return Ok(Ontology::new());
+    }
+
+    read_ontology_file(&ontology_fp).chain_err(|| format!("unable to read ontology file: '{}'", ontology_fp.to_string_lossy()))
+}
+
+fn run_dump(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut show_trace = false;
+    let mut show_sources = false;
+    let mut substitute_vars = false;
+    let mut opt_separator: Option<String> = None;
+    let mut positionals: Vec<String> = vec![];
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => { show_trace = true; },
+            "--show-sources" => { show_sources = true; },
+            "--subst" => { substitute_vars = true; },
+            "--join" => {
+                let val = args.next().ok_or("missing value for '--join'")?;
+                opt_separator = Some(val);
+            },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(positionals.len() >= 2, "'dump' requires an item path and at least one field name\n{}", USAGE);
+
+    let item_path = Path::new(&positionals[0]).canonicalize()?;
+    let field_names = &positionals[1..];
+
+    let media_lib = open_library(global_opts)?;
+    let mut lookup_ctx = LookupContext::new(&media_lib);
+    lookup_ctx.set_sort_name_rules(default_sort_name_rules(media_lib.root_dir())?);
+
+    // Meta files that have not changed since the cache was built do not need to be parsed again.
+    PersistentCache::load(media_lib.root_dir())?.seed_context(&mut lookup_ctx);
+
+    if show_trace {
+        lookup_ctx.enable_trace();
+    }
+
+    let opt_resolved = if show_sources { Some(lookup_ctx.resolve_block(&item_path)?) } else { None };
+    let source_of = |field_name: &str| opt_resolved.as_ref().and_then(|rb| rb.source(field_name));
+
+    let json_output = global_opts.output.is_json();
+    let mut json_fields = vec![];
+
+    // Fields provided by virtual fields or meta sources have no meta file to show.
+    let print_source = |field_name: &str| {
+        if let Some(source) = source_of(field_name) {
+            println!("    from: {}", source);
+        }
+    };
+
+    // Sources and traces are only included in JSON output when asked for, same as in text output.
+    let field_json = |field_name: &str, opt_val: Option<&MetaValue>, opt_trace: Option<&LookupTrace>| {
+        let mut pairs = vec![
+            ("name", Json::str(field_name)),
+            ("value", meta_value_json(opt_val)),
+        ];
+
+        if show_sources {
+            pairs.push(("source", source_json(source_of(field_name))));
+        }
+
+        if let Some(trace) = opt_trace {
+            pairs.push(("trace", trace_json(trace)));
+        }
+
+        Json::object(pairs)
+    };
+
+    for field_name in field_names {
+        // Glob patterns dump every matching field of the item itself.
+        if field_name.contains(|c| c == '*' || c == '?' || c == '[') {
+            let matches = lookup_ctx.lookup_matching_fields(&item_path, field_name)?;
+            let opt_trace = lookup_ctx.take_trace();
+
+            if json_output {
+                // The trace covers every match, so it is only attached to the first one.
+                for (i, (matched_name, val)) in matches.into_iter().enumerate() {
+                    let opt_trace = if i == 0 { opt_trace.as_ref() } else { None };
+                    json_fields.push(field_json(&matched_name, Some(&val), opt_trace));
+                }
+
+                continue;
+            }
+
+            for (matched_name, val) in matches {
+                println!("{}", pretty_entry(&matched_name, &val, 0));
+                print_source(&matched_name);
+            }
+
+            if let Some(trace) = opt_trace {
+                print!("{}", trace);
+            }
+
+            continue;
+        }
+
+        // Look at the item itself first, and then fall back to its ancestors.
+        let mut builder = LookupOptionsBuilder::new(field_name);
+        builder.substitute_vars(substitute_vars);
+        if let Some(ref separator) = opt_separator {
+            builder.join_seq(separator.as_str());
+        }
+        let options = builder.create();
+
+        let found = lookup_ctx.lookup(&item_path, &options)?;
+        let opt_trace = lookup_ctx.take_trace();
+
+        if json_output {
+            json_fields.push(field_json(options.field_name(), found.as_ref(), opt_trace.as_ref()));
+            continue;
+        }
+
+        match found {
+            Some(val) => println!("{}", pretty_entry(field_name, &val, 0)),
+            None => println!("{}: ~", field_name),
+        }
+
+        print_source(options.field_name());
+
+        if let Some(trace) = opt_trace {
+            print!("{}", trace);
+        }
+    }
+
+    if json_output {
+        print_json("dump", vec![
+            ("item_path", Json::path(&item_path)),
+            ("fields", Json::Array(json_fields)),
+        ]);
+    }
+
+    Ok(())
+}
+
+/// Prints the changes in a plan if this is a dry run, and otherwise carries them out.
+/// For JSON output, the result of the plan is printed along with any other fields that the command reports.
+fn finish_plan(global_opts: &GlobalOpts, command: &str, plan: WritePlan, dry_run: bool, mut json_fields: Vec<(&str, Json)>) -> Result<()> {
+    if !dry_run && !plan.is_empty() {
+        undo_journal(global_opts).record(command, &[], &plan)?;
+    }
+
+    if global_opts.output.is_json() {
+        json_fields.push(("dry_run", Json::Bool(dry_run)));
+
+        if dry_run {
+            let changes = plan.changes()?.into_iter().map(|(op, lines)| {
+                let mut pairs = match write_op_json(op) {
+                    Json::Object(pairs) => pairs,
+                    _ => vec![],
+                };
+                pairs.push((String::from("diff"), Json::Array(lines.into_iter().map(Json::Str).collect())));
+
+                Json::Object(pairs)
+            }).collect();
+
+            json_fields.push(("changes", Json::Array(changes)));
+        }
+        else {
+            let ops: Vec<Json> = plan.ops().iter().map(write_op_json).collect();
+            plan.execute()?;
+
+            json_fields.push(("written", Json::Array(ops)));
+        }
+
+        print_json(command, json_fields);
+        return Ok(());
+    }
+
+    if dry_run {
+        print!("{}", plan.diff()?);
+    }
+    else {
+        for written_path in plan.execute()? {
+            println!("wrote: {}", written_path.to_string_lossy());
+        }
+    }
+
+    Ok(())
+}
+
+/// The undo journal lives next to the meta files that commands write: in the overlay, for a library with one.
+fn undo_journal(global_opts: &GlobalOpts) -> UndoJournal {
+    UndoJournal::new(global_opts.overlay_root.as_ref().unwrap_or(&global_opts.root_dir))
+}
+
+fn run_init(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut dry_run = false;
+    let mut positionals: Vec<String> = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => { dry_run = true; },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(positionals.len() == 1, "'init' requires exactly one directory path\n{}", USAGE);
+
+    let dir_path = Path::new(&positionals[0]).canonicalize()?;
+
+    let media_lib = open_library(global_opts)?;
+    let schema = default_schema(media_lib.root_dir())?;
+
+    let mut plan = WritePlan::new();
+    scaffold_dir(&media_lib, &schema, &dir_path, &mut plan)?;
+
+    finish_plan(global_opts, "init", plan, dry_run, vec![])
+}
+
+/// Reads the tag map file in the library root on top of the default field mapping, if there is one.
+fn default_field_mapping<P: AsRef<Path>>(root_dir: P) -> Result<FieldMapping> {
+    let mut mapping = FieldMapping::default();
+    let tag_map_fp = root_dir.as_ref().join(DEFAULT_TAG_MAP_FILE_NAME);
+
+    if tag_map_fp.is_file() {
+        let yaml = read_yaml_file(&tag_map_fp)?;
+        mapping.extend_from_yaml(&yaml).chain_err(|| format!("unable to read tag map file: '{}'", tag_map_fp.to_string_lossy()))?;
+    }
+
+    mapping.split_rules(default_split_rules(root_dir)?);
+
+    Ok(mapping)
+}
+
+/// Reads the split rules file in the library root, if there is one, on top of the built-in split rules.
+fn default_split_rules<P: AsRef<Path>>(root_dir: P) -> Result<SplitRules> {
+    let mut split_rules = SplitRules::default();
+    let split_fp = root_dir.as_ref().join(DEFAULT_SPLIT_FILE_NAME);
+
+    if split_fp.is_file() {
+        let yaml = read_yaml_file(&split_fp)?;
+        split_rules.extend_from_yaml(&yaml).chain_err(|| format!("unable to read split rules file: '{}'", split_fp.to_string_lossy()))?;
+    }
+
+    Ok(split_rules)
+}
+
+fn run_sync(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut opt_direction: Option<SyncDirection> = None;
+    let mut dry_run = false;
+    let mut extra_pairs: Vec<(String, String)> = vec![];
+    let mut positionals: Vec<String> = vec![];
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--direction" => {
+                let val = args.next().ok_or("missing value for '--direction'")?;
+                opt_direction = Some(val.parse()?);
+            },
+            "--dry-run" => { dry_run = true; },
+            "--map" => {
+                let val = args.next().ok_or("missing value for '--map'")?;
+
+                match val.find('=') {
+                    Some(i) => extra_pairs.push((val[..i].to_string(), val[i + 1..].to_string())),
+                    None => bail!("expected '<field>=<tag>' for '--map', got: '{}'", val),
+                }
+            },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    let direction = opt_direction.ok_or_else(|| format!("'sync' requires a direction\n{}", USAGE))?;
+    ensure!(positionals.len() == 1, "'sync' requires exactly one directory path\n{}", USAGE);
+
+    let dir_path = Path::new(&positionals[0]).canonicalize()?;
+
+    let media_lib = open_library(global_opts)?;
+    let mut mapping = default_field_mapping(media_lib.root_dir())?;
+
+    for (field_name, tag_name) in extra_pairs {
+        mapping.map(field_name, tag_name);
+    }
+
+    let mut plan = WritePlan::new();
+    let mut status_line = StatusLine::new(global_opts.show_progress);
+    let reports = sync_dir(&media_lib, &dir_path, &mapping, direction, &mut plan, &mut status_line)?;
+    status_line.finish();
+
+    let reports: Vec<_> = reports.into_iter().filter(|r| !r.is_in_sync()).collect();
+
+    if global_opts.output.is_json() {
+        let reports_json = reports.iter().map(|report| {
+            Json::object(vec![
+                ("item_path", Json::path(&report.item_path)),
+                ("tag_updates", Json::Object(report.tag_updates.iter().map(|(tag_name, vals)| {
+                    (tag_name.clone(), Json::Array(vals.iter().map(|v| Json::str(v.as_str())).collect()))
+                }).collect())),
+                ("yaml_updates", Json::Object(report.yaml_updates.iter().map(|(field_name, val)| {
+                    (field_name.clone(), Json::from_meta_value(val))
+                }).collect())),
+                ("conflicts", Json::Array(report.conflicts.iter().map(|conflict| {
+                    Json::object(vec![
+                        ("field", Json::str(conflict.field_name.as_str())),
+                        ("tag", Json::str(conflict.tag_name.as_str())),
+                        ("yaml_values", Json::Array(conflict.yaml_values.iter().map(|v| Json::str(v.as_str())).collect())),
+                        ("tag_values", Json::Array(conflict.tag_values.iter().map(|v| Json::str(v.as_str())).collect())),
+                    ])
+                }).collect())),
+            ])
+        }).collect();
+
+        return finish_plan(global_opts, "sync", plan, dry_run, vec![("reports", Json::Array(reports_json))]);
+    }
+
+    for report in reports {
+        println!("{}", report.item_path.to_string_lossy());
+
+        for (tag_name, vals) in &report.tag_updates {
+            println!("    set tag {}: {:?}", tag_name, vals);
+        }
+
+        for (field_name, val) in &report.yaml_updates {
+            println!("{}", pretty_entry(format!("set field {}", field_name), val, 4));
+        }
+
+        for conflict in &report.conflicts {
+            println!("    conflict: {}", conflict);
+        }
+    }
+
+    finish_plan(global_opts, "sync", plan, dry_run, vec![])
+}
+
+fn run_beets_import(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut dry_run = false;
+    let mut positionals: Vec<String> = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => { dry_run = true; },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(positionals.len() == 1, "'beets-import' requires exactly one listing file path\n{}", USAGE);
+
+    let listing_fp = Path::new(&positionals[0]);
+    let items = read_beets_items(&read_yaml_file(listing_fp)?)
+        .chain_err(|| format!("unable to read beets listing: '{}'", listing_fp.to_string_lossy()))?;
+
+    let media_lib = open_library(global_opts)?;
+
+    let mut plan = WritePlan::new();
+
+    let imported = import_beets_items(&media_lib, &items, &mut plan)?;
+
+    if global_opts.output.is_json() {
+        let imported_json = imported.iter().map(Json::path).collect();
+        return finish_plan(global_opts, "beets-import", plan, dry_run, vec![("imported", Json::Array(imported_json))]);
+    }
+
+    for item_path in imported {
+        println!("imported: {}", item_path.to_string_lossy());
+    }
+
+    finish_plan(global_opts, "beets-import", plan, dry_run, vec![])
+}
+
+fn run_beets_export(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut redact = false;
+    let mut positionals: Vec<String> = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--redact" => { redact = true; },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(positionals.len() == 1, "'beets-export' requires exactly one directory path\n{}", USAGE);
+
+    let dir_path = Path::new(&positionals[0]).canonicalize()?;
+
+    let media_lib = open_library(global_opts)?;
+
+    let mut redaction = Redaction::new();
+
+    if redact {
+        let redact_fp = media_lib.root_dir().join(DEFAULT_REDACT_FILE_NAME);
+
+        // Rule: redactions must be configured, rather than silently exporting everything.
+        ensure!(redact_fp.is_file(), "'--redact' requires a redaction file: '{}'", redact_fp.to_string_lossy());
+
+        let yaml = read_yaml_file(&redact_fp)?;
+        redaction.extend_from_yaml(&yaml).chain_err(|| format!("unable to read redaction file: '{}'", redact_fp.to_string_lossy()))?;
+    }
+
+    let mut status_line = StatusLine::new(global_opts.show_progress);
+    let listing = export_beets_items(&media_lib, &dir_path, &redaction, &mut status_line)?;
+    status_line.finish();
+
+    if global_opts.output.is_json() {
+        print_json("beets-export", vec![("items", Json::from_yaml(&listing))]);
+        return Ok(());
+    }
+
+    let mut buffer = String::new();
+    YamlEmitter::new(&mut buffer).dump(&listing).map_err(|e| format!("unable to emit YAML: {:?}", e))?;
+    println!("{}", buffer);
+
+    Ok(())
+}
+
+/// Asks a yes or no question on the terminal, where any answer but yes counts as no.
+#[cfg(feature = "discogs")]
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => true,
+        _ => false,
+    })
+}
+
+#[cfg(feature = "discogs")]
+fn run_discogs_import(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut dry_run = false;
+    let mut yes = false;
+    let mut positionals: Vec<String> = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => { dry_run = true; },
+            "--yes" => { yes = true; },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(positionals.len() == 1, "'discogs-import' requires exactly one directory path\n{}", USAGE);
+
+    let dir_path = Path::new(&positionals[0]).canonicalize()?;
+
+    let media_lib = open_library(global_opts)?;
+    let release = fetch_release(discogs_release_id(&media_lib, &dir_path)?)?;
+    let item_paths = media_lib.walk_children_paths(&dir_path)?;
+
+    // Tracks are matched up in order, so a different count means that some of them are likely to be wrong.
+    if item_paths.len() != release.tracks.len() && !yes {
+        ensure!(!global_opts.output.is_json(), "release has {} tracks, but the directory has {} items, use '--yes' to import anyway", release.tracks.len(), item_paths.len());
+
+        eprintln!("release {} ('{}') has {} tracks, but the directory has {} items:", release.release_id, release.title, release.tracks.len(), item_paths.len());
+
+        for i in 0..item_paths.len().max(release.tracks.len()) {
+            let item_name = item_paths.get(i).and_then(|p| p.file_name()).map_or_else(|| String::from("-"), |s| s.to_string_lossy().into_owned());
+            let track_title = match release.tracks.get(i).and_then(|mb| mb.get("title")) {
+                Some(&MetaValue::Str(ref title)) => title.clone(),
+                _ => String::from("-"),
+            };
+
+            eprintln!("    {} <- {}", item_name, track_title);
+        }
+
+        ensure!(confirm("import anyway?")?, "import cancelled");
+    }
+
+    let mut plan = WritePlan::new();
+    let imported = plan_release_import(&media_lib, &mut plan, &dir_path, &release)?;
+
+    if global_opts.output.is_json() {
+        let imported_json = imported.iter().map(Json::path).collect();
+        return finish_plan(global_opts, "discogs-import", plan, dry_run, vec![("imported", Json::Array(imported_json))]);
+    }
+
+    for item_path in imported {
+        println!("imported: {}", item_path.to_string_lossy());
+    }
+
+    finish_plan(global_opts, "discogs-import", plan, dry_run, vec![])
+}
+
+fn run_itunes_import(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut dry_run = false;
+    let mut opt_music_folder: Option<String> = None;
+    let mut positionals: Vec<String> = vec![];
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => { dry_run = true; },
+            "--music-folder" => {
+                let val = args.next().ok_or("missing value for '--music-folder'")?;
+                opt_music_folder = Some(val);
+            },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(positionals.len() == 1, "'itunes-import' requires exactly one library file path\n{}", USAGE);
+
+    let library_fp = Path::new(&positionals[0]);
+    let itunes_library = read_itunes_library(&fs::read_to_string(library_fp)?)
+        .chain_err(|| format!("unable to read iTunes library: '{}'", library_fp.to_string_lossy()))?;
+
+    // The music folder can be given as a path, or as a URL like the locations in the export.
+    let music_folder = opt_music_folder.map(|mf| file_url_path(&mf).unwrap_or(mf)).or(itunes_library.music_folder)
+        .ok_or_else(|| format!("iTunes library does not name its music folder, use '--music-folder'\n{}", USAGE))?;
+
+    let media_lib = open_library(global_opts)?;
+
+    let mut plan = WritePlan::new();
+
+    let (imported, unmatched) = import_itunes_tracks(&media_lib, &itunes_library.tracks, &music_folder, &mut plan)?;
+
+    if global_opts.output.is_json() {
+        let imported_json = imported.iter().map(Json::path).collect();
+        let unmatched_json = unmatched.iter().map(Json::str).collect();
+
+        return finish_plan(global_opts, "itunes-import", plan, dry_run, vec![
+            ("imported", Json::Array(imported_json)),
+            ("unmatched", Json::Array(unmatched_json)),
+        ]);
+    }
+
+    for item_path in imported {
+        println!("imported: {}", item_path.to_string_lossy());
+    }
+
+    for location in unmatched {
+        println!("unmatched: {}", location);
+    }
+
+    finish_plan(global_opts, "itunes-import", plan, dry_run, vec![])
+}
+
+#[cfg(feature = "lastfm")]
+fn run_lastfm_import(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut dry_run = false;
+    let mut positionals: Vec<String> = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => { dry_run = true; },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(positionals.len() == 1, "'lastfm-import' requires exactly one scrobble file path\n{}", USAGE);
+
+    let scrobbles_fp = Path::new(&positionals[0]);
+    let scrobbles = read_scrobbles(&fs::read_to_string(scrobbles_fp)?)
+        .chain_err(|| format!("unable to read scrobbles: '{}'", scrobbles_fp.to_string_lossy()))?;
+
+    let media_lib = open_library(global_opts)?;
+    let mut plan = WritePlan::new();
+    let mut status_line = StatusLine::new(global_opts.show_progress);
+    let report = import_scrobbles(&media_lib, &scrobbles, &mut plan, &mut status_line)?;
+    status_line.finish();
+
+    if global_opts.output.is_json() {
+        let updated_json = report.updated.iter()
+            .map(|(item_path, &plays)| Json::object(vec![("path", Json::path(item_path)), ("plays", Json::Int(plays as i64))]))
+            .collect();
+
+        let unmatched_json = report.unmatched.iter()
+            .map(|&(ref scrobble, failure)| Json::object(vec![
+                ("line", Json::Int(scrobble.line_num as i64)),
+                ("artist", Json::str(&scrobble.artist)),
+                ("title", Json::str(&scrobble.title)),
+                ("reason", Json::str(failure.to_string())),
+            ]))
+            .collect();
+
+        return finish_plan(global_opts, "lastfm-import", plan, dry_run, vec![
+            ("updated", Json::Array(updated_json)),
+            ("already_counted", Json::Int(report.already_counted as i64)),
+            ("unmatched", Json::Array(unmatched_json)),
+        ]);
+    }
+
+    for (item_path, plays) in &report.updated {
+        println!("updated: {} (+{})", item_path.to_string_lossy(), plays);
+    }
+
+    for &(ref scrobble, failure) in &report.unmatched {
+        println!("unmatched: line {}: {} - {} ({})", scrobble.line_num, scrobble.artist, scrobble.title, failure);
+    }
+
+    if report.already_counted > 0 {
+        println!("already counted: {}", report.already_counted);
+    }
+
+    finish_plan(global_opts, "lastfm-import", plan, dry_run, vec![])
+}
+
+fn run_mpd_stickers(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut field_names: Vec<String> = vec![];
+    let mut positionals: Vec<String> = vec![];
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--field" => {
+                let val = args.next().ok_or("missing value for '--field'")?;
+                field_names.push(val);
+            },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(positionals.len() == 1, "'mpd-stickers' requires exactly one directory path\n{}", USAGE);
+
+    let dir_path = Path::new(&positionals[0]).canonicalize()?;
+
+    let media_lib = open_library(global_opts)?;
+
+    // Without explicit fields, export the same fields that are synced to tags.
+    if field_names.is_empty() {
+        let mapping = default_field_mapping(media_lib.root_dir())?;
+        field_names = mapping.pairs().iter().map(|&(ref field_name, _)| field_name.clone()).collect();
+    }
+
+    let mut status_line = StatusLine::new(global_opts.show_progress);
+    let stickers = collect_stickers(&media_lib, &dir_path, &field_names, &mut status_line)?;
+    status_line.finish();
+
+    if global_opts.output.is_json() {
+        let stickers_json = stickers.iter().map(|sticker| {
+            Json::object(vec![
+                ("uri", Json::str(sticker.uri.as_str())),
+                ("name", Json::str(sticker.name.as_str())),
+                ("value", Json::str(sticker.value.as_str())),
+            ])
+        }).collect();
+
+        print_json("mpd-stickers", vec![("stickers", Json::Array(stickers_json))]);
+        return Ok(());
+    }
+
+    print!("{}", stickers_as_sql(&stickers));
+
+    Ok(())
+}
+
+fn run_cache(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    ensure!(args.len() == 1, "'cache' requires exactly one action\n{}", USAGE);
+
+    let media_lib = open_library(global_opts)?;
+    let root_dir = media_lib.root_dir();
+    let json_output = global_opts.output.is_json();
+
+    match args[0].as_str() {
+        "build" => {
+            media_lib.ensure_writable()?;
+
+            let mut lookup_ctx = LookupContext::new(&media_lib);
+            let mut status_line = StatusLine::new(global_opts.show_progress);
+            let mut cache = PersistentCache::build(&mut lookup_ctx, &mut status_line)?;
+            status_line.finish();
+
+            // Items keep the time that they were first seen, so that rebuilding the cache does not make them all look newly added.
+            cache.carry_over_first_seen(&PersistentCache::load(root_dir)?);
+
+            cache.save(root_dir)?;
+
+            if json_output {
+                print_json("cache", vec![
+                    ("action", Json::str("build")),
+                    ("meta_files", Json::Int(cache.len() as i64)),
+                    ("items", Json::Int(cache.item_count() as i64)),
+                ]);
+            }
+            else {
+                println!("cached {} meta files describing {} items", cache.len(), cache.item_count());
+            }
+        },
+        "verify" => {
+            let stale_paths = PersistentCache::load(root_dir)?.stale_paths(root_dir);
+
+            if json_output {
+                print_json("cache", vec![
+                    ("action", Json::str("verify")),
+                    ("stale", Json::Array(stale_paths.iter().map(Json::path).collect())),
+                ]);
+            }
+            else {
+                for stale_path in stale_paths {
+                    println!("stale: {}", stale_path.to_string_lossy());
+                }
+            }
+        },
+        "clear" => {
+            media_lib.ensure_writable()?;
+            let removed = PersistentCache::clear(root_dir)?;
+
+            if json_output {
+                print_json("cache", vec![
+                    ("action", Json::str("clear")),
+                    ("removed", Json::Bool(removed)),
+                ]);
+            }
+            else if removed {
+                println!("removed cache");
+            }
+        },
+        "stats" => {
+            let stats = PersistentCache::load(root_dir)?.stats(root_dir);
+
+            if json_output {
+                print_json("cache", vec![
+                    ("action", Json::str("stats")),
+                    ("meta_files", Json::Int(stats.meta_files as i64)),
+                    ("items", Json::Int(stats.items as i64)),
+                    ("stale", Json::Int(stats.stale as i64)),
+                    ("size_bytes", Json::Int(stats.size_bytes as i64)),
+                    ("hit_rate", Json::Float(stats.hit_rate())),
+                ]);
+
+                return Ok(());
+            }
+
+            println!("meta files: {}", stats.meta_files);
+            println!("items: {}", stats.items);
+            println!("stale: {}", stats.stale);
+            println!("size: {} bytes", stats.size_bytes);
+            println!("hit rate: {:.1}%", stats.hit_rate() * 100.0);
+        },
+        action => bail!("unknown cache action: '{}'\n{}", action, USAGE),
+    }
+
+    Ok(())
+}
+
+fn run_refactor(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut dry_run = false;
+    let mut opt_field_name: Option<String> = None;
+    let mut opt_from: Option<String> = None;
+    let mut opt_to: Option<String> = None;
+    let mut positionals: Vec<String> = vec![];
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => { dry_run = true; },
+            "--field" => { opt_field_name = Some(args.next().ok_or("missing value for '--field'")?); },
+            "--from" => { opt_from = Some(args.next().ok_or("missing value for '--from'")?); },
+            "--to" => { opt_to = Some(args.next().ok_or("missing value for '--to'")?); },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(!positionals.is_empty(), "'refactor' requires an action\n{}", USAGE);
+    let action = positionals.remove(0);
+
+    let media_lib = open_library(global_opts)?;
+    let mut plan = WritePlan::new();
+
+    match action.as_str() {
+        "rename-field" => {
+            ensure!(positionals.len() == 2, "'refactor rename-field' requires an old and a new field name\n{}", USAGE);
+
+            let touched = rename_field(&media_lib, &positionals[0], &positionals[1], &mut plan)?;
+
+            if global_opts.output.is_json() {
+                return finish_plan(global_opts, "refactor", plan, dry_run, vec![
+                    ("action", Json::str("rename-field")),
+                    ("touched", Json::Array(touched.iter().map(Json::path).collect())),
+                ]);
+            }
+
+            println!("renamed field '{}' to '{}' in {} meta files", positionals[0], positionals[1], touched.len());
+        },
+        "replace" => {
+            ensure!(positionals.is_empty(), "'refactor replace' takes no positional arguments\n{}", USAGE);
+
+            let field_name = opt_field_name.ok_or_else(|| format!("'refactor replace' requires '--field'\n{}", USAGE))?;
+            let from = opt_from.ok_or_else(|| format!("'refactor replace' requires '--from'\n{}", USAGE))?;
+            let to = opt_to.ok_or_else(|| format!("'refactor replace' requires '--to'\n{}", USAGE))?;
+
+            let regex = Regex::new(&from).chain_err(|| format!("invalid pattern for '--from': '{}'", from))?;
+            let touched = replace_values(&media_lib, &field_name, &regex, &to, &mut plan)?;
+
+            if global_opts.output.is_json() {
+                let touched_json = touched.iter().map(|&(ref meta_fp, count)| {
+                    Json::object(vec![
+                        ("path", Json::path(meta_fp)),
+                        ("values", Json::Int(count as i64)),
+                    ])
+                }).collect();
+
+                return finish_plan(global_opts, "refactor", plan, dry_run, vec![
+                    ("action", Json::str("replace")),
+                    ("touched", Json::Array(touched_json)),
+                ]);
+            }
+
+            let total: usize = touched.iter().map(|&(_, count)| count).sum();
+            println!("replaced {} values of field '{}' in {} meta files", total, field_name, touched.len());
+        },
+        "split-values" => {
+            ensure!(positionals.is_empty(), "'refactor split-values' takes no positional arguments\n{}", USAGE);
+
+            let split_rules = default_split_rules(media_lib.root_dir())?;
+            let touched = split_values(&media_lib, &split_rules, opt_field_name.as_ref().map(String::as_str), &mut plan)?;
+
+            if global_opts.output.is_json() {
+                let touched_json = touched.iter().map(|&(ref meta_fp, count)| {
+                    Json::object(vec![
+                        ("path", Json::path(meta_fp)),
+                        ("values", Json::Int(count as i64)),
+                    ])
+                }).collect();
+
+                return finish_plan(global_opts, "refactor", plan, dry_run, vec![
+                    ("action", Json::str("split-values")),
+                    ("touched", Json::Array(touched_json)),
+                ]);
+            }
+
+            let total: usize = touched.iter().map(|&(_, count)| count).sum();
+            println!("split {} values in {} meta files", total, touched.len());
+        },
+        "split-sidecars" => {
+            ensure!(positionals.len() == 1, "'refactor split-sidecars' requires a meta file\n{}", USAGE);
+
+            let meta_fp = Path::new(&positionals[0]).canonicalize()?;
+            let created = split_into_sidecars(&media_lib, &meta_fp, &mut plan)?;
+
+            if global_opts.output.is_json() {
+                return finish_plan(global_opts, "refactor", plan, dry_run, vec![
+                    ("action", Json::str("split-sidecars")),
+                    ("created", Json::Array(created.iter().map(Json::path).collect())),
+                ]);
+            }
+
+            println!("split '{}' into {} sidecar meta files", meta_fp.to_string_lossy(), created.len());
+        },
+        _ => bail!("unknown refactor action: '{}'\n{}", action, USAGE),
+    }
+
+    finish_plan(global_opts, "refactor", plan, dry_run, vec![])
+}
+
+fn run_apply(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut opt_csv_path: Option<PathBuf> = None;
+    let mut dry_run = false;
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--csv" => {
+                let val = args.next().ok_or("missing value for '--csv'")?;
+                opt_csv_path = Some(PathBuf::from(val));
+            },
+            "--dry-run" => { dry_run = true; },
+            _ => bail!("unexpected argument for 'apply': '{}'\n{}", arg, USAGE),
+        }
+    }
+
+    let csv_path = opt_csv_path.ok_or_else(|| format!("'apply' requires a CSV file of changes\n{}", USAGE))?;
+    let rows = read_retag_csv(&fs::read_to_string(&csv_path)?)
+        .chain_err(|| format!("unable to read changes: '{}'", csv_path.to_string_lossy()))?;
+
+    let media_lib = open_library(global_opts)?;
+    let schema = default_schema(media_lib.root_dir())?;
+    let split_rules = default_split_rules(media_lib.root_dir())?;
+
+    let mut lookup_ctx = LookupContext::new(&media_lib);
+    lookup_ctx.set_ontology(default_ontology(media_lib.root_dir())?);
+    lookup_ctx.set_sort_name_rules(default_sort_name_rules(media_lib.root_dir())?);
+
+    // Relative paths in the CSV file are taken to be relative to the current directory, as with paths given as arguments.
+    let current_dir = env::current_dir()?.canonicalize()?;
+    let mut plan = WritePlan::new();
+    let report = plan_retag(&mut lookup_ctx, &rows, &current_dir, &schema, &split_rules, &mut plan)?;
+
+    for &line_num in &report.unmatched {
+        warn!("query on line {} matched no items", line_num);
+    }
+
+    if !report.violations.is_empty() {
+        if global_opts.output.is_json() {
+            print_json("apply", vec![
+                ("violations", Json::Array(report.violations.iter().map(|v| {
+                    Json::object(vec![
+                        ("item_path", Json::path(&v.item_path)),
+                        ("field", Json::str(v.field_name.as_str())),
+                        ("message", Json::str(v.kind.to_string())),
+                    ])
+                }).collect())),
+            ]);
+        }
+        else {
+            for violation in &report.violations {
+                println!("{}", violation);
+            }
+        }
+
+        // Nothing has been written, and the violations have been printed already.
+        process::exit(1);
+    }
+
+    let edited_json = report.edited.iter()
+        .map(|&(ref item_path, count)| Json::object(vec![("path", Json::path(item_path)), ("fields", Json::Int(count as i64))]))
+        .collect();
+
+    finish_plan(global_opts, "apply", plan, dry_run, vec![
+        ("edited", Json::Array(edited_json)),
+        ("unmatched", Json::Array(report.unmatched.iter().map(|&n| Json::Int(n as i64)).collect())),
+    ])
+}
+
+fn run_move(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut dry_run = false;
+    let mut positionals: Vec<String> = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => { dry_run = true; },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(positionals.len() == 2, "'move' requires an item and a destination\n{}", USAGE);
+
+    // The destination does not exist yet, so only the source can be canonicalized.
+    let src_path = Path::new(&positionals[0]).canonicalize()?;
+    let dst_path = normalize(env::current_dir()?.canonicalize()?.join(&positionals[1]));
+
+    let media_lib = open_library(global_opts)?;
+
+    if dry_run {
+        let mut plan = WritePlan::new();
+        plan_move_item_meta(&media_lib, &mut plan, &src_path, &dst_path)?;
+
+        if !global_opts.output.is_json() {
+            println!("move item: {} -> {}", src_path.to_string_lossy(), dst_path.to_string_lossy());
+        }
+
+        return finish_plan(global_opts, "move", plan, true, vec![
+            ("from", Json::path(&src_path)),
+            ("to", Json::path(&dst_path)),
+        ]);
+    }
+
+    // The plan is only needed to record what the move is about to change.
+    let mut plan = WritePlan::new();
+    plan_move_item_meta(&media_lib, &mut plan, &src_path, &dst_path)?;
+    undo_journal(global_opts).record("move", &[(src_path.clone(), dst_path.clone())], &plan)?;
+
+    let touched = media_lib.move_item(&src_path, &dst_path)?;
+
+    if global_opts.output.is_json() {
+        print_json("move", vec![
+            ("from", Json::path(&src_path)),
+            ("to", Json::path(&dst_path)),
+            ("dry_run", Json::Bool(false)),
+            ("meta_files", Json::Array(touched.iter().map(Json::path).collect())),
+        ]);
+    }
+    else {
+        println!("moved: {} -> {}", src_path.to_string_lossy(), dst_path.to_string_lossy());
+
+        for meta_fp in &touched {
+            println!("wrote: {}", meta_fp.to_string_lossy());
+        }
+    }
+
+    Ok(())
+}
+
+fn run_played(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    ensure!(!args.is_empty(), "'played' requires at least one item path\n{}", USAGE);
+
+    let media_lib = open_library(global_opts)?;
+    let mut counts = vec![];
+
+    for arg in &args {
+        let item_path = Path::new(arg).canonicalize()?;
+        let count = media_lib.increment_field(&item_path, PLAY_COUNT_KEY)?;
+
+        counts.push((item_path, count));
+    }
+
+    if global_opts.output.is_json() {
+        let counts_json = counts.iter().map(|&(ref item_path, count)| {
+            Json::object(vec![("path", Json::path(item_path)), ("playcount", Json::Int(count as i64))])
+        }).collect();
+
+        print_json("played", vec![("items", Json::Array(counts_json))]);
+    }
+    else {
+        for (item_path, count) in counts {
+            println!("{}: {}", item_path.to_string_lossy(), count);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_rate(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    ensure!(args.len() == 2, "'rate' requires an item and a rating\n{}", USAGE);
+
+    let item_path = Path::new(&args[0]).canonicalize()?;
+    let opt_rating = match args[1].as_str() {
+        "none" => None,
+        s => Some(s.parse::<u8>().chain_err(|| format!("invalid rating: '{}'", s))?),
+    };
+
+    let media_lib = open_library(global_opts)?;
+    media_lib.set_rating(&item_path, opt_rating)?;
+
+    if global_opts.output.is_json() {
+        print_json("rate", vec![
+            ("path", Json::path(&item_path)),
+            ("rating", opt_rating.map_or(Json::Null, |r| Json::Int(i64::from(r)))),
+        ]);
+    }
+
+    Ok(())
+}
+
+/// Renders a field value as a single line, for a column of query output.
+fn field_text(opt_mv: Option<&MetaValue>) -> String {
+    match opt_mv {
+        None | Some(&MetaValue::Nil) => String::new(),
+        Some(&MetaValue::Str(ref s)) => s.clone(),
+        Some(&MetaValue::Seq(ref mvs)) => mvs.iter().map(|mv| field_text(Some(mv))).collect::<Vec<_>>().join("; "),
+        Some(mv) => mv.to_string().replace('\n', " "),
+    }
+}
+
+fn run_query(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    ensure!(args.len() == 1, "'query' requires exactly one query\n{}", USAGE);
+
+    let query = Query::parse(&args[0])?;
+
+    let media_lib = open_library(global_opts)?;
+    let mut lookup_ctx = LookupContext::new(&media_lib);
+    lookup_ctx.set_ontology(default_ontology(media_lib.root_dir())?);
+    lookup_ctx.set_sort_name_rules(default_sort_name_rules(media_lib.root_dir())?);
+    let rows = run_library_query(&mut lookup_ctx, media_lib.root_dir(), &query)?;
+
+    if global_opts.output.is_json() {
+        print_json("query", vec![
+            ("items", Json::Array(rows.iter().map(|row| {
+                Json::object(vec![
+                    ("path", Json::path(&row.item_path)),
+                    ("fields", Json::object(row.fields.iter().map(|&(ref name, ref opt_mv)| (name.as_str(), meta_value_json(opt_mv.as_ref()))).collect())),
+                ])
+            }).collect())),
+        ]);
+    }
+    else {
+        // One line per item, with the projected fields in tab-separated columns after the path.
+        for row in &rows {
+            let mut columns = vec![row.item_path.to_string_lossy().into_owned()];
+            columns.extend(row.fields.iter().map(|&(_, ref opt_mv)| field_text(opt_mv.as_ref())));
+
+            println!("{}", columns.join("\t"));
+        }
+    }
+
+    Ok(())
+}
+
+fn run_playlist(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut opt_query_name: Option<String> = None;
+    let mut format = PlaylistFormat::M3u;
+    let mut opt_out_path: Option<PathBuf> = None;
+    let mut opt_added_within: Option<u64> = None;
+    let mut opt_modified_within: Option<u64> = None;
+    let mut dry_run = false;
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--query" => {
+                let val = args.next().ok_or("missing value for '--query'")?;
+                opt_query_name = Some(val);
+            },
+            "--added-within" => {
+                let val = args.next().ok_or("missing value for '--added-within'")?;
+                opt_added_within = Some(val.parse().chain_err(|| format!("invalid number of days: '{}'", val))?);
+            },
+            "--modified-within" => {
+                let val = args.next().ok_or("missing value for '--modified-within'")?;
+                opt_modified_within = Some(val.parse().chain_err(|| format!("invalid number of days: '{}'", val))?);
+            },
+            "--format" => {
+                let val = args.next().ok_or("missing value for '--format'")?;
+                format = val.parse()?;
+            },
+            "--out" => {
+                let val = args.next().ok_or("missing value for '--out'")?;
+                opt_out_path = Some(PathBuf::from(val));
+            },
+            "--dry-run" => { dry_run = true; },
+            _ => bail!("unexpected argument for 'playlist': '{}'\n{}", arg, USAGE),
+        }
+    }
+
+    let query_name = opt_query_name.ok_or_else(|| format!("'playlist' requires a saved query\n{}", USAGE))?;
+
+    let media_lib = open_library(global_opts)?;
+    let queries_fp = media_lib.root_dir().join(DEFAULT_QUERIES_FILE_NAME);
+
+    let saved_queries = if queries_fp.is_file() {
+        let yaml = read_yaml_file(&queries_fp)?;
+        read_saved_queries(&yaml).chain_err(|| format!("unable to read queries file: '{}'", queries_fp.to_string_lossy()))?
+    }
+    else {
+        Default::default()
+    };
+
+    let query = match saved_queries.get(&query_name) {
+        Some(query) => query,
+        None => {
+            let known: Vec<&str> = saved_queries.keys().map(String::as_str).collect();
+            bail!("unknown saved query: '{}', expected one of: {}", query_name, known.join(", "));
+        },
+    };
+
+    let mut lookup_ctx = LookupContext::new(&media_lib);
+    lookup_ctx.set_ontology(default_ontology(media_lib.root_dir())?);
+    lookup_ctx.set_sort_name_rules(default_sort_name_rules(media_lib.root_dir())?);
+    let mut rows = run_library_query(&mut lookup_ctx, media_lib.root_dir(), query)?;
+
+    let days_ago = |days: u64| SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+
+    if let Some(days) = opt_added_within {
+        let added: BTreeSet<PathBuf> = media_lib.items_added_since(days_ago(days))?.into_iter().collect();
+        rows.retain(|row| added.contains(&row.item_path));
+    }
+
+    if let Some(days) = opt_modified_within {
+        let modified: BTreeSet<PathBuf> = media_lib.items_modified_since(days_ago(days))?.into_iter().collect();
+        rows.retain(|row| modified.contains(&row.item_path));
+    }
+
+    match opt_out_path {
+        Some(out_path) => {
+            media_lib.ensure_writable()?;
+
+            // Make the path absolute, so that item paths can be written relative to the playlist's directory.
+            let out_path = normalize(&env::current_dir()?.join(out_path));
+            let text = render_playlist(&rows, format, out_path.parent());
+
+            let mut plan = WritePlan::new();
+            plan.write_text(&out_path, text);
+
+            finish_plan(global_opts, "playlist", plan, dry_run, vec![
+                ("query", Json::str(query_name.as_str())),
+                ("items", Json::Array(rows.iter().map(|row| Json::path(&row.item_path)).collect())),
+            ])
+        },
+        None => {
+            if global_opts.output.is_json() {
+                print_json("playlist", vec![
+                    ("query", Json::str(query_name.as_str())),
+                    ("items", Json::Array(rows.iter().map(|row| Json::path(&row.item_path)).collect())),
+                ]);
+            }
+            else {
+                print!("{}", render_playlist(&rows, format, None));
+            }
+
+            Ok(())
+        },
+    }
+}
+
+fn run_compare(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut match_by = MatchBy::Path;
+    let mut positionals: Vec<String> = vec![];
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--match" => {
+                let val = args.next().ok_or("missing value for '--match'")?;
+                match_by = val.parse()?;
+            },
+            _ => { positionals.push(arg); },
+        }
+    }
+
+    ensure!(positionals.len() == 2, "'compare' requires exactly two library roots\n{}", USAGE);
+
+    let media_lib_a = library_at(global_opts, Path::new(&positionals[0]))?;
+    let media_lib_b = library_at(global_opts, Path::new(&positionals[1]))?;
+
+    let mut status_line = StatusLine::new(global_opts.show_progress);
+    let report = compare_libraries(&media_lib_a, &media_lib_b, match_by, &mut status_line)?;
+    status_line.finish();
+
+    if global_opts.output.is_json() {
+        print_json("compare", vec![("report", report.as_json())]);
+    }
+    else {
+        for rel_path in &report.missing {
+            println!("missing: {}", rel_path.to_string_lossy());
+        }
+
+        for rel_path in &report.extra {
+            println!("extra: {}", rel_path.to_string_lossy());
+        }
+
+        for item_diff in &report.changed {
+            if item_diff.rel_path_a == item_diff.rel_path_b {
+                println!("changed: {}", item_diff.rel_path_a.to_string_lossy());
+            }
+            else {
+                println!("changed: {} -> {}", item_diff.rel_path_a.to_string_lossy(), item_diff.rel_path_b.to_string_lossy());
+            }
+
+            for field_diff in &item_diff.fields {
+                let value_text = |opt_mv: &Option<MetaValue>| match *opt_mv {
+                    Some(ref mv) => format!("'{}'", field_text(Some(mv))),
+                    None => String::from("(not set)"),
+                };
+
+                println!("    {}: {} -> {}", field_diff.field_name, value_text(&field_diff.value_a), value_text(&field_diff.value_b));
+            }
+        }
+
+        println!(
+            "{} items matched, {} changed, {} missing, {} extra",
+            report.matched, report.changed.len(), report.missing.len(), report.extra.len(),
+        );
+    }
+
+    // As with lint, the differences have been printed already, so the error status is all that is left to report.
+    if !report.is_same() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn run_lint(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut fix_names = false;
+    let mut dry_run = false;
+    let mut typo_fields: Vec<String> = vec![];
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fix-names" => { fix_names = true; },
+            "--dry-run" => { dry_run = true; },
+            "--typo-field" => {
+                let val = args.next().ok_or("missing value for '--typo-field'")?;
+                typo_fields.push(val);
+            },
+            _ => bail!("unexpected argument for 'lint': '{}'\n{}", arg, USAGE),
+        }
+    }
+
+    // Rule: --dry-run only applies to the renames of --fix-names.
+    ensure!(!dry_run || fix_names, "'lint' only accepts --dry-run along with --fix-names\n{}", USAGE);
+
+    let media_lib = open_library(global_opts)?;
+    let schema = default_schema(media_lib.root_dir())?;
+    let naming_rules = default_naming_rules(media_lib.root_dir())?;
+
+    let mut status_line = StatusLine::new(global_opts.show_progress);
+    let violations = media_lib.validate_schema(&schema, &mut status_line)?;
+    status_line.finish();
+
+    let root_dir = media_lib.root_dir().to_path_buf();
+    let misaligned = misaligned_meta_files(&media_lib, &root_dir)?;
+
+    let mut lookup_ctx = LookupContext::new(&media_lib);
+    lookup_ctx.set_sort_name_rules(default_sort_name_rules(media_lib.root_dir())?);
+
+    let mut mismatches = if naming_rules.is_empty() { vec![] } else { check_names(&mut lookup_ctx, &root_dir, &naming_rules)? };
+    let tracklists = check_tracklists(&mut lookup_ctx, &root_dir)?;
+
+    if typo_fields.is_empty() {
+        typo_fields = DEFAULT_TYPO_FIELDS.iter().map(|s| s.to_string()).collect();
+    }
+
+    let mut similar_values = vec![];
+
+    for field_name in &typo_fields {
+        similar_values.extend(find_similar_values(&media_lib, field_name)?);
+    }
+
+    let mut renamed = vec![];
+
+    if fix_names {
+        let mut plan = WritePlan::new();
+
+        for mismatch in mismatches.split_off(0) {
+            let src_path = mismatch.item_path.clone();
+            let dst_path = mismatch.expected_path();
+
+            // Items are never renamed over existing files, e.g. when two items would get the same name.
+            if dst_path.exists() || renamed.iter().any(|&(_, ref p)| *p == dst_path) {
+                warn!("not renaming, target already exists: '{}'", dst_path.to_string_lossy());
+                mismatches.push(mismatch);
+                continue;
+            }
+
+            if dry_run {
+                plan_move_item_meta(&media_lib, &mut plan, &src_path, &dst_path)?;
+            }
+            else {
+                // The plan is only needed to record what the rename is about to change.
+                let mut move_plan = WritePlan::new();
+                plan_move_item_meta(&media_lib, &mut move_plan, &src_path, &dst_path)?;
+                undo_journal(global_opts).record("lint", &[(src_path.clone(), dst_path.clone())], &move_plan)?;
+
+                media_lib.move_item(&src_path, &dst_path)?;
+            }
+
+            renamed.push((src_path, dst_path));
+        }
+
+        if dry_run && !global_opts.output.is_json() {
+            for &(ref src_path, ref dst_path) in &renamed {
+                println!("rename item: {} -> {}", src_path.to_string_lossy(), dst_path.to_string_lossy());
+            }
+
+            print!("{}", plan.diff()?);
+        }
+    }
+
+    if global_opts.output.is_json() {
+        print_json("lint", vec![
+            ("violations", Json::Array(violations.iter().map(|v| {
+                Json::object(vec![
+                    ("item_path", Json::path(&v.item_path)),
+                    ("meta_path", v.meta_path.as_ref().map_or(Json::Null, Json::path)),
+                    ("field", Json::str(v.field_name.as_str())),
+                    ("message", Json::str(v.kind.to_string())),
+                ])
+            }).collect())),
+            ("misaligned", Json::Array(misaligned.iter().map(plex_report_json).collect())),
+            ("tracklists", Json::Array(tracklists.iter().map(tracklist_report_json).collect())),
+            ("similar_values", Json::Array(similar_values.iter().map(similar_values_json).collect())),
+            ("names", Json::Array(mismatches.iter().map(|m| {
+                Json::object(vec![
+                    ("item_path", Json::path(&m.item_path)),
+                    ("expected_name", Json::str(m.expected_name.as_str())),
+                ])
+            }).collect())),
+            ("renamed", Json::Array(renamed.iter().map(|&(ref src_path, ref dst_path)| {
+                Json::object(vec![
+                    ("from", Json::path(src_path)),
+                    ("to", Json::path(dst_path)),
+                ])
+            }).collect())),
+            ("dry_run", Json::Bool(dry_run)),
+        ]);
+    }
+    else {
+        for violation in &violations {
+            println!("{}", violation);
+        }
+
+        for report in &misaligned {
+            println!("{}", report);
+        }
+
+        for report in &tracklists {
+            println!("{}", report);
+        }
+
+        for report in &similar_values {
+            println!("{}", report);
+        }
+
+        for mismatch in &mismatches {
+            println!("{}", mismatch);
+        }
+
+        if !dry_run {
+            for &(ref src_path, ref dst_path) in &renamed {
+                println!("renamed: {} -> {}", src_path.to_string_lossy(), dst_path.to_string_lossy());
+            }
+        }
+    }
+
+    // The violations have been printed already, so the error status is all that is left to report.
+    // Names that were (or would be) fixed no longer count against the library, and similar values might be intended, so they never do.
+    if !violations.is_empty() || !misaligned.is_empty() || !tracklists.is_empty() || !mismatches.is_empty() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Lines up the blocks of every meta file in a directory tree with its items, and returns the meta files where they do not line up.
+/// For a scoped library, only meta files in directories in the scope are checked.
+fn misaligned_meta_files(media_lib: &Library, abs_dir_path: &Path) -> Result<Vec<PlexCheckReport>> {
+    let mut misaligned = vec![];
+
+    for meta_fp in media_lib.meta_fps_in_tree(abs_dir_path)? {
+        if !meta_fp.parent().map_or(false, |p| media_lib.is_in_scope(p)) {
+            continue;
+        }
+
+        if let Some(report) = media_lib.plex_check_meta_file(&meta_fp)? {
+            if !report.is_consistent() {
+                misaligned.push(report);
+            }
+        }
+    }
+
+    Ok(misaligned)
+}
+
+fn similar_values_json(report: &SimilarValues) -> Json {
+    Json::object(vec![
+        ("field", Json::str(report.field_name.as_str())),
+        ("values", Json::Array(report.values.iter().map(|value_use| {
+            Json::object(vec![
+                ("value", Json::str(value_use.value.as_str())),
+                ("count", Json::Int(value_use.item_count as i64)),
+                ("meta_paths", Json::Array(value_use.meta_fps.iter().map(Json::path).collect())),
+            ])
+        }).collect())),
+    ])
+}
+
+fn tracklist_report_json(report: &TracklistReport) -> Json {
+    Json::object(vec![
+        ("dir_path", Json::path(&report.dir_path)),
+        ("issues", Json::Array(report.issues.iter().map(|issue| {
+            match *issue {
+                TrackIssue::Missing(position, ref title) => Json::object(vec![
+                    ("kind", Json::str("missing")),
+                    ("position", Json::Int(position as i64)),
+                    ("title", Json::str(title.as_str())),
+                ]),
+                TrackIssue::Extra(ref item_path) => Json::object(vec![
+                    ("kind", Json::str("extra")),
+                    ("item_path", Json::path(item_path)),
+                ]),
+                TrackIssue::Misordered(ref item_path, position) => Json::object(vec![
+                    ("kind", Json::str("misordered")),
+                    ("item_path", Json::path(item_path)),
+                    ("position", Json::Int(position as i64)),
+                ]),
+            }
+        }).collect())),
+    ])
+}
+
+fn plex_report_json(report: &PlexCheckReport) -> Json {
+    Json::object(vec![
+        ("meta_path", Json::path(&report.meta_path)),
+        ("alignments", Json::Array(report.alignments.iter().map(|a| {
+            let (block, item) = match *a {
+                Alignment::Matched(ref label, ref item_name) => (Json::str(label.as_str()), Json::str(item_name.as_str())),
+                Alignment::MissingBlock(ref item_name) => (Json::Null, Json::str(item_name.as_str())),
+                Alignment::ExcessBlock(ref label) => (Json::str(label.as_str()), Json::Null),
+            };
+
+            Json::object(vec![("block", block), ("item", item)])
+        }).collect())),
+    ])
+}
+
+fn run_plex_check(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    ensure!(args.len() <= 1, "'plex-check' accepts at most one directory path\n{}", USAGE);
+
+    let media_lib = open_library(global_opts)?;
+
+    // A directory given explicitly has all of its meta files shown, while a whole library only has the ones that do not line up.
+    let reports = match args.first() {
+        Some(dir_arg) => media_lib.plex_check(Path::new(dir_arg).canonicalize()?)?,
+        None => {
+            let root_dir = media_lib.root_dir().to_path_buf();
+            misaligned_meta_files(&media_lib, &root_dir)?
+        },
+    };
+
+    if global_opts.output.is_json() {
+        print_json("plex-check", vec![("reports", Json::Array(reports.iter().map(plex_report_json).collect()))]);
+    }
+    else {
+        for report in &reports {
+            println!("{}", report);
+        }
+    }
+
+    if reports.iter().any(|r| !r.is_consistent()) {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Prints the problems found in a meta file, replacing any problems printed for it before.
+fn print_diagnostics(global_opts: &GlobalOpts, meta_fp: &Path, diagnostics: &[Diagnostic], had_diagnostics: bool) {
+    if global_opts.output.is_json() {
+        print_json("check", vec![
+            ("path", Json::path(meta_fp)),
+            ("diagnostics", Json::Array(diagnostics.iter().map(Diagnostic::as_json).collect())),
+        ]);
+    }
+    else if diagnostics.is_empty() && had_diagnostics {
+        println!("{}: no problems", meta_fp.to_string_lossy());
+    }
+    else {
+        for diagnostic in diagnostics {
+            println!("{}", diagnostic);
+        }
+    }
+}
+
+fn run_check(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut watch = false;
+    let mut interval = Duration::from_millis(DEFAULT_WATCH_INTERVAL_MS);
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => { watch = true; },
+            "--interval" => {
+                let ms = args.next().ok_or("missing value for '--interval'")?;
+                interval = Duration::from_millis(ms.parse().chain_err(|| format!("invalid interval: '{}'", ms))?);
+            },
+            _ => bail!("unexpected argument for 'check': '{}'\n{}", arg, USAGE),
+        }
+    }
+
+    let media_lib = open_library(global_opts)?;
+    let schema_fp = media_lib.root_dir().join(DEFAULT_SCHEMA_FILE_NAME);
+    let ontology_fp = media_lib.root_dir().join(DEFAULT_ONTOLOGY_FILE_NAME);
+
+    let mut schema = default_schema(media_lib.root_dir())?;
+    let mut schema_stamp = (FileStamp::read(&schema_fp), FileStamp::read(&ontology_fp));
+    let mut with_diagnostics: BTreeSet<PathBuf> = BTreeSet::new();
+
+    if !watch {
+        let mut all_diagnostics = vec![];
+
+        for meta_fp in MetaFileWatcher::new().poll(&media_lib)? {
+            let diagnostics = check_meta_file(&media_lib, &schema, &meta_fp)?;
+
+            // JSON lines are printed as each meta file is checked, so that long checks show progress.
+            if global_opts.output == OutputFormat::JsonLines && !diagnostics.is_empty() {
+                print_diagnostics(global_opts, &meta_fp, &diagnostics, false);
+            }
+
+            all_diagnostics.extend(diagnostics);
+        }
+
+        match global_opts.output {
+            OutputFormat::Json => {
+                print_json("check", vec![("diagnostics", Json::Array(all_diagnostics.iter().map(Diagnostic::as_json).collect()))]);
+            },
+            OutputFormat::Text => {
+                for diagnostic in &all_diagnostics {
+                    println!("{}", diagnostic);
+                }
+            },
+            OutputFormat::JsonLines => {},
+        }
+
+        // The problems have been printed already, so the error status is all that is left to report.
+        if !all_diagnostics.is_empty() {
+            process::exit(1);
+        }
+
+        return Ok(());
+    }
+
+    let mut watcher = PollingWatcher::new(interval);
+
+    loop {
+        let events = watcher.next_events(&media_lib)?;
+        let meta_fps = watcher.meta_fps();
+
+        // Items added to or removed from a directory can change the problems of the meta files in it.
+        let mut changed: Vec<PathBuf> = {
+            events.iter()
+                .flat_map(|event| match *event {
+                    WatchEvent::MetaFile(ref meta_fp) => vec![meta_fp.clone()],
+                    WatchEvent::Dir(ref dir_path) => meta_fps.iter().filter(|p| p.parent() == Some(dir_path.as_path())).cloned().collect(),
+                })
+                .collect()
+        };
+        changed.sort();
+        changed.dedup();
+
+        // A changed schema or ontology can change the problems of every meta file.
+        let new_schema_stamp = (FileStamp::read(&schema_fp), FileStamp::read(&ontology_fp));
+
+        if new_schema_stamp != schema_stamp {
+            schema_stamp = new_schema_stamp;
+
+            match default_schema(media_lib.root_dir()) {
+                Ok(new_schema) => {
+                    schema = new_schema;
+                    changed = watcher.meta_fps();
+                },
+                Err(e) => warn!("unable to read schema, keeping the previous one: {}", e),
+            }
+        }
+
+        for meta_fp in changed {
+            // Files can change again while they are being checked, so problems are reported and the watch goes on.
+            let diagnostics = match check_meta_file(&media_lib, &schema, &meta_fp) {
+                Ok(diagnostics) => diagnostics,
+                Err(e) => {
+                    warn!("unable to check meta file: '{}': {}", meta_fp.to_string_lossy(), e);
+                    continue;
+                },
+            };
+
+            let had_diagnostics = with_diagnostics.contains(&meta_fp);
+
+            // Editors only need to hear about files whose problems appeared or went away.
+            if !diagnostics.is_empty() || had_diagnostics {
+                print_diagnostics(global_opts, &meta_fp, &diagnostics, had_diagnostics);
+            }
+
+            if diagnostics.is_empty() {
+                with_diagnostics.remove(&meta_fp);
+            }
+            else {
+                with_diagnostics.insert(meta_fp);
+            }
+        }
+
+        io::stdout().flush()?;
+    }
+}
+
+fn run_stats(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut opt_field_name = None;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--field" => {
+                let val = args.next().ok_or("missing value for '--field'")?;
+                opt_field_name = Some(val);
+            },
+            _ => bail!("unexpected argument for 'stats': '{}'\n{}", arg, USAGE),
+        }
+    }
+
+    let field_name = opt_field_name.ok_or_else(|| format!("'stats' requires a field\n{}", USAGE))?;
+
+    let media_lib = open_library(global_opts)?;
+
+    let mut counts: Vec<(String, usize)> = media_lib.field_histogram(&field_name)?.into_iter().collect();
+    counts.sort_by(|&(ref va, ca), &(ref vb, cb)| cb.cmp(&ca).then_with(|| va.cmp(vb)));
+
+    if global_opts.output.is_json() {
+        print_json("stats", vec![
+            ("field", Json::str(field_name.as_str())),
+            ("values", Json::Array(counts.into_iter().map(|(value, count)| {
+                Json::object(vec![("value", Json::Str(value)), ("count", Json::Int(count as i64))])
+            }).collect())),
+        ]);
+    }
+    else {
+        for (value, count) in counts {
+            println!("{}\t{}", count, value);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_install_hooks(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut dry_run = false;
+    let mut force = false;
+
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => { dry_run = true; },
+            "--force" => { force = true; },
+            _ => bail!("unexpected argument for 'install-hooks': '{}'\n{}", arg, USAGE),
+        }
+    }
+
+    let media_lib = default_library(global_opts)?;
+    media_lib.ensure_writable()?;
+
+    let hook_fp = git::pre_commit_hook_fp(media_lib.root_dir())?;
+
+    // Hooks written by taggu are kept up to date, but other hooks are left alone unless asked.
+    ensure!(
+        force || !hook_fp.exists() || git::is_taggu_hook(&hook_fp),
+        "pre-commit hook already exists: '{}', use --force to replace it", hook_fp.to_string_lossy()
+    );
+
+    let mut plan = WritePlan::new();
+    plan.write_text(&hook_fp, git::pre_commit_hook_script(&media_lib));
+
+    if !dry_run {
+        if let Some(hooks_dir) = hook_fp.parent() {
+            fs::create_dir_all(hooks_dir)?;
+        }
+    }
+
+    finish_plan(global_opts, "install-hooks", plan, dry_run, vec![])?;
+
+    if !dry_run {
+        git::make_executable(&hook_fp)?;
+    }
+
+    Ok(())
+}
+
+fn run_health(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut check_tags = false;
+    let mut as_html = false;
+
+    for arg in args {
+        match arg.as_str() {
+            "--tags" => { check_tags = true; },
+            "--html" => { as_html = true; },
+            _ => bail!("unexpected argument for 'health': '{}'\n{}", arg, USAGE),
+        }
+    }
+
+    let media_lib = open_library(global_opts)?;
+    let schema = default_schema(media_lib.root_dir())?;
+
+    let mut report = HealthReport::new();
+    let mut status_line = StatusLine::new(global_opts.show_progress);
+
+    report.add_coverage(&media_lib, &mut status_line)?;
+    report.add_schema_violations(&media_lib.validate_schema(&schema, &mut status_line)?);
+
+    if check_tags {
+        // Merging reports conflicts without picking a side, and the plan is thrown away, so nothing is written.
+        let mapping = default_field_mapping(media_lib.root_dir())?;
+        let mut plan = WritePlan::new();
+        let reports = sync_dir(&media_lib, media_lib.root_dir(), &mapping, SyncDirection::Merge, &mut plan, &mut status_line)?;
+
+        report.add_sync_reports(&reports);
+    }
+
+    status_line.finish();
+    report.sort();
+
+    if global_opts.output.is_json() {
+        print_json("health", vec![("report", report.as_json())]);
+    }
+    else if as_html {
+        print!("{}", report.as_html(media_lib.root_dir()));
+    }
+    else {
+        print!("{}", report);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    ensure!(args.is_empty(), "unexpected argument for 'tui': '{}'\n{}", args[0], USAGE);
+
+    let media_lib = open_library(global_opts)?;
+    let schema = default_schema(media_lib.root_dir())?;
+    let split_rules = default_split_rules(media_lib.root_dir())?;
+
+    let mut lookup_ctx = LookupContext::new(&media_lib);
+    lookup_ctx.set_sort_name_rules(default_sort_name_rules(media_lib.root_dir())?);
+
+    tui::run(lookup_ctx, schema, split_rules, &undo_journal(global_opts))
+}
+
+fn run_undo(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    let mut list = false;
+
+    for arg in args {
+        match arg.as_str() {
+            "--list" => { list = true; },
+            _ => bail!("unexpected argument for 'undo': '{}'\n{}", arg, USAGE),
+        }
+    }
+
+    let journal = undo_journal(global_opts);
+    let batch_id = |p: &Path| p.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+    if list {
+        let batches = journal.batches()?;
+
+        if global_opts.output.is_json() {
+            print_json("undo", vec![
+                ("batches", Json::Array(batches.iter().map(|batch| {
+                    Json::object(vec![
+                        ("id", Json::str(batch_id(&batch.batch_dir_path))),
+                        ("command", Json::str(batch.command.as_str())),
+                        ("created", Json::Int(batch.created as i64)),
+                        ("changes", Json::Int(batch.entries.len() as i64)),
+                    ])
+                }).collect())),
+            ]);
+        }
+        else {
+            for batch in &batches {
+                println!("{}\t{}\t{} changes", batch_id(&batch.batch_dir_path), batch.command, batch.entries.len());
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Rule: library must be writable.
+    default_library(global_opts)?.ensure_writable()?;
+
+    let opt_batch = journal.undo_last()?;
+
+    if global_opts.output.is_json() {
+        print_json("undo", vec![
+            ("undone", opt_batch.as_ref().map_or(Json::Null, |batch| Json::object(vec![
+                ("id", Json::str(batch_id(&batch.batch_dir_path))),
+                ("command", Json::str(batch.command.as_str())),
+                ("changes", Json::Int(batch.entries.len() as i64)),
+            ]))),
+        ]);
+    }
+    else {
+        match opt_batch {
+            Some(batch) => println!("undid '{}': {} changes", batch.command, batch.entries.len()),
+            None => println!("nothing to undo"),
+        }
+    }
+
+    Ok(())
+}
+
+fn run_completions(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    ensure!(args.len() == 1, "'completions' requires exactly one shell name\n{}", USAGE);
+
+    let shell: Shell = args[0].parse()?;
+    let script = completion_script(shell);
+
+    if global_opts.output.is_json() {
+        print_json("completions", vec![
+            ("shell", Json::str(args[0].as_str())),
+            ("script", Json::Str(script)),
+        ]);
+    }
+    else {
+        print!("{}", script);
+    }
+
+    Ok(())
+}
+
+/// Prints the names of the fields available for an item, one per line.
+fn run_complete_fields(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    ensure!(args.len() == 1, "'complete-fields' requires exactly one item path");
+
+    let item_path = Path::new(&args[0]).canonicalize()?;
+
+    let media_lib = open_library(global_opts)?;
+    let mut lookup_ctx = LookupContext::new(&media_lib);
+    PersistentCache::load(media_lib.root_dir())?.seed_context(&mut lookup_ctx);
+
+    let names = field_names(&mut lookup_ctx, &item_path)?;
+
+    if global_opts.output.is_json() {
+        print_json("complete-fields", vec![
+            ("item_path", Json::path(&item_path)),
+            ("fields", Json::Array(names.into_iter().map(Json::Str).collect())),
+        ]);
+    }
+    else {
+        for name in names {
+            println!("{}", name);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_complete_values(global_opts: &GlobalOpts, args: Vec<String>) -> Result<()> {
+    ensure!(args.len() == 1 || args.len() == 2, "'complete-values' requires a field name, and optionally a prefix");
+
+    let field_name = &args[0];
+    let prefix = args.get(1).map_or("", String::as_str);
+
+    let media_lib = open_library(global_opts)?;
+    let suggestions = media_lib.suggest_values(field_name, prefix)?;
+
+    if global_opts.output.is_json() {
+        print_json("complete-values", vec![
+            ("field", Json::str(field_name.as_str())),
+            ("values", Json::Array(suggestions.into_iter().map(|(value, count)| {
+                Json::object(vec![("value", Json::Str(value)), ("count", Json::Int(count as i64))])
+            }).collect())),
+        ]);
+    }
+    else {
+        for (value, _) in suggestions {
+            println!("{}", value);
+        }
+    }
+
+    Ok(())
+}
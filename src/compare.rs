@@ -0,0 +1,294 @@
+// This module compares the items and metadata of two libraries, e.g. to check a mirror or a migration to a new layout.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use library::{Library, WalkStep};
+use lookup::LookupContext;
+use metadata::{MetaBlock, MetaValue};
+use metadata::keys::is_reserved_key;
+use json::Json;
+use helpers::content_hash;
+use progress::Progress;
+use error::*;
+
+/// How items in one library are paired with items in the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchBy {
+    /// Items only match items at the same path relative to their library root.
+    Path,
+    /// Like `Path`, but files left over are then matched by content, so that renamed or moved files still pair up.
+    Hash,
+}
+
+impl FromStr for MatchBy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "path" => Ok(MatchBy::Path),
+            "hash" => Ok(MatchBy::Hash),
+            _ => bail!("unknown match method: '{}', expected path or hash", s),
+        }
+    }
+}
+
+/// A field whose value differs between two matched items, where `None` means that the field is not set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+    pub field_name: String,
+    pub value_a: Option<MetaValue>,
+    pub value_b: Option<MetaValue>,
+}
+
+/// A pair of matched items whose metadata differs. Paths are relative to their library roots.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemDiff {
+    pub rel_path_a: PathBuf,
+    pub rel_path_b: PathBuf,
+    pub fields: Vec<FieldDiff>,
+}
+
+/// The result of comparing two libraries. Paths are relative to their library roots.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CompareReport {
+    /// Items in the first library without a match in the second.
+    pub missing: Vec<PathBuf>,
+    /// Items in the second library without a match in the first.
+    pub extra: Vec<PathBuf>,
+    /// Matched items whose metadata differs.
+    pub changed: Vec<ItemDiff>,
+    /// The number of matched items, including those whose metadata is the same.
+    pub matched: usize,
+}
+
+impl CompareReport {
+    /// Returns true if both libraries have the same items with the same metadata.
+    pub fn is_same(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn as_json(&self) -> Json {
+        let opt_json = |opt_mv: &Option<MetaValue>| opt_mv.as_ref().map_or(Json::Null, Json::from_meta_value);
+
+        Json::object(vec![
+            ("matched", Json::Int(self.matched as i64)),
+            ("missing", Json::Array(self.missing.iter().map(Json::path).collect())),
+            ("extra", Json::Array(self.extra.iter().map(Json::path).collect())),
+            ("changed", Json::Array(self.changed.iter().map(|item_diff| {
+                Json::object(vec![
+                    ("path_a", Json::path(&item_diff.rel_path_a)),
+                    ("path_b", Json::path(&item_diff.rel_path_b)),
+                    ("fields", Json::Array(item_diff.fields.iter().map(|field_diff| {
+                        Json::object(vec![
+                            ("field", Json::str(field_diff.field_name.as_str())),
+                            ("a", opt_json(&field_diff.value_a)),
+                            ("b", opt_json(&field_diff.value_b)),
+                        ])
+                    }).collect())),
+                ])
+            }).collect())),
+        ])
+    }
+}
+
+/// Lists the items in a library that are not ignored, keyed by their paths relative to the library root.
+fn library_items<G: Progress>(media_lib: &Library, progress: &mut G) -> Result<BTreeMap<PathBuf, PathBuf>> {
+    let mut lookup_ctx = LookupContext::new(media_lib);
+    let mut items = BTreeMap::new();
+
+    media_lib.walk_items(media_lib.root_dir(), |step| {
+        if let WalkStep::Item { path, .. } = step {
+            // Ignored items are left out, along with everything inside them.
+            if lookup_ctx.is_ignored(path)? {
+                return Ok(false);
+            }
+
+            progress.item_scanned(path);
+
+            if media_lib.is_in_scope(path) {
+                let rel_path = path.strip_prefix(media_lib.root_dir())
+                    .map_err(|_| ErrorKind::InvalidSubPath(path.to_path_buf(), media_lib.root_dir().to_path_buf()))?
+                    .to_path_buf();
+
+                items.insert(rel_path, path.to_path_buf());
+            }
+        }
+
+        Ok(true)
+    })?;
+
+    Ok(items)
+}
+
+/// Resolves the metadata of an item, including inherited fields, leaving out reserved keys.
+fn item_fields(lookup_ctx: &mut LookupContext, abs_item_path: &Path) -> Result<MetaBlock> {
+    Ok(lookup_ctx.resolve_block(abs_item_path)?.into_meta_block().into_iter()
+        .filter(|&(ref k, _)| !is_reserved_key(k))
+        .collect())
+}
+
+/// Lists the fields that differ between two blocks, in field name order.
+/// Values that only differ in form (e.g. `1` and `01`) are treated as the same.
+fn diff_fields(mb_a: &MetaBlock, mb_b: &MetaBlock) -> Vec<FieldDiff> {
+    let field_names: BTreeSet<&String> = mb_a.keys().chain(mb_b.keys()).collect();
+
+    field_names.into_iter()
+        .filter(|field_name| !MetaValue::eq_coerced_opt(mb_a.get(*field_name), mb_b.get(*field_name)))
+        .map(|field_name| FieldDiff {
+            field_name: field_name.clone(),
+            value_a: mb_a.get(field_name).cloned(),
+            value_b: mb_b.get(field_name).cloned(),
+        })
+        .collect()
+}
+
+/// Pairs the files left over from matching by path that have the same contents.
+/// Each file is matched at most once; if several files share the same contents, they are paired in path order.
+fn match_by_hash(
+    items_a: &BTreeMap<PathBuf, PathBuf>,
+    items_b: &BTreeMap<PathBuf, PathBuf>,
+    unmatched_a: &[PathBuf],
+    unmatched_b: &[PathBuf],
+    ) -> Result<Vec<(PathBuf, PathBuf)>>
+{
+    let mut by_hash_b: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+
+    for rel_path_b in unmatched_b.iter().rev() {
+        let abs_path_b = &items_b[rel_path_b];
+
+        if abs_path_b.is_file() {
+            by_hash_b.entry(content_hash(abs_path_b)?).or_insert_with(Vec::new).push(rel_path_b.clone());
+        }
+    }
+
+    let mut pairs = vec![];
+
+    for rel_path_a in unmatched_a {
+        let abs_path_a = &items_a[rel_path_a];
+
+        if !abs_path_a.is_file() {
+            continue;
+        }
+
+        // Candidates were added in reverse, so popping takes them in path order.
+        if let Some(rel_path_b) = by_hash_b.get_mut(&content_hash(abs_path_a)?).and_then(|rel_paths_b| rel_paths_b.pop()) {
+            pairs.push((rel_path_a.clone(), rel_path_b));
+        }
+    }
+
+    Ok(pairs)
+}
+
+/// Compares the items of two libraries and their metadata, including inherited fields.
+/// Items marked as ignored in either library are left out.
+pub fn compare_libraries<G: Progress>(media_lib_a: &Library, media_lib_b: &Library, match_by: MatchBy, progress: &mut G) -> Result<CompareReport> {
+    let items_a = library_items(media_lib_a, progress)?;
+    let items_b = library_items(media_lib_b, progress)?;
+
+    let mut pairs: Vec<(PathBuf, PathBuf)> = items_a.keys()
+        .filter(|rel_path| items_b.contains_key(*rel_path))
+        .map(|rel_path| (rel_path.clone(), rel_path.clone()))
+        .collect();
+
+    let mut missing: Vec<PathBuf> = items_a.keys().filter(|rel_path| !items_b.contains_key(*rel_path)).cloned().collect();
+    let mut extra: Vec<PathBuf> = items_b.keys().filter(|rel_path| !items_a.contains_key(*rel_path)).cloned().collect();
+
+    if match_by == MatchBy::Hash {
+        let hash_pairs = match_by_hash(&items_a, &items_b, &missing, &extra)?;
+
+        let paired_a: BTreeSet<&PathBuf> = hash_pairs.iter().map(|&(ref a, _)| a).collect();
+        let paired_b: BTreeSet<&PathBuf> = hash_pairs.iter().map(|&(_, ref b)| b).collect();
+        missing.retain(|rel_path| !paired_a.contains(rel_path));
+        extra.retain(|rel_path| !paired_b.contains(rel_path));
+
+        pairs.extend(hash_pairs);
+    }
+
+    let mut lookup_ctx_a = LookupContext::new(media_lib_a);
+    let mut lookup_ctx_b = LookupContext::new(media_lib_b);
+    let mut report = CompareReport { missing, extra, matched: pairs.len(), changed: vec![] };
+
+    for (rel_path_a, rel_path_b) in pairs {
+        let fields_a = item_fields(&mut lookup_ctx_a, &items_a[&rel_path_a])?;
+        let fields_b = item_fields(&mut lookup_ctx_b, &items_b[&rel_path_b])?;
+
+        let fields = diff_fields(&fields_a, &fields_b);
+
+        if !fields.is_empty() {
+            report.changed.push(ItemDiff { rel_path_a, rel_path_b, fields });
+        }
+    }
+
+    report.changed.sort_by(|a, b| a.rel_path_a.cmp(&b.rel_path_a));
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use tempdir::TempDir;
+
+    use metadata::MetaValue;
+    use fixtures::{create_temp_media_test_dir, fixture_library};
+    use progress::NoProgress;
+
+    use super::{MatchBy, FieldDiff, compare_libraries};
+
+    #[test]
+    fn test_compare_libraries() {
+        let temp_a = create_temp_media_test_dir("test_compare_libraries_a");
+        let temp_b = create_temp_media_test_dir("test_compare_libraries_b");
+        let (pa, pb) = (temp_a.path(), temp_b.path());
+
+        let lib_a = fixture_library(pa).unwrap();
+        let lib_b = fixture_library(pb).unwrap();
+
+        // Identical trees are the same.
+        let report = compare_libraries(&lib_a, &lib_b, MatchBy::Path, &mut NoProgress).unwrap();
+        assert!(report.is_same());
+        assert!(report.matched > 0);
+
+        // Changed fields are reported along with both values, and renamed items are missing under their old names.
+        let mut f = File::create(pb.join("ALBUM_01").join("self.yml")).unwrap();
+        writeln!(f, "ALBUM_01_self_key: changed").unwrap();
+
+        let disc_fp = pb.join("ALBUM_01").join("DISC_01");
+        let track_name = "TRACK_01.flac";
+        fs::rename(disc_fp.join(track_name), disc_fp.join("RENAMED.flac")).unwrap();
+
+        let report = compare_libraries(&lib_a, &lib_b, MatchBy::Path, &mut NoProgress).unwrap();
+        let rel_track = PathBuf::from("ALBUM_01").join("DISC_01");
+        assert_eq!(vec![rel_track.join(track_name)], report.missing);
+        assert_eq!(vec![rel_track.join("RENAMED.flac")], report.extra);
+
+        let album_diff = report.changed.iter().find(|d| d.rel_path_a == PathBuf::from("ALBUM_01")).unwrap();
+        assert_eq!(
+            vec![FieldDiff {
+                field_name: String::from("ALBUM_01_self_key"),
+                value_a: Some(MetaValue::Str(String::from("ALBUM_01_self_val"))),
+                value_b: Some(MetaValue::Str(String::from("changed"))),
+            }],
+            album_diff.fields,
+        );
+
+        // Matching by hash pairs up the renamed file, since its contents did not change.
+        let report = compare_libraries(&lib_a, &lib_b, MatchBy::Hash, &mut NoProgress).unwrap();
+        assert!(report.missing.is_empty());
+        assert!(report.extra.is_empty());
+
+        assert_eq!(MatchBy::Hash, "hash".parse().unwrap());
+        assert!("size".parse::<MatchBy>().is_err());
+
+        let empty = TempDir::new("test_compare_libraries_empty").unwrap();
+        let report = compare_libraries(&lib_a, &fixture_library(empty.path()).unwrap(), MatchBy::Path, &mut NoProgress).unwrap();
+        assert_eq!(0, report.matched);
+        assert!(report.extra.is_empty());
+        assert!(!report.missing.is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+// This module provides tab completion for interactive shells.
+// The generated scripts call back into `taggu complete-fields` to find the fields available for an item.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use lookup::LookupContext;
+use library::structure::STRUCTURAL_KEYS;
+use metadata::keys::is_reserved_key;
+use error::*;
+
+/// The subcommands offered for completion, which leaves out hidden commands.
+pub const COMMAND_NAMES: &[&str] = &[
+    "dump",
+    "init",
+    "sync",
+    "beets-import",
+    "beets-export",
+    "itunes-import",
+    "discogs-import",
+    "lastfm-import",
+    "mpd-stickers",
+    "cache",
+    "refactor",
+    "apply",
+    "move",
+    "played",
+    "rate",
+    "query",
+    "playlist",
+    "compare",
+    "lint",
+    "plex-check",
+    "check",
+    "health",
+    "stats",
+    "install-hooks",
+    "tui",
+    "undo",
+    "completions",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => bail!("unknown shell: '{}', expected bash, zsh or fish", s),
+        }
+    }
+}
+
+const BASH_SCRIPT: &str = r#"_taggu() {
+    local cur prev cmd item root i
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+
+    case "$prev" in
+        --root|--overlay) COMPREPLY=($(compgen -d -- "$cur")); return ;;
+        --paths-from) COMPREPLY=($(compgen -f -- "$cur")); return ;;
+        --output) COMPREPLY=($(compgen -W "text json json-lines" -- "$cur")); return ;;
+        --match) COMPREPLY=($(compgen -W "path hash" -- "$cur")); return ;;
+        --format) COMPREPLY=($(compgen -W "m3u paths" -- "$cur")); return ;;
+        --out|--csv) COMPREPLY=($(compgen -f -- "$cur")); return ;;
+        --direction) COMPREPLY=($(compgen -W "yaml-to-tags tags-to-yaml merge" -- "$cur")); return ;;
+        --music-folder) COMPREPLY=($(compgen -d -- "$cur")); return ;;
+        --changed-since|--interval|--join|--map|--field|--from|--to|--query|--typo-field) return ;;
+    esac
+
+    # Find the subcommand, and for dump, the item whose fields should be completed.
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            --root) root="${COMP_WORDS[i+1]}"; ((i++)) ;;
+            --overlay|--output|--paths-from|--changed-since|--interval|--join|--direction|--map|--field|--from|--to|--query|--format|--out|--match|--music-folder|--csv|--added-within|--modified-within|--typo-field) ((i++)) ;;
+            -*) ;;
+            *)
+                if [[ -z "$cmd" ]]; then
+                    cmd="${COMP_WORDS[i]}"
+                elif [[ -z "$item" ]]; then
+                    item="${COMP_WORDS[i]}"
+                fi
+                ;;
+        esac
+    done
+
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "--root --overlay --read-only --lenient --progress --timing --output --paths-from --changed-since --help --trace --show-sources --subst --join --dry-run --direction --map --field --from --to --tags --html --force --watch --interval --query --format --out --match --list --redact --music-folder --yes --fix-names --csv --added-within --modified-within --typo-field" -- "$cur"))
+        return
+    fi
+
+    case "$cmd" in
+        "") COMPREPLY=($(compgen -W "__COMMANDS__" -- "$cur")) ;;
+        cache) [[ -z "$item" ]] && COMPREPLY=($(compgen -W "build verify clear stats" -- "$cur")) ;;
+        refactor) [[ -z "$item" ]] && COMPREPLY=($(compgen -W "rename-field replace split-values split-sidecars" -- "$cur")) ;;
+        completions) [[ -z "$item" ]] && COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur")) ;;
+        dump)
+            if [[ -n "$item" ]]; then
+                COMPREPLY=($(compgen -W "$(taggu ${root:+--root "$root"} complete-fields "$item" 2>/dev/null)" -- "$cur"))
+            else
+                COMPREPLY=($(compgen -f -- "$cur"))
+            fi
+            ;;
+        *) COMPREPLY=($(compgen -f -- "$cur")) ;;
+    esac
+}
+
+complete -o filenames -F _taggu taggu
+"#;
+
+const FISH_SCRIPT: &str = r#"function __taggu_option_value
+    set -l tokens (commandline -opc)
+    set -l i (contains -i -- $argv[1] $tokens)
+    and test (count $tokens) -gt $i
+    and echo $tokens[(math $i + 1)]
+end
+
+function __taggu_dump_item
+    set -l seen_dump 0
+    set -l skip 0
+
+    for token in (commandline -opc)[2..-1]
+        if test $skip = 1
+            set skip 0
+            continue
+        end
+
+        switch $token
+            case --root --overlay --output --paths-from --changed-since --join
+                set skip 1
+            case '-*'
+            case '*'
+                if test $seen_dump = 1
+                    echo $token
+                    return 0
+                else if test $token = dump
+                    set seen_dump 1
+                end
+        end
+    end
+
+    return 1
+end
+
+function __taggu_item_fields
+    set -l root (__taggu_option_value --root)
+
+    if test -n "$root"
+        taggu --root $root complete-fields (__taggu_dump_item) 2>/dev/null
+    else
+        taggu complete-fields (__taggu_dump_item) 2>/dev/null
+    end
+end
+
+complete -c taggu -l root -r -a '(__fish_complete_directories)'
+complete -c taggu -l overlay -r -a '(__fish_complete_directories)'
+complete -c taggu -l read-only
+complete -c taggu -l lenient
+complete -c taggu -l progress
+complete -c taggu -l timing
+complete -c taggu -l output -x -a 'text json json-lines'
+complete -c taggu -l paths-from -r
+complete -c taggu -l changed-since -x
+complete -c taggu -l help
+complete -c taggu -f -n __fish_use_subcommand -a '__COMMANDS__'
+complete -c taggu -n '__fish_seen_subcommand_from dump' -l trace
+complete -c taggu -n '__fish_seen_subcommand_from dump' -l show-sources
+complete -c taggu -n '__fish_seen_subcommand_from dump' -l subst
+complete -c taggu -n '__fish_seen_subcommand_from dump' -l join -x
+complete -c taggu -n '__fish_seen_subcommand_from dump; and __taggu_dump_item >/dev/null' -f -a '(__taggu_item_fields)'
+complete -c taggu -n '__fish_seen_subcommand_from init sync beets-import itunes-import discogs-import lastfm-import refactor apply move playlist lint install-hooks' -l dry-run
+complete -c taggu -n '__fish_seen_subcommand_from beets-export' -l redact
+complete -c taggu -n '__fish_seen_subcommand_from itunes-import' -l music-folder -r
+complete -c taggu -n '__fish_seen_subcommand_from discogs-import' -l yes
+complete -c taggu -n '__fish_seen_subcommand_from lint' -l fix-names
+complete -c taggu -n '__fish_seen_subcommand_from lint' -l typo-field -x
+complete -c taggu -n '__fish_seen_subcommand_from sync' -l direction -x -a 'yaml-to-tags tags-to-yaml merge'
+complete -c taggu -n '__fish_seen_subcommand_from sync' -l map -x
+complete -c taggu -n '__fish_seen_subcommand_from mpd-stickers' -l field -x
+complete -c taggu -n '__fish_seen_subcommand_from cache' -f -a 'build verify clear stats'
+complete -c taggu -n '__fish_seen_subcommand_from refactor' -f -a 'rename-field replace split-values split-sidecars'
+complete -c taggu -n '__fish_seen_subcommand_from refactor' -l field -x
+complete -c taggu -n '__fish_seen_subcommand_from refactor' -l from -x
+complete -c taggu -n '__fish_seen_subcommand_from refactor' -l to -x
+complete -c taggu -n '__fish_seen_subcommand_from apply' -l csv -r
+complete -c taggu -n '__fish_seen_subcommand_from playlist' -l query -x
+complete -c taggu -n '__fish_seen_subcommand_from playlist' -l format -x -a 'm3u paths'
+complete -c taggu -n '__fish_seen_subcommand_from playlist' -l added-within -x
+complete -c taggu -n '__fish_seen_subcommand_from playlist' -l modified-within -x
+complete -c taggu -n '__fish_seen_subcommand_from playlist' -l out -r
+complete -c taggu -n '__fish_seen_subcommand_from compare' -l match -x -a 'path hash'
+complete -c taggu -n '__fish_seen_subcommand_from compare' -a '(__fish_complete_directories)'
+complete -c taggu -n '__fish_seen_subcommand_from undo' -l list
+complete -c taggu -n '__fish_seen_subcommand_from completions' -f -a 'bash zsh fish'
+complete -c taggu -n '__fish_seen_subcommand_from health' -l tags
+complete -c taggu -n '__fish_seen_subcommand_from health' -l html
+complete -c taggu -n '__fish_seen_subcommand_from stats' -l field -x
+complete -c taggu -n '__fish_seen_subcommand_from install-hooks' -l force
+complete -c taggu -n '__fish_seen_subcommand_from check' -l watch
+complete -c taggu -n '__fish_seen_subcommand_from check' -l interval -x
+"#;
+
+/// Generates a script that sets up completion of `taggu` invocations for a shell.
+pub fn completion_script(shell: Shell) -> String {
+    let commands = COMMAND_NAMES.join(" ");
+
+    match shell {
+        Shell::Bash => BASH_SCRIPT.replace("__COMMANDS__", &commands),
+        // Zsh can run bash completion functions as-is.
+        Shell::Zsh => format!("autoload -U +X bashcompinit && bashcompinit\n{}", BASH_SCRIPT.replace("__COMMANDS__", &commands)),
+        Shell::Fish => FISH_SCRIPT.replace("__COMMANDS__", &commands),
+    }
+}
+
+/// Lists the names of the fields that can be looked up for an item, in sorted order.
+/// This includes inherited fields and the virtual fields provided by the library, but not reserved keys.
+pub fn field_names<P: AsRef<Path>>(lookup_ctx: &mut LookupContext, abs_item_path: P) -> Result<Vec<String>> {
+    let resolved = lookup_ctx.resolve_block(abs_item_path)?;
+
+    let mut names: BTreeSet<String> = resolved.iter()
+        .map(|(field_name, _)| field_name.clone())
+        .filter(|field_name| !is_reserved_key(field_name))
+        .collect();
+
+    names.extend(lookup_ctx.media_lib().asset_rules().iter().map(|r| r.field_name().to_string()));
+    names.extend(STRUCTURAL_KEYS.iter().map(|k| k.to_string()));
+    names.extend(lookup_ctx.media_lib().plugin_field_names());
+
+    Ok(names.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use lookup::LookupContext;
+    use fixtures::default_setup;
+
+    use super::{Shell, completion_script, field_names};
+
+    #[test]
+    fn test_completion_script() {
+        assert_eq!(Shell::Fish, "fish".parse().unwrap());
+        assert!("tcsh".parse::<Shell>().is_err());
+
+        let bash = completion_script(Shell::Bash);
+        assert!(bash.contains("complete -o filenames -F _taggu taggu"));
+        assert!(bash.contains("\"dump init sync beets-import beets-export itunes-import discogs-import lastfm-import mpd-stickers cache refactor apply move played rate query playlist compare lint plex-check check health stats install-hooks tui undo completions\""));
+        assert!(!bash.contains("__COMMANDS__"));
+
+        let zsh = completion_script(Shell::Zsh);
+        assert!(zsh.starts_with("autoload -U +X bashcompinit && bashcompinit\n"));
+
+        let fish = completion_script(Shell::Fish);
+        assert!(fish.contains("complete-fields"));
+        assert!(!fish.contains("__COMMANDS__"));
+    }
+
+    #[test]
+    fn test_field_names() {
+        let (temp_media_root, media_lib) = default_setup("test_field_names");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        let names = field_names(&mut lookup_ctx, tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac")).unwrap();
+
+        assert!(names.contains(&String::from("item_key")));
+        assert!(names.contains(&String::from("self_key")));
+        assert!(names.contains(&String::from("__index")));
+
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(sorted, names);
+    }
+}
@@ -0,0 +1,276 @@
+// Checks meta files one at a time, and reports the problems found with positions in their text, in the shape that language servers use.
+// This lets editors show problems inline while a meta file is being edited.
+// Positions count lines and characters from 0, as in the language server protocol, but characters are counted in bytes.
+
+use std::collections::BTreeSet;
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use library::Library;
+use schema::{Schema, SchemaViolation, ViolationKind};
+use health::Severity;
+use roundtrip::{KeySpan, find_keys};
+use progress::NoProgress;
+use json::Json;
+use error::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Range {
+    fn on_line(line: usize, start: usize, end: usize) -> Self {
+        Range {
+            start: Position { line, character: start },
+            end: Position { line, character: end },
+        }
+    }
+
+    fn of_key(span: &KeySpan) -> Self {
+        Range::on_line(span.line, span.start, span.end)
+    }
+}
+
+/// A problem found in a meta file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub meta_path: PathBuf,
+    pub range: Range,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// The number for the severity in the language server protocol.
+    fn lsp_severity(&self) -> i64 {
+        match self.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+            Severity::Info => 3,
+        }
+    }
+
+    pub fn as_json(&self) -> Json {
+        let position_json = |p: &Position| Json::object(vec![
+            ("line", Json::Int(p.line as i64)),
+            ("character", Json::Int(p.character as i64)),
+        ]);
+
+        Json::object(vec![
+            ("path", Json::path(&self.meta_path)),
+            ("range", Json::object(vec![
+                ("start", position_json(&self.range.start)),
+                ("end", position_json(&self.range.end)),
+            ])),
+            ("severity", Json::Int(self.lsp_severity())),
+            ("message", Json::str(self.message.as_str())),
+            ("source", Json::str("taggu")),
+        ])
+    }
+}
+
+impl Display for Diagnostic {
+    /// Shows positions counting from 1, as compilers do.
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f, "{}:{}:{}: {}: {}",
+            self.meta_path.to_string_lossy(), self.range.start.line + 1, self.range.start.character + 1, self.severity, self.message,
+        )
+    }
+}
+
+/// Returns the value that a violation is about, if it is about a single value.
+fn violation_value(kind: &ViolationKind) -> Option<&str> {
+    match *kind {
+        ViolationKind::NotAllowed(ref s)
+        | ViolationKind::PatternMismatch(ref s)
+        | ViolationKind::NotNumeric(ref s)
+        | ViolationKind::OutOfRange(ref s)
+        | ViolationKind::UnknownTerm(ref s) => Some(s),
+        ViolationKind::Missing | ViolationKind::UnexpectedMapping => None,
+    }
+}
+
+fn key_matches(span: &KeySpan, name: &str, fold_case: bool) -> bool {
+    match span.key {
+        Yaml::String(ref s) => if fold_case { s.to_lowercase() == name.to_lowercase() } else { s == name },
+        _ => false,
+    }
+}
+
+/// Finds where in the text of a meta file a violation should be shown.
+/// Violations are matched to the keys of their fields in text order, preferring keys whose value is the value the violation is about.
+/// Missing fields are shown at the key naming their item, if there is one, and otherwise at the start of the file.
+fn locate_violation(spans: &[KeySpan], used: &mut [bool], violation: &SchemaViolation, fold_case: bool) -> Range {
+    let start_of_file = Range::on_line(0, 0, 0);
+
+    if violation.kind == ViolationKind::Missing {
+        let item_name = match violation.item_path.file_name().and_then(|s| s.to_str()) {
+            Some(item_name) => item_name,
+            None => return start_of_file,
+        };
+
+        return spans.iter().find(|span| key_matches(span, item_name, false)).map_or(start_of_file, Range::of_key);
+    }
+
+    let candidates: Vec<usize> = (0..spans.len()).filter(|&i| key_matches(&spans[i], &violation.field_name, fold_case)).collect();
+    let has_value = |i: &usize| {
+        let value_text = spans[*i].value_text.trim_matches(|c| c == '\'' || c == '"');
+        violation_value(&violation.kind).map_or(false, |v| value_text == v)
+    };
+
+    let found = candidates.iter().find(|i| !used[**i] && has_value(i))
+        .or_else(|| candidates.iter().find(|i| !used[**i]))
+        .or_else(|| candidates.first());
+
+    match found {
+        Some(&i) => {
+            used[i] = true;
+            Range::of_key(&spans[i])
+        },
+        None => start_of_file,
+    }
+}
+
+/// Checks a single meta file, returning the problems found in it sorted by position.
+/// That covers YAML syntax errors, metadata that cannot be read, and schema violations of the items that the meta file describes.
+/// Meta files that do not exist have no problems, so that checking a deleted meta file clears its problems.
+pub fn check_meta_file<P: AsRef<Path>>(media_lib: &Library, schema: &Schema, abs_meta_path: P) -> Result<Vec<Diagnostic>> {
+    let abs_meta_path = abs_meta_path.as_ref();
+
+    if !abs_meta_path.is_file() {
+        return Ok(vec![]);
+    }
+
+    let mut text = String::new();
+    File::open(abs_meta_path)?.read_to_string(&mut text)?;
+
+    let error_at = |range: Range, message: String| Diagnostic {
+        meta_path: abs_meta_path.to_path_buf(),
+        range,
+        severity: Severity::Error,
+        message,
+    };
+
+    if let Err(e) = YamlLoader::load_from_str(&text) {
+        // Markers count lines from 1 and columns from 0.
+        let marker = e.marker();
+        let position = Range::on_line(marker.line().saturating_sub(1), marker.col(), marker.col());
+
+        return Ok(vec![error_at(position, e.to_string())]);
+    }
+
+    // Metadata that does not fit the meta target has no good position, since the whole file is at fault.
+    let structure_check = media_lib.read_meta_file(abs_meta_path).and_then(|parsed| {
+        media_lib.iter_item_blocks(&parsed)?;
+        Ok(())
+    });
+
+    if let Err(e) = structure_check {
+        let message = e.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(": ");
+        return Ok(vec![error_at(Range::on_line(0, 0, 0), message)]);
+    }
+
+    // A meta file describing a directory brings everything below it into the scope, so violations are filtered to the items it describes.
+    let violations = media_lib.scoped_to(vec![abs_meta_path])?.validate_schema(schema, &mut NoProgress)?;
+    let described: BTreeSet<PathBuf> = media_lib.item_fps_from_meta_fp(abs_meta_path)?.into_iter().map(|(item_path, _)| item_path).collect();
+
+    let spans = find_keys(&text);
+    let mut used = vec![false; spans.len()];
+    let mut diagnostics = vec![];
+
+    for violation in violations {
+        // Items may also be described by other meta files, whose violations are shown there.
+        if !described.contains(&violation.item_path) || violation.meta_path.as_ref().map_or(false, |p| p != abs_meta_path) {
+            continue;
+        }
+
+        let range = locate_violation(&spans, &mut used, &violation, media_lib.fold_field_case());
+
+        let message = match violation.kind {
+            ViolationKind::Missing => format!("{}: {}: '{}'", violation.item_path.to_string_lossy(), violation.kind, violation.field_name),
+            _ => format!("{}: {}", violation.field_name, violation.kind),
+        };
+
+        diagnostics.push(Diagnostic {
+            meta_path: abs_meta_path.to_path_buf(),
+            range,
+            severity: Severity::of_violation(&violation.kind),
+            message,
+        });
+    }
+
+    diagnostics.sort_by(|a, b| a.range.cmp(&b.range));
+
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use regex::Regex;
+
+    use schema::{Schema, FieldSpec, FieldConstraint};
+    use health::Severity;
+    use fixtures::default_setup;
+
+    use super::{check_meta_file, Range};
+
+    #[test]
+    fn test_check_meta_file() {
+        let (temp_media_root, media_lib) = default_setup("test_check_meta_file");
+        let tp = temp_media_root.path();
+        let disc_dir = tp.join("ALBUM_01").join("DISC_01");
+
+        let mut schema = Schema::new();
+        schema
+            .field("item_key", FieldSpec::new().required(true))
+            .field("year", FieldSpec::new().constraint(FieldConstraint::Pattern(Regex::new(r"\d{4}").unwrap())));
+
+        let item_fp = disc_dir.join("item.yml");
+        assert!(check_meta_file(&media_lib, &schema, &item_fp).unwrap().is_empty());
+
+        // Violations are shown at the key of their field, in the block of their item.
+        let mut f = File::create(&item_fp).unwrap();
+        write!(f, "- item_key: a\n  year: 1999\n- item_key: b\n  year: '99'  # typo\n- item_key: c\n").unwrap();
+
+        let produced = check_meta_file(&media_lib, &schema, &item_fp).unwrap();
+        assert_eq!(1, produced.len());
+        assert_eq!(Range::on_line(3, 2, 6), produced[0].range);
+        assert_eq!(Severity::Error, produced[0].severity);
+        assert_eq!("year: value does not match pattern: '99'", produced[0].message);
+
+        // Missing fields are warnings.
+        let self_fp = tp.join("self.yml");
+        let produced = check_meta_file(&media_lib, &schema, &self_fp).unwrap();
+        assert_eq!(1, produced.len());
+        assert_eq!(Range::on_line(0, 0, 0), produced[0].range);
+        assert_eq!(Severity::Warning, produced[0].severity);
+
+        // Syntax errors are shown where the parser gave up.
+        let mut f = File::create(&item_fp).unwrap();
+        write!(f, "- item_key: a\n  year: [1999\n").unwrap();
+
+        let produced = check_meta_file(&media_lib, &schema, &item_fp).unwrap();
+        assert_eq!(1, produced.len());
+        assert_eq!(Severity::Error, produced[0].severity);
+        assert!(produced[0].range.start.line >= 1);
+
+        // Deleted meta files have nothing to show.
+        assert!(check_meta_file(&media_lib, &schema, disc_dir.join("missing.yml")).unwrap().is_empty());
+    }
+}
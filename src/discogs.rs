@@ -0,0 +1,371 @@
+// Imports the tracks of a Discogs release into the meta files of a directory, given the release ID in the metadata of the directory itself.
+// Tracks are matched up with the items in the directory in order, so the directory should hold one item per track.
+// The release is fetched by running curl, so it needs to be on the `PATH`; all of this needs the `discogs` feature.
+// A personal access token in `DISCOGS_TOKEN` is sent along if set, which raises the rate limit of the Discogs API.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use library::{Library, is_meta_file_pattern};
+use lookup::LookupContext;
+use metadata::{MetaBlock, MetaKey, MetaValue, MetaTarget};
+use metadata::keys::TRACKLIST_KEY;
+use writer::plan_edit_item_block;
+use plan::WritePlan;
+use yaml::meta_block_as_yaml;
+use error::*;
+
+/// The field of a directory that holds the ID of the Discogs release that it contains.
+pub const RELEASE_ID_KEY: &str = "discogs:release_id";
+
+/// The field that holds the position of a track on its release, e.g. `A1` or `2-03`.
+const POSITION_KEY: &str = "discogs:position";
+
+const TITLE_FIELD: &str = "title";
+const ARTIST_FIELD: &str = "artist";
+
+/// The field that holds the credits of a track, as a mapping of roles to names.
+const CREDITS_FIELD: &str = "credits";
+
+/// A release as read from the Discogs API, with a block for each track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscogsRelease {
+    pub release_id: u64,
+    pub title: String,
+    pub tracks: Vec<MetaBlock>,
+}
+
+/// Discogs tells apart artists with the same name by numbering them, e.g. `Nirvana (2)`.
+fn strip_disambiguation(name: &str) -> &str {
+    let name = name.trim();
+
+    if name.ends_with(')') {
+        if let Some(start) = name.rfind(" (") {
+            let number = &name[(start + 2)..(name.len() - 1)];
+
+            if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+                return &name[..start];
+            }
+        }
+    }
+
+    name
+}
+
+/// The name of an artist as credited on the release, which may be a variation of their usual name.
+fn artist_name(artist_y: &Yaml) -> Option<String> {
+    let name = match artist_y["anv"].as_str() {
+        Some(anv) if !anv.trim().is_empty() => anv,
+        _ => artist_y["name"].as_str()?,
+    };
+
+    Some(strip_disambiguation(name).to_string())
+}
+
+fn artists_value(artists_y: &Yaml) -> Option<MetaValue> {
+    let mut names: Vec<MetaValue> = artists_y.as_vec()?.iter().filter_map(artist_name).map(MetaValue::Str).collect();
+
+    match names.len() {
+        0 => None,
+        1 => names.pop(),
+        _ => Some(MetaValue::Seq(names)),
+    }
+}
+
+fn credits_value(extra_artists_y: &Yaml) -> Option<MetaValue> {
+    let mut credits: BTreeMap<MetaKey, Vec<MetaValue>> = BTreeMap::new();
+
+    for artist_y in extra_artists_y.as_vec()? {
+        if let (Some(role), Some(name)) = (artist_y["role"].as_str(), artist_name(artist_y)) {
+            credits.entry(MetaKey::Str(role.trim().to_string())).or_insert_with(Vec::new).push(MetaValue::Str(name));
+        }
+    }
+
+    if credits.is_empty() {
+        return None;
+    }
+
+    Some(MetaValue::Map(credits.into_iter().map(|(role, names)| (role, MetaValue::Seq(names))).collect()))
+}
+
+/// Collects the blocks of the tracks in a tracklist, in order.
+/// Headings are left out, and the sub-tracks of an index track count as tracks of their own.
+/// Tracks without artists of their own are given the artists of the release.
+fn collect_tracks(tracklist_y: &[Yaml], release_artists: Option<&MetaValue>, tracks: &mut Vec<MetaBlock>) {
+    for track_y in tracklist_y {
+        match track_y["type_"].as_str() {
+            Some("heading") => {},
+            Some("index") => {
+                if let Some(sub_tracks_y) = track_y["sub_tracks"].as_vec() {
+                    collect_tracks(sub_tracks_y, release_artists, tracks);
+                }
+            },
+            _ => {
+                let mut mb = MetaBlock::new();
+
+                if let Some(title) = track_y["title"].as_str() {
+                    mb.insert(TITLE_FIELD.to_string(), MetaValue::Str(title.trim().to_string()));
+                }
+
+                if let Some(artists) = artists_value(&track_y["artists"]).or_else(|| release_artists.cloned()) {
+                    mb.insert(ARTIST_FIELD.to_string(), artists);
+                }
+
+                if let Some(position) = track_y["position"].as_str().filter(|p| !p.trim().is_empty()) {
+                    mb.insert(POSITION_KEY.to_string(), MetaValue::Str(position.trim().to_string()));
+                }
+
+                if let Some(credits) = credits_value(&track_y["extraartists"]) {
+                    mb.insert(CREDITS_FIELD.to_string(), credits);
+                }
+
+                tracks.push(mb);
+            },
+        }
+    }
+}
+
+/// Reads a release, as returned by the `releases` endpoint of the Discogs API.
+pub fn read_release(release_id: u64, release_y: &Yaml) -> Result<DiscogsRelease> {
+    let tracklist_y = release_y["tracklist"].as_vec().ok_or("Discogs release is missing its tracklist")?;
+    let release_artists = artists_value(&release_y["artists"]);
+
+    let mut tracks = vec![];
+    collect_tracks(tracklist_y, release_artists.as_ref(), &mut tracks);
+
+    Ok(DiscogsRelease {
+        release_id,
+        title: release_y["title"].as_str().unwrap_or_default().to_string(),
+        tracks,
+    })
+}
+
+/// Fetches a release from the Discogs API.
+pub fn fetch_release(release_id: u64) -> Result<DiscogsRelease> {
+    let text = fetcher::fetch(release_id)?;
+
+    // JSON is a subset of YAML, so the YAML parser reads it as well.
+    let docs = YamlLoader::load_from_str(&text).chain_err(|| ErrorKind::DiscogsRequestFailed(release_id, String::from("response is not valid JSON")))?;
+    let release_y = docs.first().ok_or_else(|| ErrorKind::DiscogsRequestFailed(release_id, String::from("response is empty")))?;
+
+    read_release(release_id, release_y)
+}
+
+/// Reads the Discogs release ID from the metadata of a directory itself.
+pub fn release_id(media_lib: &Library, abs_dir_path: &Path) -> Result<u64> {
+    match LookupContext::new(media_lib).lookup_origin(abs_dir_path, RELEASE_ID_KEY)? {
+        Some(MetaValue::Str(ref s)) => Ok(s.trim().parse().chain_err(|| format!("invalid Discogs release ID: '{}'", s))?),
+        _ => bail!("directory has no '{}' field: '{}'", RELEASE_ID_KEY, abs_dir_path.to_string_lossy()),
+    }
+}
+
+/// Plans writing the tracks of a release into the blocks of the items in a directory, matching them up in order.
+/// If no sibling meta file describes the items yet, a new one is written as a sequence with a block per item; otherwise, the blocks of the items are edited.
+/// Items or tracks left over when the counts differ are left alone.
+/// The titles of the tracks are also kept as the tracklist of the directory, so that lint can tell if tracks go missing later on.
+/// Returns the paths of the items that were given a track.
+pub fn plan_release_import(media_lib: &Library, plan: &mut WritePlan, abs_dir_path: &Path, release: &DiscogsRelease) -> Result<Vec<PathBuf>> {
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    // Rule: dir path must be a directory.
+    ensure!(abs_dir_path.is_dir(), ErrorKind::NotADirectory(abs_dir_path.to_path_buf()));
+
+    let item_paths = media_lib.walk_children_paths(abs_dir_path)?;
+    let paired: Vec<(&PathBuf, &MetaBlock)> = item_paths.iter().zip(release.tracks.iter()).collect();
+
+    let mut siblings_fps = vec![];
+    let mut existing = false;
+
+    for &(ref meta_fn, ref meta_target) in media_lib.meta_target_specs() {
+        if *meta_target != MetaTarget::Siblings {
+            continue;
+        }
+
+        if is_meta_file_pattern(meta_fn) {
+            existing |= !media_lib.meta_fps_in_dir(abs_dir_path, meta_fn)?.is_empty();
+        }
+        else {
+            let meta_fp = media_lib.writable_meta_path(abs_dir_path.join(meta_fn));
+            existing |= plan.file_exists(&meta_fp) || abs_dir_path.join(meta_fn).is_file();
+            siblings_fps.push(meta_fp);
+        }
+    }
+
+    match siblings_fps.first() {
+        Some(meta_fp) if !existing => {
+            let blocks_y = item_paths.iter()
+                .map(|item_path| {
+                    let mb = paired.iter().find(|&&(p, _)| p == item_path).map(|&(_, mb)| mb.clone()).unwrap_or_default();
+                    meta_block_as_yaml(&mb)
+                })
+                .collect();
+
+            plan.write_yaml(meta_fp.clone(), Yaml::Array(blocks_y));
+        },
+        _ => {
+            for &(item_path, mb) in &paired {
+                let fields = mb.clone();
+                plan_edit_item_block(media_lib, plan, item_path, |item_mb| item_mb.extend(fields))?;
+            }
+        },
+    }
+
+    let tracklist = release.tracks.iter().filter_map(|mb| mb.get(TITLE_FIELD).cloned()).collect();
+    plan_edit_item_block(media_lib, plan, abs_dir_path, |dir_mb| { dir_mb.insert(TRACKLIST_KEY.to_string(), MetaValue::Seq(tracklist)); })?;
+
+    Ok(paired.into_iter().map(|(item_path, _)| item_path.clone()).collect())
+}
+
+mod fetcher {
+    use std::env;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    use error::*;
+
+    const API_URL: &str = "https://api.discogs.com/releases";
+
+    /// Discogs asks clients to identify themselves.
+    const USER_AGENT: &str = concat!("taggu/", env!("CARGO_PKG_VERSION"));
+
+    const TOKEN_VAR: &str = "DISCOGS_TOKEN";
+
+    pub fn fetch(release_id: u64) -> Result<String> {
+        let failed = |message: &str| ErrorKind::DiscogsRequestFailed(release_id, message.to_string());
+
+        // Headers are read from stdin, so that the token does not show up in the list of processes.
+        let mut child = Command::new("curl")
+            .args(&["--silent", "--show-error", "--fail", "--location", "--user-agent", USER_AGENT, "--header", "@-"])
+            .arg(format!("{}/{}", API_URL, release_id))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .chain_err(|| failed("unable to run curl"))?;
+
+        if let (Ok(token), Some(stdin)) = (env::var(TOKEN_VAR), child.stdin.as_mut()) {
+            writeln!(stdin, "Authorization: Discogs token={}", token).chain_err(|| failed("unable to pass token to curl"))?;
+        }
+
+        let output = child.wait_with_output().chain_err(|| failed("unable to run curl"))?;
+
+        ensure!(output.status.success(), failed(String::from_utf8_lossy(&output.stderr).trim()));
+
+        String::from_utf8(output.stdout).chain_err(|| failed("response is not valid UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use yaml_rust::{Yaml, YamlLoader};
+
+    use lookup::LookupContext;
+    use metadata::{MetaKey, MetaValue};
+    use fixtures::default_setup;
+    use plan::WritePlan;
+    use yaml::read_yaml_file;
+
+    use super::{DiscogsRelease, read_release, release_id, plan_release_import};
+
+    const RELEASE_JSON: &str = r#"{
+        "id": 1234,
+        "title": "Album",
+        "artists": [{"name": "Band (2)", "anv": "", "join": ""}],
+        "tracklist": [
+            {"position": "", "type_": "heading", "title": "Side A"},
+            {"position": "A1", "type_": "track", "title": "First", "extraartists": [
+                {"name": "Producer A", "anv": "", "role": "Producer"},
+                {"name": "Producer B (3)", "anv": "", "role": "Producer"}
+            ]},
+            {"position": "", "type_": "index", "title": "Suite", "sub_tracks": [
+                {"position": "A2a", "type_": "track", "title": "Suite, Part 1"},
+                {"position": "A2b", "type_": "track", "title": "Suite, Part 2", "artists": [
+                    {"name": "Singer", "anv": "The Singer"},
+                    {"name": "Band (2)", "anv": ""}
+                ]}
+            ]}
+        ]
+    }"#;
+
+    fn release() -> DiscogsRelease {
+        let release_y = &YamlLoader::load_from_str(RELEASE_JSON).unwrap()[0];
+        read_release(1234, release_y).unwrap()
+    }
+
+    #[test]
+    fn test_read_release() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let release = release();
+        assert_eq!("Album", release.title);
+        assert_eq!(3, release.tracks.len());
+
+        assert_eq!(
+            btreemap![
+                String::from("title") => str_val("First"),
+                String::from("artist") => str_val("Band"),
+                String::from("discogs:position") => str_val("A1"),
+                String::from("credits") => MetaValue::Map(btreemap![
+                    MetaKey::Str(String::from("Producer")) => MetaValue::Seq(vec![str_val("Producer A"), str_val("Producer B")]),
+                ]),
+            ],
+            release.tracks[0]
+        );
+        assert_eq!(Some(&str_val("Suite, Part 1")), release.tracks[1].get("title"));
+        assert_eq!(Some(&MetaValue::Seq(vec![str_val("The Singer"), str_val("Band")])), release.tracks[2].get("artist"));
+
+        let y = &YamlLoader::load_from_str(r#"{"id": 1, "title": "No Tracks"}"#).unwrap()[0];
+        assert!(read_release(1, y).is_err());
+    }
+
+    #[test]
+    fn test_plan_release_import() {
+        let (temp_media_root, media_lib) = default_setup("test_plan_release_import");
+        let tp = temp_media_root.path();
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        // The release ID comes from the directory's own metadata.
+        let disc_path = tp.join("ALBUM_01").join("DISC_01");
+        assert!(release_id(&media_lib, &disc_path).is_err());
+        fs::write(disc_path.join("self.yml"), "discogs:release_id: 1234\n").unwrap();
+        assert_eq!(1234, release_id(&media_lib, &disc_path).unwrap());
+
+        // Existing blocks are edited.
+        let mut plan = WritePlan::new();
+        let paired = plan_release_import(&media_lib, &mut plan, &disc_path, &release()).unwrap();
+        assert_eq!(3, paired.len());
+        plan.execute().unwrap();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(str_val("Suite, Part 2")), lookup_ctx.lookup_origin(disc_path.join("TRACK_03.flac"), "title").unwrap());
+        assert_eq!(Some(str_val("TRACK_03_item_val")), lookup_ctx.lookup_origin(disc_path.join("TRACK_03.flac"), "TRACK_03_item_key").unwrap());
+        assert_eq!(
+            Some(MetaValue::Seq(vec![str_val("First"), str_val("Suite, Part 1"), str_val("Suite, Part 2")])),
+            lookup_ctx.lookup_origin(&disc_path, "__tracklist").unwrap()
+        );
+
+        // Without a sibling meta file, a new sequence is written, with empty blocks for items left over.
+        let album_path = tp.join("ALBUM_02");
+        fs::remove_file(album_path.join("item.yml")).unwrap();
+
+        let mut plan = WritePlan::new();
+        let paired = plan_release_import(&media_lib, &mut plan, &album_path, &release()).unwrap();
+        assert_eq!(vec![album_path.join("DISC_01"), album_path.join("TRACK_01.flac"), album_path.join("TRACK_02.flac")], paired);
+        plan.execute().unwrap();
+
+        match read_yaml_file(album_path.join("item.yml")).unwrap() {
+            Yaml::Array(ref blocks_y) => {
+                assert_eq!(4, blocks_y.len());
+                assert_eq!(Some("Suite, Part 1"), blocks_y[1]["title"].as_str());
+                assert_eq!(Yaml::Hash(Default::default()), blocks_y[3]);
+            },
+            other => panic!("expected a sequence: {:?}", other),
+        }
+    }
+}
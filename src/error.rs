@@ -14,14 +14,54 @@ error_chain!{
             description("path does not exist"),
             display("path does not exist: '{}'", p.to_string_lossy()),
         }
+        AlreadyExists(p: PathBuf) {
+            description("path already exists")
+            display("path already exists: '{}'", p.to_string_lossy())
+        }
         InvalidSubPath(p: PathBuf, root: PathBuf) {
             description("subpath is not a descendant of root"),
             display("subpath is not a descendant of root: '{}', '{}'", p.to_string_lossy(), root.to_string_lossy()),
         }
+        UnsafePath(p: PathBuf, root: PathBuf) {
+            description("path escapes its root"),
+            display("path escapes its root: '{}', '{}'", p.to_string_lossy(), root.to_string_lossy()),
+        }
+        NotAnAncestor(p: PathBuf, desc: PathBuf) {
+            description("path is not an ancestor of descendant")
+            display("path is not an ancestor of descendant: '{}', '{}'", p.to_string_lossy(), desc.to_string_lossy())
+        }
         InvalidMetaFileName(s: String) {
             description("meta file name is invalid"),
             display("meta file name is invalid: '{}'", s),
         }
+        InvalidFieldPattern(s: String) {
+            description("field name pattern is invalid")
+            display("field name pattern is invalid: '{}'", s)
+        }
+        UndefinedVariable(s: String) {
+            description("variable is not defined")
+            display("variable is not defined: '{}'", s)
+        }
+        UnclosedVariable(s: String) {
+            description("variable reference is missing its closing brace")
+            display("variable reference is missing its closing brace: '{}'", s)
+        }
+        InvalidExpression(s: String) {
+            description("expression is invalid")
+            display("expression is invalid: '{}'", s)
+        }
+        InvalidQuery(s: String) {
+            description("query is invalid")
+            display("query is invalid: '{}'", s)
+        }
+        InvalidNameTemplate(s: String) {
+            description("name template is invalid")
+            display("name template is invalid: '{}'", s)
+        }
+        ReservedKey(s: String) {
+            description("field name uses reserved prefix")
+            display("field name uses reserved prefix: '{}'", s)
+        }
         EmptyMetaFile(p: PathBuf) {
             description("meta file did not contain any data")
             display("meta file did not contain any data: '{}'", p.to_string_lossy())
@@ -34,6 +74,58 @@ error_chain!{
             description("cannot go above file system root")
             display("cannot go above file system root")
         }
+        InvalidArchive(p: PathBuf) {
+            description("unable to read archive")
+            display("unable to read archive: '{}'", p.to_string_lossy())
+        }
+        ReadOnlyArchive(p: PathBuf) {
+            description("archives are read-only")
+            display("archives are read-only, cannot write inside: '{}'", p.to_string_lossy())
+        }
+        ArchivesNotSupported {
+            description("archive support was not compiled in")
+            display("archive support was not compiled in, rebuild with the 'archives' feature")
+        }
+        NotInGitRepository(p: PathBuf) {
+            description("path is not in a git repository")
+            display("path is not in a git repository: '{}'", p.to_string_lossy())
+        }
+        GitCommandFailed(args: String, message: String) {
+            description("git command failed")
+            display("git command failed: 'git {}': {}", args, message)
+        }
+        GitNotSupported {
+            description("git support was not compiled in")
+            display("git support was not compiled in, rebuild with the 'git' feature")
+        }
+        DiscogsRequestFailed(release_id: u64, message: String) {
+            description("Discogs request failed")
+            display("unable to fetch Discogs release {}: {}", release_id, message)
+        }
+        InvalidLocale(tag: String) {
+            description("invalid or unsupported locale")
+            display("invalid or unsupported locale: '{}'", tag)
+        }
+        ReadOnlyLibrary(root: PathBuf) {
+            description("library is read-only")
+            display("library is read-only, refusing to write: '{}'", root.to_string_lossy())
+        }
+        OverlappingOverlay(overlay: PathBuf, root: PathBuf) {
+            description("overlay root overlaps the library it overlays")
+            display("overlay root overlaps the library it overlays: '{}', '{}'", overlay.to_string_lossy(), root.to_string_lossy())
+        }
+        UnexpectedItemName(p: PathBuf, expected: String) {
+            description("item does not have the name its metadata expects")
+            display("item does not have the name its metadata expects ('{}'): '{}'", expected, p.to_string_lossy())
+        }
+        UnexpectedItemHash(p: PathBuf, expected: String, found: String) {
+            description("item does not have the contents its metadata expects")
+            display("item does not have the contents its metadata expects (hash '{}', found '{}'): '{}'", expected, found, p.to_string_lossy())
+        }
+        CollationNotSupported {
+            description("locale-aware sorting was not compiled in")
+            display("locale-aware sorting was not compiled in, rebuild with the 'collation' feature")
+        }
     }
 
     foreign_links {
@@ -0,0 +1,406 @@
+// Libraries on disk to test and benchmark against: a small, hand-written library with some awkward shapes for unit tests,
+// and synthetic libraries of any size for stressing features reproducibly.
+// Needs the `fixtures` feature outside of tests.
+
+use std::fs::{DirBuilder, File};
+use std::path::Path;
+use std::io::Write;
+use std::thread::sleep;
+use std::time::Duration;
+
+use tempdir::TempDir;
+
+use library::{Library, LibraryBuilder};
+use library::selection::Selection;
+use metadata::MetaTarget;
+use error::*;
+
+enum TEntry<'a> {
+    Dir(&'a str, &'a [TEntry<'a>]),
+    File(&'a str)
+}
+
+impl<'a> TEntry<'a> {
+    pub fn name(&self) -> &str {
+        match *self {
+            TEntry::Dir(ref name, _) => name,
+            TEntry::File(ref name) => name,
+        }
+    }
+}
+
+const TEST_DIR_ENTRIES: &[TEntry] = &[
+    // Well-behaved album.
+    TEntry::Dir("ALBUM_01", &[
+        TEntry::Dir("DISC_01", &[
+            TEntry::File("TRACK_01"),
+            TEntry::File("TRACK_02"),
+            TEntry::File("TRACK_03"),
+        ]),
+        TEntry::Dir("DISC_02", &[
+            TEntry::File("TRACK_01"),
+            TEntry::File("TRACK_02"),
+            TEntry::File("TRACK_03"),
+        ]),
+    ]),
+
+    // Album with a disc and tracks, and loose tracks not on a disc.
+    TEntry::Dir("ALBUM_02", &[
+        TEntry::Dir("DISC_01", &[
+            TEntry::File("TRACK_01"),
+            TEntry::File("TRACK_02"),
+            TEntry::File("TRACK_03"),
+        ]),
+        TEntry::File("TRACK_01"),
+        TEntry::File("TRACK_02"),
+        TEntry::File("TRACK_03"),
+    ]),
+
+    // Album with discs and tracks, and subtracks on one disc.
+    TEntry::Dir("ALBUM_03", &[
+        TEntry::Dir("DISC_01", &[
+            TEntry::File("TRACK_01"),
+            TEntry::File("TRACK_02"),
+            TEntry::File("TRACK_03"),
+        ]),
+        TEntry::Dir("DISC_02", &[
+            TEntry::Dir("TRACK_01", &[
+                TEntry::File("SUBTRACK_01"),
+                TEntry::File("SUBTRACK_02"),
+            ]),
+            TEntry::Dir("TRACK_02", &[
+                TEntry::File("SUBTRACK_01"),
+                TEntry::File("SUBTRACK_02"),
+            ]),
+            TEntry::File("TRACK_03"),
+            TEntry::File("TRACK_04"),
+        ]),
+    ]),
+
+    // Album that consists of one file.
+    TEntry::File("ALBUM_04"),
+
+    // A very messed-up album.
+    TEntry::Dir("ALBUM_05", &[
+        TEntry::Dir("DISC_01", &[
+            TEntry::File("SUBTRACK_01"),
+            TEntry::File("SUBTRACK_02"),
+            TEntry::File("SUBTRACK_03"),
+        ]),
+        TEntry::Dir("DISC_02", &[
+            TEntry::Dir("TRACK_01", &[
+                TEntry::File("SUBTRACK_01"),
+                TEntry::File("SUBTRACK_02"),
+            ]),
+        ]),
+        TEntry::File("TRACK_01"),
+        TEntry::File("TRACK_02"),
+        TEntry::File("TRACK_03"),
+    ]),
+];
+
+const MEDIA_FILE_EXT: &str = "flac";
+
+// LEARN: Why unable to use IntoIterator<Item = Entry>?
+fn create_test_dir_entries<'a, P, S>(identifier: S, target_dir_path: P, subentries: &[TEntry<'a>], db: &DirBuilder)
+where P: AsRef<Path>,
+      S: AsRef<str>,
+{
+    let identifier = identifier.as_ref();
+    let target_dir_path = target_dir_path.as_ref();
+
+    // Create self meta file for this directory.
+    let mut self_meta_file = File::create(target_dir_path.join("self.yml")).expect("Unable to create self meta file");
+    writeln!(self_meta_file, "const_key: const_val\nself_key: self_val\n{}_self_key: {}_self_val", identifier, identifier).expect("Unable to write to self meta file");
+
+    // Create all sub-entries, and collect info to create item metadata.
+    let mut item_meta_contents = String::new();
+    for subentry in subentries.into_iter() {
+        // helper(&subentry, &target_dir_path, db /*, imt*/);
+
+        match *subentry {
+            TEntry::File(name) => {
+                File::create(target_dir_path.join(name).with_extension(MEDIA_FILE_EXT)).expect("Unable to create file");
+            },
+            TEntry::Dir(name, new_subentries) => {
+                let new_dir_path = target_dir_path.join(name);
+                db.create(&new_dir_path).expect("Unable to create dir");
+
+                create_test_dir_entries(name, new_dir_path, new_subentries, db);
+            }
+        }
+
+        let entry_string = format!("- const_key: const_val\n  item_key: item_val\n  {}_item_key: {}_item_val\n", subentry.name(), subentry.name());
+        item_meta_contents.push_str(&entry_string);
+    }
+
+    // Create item meta file for all items in this directory.
+    let mut item_meta_file = File::create(target_dir_path.join("item.yml")).expect("Unable to create item meta file");
+    item_meta_file.write_all(item_meta_contents.as_bytes()).expect("Unable to write to item meta file");
+}
+
+pub fn create_temp_media_test_dir(name: &str /*, imt: ItemMetaType*/) -> TempDir {
+    let root_dir = TempDir::new(name).expect("Unable to create temp directory");
+    let db = DirBuilder::new();
+
+    create_test_dir_entries("ROOT", root_dir.path(), TEST_DIR_ENTRIES, &db);
+
+    sleep(Duration::from_millis(1));
+    root_dir
+}
+
+/// Opens a library over a fixture directory, which has `self.yml` and `item.yml` meta files, and `.flac` items.
+pub fn fixture_library<P: AsRef<Path>>(root_dir: P) -> Result<Library> {
+    let meta_target_specs = vec![
+        (String::from("self.yml"), MetaTarget::Contains),
+        (String::from("item.yml"), MetaTarget::Siblings),
+    ];
+
+    let selection = Selection::Or(
+        Box::new(Selection::Ext(String::from(MEDIA_FILE_EXT))),
+        Box::new(Selection::IsDir),
+    );
+
+    LibraryBuilder::new(root_dir.as_ref(), meta_target_specs).selection(selection).create()
+}
+
+pub fn default_setup(name: &str) -> (TempDir, Library) {
+    let temp_media_root = create_temp_media_test_dir(name);
+    let media_lib = fixture_library(temp_media_root.path()).expect("Unable to create media library");
+
+    (temp_media_root, media_lib)
+}
+
+/// A small pseudo-random number generator (xorshift64*), so that synthetic libraries are the same for the same seed on every platform and run.
+struct FixtureRng(u64);
+
+impl FixtureRng {
+    fn new(seed: u64) -> Self {
+        // Scramble the seed with a splitmix64 step, so that nearby seeds give unrelated states.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        // The state must never be zero.
+        FixtureRng(if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z })
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A lowercase word, prefixed so that YAML never reads it as a null, bool or number.
+    fn word(&mut self, len: usize) -> String {
+        let mut word = String::from("v_");
+
+        for _ in 0..len {
+            word.push((b'a' + (self.next() % 26) as u8) as char);
+        }
+
+        word
+    }
+}
+
+/// What was written by `SyntheticLibrary::generate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SyntheticStats {
+    pub dirs: usize,
+    pub files: usize,
+    pub meta_files: usize,
+    pub bytes: usize,
+}
+
+/// Generates libraries of a configurable shape, laid out like the test library: every directory has a `self.yml` and an `item.yml` with a sequence block per item.
+/// Every directory below the root holds `width` subdirectories until `depth` is reached, and every directory, the root included, holds `files_per_dir` items.
+/// Each block has a `title` field with the name of its item, and `fields_per_block` fields `field_NN` with random words of `value_len` letters.
+/// The same settings and seed always give the same library.
+#[derive(Debug, Clone)]
+pub struct SyntheticLibrary {
+    width: usize,
+    depth: usize,
+    files_per_dir: usize,
+    fields_per_block: usize,
+    value_len: usize,
+    seed: u64,
+}
+
+impl Default for SyntheticLibrary {
+    fn default() -> Self {
+        SyntheticLibrary {
+            width: 4,
+            depth: 2,
+            files_per_dir: 10,
+            fields_per_block: 5,
+            value_len: 8,
+            seed: 0,
+        }
+    }
+}
+
+impl SyntheticLibrary {
+    pub fn new() -> Self {
+        SyntheticLibrary::default()
+    }
+
+    pub fn width(&mut self, width: usize) -> &mut Self {
+        self.width = width;
+        self
+    }
+
+    pub fn depth(&mut self, depth: usize) -> &mut Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn files_per_dir(&mut self, files_per_dir: usize) -> &mut Self {
+        self.files_per_dir = files_per_dir;
+        self
+    }
+
+    pub fn fields_per_block(&mut self, fields_per_block: usize) -> &mut Self {
+        self.fields_per_block = fields_per_block;
+        self
+    }
+
+    pub fn value_len(&mut self, value_len: usize) -> &mut Self {
+        self.value_len = value_len;
+        self
+    }
+
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Writes a synthetic library into an existing, empty directory.
+    pub fn generate<P: AsRef<Path>>(&self, root_dir: P) -> Result<SyntheticStats> {
+        let root_dir = root_dir.as_ref();
+
+        // Rule: root must be an empty directory, so that the library is exactly the generated one.
+        ensure!(root_dir.is_dir(), ErrorKind::NotADirectory(root_dir.to_path_buf()));
+        ensure!(root_dir.read_dir()?.next().is_none(), "synthetic library root is not empty: '{}'", root_dir.to_string_lossy());
+
+        let mut rng = FixtureRng::new(self.seed);
+        let mut stats = SyntheticStats::default();
+
+        self.generate_dir(root_dir, "ROOT", 0, &mut rng, &mut stats)?;
+
+        Ok(stats)
+    }
+
+    /// Writes a synthetic library into a new temporary directory, which is removed when dropped, and opens it with `fixture_library`.
+    pub fn generate_temp(&self, name: &str) -> Result<(TempDir, Library, SyntheticStats)> {
+        let temp_media_root = TempDir::new(name)?;
+        let stats = self.generate(temp_media_root.path())?;
+        let media_lib = fixture_library(temp_media_root.path())?;
+
+        Ok((temp_media_root, media_lib, stats))
+    }
+
+    fn block_yaml(&self, name: &str, indent: &str, rng: &mut FixtureRng) -> String {
+        let mut text = format!("title: {}\n", name);
+
+        for i in 0..self.fields_per_block {
+            text.push_str(&format!("{}field_{:02}: {}\n", indent, i, rng.word(self.value_len)));
+        }
+
+        text
+    }
+
+    fn generate_dir(&self, dir_path: &Path, name: &str, depth: usize, rng: &mut FixtureRng, stats: &mut SyntheticStats) -> Result<()> {
+        // Items are named so that they sort the same by name as they are numbered, and subdirectories come first.
+        let mut item_names = vec![];
+
+        if depth < self.depth {
+            item_names.extend((1..=self.width).map(|i| format!("DIR_{:04}", i)));
+        }
+
+        item_names.extend((1..=self.files_per_dir).map(|i| format!("TRACK_{:04}.{}", i, MEDIA_FILE_EXT)));
+
+        let self_text = self.block_yaml(name, "", rng);
+        let mut item_text = String::new();
+
+        for item_name in &item_names {
+            item_text.push_str("- ");
+            item_text.push_str(&self.block_yaml(item_name, "  ", rng));
+        }
+
+        for &(meta_fn, ref text) in &[("self.yml", self_text), ("item.yml", item_text)] {
+            File::create(dir_path.join(meta_fn))?.write_all(text.as_bytes())?;
+            stats.meta_files += 1;
+            stats.bytes += text.len();
+        }
+
+        for item_name in &item_names {
+            let item_path = dir_path.join(item_name);
+
+            if item_name.starts_with("DIR_") {
+                DirBuilder::new().create(&item_path)?;
+                stats.dirs += 1;
+
+                self.generate_dir(&item_path, item_name, depth + 1, rng, stats)?;
+            }
+            else {
+                File::create(&item_path)?;
+                stats.files += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use tempdir::TempDir;
+
+    use lookup::LookupContext;
+    use metadata::MetaValue;
+
+    use super::SyntheticLibrary;
+
+    #[test]
+    fn test_synthetic_library() {
+        let mut synthetic = SyntheticLibrary::new();
+        synthetic.width(3).depth(2).files_per_dir(4).fields_per_block(2).seed(42);
+
+        let (temp_media_root, media_lib, stats) = synthetic.generate_temp("test_synthetic_library").expect("Unable to generate library");
+        let tp = temp_media_root.path();
+
+        assert_eq!(3 + 9, stats.dirs);
+        assert_eq!(4 * 13, stats.files);
+        assert_eq!(2 * 13, stats.meta_files);
+
+        assert_eq!(7, media_lib.children_paths(tp).unwrap().len());
+
+        let track_fp = tp.join("DIR_0002").join("DIR_0003").join("TRACK_0004.flac");
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(MetaValue::Str(String::from("TRACK_0004.flac"))), lookup_ctx.lookup_origin(&track_fp, "title").unwrap());
+        assert!(lookup_ctx.lookup_origin(&track_fp, "field_01").unwrap().is_some());
+
+        // The same seed gives the same library, and a different one does not.
+        let read_item_meta = |root: &::std::path::Path| {
+            let mut text = String::new();
+            File::open(root.join("DIR_0001").join("item.yml")).unwrap().read_to_string(&mut text).unwrap();
+            text
+        };
+
+        let other = TempDir::new("test_synthetic_library_other").unwrap();
+        assert_eq!(stats, synthetic.generate(other.path()).unwrap());
+        assert_eq!(read_item_meta(tp), read_item_meta(other.path()));
+
+        let reseeded = TempDir::new("test_synthetic_library_reseeded").unwrap();
+        synthetic.seed(43).generate(reseeded.path()).unwrap();
+        assert_ne!(read_item_meta(tp), read_item_meta(reseeded.path()));
+
+        // Only empty directories are generated into.
+        assert!(synthetic.generate(tp).is_err());
+    }
+}
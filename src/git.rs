@@ -0,0 +1,294 @@
+// Finds the meta files of a library that changed in the git repository holding it, so that checks can be limited to the directories they affect.
+// This is meant for pre-commit hooks and CI jobs, where checking the whole library on every change would be slow.
+// Git is run as a command, so it needs to be on the `PATH`; only running it needs the `git` feature.
+// Installing hooks does not run git, so it works without the feature.
+
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use library::{Library, is_meta_file_pattern};
+use error::*;
+
+/// Marks hook scripts written by taggu, so that they can be replaced without asking.
+const HOOK_MARKER: &str = "taggu install-hooks";
+
+/// Finds the root of the git repository that holds a directory.
+pub fn repo_root<P: AsRef<Path>>(abs_dir_path: P) -> Result<PathBuf> {
+    let abs_dir_path = abs_dir_path.as_ref();
+
+    let output = runner::run(abs_dir_path, &["rev-parse", "--show-toplevel"])
+        .chain_err(|| ErrorKind::NotInGitRepository(abs_dir_path.to_path_buf()))?;
+
+    Ok(PathBuf::from(output.trim_end_matches('\n')))
+}
+
+/// Splits the output of a git command run with `-z` into paths, taking them to be relative to a directory.
+fn split_paths(output: &str, abs_dir_path: &Path) -> Vec<PathBuf> {
+    output.split('\0').filter(|s| !s.is_empty()).map(|s| abs_dir_path.join(s)).collect()
+}
+
+/// Lists the files inside of a directory that differ from a git ref, including files that are not tracked yet.
+/// Both the old and the new paths of renamed files are listed, as are deleted files.
+pub fn changed_files<P: AsRef<Path>>(abs_dir_path: P, since_ref: &str) -> Result<Vec<PathBuf>> {
+    let abs_dir_path = abs_dir_path.as_ref();
+
+    // Rule: dir path must be in a git repository.
+    repo_root(abs_dir_path)?;
+
+    // Running git from inside the directory limits the paths to the directory, and makes them relative to it.
+    let changed = runner::run(abs_dir_path, &["diff", "--name-only", "--no-renames", "--relative", "-z", since_ref, "--"])?;
+    let untracked = runner::run(abs_dir_path, &["ls-files", "--others", "--exclude-standard", "-z"])?;
+
+    let mut changed_fps: BTreeSet<PathBuf> = BTreeSet::new();
+    changed_fps.extend(split_paths(&changed, abs_dir_path));
+    changed_fps.extend(split_paths(&untracked, abs_dir_path));
+
+    Ok(changed_fps.into_iter().collect())
+}
+
+/// Lists the meta files of a library that differ from a git ref, including meta files that were deleted.
+pub fn changed_meta_fps(media_lib: &Library, since_ref: &str) -> Result<Vec<PathBuf>> {
+    let changed_fps = changed_files(media_lib.root_dir(), since_ref)?;
+
+    Ok(changed_fps.into_iter().filter(|p| media_lib.meta_target_of(p).is_ok()).collect())
+}
+
+/// Finds the directories whose items are affected by changes to meta files.
+/// That is the directory holding each meta file, since it holds every item a meta file can describe, along with every item that can inherit from them.
+/// Directories below another affected directory are left out, as are directories that no longer exist.
+pub fn affected_dirs<I, P>(media_lib: &Library, abs_meta_paths: I) -> Vec<PathBuf>
+where I: IntoIterator<Item = P>,
+      P: AsRef<Path>,
+{
+    let dir_paths: BTreeSet<PathBuf> = abs_meta_paths
+        .into_iter()
+        .filter_map(|p| p.as_ref().parent().map(Path::to_path_buf))
+        .filter(|p| media_lib.is_proper_sub_path(p) && p.is_dir())
+        .collect();
+
+    dir_paths.iter().filter(|p| !p.ancestors().skip(1).any(|a| dir_paths.contains(a))).cloned().collect()
+}
+
+/// Finds the git directory of the repository that holds a directory, without running git.
+/// Worktrees and submodules, where `.git` is a file that points to the git directory, are followed.
+pub fn find_git_dir<P: AsRef<Path>>(abs_dir_path: P) -> Result<PathBuf> {
+    let abs_dir_path = abs_dir_path.as_ref();
+
+    for dir_path in abs_dir_path.ancestors() {
+        let dot_git_path = dir_path.join(".git");
+
+        if dot_git_path.is_dir() {
+            return Ok(dot_git_path);
+        }
+
+        if dot_git_path.is_file() {
+            let mut text = String::new();
+            File::open(&dot_git_path)?.read_to_string(&mut text)?;
+
+            if let Some(git_dir) = text.lines().filter_map(|l| l.strip_prefix("gitdir:")).next() {
+                // Relative paths are relative to the directory holding the `.git` file.
+                return Ok(dir_path.join(git_dir.trim()));
+            }
+        }
+    }
+
+    bail!(ErrorKind::NotInGitRepository(abs_dir_path.to_path_buf()))
+}
+
+/// Returns the path of the pre-commit hook of the repository that holds a directory.
+pub fn pre_commit_hook_fp<P: AsRef<Path>>(abs_dir_path: P) -> Result<PathBuf> {
+    Ok(find_git_dir(abs_dir_path)?.join("hooks").join("pre-commit"))
+}
+
+/// Returns true if a hook script was written by taggu.
+pub fn is_taggu_hook<P: AsRef<Path>>(hook_fp: P) -> bool {
+    let mut text = String::new();
+
+    match File::open(hook_fp).and_then(|mut f| f.read_to_string(&mut text)) {
+        Ok(_) => text.contains(HOOK_MARKER),
+        Err(_) => false,
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Renders a pre-commit hook that lints the items described by the staged meta files of a library, and blocks the commit if there are violations.
+/// Git runs hooks from the top of the work tree, which is where the staged paths are relative to.
+/// The files in the work tree are linted, which differ from the staged files if some changes are not staged.
+pub fn pre_commit_hook_script(media_lib: &Library) -> String {
+    // Meta file names that are patterns are matched as shell patterns, which use the same syntax for the basics.
+    let meta_fn_patterns: Vec<String> = media_lib.meta_target_specs().iter()
+        .map(|&(ref meta_fn, ref meta_target)| {
+            if let Some(suffix) = meta_target.sidecar_suffix() { format!("*.{}", shell_quote(suffix)) }
+            else if is_meta_file_pattern(meta_fn) { meta_fn.clone() }
+            else { shell_quote(meta_fn) }
+        })
+        .collect();
+
+    format!("\
+#!/bin/sh
+# Checks the items described by staged meta files against the schema, installed by `{marker}`.
+# Use `git commit --no-verify` to commit anyway.
+
+meta_fps=$(git diff --cached --name-only --diff-filter=ACMR | while IFS= read -r path; do
+    case \"${{path##*/}}\" in
+        {patterns}) printf '%s\\n' \"$path\" ;;
+    esac
+done)
+
+[ -n \"$meta_fps\" ] || exit 0
+
+printf '%s\\n' \"$meta_fps\" | taggu --root {root} lint --paths-from - || {{
+    echo 'taggu: staged metadata has schema violations, commit aborted' >&2
+    exit 1
+}}
+",
+        marker = HOOK_MARKER,
+        patterns = meta_fn_patterns.join("|"),
+        root = shell_quote(&media_lib.root_dir().to_string_lossy()),
+    )
+}
+
+/// Makes a hook script executable, which git requires before it runs it.
+#[cfg(unix)]
+pub fn make_executable<P: AsRef<Path>>(hook_fp: P) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let hook_fp = hook_fp.as_ref();
+    let mut permissions = fs::metadata(hook_fp)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+    fs::set_permissions(hook_fp, permissions)?;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn make_executable<P: AsRef<Path>>(_hook_fp: P) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(feature = "git")]
+mod runner {
+    use std::path::Path;
+    use std::process::Command;
+
+    use error::*;
+
+    pub fn run(abs_dir_path: &Path, args: &[&str]) -> Result<String> {
+        let output = Command::new("git").arg("-C").arg(abs_dir_path).args(args).output()
+            .chain_err(|| ErrorKind::GitCommandFailed(args.join(" "), String::from("unable to run git")))?;
+
+        ensure!(
+            output.status.success(),
+            ErrorKind::GitCommandFailed(args.join(" "), String::from_utf8_lossy(&output.stderr).trim().to_string())
+        );
+
+        String::from_utf8(output.stdout).chain_err(|| ErrorKind::GitCommandFailed(args.join(" "), String::from("output is not valid UTF-8")))
+    }
+}
+
+#[cfg(not(feature = "git"))]
+mod runner {
+    use std::path::Path;
+
+    use error::*;
+
+    pub fn run(_abs_dir_path: &Path, _args: &[&str]) -> Result<String> {
+        bail!(ErrorKind::GitNotSupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use fixtures::default_setup;
+
+    use super::{affected_dirs, find_git_dir, pre_commit_hook_fp, pre_commit_hook_script};
+
+    #[test]
+    fn test_affected_dirs() {
+        let (temp_media_root, media_lib) = default_setup("test_affected_dirs");
+        let tp = temp_media_root.path();
+
+        let album_dir = tp.join("ALBUM_01");
+        let disc_dir = album_dir.join("DISC_01");
+
+        // Directories below other affected directories are covered already.
+        let produced = affected_dirs(&media_lib, vec![disc_dir.join("item.yml"), album_dir.join("self.yml"), tp.join("ALBUM_02").join("item.yml")]);
+        assert_eq!(vec![album_dir.clone(), tp.join("ALBUM_02")], produced);
+
+        // Meta files in directories that were deleted do not affect anything.
+        fs::remove_dir_all(&disc_dir).unwrap();
+        let produced = affected_dirs(&media_lib, vec![disc_dir.join("item.yml")]);
+        assert!(produced.is_empty());
+    }
+
+    #[test]
+    fn test_pre_commit_hook() {
+        use std::fs::File;
+        use std::io::Write;
+
+        let (temp_media_root, media_lib) = default_setup("test_pre_commit_hook");
+        let tp = temp_media_root.path();
+
+        fs::create_dir_all(tp.join(".git").join("hooks")).unwrap();
+        assert_eq!(tp.join(".git"), find_git_dir(tp.join("ALBUM_01")).unwrap());
+        assert_eq!(tp.join(".git").join("hooks").join("pre-commit"), pre_commit_hook_fp(tp).unwrap());
+
+        // Worktrees have a `.git` file that points to the git directory.
+        let worktree_dir = tp.join("ALBUM_02");
+        let mut f = File::create(worktree_dir.join(".git")).unwrap();
+        writeln!(f, "gitdir: ../.git/worktrees/album").unwrap();
+        assert_eq!(worktree_dir.join("../.git/worktrees/album"), find_git_dir(worktree_dir.join("DISC_01")).unwrap());
+
+        let script = pre_commit_hook_script(&media_lib);
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("'self.yml'|'item.yml')"));
+        assert!(script.contains(&format!("taggu --root '{}' lint --paths-from -", tp.to_string_lossy())));
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn test_changed_meta_fps() {
+        use std::fs::File;
+        use std::io::Write;
+        use std::process::Command;
+
+        use super::{changed_meta_fps, repo_root};
+
+        let (temp_media_root, media_lib) = default_setup("test_changed_meta_fps");
+        let tp = temp_media_root.path();
+
+        let git = |args: &[&str]| {
+            let status = Command::new("git").arg("-C").arg(tp).args(args).status().expect("Unable to run git");
+            assert!(status.success());
+        };
+
+        git(&["init", "-q"]);
+        git(&["add", "-A"]);
+        git(&["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "-m", "initial"]);
+
+        assert_eq!(tp.canonicalize().unwrap(), repo_root(tp).unwrap().canonicalize().unwrap());
+        assert!(changed_meta_fps(&media_lib, "HEAD").unwrap().is_empty());
+
+        // Edited, deleted and untracked meta files are all found, but other files are not.
+        let mut f = File::create(tp.join("ALBUM_01").join("self.yml")).unwrap();
+        writeln!(f, "self_key: edited").unwrap();
+        fs::remove_file(tp.join("ALBUM_02").join("item.yml")).unwrap();
+        fs::create_dir(tp.join("ALBUM_06")).unwrap();
+        File::create(tp.join("ALBUM_06").join("self.yml")).unwrap();
+        File::create(tp.join("ALBUM_06").join("notes.txt")).unwrap();
+
+        let expected = vec![
+            tp.join("ALBUM_01").join("self.yml"),
+            tp.join("ALBUM_02").join("item.yml"),
+            tp.join("ALBUM_06").join("self.yml"),
+        ];
+        assert_eq!(expected, changed_meta_fps(&media_lib, "HEAD").unwrap());
+    }
+}
@@ -0,0 +1,354 @@
+// This module combines the checks that can be run against a library into a single health report.
+// The report has a score, so that the quality of a library can be tracked over time.
+
+use std::collections::BTreeSet;
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::path::{Path, PathBuf};
+
+use library::{Library, WalkStep};
+use schema::{SchemaViolation, ViolationKind};
+use sync::SyncReport;
+use progress::Progress;
+use json::Json;
+use error::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl Severity {
+    /// Missing required fields are warnings, since another meta file may still provide them, and invalid values are errors.
+    pub fn of_violation(kind: &ViolationKind) -> Severity {
+        match *kind {
+            ViolationKind::Missing => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    /// A value that breaks the rules of the schema.
+    Schema,
+    /// An item that is not described by any meta file.
+    Coverage,
+    /// A field whose metadata and embedded tags disagree.
+    Conflict,
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            Category::Schema => write!(f, "schema"),
+            Category::Coverage => write!(f, "coverage"),
+            Category::Conflict => write!(f, "conflict"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub category: Category,
+    pub item_path: PathBuf,
+    pub message: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{} [{}] {}: {}", self.severity, self.category, self.item_path.to_string_lossy(), self.message)
+    }
+}
+
+/// The findings of all checks run against a library, along with how many of its items are described by metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HealthReport {
+    findings: Vec<Finding>,
+    items_total: usize,
+    items_described: usize,
+}
+
+impl HealthReport {
+    pub fn new() -> Self {
+        HealthReport::default()
+    }
+
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+
+    pub fn items_total(&self) -> usize {
+        self.items_total
+    }
+
+    pub fn items_described(&self) -> usize {
+        self.items_described
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|f| f.severity == severity).count()
+    }
+
+    /// The fraction of items that are described by at least one meta file.
+    /// A library without items is fully covered.
+    pub fn coverage(&self) -> f64 {
+        if self.items_total == 0 { 1.0 }
+        else { self.items_described as f64 / self.items_total as f64 }
+    }
+
+    /// Rates the library from 0 to 100.
+    /// The score starts at the coverage percentage, and is then reduced in proportion to the number of errors per item,
+    /// where a warning counts as a quarter of an error and infos do not count.
+    pub fn score(&self) -> u8 {
+        let weight = self.count(Severity::Error) as f64 + self.count(Severity::Warning) as f64 / 4.0;
+        let penalty = (weight / self.items_total.max(1) as f64).min(1.0);
+
+        (100.0 * self.coverage() * (1.0 - penalty)).round() as u8
+    }
+
+    pub fn push(&mut self, finding: Finding) -> &mut Self {
+        self.findings.push(finding);
+        self
+    }
+
+    /// Adds schema violations, with severities as given by `Severity::of_violation`.
+    pub fn add_schema_violations(&mut self, violations: &[SchemaViolation]) -> &mut Self {
+        for violation in violations {
+            self.push(Finding {
+                severity: Severity::of_violation(&violation.kind),
+                category: Category::Schema,
+                item_path: violation.item_path.clone(),
+                message: format!("{}: {}", violation.field_name, violation.kind),
+            });
+        }
+
+        self
+    }
+
+    /// Adds the conflicts between metadata and embedded tags found while syncing, as warnings.
+    pub fn add_sync_reports(&mut self, reports: &[SyncReport]) -> &mut Self {
+        for report in reports {
+            for conflict in &report.conflicts {
+                self.push(Finding {
+                    severity: Severity::Warning,
+                    category: Category::Conflict,
+                    item_path: report.item_path.clone(),
+                    message: conflict.to_string(),
+                });
+            }
+        }
+
+        self
+    }
+
+    /// Counts the items in a library and the items described by its meta files, and adds an info for every item that is not described.
+    pub fn add_coverage<G: Progress>(&mut self, media_lib: &Library, progress: &mut G) -> Result<&mut Self> {
+        let mut described: BTreeSet<PathBuf> = BTreeSet::new();
+
+        for meta_fp in media_lib.meta_fps_in_tree(media_lib.root_dir())? {
+            let parsed = media_lib.read_meta_file(&meta_fp)?;
+            progress.meta_file_parsed(&meta_fp);
+
+            described.extend(media_lib.iter_item_blocks(&parsed)?.map(|(item_path, _)| item_path));
+        }
+
+        media_lib.walk_items(media_lib.root_dir(), |step| {
+            // Directories that only lead into the scope are walked, but not counted.
+            if let WalkStep::Item { path, .. } = step {
+                if media_lib.is_in_scope(path) {
+                    progress.item_scanned(path);
+                    self.items_total += 1;
+
+                    if described.contains(path) {
+                        self.items_described += 1;
+                    }
+                    else {
+                        self.push(Finding {
+                            severity: Severity::Info,
+                            category: Category::Coverage,
+                            item_path: path.to_path_buf(),
+                            message: String::from("not described by any meta file"),
+                        });
+                    }
+                }
+            }
+
+            Ok(true)
+        })?;
+
+        Ok(self)
+    }
+
+    /// Orders findings from most to least severe, and then by item path.
+    pub fn sort(&mut self) {
+        self.findings.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.item_path.cmp(&b.item_path)));
+    }
+
+    fn summary_pairs(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("score", self.score().to_string()),
+            ("items", self.items_total.to_string()),
+            ("described", self.items_described.to_string()),
+            ("errors", self.count(Severity::Error).to_string()),
+            ("warnings", self.count(Severity::Warning).to_string()),
+            ("infos", self.count(Severity::Info).to_string()),
+        ]
+    }
+
+    pub fn as_json(&self) -> Json {
+        Json::object(vec![
+            ("score", Json::Int(self.score() as i64)),
+            ("items", Json::Int(self.items_total as i64)),
+            ("described", Json::Int(self.items_described as i64)),
+            ("coverage", Json::Float(self.coverage())),
+            ("errors", Json::Int(self.count(Severity::Error) as i64)),
+            ("warnings", Json::Int(self.count(Severity::Warning) as i64)),
+            ("infos", Json::Int(self.count(Severity::Info) as i64)),
+            ("findings", Json::Array(self.findings.iter().map(|finding| {
+                Json::object(vec![
+                    ("severity", Json::str(finding.severity.to_string())),
+                    ("category", Json::str(finding.category.to_string())),
+                    ("item_path", Json::path(&finding.item_path)),
+                    ("message", Json::str(finding.message.as_str())),
+                ])
+            }).collect())),
+        ])
+    }
+
+    /// Renders the report as a standalone HTML page.
+    /// Paths are shown relative to a root directory, if they are inside of it.
+    pub fn as_html<P: AsRef<Path>>(&self, root_dir: P) -> String {
+        let root_dir = root_dir.as_ref();
+        let mut html = String::new();
+
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>taggu health report</title>\n</head>\n<body>\n");
+        html.push_str(&format!("<h1>Health report for {}</h1>\n", escape_html(&root_dir.to_string_lossy())));
+
+        html.push_str("<table class=\"summary\">\n");
+        for (name, value) in self.summary_pairs() {
+            html.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", name, value));
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<table class=\"findings\">\n<tr><th>severity</th><th>category</th><th>item</th><th>message</th></tr>\n");
+        for finding in &self.findings {
+            let item_path = finding.item_path.strip_prefix(root_dir).unwrap_or(&finding.item_path);
+
+            html.push_str(&format!(
+                "<tr class=\"{0}\"><td>{0}</td><td>{1}</td><td>{2}</td><td>{3}</td></tr>\n",
+                finding.severity,
+                finding.category,
+                escape_html(&item_path.to_string_lossy()),
+                escape_html(&finding.message),
+            ));
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+
+        html
+    }
+}
+
+impl Display for HealthReport {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+
+        for (name, value) in self.summary_pairs() {
+            writeln!(f, "{}: {}", name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+
+    use schema::{SchemaViolation, ViolationKind};
+    use progress::NoProgress;
+    use fixtures::default_setup;
+
+    use super::{HealthReport, Severity, Category, escape_html};
+
+    #[test]
+    fn test_health_report() {
+        let (temp_media_root, media_lib) = default_setup("test_health_report");
+        let tp = temp_media_root.path();
+
+        let mut report = HealthReport::new();
+        report.add_coverage(&media_lib, &mut NoProgress).unwrap();
+
+        assert!(report.items_total() > 0);
+        let fully_described = report.items_described();
+
+        // A new file is an item that no meta file describes yet.
+        let undescribed_fp = tp.join("ALBUM_01").join("EXTRA.flac");
+        File::create(&undescribed_fp).unwrap();
+
+        let mut report = HealthReport::new();
+        report.add_coverage(&media_lib, &mut NoProgress).unwrap();
+        assert_eq!(fully_described, report.items_described());
+        assert_eq!(report.items_total(), report.items_described() + 1);
+        assert_eq!(1, report.count(Severity::Info));
+        assert_eq!(Category::Coverage, report.findings()[0].category);
+        assert_eq!(undescribed_fp, report.findings()[0].item_path);
+
+        let coverage_score = report.score();
+        assert!(coverage_score < 100);
+
+        report.add_schema_violations(&[
+            SchemaViolation { item_path: PathBuf::from("a"), meta_path: None, field_name: String::from("title"), kind: ViolationKind::Missing },
+            SchemaViolation { item_path: PathBuf::from("b"), meta_path: None, field_name: String::from("year"), kind: ViolationKind::NotNumeric(String::from("x")) },
+        ]);
+        report.sort();
+
+        assert_eq!(1, report.count(Severity::Error));
+        assert_eq!(1, report.count(Severity::Warning));
+        assert_eq!(Severity::Error, report.findings()[0].severity);
+        assert!(report.score() < coverage_score);
+
+        assert!(report.to_string().contains("error [schema] b: year: value is not numeric: 'x'\n"));
+        assert!(report.as_json().to_string().contains(r#""errors":1,"warnings":1,"infos":1"#));
+        assert!(report.as_html(tp).contains("<td>ALBUM_01/EXTRA.flac</td>"));
+
+        fs::remove_file(&undescribed_fp).unwrap();
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!("a &lt;b&gt; &amp; &quot;c&quot; &#39;d&#39;", escape_html("a <b> & \"c\" 'd'"));
+    }
+}
@@ -1,9 +1,15 @@
 use std::path::{Path, PathBuf, Component};
 use std::fmt::{Formatter, Result as FmtResult, Display};
 use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::time::SystemTime;
 
 use glob;
 
+use vfs::{Vfs, StdFs};
+use error::{ErrorKind, Result as TagguResult};
+
 // #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 // pub struct NormedPath {
 //     path: PathBuf,
@@ -21,6 +27,79 @@ use glob;
 //     }
 // }
 
+/// What a file looked like on disk at some point, used by caches to tell if it has changed since.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+}
+
+impl FileStamp {
+    /// Returns `None` if the file does not exist or cannot be read.
+    pub fn read(path: &Path) -> Option<FileStamp> {
+        FileStamp::read_with(&StdFs, path)
+    }
+
+    /// Like `read`, but for a file on a given file system.
+    pub fn read_with<V: Vfs + ?Sized>(vfs: &V, path: &Path) -> Option<FileStamp> {
+        vfs.metadata(path).ok().map(|md| FileStamp { modified: md.modified, len: md.len })
+    }
+}
+
+pub const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Feeds bytes into a 64-bit FNV-1a hash, which starts out as `FNV_OFFSET_BASIS`.
+pub fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    hash
+}
+
+/// Hashes the contents of a file with 64-bit FNV-1a, written as 16 lowercase hex digits.
+/// This is not a cryptographic hash; it only serves to tell if a file is still the one that metadata was written for.
+pub fn content_hash(path: &Path) -> TagguResult<String> {
+    let mut f = File::open(path)?;
+    let mut buffer = [0u8; 8192];
+    let mut hash = FNV_OFFSET_BASIS;
+
+    loop {
+        let n = f.read(&mut buffer)?;
+
+        if n == 0 {
+            break;
+        }
+
+        hash = fnv1a(hash, &buffer[..n]);
+    }
+
+    Ok(format!("{:016x}", hash))
+}
+
+/// Writes a file by writing a temporary file next to it and renaming it into place.
+/// Readers see either the old or the new contents, never a partly written file.
+pub fn write_file_atomic(path: &Path, bytes: &[u8]) -> TagguResult<()> {
+    let file_name = path.file_name().ok_or_else(|| ErrorKind::NotAFile(path.to_path_buf()))?;
+
+    let mut temp_name = file_name.to_os_string();
+    temp_name.push(".taggu-tmp");
+    let temp_path = path.with_file_name(format!(".{}", temp_name.to_string_lossy()));
+
+    let result = File::create(&temp_path)
+        .and_then(|mut f| f.write_all(bytes).and_then(|_| f.sync_all()))
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    Ok(result?)
+}
+
 pub fn normalize<P: AsRef<Path>>(p: P) -> PathBuf {
     let p = p.as_ref();
     let mut stack: Vec<Component> = vec![];
@@ -84,6 +163,46 @@ pub fn normalize<P: AsRef<Path>>(p: P) -> PathBuf {
     norm_path
 }
 
+/// Joins a relative path referenced from metadata onto a root directory, making sure that the result stays inside the root.
+/// Absolute paths and `..` components that climb out of the root are rejected, but symlinks are not followed.
+pub fn lexical_join<P: AsRef<Path>, Q: AsRef<Path>>(root_dir: P, rel_path: Q) -> TagguResult<PathBuf> {
+    let root_dir = normalize(root_dir.as_ref());
+    let rel_path = rel_path.as_ref();
+
+    // Rule: path must be relative.
+    let is_relative = rel_path.components().all(|c| match c {
+        Component::Prefix(_) | Component::RootDir => false,
+        _ => true,
+    });
+
+    ensure!(is_relative, ErrorKind::UnsafePath(rel_path.to_path_buf(), root_dir.clone()));
+
+    let joined = normalize(root_dir.join(rel_path));
+
+    // Rule: path must not climb out of the root.
+    ensure!(joined.starts_with(&root_dir), ErrorKind::UnsafePath(joined.clone(), root_dir.clone()));
+
+    Ok(joined)
+}
+
+/// Joins a relative path referenced from metadata onto a root directory, making sure that the result stays inside the root.
+/// Absolute paths, `..` components that climb out of the root, and symlinks that point outside of the root are all rejected.
+/// Paths that do not exist yet are checked up to their nearest existing ancestor.
+pub fn safe_join<P: AsRef<Path>, Q: AsRef<Path>>(root_dir: P, rel_path: Q) -> TagguResult<PathBuf> {
+    let root_dir = normalize(root_dir.as_ref());
+    let joined = lexical_join(&root_dir, rel_path)?;
+
+    // Rule: path must not resolve to outside of the root, once symlinks are followed.
+    if let Ok(canon_root_dir) = root_dir.canonicalize() {
+        if let Some(existing) = joined.ancestors().find(|p| p.exists()) {
+            let canon_existing = existing.canonicalize()?;
+            ensure!(canon_existing.starts_with(&canon_root_dir), ErrorKind::UnsafePath(joined.clone(), root_dir.clone()));
+        }
+    }
+
+    Ok(joined)
+}
+
 pub fn is_valid_item_name<S: AsRef<str>>(file_name: S) -> bool {
     let file_name = file_name.as_ref();
     let normed = normalize(Path::new(file_name));
@@ -129,6 +248,26 @@ pub fn is_valid_fn<S: AsRef<str>>(s: S) -> bool {
     p.as_os_str() == s_path.as_os_str()
 }
 
+/// Splits a line of CSV into its fields, where fields may be quoted, and quotes inside quoted fields are doubled.
+pub fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => { chars.next(); field.push('"'); },
+            '"' => { in_quotes = !in_quotes; },
+            ',' if !in_quotes => { fields.push(field.clone()); field.clear(); },
+            _ => { field.push(c); },
+        }
+    }
+
+    fields.push(field);
+    fields
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum FuzzyMatchError {
     InvalidPattern(String),
@@ -193,9 +332,16 @@ where N: AsRef<str>,
 #[cfg(test)]
 mod tests {
     use std::path::{Path, PathBuf};
+    use std::fs::{DirBuilder, File};
+    use std::io::Write;
+    use std::os::unix::fs::symlink;
+
+    use tempdir::TempDir;
 
     use super::{
+        content_hash,
         normalize,
+        safe_join,
         is_valid_item_name,
         fuzzy_name_match,
         FuzzyMatchError,
@@ -428,6 +574,53 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_safe_join() {
+        let temp = TempDir::new("test_safe_join").unwrap();
+        let outside = TempDir::new("test_safe_join_outside").unwrap();
+        let root = temp.path().canonicalize().unwrap();
+
+        DirBuilder::new().create(root.join("album")).unwrap();
+        File::create(root.join("album").join("cover.jpg")).unwrap();
+        File::create(outside.path().join("secret.jpg")).unwrap();
+        symlink(outside.path(), root.join("escape")).unwrap();
+        symlink(root.join("album"), root.join("alias")).unwrap();
+
+        let inputs_and_expected = vec![
+            ("album/cover.jpg", Some(root.join("album").join("cover.jpg"))),
+            ("album/../album/cover.jpg", Some(root.join("album").join("cover.jpg"))),
+            ("album/missing/new.jpg", Some(root.join("album").join("missing").join("new.jpg"))),
+            ("alias/cover.jpg", Some(root.join("alias").join("cover.jpg"))),
+            ("", Some(root.clone())),
+            ("..", None),
+            ("album/../../secret.jpg", None),
+            ("/etc/passwd", None),
+            ("escape/secret.jpg", None),
+            ("escape/missing.jpg", None),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = safe_join(&root, input).ok();
+            assert_eq!(expected, produced, "unexpected result for: {}", input);
+        }
+
+        // Absolute paths are rejected even if they are inside the root.
+        assert!(safe_join(&root, root.join("album")).is_err());
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let temp = TempDir::new("test_content_hash").unwrap();
+        let tp = temp.path();
+
+        File::create(tp.join("empty")).unwrap();
+        write!(File::create(tp.join("a")).unwrap(), "a").unwrap();
+
+        assert_eq!("cbf29ce484222325", content_hash(&tp.join("empty")).unwrap());
+        assert_eq!("af63dc4c8601ec8c", content_hash(&tp.join("a")).unwrap());
+        assert!(content_hash(&tp.join("missing")).is_err());
+    }
+
     #[test]
     fn test_fuzzy_name_match() {
         let haystack = [
@@ -0,0 +1,468 @@
+// This module imports the metadata of tracks from an iTunes (or Apple Music) library, as exported to a `Library.xml` property list.
+
+use std::path::PathBuf;
+
+use library::Library;
+use metadata::{MetaBlock, MetaValue};
+use metadata::keys::{RATING_KEY, PLAY_COUNT_KEY, LAST_PLAYED_KEY};
+use writer::plan_edit_item_block;
+use plan::WritePlan;
+use error::*;
+
+/// Pairs of iTunes track keys and the taggu field names that they correspond to.
+/// Ratings and compilations need converting, and are handled separately.
+const ITUNES_FIELD_PAIRS: &[(&str, &str)] = &[
+    ("Name", "title"),
+    ("Artist", "artist"),
+    ("Album Artist", "album_artist"),
+    ("Album", "album"),
+    ("Composer", "composer"),
+    ("Genre", "genre"),
+    ("Year", "date"),
+    ("Track Number", "track_num"),
+    ("Disc Number", "disc_num"),
+    ("Comments", "comment"),
+    ("Play Count", PLAY_COUNT_KEY),
+    ("Play Date UTC", LAST_PLAYED_KEY),
+    ("Sort Name", "title_sort"),
+    ("Sort Artist", "artist_sort"),
+    ("Sort Album Artist", "album_artist_sort"),
+    ("Sort Album", "album_sort"),
+    ("Sort Composer", "composer_sort"),
+];
+
+const COMPILATION_FIELD: &str = "compilation";
+
+/// iTunes rates from 0 to 100 in steps of 20, i.e. one star is 20.
+const ITUNES_RATING_STEP: i64 = 20;
+
+/// A value in a property list. Dates, reals and data are kept as the text that they are written as.
+#[derive(Debug, Clone, PartialEq)]
+enum PlistValue {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Array(Vec<PlistValue>),
+    Dict(Vec<(String, PlistValue)>),
+}
+
+impl PlistValue {
+    fn get(&self, key: &str) -> Option<&PlistValue> {
+        match *self {
+            PlistValue::Dict(ref entries) => entries.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match *self {
+            PlistValue::Str(ref s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Replaces the character and entity references in XML text.
+fn unescape_xml(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let entity = &rest[1..end];
+
+        let opt_c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse::<u32>().ok().and_then(::std::char::from_u32),
+            _ => None,
+        };
+
+        match opt_c {
+            Some(c) => {
+                unescaped.push(c);
+                rest = &rest[(end + 1)..];
+            },
+            // Unknown references are kept as they are.
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Reads the subset of XML used by property lists, i.e. elements without attributes that matter, and no mixed content.
+struct PlistParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> PlistParser<'a> {
+    fn new(text: &'a str) -> Self {
+        PlistParser { text, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    /// Skips whitespace, along with the XML declaration, the doctype and comments.
+    fn skip_misc(&mut self) {
+        loop {
+            let trimmed = self.rest().trim_start();
+            self.pos = self.text.len() - trimmed.len();
+
+            let end_marker = if trimmed.starts_with("<?") { "?>" }
+                else if trimmed.starts_with("<!--") { "-->" }
+                else if trimmed.starts_with("<!") { ">" }
+                else { return; };
+
+            match trimmed.find(end_marker) {
+                Some(i) => { self.pos += i + end_marker.len(); },
+                None => {
+                    self.pos = self.text.len();
+                    return;
+                },
+            }
+        }
+    }
+
+    /// Reads an opening tag, returning its name and whether it closes itself, e.g. `<true/>`.
+    fn read_tag(&mut self) -> Result<(&'a str, bool)> {
+        self.skip_misc();
+
+        let rest = self.rest();
+
+        // Rule: values must start with a tag.
+        ensure!(rest.starts_with('<') && !rest.starts_with("</"), "expected a property list element at byte {}", self.pos);
+
+        let end = rest.find('>').ok_or("unterminated property list element")?;
+        let inner = &rest[1..end];
+        self.pos += end + 1;
+
+        let self_closing = inner.ends_with('/');
+        let name = inner.trim_end_matches('/').split_whitespace().next().unwrap_or("");
+
+        Ok((name, self_closing))
+    }
+
+    /// Reads the text of an element, up to its closing tag.
+    fn read_text(&mut self, name: &str) -> Result<String> {
+        let close = format!("</{}>", name);
+        let end = self.rest().find(&close).ok_or_else(|| format!("missing closing tag: '{}'", close))?;
+        let text = unescape_xml(&self.rest()[..end]);
+        self.pos += end + close.len();
+
+        Ok(text)
+    }
+
+    /// Consumes a closing tag, if it comes next.
+    fn read_close(&mut self, name: &str) -> bool {
+        self.skip_misc();
+
+        let close = format!("</{}>", name);
+
+        if self.rest().starts_with(&close) {
+            self.pos += close.len();
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    fn read_value(&mut self) -> Result<PlistValue> {
+        let (name, self_closing) = self.read_tag()?;
+
+        let text = |parser: &mut Self| if self_closing { Ok(String::new()) } else { parser.read_text(name) };
+
+        match name {
+            "plist" => {
+                let value = self.read_value()?;
+                self.read_close("plist");
+                Ok(value)
+            },
+            "dict" => {
+                let mut entries = vec![];
+
+                while !self_closing && !self.read_close("dict") {
+                    let (key_name, key_closing) = self.read_tag()?;
+
+                    // Rule: dict entries must start with a key.
+                    ensure!(key_name == "key", "expected a key in a property list dict, found '{}'", key_name);
+
+                    let key = if key_closing { String::new() } else { self.read_text("key")? };
+                    entries.push((key, self.read_value()?));
+                }
+
+                Ok(PlistValue::Dict(entries))
+            },
+            "array" => {
+                let mut values = vec![];
+
+                while !self_closing && !self.read_close("array") {
+                    values.push(self.read_value()?);
+                }
+
+                Ok(PlistValue::Array(values))
+            },
+            "true" | "false" => {
+                text(self)?;
+                Ok(PlistValue::Bool(name == "true"))
+            },
+            "integer" => {
+                let s = text(self)?;
+                Ok(PlistValue::Int(s.trim().parse().chain_err(|| format!("invalid property list integer: '{}'", s))?))
+            },
+            "string" | "date" | "real" | "data" => Ok(PlistValue::Str(text(self)?)),
+            _ => bail!("unexpected property list element: '{}'", name),
+        }
+    }
+}
+
+/// Turns a `file://` URL into a path, decoding any percent-encoded bytes.
+pub fn file_url_path(url: &str) -> Option<String> {
+    let encoded = if url.starts_with("file://localhost/") { &url["file://localhost".len()..] }
+        else if url.starts_with("file:///") { &url["file://".len()..] }
+        else { return None; };
+
+    let bytes = encoded.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let opt_byte = if bytes[i] == b'%' { encoded.get((i + 1)..(i + 3)).and_then(|hex| u8::from_str_radix(hex, 16).ok()) } else { None };
+
+        match opt_byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            },
+        }
+    }
+
+    Some(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// A single track read from an iTunes library, with fields already renamed to their taggu names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItunesTrack {
+    /// The path of the track's file on the machine that the library was exported on.
+    pub location: String,
+    pub fields: MetaBlock,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ItunesLibrary {
+    /// The path of the folder that iTunes keeps its media in, if the library says.
+    pub music_folder: Option<String>,
+    pub tracks: Vec<ItunesTrack>,
+}
+
+fn itunes_track(track_v: &PlistValue) -> Option<ItunesTrack> {
+    // Tracks without a file, e.g. streams, have no location.
+    let location = track_v.get("Location").and_then(PlistValue::as_str).and_then(file_url_path)?;
+
+    let mut fields = MetaBlock::new();
+
+    for &(itunes_key, field_name) in ITUNES_FIELD_PAIRS {
+        let opt_val = match track_v.get(itunes_key) {
+            Some(&PlistValue::Str(ref s)) if !s.is_empty() => Some(s.clone()),
+            Some(&PlistValue::Int(i)) => Some(i.to_string()),
+            _ => None,
+        };
+
+        if let Some(val) = opt_val {
+            fields.insert(field_name.to_string(), MetaValue::Str(val));
+        }
+    }
+
+    // A computed rating comes from the rating of the album, rather than the track itself.
+    if let (Some(&PlistValue::Int(rating)), None) = (track_v.get("Rating"), track_v.get("Rating Computed")) {
+        fields.insert(RATING_KEY.to_string(), MetaValue::Str((rating / ITUNES_RATING_STEP).to_string()));
+    }
+
+    if let Some(&PlistValue::Bool(true)) = track_v.get("Compilation") {
+        fields.insert(COMPILATION_FIELD.to_string(), MetaValue::Str(String::from("true")));
+    }
+
+    Some(ItunesTrack { location, fields })
+}
+
+/// Reads the tracks in an iTunes library export. Tracks that are not files are left out.
+pub fn read_itunes_library(text: &str) -> Result<ItunesLibrary> {
+    let library_v = PlistParser::new(text).read_value()?;
+
+    let tracks_v = match library_v.get("Tracks") {
+        Some(&PlistValue::Dict(ref entries)) => entries,
+        _ => bail!("iTunes library is missing its tracks"),
+    };
+
+    Ok(ItunesLibrary {
+        music_folder: library_v.get("Music Folder").and_then(PlistValue::as_str).and_then(file_url_path),
+        tracks: tracks_v.iter().filter_map(|&(_, ref track_v)| itunes_track(track_v)).collect(),
+    })
+}
+
+/// Plans writing the fields of iTunes tracks into the blocks of the matching library items.
+/// A track matches the item at the same path relative to the library root as the track's location is to `music_folder`.
+/// Returns the paths of the imported items, and the locations of the tracks that match no item.
+pub fn import_itunes_tracks(media_lib: &Library, tracks: &[ItunesTrack], music_folder: &str, plan: &mut WritePlan) -> Result<(Vec<PathBuf>, Vec<String>)> {
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    let music_folder = music_folder.trim_end_matches('/');
+
+    let mut imported = vec![];
+    let mut unmatched = vec![];
+
+    for track in tracks {
+        let opt_rel_path = if track.location.starts_with(music_folder) && track.location[music_folder.len()..].starts_with('/') {
+            Some(track.location[music_folder.len()..].trim_start_matches('/'))
+        }
+        else {
+            None
+        };
+
+        let abs_item_path = match opt_rel_path.map(|rel_path| media_lib.safe_sub_path(rel_path)) {
+            Some(Ok(ref p)) if p.is_file() => p.clone(),
+            _ => {
+                unmatched.push(track.location.clone());
+                continue;
+            },
+        };
+
+        let fields = track.fields.clone();
+        plan_edit_item_block(media_lib, plan, &abs_item_path, |mb| mb.extend(fields))?;
+
+        imported.push(abs_item_path);
+    }
+
+    Ok((imported, unmatched))
+}
+
+#[cfg(test)]
+mod tests {
+    use lookup::LookupContext;
+    use metadata::MetaValue;
+    use fixtures::default_setup;
+    use plan::WritePlan;
+
+    use super::{ItunesTrack, read_itunes_library, import_itunes_tracks, unescape_xml, file_url_path};
+
+    const LIBRARY_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple Computer//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Major Version</key><integer>1</integer>
+	<key>Music Folder</key><string>file://localhost/Users/me/Music/iTunes%20Media/</string>
+	<key>Tracks</key>
+	<dict>
+		<key>101</key>
+		<dict>
+			<key>Track ID</key><integer>101</integer>
+			<key>Name</key><string>Rock &amp; Roll</string>
+			<key>Artist</key><string>Artist</string>
+			<key>Sort Artist</key><string>Artist, The</string>
+			<key>Year</key><integer>1999</integer>
+			<key>Play Count</key><integer>7</integer>
+			<key>Play Date UTC</key><date>2020-01-31T12:34:00Z</date>
+			<key>Rating</key><integer>80</integer>
+			<key>Compilation</key><true/>
+			<key>Location</key><string>file://localhost/Users/me/Music/iTunes%20Media/ALBUM_02/TRACK_01.flac</string>
+		</dict>
+		<key>102</key>
+		<dict>
+			<key>Name</key><string>Stream</string>
+			<key>Rating</key><integer>100</integer>
+			<key>Rating Computed</key><true/>
+		</dict>
+	</dict>
+	<key>Playlists</key>
+	<array>
+		<dict><key>Name</key><string>Library</string></dict>
+	</array>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn test_read_itunes_library() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let library = read_itunes_library(LIBRARY_XML).unwrap();
+        assert_eq!(Some(String::from("/Users/me/Music/iTunes Media/")), library.music_folder);
+
+        let expected = vec![
+            ItunesTrack {
+                location: String::from("/Users/me/Music/iTunes Media/ALBUM_02/TRACK_01.flac"),
+                fields: btreemap![
+                    String::from("title") => str_val("Rock & Roll"),
+                    String::from("artist") => str_val("Artist"),
+                    String::from("artist_sort") => str_val("Artist, The"),
+                    String::from("date") => str_val("1999"),
+                    String::from("playcount") => str_val("7"),
+                    String::from("lastplayed") => str_val("2020-01-31T12:34:00Z"),
+                    String::from("rating") => str_val("4"),
+                    String::from("compilation") => str_val("true"),
+                ],
+            },
+        ];
+        assert_eq!(expected, library.tracks);
+
+        assert!(read_itunes_library("<plist><array></array></plist>").is_err());
+        assert!(read_itunes_library("<plist><dict><key>Tracks</key><dict><string>x</string></dict></dict></plist>").is_err());
+        assert!(read_itunes_library("<plist><dict><key>Tracks</key>").is_err());
+    }
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!("a & b < c \u{e9} &bogus;", unescape_xml("a &amp; b &lt; c &#233; &bogus;"));
+        assert_eq!(Some(String::from("/M\u{fc}sic/a b.flac")), file_url_path("file:///M%C3%BCsic/a%20b.flac"));
+        assert_eq!(Some(String::from("/C:/Music/100%.flac")), file_url_path("file://localhost/C:/Music/100%.flac"));
+        assert_eq!(None, file_url_path("http://example.com/a.mp3"));
+    }
+
+    #[test]
+    fn test_import_itunes_tracks() {
+        let (temp_media_root, media_lib) = default_setup("test_import_itunes_tracks");
+        let tp = temp_media_root.path();
+
+        let mut tracks = read_itunes_library(LIBRARY_XML).unwrap().tracks;
+        tracks.push(ItunesTrack { location: String::from("/Users/me/Music/iTunes Media/ALBUM_02/TRACK_09.flac"), fields: btreemap![] });
+        tracks.push(ItunesTrack { location: String::from("/Users/me/Music/iTunes Media/../ESCAPED.flac"), fields: btreemap![] });
+        tracks.push(ItunesTrack { location: String::from("/Elsewhere/ALBUM_02/TRACK_02.flac"), fields: btreemap![] });
+
+        let mut plan = WritePlan::new();
+        let (imported, unmatched) = import_itunes_tracks(&media_lib, &tracks, "/Users/me/Music/iTunes Media/", &mut plan).unwrap();
+
+        let track_fp = tp.join("ALBUM_02").join("TRACK_01.flac");
+        assert_eq!(vec![track_fp.clone()], imported);
+        assert_eq!(3, unmatched.len());
+
+        plan.execute().unwrap();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(MetaValue::Str(String::from("Rock & Roll"))), lookup_ctx.lookup_origin(&track_fp, "title").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("TRACK_01_item_val"))), lookup_ctx.lookup_origin(&track_fp, "TRACK_01_item_key").unwrap());
+    }
+}
@@ -0,0 +1,181 @@
+// This module provides a minimal JSON representation, for machine-readable command output.
+// Objects keep their keys in insertion order, so that output is stable from run to run.
+
+use std::fmt::{Formatter, Result as FmtResult, Display, Write};
+use std::path::Path;
+
+use yaml_rust::Yaml;
+
+use metadata::{MetaKey, MetaValue};
+
+/// The version of the JSON output of every command.
+/// It is increased whenever existing keys are removed or change meaning, but not when new keys are added.
+pub const OUTPUT_FORMAT_VERSION: i64 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn str<S: Into<String>>(s: S) -> Json {
+        Json::Str(s.into())
+    }
+
+    pub fn path<P: AsRef<Path>>(p: P) -> Json {
+        Json::Str(p.as_ref().to_string_lossy().into_owned())
+    }
+
+    pub fn object<S: Into<String>>(pairs: Vec<(S, Json)>) -> Json {
+        Json::Object(pairs.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    pub fn from_meta_value(mv: &MetaValue) -> Json {
+        match *mv {
+            MetaValue::Nil => Json::Null,
+            MetaValue::Str(ref s) => Json::Str(s.clone()),
+            MetaValue::Seq(ref mvs) => Json::Array(mvs.iter().map(Json::from_meta_value).collect()),
+            MetaValue::Map(ref map) => {
+                // JSON keys must be strings, so a nil key is written as an empty string.
+                Json::Object(map.iter().map(|(mk, mv)| {
+                    let key = match *mk {
+                        MetaKey::Nil => String::new(),
+                        MetaKey::Str(ref s) => s.clone(),
+                    };
+
+                    (key, Json::from_meta_value(mv))
+                }).collect())
+            },
+        }
+    }
+
+    pub fn from_yaml(y: &Yaml) -> Json {
+        match *y {
+            Yaml::Real(ref s) => s.parse().map(Json::Float).unwrap_or_else(|_| Json::Str(s.clone())),
+            Yaml::Integer(i) => Json::Int(i),
+            Yaml::String(ref s) => Json::Str(s.clone()),
+            Yaml::Boolean(b) => Json::Bool(b),
+            Yaml::Array(ref arr) => Json::Array(arr.iter().map(Json::from_yaml).collect()),
+            Yaml::Hash(ref hsh) => {
+                Json::Object(hsh.iter().map(|(k, v)| {
+                    let key = match *k {
+                        Yaml::String(ref s) => s.clone(),
+                        Yaml::Integer(i) => i.to_string(),
+                        Yaml::Real(ref s) => s.clone(),
+                        Yaml::Boolean(b) => b.to_string(),
+                        _ => String::new(),
+                    };
+
+                    (key, Json::from_yaml(v))
+                }).collect())
+            },
+            Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => Json::Null,
+        }
+    }
+}
+
+fn write_json_str(f: &mut Formatter, s: &str) -> FmtResult {
+    f.write_char('"')?;
+
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+
+    f.write_char('"')
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(i) => write!(f, "{}", i),
+            // JSON has no representation for infinities or NaN.
+            Json::Float(n) if !n.is_finite() => write!(f, "null"),
+            Json::Float(n) => write!(f, "{}", n),
+            Json::Str(ref s) => write_json_str(f, s),
+            Json::Array(ref items) => {
+                f.write_char('[')?;
+
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+
+                    write!(f, "{}", item)?;
+                }
+
+                f.write_char(']')
+            },
+            Json::Object(ref pairs) => {
+                f.write_char('{')?;
+
+                for (i, &(ref k, ref v)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+
+                    write_json_str(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+
+                f.write_char('}')
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+
+    use metadata::{MetaKey, MetaValue};
+
+    use super::Json;
+
+    #[test]
+    fn test_display() {
+        let json = Json::object(vec![
+            ("version", Json::Int(1)),
+            ("ok", Json::Bool(true)),
+            ("rate", Json::Float(0.5)),
+            ("text", Json::str("say \"hi\"\n\\")),
+            ("items", Json::Array(vec![Json::Null, Json::str("a")])),
+            ("empty", Json::Object(vec![])),
+        ]);
+
+        assert_eq!(r#"{"version":1,"ok":true,"rate":0.5,"text":"say \"hi\"\n\\","items":[null,"a"],"empty":{}}"#, json.to_string());
+        assert_eq!(r#""\u0001""#, Json::str("\u{1}").to_string());
+    }
+
+    #[test]
+    fn test_from_meta_value() {
+        let mv = MetaValue::Map(btreemap![
+            MetaKey::Nil => MetaValue::Nil,
+            MetaKey::Str(String::from("artists")) => MetaValue::Seq(vec![MetaValue::Str(String::from("A"))]),
+        ]);
+
+        assert_eq!(r#"{"":null,"artists":["A"]}"#, Json::from_meta_value(&mv).to_string());
+    }
+
+    #[test]
+    fn test_from_yaml() {
+        let y = &YamlLoader::load_from_str("- {a: 1, b: 2.5, c: x, d: true, e: ~}").unwrap()[0];
+
+        assert_eq!(r#"[{"a":1,"b":2.5,"c":"x","d":true,"e":null}]"#, Json::from_yaml(y).to_string());
+    }
+}
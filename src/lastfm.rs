@@ -0,0 +1,555 @@
+// This module imports listening history from Last.fm, either from a scrobble log (e.g. the `.scrobbler.log` written by Rockbox) or from a CSV export of scrobbles.
+// Scrobbles are matched up with items by artist and title, and are added to the `playcount` and `lastplayed` fields of the items.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::path::{Path, PathBuf};
+
+use library::{Library, WalkStep};
+use lookup::LookupContext;
+use lookup::options::LookupOptions;
+use metadata::MetaValue;
+use metadata::keys::{PLAY_COUNT_KEY, LAST_PLAYED_KEY};
+use writer::plan_edit_item_block;
+use plan::WritePlan;
+use progress::Progress;
+use helpers::split_csv_line;
+use error::*;
+
+/// The first line of a scrobble log, in the format used by the Audioscrobbler portable player protocol.
+const SCROBBLER_LOG_HEADER: &str = "#AUDIOSCROBBLER";
+
+/// Marks a track in a scrobble log that was skipped rather than listened to.
+const SCROBBLER_LOG_SKIPPED: &str = "S";
+
+const ARTIST_FIELD: &str = "artist";
+const ALBUM_FIELD: &str = "album";
+const TITLE_FIELD: &str = "title";
+
+const MONTH_NAMES: &[&str] = &["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// A single play of a track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scrobble {
+    /// The line that the scrobble was read from, starting at 1.
+    pub line_num: usize,
+    pub artist: String,
+    pub album: Option<String>,
+    pub title: String,
+    /// When the track was played, in seconds since the Unix epoch, if known.
+    pub played_at: Option<u64>,
+}
+
+/// Why a scrobble could not be matched up with an item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchFailure {
+    NoItem,
+    /// Several items have the artist and title of the scrobble, and its album (if any) does not tell them apart.
+    SeveralItems,
+}
+
+impl Display for MatchFailure {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            MatchFailure::NoItem => write!(f, "no item has this artist and title"),
+            MatchFailure::SeveralItems => write!(f, "several items have this artist and title"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ScrobbleReport {
+    /// The items whose fields are updated, along with how many plays are added to each.
+    pub updated: BTreeMap<PathBuf, u64>,
+    /// The number of scrobbles that were no later than the `lastplayed` of their item, and so were counted before.
+    pub already_counted: usize,
+    pub unmatched: Vec<(Scrobble, MatchFailure)>,
+}
+
+/// The positions of the columns of a scrobble CSV.
+struct CsvColumns {
+    artist: usize,
+    album: Option<usize>,
+    title: usize,
+    date: Option<usize>,
+}
+
+impl CsvColumns {
+    /// Reads the columns from a header, if the fields are column names rather than a scrobble.
+    fn from_header(fields: &[String]) -> Option<Self> {
+        let position = |names: &[&str]| fields.iter().position(|f| names.contains(&f.trim().to_lowercase().as_str()));
+
+        Some(CsvColumns {
+            artist: position(&["artist"])?,
+            album: position(&["album"]),
+            title: position(&["title", "track", "name"])?,
+            date: position(&["date", "timestamp", "uts"]),
+        })
+    }
+}
+
+impl Default for CsvColumns {
+    /// The columns of a Last.fm CSV export, which has no header.
+    fn default() -> Self {
+        CsvColumns { artist: 0, album: Some(1), title: 2, date: Some(3) }
+    }
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    let s = s.trim();
+
+    if s.is_empty() { None } else { Some(s.to_string()) }
+}
+
+/// Counts the days from the Unix epoch to a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of `days_from_civil`, returning the year, month and day.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+
+    (if month <= 2 { year_of_era + era * 400 + 1 } else { year_of_era + era * 400 }, month, day)
+}
+
+/// Writes a time in seconds since the Unix epoch as an ISO 8601 timestamp in UTC, e.g. `2020-01-31T12:34:00Z`.
+pub fn iso_timestamp(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
+
+fn seconds_since_epoch(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64) -> Option<u64> {
+    let valid = (1..=12).contains(&month) && (1..=31).contains(&day) && (0..24).contains(&hour) && (0..60).contains(&minute) && (0..60).contains(&second);
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second;
+
+    if valid && secs >= 0 { Some(secs as u64) } else { None }
+}
+
+/// Reads a timestamp written by `iso_timestamp`.
+pub fn parse_iso_timestamp(s: &str) -> Option<u64> {
+    let s = s.trim();
+
+    if s.len() != 20 || s.get(10..11) != Some("T") || s.get(19..) != Some("Z") {
+        return None;
+    }
+
+    let num = |range: ::std::ops::Range<usize>| s.get(range).and_then(|n| n.parse::<i64>().ok());
+
+    seconds_since_epoch(num(0..4)?, num(5..7)?, num(8..10)?, num(11..13)?, num(14..16)?, num(17..19)?)
+}
+
+/// Reads the time of a scrobble, either as seconds since the Unix epoch, or as written in a Last.fm export, e.g. `31 Jan 2020 12:34` or `31 Jan 2020, 12:34`.
+fn parse_scrobble_date(s: &str) -> Option<u64> {
+    let s = s.trim();
+
+    if let Ok(secs) = s.parse::<u64>() {
+        return Some(secs);
+    }
+
+    let parts: Vec<&str> = s.split(|c: char| c.is_whitespace() || c == ',').filter(|p| !p.is_empty()).collect();
+
+    if parts.len() != 4 {
+        return None;
+    }
+
+    let month_name = parts[1].to_lowercase();
+    let month = MONTH_NAMES.iter().position(|m| month_name.starts_with(m))? as i64 + 1;
+    let mut hour_minute = parts[3].splitn(2, ':');
+    let hour = hour_minute.next()?.parse().ok()?;
+    let minute = hour_minute.next()?.parse().ok()?;
+
+    seconds_since_epoch(parts[2].parse().ok()?, month, parts[0].parse().ok()?, hour, minute, 0)
+}
+
+/// Reads a scrobble log, leaving out tracks that were skipped.
+/// Times in a scrobble log are taken to be in UTC, even if the log says that the player did not know its time zone.
+fn read_scrobbler_log(text: &str) -> Result<Vec<Scrobble>> {
+    let mut scrobbles = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+
+        let cols: Vec<&str> = line.split('\t').collect();
+
+        // Rule: lines must have an artist, album, title, track number, duration, rating and time.
+        ensure!(cols.len() >= 7, "scrobble log line {} has {} columns, expected at least 7", i + 1, cols.len());
+
+        if cols[5] == SCROBBLER_LOG_SKIPPED {
+            continue;
+        }
+
+        let played_at = cols[6].trim().parse::<u64>().chain_err(|| format!("scrobble log line {} has an invalid time: '{}'", i + 1, cols[6]))?;
+
+        scrobbles.push(Scrobble {
+            line_num: i + 1,
+            artist: cols[0].trim().to_string(),
+            album: non_empty(cols[1]),
+            title: cols[2].trim().to_string(),
+            played_at: Some(played_at),
+        });
+    }
+
+    Ok(scrobbles)
+}
+
+/// Reads a CSV of scrobbles, with a header naming its columns or else in the `artist,album,title,date` layout of a Last.fm export.
+fn read_scrobble_csv(text: &str) -> Result<Vec<Scrobble>> {
+    let mut lines = text.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty()).peekable();
+
+    let columns = match lines.peek().and_then(|&(_, line)| CsvColumns::from_header(&split_csv_line(line))) {
+        Some(columns) => {
+            lines.next();
+            columns
+        },
+        None => CsvColumns::default(),
+    };
+
+    let mut scrobbles = vec![];
+
+    for (i, line) in lines {
+        let fields = split_csv_line(line);
+
+        let field = |opt_col: Option<usize>| opt_col.and_then(|col| fields.get(col)).and_then(|f| non_empty(f));
+
+        let (artist, title) = match (field(Some(columns.artist)), field(Some(columns.title))) {
+            (Some(artist), Some(title)) => (artist, title),
+            _ => bail!("scrobble line {} is missing an artist or title", i + 1),
+        };
+
+        let played_at = match field(columns.date) {
+            Some(date) => Some(parse_scrobble_date(&date).ok_or_else(|| format!("scrobble line {} has an invalid date: '{}'", i + 1, date))?),
+            None => None,
+        };
+
+        scrobbles.push(Scrobble { line_num: i + 1, artist, album: field(columns.album), title, played_at });
+    }
+
+    Ok(scrobbles)
+}
+
+/// Reads scrobbles from a scrobble log, or from a CSV of scrobbles.
+pub fn read_scrobbles(text: &str) -> Result<Vec<Scrobble>> {
+    if text.trim_start().starts_with(SCROBBLER_LOG_HEADER) {
+        read_scrobbler_log(text)
+    }
+    else {
+        read_scrobble_csv(text)
+    }
+}
+
+/// Folds a value for matching, ignoring case and surrounding whitespace.
+fn fold(s: &str) -> String {
+    s.trim().to_lowercase()
+}
+
+/// The ways that a field value can be written in a scrobble: a list of artists can appear as any one of them, or all of them joined.
+fn value_variants(mv: MetaValue) -> Vec<String> {
+    match mv {
+        MetaValue::Str(s) => vec![fold(&s)],
+        MetaValue::Seq(mvs) => {
+            let strs: Vec<String> = mvs.into_iter().filter_map(|mv| match mv { MetaValue::Str(s) => Some(fold(&s)), _ => None }).collect();
+
+            let mut variants = vec![strs.join(", "), strs.join(" & ")];
+            variants.extend(strs);
+            variants.sort();
+            variants.dedup();
+            variants
+        },
+        _ => vec![],
+    }
+}
+
+fn lookup_variants(lookup_ctx: &mut LookupContext, abs_item_path: &Path, field_name: &str) -> Result<Vec<String>> {
+    Ok(lookup_ctx.lookup(abs_item_path, &LookupOptions::exact(field_name))?.map_or_else(Vec::new, value_variants))
+}
+
+/// Maps the folded artist and title of every non-directory item in the library to the items that have them.
+fn index_items<G: Progress>(media_lib: &Library, lookup_ctx: &mut LookupContext, progress: &mut G) -> Result<BTreeMap<(String, String), Vec<PathBuf>>> {
+    let mut index: BTreeMap<(String, String), Vec<PathBuf>> = BTreeMap::new();
+
+    media_lib.walk_items(media_lib.root_dir(), |step| {
+        let (item_path, is_dir) = match step {
+            WalkStep::Item { path, is_dir } => (path, is_dir),
+            _ => return Ok(true),
+        };
+
+        // Ignored items are left out, along with everything inside them.
+        if lookup_ctx.is_ignored(item_path)? {
+            return Ok(false);
+        }
+
+        progress.item_scanned(item_path);
+
+        if is_dir || !media_lib.is_in_scope(item_path) {
+            return Ok(true);
+        }
+
+        let titles = lookup_variants(lookup_ctx, item_path, TITLE_FIELD)?;
+
+        for artist in lookup_variants(lookup_ctx, item_path, ARTIST_FIELD)? {
+            for title in &titles {
+                index.entry((artist.clone(), title.clone())).or_insert_with(Vec::new).push(item_path.to_path_buf());
+            }
+        }
+
+        Ok(true)
+    })?;
+
+    Ok(index)
+}
+
+/// Picks the item that a scrobble is for, out of the items with its artist and title.
+fn pick_item(lookup_ctx: &mut LookupContext, candidates: &[PathBuf], scrobble: &Scrobble) -> Result<::std::result::Result<PathBuf, MatchFailure>> {
+    match candidates.len() {
+        0 => return Ok(Err(MatchFailure::NoItem)),
+        1 => return Ok(Ok(candidates[0].clone())),
+        _ => {},
+    }
+
+    if let Some(ref album) = scrobble.album {
+        let album = fold(album);
+        let mut on_album = vec![];
+
+        for abs_item_path in candidates {
+            if lookup_variants(lookup_ctx, abs_item_path, ALBUM_FIELD)?.contains(&album) {
+                on_album.push(abs_item_path);
+            }
+        }
+
+        if on_album.len() == 1 {
+            return Ok(Ok(on_album[0].clone()));
+        }
+    }
+
+    Ok(Err(MatchFailure::SeveralItems))
+}
+
+/// Reads a count field of an item, where a missing or nil field counts as zero.
+fn lookup_count(lookup_ctx: &mut LookupContext, abs_item_path: &Path, field_name: &str) -> Result<u64> {
+    match lookup_ctx.lookup_origin(abs_item_path, field_name)? {
+        None | Some(MetaValue::Nil) => Ok(0),
+        Some(MetaValue::Str(ref s)) => Ok(s.trim().parse::<u64>().chain_err(|| format!("field '{}' is not a count: '{}'", field_name, s))?),
+        Some(_) => bail!("field '{}' is not a count", field_name),
+    }
+}
+
+/// New plays of a single item.
+struct ItemPlays {
+    /// The `lastplayed` of the item before the import; scrobbles up to this time were counted before.
+    counted_until: Option<u64>,
+    count: u64,
+    latest: Option<u64>,
+}
+
+/// Plans adding scrobbles to the `playcount` and `lastplayed` fields of the items that they match, by artist and title, ignoring case.
+/// If several items match, the album of the scrobble (if any) is used to pick one.
+/// Scrobbles no later than the `lastplayed` of their item are taken to be counted already, so that importing the same log twice does not count plays twice.
+pub fn import_scrobbles<G: Progress>(media_lib: &Library, scrobbles: &[Scrobble], plan: &mut WritePlan, progress: &mut G) -> Result<ScrobbleReport> {
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    let mut lookup_ctx = LookupContext::new(media_lib);
+    let index = index_items(media_lib, &mut lookup_ctx, progress)?;
+
+    let mut report = ScrobbleReport::default();
+    let mut plays: BTreeMap<PathBuf, ItemPlays> = BTreeMap::new();
+
+    for scrobble in scrobbles {
+        let candidates = index.get(&(fold(&scrobble.artist), fold(&scrobble.title))).map_or(&[][..], Vec::as_slice);
+
+        let abs_item_path = match pick_item(&mut lookup_ctx, candidates, scrobble)? {
+            Ok(abs_item_path) => abs_item_path,
+            Err(failure) => {
+                report.unmatched.push((scrobble.clone(), failure));
+                continue;
+            },
+        };
+
+        let item_plays = match plays.entry(abs_item_path) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let counted_until = match lookup_ctx.lookup_origin(e.key(), LAST_PLAYED_KEY)? {
+                    Some(MetaValue::Str(ref s)) => parse_iso_timestamp(s),
+                    _ => None,
+                };
+
+                e.insert(ItemPlays { counted_until, count: 0, latest: counted_until })
+            },
+        };
+
+        if let (Some(played_at), Some(counted_until)) = (scrobble.played_at, item_plays.counted_until) {
+            if played_at <= counted_until {
+                report.already_counted += 1;
+                continue;
+            }
+        }
+
+        item_plays.count += 1;
+        item_plays.latest = item_plays.latest.max(scrobble.played_at);
+    }
+
+    for (abs_item_path, item_plays) in plays {
+        if item_plays.count == 0 {
+            continue;
+        }
+
+        let play_count = lookup_count(&mut lookup_ctx, &abs_item_path, PLAY_COUNT_KEY)? + item_plays.count;
+        let opt_last_played = item_plays.latest.map(iso_timestamp);
+
+        plan_edit_item_block(media_lib, plan, &abs_item_path, |mb| {
+            mb.insert(PLAY_COUNT_KEY.to_string(), MetaValue::Str(play_count.to_string()));
+
+            if let Some(last_played) = opt_last_played {
+                mb.insert(LAST_PLAYED_KEY.to_string(), MetaValue::Str(last_played));
+            }
+        })?;
+
+        report.updated.insert(abs_item_path, item_plays.count);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use writer::edit_item_block;
+    use lookup::LookupContext;
+    use metadata::MetaValue;
+    use fixtures::default_setup;
+    use progress::NoProgress;
+    use plan::WritePlan;
+
+    use super::{Scrobble, MatchFailure, read_scrobbles, import_scrobbles, iso_timestamp, parse_iso_timestamp, parse_scrobble_date};
+
+    #[test]
+    fn test_timestamps() {
+        let inputs_and_expected = vec![
+            (0, "1970-01-01T00:00:00Z"),
+            (951_827_696, "2000-02-29T12:34:56Z"),
+            (1_580_474_040, "2020-01-31T12:34:00Z"),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, iso_timestamp(input));
+            assert_eq!(Some(input), parse_iso_timestamp(expected));
+        }
+
+        assert_eq!(Some(1_580_474_040), parse_scrobble_date("31 Jan 2020 12:34"));
+        assert_eq!(Some(1_580_474_040), parse_scrobble_date("31 January 2020, 12:34"));
+        assert_eq!(Some(1_580_474_040), parse_scrobble_date("1580474040"));
+        assert_eq!(None, parse_scrobble_date("31 Foo 2020 12:34"));
+        assert_eq!(None, parse_iso_timestamp("2020-13-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_read_scrobbles() {
+        let log = "\
+#AUDIOSCROBBLER/1.1
+#TZ/UTC
+#CLIENT/Rockbox
+Artist A\tAlbum\tTitle A\t1\t200\tL\t1580474040\t
+Artist B\t\tTitle B\t2\t180\tS\t1580474240\t
+";
+        let expected = vec![
+            Scrobble { line_num: 4, artist: "Artist A".into(), album: Some("Album".into()), title: "Title A".into(), played_at: Some(1_580_474_040) },
+        ];
+        assert_eq!(expected, read_scrobbles(log).unwrap());
+
+        let csv = "\
+Artist A,Album,\"Title, With Comma\",31 Jan 2020 12:34
+Artist B,,Title B,
+";
+        let expected = vec![
+            Scrobble { line_num: 1, artist: "Artist A".into(), album: Some("Album".into()), title: "Title, With Comma".into(), played_at: Some(1_580_474_040) },
+            Scrobble { line_num: 2, artist: "Artist B".into(), album: None, title: "Title B".into(), played_at: None },
+        ];
+        assert_eq!(expected, read_scrobbles(csv).unwrap());
+
+        let csv = "uts,title,artist\n1580474040,Title A,Artist A\n";
+        let expected = vec![
+            Scrobble { line_num: 2, artist: "Artist A".into(), album: None, title: "Title A".into(), played_at: Some(1_580_474_040) },
+        ];
+        assert_eq!(expected, read_scrobbles(csv).unwrap());
+
+        assert!(read_scrobbles("Artist A,Album\n").is_err());
+        assert!(read_scrobbles("Artist A,Album,Title A,yesterday\n").is_err());
+        assert!(read_scrobbles("#AUDIOSCROBBLER/1.1\nArtist A\tAlbum\tTitle A\n").is_err());
+    }
+
+    #[test]
+    fn test_import_scrobbles() {
+        let (temp_media_root, media_lib) = default_setup("test_import_scrobbles");
+        let tp = temp_media_root.path();
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let track_a = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+        let track_b = tp.join("ALBUM_01").join("DISC_02").join("TRACK_01.flac");
+        let track_c = tp.join("ALBUM_02").join("TRACK_01.flac");
+
+        for &(ref path, title, album) in &[(&track_a, "Song A", "First"), (&track_b, "Song B", "First"), (&track_c, "Song B", "Second")] {
+            edit_item_block(&media_lib, path, |mb| {
+                mb.insert("artist".to_string(), MetaValue::Seq(vec![str_val("Artist"), str_val("Guest")]));
+                mb.insert("title".to_string(), str_val(title));
+                mb.insert("album".to_string(), str_val(album));
+            }).unwrap();
+        }
+
+        let scrobble = |line_num: usize, artist: &str, album: Option<&str>, title: &str, played_at: u64| Scrobble {
+            line_num,
+            artist: artist.to_string(),
+            album: album.map(String::from),
+            title: title.to_string(),
+            played_at: Some(played_at),
+        };
+
+        let scrobbles = vec![
+            scrobble(1, "artist", None, "song a", 100),
+            scrobble(2, "Artist & Guest", None, "Song A", 300),
+            scrobble(3, "Guest", Some("Second"), "Song B", 200),
+            scrobble(4, "Artist", None, "Song B", 400),
+            scrobble(5, "Nobody", None, "Song A", 500),
+        ];
+
+        let mut plan = WritePlan::new();
+        let report = import_scrobbles(&media_lib, &scrobbles, &mut plan, &mut NoProgress).unwrap();
+        assert_eq!(btreemap![track_a.clone() => 2, track_c.clone() => 1], report.updated);
+        assert_eq!(vec![MatchFailure::SeveralItems, MatchFailure::NoItem], report.unmatched.iter().map(|&(_, f)| f).collect::<Vec<_>>());
+        assert_eq!(vec![4, 5], report.unmatched.iter().map(|&(ref s, _)| s.line_num).collect::<Vec<_>>());
+        plan.execute().unwrap();
+
+        let origin = |path: &PathBuf, field_name: &str| LookupContext::new(&media_lib).lookup_origin(path, field_name).unwrap();
+        assert_eq!(Some(str_val("2")), origin(&track_a, "playcount"));
+        assert_eq!(Some(str_val("1970-01-01T00:05:00Z")), origin(&track_a, "lastplayed"));
+        assert_eq!(Some(str_val("1")), origin(&track_c, "playcount"));
+        assert_eq!(None, origin(&track_b, "playcount"));
+
+        // Importing the same scrobbles again does not count them twice.
+        let mut plan = WritePlan::new();
+        let report = import_scrobbles(&media_lib, &scrobbles, &mut plan, &mut NoProgress).unwrap();
+        assert!(report.updated.is_empty());
+        assert_eq!(3, report.already_counted);
+        assert!(plan.is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+// Lets `.zip` archives stand in for directories of items, e.g. for albums that are kept archived.
+// Paths inside of an archive are virtual: `ALBUM.zip/DISC_01/TRACK_01.flac` names an entry of the archive file `ALBUM.zip`.
+// Entries are read-only, and are described by meta files stored inside the archive, next to the entries they describe.
+// Only the reading of archives needs the `archives` feature; working out where archives are in a path does not.
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use vfs::{Vfs, StdFs};
+use error::*;
+
+pub const ARCHIVE_EXT: &str = "zip";
+
+/// Returns true if a path is an archive file on a given file system.
+pub fn is_archive_file_with<V: Vfs + ?Sized, P: AsRef<Path>>(vfs: &V, path: P) -> bool {
+    let path = path.as_ref();
+
+    path.extension().and_then(|e| e.to_str()).map_or(false, |e| e.eq_ignore_ascii_case(ARCHIVE_EXT)) && vfs.is_file(path)
+}
+
+/// Splits a path into the archive file that it lies in, and the path of the entry inside of the archive.
+/// The entry path is empty for the archive itself.
+/// Returns `None` if the path does not lead through an archive.
+pub fn split_archive_path<P: AsRef<Path>>(path: P) -> Option<(PathBuf, PathBuf)> {
+    split_archive_path_with(&StdFs, path)
+}
+
+/// Like `split_archive_path`, but looking for the archive file on a given file system.
+pub fn split_archive_path_with<V: Vfs + ?Sized, P: AsRef<Path>>(vfs: &V, path: P) -> Option<(PathBuf, PathBuf)> {
+    let path = path.as_ref();
+
+    // Only one ancestor can be a file on disk, so archives stored inside of archives are just entries.
+    let archive_fp = path.ancestors().find(|a| is_archive_file_with(vfs, a))?;
+    let inner_path = path.strip_prefix(archive_fp).ok()?;
+
+    Some((archive_fp.to_path_buf(), inner_path.to_path_buf()))
+}
+
+/// Converts the path of an entry to the name it has in the archive, which always uses `/` as a separator.
+/// Returns `None` for paths that cannot name an entry, e.g. ones that contain `..`.
+fn entry_name(inner_path: &Path) -> Option<String> {
+    let mut parts = vec![];
+
+    for component in inner_path.components() {
+        match component {
+            Component::Normal(s) => parts.push(s.to_str()?),
+            Component::CurDir => {},
+            _ => return None,
+        }
+    }
+
+    Some(parts.join("/"))
+}
+
+/// Finds the names of the entries directly inside of a directory of an archive, and whether each one is a directory.
+/// Archives need not list their directories, so directories are also inferred from the names of the entries below them.
+/// Names that would lead outside of the directory are skipped.
+fn child_entries(names: &[String], dir_name: &str) -> BTreeMap<String, bool> {
+    let prefix = if dir_name.is_empty() { String::new() } else { format!("{}/", dir_name) };
+    let mut children: BTreeMap<String, bool> = BTreeMap::new();
+
+    for name in names {
+        let rest = match name.strip_prefix(prefix.as_str()) {
+            Some(rest) => rest,
+            None => continue,
+        };
+
+        let mut parts = rest.splitn(2, '/');
+        let child_name = parts.next().unwrap_or("");
+
+        if child_name.is_empty() || child_name == "." || child_name == ".." {
+            continue;
+        }
+
+        let is_dir = children.entry(child_name.to_string()).or_insert(false);
+        *is_dir = *is_dir || parts.next().is_some();
+    }
+
+    children
+}
+
+/// Returns whether an entry is a directory, a file, or missing from an archive, given the names of all of its entries.
+fn kind_of_entry(names: &[String], name: &str) -> Option<bool> {
+    if name.is_empty() {
+        return Some(true);
+    }
+
+    let dir_prefix = format!("{}/", name);
+
+    if names.iter().any(|n| n.starts_with(&dir_prefix)) {
+        Some(true)
+    }
+    else if names.iter().any(|n| n == name) {
+        Some(false)
+    }
+    else {
+        None
+    }
+}
+
+/// An entry directly inside of a directory of an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// The virtual path of the entry, through the archive file.
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// Returns `Some(true)` if an entry is a directory, `Some(false)` if it is a file, and `None` if the archive does not contain it.
+/// The archive itself, with an empty entry path, is a directory.
+pub fn entry_kind<P: AsRef<Path>, Q: AsRef<Path>>(archive_fp: P, inner_path: Q) -> Result<Option<bool>> {
+    let archive_fp = archive_fp.as_ref();
+
+    let name = match entry_name(inner_path.as_ref()) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+
+    if name.is_empty() {
+        return Ok(Some(true));
+    }
+
+    Ok(kind_of_entry(&reader::entry_names(archive_fp)?, &name))
+}
+
+/// Lists the entries directly inside of a directory of an archive, sorted by name.
+pub fn list_dir<P: AsRef<Path>, Q: AsRef<Path>>(archive_fp: P, inner_dir_path: Q) -> Result<Vec<ArchiveEntry>> {
+    let archive_fp = archive_fp.as_ref();
+    let inner_dir_path = inner_dir_path.as_ref();
+    let abs_dir_path = archive_fp.join(inner_dir_path);
+
+    let names = reader::entry_names(archive_fp)?;
+    let dir_name = entry_name(inner_dir_path).ok_or(ErrorKind::DoesNotExist(abs_dir_path.clone()))?;
+
+    // Rule: dir path must be a directory in the archive.
+    ensure!(kind_of_entry(&names, &dir_name) == Some(true), ErrorKind::NotADirectory(abs_dir_path.clone()));
+
+    Ok(child_entries(&names, &dir_name)
+        .into_iter()
+        .map(|(child_name, is_dir)| ArchiveEntry { path: abs_dir_path.join(child_name), is_dir })
+        .collect())
+}
+
+/// Reads a file entry of an archive as text.
+pub fn read_entry<P: AsRef<Path>, Q: AsRef<Path>>(archive_fp: P, inner_path: Q) -> Result<String> {
+    let archive_fp = archive_fp.as_ref();
+    let inner_path = inner_path.as_ref();
+
+    let name = entry_name(inner_path).ok_or(ErrorKind::DoesNotExist(archive_fp.join(inner_path)))?;
+
+    reader::read_entry(archive_fp, &name)
+}
+
+#[cfg(feature = "archives")]
+mod reader {
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::Path;
+
+    use zip::ZipArchive;
+
+    use error::*;
+
+    fn open(archive_fp: &Path) -> Result<ZipArchive<File>> {
+        let f = File::open(archive_fp)?;
+
+        ZipArchive::new(f).chain_err(|| ErrorKind::InvalidArchive(archive_fp.to_path_buf()))
+    }
+
+    pub fn entry_names(archive_fp: &Path) -> Result<Vec<String>> {
+        let mut zip = open(archive_fp)?;
+        let mut names = Vec::with_capacity(zip.len());
+
+        for i in 0..zip.len() {
+            let entry = zip.by_index(i).chain_err(|| ErrorKind::InvalidArchive(archive_fp.to_path_buf()))?;
+            names.push(entry.name().to_string());
+        }
+
+        Ok(names)
+    }
+
+    pub fn read_entry(archive_fp: &Path, name: &str) -> Result<String> {
+        let mut zip = open(archive_fp)?;
+        let mut entry = zip.by_name(name).chain_err(|| ErrorKind::DoesNotExist(archive_fp.join(name)))?;
+
+        let mut buffer = String::new();
+        entry.read_to_string(&mut buffer)?;
+
+        Ok(buffer)
+    }
+}
+
+#[cfg(not(feature = "archives"))]
+mod reader {
+    use std::path::Path;
+
+    use error::*;
+
+    pub fn entry_names(_archive_fp: &Path) -> Result<Vec<String>> {
+        bail!(ErrorKind::ArchivesNotSupported)
+    }
+
+    pub fn read_entry(_archive_fp: &Path, _name: &str) -> Result<String> {
+        bail!(ErrorKind::ArchivesNotSupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::path::{Path, PathBuf};
+
+    use tempdir::TempDir;
+
+    use super::{split_archive_path, entry_name, child_entries, kind_of_entry};
+
+    #[test]
+    fn test_split_archive_path() {
+        let temp = TempDir::new("test_split_archive_path").unwrap();
+        let tp = temp.path();
+        let archive_fp = tp.join("ALBUM.zip");
+        File::create(&archive_fp).unwrap();
+
+        assert_eq!(Some((archive_fp.clone(), PathBuf::new())), split_archive_path(&archive_fp));
+        assert_eq!(
+            Some((archive_fp.clone(), PathBuf::from("DISC_01/TRACK_01.flac"))),
+            split_archive_path(archive_fp.join("DISC_01").join("TRACK_01.flac")),
+        );
+        assert_eq!(None, split_archive_path(tp.join("OTHER.zip").join("TRACK_01.flac")));
+        assert_eq!(None, split_archive_path(tp));
+    }
+
+    #[test]
+    fn test_child_entries() {
+        let names: Vec<String> = vec![
+            "item.yml",
+            "DISC_01/",
+            "DISC_01/TRACK_01.flac",
+            "DISC_02/TRACK_01.flac",
+            "../escape.flac",
+            "./dot.flac",
+        ].into_iter().map(String::from).collect();
+
+        let produced: Vec<_> = child_entries(&names, "").into_iter().collect();
+        assert_eq!(
+            vec![(String::from("DISC_01"), true), (String::from("DISC_02"), true), (String::from("item.yml"), false)],
+            produced,
+        );
+
+        let produced: Vec<_> = child_entries(&names, "DISC_02").into_iter().collect();
+        assert_eq!(vec![(String::from("TRACK_01.flac"), false)], produced);
+
+        assert_eq!(Some(true), kind_of_entry(&names, ""));
+        assert_eq!(Some(true), kind_of_entry(&names, "DISC_02"));
+        assert_eq!(Some(false), kind_of_entry(&names, "DISC_01/TRACK_01.flac"));
+        assert_eq!(None, kind_of_entry(&names, "DISC_03"));
+
+        assert_eq!(Some(String::from("DISC_01/TRACK_01.flac")), entry_name(Path::new("DISC_01/./TRACK_01.flac")));
+        assert_eq!(None, entry_name(Path::new("../TRACK_01.flac")));
+    }
+
+    #[cfg(feature = "archives")]
+    #[test]
+    fn test_list_dir_and_read_entry() {
+        use std::io::Write;
+
+        use zip::ZipWriter;
+        use zip::write::FileOptions;
+
+        use super::{list_dir, read_entry, entry_kind, ArchiveEntry};
+
+        let temp = TempDir::new("test_list_dir_and_read_entry").unwrap();
+        let archive_fp = temp.path().join("ALBUM.zip");
+
+        {
+            let mut zip = ZipWriter::new(File::create(&archive_fp).unwrap());
+            zip.start_file("item.yml", FileOptions::default()).unwrap();
+            zip.write_all(b"- title: Track 1\n").unwrap();
+            zip.start_file("TRACK_01.flac", FileOptions::default()).unwrap();
+            zip.start_file("EXTRA/notes.txt", FileOptions::default()).unwrap();
+            zip.finish().unwrap();
+        }
+
+        let expected = vec![
+            ArchiveEntry { path: archive_fp.join("EXTRA"), is_dir: true },
+            ArchiveEntry { path: archive_fp.join("TRACK_01.flac"), is_dir: false },
+            ArchiveEntry { path: archive_fp.join("item.yml"), is_dir: false },
+        ];
+        assert_eq!(expected, list_dir(&archive_fp, "").unwrap());
+        assert!(list_dir(&archive_fp, "TRACK_01.flac").is_err());
+
+        assert_eq!(Some(true), entry_kind(&archive_fp, "EXTRA").unwrap());
+        assert_eq!(None, entry_kind(&archive_fp, "MISSING").unwrap());
+
+        assert_eq!("- title: Track 1\n", read_entry(&archive_fp, "item.yml").unwrap());
+        assert!(read_entry(&archive_fp, "MISSING").is_err());
+    }
+}
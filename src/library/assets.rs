@@ -0,0 +1,135 @@
+// Assets are non-item files (e.g. cover.jpg, booklet.pdf) that belong to a directory or item.
+// Each asset rule is exposed as a virtual field, named `__<rule name>_path`.
+// TODO: Include assets in exports, once there are exports.
+
+use std::path::{Path, PathBuf};
+
+use library::selection::Selection;
+use metadata::keys::RESERVED_PREFIX;
+use helpers::normalize;
+use vfs::{Vfs, StdFs};
+use error::*;
+
+const ASSET_FIELD_SUFFIX: &str = "_path";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AssetScope {
+    /// Matching files in a directory belong to that directory.
+    Dir,
+    /// Matching files that share a file stem with an item belong to that item (e.g. `track.lrc` for `track.flac`).
+    Item,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetRule {
+    name: String,
+    selection: Selection,
+    scope: AssetScope,
+}
+
+impl AssetRule {
+    pub fn new<S: Into<String>>(name: S, selection: Selection, scope: AssetScope) -> Self {
+        AssetRule {
+            name: name.into(),
+            selection,
+            scope,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn selection(&self) -> &Selection {
+        &self.selection
+    }
+
+    pub fn scope(&self) -> AssetScope {
+        self.scope
+    }
+
+    /// The name of the virtual field that exposes this asset, e.g. `__cover_path`.
+    pub fn field_name(&self) -> String {
+        format!("{}{}{}", RESERVED_PREFIX, self.name, ASSET_FIELD_SUFFIX)
+    }
+
+    /// Finds the files matching this rule that belong to an item, sorted by path.
+    pub fn find_assets<P: AsRef<Path>>(&self, abs_item_path: P) -> Result<Vec<PathBuf>> {
+        self.find_assets_with(&StdFs, abs_item_path)
+    }
+
+    /// Like `find_assets`, but looking for files on a given file system.
+    pub fn find_assets_with<V: Vfs + ?Sized, P: AsRef<Path>>(&self, vfs: &V, abs_item_path: P) -> Result<Vec<PathBuf>> {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+
+        let (search_dir_path, opt_stem) = match self.scope {
+            AssetScope::Dir => {
+                if !vfs.is_dir(&abs_item_path) {
+                    return Ok(vec![]);
+                }
+
+                (abs_item_path.clone(), None)
+            },
+            AssetScope::Item => {
+                match (abs_item_path.parent(), abs_item_path.file_stem()) {
+                    (Some(parent), Some(stem)) => (parent.to_path_buf(), Some(stem.to_os_string())),
+                    _ => return Ok(vec![]),
+                }
+            },
+        };
+
+        let mut found: Vec<PathBuf> = {
+            self.selection.selected_entries_in_dir_with(vfs, &search_dir_path)?
+                .into_iter()
+                .filter(|e| e.path != abs_item_path && !e.is_dir)
+                .map(|e| e.path)
+                .filter(|p| opt_stem.as_ref().map_or(true, |stem| p.file_stem() == Some(stem.as_os_str())))
+                .collect()
+        };
+
+        found.sort();
+
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{DirBuilder, File};
+
+    use regex::Regex;
+    use tempdir::TempDir;
+
+    use library::selection::Selection;
+
+    use super::{AssetRule, AssetScope};
+
+    #[test]
+    fn test_find_assets() {
+        let temp = TempDir::new("test_find_assets").unwrap();
+        let tp = temp.path();
+
+        DirBuilder::new().create(tp.join("ALBUM")).unwrap();
+        for name in &["cover.jpg", "folder.png", "booklet.pdf", "TRACK_01.flac", "TRACK_01.lrc", "TRACK_02.flac"] {
+            File::create(tp.join("ALBUM").join(name)).unwrap();
+        }
+
+        let cover_rule = AssetRule::new("cover", Selection::Regex(Regex::new(r"^(cover|folder)\.(jpg|png)$").unwrap()), AssetScope::Dir);
+        let lyrics_rule = AssetRule::new("lyrics", Selection::Ext(String::from("lrc")), AssetScope::Item);
+
+        assert_eq!("__cover_path", cover_rule.field_name());
+
+        let inputs_and_expected = vec![
+            ((&cover_rule, tp.join("ALBUM")), vec![tp.join("ALBUM").join("cover.jpg"), tp.join("ALBUM").join("folder.png")]),
+            ((&cover_rule, tp.join("ALBUM").join("TRACK_01.flac")), vec![]),
+            ((&lyrics_rule, tp.join("ALBUM").join("TRACK_01.flac")), vec![tp.join("ALBUM").join("TRACK_01.lrc")]),
+            ((&lyrics_rule, tp.join("ALBUM").join("TRACK_02.flac")), vec![]),
+            ((&lyrics_rule, tp.join("ALBUM")), vec![]),
+        ];
+
+        for ((rule, item_path), expected) in inputs_and_expected {
+            let produced = rule.find_assets(&item_path).expect("Unable to find assets");
+            assert_eq!(expected, produced);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// An opt-in cache of directory listings and parsed meta files, shared between clones of a library.
+// It sits behind locks, so that a single library can serve lookups from several threads at once (e.g. in a server or GUI).
+// Entries are checked against the modification time and size on the library's file system whenever they are used, and reloaded if they have changed.
+// Directory listings are only reloaded when entries are added to or removed from the directory itself,
+// so sort orders that depend on the contents of the entries can go stale; `Library::clear_cache` starts over.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use helpers::FileStamp;
+use vfs::Vfs;
+use error::*;
+
+use super::ParsedMetaFile;
+
+#[derive(Debug)]
+struct CachedEntry<T> {
+    stamp: FileStamp,
+    value: T,
+}
+
+/// Looks up a cached value for a path, or loads it and caches it if it is missing or stale.
+/// Locks are only held while reading or updating the map, never while loading, so a slow load does not block other threads.
+fn get_or_load<T, V, F>(map: &RwLock<HashMap<PathBuf, CachedEntry<T>>>, vfs: &V, path: &Path, load: F) -> Result<T>
+where T: Clone,
+      V: Vfs + ?Sized,
+      F: FnOnce() -> Result<T>,
+{
+    let opt_stamp = FileStamp::read_with(vfs, path);
+
+    if let Some(stamp) = opt_stamp {
+        // The map is only ever updated by whole entries, so it is still consistent even if another thread panicked while holding the lock.
+        let entries = map.read().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(entry) = entries.get(path) {
+            if entry.stamp == stamp {
+                return Ok(entry.value.clone());
+            }
+        }
+    }
+
+    let value = load()?;
+
+    // Files that cannot be stamped cannot be checked later, so they are not cached.
+    if let Some(stamp) = opt_stamp {
+        let mut entries = map.write().unwrap_or_else(|e| e.into_inner());
+        entries.insert(path.to_path_buf(), CachedEntry { stamp, value: value.clone() });
+    }
+
+    Ok(value)
+}
+
+#[derive(Debug, Default)]
+pub struct LibraryCache {
+    listings: RwLock<HashMap<PathBuf, CachedEntry<Vec<PathBuf>>>>,
+    meta_files: RwLock<HashMap<PathBuf, CachedEntry<ParsedMetaFile>>>,
+}
+
+impl LibraryCache {
+    pub fn new() -> Self {
+        LibraryCache::default()
+    }
+
+    /// Returns the cached sorted and selected entries of a directory, loading them if needed.
+    pub fn listing<V, F>(&self, vfs: &V, abs_dir_path: &Path, load: F) -> Result<Vec<PathBuf>>
+    where V: Vfs + ?Sized,
+          F: FnOnce() -> Result<Vec<PathBuf>>,
+    {
+        get_or_load(&self.listings, vfs, abs_dir_path, load)
+    }
+
+    /// Returns a cached parsed meta file, loading it if needed.
+    pub fn meta_file<V, F>(&self, vfs: &V, abs_meta_path: &Path, load: F) -> Result<ParsedMetaFile>
+    where V: Vfs + ?Sized,
+          F: FnOnce() -> Result<ParsedMetaFile>,
+    {
+        get_or_load(&self.meta_files, vfs, abs_meta_path, load)
+    }
+
+    pub fn listing_count(&self) -> usize {
+        self.listings.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn meta_file_count(&self) -> usize {
+        self.meta_files.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn clear(&self) {
+        self.listings.write().unwrap_or_else(|e| e.into_inner()).clear();
+        self.meta_files.write().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use library::{Library, LibraryBuilder};
+    use library::hooks::LibraryHook;
+    use library::selection::Selection;
+    use metadata::{Metadata, MetaTarget};
+    use fixtures::create_temp_media_test_dir;
+
+    #[derive(Default)]
+    struct ParseCounter(AtomicUsize);
+
+    impl LibraryHook for ParseCounter {
+        fn meta_parsed(&self, _abs_meta_path: &Path, _metadata: &Metadata) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_library_cache() {
+        assert_send_sync::<Library>();
+
+        let temp_media_root = create_temp_media_test_dir("test_library_cache");
+        let tp = temp_media_root.path();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+
+        let counter = Arc::new(ParseCounter::default());
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs)
+            .selection(Selection::Ext(String::from("flac")))
+            .hook(Arc::clone(&counter))
+            .caching(true)
+            .create()
+            .expect("Unable to create media library");
+
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        let meta_fp = disc_fp.join("item.yml");
+
+        // Clones share the cache, so the meta file is only parsed once no matter how many threads read it.
+        media_lib.read_meta_file(&meta_fp).unwrap();
+
+        let handles: Vec<_> = (0..4).map(|_| {
+            let media_lib = media_lib.clone();
+            let disc_fp = disc_fp.clone();
+
+            thread::spawn(move || {
+                let children = media_lib.children_paths(&disc_fp).unwrap();
+                let parsed = media_lib.read_meta_file(disc_fp.join("item.yml")).unwrap();
+                (children.len(), media_lib.iter_item_blocks(&parsed).unwrap().count())
+            })
+        }).collect();
+
+        for handle in handles {
+            assert_eq!((3, 3), handle.join().unwrap());
+        }
+
+        assert_eq!(1, counter.0.load(Ordering::SeqCst));
+        assert_eq!(1, media_lib.cache().unwrap().meta_file_count());
+
+        // Changed files are parsed again.
+        let mut f = File::create(&meta_fp).unwrap();
+        writeln!(f, "- title: A\n- title: B\n- title: C\n- title: D").unwrap();
+        media_lib.read_meta_file(&meta_fp).unwrap();
+        assert_eq!(2, counter.0.load(Ordering::SeqCst));
+
+        // New entries show up in listings.
+        File::create(disc_fp.join("TRACK_04.flac")).unwrap();
+        assert_eq!(4, media_lib.children_paths(&disc_fp).unwrap().len());
+
+        // Views with a different selection do not share listings.
+        let view = media_lib.with_selection(Selection::True);
+        assert!(view.is_caching());
+        assert_eq!(0, view.cache().unwrap().listing_count());
+
+        media_lib.clear_cache();
+        assert_eq!(0, media_lib.cache().unwrap().meta_file_count());
+        media_lib.read_meta_file(&meta_fp).unwrap();
+        assert_eq!(3, counter.0.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,149 @@
+// Hooks let embedding applications react to what the library does (e.g. for logging, statistics, or auto-fixes), without changing its walkers.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use metadata::Metadata;
+use metadata::reader::ReadDiagnostic;
+use sync::SyncConflict;
+
+/// Receives events from a library. All methods do nothing by default.
+/// Hooks are shared between clones of a library, so any state needs to be kept behind interior mutability.
+pub trait LibraryHook {
+    /// Called for each selected item whenever a directory of the library is listed.
+    fn item_discovered(&self, _abs_item_path: &Path) {}
+
+    /// Called after a meta file has been read and parsed, before its blocks are matched up with items.
+    fn meta_parsed(&self, _abs_meta_path: &Path, _metadata: &Metadata) {}
+
+    /// Called for each block that was skipped while leniently reading a meta file, before `meta_parsed` is called for the file.
+    fn read_diagnostic(&self, _abs_meta_path: &Path, _diagnostic: &ReadDiagnostic) {}
+
+    /// Called when syncing finds a field whose YAML and tag values differ, even on dry runs.
+    fn conflict(&self, _abs_item_path: &Path, _conflict: &SyncConflict) {}
+}
+
+/// Lets a caller keep a handle to a hook (e.g. to read its statistics) after handing it to a library.
+impl<H: LibraryHook + ?Sized> LibraryHook for Arc<H> {
+    fn item_discovered(&self, abs_item_path: &Path) {
+        (**self).item_discovered(abs_item_path)
+    }
+
+    fn meta_parsed(&self, abs_meta_path: &Path, metadata: &Metadata) {
+        (**self).meta_parsed(abs_meta_path, metadata)
+    }
+
+    fn read_diagnostic(&self, abs_meta_path: &Path, diagnostic: &ReadDiagnostic) {
+        (**self).read_diagnostic(abs_meta_path, diagnostic)
+    }
+
+    fn conflict(&self, abs_item_path: &Path, conflict: &SyncConflict) {
+        (**self).conflict(abs_item_path, conflict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+
+    use library::LibraryBuilder;
+    use library::selection::Selection;
+    use metadata::{Metadata, MetaTarget};
+    use metadata::reader::{BlockLocation, ReadDiagnostic};
+    use fixtures::create_temp_media_test_dir;
+
+    use super::LibraryHook;
+
+    #[derive(Default)]
+    struct RecordingHook {
+        discovered: Mutex<Vec<PathBuf>>,
+        parsed: Mutex<Vec<PathBuf>>,
+        diagnostics: Mutex<Vec<(PathBuf, BlockLocation)>>,
+    }
+
+    impl LibraryHook for RecordingHook {
+        fn item_discovered(&self, abs_item_path: &Path) {
+            self.discovered.lock().unwrap().push(abs_item_path.to_path_buf());
+        }
+
+        fn meta_parsed(&self, abs_meta_path: &Path, _metadata: &Metadata) {
+            self.parsed.lock().unwrap().push(abs_meta_path.to_path_buf());
+        }
+
+        fn read_diagnostic(&self, abs_meta_path: &Path, diagnostic: &ReadDiagnostic) {
+            self.diagnostics.lock().unwrap().push((abs_meta_path.to_path_buf(), diagnostic.location.clone()));
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let temp_media_root = create_temp_media_test_dir("test_hooks");
+        let tp = temp_media_root.path();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+
+        let hook = Arc::new(RecordingHook::default());
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs)
+            .selection(Selection::Ext(String::from("flac")))
+            .hook(Arc::clone(&hook))
+            .create()
+            .expect("Unable to create media library");
+
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        media_lib.children_paths(&disc_fp).expect("Unable to list children");
+        media_lib.item_fps_from_meta_fp(disc_fp.join("item.yml")).expect("Unable to read meta file");
+
+        let expected = vec![disc_fp.join("TRACK_01.flac"), disc_fp.join("TRACK_02.flac"), disc_fp.join("TRACK_03.flac")];
+        assert_eq!(expected, *hook.discovered.lock().unwrap());
+        assert_eq!(vec![disc_fp.join("item.yml")], *hook.parsed.lock().unwrap());
+
+        // Alternate views share the same hooks.
+        let view = media_lib.with_selection(Selection::True);
+        view.item_fps_from_meta_fp(disc_fp.join("self.yml")).expect("Unable to read meta file");
+        assert_eq!(2, hook.parsed.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_read_diagnostic() {
+        let temp_media_root = create_temp_media_test_dir("test_read_diagnostic");
+        let tp = temp_media_root.path();
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+
+        fs::write(disc_fp.join("item.yml"), "- title: One\n- just a string\n- title: Three\n").unwrap();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+
+        let titled_items = |lenient: bool, hook: Arc<RecordingHook>| -> Vec<PathBuf> {
+            let media_lib = LibraryBuilder::new(tp, meta_target_specs.clone())
+                .selection(Selection::Ext(String::from("flac")))
+                .lenient(lenient)
+                .hook(hook)
+                .create()
+                .expect("Unable to create media library");
+
+            media_lib.item_fps_from_meta_fp(disc_fp.join("item.yml")).expect("Unable to read meta file")
+                .into_iter()
+                .filter(|&(_, ref mb)| mb.contains_key("title"))
+                .map(|(item_path, _)| item_path)
+                .collect()
+        };
+
+        // By default, the bad block is dropped without notice, and the blocks after it shift up onto the wrong items.
+        let hook = Arc::new(RecordingHook::default());
+        assert_eq!(vec![disc_fp.join("TRACK_01.flac"), disc_fp.join("TRACK_02.flac")], titled_items(false, Arc::clone(&hook)));
+        assert!(hook.diagnostics.lock().unwrap().is_empty());
+
+        // Leniently, the bad block still takes up its place, and is reported.
+        let hook = Arc::new(RecordingHook::default());
+        assert_eq!(vec![disc_fp.join("TRACK_01.flac"), disc_fp.join("TRACK_03.flac")], titled_items(true, Arc::clone(&hook)));
+        assert_eq!(vec![(disc_fp.join("item.yml"), BlockLocation::Index(1))], *hook.diagnostics.lock().unwrap());
+    }
+}
@@ -0,0 +1,130 @@
+// Counters of the work that a library does, e.g. how many meta files it parsed and how long that took, to guide optimization.
+// A set of metrics is shared between clones and views of a library, and can be shared between libraries as well, see `LibraryBuilder::metrics`.
+// Counters are atomic, so that a library can be used from several threads at once, as with the cache.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// The number of times an operation ran, and the wall time it took in total.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OperationTiming {
+    pub count: usize,
+    pub total: Duration,
+}
+
+impl OperationTiming {
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::from_secs(0)
+        }
+        else {
+            self.total / self.count as u32
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    yaml_parses: AtomicUsize,
+    dir_reads: AtomicUsize,
+    cache_hits: AtomicUsize,
+    bytes_read: AtomicUsize,
+    timings: Mutex<BTreeMap<String, OperationTiming>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn yaml_parsed(&self, num_bytes: usize) {
+        self.yaml_parses.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(num_bytes, Ordering::Relaxed);
+    }
+
+    pub fn dir_read(&self) {
+        self.dir_reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Adds a run of an operation that took a given wall time.
+    pub fn record_time(&self, operation: &str, elapsed: Duration) {
+        // The map is only ever updated by whole entries, so it is still consistent even if another thread panicked while holding the lock.
+        let mut timings = self.timings.lock().unwrap_or_else(|e| e.into_inner());
+        let timing = timings.entry(operation.to_string()).or_insert_with(OperationTiming::default);
+
+        timing.count += 1;
+        timing.total += elapsed;
+    }
+
+    /// Starts timing an operation, which is recorded when the returned timer is dropped.
+    pub fn time<'a>(&'a self, operation: &'a str) -> Timer<'a> {
+        Timer { metrics: self, operation, start: Instant::now() }
+    }
+
+    /// Copies the current values of the counters.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            yaml_parses: self.yaml_parses.load(Ordering::Relaxed),
+            dir_reads: self.dir_reads.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            timings: self.timings.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.yaml_parses.store(0, Ordering::Relaxed);
+        self.dir_reads.store(0, Ordering::Relaxed);
+        self.cache_hits.store(0, Ordering::Relaxed);
+        self.bytes_read.store(0, Ordering::Relaxed);
+        self.timings.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+/// Records the wall time of an operation when dropped, see `Metrics::time`.
+pub struct Timer<'a> {
+    metrics: &'a Metrics,
+    operation: &'a str,
+    start: Instant,
+}
+
+impl<'a> Drop for Timer<'a> {
+    fn drop(&mut self) {
+        self.metrics.record_time(self.operation, self.start.elapsed());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MetricsSnapshot {
+    pub yaml_parses: usize,
+    pub dir_reads: usize,
+    pub cache_hits: usize,
+    pub bytes_read: usize,
+    pub timings: BTreeMap<String, OperationTiming>,
+}
+
+fn as_millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + f64::from(d.subsec_nanos()) / 1_000_000.0
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "yaml parses: {}", self.yaml_parses)?;
+        writeln!(f, "dir reads: {}", self.dir_reads)?;
+        writeln!(f, "cache hits: {}", self.cache_hits)?;
+        write!(f, "bytes read: {}", self.bytes_read)?;
+
+        for (operation, timing) in &self.timings {
+            write!(f, "\n{}: {} x {:.3} ms = {:.3} ms", operation, timing.count, as_millis(timing.mean()), as_millis(timing.total))?;
+        }
+
+        Ok(())
+    }
+}
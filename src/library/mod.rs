@@ -1,25 +1,179 @@
 pub mod selection;
 pub mod sort_order;
-
+pub mod assets;
+pub mod hooks;
+pub mod structure;
+pub mod cache;
+pub mod archive;
+pub mod metrics;
+pub mod plugins;
+
+use std::borrow::Cow;
+use std::fs;
 use std::path::{Path, PathBuf};
-
-use helpers::normalize;
-use metadata::{MetaBlock, MetaTarget};
-use yaml::{read_yaml_file, yaml_as_metadata};
-use plexer::multiplex;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use glob;
+use regex::{self, Regex};
+
+use helpers::{normalize, lexical_join, safe_join, is_valid_item_name};
+use metadata::{Metadata, MetaBlock, MetaTarget, MetaValue, sidecar_path, sidecar_item_path};
+use metadata::source::MetaSource;
+use metadata::keys::{get_field, field_names_match_folded, RATING_KEY, RATING_MAX};
+use yaml::{read_yaml_str, yaml_as_metadata};
+use metadata::reader::yaml::yaml_as_metadata_lenient;
+use plexer::{PlexRecord, PlexCheckReport, Alignment, multiplex, multiplex_with_names, multiplex_nested_with, default_block, apply_defaults, align};
+use schema::{Schema, SchemaViolation, ViolationKind};
+use progress::Progress;
+use plan::WritePlan;
+use writer::{plan_move_item_meta, edit_item_block};
+use lookup::LookupContext;
+use lookup::persist::PersistentCache;
+use vfs::{Vfs, StdFs};
 use error::*;
 
 use self::selection::Selection;
-use self::sort_order::SortOrder;
+use self::sort_order::{SortOrder, GroupOrder};
+use self::assets::AssetRule;
+use self::hooks::LibraryHook;
+use self::structure::structural_field;
+use self::cache::LibraryCache;
+use self::metrics::Metrics;
+use self::archive::split_archive_path_with;
+use self::plugins::{FieldProvider, plugin_field_name, PLUGIN_PREFIX};
+
+/// A meta file that has been read and parsed, ready to be matched up with the items it describes.
+/// The metadata is shared, so that copies handed out by the library cache are cheap.
+#[derive(Debug, Clone)]
+pub struct ParsedMetaFile {
+    meta_fn: String,
+    working_dir_path: PathBuf,
+    metadata: Arc<Metadata>,
+}
+
+impl ParsedMetaFile {
+    /// The meta file name of the meta target spec that the meta file matched.
+    pub fn meta_fn(&self) -> &str {
+        &self.meta_fn
+    }
+
+    pub fn working_dir_path(&self) -> &Path {
+        &self.working_dir_path
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+/// A file that marks a directory as the root of an independent collection nested inside the library.
+/// Walks up the tree stop at the nearest marked directory instead of at the library root.
+pub const ROOT_MARKER_FILE_NAME: &str = ".taggu_root";
+
+/// Returns true if a meta file name from a meta target spec is a glob pattern, which can match several files in a directory.
+pub fn is_meta_file_pattern(meta_fn: &str) -> bool {
+    meta_fn.contains(|c| c == '*' || c == '?' || c == '[')
+}
+
+/// Returns true if a file name is matched by a meta file name from a meta target spec.
+fn meta_file_name_matches(meta_fn: &str, file_name: &str) -> bool {
+    if is_meta_file_pattern(meta_fn) {
+        glob::Pattern::new(meta_fn).map(|p| p.matches(file_name)).unwrap_or(false)
+    }
+    else {
+        meta_fn == file_name
+    }
+}
+
+/// Returns true if a file name is matched by a meta target spec.
+/// Sidecar specs match any file name that ends with their suffix, after the name of an item.
+fn meta_spec_matches(meta_fn: &str, meta_target: &MetaTarget, file_name: &str) -> bool {
+    match meta_target.sidecar_suffix() {
+        Some(suffix) => sidecar_item_path(file_name, suffix).is_some(),
+        None => meta_file_name_matches(meta_fn, file_name),
+    }
+}
+
+/// Narrows a selection so that sidecar meta files are never selected as items, since they are named after the items they describe.
+fn exclude_sidecars(selection: Selection, meta_target_specs: &[(String, MetaTarget)]) -> Selection {
+    meta_target_specs.iter()
+        .filter_map(|&(_, ref meta_target)| meta_target.sidecar_suffix())
+        .fold(selection, |selection, suffix| {
+            // Suffixes are checked to be plain file names when the library is created, so this always compiles.
+            let sidecar_regex = Regex::new(&format!(r"^.+\.{}$", regex::escape(suffix))).expect("invalid sidecar suffix");
+
+            Selection::And(Box::new(selection), Box::new(Selection::Not(Box::new(Selection::Regex(sidecar_regex)))))
+        })
+}
+
+/// How the blocks from the different meta files that describe an item are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetPolicy {
+    /// Every meta file is consulted in meta target order, and earlier ones win for fields that several of them set.
+    Merge,
+    /// Only the first meta file (in meta target order) that has a block for an item is consulted, even for fields that it does not set.
+    First,
+}
+
+/// A step of a walk over the items of a library, see `Library::walk_items`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkStep<'a> {
+    /// A directory is about to be listed.
+    EnterDir(&'a Path),
+    /// A selected child of the directory that was entered last.
+    Item { path: &'a Path, is_dir: bool },
+    /// Every child of the directory that was entered last has been visited.
+    LeaveDir(&'a Path),
+}
+
+/// A directory that mirrors the directories of a library, and holds meta files that take precedence over the library's own.
+/// This lets metadata be kept for a collection that cannot be written to.
+#[derive(Debug)]
+struct Overlay {
+    base_root: PathBuf,
+    overlay_root: PathBuf,
+}
+
+impl Overlay {
+    /// Returns the path in the overlay that mirrors a path in the library.
+    fn to_overlay(&self, base_path: &Path) -> Option<PathBuf> {
+        base_path.strip_prefix(&self.base_root).ok().map(|rel| self.overlay_root.join(rel))
+    }
+
+    /// Returns the path in the library that a path in the overlay mirrors.
+    fn to_base(&self, overlay_path: &Path) -> Option<PathBuf> {
+        overlay_path.strip_prefix(&self.overlay_root).ok().map(|rel| self.base_root.join(rel))
+    }
+}
 
 pub struct LibraryBuilder {
     root_dir: PathBuf,
     meta_target_specs: Vec<(String, MetaTarget)>,
     selection: Selection,
+    target_selections: Vec<(String, Selection)>,
     sort_order: SortOrder,
+    group_order: GroupOrder,
+    asset_rules: Vec<AssetRule>,
+    meta_sources: Vec<Arc<MetaSource + Send + Sync>>,
+    hooks: Vec<Arc<LibraryHook + Send + Sync>>,
+    field_providers: Vec<(String, Arc<FieldProvider>)>,
+    fold_field_case: bool,
+    nested_items: bool,
+    caching: bool,
+    archives: bool,
+    local_fields: Vec<String>,
+    target_policy: TargetPolicy,
+    read_only: bool,
+    lenient: bool,
+    metrics: Option<Arc<Metrics>>,
+    vfs: Arc<Vfs + Send + Sync>,
 }
 
 impl LibraryBuilder {
+    /// Meta target specs are given in precedence order: if several meta files describe the same item, earlier specs override later ones.
+    /// This lets e.g. `item.local.yml` layer local overrides on top of a shared `item.yml`, by being listed first.
     pub fn new<P, I>(root_dir: P, meta_target_specs: I) -> Self
     where P: Into<PathBuf>,
           I: IntoIterator<Item = (String, MetaTarget)>,
@@ -28,7 +182,23 @@ impl LibraryBuilder {
             root_dir: root_dir.into(),
             meta_target_specs: meta_target_specs.into_iter().collect(),
             selection: Selection::True,
+            target_selections: vec![],
             sort_order: SortOrder::Name,
+            group_order: GroupOrder::Mixed,
+            asset_rules: vec![],
+            meta_sources: vec![],
+            hooks: vec![],
+            field_providers: vec![],
+            fold_field_case: false,
+            nested_items: false,
+            caching: false,
+            archives: false,
+            local_fields: vec![],
+            target_policy: TargetPolicy::Merge,
+            read_only: false,
+            lenient: false,
+            metrics: None,
+            vfs: Arc::new(StdFs),
         }
     }
 
@@ -37,245 +207,2642 @@ impl LibraryBuilder {
         self
     }
 
+    /// Sets the selection that the blocks of the meta files of one meta target spec are matched up with, in place of the library's selection.
+    /// This lets e.g. `item.yml` describe only audio files, while directories are described by `self.yml`.
+    /// The spec is named by its meta file name, as given to `new`; the library's selection still decides which items are walked.
+    pub fn target_selection<S: Into<String>>(&mut self, meta_fn: S, selection: Selection) -> &mut Self {
+        let meta_fn = meta_fn.into();
+
+        self.target_selections.retain(|&(ref s, _)| *s != meta_fn);
+        self.target_selections.push((meta_fn, selection));
+        self
+    }
+
     pub fn sort_order(&mut self, sort_order: SortOrder) -> &mut Self {
         self.sort_order = sort_order;
         self
     }
 
+    pub fn group_order(&mut self, group_order: GroupOrder) -> &mut Self {
+        self.group_order = group_order;
+        self
+    }
+
+    pub fn asset_rule(&mut self, asset_rule: AssetRule) -> &mut Self {
+        self.asset_rules.push(asset_rule);
+        self
+    }
+
+    /// Adds a source of metadata besides meta files.
+    /// Sources are consulted in the order they were added, after all meta files.
+    pub fn meta_source<M: MetaSource + Send + Sync + 'static>(&mut self, meta_source: M) -> &mut Self {
+        self.meta_sources.push(Arc::new(meta_source));
+        self
+    }
+
+    /// Adds a hook that is notified of library events.
+    /// Hooks are notified in the order they were added.
+    pub fn hook<H: LibraryHook + Send + Sync + 'static>(&mut self, hook: H) -> &mut Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Registers a provider for a plugin field, which is looked up as the plugin prefix followed by its name, e.g. `__plugin:bpm` for `bpm`.
+    /// Registering a provider under a name that already has one replaces it.
+    pub fn field_provider<S, F>(&mut self, name: S, provider: F) -> &mut Self
+    where S: Into<String>,
+          F: Fn(&Library, &Path) -> Option<MetaValue> + Send + Sync + 'static,
+    {
+        let name = name.into();
+
+        self.field_providers.retain(|&(ref n, _)| *n != name);
+        self.field_providers.push((name, Arc::new(provider)));
+        self
+    }
+
+    /// Sets whether field names are matched without regard to case in lookups and schema validation, e.g. so that `Artist` is found when looking up `artist`.
+    /// Field names are still written out as they appear in meta files.
+    pub fn fold_field_case(&mut self, fold_field_case: bool) -> &mut Self {
+        self.fold_field_case = fold_field_case;
+        self
+    }
+
+    /// Sets whether a meta block for a directory item may describe the children of that directory, in a nested `__items` sequence.
+    /// Enabling this means that lookups also consult the meta files of the ancestors of an item, which is slower.
+    pub fn nested_items(&mut self, nested_items: bool) -> &mut Self {
+        self.nested_items = nested_items;
+        self
+    }
+
+    /// Sets whether directory listings and parsed meta files are cached inside the library, and shared between its clones and threads.
+    /// Cached entries are checked against the disk before being used, which is cheaper than reading them again.
+    pub fn caching(&mut self, caching: bool) -> &mut Self {
+        self.caching = caching;
+        self
+    }
+
+    /// Sets whether `.zip` archives are treated as read-only directories of items, instead of as plain files.
+    /// Needs the `archives` feature.
+    pub fn archives(&mut self, archives: bool) -> &mut Self {
+        self.archives = archives;
+        self
+    }
+
+    /// Marks the fields whose names match a glob pattern (e.g. `title` or `track_*`) as local to the item they are set on.
+    /// Lookups never inherit local fields from ancestors, so e.g. a track without a title does not get the title of its album.
+    pub fn local_field<S: Into<String>>(&mut self, field_pattern: S) -> &mut Self {
+        self.local_fields.push(field_pattern.into());
+        self
+    }
+
+    /// Sets how the blocks from several meta files that describe the same item are combined, in lookups and everywhere else.
+    pub fn target_policy(&mut self, target_policy: TargetPolicy) -> &mut Self {
+        self.target_policy = target_policy;
+        self
+    }
+
+    /// Makes every API that writes to the file system (edits, refactorings, syncing, scaffolding, imports) fail with `ErrorKind::ReadOnlyLibrary`,
+    /// so that e.g. a server answering queries can guarantee that it never writes anything.
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets whether meta files are read leniently, so that a block that cannot be parsed does not spoil the rest of its file.
+    /// A bad block in a sequence is replaced by an empty one (so that later blocks still line up with their items), a bad block in a mapping is dropped,
+    /// and each one is reported to the hooks and logged as a warning.
+    pub fn lenient(&mut self, lenient: bool) -> &mut Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Sets the metrics that the library counts its work in, so that e.g. several libraries can be measured together.
+    /// By default, each library gets metrics of its own.
+    pub fn metrics(&mut self, metrics: Arc<Metrics>) -> &mut Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Sets the file system that items and meta files are read from, e.g. a `MemoryFs` where there is no disk to read.
+    /// A library over any file system but the native one is read-only, since writes always go to the native file system.
+    pub fn vfs<V: Vfs + Send + Sync + 'static>(&mut self, vfs: V) -> &mut Self {
+        self.vfs = Arc::new(vfs);
+        self
+    }
+
     pub fn create(&self) -> Result<Library> {
-        let root_dir = self.root_dir.canonicalize()?;
+        let root_dir = self.vfs.canonicalize(&self.root_dir)?;
+
+        ensure!(self.vfs.is_dir(&root_dir), ErrorKind::NotADirectory(root_dir.clone()));
+
+        // Rule: archives can only be read if support for them was compiled in.
+        ensure!(!self.archives || cfg!(feature = "archives"), ErrorKind::ArchivesNotSupported);
+
+        // Rule: archives are always opened from the native file system.
+        ensure!(!self.archives || self.vfs.is_native(), "archives can only be read from the native file system");
+
+        // Rule: meta file name patterns must be valid.
+        for &(ref meta_fn, ref meta_target) in &self.meta_target_specs {
+            if is_meta_file_pattern(meta_fn) {
+                glob::Pattern::new(meta_fn).chain_err(|| ErrorKind::InvalidMetaFileName(meta_fn.clone()))?;
+            }
+
+            // Rule: sidecar suffixes must be plain file names, since they are appended to item names.
+            if let Some(suffix) = meta_target.sidecar_suffix() {
+                ensure!(is_valid_item_name(suffix) && !is_meta_file_pattern(suffix), ErrorKind::InvalidMetaFileName(suffix.to_string()));
+            }
+        }
+
+        // Rule: target selections must be for meta target specs of this library.
+        for &(ref meta_fn, _) in &self.target_selections {
+            ensure!(self.meta_target_specs.iter().any(|&(ref s, _)| s == meta_fn), ErrorKind::InvalidMetaFileName(meta_fn.clone()));
+        }
 
-        ensure!(root_dir.is_dir(), ErrorKind::NotADirectory(root_dir.clone()));
+        let target_selections = {
+            self.target_selections.iter()
+                .map(|&(ref meta_fn, ref selection)| (meta_fn.clone(), exclude_sidecars(selection.clone(), &self.meta_target_specs)))
+                .collect()
+        };
+
+        // Rule: plugin field names must not be empty.
+        ensure!(self.field_providers.iter().all(|&(ref name, _)| !name.is_empty()), "plugin field names must not be empty");
+
+        // Rule: local field patterns must be valid.
+        let mut local_fields = vec![];
+
+        for field_pattern in &self.local_fields {
+            local_fields.push(glob::Pattern::new(field_pattern).chain_err(|| ErrorKind::InvalidFieldPattern(field_pattern.clone()))?);
+        }
 
         // TODO: Make this more efficient!
         Ok(Library {
-            root_dir,
-            meta_target_specs: self.meta_target_specs.clone(),
-            selection: self.selection.clone(),
-            sort_order: self.sort_order,
+            root_dir: Arc::new(root_dir),
+            meta_target_specs: Arc::new(self.meta_target_specs.clone()),
+            selection: exclude_sidecars(self.selection.clone(), &self.meta_target_specs),
+            target_selections: Arc::new(target_selections),
+            sort_order: self.sort_order.clone(),
+            group_order: self.group_order,
+            asset_rules: Arc::new(self.asset_rules.clone()),
+            meta_sources: Arc::new(self.meta_sources.clone()),
+            hooks: Arc::new(self.hooks.clone()),
+            field_providers: Arc::new(self.field_providers.clone()),
+            fold_field_case: self.fold_field_case,
+            nested_items: self.nested_items,
+            cache: if self.caching { Some(Arc::new(LibraryCache::new())) } else { None },
+            archives: self.archives,
+            scope: None,
+            local_fields: Arc::new(local_fields),
+            target_policy: self.target_policy,
+            overlay: None,
+            read_only: self.read_only || !self.vfs.is_native(),
+            lenient: self.lenient,
+            metrics: self.metrics.clone().unwrap_or_else(|| Arc::new(Metrics::new())),
+            vfs: Arc::clone(&self.vfs),
         })
     }
 }
 
+/// A view over a media library root.
+/// The root directory and meta target specs are shared between clones, so alternate views are cheap to create.
+#[derive(Clone)]
 pub struct Library {
-    root_dir: PathBuf,
-    meta_target_specs: Vec<(String, MetaTarget)>,
+    root_dir: Arc<PathBuf>,
+    meta_target_specs: Arc<Vec<(String, MetaTarget)>>,
     selection: Selection,
+    target_selections: Arc<Vec<(String, Selection)>>,
     sort_order: SortOrder,
+    group_order: GroupOrder,
+    asset_rules: Arc<Vec<AssetRule>>,
+    meta_sources: Arc<Vec<Arc<MetaSource + Send + Sync>>>,
+    hooks: Arc<Vec<Arc<LibraryHook + Send + Sync>>>,
+    field_providers: Arc<Vec<(String, Arc<FieldProvider>)>>,
+    fold_field_case: bool,
+    nested_items: bool,
+    cache: Option<Arc<LibraryCache>>,
+    archives: bool,
+    scope: Option<Arc<BTreeSet<PathBuf>>>,
+    local_fields: Arc<Vec<glob::Pattern>>,
+    target_policy: TargetPolicy,
+    overlay: Option<Arc<Overlay>>,
+    read_only: bool,
+    lenient: bool,
+    metrics: Arc<Metrics>,
+    vfs: Arc<Vfs + Send + Sync>,
 }
 
 impl Library {
-    pub fn is_proper_sub_path<P: AsRef<Path>>(&self, abs_sub_path: P) -> bool {
-        let abs_sub_path = normalize(abs_sub_path.as_ref());
+    /// Creates a new view of this library that uses a different selection.
+    /// The root directory is not re-canonicalized.
+    pub fn with_selection(&self, selection: Selection) -> Library {
+        Library {
+            selection: exclude_sidecars(selection, &self.meta_target_specs),
+            cache: self.fresh_cache(),
+            ..self.clone()
+        }
+    }
 
-        abs_sub_path.starts_with(&self.root_dir)
+    /// Creates a new view of this library that uses a different sort order.
+    /// The root directory is not re-canonicalized.
+    pub fn with_sort_order(&self, sort_order: SortOrder) -> Library {
+        Library {
+            sort_order,
+            cache: self.fresh_cache(),
+            ..self.clone()
+        }
     }
 
-    // pub fn meta_fps_from_item_fp<P: AsRef<Path>>(&self, abs_item_path: P) -> Result<Vec<PathBuf>> {
-    //     let abs_item_path = normalize(abs_item_path.as_ref());
+    /// Creates a new library rooted at a directory inside this one, with the same meta target specs, selection, and everything else.
+    /// Lookups in the new library do not see meta files above its root.
+    pub fn sub_library<P: AsRef<Path>>(&self, abs_dir_path: P) -> Result<Library> {
+        let abs_dir_path = self.vfs.canonicalize(abs_dir_path.as_ref())?;
 
-    //     // Rule: item path must be proper.
-    //     ensure!(self.is_proper_sub_path(&abs_item_path), ErrorKind::InvalidSubPath(abs_item_path.clone(), self.root_dir.clone()));
+        // Rule: new root must be a directory inside this library.
+        ensure!(self.is_proper_sub_path(&abs_dir_path), ErrorKind::InvalidSubPath(abs_dir_path.clone(), self.root_dir.to_path_buf()));
+        ensure!(self.vfs.is_dir(&abs_dir_path), ErrorKind::NotADirectory(abs_dir_path.clone()));
 
-    //     // Rule: item path must exist.
-    //     ensure!(abs_item_path.exists(), ErrorKind::DoesNotExist(abs_item_path.clone()));
+        Ok(Library {
+            root_dir: Arc::new(abs_dir_path),
+            // Listings and meta files do not depend on the root, so the cache can be shared.
+            ..self.clone()
+        })
+    }
 
-    //     let mut results: Vec<PathBuf> = vec![];
+    /// Creates a new view of this library whose walks only visit the given paths, the items below them, and the directories that lead to them.
+    /// A meta file in the scope brings every item that it describes into the scope, so that e.g. the output of `git diff --name-only` covers the metadata that changed.
+    /// Lookups are not affected, so inherited fields and fields of children are still found outside of the scope.
+    /// An item that was added or removed changes which blocks of a sequence its siblings get, so its directory should be in the scope to check them too.
+    pub fn scoped_to<I, P>(&self, abs_paths: I) -> Result<Library>
+    where I: IntoIterator<Item = P>,
+          P: AsRef<Path>,
+    {
+        let mut scope = BTreeSet::new();
 
-    //     for &(ref meta_file_name, ref meta_target) in &self.meta_target_specs {
-    //         if let Some(meta_target_dir_path) = meta_target.target_dir_path(&abs_item_path) {
-    //             // Rule: target dir path must be proper.
-    //             if !self.is_proper_sub_path(&meta_target_dir_path) {
-    //                 continue;
-    //             }
+        for abs_path in abs_paths {
+            let abs_path = normalize(abs_path.as_ref());
 
-    //             let meta_file_path = meta_target_dir_path.join(meta_file_name);
+            // Rule: scoped paths must be proper.
+            ensure!(self.is_proper_sub_path(&abs_path), ErrorKind::InvalidSubPath(abs_path.clone(), self.root_dir.to_path_buf()));
 
-    //             if !meta_file_path.is_file() {
-    //                 continue;
-    //             }
+            // Meta files that cannot be read are still in the scope themselves, so that walks report the problem.
+            if self.meta_target_of(&abs_path).is_ok() && self.item_exists(&abs_path) {
+                match self.item_fps_from_meta_fp(&abs_path) {
+                    Ok(item_blocks) => scope.extend(item_blocks.into_iter().map(|(item_path, _)| item_path)),
+                    Err(e) => warn!("unable to read meta file: '{}': {}", abs_path.to_string_lossy(), e),
+                }
+            }
 
-    //             results.push(meta_file_path);
-    //         } else {
-    //             // TODO: Figure out what to do here.
-    //             // No meta taregt dir path was able to be produced from the item path.
-    //         }
-    //     }
+            scope.insert(abs_path);
+        }
 
-    //     Ok(results)
-    // }
+        Ok(Library {
+            scope: Some(Arc::new(scope)),
+            ..self.clone()
+        })
+    }
 
-    pub fn item_fps_from_meta_fp<P: AsRef<Path>>(&self, abs_meta_path: P) -> Result<Vec<(PathBuf, MetaBlock)>> {
-        let abs_meta_path = normalize(abs_meta_path.as_ref());
+    /// Creates a new view of a library whose meta files are layered under those of an overlay directory, which mirrors the directories of the library.
+    /// For each directory, meta files in the overlay come before the library's own, so their fields shadow or augment the library's fields.
+    /// Items still come from the library alone, and new meta files and blocks are written to the overlay, so the library itself can be read-only.
+    pub fn overlay<P: AsRef<Path>>(base: &Library, overlay_root: P) -> Result<Library> {
+        let overlay_root = base.vfs.canonicalize(overlay_root.as_ref())?;
 
-        // Rule: meta file path must be proper.
-        ensure!(self.is_proper_sub_path(&abs_meta_path), ErrorKind::InvalidSubPath(abs_meta_path.clone(), self.root_dir.clone()));
+        ensure!(base.vfs.is_dir(&overlay_root), ErrorKind::NotADirectory(overlay_root.clone()));
 
-        // Rule: meta file path must exist and be a file.
-        ensure!(abs_meta_path.is_file(), ErrorKind::NotAFile(abs_meta_path.clone()));
-
-        let mut results: Vec<(PathBuf, MetaBlock)> = vec![];
-
-        if let Some(working_dir_path) = abs_meta_path.parent() {
-            // TODO: Need to check if working_dir_path is proper?
-            if let Some(found_meta_fn) = abs_meta_path.file_name().and_then(|s| s.to_str()) {
-                // We have a meta file name, now try and match it to any of the file names in meta targets.
-                match self.meta_target_specs.iter().find(|&&(ref s, _)| *s == found_meta_fn) {
-                    Some(&(_, ref meta_target)) => {
-                        // Read meta file, and parse.
-                        let yaml_data = read_yaml_file(&abs_meta_path)?;
-
-                        match yaml_as_metadata(&yaml_data, meta_target) {
-                            Some(md) => {
-                                let plex_results = multiplex(&md, &working_dir_path, &self.selection, self.sort_order, true)?;
-
-                                for (plex_target, mb) in plex_results {
-                                    let item_path = plex_target.resolve(working_dir_path);
-
-                                    results.push((item_path, mb.clone()));
-                                }
-                            },
-                            None => {
-                                Err(ErrorKind::InvalidMetadata)?
-                            },
-                        }
-                    },
-                    None => {
-                        Err(ErrorKind::InvalidMetaFileName(found_meta_fn.to_string()))?
-                    },
-                }
-            } else {
-                // TODO: Figure out what to do here.
-                // The meta path has no file name.
-            }
-        } else {
-            // TODO: Figure out what to do here.
-            // The working dir path has no parent.
-        }
+        // Rule: overlay root and library root must not contain each other, so that meta files are never seen from both sides.
+        ensure!(
+            !overlay_root.starts_with(base.root_dir.as_path()) && !base.root_dir.starts_with(&overlay_root),
+            ErrorKind::OverlappingOverlay(overlay_root.clone(), base.root_dir.to_path_buf())
+        );
 
-        Ok(results)
+        // Rule: libraries can only have one overlay.
+        ensure!(base.overlay.is_none(), "library already has an overlay");
+
+        let mut media_lib = base.clone();
+        media_lib.cache = base.fresh_cache();
+        media_lib.overlay = Some(Arc::new(Overlay { base_root: base.root_dir.to_path_buf(), overlay_root }));
+
+        Ok(media_lib)
     }
 
-    pub fn children_paths<P: AsRef<Path>>(&self, abs_meta_path: P) -> Result<Vec<PathBuf>> {
+    /// The root of the overlay directory, if this library has one.
+    pub fn overlay_root(&self) -> Option<&Path> {
+        self.overlay.as_ref().map(|o| o.overlay_root.as_path())
+    }
+
+    /// Returns true if a path is inside the overlay of this library.
+    pub fn is_overlay_path<P: AsRef<Path>>(&self, abs_path: P) -> bool {
+        let abs_path = normalize(abs_path.as_ref());
+
+        self.overlay.as_ref().map_or(false, |o| abs_path.starts_with(&o.overlay_root))
+    }
+
+    /// Returns the path that changes to a meta file should be written to: the path that mirrors it in the overlay, if this library has one.
+    pub fn writable_meta_path<P: AsRef<Path>>(&self, abs_meta_path: P) -> PathBuf {
         let abs_meta_path = abs_meta_path.as_ref();
 
-        let mut dir_entries = self.selection.selected_entries_in_dir(abs_meta_path)?;
-        dir_entries.sort_unstable_by(|a, b| self.sort_order.path_sort_cmp(a.path(), b.path()));
+        self.overlay.as_ref().and_then(|o| o.to_overlay(abs_meta_path)).unwrap_or_else(|| abs_meta_path.to_path_buf())
+    }
 
-        let paths: Vec<_> = dir_entries.iter().map(|e| e.path()).collect();
+    /// The paths that walks are limited to, if this library is scoped.
+    pub fn scope(&self) -> Option<&BTreeSet<PathBuf>> {
+        self.scope.as_ref().map(|s| s.as_ref())
+    }
 
-        Ok(paths)
+    /// Returns true if an item is at or below a path in the scope, or if this library is not scoped.
+    pub fn is_in_scope<P: AsRef<Path>>(&self, abs_item_path: P) -> bool {
+        match self.scope {
+            Some(ref scope) => normalize(abs_item_path.as_ref()).ancestors().any(|a| scope.contains(a)),
+            None => true,
+        }
     }
-}
 
+    /// Returns true if a walk should visit an item: if it is in the scope, or if it is a directory with paths in the scope below it.
+    pub fn leads_into_scope<P: AsRef<Path>>(&self, abs_item_path: P) -> bool {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+
+        match self.scope {
+            // Paths sort by component, so the paths below an item come right after it.
+            Some(ref scope) => {
+                self.is_in_scope(&abs_item_path)
+                    || scope.range(abs_item_path.clone()..).next().map_or(false, |p| p.starts_with(&abs_item_path))
+            },
+            None => true,
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::path::{PathBuf};
-    use std::fs::{File, DirBuilder};
-    use std::io::Write;
-    use std::thread::sleep;
-    use std::time::Duration;
+    /// Directory listings depend on the selection and sort order, so views that change either need a cache of their own.
+    fn fresh_cache(&self) -> Option<Arc<LibraryCache>> {
+        self.cache.as_ref().map(|_| Arc::new(LibraryCache::new()))
+    }
 
-    use tempdir::TempDir;
+    pub fn is_caching(&self) -> bool {
+        self.cache.is_some()
+    }
 
-    use metadata::{MetaValue, MetaTarget};
-    use library::{SortOrder, LibraryBuilder};
-    use library::selection::Selection;
+    pub fn cache(&self) -> Option<&LibraryCache> {
+        self.cache.as_ref().map(|c| c.as_ref())
+    }
 
-    #[test]
-    fn test_is_proper_sub_path() {
-        // Create temp directory.
-        let temp = TempDir::new("test_is_proper_sub_path").unwrap();
-        let tp = temp.path();
+    /// The counters of the work this library has done, which are shared with its clones and views.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
 
-        let ml = LibraryBuilder::new(tp, vec![]).create().expect("Unable to create media library");
+    /// Drops all cached directory listings and meta files, if caching is enabled.
+    pub fn clear_cache(&self) {
+        if let Some(ref cache) = self.cache {
+            cache.clear();
+        }
+    }
 
-        let inputs_and_expected = vec![
-            (tp.join("sub"), true),
-            (tp.join("sub").join("more"), true),
-            (tp.join(".."), false),
-            (tp.join("."), true),
-            (TempDir::new("other").unwrap().path().to_path_buf(), false),
-            (tp.join("sub").join("more").join("..").join("back"), true),
-            (tp.join("sub").join("more").join("..").join(".."), true),
-            (tp.join("sub").join("..").join(".."), false),
-            (tp.join(".").join("sub").join("."), true),
-        ];
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
 
-        for (input, expected) in inputs_and_expected {
-            let produced = ml.is_proper_sub_path(&input);
-            assert_eq!(expected, produced);
+    pub fn meta_target_specs(&self) -> &[(String, MetaTarget)] {
+        &self.meta_target_specs
+    }
+
+    pub fn selection(&self) -> &Selection {
+        &self.selection
+    }
+
+    /// The selection that the blocks of the meta files of a meta target spec are matched up with, if it has one of its own.
+    pub fn target_selection(&self, meta_fn: &str) -> Option<&Selection> {
+        self.target_selections.iter().find(|&&(ref s, _)| s == meta_fn).map(|&(_, ref selection)| selection)
+    }
+
+    pub fn sort_order(&self) -> &SortOrder {
+        &self.sort_order
+    }
+
+    pub fn group_order(&self) -> GroupOrder {
+        self.group_order
+    }
+
+    pub fn fold_field_case(&self) -> bool {
+        self.fold_field_case
+    }
+
+    pub fn target_policy(&self) -> TargetPolicy {
+        self.target_policy
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The file system that items and meta files are read from.
+    pub fn vfs(&self) -> &(Vfs + Send + Sync) {
+        &*self.vfs
+    }
+
+    /// Fails with `ErrorKind::ReadOnlyLibrary` if this library is read-only.
+    /// Every API that writes to the file system on behalf of a library checks this first.
+    pub fn ensure_writable(&self) -> Result<()> {
+        ensure!(!self.read_only, ErrorKind::ReadOnlyLibrary(self.root_dir.to_path_buf()));
+        Ok(())
+    }
+
+    /// Keeps the blocks that count for an item, given the blocks from each of the meta files that describe it in meta target order.
+    pub fn effective_blocks<T>(&self, blocks: Vec<T>) -> Vec<T> {
+        match self.target_policy {
+            TargetPolicy::Merge => blocks,
+            TargetPolicy::First => blocks.into_iter().take(1).collect(),
         }
     }
 
-    // #[test]
-    // fn test_meta_fps_from_item_fp() {
-    //     // Create temp directory.
-    //     let temp = TempDir::new("test_meta_fps_from_item_fp").unwrap();
-    //     let tp = temp.path();
+    /// Returns true if a field is local to the item it is set on, and so is never inherited from ancestors.
+    pub fn is_local_field<S: AsRef<str>>(&self, field_name: S) -> bool {
+        let match_options = glob::MatchOptions { case_sensitive: !self.fold_field_case, ..glob::MatchOptions::new() };
 
-    //     let db = DirBuilder::new();
+        self.local_fields.iter().any(|p| p.matches_with(field_name.as_ref(), &match_options))
+    }
 
-    //     let meta_targets = vec![
-    //         (String::from("self.yml"), MetaTarget::Contains),
-    //         (String::from("item.yml"), MetaTarget::Siblings),
-    //     ];
-    //     let selection = Selection::Or(
-    //         Box::new(Selection::IsDir),
-    //         Box::new(
-    //             Selection::And(
-    //                 Box::new(Selection::IsFile),
-    //                 Box::new(Selection::Ext("flac".to_string())),
-    //             ),
-    //         ),
-    //     );
+    pub fn nested_items(&self) -> bool {
+        self.nested_items
+    }
 
-    //     // Create sample item files and directories.
-    //     db.create(tp.join("subdir")).unwrap();
-    //     sleep(Duration::from_millis(5));
-    //     File::create(tp.join("item.flac")).unwrap();
-    //     sleep(Duration::from_millis(5));
-    //     File::create(tp.join("subdir").join("subitem.flac")).unwrap();
-    //     sleep(Duration::from_millis(5));
+    pub fn archives(&self) -> bool {
+        self.archives
+    }
 
-    //     // Create meta files.
-    //     let mut meta_file = File::create(tp.join("self.yml"))
-    //         .expect("Unable to create metadata file");
+    /// Splits a path that leads through an archive into the archive file and the path inside of it, if archives are enabled.
+    fn archive_location(&self, path: &Path) -> Option<(PathBuf, PathBuf)> {
+        if self.archives { archive::split_archive_path_with(&*self.vfs, path) } else { None }
+    }
 
-    //     writeln!(meta_file, "title: PsyStyle Nation\nartist: [lapix, Massive New Krew]")
-    //         .expect("Unable to write metadata file");
+    /// Returns true if an item can have children: if it is a directory, or, with archives enabled, an archive or a directory inside of one.
+    pub fn is_item_dir<P: AsRef<Path>>(&self, abs_item_path: P) -> bool {
+        let abs_item_path = abs_item_path.as_ref();
 
-    //     let mut meta_file = File::create(tp.join("item.yml"))
-    //         .expect("Unable to create metadata file");
+        match self.archive_location(abs_item_path) {
+            Some((archive_fp, inner_path)) => archive::entry_kind(&archive_fp, &inner_path).ok() == Some(Some(true)),
+            None => self.vfs.is_dir(abs_item_path),
+        }
+    }
 
-    //     writeln!(meta_file, "item.flac:\n  title: Black Mamba\n  artist: lapix\nsubdir:\n  title: What Is This?")
-    //         .expect("Unable to write metadata file");
+    /// Returns true if an item exists, either on disk or, with archives enabled, inside of an archive.
+    pub fn item_exists<P: AsRef<Path>>(&self, abs_item_path: P) -> bool {
+        let abs_item_path = abs_item_path.as_ref();
 
-    //     let mut meta_file = File::create(tp.join("subdir").join("self.yml"))
-    //         .expect("Unable to create metadata file");
+        match self.archive_location(abs_item_path) {
+            Some((archive_fp, inner_path)) => archive::entry_kind(&archive_fp, &inner_path).ok().map_or(false, |k| k.is_some()),
+            None => self.vfs.exists(abs_item_path),
+        }
+    }
 
-    //     writeln!(meta_file, "title: A Subtrack?\nartist: Massive New Krew")
-    //         .expect("Unable to write metadata file");
+    pub fn hooks(&self) -> &[Arc<LibraryHook + Send + Sync>] {
+        &self.hooks
+    }
 
-    //     // Create media library.
-    //     let media_lib = LibraryBuilder::new(&tp, meta_targets).selection(selection).create().expect("Unable to create media library"); //Library::new_with_options(&tp, meta_targets, library_options).expect("Unable to create media library");
+    /// The names of the plugin fields that have providers, with the plugin prefix, in sorted order.
+    pub fn plugin_field_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.field_providers.iter().map(|&(ref name, _)| format!("{}{}", PLUGIN_PREFIX, name)).collect();
+        names.sort();
+        names
+    }
 
-    //     // Run tests.
-    //     let found: Vec<_> = media_lib.meta_fps_from_item_fp(&tp).expect("Unable to get meta fps");
-    //     assert_eq!(vec![tp.join("self.yml")], found);
+    pub fn asset_rules(&self) -> &[AssetRule] {
+        &self.asset_rules
+    }
+
+    /// Finds the assets that belong to an item, for each asset rule that has any matches.
+    pub fn assets_for_item<P: AsRef<Path>>(&self, abs_item_path: P) -> Result<Vec<(&AssetRule, Vec<PathBuf>)>> {
+        let abs_item_path = abs_item_path.as_ref();
+        let mut results = vec![];
 
-    //     let found: Vec<_> = media_lib.meta_fps_from_item_fp(tp.join("item.flac")).expect("Unable to get meta fps");
-    //     assert_eq!(vec![tp.join("item.yml")], found);
+        for asset_rule in self.asset_rules.iter() {
+            let found = self.safe_assets(asset_rule.find_assets_with(&*self.vfs, abs_item_path)?);
 
-    //     let found: Vec<_> = media_lib.meta_fps_from_item_fp(tp.join("subdir")).expect("Unable to get meta fps");
-    //     assert_eq!(vec![tp.join("subdir").join("self.yml"), tp.join("item.yml")], found);
+            if !found.is_empty() {
+                results.push((asset_rule, found));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Drops assets that lead outside of the library, e.g. a cover that is a symlink to a file elsewhere.
+    fn safe_assets(&self, found: Vec<PathBuf>) -> Vec<PathBuf> {
+        found.into_iter().filter(|p| self.safe_sub_path(p).is_ok()).collect()
+    }
 
-    //     assert!(media_lib.meta_fps_from_item_fp(tp.join("DOES_NOT_EXIST")).is_err());
+    /// Looks up a field in the blocks that meta sources provide for an item, in source order.
+    // TODO: Source blocks are re-read on every call; cache them like meta files.
+    pub fn source_field<P: AsRef<Path>, S: AsRef<str>>(&self, abs_item_path: P, field_name: S) -> Result<Option<MetaValue>> {
+        let abs_item_path = abs_item_path.as_ref();
+        let field_name = field_name.as_ref();
 
-    //     let found: Vec<_> = media_lib.meta_fps_from_item_fp(tp.join("subdir").join("subitem.flac")).expect("Unable to get meta fps");
-    //     assert_eq!(Vec::<PathBuf>::new(), found);
-    // }
+        for meta_source in self.meta_sources.iter().filter(|ms| ms.provides_field(field_name)) {
+            let opt_block = meta_source.block_for_item(abs_item_path).chain_err(|| format!("unable to read meta source: '{}'", meta_source.name()))?;
+
+            if let Some(val) = opt_block.and_then(|mut mb| mb.remove(field_name)) {
+                return Ok(Some(val));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Resolves a virtual field provided by the library itself, or by a plugin field provider, instead of by a meta file.
+    /// Returns `None` if the field is not virtual, or if it has no value for this item.
+    pub fn virtual_field<P: AsRef<Path>, S: AsRef<str>>(&self, abs_item_path: P, field_name: S) -> Result<Option<MetaValue>> {
+        let field_name = field_name.as_ref();
+
+        if let Some(plugin_name) = plugin_field_name(field_name) {
+            let opt_provider = self.field_providers.iter().find(|&&(ref n, _)| n == plugin_name).map(|&(_, ref provider)| provider);
+
+            return Ok(opt_provider.and_then(|provider| provider(self, abs_item_path.as_ref())));
+        }
+
+        match self.asset_rules.iter().find(|r| r.field_name() == field_name) {
+            Some(asset_rule) => {
+                // If there are multiple matches, the first one by name wins.
+                let found = self.safe_assets(asset_rule.find_assets_with(&*self.vfs, abs_item_path)?);
+                Ok(found.first().map(|p| MetaValue::Str(p.to_string_lossy().into_owned())))
+            },
+            None => structural_field(self, abs_item_path, field_name),
+        }
+    }
+
+    pub fn is_proper_sub_path<P: AsRef<Path>>(&self, abs_sub_path: P) -> bool {
+        let abs_sub_path = normalize(abs_sub_path.as_ref());
+
+        abs_sub_path.starts_with(self.root_dir.as_path())
+    }
+
+    /// Returns true if a directory contains a root marker file, making it the root of a nested collection.
+    pub fn is_collection_root<P: AsRef<Path>>(&self, abs_dir_path: P) -> bool {
+        self.vfs.is_file(&abs_dir_path.as_ref().join(ROOT_MARKER_FILE_NAME))
+    }
+
+    /// Returns the root of the collection an item belongs to: the nearest directory at or above the item that has a root marker file,
+    /// or the library root if there is none.
+    pub fn collection_root<P: AsRef<Path>>(&self, abs_item_path: P) -> Result<PathBuf> {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+
+        // Rule: item path must be proper.
+        ensure!(self.is_proper_sub_path(&abs_item_path), ErrorKind::InvalidSubPath(abs_item_path.clone(), self.root_dir.to_path_buf()));
+
+        for ancestor in abs_item_path.ancestors() {
+            if ancestor == self.root_dir.as_path() || self.is_collection_root(ancestor) {
+                return Ok(ancestor.to_path_buf());
+            }
+        }
+
+        Ok(self.root_dir.to_path_buf())
+    }
+
+    /// Checks that a path referenced from metadata stays inside the library, even after following symlinks.
+    /// Relative paths are taken to be relative to the library root.
+    pub fn safe_sub_path<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let path = normalize(path.as_ref());
+
+        let rel_path = if path.is_absolute() {
+            path.strip_prefix(self.root_dir.as_path()).chain_err(|| ErrorKind::UnsafePath(path.clone(), self.root_dir.to_path_buf()))?.to_path_buf()
+        }
+        else {
+            path
+        };
+
+        // Other file systems have no symlinks, and must not be checked against the disk.
+        if self.vfs.is_native() { safe_join(self.root_dir.as_path(), rel_path) } else { lexical_join(self.root_dir.as_path(), rel_path) }
+    }
+
+    /// Returns the ancestor directories of an item, nearest first.
+    /// The walk always ends at the library root (inclusive), unless a closer ancestor to stop at is given.
+    /// It also ends at the nearest collection root (inclusive), so that nested collections do not inherit from the collection around them.
+    /// The library root and collection roots themselves have no ancestors within the library.
+    pub fn ancestor_paths<P: AsRef<Path>>(&self, abs_item_path: P, opt_stop_path: Option<&Path>) -> Result<Vec<PathBuf>> {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+
+        // Rule: item path must be proper.
+        ensure!(self.is_proper_sub_path(&abs_item_path), ErrorKind::InvalidSubPath(abs_item_path.clone(), self.root_dir.to_path_buf()));
+
+        let stop_path = match opt_stop_path {
+            Some(stop_path) => {
+                let stop_path = normalize(stop_path);
+
+                // Rule: stop path must be proper, and an ancestor of the item path.
+                ensure!(self.is_proper_sub_path(&stop_path), ErrorKind::InvalidSubPath(stop_path.clone(), self.root_dir.to_path_buf()));
+                ensure!(abs_item_path != stop_path && abs_item_path.starts_with(&stop_path), ErrorKind::NotAnAncestor(stop_path.clone(), abs_item_path.clone()));
+
+                stop_path
+            },
+            None => self.root_dir.to_path_buf(),
+        };
+
+        let mut results: Vec<PathBuf> = vec![];
+
+        // Since the item path is normalized and starts with the stop path, this walk is guaranteed to hit the stop path.
+        if abs_item_path != stop_path && !self.is_collection_root(&abs_item_path) {
+            for ancestor in abs_item_path.ancestors().skip(1) {
+                results.push(ancestor.to_path_buf());
+
+                if ancestor == stop_path || self.is_collection_root(ancestor) {
+                    break;
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Finds the meta files in a directory for a meta file name from a meta target spec.
+    /// Plain names give at most one file; patterns give every matching file, sorted by name, so that they can be merged in a stable order.
+    /// If this library has an overlay, the meta files in the mirrored overlay directory come first.
+    pub fn meta_fps_in_dir<P: AsRef<Path>>(&self, abs_dir_path: P, meta_fn: &str) -> Result<Vec<PathBuf>> {
+        let abs_dir_path = abs_dir_path.as_ref();
+
+        let mut meta_fps = match self.overlay.as_ref().and_then(|o| o.to_overlay(abs_dir_path)) {
+            Some(overlay_dir_path) => self.meta_fps_in_layer(&overlay_dir_path, meta_fn)?,
+            None => vec![],
+        };
+
+        meta_fps.extend(self.meta_fps_in_layer(abs_dir_path, meta_fn)?);
+
+        Ok(meta_fps)
+    }
+
+    /// Finds the meta files in a single directory, without looking in the overlay, see `meta_fps_in_dir`.
+    fn meta_fps_in_layer(&self, abs_dir_path: &Path, meta_fn: &str) -> Result<Vec<PathBuf>> {
+        if let Some((archive_fp, inner_path)) = self.archive_location(abs_dir_path) {
+            if archive::entry_kind(&archive_fp, &inner_path)? != Some(true) {
+                return Ok(vec![]);
+            }
+
+            // Entries are listed sorted by name already.
+            return Ok(archive::list_dir(&archive_fp, &inner_path)?
+                .into_iter()
+                .filter(|e| !e.is_dir && e.path.file_name().and_then(|s| s.to_str()).map_or(false, |name| meta_file_name_matches(meta_fn, name)))
+                .map(|e| e.path)
+                .collect());
+        }
+
+        if !is_meta_file_pattern(meta_fn) {
+            let meta_fp = abs_dir_path.join(meta_fn);
+            return Ok(if self.vfs.is_file(&meta_fp) { vec![meta_fp] } else { vec![] });
+        }
+
+        if !self.vfs.is_dir(abs_dir_path) {
+            return Ok(vec![]);
+        }
+
+        let mut meta_fps = vec![];
+
+        self.metrics.dir_read();
+
+        for entry in self.vfs.read_dir(abs_dir_path)? {
+            let matches = entry.path.file_name().and_then(|s| s.to_str()).map_or(false, |name| meta_file_name_matches(meta_fn, name));
+
+            if matches && !entry.is_dir {
+                meta_fps.push(entry.path);
+            }
+        }
+
+        meta_fps.sort();
+
+        Ok(meta_fps)
+    }
+
+    /// Finds the meta files in a directory for a meta target spec.
+    /// For sidecar specs, that is the sidecar of every item in the directory that has one.
+    /// Files that an earlier spec claims, e.g. `item.yml` for the sidecar suffix `yml`, are left to that spec.
+    pub fn meta_fps_for_spec<P: AsRef<Path>>(&self, abs_dir_path: P, meta_fn: &str, meta_target: &MetaTarget) -> Result<Vec<PathBuf>> {
+        match meta_target.sidecar_suffix() {
+            Some(suffix) => {
+                let sidecar_pattern = format!("*.{}", glob::Pattern::escape(suffix));
+
+                Ok(self.meta_fps_in_dir(abs_dir_path, &sidecar_pattern)?
+                    .into_iter()
+                    .filter(|p| self.meta_target_of(p).ok().as_ref() == Some(meta_target))
+                    .collect())
+            },
+            None => self.meta_fps_in_dir(abs_dir_path, meta_fn),
+        }
+    }
+
+    /// Finds every meta file in a directory and the selected directories below it.
+    /// Meta files are listed in walk order (depth-first, in sort order), and by meta target spec within each directory.
+    /// For a scoped library, only directories that lead into the scope are visited.
+    pub fn meta_fps_in_tree<P: AsRef<Path>>(&self, abs_dir_path: P) -> Result<Vec<PathBuf>> {
+        let mut meta_fps = vec![];
+
+        self.walk_items(abs_dir_path, |step| {
+            if let WalkStep::EnterDir(dir_path) = step {
+                for &(ref meta_fn, ref meta_target) in self.meta_target_specs.iter() {
+                    meta_fps.extend(self.meta_fps_for_spec(dir_path, meta_fn, meta_target)?);
+                }
+            }
+
+            Ok(true)
+        })?;
+
+        Ok(meta_fps)
+    }
+
+    /// Returns the meta files that could describe an item, in precedence order.
+    /// Files are ordered by meta target spec, and then by name for specs that are patterns.
+    /// For directories, sibling meta files inside of the directory are included too, since they can describe it under the null key.
+    /// All of them are returned regardless of the target policy, which decides which of their blocks count once they are read.
+    pub fn meta_fps_from_item_fp<P: AsRef<Path>>(&self, abs_item_path: P) -> Result<Vec<PathBuf>> {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+
+        // Rule: item path must be proper.
+        ensure!(self.is_proper_sub_path(&abs_item_path), ErrorKind::InvalidSubPath(abs_item_path.clone(), self.root_dir.to_path_buf()));
+
+        // Rule: item path must exist.
+        ensure!(self.item_exists(&abs_item_path), ErrorKind::DoesNotExist(abs_item_path.clone()));
+
+        let mut results: Vec<PathBuf> = vec![];
+
+        for &(ref meta_file_name, ref meta_target) in self.meta_target_specs.iter() {
+            // A sidecar can only describe the item it is named after.
+            if let Some(suffix) = meta_target.sidecar_suffix() {
+                if let Some(meta_fp) = sidecar_path(&abs_item_path, suffix) {
+                    if let Some(overlay_meta_fp) = self.overlay.as_ref().and_then(|o| o.to_overlay(&meta_fp)) {
+                        if self.vfs.is_file(&overlay_meta_fp) {
+                            results.push(overlay_meta_fp);
+                        }
+                    }
+
+                    if self.is_proper_sub_path(&meta_fp) && self.item_exists(&meta_fp) && !self.is_item_dir(&meta_fp) {
+                        results.push(meta_fp);
+                    }
+                }
+
+                continue;
+            }
+
+            if let Some(meta_target_dir_path) = self.meta_target_dir_path(meta_target, &abs_item_path) {
+                // Rule: target dir path must be proper.
+                if !self.is_proper_sub_path(&meta_target_dir_path) {
+                    continue;
+                }
+
+                results.extend(self.meta_fps_in_dir(&meta_target_dir_path, meta_file_name)?);
+            } else {
+                // TODO: Figure out what to do here.
+                // No meta taregt dir path was able to be produced from the item path.
+            }
+
+            // A sibling meta file that is a mapping can describe its own directory, in the block under the null key.
+            // Those come after the meta file next to the directory, so that they only fill in the gaps.
+            if *meta_target == MetaTarget::Siblings && self.is_item_dir(&abs_item_path) {
+                for meta_fp in self.meta_fps_in_dir(&abs_item_path, meta_file_name)? {
+                    if !results.contains(&meta_fp) {
+                        results.push(meta_fp);
+                    }
+                }
+            }
+        }
+
+        // Nested blocks for an item can be in any meta file further up the tree, so those come last, nearest first.
+        if self.nested_items {
+            for ancestor_path in self.ancestor_paths(&abs_item_path, None)? {
+                // Sidecars are not searched for nested blocks, since they only describe the item they are named after.
+                for &(ref meta_file_name, _) in self.meta_target_specs.iter().filter(|&&(_, ref t)| t.sidecar_suffix().is_none()) {
+                    for meta_fp in self.meta_fps_in_dir(&ancestor_path, meta_file_name)? {
+                        if !results.contains(&meta_fp) {
+                            results.push(meta_fp);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Returns the directory in which a meta file of a meta target would be found for an item, see `MetaTarget::target_dir_path`.
+    /// With archives enabled, archives and the directories inside of them can contain meta files too.
+    pub fn meta_target_dir_path(&self, meta_target: &MetaTarget, abs_item_path: &Path) -> Option<PathBuf> {
+        match *meta_target {
+            MetaTarget::Contains if self.archives => {
+                if self.is_item_dir(abs_item_path) { Some(abs_item_path.to_path_buf()) } else { None }
+            },
+            _ => meta_target.target_dir_path_with(&*self.vfs, abs_item_path),
+        }
+    }
+
+    /// Finds the meta target of a meta file, by matching its name against the meta target specs of this library.
+    pub fn meta_target_of<P: AsRef<Path>>(&self, abs_meta_path: P) -> Result<MetaTarget> {
+        Ok(self.meta_spec_of(abs_meta_path.as_ref())?.1)
+    }
+
+    /// Finds the first meta target spec that matches the name of a meta file.
+    fn meta_spec_of(&self, abs_meta_path: &Path) -> Result<(String, MetaTarget)> {
+        let found_meta_fn = abs_meta_path.file_name().and_then(|s| s.to_str()).ok_or(ErrorKind::NotAFile(abs_meta_path.to_path_buf()))?;
+
+        match self.meta_target_specs.iter().find(|&&(ref s, ref t)| meta_spec_matches(s, t, found_meta_fn)) {
+            Some(spec) => Ok(spec.clone()),
+            None => Err(ErrorKind::InvalidMetaFileName(found_meta_fn.to_string()))?,
+        }
+    }
+
+    /// Reads and parses a meta file, checking that its name matches one of the meta targets of this library.
+    /// If caching is enabled, an unchanged meta file is only parsed once, and hooks are only notified when it is parsed.
+    pub fn read_meta_file<P: AsRef<Path>>(&self, abs_meta_path: P) -> Result<ParsedMetaFile> {
+        let abs_meta_path = normalize(abs_meta_path.as_ref());
+
+        // Rule: meta file path must be proper, or in the overlay.
+        ensure!(
+            self.is_proper_sub_path(&abs_meta_path) || self.is_overlay_path(&abs_meta_path),
+            ErrorKind::InvalidSubPath(abs_meta_path.clone(), self.root_dir.to_path_buf())
+        );
+
+        match self.cache {
+            Some(ref cache) => {
+                let mut parsed = false;
+                let result = cache.meta_file(&*self.vfs, &abs_meta_path, || { parsed = true; self.parse_meta_file(&abs_meta_path) });
+
+                if result.is_ok() && !parsed {
+                    self.metrics.cache_hit();
+                }
+
+                result
+            },
+            None => self.parse_meta_file(&abs_meta_path),
+        }
+    }
+
+    fn parse_meta_file(&self, abs_meta_path: &Path) -> Result<ParsedMetaFile> {
+        let abs_meta_path = abs_meta_path.to_path_buf();
+        let opt_archive_location = self.archive_location(&abs_meta_path);
+
+        // Rule: meta file path must exist and be a file.
+        ensure!(opt_archive_location.is_some() || self.vfs.is_file(&abs_meta_path), ErrorKind::NotAFile(abs_meta_path.clone()));
+
+        let (meta_fn, meta_target) = self.meta_spec_of(&abs_meta_path)?;
+
+        // TODO: Need to check if working_dir_path is proper?
+        // A sidecar is matched up as if the item it is named after were its working directory, so that its block describes the item.
+        let working_dir_path = match meta_target.sidecar_suffix() {
+            Some(suffix) => sidecar_item_path(&abs_meta_path, suffix).ok_or_else(|| ErrorKind::InvalidMetaFileName(suffix.to_string()))?,
+            None => abs_meta_path.parent().ok_or(ErrorKind::CappedAtRoot)?.to_path_buf(),
+        };
+
+        // Meta files in the overlay describe the items at the mirrored paths in the library.
+        let working_dir_path = match self.overlay.as_ref().and_then(|o| o.to_base(&working_dir_path)) {
+            Some(base_path) => base_path,
+            None => working_dir_path,
+        };
+
+        // Read meta file, and parse.
+        let _timer = self.metrics.time("parse meta file");
+
+        let text = match opt_archive_location {
+            Some((archive_fp, inner_path)) => archive::read_entry(&archive_fp, &inner_path)?,
+            None => self.vfs.read_to_string(&abs_meta_path)?,
+        };
+        let yaml_data = read_yaml_str(&text, &abs_meta_path)?;
+        self.metrics.yaml_parsed(text.len());
+
+        let metadata = if self.lenient {
+            let (metadata, diagnostics) = yaml_as_metadata_lenient(&yaml_data, meta_target).chain_err(|| ErrorKind::InvalidMetadata)?;
+
+            for diagnostic in &diagnostics {
+                warn!("skipping bad block in meta file: '{}': {}", abs_meta_path.to_string_lossy(), diagnostic);
+
+                for hook in self.hooks.iter() {
+                    hook.read_diagnostic(&abs_meta_path, diagnostic);
+                }
+            }
+
+            metadata
+        }
+        else {
+            yaml_as_metadata(&yaml_data, &meta_target).ok_or(ErrorKind::InvalidMetadata)?
+        };
+
+        for hook in self.hooks.iter() {
+            hook.meta_parsed(&abs_meta_path, &metadata);
+        }
+
+        Ok(ParsedMetaFile { meta_fn, working_dir_path, metadata: Arc::new(metadata) })
+    }
+
+    /// Yields the item paths described by a parsed meta file, along with their blocks.
+    /// Blocks are not copied, except for nested blocks if nested items are enabled.
+    pub fn iter_item_blocks<'a>(&self, parsed: &'a ParsedMetaFile) -> Result<impl Iterator<Item = (PathBuf, Cow<'a, MetaBlock>)> + 'a> {
+        let working_dir_path = &parsed.working_dir_path;
+
+        let plex_results: Vec<(PathBuf, Cow<MetaBlock>)> = if self.nested_items {
+            multiplex_nested_with(
+                &parsed.metadata,
+                working_dir_path,
+                &|md, dir_path| self.multiplex_items(md, dir_path, &parsed.meta_fn),
+                &|p| self.is_item_dir(p),
+            )?
+        }
+        else {
+            let opt_default_block = default_block(&parsed.metadata);
+
+            self.multiplex_items(&parsed.metadata, working_dir_path, &parsed.meta_fn)?
+                .into_iter()
+                .map(|(plex_target, mb)| (plex_target.resolve(working_dir_path), apply_defaults(mb, opt_default_block.as_ref().map(|d| &**d))))
+                .collect()
+        };
+
+        // Item names come from metadata, so make sure that they do not lead outside of the library.
+        let media_lib = self.clone();
+        let safe_results = plex_results.into_iter().filter(move |&(ref item_path, _)| {
+            match media_lib.safe_sub_path(item_path) {
+                Ok(_) => true,
+                Err(_) => {
+                    warn!("skipping item outside of the library: '{}'", item_path.to_string_lossy());
+                    false
+                },
+            }
+        });
+
+        Ok(safe_results)
+    }
+
+    /// Matches up the blocks of metadata with the items in its working directory, as selected for the meta target spec named by `meta_fn`.
+    /// With archives enabled, or over a file system other than the native one, entries are not on disk, so items are named from library listings instead.
+    pub fn multiplex_items<'a>(&self, metadata: &'a Metadata, working_dir_path: &Path, meta_fn: &str) -> Result<Vec<PlexRecord<'a>>> {
+        if !self.archives && self.vfs.is_native() {
+            let selection = self.target_selection(meta_fn).unwrap_or(&self.selection);
+
+            return multiplex(metadata, working_dir_path, selection, &self.sort_order, self.group_order, true);
+        }
+
+        let item_file_names: Vec<String> = match *metadata {
+            Metadata::Contains(_) => vec![],
+            _ => {
+                self.plex_children(working_dir_path, meta_fn)?
+                    .iter()
+                    .filter_map(|p| p.file_name())
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .collect()
+            },
+        };
+
+        multiplex_with_names(metadata, working_dir_path, &item_file_names, true)
+    }
+
+    /// Returns the item paths described by a meta file, along with copies of their blocks.
+    pub fn item_fps_from_meta_fp<P: AsRef<Path>>(&self, abs_meta_path: P) -> Result<Vec<(PathBuf, MetaBlock)>> {
+        let parsed = self.read_meta_file(abs_meta_path)?;
+        let results = self.iter_item_blocks(&parsed)?.map(|(item_path, mb)| (item_path, mb.into_owned())).collect();
+
+        Ok(results)
+    }
+
+    /// Lines up the blocks of a meta file with the items of its working directory, to show which items have no block and which blocks have no item.
+    /// Only the top level of blocks is checked, even if nested items are enabled.
+    /// A sidecar lines up with the item it is named after, as long as that item is still there and selected.
+    /// Returns nothing for meta files that describe their own directory.
+    pub fn plex_check_meta_file<P: AsRef<Path>>(&self, abs_meta_path: P) -> Result<Option<PlexCheckReport>> {
+        let abs_meta_path = abs_meta_path.as_ref();
+        let parsed = self.read_meta_file(abs_meta_path)?;
+
+        if self.meta_target_of(abs_meta_path)?.sidecar_suffix().is_some() {
+            let item_path = &parsed.working_dir_path;
+            let item_name = item_path.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let label = String::from("block 1");
+
+            let is_present = match item_path.parent() {
+                Some(parent_dir_path) => self.plex_children(parent_dir_path, &parsed.meta_fn)?.contains(item_path),
+                None => false,
+            };
+
+            return Ok(Some(PlexCheckReport {
+                meta_path: abs_meta_path.to_path_buf(),
+                alignments: vec![if is_present { Alignment::Matched(label, item_name) } else { Alignment::ExcessBlock(label) }],
+            }));
+        }
+
+        if let Metadata::Contains(_) = *parsed.metadata {
+            return Ok(None);
+        }
+
+        let item_file_names: Vec<String> = {
+            self.plex_children(&parsed.working_dir_path, &parsed.meta_fn)?
+                .iter()
+                .filter_map(|p| p.file_name())
+                .map(|s| s.to_string_lossy().into_owned())
+                .collect()
+        };
+
+        let plex_records = self.multiplex_items(&parsed.metadata, &parsed.working_dir_path, &parsed.meta_fn)?;
+
+        Ok(Some(PlexCheckReport {
+            meta_path: abs_meta_path.to_path_buf(),
+            alignments: align(&parsed.metadata, &item_file_names, &plex_records),
+        }))
+    }
+
+    /// Moves or renames an item within the library, and moves its metadata along with it, see `plan_move_item_meta`.
+    /// The item is moved before any meta files are written, so that a failed move leaves everything as it was.
+    /// Returns the paths of the meta files that were written or removed.
+    pub fn move_item<P: AsRef<Path>, Q: AsRef<Path>>(&self, abs_src_path: P, abs_dst_path: Q) -> Result<Vec<PathBuf>> {
+        let abs_src_path = normalize(abs_src_path.as_ref());
+        let abs_dst_path = normalize(abs_dst_path.as_ref());
+
+        // Rule: both paths must be proper, and neither can be the library root.
+        for path in &[&abs_src_path, &abs_dst_path] {
+            ensure!(self.is_proper_sub_path(path) && **path != *self.root_dir, ErrorKind::InvalidSubPath(path.to_path_buf(), self.root_dir.to_path_buf()));
+        }
+
+        // Rule: archives are read-only.
+        for path in &[&abs_src_path, &abs_dst_path] {
+            if let Some((archive_fp, _)) = split_archive_path_with(&*self.vfs, path) {
+                bail!(ErrorKind::ReadOnlyArchive(archive_fp));
+            }
+        }
+
+        // Rule: source must exist, and destination must not.
+        ensure!(self.vfs.exists(&abs_src_path), ErrorKind::DoesNotExist(abs_src_path.clone()));
+        ensure!(!self.vfs.exists(&abs_dst_path), ErrorKind::AlreadyExists(abs_dst_path.clone()));
+
+        // Rule: destination must be in an existing directory.
+        let dst_dir_path = abs_dst_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        ensure!(self.vfs.is_dir(&dst_dir_path), ErrorKind::NotADirectory(dst_dir_path));
+
+        // Rule: a directory cannot be moved into itself.
+        ensure!(!abs_dst_path.starts_with(&abs_src_path), ErrorKind::InvalidSubPath(abs_dst_path.clone(), abs_src_path.clone()));
+
+        let mut plan = WritePlan::new();
+        let touched = plan_move_item_meta(self, &mut plan, &abs_src_path, &abs_dst_path)?;
+
+        fs::rename(&abs_src_path, &abs_dst_path)?;
+
+        // Listings of both directories changed, and sort orders that depend on contents may not notice.
+        self.clear_cache();
+
+        plan.execute()?;
+
+        Ok(touched)
+    }
+
+    /// Adds one to a count field of an item (e.g. `playcount`), in the item's own meta block, and returns the new count.
+    /// A missing or nil field counts as zero; the field is never inherited, since each item has its own count.
+    /// The meta file is replaced in one step, so readers never see it half written, but increments from separate processes at the same time may still race.
+    pub fn increment_field<P: AsRef<Path>, S: AsRef<str>>(&self, abs_item_path: P, field_name: S) -> Result<u64> {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+        let field_name = field_name.as_ref();
+
+        let count = match LookupContext::new(self).lookup_origin(&abs_item_path, field_name)? {
+            None | Some(MetaValue::Nil) => 0,
+            Some(MetaValue::Str(ref s)) => s.trim().parse::<u64>().chain_err(|| format!("field '{}' is not a count: '{}'", field_name, s))?,
+            Some(_) => bail!("field '{}' is not a count", field_name),
+        } + 1;
+
+        edit_item_block(self, &abs_item_path, |mb| { mb.insert(field_name.to_string(), MetaValue::Str(count.to_string())); })?;
+
+        // The meta file may look unchanged to the cache, e.g. when a count goes from 1 to 2 within the same second.
+        self.clear_cache();
+
+        Ok(count)
+    }
+
+    /// Sets the rating of an item, from 0 to `RATING_MAX`, or removes it if `None`.
+    pub fn set_rating<P: AsRef<Path>>(&self, abs_item_path: P, opt_rating: Option<u8>) -> Result<()> {
+        if let Some(rating) = opt_rating {
+            // Rule: rating must be within range.
+            ensure!(rating <= RATING_MAX, "rating must be from 0 to {}, got {}", RATING_MAX, rating);
+        }
+
+        edit_item_block(self, abs_item_path, |mb| {
+            match opt_rating {
+                Some(rating) => { mb.insert(RATING_KEY.to_string(), MetaValue::Str(rating.to_string())); },
+                None => { mb.remove(RATING_KEY); },
+            }
+        })?;
+
+        self.clear_cache();
+
+        Ok(())
+    }
+
+    /// Checks every meta file in a directory that describes the items in it, see `plex_check_meta_file`.
+    pub fn plex_check<P: AsRef<Path>>(&self, abs_dir_path: P) -> Result<Vec<PlexCheckReport>> {
+        let abs_dir_path = abs_dir_path.as_ref();
+        let mut reports = vec![];
+
+        for &(ref meta_fn, ref meta_target) in self.meta_target_specs.iter() {
+            for meta_fp in self.meta_fps_for_spec(abs_dir_path, meta_fn, meta_target)? {
+                reports.extend(self.plex_check_meta_file(&meta_fp)?);
+            }
+        }
+
+        Ok(reports)
+    }
+
+    /// Calls a function with each string value of a field in the blocks of this library, along with the meta file and item that the block is for.
+    /// Each string in a sequence counts as a value of its own, and mappings are skipped; a value repeated within a block is only visited once.
+    fn visit_field_values<F>(&self, field_name: &str, mut visit: F) -> Result<()>
+    where F: FnMut(&Path, &Path, &str),
+    {
+        fn collect_strs<'a>(mv: &'a MetaValue, strs: &mut BTreeSet<&'a str>) {
+            match *mv {
+                MetaValue::Str(ref s) => { strs.insert(s); },
+                MetaValue::Seq(ref mvs) => {
+                    for mv in mvs {
+                        collect_strs(mv, strs);
+                    }
+                },
+                MetaValue::Nil | MetaValue::Map(_) => {},
+            }
+        }
+
+        for meta_fp in self.meta_fps_in_tree(self.root_dir.as_path())? {
+            let parsed = self.read_meta_file(&meta_fp)?;
+
+            for (item_path, mb) in self.iter_item_blocks(&parsed)? {
+                if !self.is_in_scope(&item_path) {
+                    continue;
+                }
+
+                if let Some(mv) = get_field(&mb, field_name, self.fold_field_case) {
+                    let mut strs = BTreeSet::new();
+                    collect_strs(mv, &mut strs);
+
+                    for s in strs {
+                        visit(&meta_fp, &item_path, s);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts the items in this library that use each value of a field, e.g. to see which genres are in use, or to spot typos among them.
+    /// Only the values that items have in their own metadata are counted, not inherited ones.
+    /// Each string in a sequence counts as a value of its own, and mappings are skipped; an item is counted once per value, even if it repeats it.
+    pub fn field_histogram(&self, field_name: &str) -> Result<BTreeMap<String, usize>> {
+        // An item can have blocks in several meta files, e.g. a directory in its own meta file and under the null key of a sibling meta file.
+        let mut value_items: BTreeMap<String, BTreeSet<PathBuf>> = BTreeMap::new();
+
+        self.visit_field_values(field_name, |_, item_path, value| {
+            value_items.entry(value.to_string()).or_insert_with(BTreeSet::new).insert(item_path.to_path_buf());
+        })?;
+
+        Ok(value_items.into_iter().map(|(value, item_paths)| (value, item_paths.len())).collect())
+    }
+
+    /// Returns the meta files that each value of a field is used in, as counted by `field_histogram`.
+    pub fn field_value_meta_fps(&self, field_name: &str) -> Result<BTreeMap<String, BTreeSet<PathBuf>>> {
+        let mut value_meta_fps: BTreeMap<String, BTreeSet<PathBuf>> = BTreeMap::new();
+
+        self.visit_field_values(field_name, |meta_fp, _, value| {
+            value_meta_fps.entry(value.to_string()).or_insert_with(BTreeSet::new).insert(meta_fp.to_path_buf());
+        })?;
+
+        Ok(value_meta_fps)
+    }
+
+    /// Suggests values for a field that start with a prefix, drawn from the values the field already has in this library, e.g. to complete artist names.
+    /// The prefix is matched ignoring case, and values that are used by more items come first, with ties in order of value.
+    /// Returns each value along with the number of items that use it, counted as in `field_histogram`.
+    pub fn suggest_values(&self, field_name: &str, prefix: &str) -> Result<Vec<(String, usize)>> {
+        let prefix = prefix.to_lowercase();
+
+        let mut suggestions: Vec<(String, usize)> = {
+            self.field_histogram(field_name)?
+                .into_iter()
+                .filter(|&(ref value, _)| value.to_lowercase().starts_with(&prefix))
+                .collect()
+        };
+        suggestions.sort_by(|&(ref va, ca), &(ref vb, cb)| cb.cmp(&ca).then_with(|| va.cmp(vb)));
+
+        Ok(suggestions)
+    }
+
+    /// Looks up multiple fields for every child item of a directory, in sort order.
+    /// Each meta file involved is only read and parsed once, no matter how many children it describes.
+    pub fn fields_for_children<P, S>(&self, abs_dir_path: P, field_names: &[S]) -> Result<Vec<(PathBuf, BTreeMap<String, Option<MetaValue>>)>>
+    where P: AsRef<Path>,
+          S: AsRef<str>,
+    {
+        let abs_dir_path = normalize(abs_dir_path.as_ref());
+
+        // Rule: dir path must be proper.
+        ensure!(self.is_proper_sub_path(&abs_dir_path), ErrorKind::InvalidSubPath(abs_dir_path.clone(), self.root_dir.to_path_buf()));
+
+        // Rule: dir path must be a directory.
+        ensure!(self.is_item_dir(&abs_dir_path), ErrorKind::NotADirectory(abs_dir_path.clone()));
+
+        // Parsed meta files, mapping meta file paths to the item paths and blocks that they provide.
+        let mut parsed: HashMap<PathBuf, HashMap<PathBuf, MetaBlock>> = hashmap![];
+
+        let mut results = vec![];
+
+        for child_path in self.children_paths(&abs_dir_path)? {
+            let meta_fps = self.meta_fps_from_item_fp(&child_path)?;
+
+            for meta_fp in &meta_fps {
+                if !parsed.contains_key(meta_fp) {
+                    let item_blocks = self.item_fps_from_meta_fp(meta_fp)?.into_iter().collect();
+                    parsed.insert(meta_fp.clone(), item_blocks);
+                }
+            }
+
+            let child_blocks: Vec<&MetaBlock> = {
+                let blocks = {
+                    meta_fps
+                        .iter()
+                        .filter_map(|meta_fp| parsed.get(meta_fp))
+                        .filter_map(|item_blocks| item_blocks.get(&child_path))
+                        .collect()
+                };
+
+                self.effective_blocks(blocks)
+            };
+
+            let mut fields = BTreeMap::new();
+
+            for field_name in field_names {
+                let field_name = field_name.as_ref();
+
+                // As with lookups, meta files are consulted in meta target order.
+                let found = {
+                    child_blocks
+                        .iter()
+                        .filter_map(|mb| get_field(mb, field_name, self.fold_field_case))
+                        .next()
+                        .cloned()
+                };
+
+                fields.insert(field_name.to_string(), found);
+            }
+
+            results.push((child_path, fields));
+        }
+
+        Ok(results)
+    }
+
+    /// Checks all metadata in the library against a schema.
+    /// Constraint violations are reported in walk order (depth-first, in sort order), followed by missing required fields.
+    /// Only items that are described by at least one meta file are checked for required fields, and only the blocks that count under the target policy can provide them.
+    /// For a scoped library, only items in the scope are checked.
+    pub fn validate_schema<G: Progress>(&self, schema: &Schema, progress: &mut G) -> Result<Vec<SchemaViolation>> {
+        let mut violations = vec![];
+        let mut found_fields: BTreeMap<PathBuf, BTreeMap<PathBuf, BTreeSet<String>>> = btreemap![];
+
+        self.validate_schema_in_dir(schema, &self.root_dir, &mut violations, &mut found_fields, progress)?;
+
+        let required_fields = schema.required_fields();
+
+        for (item_path, fields_by_meta_fp) in found_fields {
+            // Only the blocks that count for the item can provide its required fields.
+            let field_names: BTreeSet<String> = {
+                let blocks: Vec<&BTreeSet<String>> = match self.target_policy {
+                    TargetPolicy::Merge => fields_by_meta_fp.values().collect(),
+                    TargetPolicy::First => {
+                        self.meta_fps_from_item_fp(&item_path)?
+                            .iter()
+                            .filter_map(|meta_fp| fields_by_meta_fp.get(meta_fp))
+                            .collect()
+                    },
+                };
+
+                self.effective_blocks(blocks).into_iter().flat_map(|fs| fs.iter().cloned()).collect()
+            };
+
+            for &required_field in &required_fields {
+                let is_found = {
+                    if self.fold_field_case { field_names.iter().any(|f| field_names_match_folded(f, required_field)) }
+                    else { field_names.contains(required_field) }
+                };
+
+                if !is_found {
+                    violations.push(SchemaViolation {
+                        item_path: item_path.clone(),
+                        meta_path: None,
+                        field_name: required_field.to_string(),
+                        kind: ViolationKind::Missing,
+                    });
+                }
+            }
+        }
+
+        Ok(violations)
+    }
+
+    fn validate_schema_in_dir<G: Progress>(
+        &self,
+        schema: &Schema,
+        abs_dir_path: &Path,
+        violations: &mut Vec<SchemaViolation>,
+        found_fields: &mut BTreeMap<PathBuf, BTreeMap<PathBuf, BTreeSet<String>>>,
+        progress: &mut G,
+    ) -> Result<()>
+    {
+        let mut meta_fps = vec![];
+
+        for &(ref meta_fn, ref meta_target) in self.meta_target_specs.iter() {
+            meta_fps.extend(self.meta_fps_for_spec(abs_dir_path, meta_fn, meta_target)?);
+        }
+
+        for meta_fp in meta_fps {
+            let parsed = self.read_meta_file(&meta_fp)?;
+            progress.meta_file_parsed(&meta_fp);
+
+            for (item_path, mb) in self.iter_item_blocks(&parsed)? {
+                if !self.is_in_scope(&item_path) {
+                    continue;
+                }
+
+                progress.item_scanned(&item_path);
+
+                for (field_name, kind) in schema.check_block_with_case(&mb, self.fold_field_case) {
+                    violations.push(SchemaViolation {
+                        item_path: item_path.clone(),
+                        meta_path: Some(meta_fp.clone()),
+                        field_name,
+                        kind,
+                    });
+                }
+
+                found_fields
+                    .entry(item_path)
+                    .or_insert_with(BTreeMap::new)
+                    .entry(meta_fp.clone())
+                    .or_insert_with(BTreeSet::new)
+                    .extend(mb.keys().cloned());
+            }
+        }
+
+        for child_path in self.walk_children_paths(abs_dir_path)? {
+            if self.is_item_dir(&child_path) {
+                self.validate_schema_in_dir(schema, &child_path, violations, found_fields, progress)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the selected entries of a directory in sort order, without notifying hooks.
+    fn sorted_children(&self, abs_dir_path: &Path) -> Result<Vec<PathBuf>> {
+        match self.cache {
+            Some(ref cache) => {
+                let mut read = false;
+                let result = cache.listing(&*self.vfs, abs_dir_path, || { read = true; self.read_sorted_children(abs_dir_path) });
+
+                if result.is_ok() && !read {
+                    self.metrics.cache_hit();
+                }
+
+                result
+            },
+            None => self.read_sorted_children(abs_dir_path),
+        }
+    }
+
+    /// Returns the entries of a directory that the blocks of a meta target spec's meta files are matched up with, in sort order.
+    /// Listings for specs with a selection of their own are not cached.
+    fn plex_children(&self, abs_dir_path: &Path, meta_fn: &str) -> Result<Vec<PathBuf>> {
+        match self.target_selection(meta_fn) {
+            Some(selection) => self.read_sorted_children_with(abs_dir_path, selection),
+            None => self.sorted_children(abs_dir_path),
+        }
+    }
+
+    fn read_sorted_children(&self, abs_dir_path: &Path) -> Result<Vec<PathBuf>> {
+        self.read_sorted_children_with(abs_dir_path, &self.selection)
+    }
+
+    fn read_sorted_children_with(&self, abs_dir_path: &Path, selection: &Selection) -> Result<Vec<PathBuf>> {
+        let _timer = self.metrics.time("read dir");
+        self.metrics.dir_read();
+
+        let mut entries: Vec<(PathBuf, bool)> = if self.archives {
+            self.archive_aware_entries(abs_dir_path)?
+                .into_iter()
+                .filter(|&(ref path, is_dir)| selection.is_selected_entry(path, is_dir))
+                .collect()
+        } else {
+            selection.selected_entries_in_dir_with(&*self.vfs, abs_dir_path)?
+                .into_iter()
+                .map(|e| (e.path, e.is_dir))
+                .collect()
+        };
+
+        self.group_order.sort_paths_with(&*self.vfs, &self.sort_order, &mut entries);
+
+        Ok(entries.into_iter().map(|(path, _)| path).collect())
+    }
+
+    /// Lists the entries of a directory along with whether each is a directory, where archives count as directories.
+    /// Directories inside of archives are listed from the archive.
+    fn archive_aware_entries(&self, abs_dir_path: &Path) -> Result<Vec<(PathBuf, bool)>> {
+        if let Some((archive_fp, inner_path)) = archive::split_archive_path_with(&*self.vfs, abs_dir_path) {
+            return Ok(archive::list_dir(&archive_fp, &inner_path)?.into_iter().map(|e| (e.path, e.is_dir)).collect());
+        }
+
+        let mut entries = vec![];
+
+        for entry in self.vfs.read_dir(abs_dir_path)? {
+            let is_dir = entry.is_dir || archive::is_archive_file_with(&*self.vfs, &entry.path);
+
+            entries.push((entry.path, is_dir));
+        }
+
+        Ok(entries)
+    }
+
+    pub fn children_paths<P: AsRef<Path>>(&self, abs_meta_path: P) -> Result<Vec<PathBuf>> {
+        let paths = self.sorted_children(abs_meta_path.as_ref())?;
+
+        for path in &paths {
+            for hook in self.hooks.iter() {
+                hook.item_discovered(path);
+            }
+        }
+
+        Ok(paths)
+    }
+
+    /// Like `children_paths`, but for walks over the library: for a scoped library, children that do not lead into the scope are left out.
+    pub fn walk_children_paths<P: AsRef<Path>>(&self, abs_dir_path: P) -> Result<Vec<PathBuf>> {
+        let paths = self.children_paths(abs_dir_path)?;
+
+        if self.scope.is_none() {
+            return Ok(paths);
+        }
+
+        Ok(paths.into_iter().filter(|p| self.leads_into_scope(p)).collect())
+    }
+
+    /// Returns every item in the library, in walk order (depth-first, in sort order).
+    /// For a scoped library, only items in the scope are returned.
+    fn walk_item_paths(&self) -> Result<Vec<PathBuf>> {
+        let mut item_paths = vec![];
+
+        self.walk_items(self.root_dir(), |step| {
+            if let WalkStep::Item { path, .. } = step {
+                if self.is_in_scope(path) {
+                    item_paths.push(path.to_path_buf());
+                }
+            }
+
+            Ok(true)
+        })?;
+
+        Ok(item_paths)
+    }
+
+    /// Walks a directory and the selected directories below it, depth-first and in sort order, calling a visitor at each step.
+    /// Every directory is entered, has each of its selected children visited, and is left, before the directories inside of it are walked.
+    /// For an item, the visitor returns whether to go on into it; returning false for a directory skips everything inside of it.
+    /// For a scoped library, only directories that lead into the scope are walked, but the directories that lead to it are visited as items too.
+    pub fn walk_items<P, F>(&self, abs_dir_path: P, mut visit: F) -> Result<()>
+    where P: AsRef<Path>,
+          F: FnMut(WalkStep) -> Result<bool>,
+    {
+        let mut dir_stack = vec![normalize(abs_dir_path.as_ref())];
+
+        while let Some(dir_path) = dir_stack.pop() {
+            let mut sub_dir_paths = vec![];
+
+            visit(WalkStep::EnterDir(&dir_path))?;
+
+            for child_path in self.walk_children_paths(&dir_path)? {
+                let is_dir = self.is_item_dir(&child_path);
+
+                if visit(WalkStep::Item { path: &child_path, is_dir })? && is_dir {
+                    sub_dir_paths.push(child_path);
+                }
+            }
+
+            visit(WalkStep::LeaveDir(&dir_path))?;
+
+            // Push in reverse, so that directories are visited in sort order.
+            dir_stack.extend(sub_dir_paths.into_iter().rev());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the items whose files or metadata were modified at or after a time, in walk order, e.g. for incremental exports.
+    /// An item counts as modified if any meta file that could describe it was; a directory does not count through its own modification time, which changes whenever items are added to or removed from it.
+    pub fn items_modified_since(&self, since: SystemTime) -> Result<Vec<PathBuf>> {
+        let is_modified = |path: &Path| self.vfs.metadata(path).ok().and_then(|md| md.modified).map_or(false, |t| t >= since);
+        let mut item_paths = vec![];
+
+        for item_path in self.walk_item_paths()? {
+            if (!self.is_item_dir(&item_path) && is_modified(&item_path)) || self.meta_fps_from_item_fp(&item_path)?.iter().any(|p| is_modified(p)) {
+                item_paths.push(item_path);
+            }
+        }
+
+        Ok(item_paths)
+    }
+
+    /// Returns the items that were added at or after a time, in walk order, e.g. for "recently added" playlists.
+    /// This goes by when builds of the persistent cache first saw each item, see `PersistentCache::first_seen`.
+    pub fn items_added_since(&self, since: SystemTime) -> Result<Vec<PathBuf>> {
+        let persistent_cache = PersistentCache::load(self.root_dir())?;
+        let mut item_paths = vec![];
+
+        for item_path in self.walk_item_paths()? {
+            if persistent_cache.first_seen_with(&*self.vfs, self.root_dir(), &item_path).map_or(false, |t| t >= since) {
+                item_paths.push(item_path);
+            }
+        }
+
+        Ok(item_paths)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::{PathBuf};
+    use std::fs::{self, File, DirBuilder};
+    use std::io::Write;
+    use std::thread::sleep;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use std::sync::Arc;
+
+    use tempdir::TempDir;
+
+    use metadata::{MetaValue, MetaTarget};
+    use library::{Library, SortOrder, LibraryBuilder, WalkStep, ROOT_MARKER_FILE_NAME};
+    use library::sort_order::{TieBreaker, GroupOrder};
+    use library::metrics::{Metrics, MetricsSnapshot};
+    use library::selection::Selection;
+    use plexer::Alignment;
+    use lookup::LookupContext;
+    use lookup::options::LookupOptions;
+    use lookup::persist::PersistentCache;
+    use plan::WritePlan;
+    use writer::plan_edit_item_block;
+    use refactor::rename_field;
+    use scaffold::scaffold_dir;
+    use vfs::MemoryFs;
+    use error::{Error, ErrorKind};
+    use fixtures::{create_temp_media_test_dir, default_setup};
+    use schema::{Schema, FieldSpec, FieldConstraint, SchemaViolation, ViolationKind};
+    use progress::ProgressCounts;
+    use regex::Regex;
+
+    #[test]
+    fn test_is_proper_sub_path() {
+        // Create temp directory.
+        let temp = TempDir::new("test_is_proper_sub_path").unwrap();
+        let tp = temp.path();
+
+        let ml = LibraryBuilder::new(tp, vec![]).create().expect("Unable to create media library");
+
+        let inputs_and_expected = vec![
+            (tp.join("sub"), true),
+            (tp.join("sub").join("more"), true),
+            (tp.join(".."), false),
+            (tp.join("."), true),
+            (TempDir::new("other").unwrap().path().to_path_buf(), false),
+            (tp.join("sub").join("more").join("..").join("back"), true),
+            (tp.join("sub").join("more").join("..").join(".."), true),
+            (tp.join("sub").join("..").join(".."), false),
+            (tp.join(".").join("sub").join("."), true),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = ml.is_proper_sub_path(&input);
+            assert_eq!(expected, produced);
+        }
+    }
+
+    #[test]
+    fn test_with_selection_and_sort_order() {
+        // Create temp directory.
+        let temp = TempDir::new("test_with_selection_and_sort_order").unwrap();
+        let tp = temp.path();
+
+        let db = DirBuilder::new();
+
+        db.create(tp.join("subdir")).unwrap();
+
+        // Set mod times explicitly, so the test does not depend on file system time resolution.
+        let base_time = SystemTime::now();
+        File::create(tp.join("item_b.flac")).unwrap().set_modified(base_time).unwrap();
+        File::create(tp.join("item_a.flac")).unwrap().set_modified(base_time + Duration::from_secs(1)).unwrap();
+        File::create(tp.join("cover.jpg")).unwrap().set_modified(base_time + Duration::from_secs(2)).unwrap();
+
+        let everything = LibraryBuilder::new(tp, vec![]).create().expect("Unable to create media library");
+        let audio_only = everything.with_selection(Selection::Ext("flac".to_string()));
+        let audio_by_time = audio_only.with_sort_order(SortOrder::ModTime(TieBreaker::Name));
+
+        // The views share their root and specs.
+        assert!(Arc::ptr_eq(&everything.root_dir, &audio_only.root_dir));
+        assert!(Arc::ptr_eq(&everything.meta_target_specs, &audio_by_time.meta_target_specs));
+
+        let expected = vec![
+            tp.join("cover.jpg"),
+            tp.join("item_a.flac"),
+            tp.join("item_b.flac"),
+            tp.join("subdir"),
+        ];
+        assert_eq!(expected, everything.children_paths(tp).expect("Unable to get children"));
+
+        let expected = vec![
+            tp.join("item_a.flac"),
+            tp.join("item_b.flac"),
+        ];
+        assert_eq!(expected, audio_only.children_paths(tp).expect("Unable to get children"));
+
+        let expected = vec![
+            tp.join("item_b.flac"),
+            tp.join("item_a.flac"),
+        ];
+        assert_eq!(expected, audio_by_time.children_paths(tp).expect("Unable to get children"));
+    }
+
+    #[test]
+    fn test_group_order() {
+        let (temp_media_root, media_lib) = default_setup("test_group_order");
+        let album_dir = temp_media_root.path().join("ALBUM_02");
+
+        let expected = vec![
+            album_dir.join("DISC_01"),
+            album_dir.join("TRACK_01.flac"),
+            album_dir.join("TRACK_02.flac"),
+            album_dir.join("TRACK_03.flac"),
+        ];
+        assert_eq!(expected, media_lib.children_paths(&album_dir).expect("Unable to get children"));
+
+        let files_first = {
+            LibraryBuilder::new(media_lib.root_dir(), media_lib.meta_target_specs().to_vec())
+                .selection(media_lib.selection().clone())
+                .group_order(GroupOrder::FilesFirst)
+                .create()
+                .expect("Unable to create media library")
+        };
+
+        let expected = vec![
+            album_dir.join("TRACK_01.flac"),
+            album_dir.join("TRACK_02.flac"),
+            album_dir.join("TRACK_03.flac"),
+            album_dir.join("DISC_01"),
+        ];
+        assert_eq!(expected, files_first.children_paths(&album_dir).expect("Unable to get children"));
+
+        // Sequences of blocks are plexed in the same order.
+        let produced: Vec<_> = {
+            files_first.item_fps_from_meta_fp(album_dir.join("item.yml"))
+                .expect("Unable to plex meta file")
+                .into_iter()
+                .map(|(item_path, _)| item_path)
+                .collect()
+        };
+        assert_eq!(expected, produced);
+    }
+
+    #[test]
+    fn test_sub_library() {
+        let (temp_media_root, media_lib) = default_setup("test_sub_library");
+        let tp = temp_media_root.path();
+
+        let album_lib = media_lib.sub_library(tp.join("ALBUM_03")).expect("Unable to create sub library");
+
+        assert_eq!(tp.join("ALBUM_03").as_path(), album_lib.root_dir());
+        assert_eq!(media_lib.meta_target_specs(), album_lib.meta_target_specs());
+        assert!(Arc::ptr_eq(&media_lib.meta_target_specs, &album_lib.meta_target_specs));
+
+        let item_fp = tp.join("ALBUM_03").join("DISC_02").join("TRACK_01");
+        let expected = vec![
+            tp.join("ALBUM_03").join("DISC_02"),
+            tp.join("ALBUM_03"),
+        ];
+        assert_eq!(expected, album_lib.ancestor_paths(&item_fp, None).expect("Unable to get ancestors"));
+
+        // Meta files outside of the new root are not used, even if they describe it.
+        assert_eq!(vec![tp.join("ALBUM_03").join("self.yml")], album_lib.meta_fps_from_item_fp(tp.join("ALBUM_03")).expect("Unable to get meta files"));
+        assert!(!album_lib.is_proper_sub_path(tp.join("ALBUM_01")));
+
+        assert!(media_lib.sub_library(tp.join("..")).is_err());
+        assert!(media_lib.sub_library(tp.join("ALBUM_01").join("item.yml")).is_err());
+        assert!(media_lib.sub_library(tp.join("MISSING")).is_err());
+    }
+
+    #[test]
+    fn test_ancestor_paths() {
+        let (temp_media_root, media_lib) = default_setup("test_ancestor_paths");
+        let tp = temp_media_root.path();
+
+        let item_fp = tp.join("ALBUM_03").join("DISC_02").join("TRACK_01");
+
+        let expected = vec![
+            tp.join("ALBUM_03").join("DISC_02"),
+            tp.join("ALBUM_03"),
+            tp.to_path_buf(),
+        ];
+        assert_eq!(expected, media_lib.ancestor_paths(&item_fp, None).expect("Unable to get ancestors"));
+
+        // Unnormalized paths still end at the root.
+        let expected = vec![
+            tp.to_path_buf(),
+        ];
+        assert_eq!(expected, media_lib.ancestor_paths(tp.join("ALBUM_03").join("..").join("ALBUM_01"), None).expect("Unable to get ancestors"));
+
+        // The root has no ancestors.
+        assert!(media_lib.ancestor_paths(tp, None).expect("Unable to get ancestors").is_empty());
+
+        let stop_fp = tp.join("ALBUM_03");
+        let expected = vec![
+            tp.join("ALBUM_03").join("DISC_02"),
+            tp.join("ALBUM_03"),
+        ];
+        assert_eq!(expected, media_lib.ancestor_paths(&item_fp, Some(&stop_fp)).expect("Unable to get ancestors"));
+
+        // Stop paths must be ancestors.
+        assert!(media_lib.ancestor_paths(&item_fp, Some(&tp.join("ALBUM_01"))).is_err());
+        assert!(media_lib.ancestor_paths(&item_fp, Some(&item_fp)).is_err());
+        assert!(media_lib.ancestor_paths(tp.join(".."), None).is_err());
+
+        // Root markers start a nested collection, which stops the walk.
+        File::create(tp.join("ALBUM_03").join(ROOT_MARKER_FILE_NAME)).expect("Unable to create root marker");
+
+        let expected = vec![
+            tp.join("ALBUM_03").join("DISC_02"),
+            tp.join("ALBUM_03"),
+        ];
+        assert_eq!(expected, media_lib.ancestor_paths(&item_fp, None).expect("Unable to get ancestors"));
+        assert!(media_lib.ancestor_paths(tp.join("ALBUM_03"), None).expect("Unable to get ancestors").is_empty());
+
+        assert_eq!(tp.join("ALBUM_03"), media_lib.collection_root(&item_fp).expect("Unable to get collection root"));
+        assert_eq!(tp.to_path_buf(), media_lib.collection_root(tp.join("ALBUM_01")).expect("Unable to get collection root"));
+    }
+
+    #[test]
+    fn test_fields_for_children() {
+        let (temp_media_root, media_lib) = default_setup("test_fields_for_children");
+        let tp = temp_media_root.path();
+
+        let field_names = ["const_key", "DISC_01_item_key", "DISC_02_self_key", "NON_EXISTENT_FIELD"];
+
+        let expected = vec![
+            (tp.join("ALBUM_01").join("DISC_01"), btreemap![
+                String::from("const_key") => Some(MetaValue::Str(String::from("const_val"))),
+                String::from("DISC_01_item_key") => Some(MetaValue::Str(String::from("DISC_01_item_val"))),
+                String::from("DISC_02_self_key") => None,
+                String::from("NON_EXISTENT_FIELD") => None,
+            ]),
+            (tp.join("ALBUM_01").join("DISC_02"), btreemap![
+                String::from("const_key") => Some(MetaValue::Str(String::from("const_val"))),
+                String::from("DISC_01_item_key") => None,
+                String::from("DISC_02_self_key") => Some(MetaValue::Str(String::from("DISC_02_self_val"))),
+                String::from("NON_EXISTENT_FIELD") => None,
+            ]),
+        ];
+        let produced = media_lib.fields_for_children(tp.join("ALBUM_01"), &field_names).expect("Unable to get fields");
+        assert_eq!(expected, produced);
+
+        // Files have no children.
+        assert!(media_lib.fields_for_children(tp.join("ALBUM_04.flac"), &field_names).is_err());
+    }
+
+    #[test]
+    fn test_plex_check() {
+        let (temp_media_root, media_lib) = default_setup("test_plex_check");
+        let album_dir = temp_media_root.path().join("ALBUM_02");
+
+        let reports = media_lib.plex_check(&album_dir).expect("Unable to check meta files");
+        assert_eq!(1, reports.len());
+        assert_eq!(album_dir.join("item.yml"), reports[0].meta_path);
+        assert!(reports[0].is_consistent());
+
+        // Items without blocks, and blocks without items, are both reported.
+        fs::remove_file(album_dir.join("TRACK_03.flac")).unwrap();
+        File::create(album_dir.join("TRACK_04.flac")).unwrap();
+        File::create(album_dir.join("TRACK_05.flac")).unwrap();
+
+        let report = media_lib.plex_check_meta_file(album_dir.join("item.yml")).unwrap().unwrap();
+        assert!(!report.is_consistent());
+        assert_eq!(Alignment::MissingBlock(String::from("TRACK_05.flac")), report.alignments[4]);
+
+        // Meta files that describe their own directory are skipped.
+        assert_eq!(None, media_lib.plex_check_meta_file(album_dir.join("self.yml")).unwrap());
+    }
+
+    #[test]
+    fn test_sidecar_meta_target() {
+        let temp_media_root = create_temp_media_test_dir("test_sidecar_meta_target");
+        let tp = temp_media_root.path();
+        let album_dir = tp.join("ALBUM_02");
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+            (String::from("sidecar"), MetaTarget::Sidecar { suffix: String::from("yml") }),
+        ];
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs).create().expect("Unable to create media library");
+
+        let track_fp = album_dir.join("TRACK_01.flac");
+        let sidecar_fp = album_dir.join("TRACK_01.flac.yml");
+        let mut f = File::create(&sidecar_fp).unwrap();
+        writeln!(f, "sidecar_key: sidecar_val").unwrap();
+
+        // Sidecars are never items themselves, even with a selection that would pick them.
+        assert!(media_lib.selection().is_selected_entry(&track_fp, false));
+        assert!(!media_lib.selection().is_selected_entry(&sidecar_fp, false));
+
+        assert_eq!(MetaTarget::Sidecar { suffix: String::from("yml") }, media_lib.meta_target_of(&sidecar_fp).unwrap());
+        let meta_fps = media_lib.meta_fps_in_tree(tp).unwrap();
+        assert!(meta_fps.contains(&sidecar_fp));
+        assert_eq!(1, meta_fps.iter().filter(|p| **p == album_dir.join("item.yml")).count());
+        assert!(media_lib.meta_fps_from_item_fp(&track_fp).unwrap().contains(&sidecar_fp));
+
+        let item_blocks = media_lib.item_fps_from_meta_fp(&sidecar_fp).unwrap();
+        assert_eq!(1, item_blocks.len());
+        assert_eq!(track_fp, item_blocks[0].0);
+        assert_eq!(Some(&MetaValue::Str(String::from("sidecar_val"))), item_blocks[0].1.get("sidecar_key"));
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(MetaValue::Str(String::from("sidecar_val"))), lookup_ctx.lookup_origin(&track_fp, "sidecar_key").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("item_val"))), lookup_ctx.lookup_origin(&track_fp, "item_key").unwrap());
+
+        // A sidecar whose item is gone is reported when checking.
+        assert!(media_lib.plex_check_meta_file(&sidecar_fp).unwrap().unwrap().is_consistent());
+
+        let orphan_fp = album_dir.join("TRACK_09.flac.yml");
+        let mut f = File::create(&orphan_fp).unwrap();
+        writeln!(f, "sidecar_key: orphan_val").unwrap();
+        let report = media_lib.plex_check_meta_file(&orphan_fp).unwrap().unwrap();
+        assert_eq!(vec![Alignment::ExcessBlock(String::from("block 1"))], report.alignments);
+        assert_eq!(2, media_lib.plex_check(&album_dir).unwrap().iter().filter(|r| r.meta_path != album_dir.join("item.yml")).count());
+
+        // Suffixes must be plain file names.
+        let bad_specs = vec![(String::from("sidecar"), MetaTarget::Sidecar { suffix: String::from("*.yml") })];
+        assert!(LibraryBuilder::new(tp, bad_specs).create().is_err());
+    }
+
+    #[test]
+    fn test_read_only() {
+        let temp_media_root = create_temp_media_test_dir("test_read_only");
+        let tp = temp_media_root.path();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs).read_only(true).create().expect("Unable to create media library");
+        assert!(media_lib.is_read_only());
+
+        // Reading works as usual.
+        let track_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(MetaValue::Str(String::from("item_val"))), lookup_ctx.lookup_origin(&track_fp, "item_key").unwrap());
+
+        // Writing fails before anything is planned.
+        let is_read_only_error = |e: Error| match *e.kind() {
+            ErrorKind::ReadOnlyLibrary(ref root) => root.as_path() == media_lib.root_dir(),
+            _ => false,
+        };
+
+        let mut plan = WritePlan::new();
+        assert!(is_read_only_error(plan_edit_item_block(&media_lib, &mut plan, &track_fp, |mb| { mb.clear(); }).unwrap_err()));
+        assert!(is_read_only_error(rename_field(&media_lib, "item_key", "other_key", &mut plan).unwrap_err()));
+        assert!(is_read_only_error(scaffold_dir(&media_lib, &Schema::new(), tp, &mut plan).unwrap_err()));
+        assert!(plan.is_empty());
+
+        // Views of the library stay read-only.
+        assert!(media_lib.with_selection(Selection::True).ensure_writable().is_err());
+        assert!(media_lib.sub_library(tp.join("ALBUM_01")).unwrap().ensure_writable().is_err());
+    }
+
+    #[test]
+    fn test_metrics() {
+        let temp_media_root = create_temp_media_test_dir("test_metrics");
+        let tp = temp_media_root.path();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let metrics = Arc::new(Metrics::new());
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs).caching(true).metrics(Arc::clone(&metrics)).create().expect("Unable to create media library");
+
+        let meta_fp = tp.join("ALBUM_01").join("self.yml");
+        media_lib.read_meta_file(&meta_fp).unwrap();
+        media_lib.read_meta_file(&meta_fp).unwrap();
+        media_lib.children_paths(tp.join("ALBUM_01")).unwrap();
+
+        let snapshot = media_lib.metrics().snapshot();
+        assert_eq!(1, snapshot.yaml_parses);
+        assert_eq!(1, snapshot.dir_reads);
+        assert_eq!(1, snapshot.cache_hits);
+        assert_eq!(meta_fp.metadata().unwrap().len() as usize, snapshot.bytes_read);
+        assert_eq!(1, snapshot.timings["parse meta file"].count);
+        assert_eq!(1, snapshot.timings["read dir"].count);
+
+        // Views count into the same metrics, as does anything else given them.
+        media_lib.with_selection(Selection::True).children_paths(tp.join("ALBUM_01")).unwrap();
+        assert_eq!(2, metrics.snapshot().dir_reads);
+
+        metrics.reset();
+        assert_eq!(MetricsSnapshot::default(), media_lib.metrics().snapshot());
+    }
+
+    #[test]
+    fn test_overlay() {
+        let (temp_media_root, media_lib) = default_setup("test_overlay");
+        let tp = temp_media_root.path();
+        let temp_overlay_root = TempDir::new("test_overlay_meta").unwrap();
+        let op = temp_overlay_root.path().canonicalize().unwrap();
+
+        let album_dir = tp.join("ALBUM_02");
+        let track_fp = album_dir.join("TRACK_01.flac");
+        let overlay_meta_fp = op.join("ALBUM_02").join("item.yml");
+
+        DirBuilder::new().create(op.join("ALBUM_02")).unwrap();
+        let mut f = File::create(&overlay_meta_fp).unwrap();
+        writeln!(f, "TRACK_01.flac:\n  item_key: overlay_val\n  overlay_key: overlay_val").unwrap();
+
+        let overlaid = Library::overlay(&media_lib, &op).expect("Unable to overlay media library");
+        assert_eq!(Some(op.as_path()), overlaid.overlay_root());
+        assert_eq!(None, media_lib.overlay_root());
+
+        // Meta files in the overlay come before the library's own.
+        assert_eq!(vec![overlay_meta_fp.clone(), album_dir.join("item.yml")], overlaid.meta_fps_from_item_fp(&track_fp).unwrap());
+        assert!(overlaid.meta_fps_in_tree(tp).unwrap().contains(&overlay_meta_fp));
+
+        let item_blocks = overlaid.item_fps_from_meta_fp(&overlay_meta_fp).unwrap();
+        assert_eq!(1, item_blocks.len());
+        assert_eq!(track_fp, item_blocks[0].0);
+
+        let mut lookup_ctx = LookupContext::new(&overlaid);
+        assert_eq!(Some(MetaValue::Str(String::from("overlay_val"))), lookup_ctx.lookup_origin(&track_fp, "item_key").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("overlay_val"))), lookup_ctx.lookup_origin(&track_fp, "overlay_key").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("TRACK_01_item_val"))), lookup_ctx.lookup_origin(&track_fp, "TRACK_01_item_key").unwrap());
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(None, lookup_ctx.lookup_origin(&track_fp, "overlay_key").unwrap());
+
+        // Writes to the library's own meta files are redirected into the overlay.
+        assert_eq!(overlay_meta_fp, overlaid.writable_meta_path(album_dir.join("item.yml")));
+        assert_eq!(album_dir.join("item.yml"), media_lib.writable_meta_path(album_dir.join("item.yml")));
+
+        // Overlays cannot overlap the library, and cannot be stacked.
+        assert!(Library::overlay(&media_lib, &album_dir).is_err());
+        assert!(Library::overlay(&overlaid, &op).is_err());
+    }
+
+    #[test]
+    fn test_field_histogram() {
+        let (temp_media_root, media_lib) = default_setup("test_field_histogram");
+        let tp = temp_media_root.path();
+        let album_dir = tp.join("ALBUM_01");
+
+        fs::write(album_dir.join("item.yml"), "- genre: [psytrance, trance]\n- genre: psy-trance\n").unwrap();
+        fs::write(album_dir.join("DISC_01").join("self.yml"), "genre: psytrance\n").unwrap();
+        fs::write(album_dir.join("DISC_01").join("item.yml"), "- genre: psytrance\n- {}\n- genre: [Psytrance, Psytrance]\n").unwrap();
+
+        // An item described by two meta files counts once per value.
+        let expected = btreemap![
+            String::from("Psytrance") => 1,
+            String::from("psy-trance") => 1,
+            String::from("psytrance") => 2,
+            String::from("trance") => 1,
+        ];
+        assert_eq!(expected, media_lib.field_histogram("genre").unwrap());
+
+        assert!(media_lib.field_histogram("NON_EXISTENT_FIELD").unwrap().is_empty());
+
+        let meta_fps = media_lib.field_value_meta_fps("genre").unwrap();
+        assert_eq!(vec![&album_dir.join("DISC_01").join("item.yml"), &album_dir.join("DISC_01").join("self.yml"), &album_dir.join("item.yml")], meta_fps["psytrance"].iter().collect::<Vec<_>>());
+        assert_eq!(vec![&album_dir.join("item.yml")], meta_fps["psy-trance"].iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_suggest_values() {
+        let (temp_media_root, media_lib) = default_setup("test_suggest_values");
+        let tp = temp_media_root.path();
+        let disc_dir = tp.join("ALBUM_01").join("DISC_01");
+
+        let mut f = File::create(disc_dir.join("item.yml")).unwrap();
+        write!(f, "- artist: [Alice, Bob, Alice]\n- artist: alice cooper\n- artist: Alice\n").unwrap();
+
+        // Values used by more items come first, and the prefix ignores case.
+        let expected = vec![(String::from("Alice"), 2), (String::from("alice cooper"), 1)];
+        assert_eq!(expected, media_lib.suggest_values("artist", "AL").expect("Unable to suggest values"));
+
+        let expected = vec![(String::from("Alice"), 2), (String::from("Bob"), 1), (String::from("alice cooper"), 1)];
+        assert_eq!(expected, media_lib.suggest_values("artist", "").expect("Unable to suggest values"));
+
+        assert!(media_lib.suggest_values("NON_EXISTENT_FIELD", "").expect("Unable to suggest values").is_empty());
+
+        // Only values in the scope are suggested.
+        let scoped_lib = media_lib.scoped_to(vec![tp.join("ALBUM_02")]).expect("Unable to scope media library");
+        assert!(scoped_lib.suggest_values("artist", "").expect("Unable to suggest values").is_empty());
+    }
+
+    #[test]
+    fn test_iter_item_blocks() {
+        let (temp_media_root, media_lib) = default_setup("test_iter_item_blocks");
+        let tp = temp_media_root.path();
+
+        let meta_fp = tp.join("ALBUM_01").join("item.yml");
+        let parsed = media_lib.read_meta_file(&meta_fp).expect("Unable to read meta file");
+        assert_eq!(tp.join("ALBUM_01"), parsed.working_dir_path());
+
+        let produced: Vec<_> = media_lib.iter_item_blocks(&parsed).expect("Unable to plex meta file")
+            .map(|(item_path, mb)| (item_path, mb.get("item_key").cloned()))
+            .collect();
+        let expected = vec![
+            (tp.join("ALBUM_01").join("DISC_01"), Some(MetaValue::Str(String::from("item_val")))),
+            (tp.join("ALBUM_01").join("DISC_02"), Some(MetaValue::Str(String::from("item_val")))),
+        ];
+        assert_eq!(expected, produced);
+
+        // The owned version produces the same items.
+        let owned_item_fps: Vec<_> = media_lib.item_fps_from_meta_fp(&meta_fp).expect("Unable to read meta file")
+            .into_iter()
+            .map(|(item_path, _)| item_path)
+            .collect();
+        assert_eq!(vec![tp.join("ALBUM_01").join("DISC_01"), tp.join("ALBUM_01").join("DISC_02")], owned_item_fps);
+
+        assert!(media_lib.read_meta_file(tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac")).is_err());
+    }
+
+    #[test]
+    fn test_validate_schema() {
+        let (temp_media_root, media_lib) = default_setup("test_validate_schema");
+        let tp = temp_media_root.path();
+
+        let mut schema = Schema::new();
+        schema
+            .field("const_key", FieldSpec::new().required(true).constraint(FieldConstraint::OneOf(vec![String::from("const_val")])))
+            .field("item_key", FieldSpec::new().required(true))
+            .field("ALBUM_03_self_key", FieldSpec::new().constraint(FieldConstraint::Pattern(Regex::new(r"\d+").unwrap())));
+
+        let expected = vec![
+            SchemaViolation {
+                item_path: tp.join("ALBUM_03"),
+                meta_path: Some(tp.join("ALBUM_03").join("self.yml")),
+                field_name: String::from("ALBUM_03_self_key"),
+                kind: ViolationKind::PatternMismatch(String::from("ALBUM_03_self_val")),
+            },
+            // The root is not described by any item meta file.
+            SchemaViolation {
+                item_path: tp.to_path_buf(),
+                meta_path: None,
+                field_name: String::from("item_key"),
+                kind: ViolationKind::Missing,
+            },
+        ];
+        let mut progress = ProgressCounts::default();
+        let produced = media_lib.validate_schema(&schema, &mut progress).expect("Unable to validate schema");
+        assert_eq!(expected, produced);
+
+        // Every directory has both a self and an item meta file.
+        assert_eq!(30, progress.meta_files_parsed);
+    }
+
+    #[test]
+    fn test_scoped_to() {
+        let (temp_media_root, media_lib) = default_setup("test_scoped_to");
+        let tp = temp_media_root.path();
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+
+        let scoped_lib = media_lib.scoped_to(vec![disc_fp.join("TRACK_01.flac")]).expect("Unable to scope media library");
+
+        assert!(scoped_lib.is_in_scope(disc_fp.join("TRACK_01.flac")));
+        assert!(!scoped_lib.is_in_scope(disc_fp.join("TRACK_02.flac")));
+        assert!(!scoped_lib.is_in_scope(&disc_fp));
+        assert!(scoped_lib.leads_into_scope(&disc_fp));
+        assert!(!scoped_lib.leads_into_scope(tp.join("ALBUM_02")));
+        assert_eq!(vec![tp.join("ALBUM_01")], scoped_lib.walk_children_paths(tp).unwrap());
+
+        // Lookups are not scoped.
+        assert_eq!(media_lib.children_paths(tp).unwrap(), scoped_lib.children_paths(tp).unwrap());
+        assert!(media_lib.is_in_scope(tp.join("ALBUM_02")));
+
+        // Meta files bring the items that they describe into the scope.
+        let scoped_lib = media_lib.scoped_to(vec![disc_fp.join("item.yml")]).expect("Unable to scope media library");
+        assert!(scoped_lib.is_in_scope(disc_fp.join("TRACK_02.flac")));
+
+        let mut schema = Schema::new();
+        schema
+            .field("item_key", FieldSpec::new().required(true))
+            .field("ALBUM_03_self_key", FieldSpec::new().constraint(FieldConstraint::Pattern(Regex::new(r"\d+").unwrap())));
+
+        // The root is out of the scope, so its missing item field is not reported.
+        let scoped_lib = media_lib.scoped_to(vec![tp.join("ALBUM_03")]).expect("Unable to scope media library");
+        let expected = vec![
+            SchemaViolation {
+                item_path: tp.join("ALBUM_03"),
+                meta_path: Some(tp.join("ALBUM_03").join("self.yml")),
+                field_name: String::from("ALBUM_03_self_key"),
+                kind: ViolationKind::PatternMismatch(String::from("ALBUM_03_self_val")),
+            },
+        ];
+        assert_eq!(expected, scoped_lib.validate_schema(&schema, &mut ProgressCounts::default()).expect("Unable to validate schema"));
+
+        // Nothing is in an empty scope.
+        let scoped_lib = media_lib.scoped_to(Vec::<PathBuf>::new()).expect("Unable to scope media library");
+        assert!(scoped_lib.validate_schema(&schema, &mut ProgressCounts::default()).unwrap().is_empty());
+
+        assert!(media_lib.scoped_to(vec![tp.join("..")]).is_err());
+    }
+
+    #[test]
+    fn test_meta_fps_from_item_fp() {
+        // Create temp directory.
+        let temp = TempDir::new("test_meta_fps_from_item_fp").unwrap();
+        let tp = temp.path();
+
+        let db = DirBuilder::new();
+
+        let meta_targets = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let selection = Selection::Or(
+            Box::new(Selection::IsDir),
+            Box::new(
+                Selection::And(
+                    Box::new(Selection::IsFile),
+                    Box::new(Selection::Ext("flac".to_string())),
+                ),
+            ),
+        );
+
+        // Create sample item files and directories.
+        db.create(tp.join("subdir")).unwrap();
+        sleep(Duration::from_millis(5));
+        File::create(tp.join("item.flac")).unwrap();
+        sleep(Duration::from_millis(5));
+        File::create(tp.join("subdir").join("subitem.flac")).unwrap();
+        sleep(Duration::from_millis(5));
+
+        // Create meta files.
+        let mut meta_file = File::create(tp.join("self.yml"))
+            .expect("Unable to create metadata file");
+
+        writeln!(meta_file, "title: PsyStyle Nation\nartist: [lapix, Massive New Krew]")
+            .expect("Unable to write metadata file");
+
+        let mut meta_file = File::create(tp.join("item.yml"))
+            .expect("Unable to create metadata file");
+
+        writeln!(meta_file, "item.flac:\n  title: Black Mamba\n  artist: lapix\nsubdir:\n  title: What Is This?")
+            .expect("Unable to write metadata file");
+
+        let mut meta_file = File::create(tp.join("subdir").join("self.yml"))
+            .expect("Unable to create metadata file");
+
+        writeln!(meta_file, "title: A Subtrack?\nartist: Massive New Krew")
+            .expect("Unable to write metadata file");
+
+        // Create media library.
+        let media_lib = LibraryBuilder::new(&tp, meta_targets).selection(selection).create().expect("Unable to create media library"); //Library::new_with_options(&tp, meta_targets, library_options).expect("Unable to create media library");
+
+        // Run tests.
+        let found: Vec<_> = media_lib.meta_fps_from_item_fp(&tp).expect("Unable to get meta fps");
+        assert_eq!(vec![tp.join("self.yml")], found);
+
+        let found: Vec<_> = media_lib.meta_fps_from_item_fp(tp.join("item.flac")).expect("Unable to get meta fps");
+        assert_eq!(vec![tp.join("item.yml")], found);
+
+        let found: Vec<_> = media_lib.meta_fps_from_item_fp(tp.join("subdir")).expect("Unable to get meta fps");
+        assert_eq!(vec![tp.join("subdir").join("self.yml"), tp.join("item.yml")], found);
+
+        assert!(media_lib.meta_fps_from_item_fp(tp.join("DOES_NOT_EXIST")).is_err());
+
+        let found: Vec<_> = media_lib.meta_fps_from_item_fp(tp.join("subdir").join("subitem.flac")).expect("Unable to get meta fps");
+        assert_eq!(Vec::<PathBuf>::new(), found);
+    }
+
+    #[test]
+    fn test_meta_file_patterns() {
+        let temp = TempDir::new("test_meta_file_patterns").unwrap();
+        let tp = temp.path();
+
+        File::create(tp.join("TRACK_01.flac")).unwrap();
+        File::create(tp.join("TRACK_02.flac")).unwrap();
+
+        let mut f = File::create(tp.join("b.meta.yml")).unwrap();
+        writeln!(f, "TRACK_01.flac:\n  title: B Title\n  artist: B Artist").unwrap();
+        let mut f = File::create(tp.join("a.meta.yml")).unwrap();
+        writeln!(f, "TRACK_01.flac:\n  title: A Title\nTRACK_02.flac:\n  title: Second").unwrap();
+        File::create(tp.join("other.yml")).unwrap();
+
+        let meta_targets = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("*.meta.yml"), MetaTarget::Siblings),
+        ];
+        let media_lib = LibraryBuilder::new(&tp, meta_targets).selection(Selection::Ext(String::from("flac"))).create().expect("Unable to create media library");
+
+        let found = media_lib.meta_fps_from_item_fp(tp.join("TRACK_01.flac")).expect("Unable to get meta fps");
+        assert_eq!(vec![tp.join("a.meta.yml"), tp.join("b.meta.yml")], found);
+
+        // Earlier files by name win, and later files fill in the gaps.
+        let fields = media_lib.fields_for_children(&tp, &["title", "artist"]).expect("Unable to get fields");
+        assert_eq!(btreemap![
+            String::from("title") => Some(MetaValue::Str(String::from("A Title"))),
+            String::from("artist") => Some(MetaValue::Str(String::from("B Artist"))),
+        ], fields[0].1);
+
+        assert!(media_lib.read_meta_file(tp.join("b.meta.yml")).is_ok());
+        assert!(media_lib.read_meta_file(tp.join("other.yml")).is_err());
+
+        let bad_targets = vec![(String::from("[.yml"), MetaTarget::Siblings)];
+        assert!(LibraryBuilder::new(&tp, bad_targets).create().is_err());
+    }
+
+    #[test]
+    fn test_working_dir_block() {
+        let temp = TempDir::new("test_working_dir_block").unwrap();
+        let tp = temp.path();
+        let album_fp = tp.join("ALBUM");
+
+        DirBuilder::new().create(&album_fp).unwrap();
+        File::create(album_fp.join("TRACK_01.flac")).unwrap();
+
+        let mut f = File::create(album_fp.join("item.yml")).unwrap();
+        writeln!(f, "~:\n  album: Album Title\n  title: Ignored\nTRACK_01.flac:\n  title: Track Title").unwrap();
+        let mut f = File::create(tp.join("item.yml")).unwrap();
+        writeln!(f, "ALBUM:\n  title: Album Name").unwrap();
+
+        let meta_targets = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let selection = Selection::Or(Box::new(Selection::Ext(String::from("flac"))), Box::new(Selection::IsDir));
+        let media_lib = LibraryBuilder::new(&tp, meta_targets).selection(selection).create().expect("Unable to create media library");
+
+        // The null key describes the directory that the meta file is in.
+        let item_blocks = media_lib.item_fps_from_meta_fp(album_fp.join("item.yml")).expect("Unable to get item blocks");
+        assert_eq!(2, item_blocks.len());
+        assert!(item_blocks.iter().any(|&(ref item_path, ref mb)| *item_path == album_fp && mb.contains_key("album")));
+
+        assert_eq!(
+            vec![tp.join("item.yml"), album_fp.join("item.yml")],
+            media_lib.meta_fps_from_item_fp(&album_fp).expect("Unable to get meta files"),
+        );
+
+        // The meta file next to the directory wins, and the one inside of it fills in the gaps.
+        let fields = media_lib.fields_for_children(&tp, &["title", "album"]).expect("Unable to get fields");
+        assert_eq!(btreemap![
+            String::from("title") => Some(MetaValue::Str(String::from("Album Name"))),
+            String::from("album") => Some(MetaValue::Str(String::from("Album Title"))),
+        ], fields[0].1);
+    }
+
+    #[cfg(not(feature = "archives"))]
+    #[test]
+    fn test_archives_not_supported() {
+        let temp = TempDir::new("test_archives_not_supported").unwrap();
+
+        assert!(LibraryBuilder::new(temp.path(), vec![]).archives(true).create().is_err());
+    }
+
+    #[cfg(feature = "archives")]
+    #[test]
+    fn test_archives() {
+        use zip::ZipWriter;
+        use zip::write::FileOptions;
+
+        let temp = TempDir::new("test_archives").unwrap();
+        let tp = temp.path();
+        let archive_fp = tp.join("ALBUM.zip");
+
+        File::create(tp.join("TRACK_01.flac")).unwrap();
+        let mut f = File::create(tp.join("item.yml")).unwrap();
+        writeln!(f, "- title: Archived Album\n- title: Loose Track").unwrap();
+
+        {
+            let mut zip = ZipWriter::new(File::create(&archive_fp).unwrap());
+            zip.start_file("self.yml", FileOptions::default()).unwrap();
+            zip.write_all(b"artist: Archived Artist\n").unwrap();
+            zip.start_file("item.yml", FileOptions::default()).unwrap();
+            zip.write_all(b"- title: Track 1\n- title: Track 2\n").unwrap();
+            zip.start_file("TRACK_01.flac", FileOptions::default()).unwrap();
+            zip.start_file("TRACK_02.flac", FileOptions::default()).unwrap();
+            zip.start_file("cover.jpg", FileOptions::default()).unwrap();
+            zip.finish().unwrap();
+        }
+
+        let meta_targets = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let selection = Selection::Or(Box::new(Selection::Ext(String::from("flac"))), Box::new(Selection::IsDir));
+
+        // Without archives, the archive is a plain file, and is not selected.
+        let media_lib = LibraryBuilder::new(&tp, meta_targets.clone()).selection(selection.clone()).create().expect("Unable to create media library");
+        assert_eq!(vec![tp.join("TRACK_01.flac")], media_lib.children_paths(&tp).unwrap());
+
+        let media_lib = LibraryBuilder::new(&tp, meta_targets).selection(selection).archives(true).create().expect("Unable to create media library");
+
+        // Archives count as directories, and their entries are selected like any others.
+        assert_eq!(vec![archive_fp.clone(), tp.join("TRACK_01.flac")], media_lib.children_paths(&tp).unwrap());
+        assert_eq!(vec![archive_fp.join("TRACK_01.flac"), archive_fp.join("TRACK_02.flac")], media_lib.children_paths(&archive_fp).unwrap());
+        assert!(media_lib.is_item_dir(&archive_fp));
+        assert!(media_lib.item_exists(archive_fp.join("TRACK_02.flac")));
+        assert!(!media_lib.item_exists(archive_fp.join("TRACK_03.flac")));
+
+        assert_eq!(
+            vec![tp.join("item.yml"), archive_fp.join("self.yml"), archive_fp.join("item.yml")],
+            media_lib.meta_fps_in_tree(&tp).unwrap(),
+        );
+
+        // The archive is described by the meta files around it and inside of it, and its entries by the meta files next to them.
+        let fields = media_lib.fields_for_children(&tp, &["title", "artist"]).expect("Unable to get fields");
+        assert_eq!(btreemap![
+            String::from("title") => Some(MetaValue::Str(String::from("Archived Album"))),
+            String::from("artist") => Some(MetaValue::Str(String::from("Archived Artist"))),
+        ], fields[0].1);
+
+        let fields = media_lib.fields_for_children(&archive_fp, &["title"]).expect("Unable to get fields");
+        assert_eq!(Some(MetaValue::Str(String::from("Track 2"))), fields[1].1["title"]);
+    }
+
+    #[test]
+    fn test_move_item() {
+        let (temp_media_root, media_lib) = default_setup("test_move_item");
+        let tp = temp_media_root.path();
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let origin = |path: &PathBuf, field_name: &str| LookupContext::new(&media_lib).lookup_origin(path, field_name).unwrap();
+
+        // A file moved to another directory takes its block along, and the blocks it leaves behind stay lined up.
+        let disc_path = tp.join("ALBUM_01").join("DISC_01");
+        let moved_path = tp.join("ALBUM_02").join("TRACK_09.flac");
+        let touched = media_lib.move_item(disc_path.join("TRACK_02.flac"), &moved_path).expect("Unable to move item");
+        assert_eq!(vec![disc_path.join("item.yml"), tp.join("ALBUM_02").join("item.yml")], touched);
+
+        assert!(moved_path.is_file());
+        assert!(!disc_path.join("TRACK_02.flac").exists());
+        assert_eq!(Some(str_val("TRACK_02_item_val")), origin(&moved_path, "TRACK_02_item_key"));
+        assert_eq!(Some(str_val("TRACK_03_item_val")), origin(&disc_path.join("TRACK_03.flac"), "TRACK_03_item_key"));
+        assert_eq!(Some(str_val("TRACK_03_item_val")), origin(&tp.join("ALBUM_02").join("TRACK_03.flac"), "TRACK_03_item_key"));
+
+        // Renaming within a directory works the same way.
+        let renamed_path = disc_path.join("TRACK_10.flac");
+        media_lib.move_item(disc_path.join("TRACK_01.flac"), &renamed_path).expect("Unable to rename item");
+        assert_eq!(Some(str_val("TRACK_01_item_val")), origin(&renamed_path, "TRACK_01_item_key"));
+        assert_eq!(Some(str_val("TRACK_03_item_val")), origin(&disc_path.join("TRACK_03.flac"), "TRACK_03_item_key"));
+
+        // A moved directory keeps the meta files inside of it, and its block in the meta file next to it moves too.
+        let moved_dir_path = tp.join("ALBUM_02").join("DISC_02");
+        media_lib.move_item(tp.join("ALBUM_01").join("DISC_02"), &moved_dir_path).expect("Unable to move directory");
+        assert_eq!(Some(str_val("DISC_02_item_val")), origin(&moved_dir_path, "DISC_02_item_key"));
+        assert_eq!(Some(str_val("DISC_02_self_val")), origin(&moved_dir_path, "DISC_02_self_key"));
+        assert_eq!(Some(str_val("TRACK_02_item_val")), origin(&moved_dir_path.join("TRACK_02.flac"), "TRACK_02_item_key"));
+        assert_eq!(Some(str_val("DISC_01_item_val")), origin(&tp.join("ALBUM_02").join("DISC_01"), "DISC_01_item_key"));
+
+        // Invalid moves fail without changing anything.
+        assert!(media_lib.move_item(&moved_path, tp.join("ALBUM_02").join("TRACK_01.flac")).is_err());
+        assert!(media_lib.move_item(&moved_dir_path, moved_dir_path.join("INSIDE")).is_err());
+        assert!(media_lib.move_item(tp.join("DOES_NOT_EXIST.flac"), tp.join("NEW.flac")).is_err());
+        assert!(media_lib.move_item(&moved_path, tp.join("..").join("ESCAPED.flac")).is_err());
+        assert!(moved_path.is_file());
+
+        let read_only = LibraryBuilder::new(tp, vec![(String::from("item.yml"), MetaTarget::Siblings)]).read_only(true).create().unwrap();
+        assert!(read_only.move_item(&moved_path, tp.join("TRACK_09.flac")).is_err());
+        assert!(moved_path.is_file());
+    }
+
+    #[test]
+    fn test_increment_field() {
+        let (temp_media_root, media_lib) = default_setup("test_increment_field");
+        let tp = temp_media_root.path();
+
+        let track_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+        let origin = |field_name: &str| LookupContext::new(&media_lib).lookup_origin(&track_fp, field_name).unwrap();
+
+        assert_eq!(1, media_lib.increment_field(&track_fp, "playcount").unwrap());
+        assert_eq!(2, media_lib.increment_field(&track_fp, "playcount").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("2"))), origin("playcount"));
+
+        // Other fields in the block are kept.
+        assert_eq!(Some(MetaValue::Str(String::from("TRACK_01_item_val"))), origin("TRACK_01_item_key"));
+
+        // Values that are not counts are left alone.
+        assert!(media_lib.increment_field(&track_fp, "TRACK_01_item_key").is_err());
+        assert!(media_lib.increment_field(tp.join("DOES_NOT_EXIST.flac"), "playcount").is_err());
+
+        media_lib.set_rating(&track_fp, Some(4)).unwrap();
+        assert_eq!(Some(MetaValue::Str(String::from("4"))), origin("rating"));
+        assert!(media_lib.set_rating(&track_fp, Some(6)).is_err());
+        media_lib.set_rating(&track_fp, None).unwrap();
+        assert_eq!(None, origin("rating"));
+    }
+
+    #[test]
+    fn test_target_selection() {
+        let temp = TempDir::new("test_target_selection").unwrap();
+        let tp = temp.path();
+
+        DirBuilder::new().create(tp.join("0_SUB")).unwrap();
+        File::create(tp.join("A.flac")).unwrap();
+        File::create(tp.join("B.flac")).unwrap();
+        fs::write(tp.join("item.yml"), "- title: a\n- title: b\n").unwrap();
+
+        let meta_targets = vec![
+            (String::from("item.yml"), MetaTarget::Siblings),
+            (String::from("self.yml"), MetaTarget::Contains),
+        ];
+        let selection = Selection::Or(Box::new(Selection::Ext(String::from("flac"))), Box::new(Selection::IsDir));
+
+        let title = |media_lib: &Library, name: &str| LookupContext::new(media_lib).lookup_origin(tp.join(name), "title").unwrap();
+        let str_val = |s: &str| Some(MetaValue::Str(String::from(s)));
+
+        // By default, blocks are matched up with every item that the library selects.
+        let media_lib = LibraryBuilder::new(tp, meta_targets.clone()).selection(selection.clone()).create().unwrap();
+        assert_eq!(str_val("a"), title(&media_lib, "0_SUB"));
+        assert_eq!(str_val("b"), title(&media_lib, "A.flac"));
+        assert_eq!(None, title(&media_lib, "B.flac"));
+
+        let media_lib = {
+            LibraryBuilder::new(tp, meta_targets.clone())
+                .selection(selection.clone())
+                .target_selection("item.yml", Selection::Ext(String::from("flac")))
+                .create()
+                .unwrap()
+        };
+        assert_eq!(None, title(&media_lib, "0_SUB"));
+        assert_eq!(str_val("a"), title(&media_lib, "A.flac"));
+        assert_eq!(str_val("b"), title(&media_lib, "B.flac"));
+
+        // Walks still follow the library's selection.
+        assert_eq!(vec![tp.join("0_SUB"), tp.join("A.flac"), tp.join("B.flac")], media_lib.children_paths(tp).unwrap());
+
+        assert!(media_lib.plex_check_meta_file(tp.join("item.yml")).unwrap().unwrap().is_consistent());
+
+        // New blocks only go to meta files that can describe the item.
+        let mut plan = WritePlan::new();
+        let written_fp = plan_edit_item_block(&media_lib, &mut plan, tp.join("0_SUB"), |mb| { mb.insert(String::from("title"), MetaValue::Str(String::from("sub"))); }).unwrap();
+        assert_eq!(tp.join("0_SUB").join("self.yml"), written_fp);
+
+        assert!(LibraryBuilder::new(tp, meta_targets).target_selection("other.yml", Selection::True).create().is_err());
+    }
+
+    #[test]
+    fn test_items_since() {
+        let (temp_media_root, media_lib) = default_setup("test_items_since");
+        let tp = temp_media_root.path();
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+
+        sleep(Duration::from_millis(10));
+        let since = SystemTime::now();
+        sleep(Duration::from_millis(10));
+
+        assert!(media_lib.items_modified_since(since).unwrap().is_empty());
+        assert!(media_lib.items_added_since(since).unwrap().is_empty());
+
+        // Changing a meta file modifies every item that it describes, including its own directory.
+        // Set mod times explicitly, so the test does not depend on file system time resolution.
+        let mut f = File::create(disc_fp.join("item.yml")).unwrap();
+        writeln!(f, "TRACK_01.flac: {{title: A}}").unwrap();
+        f.set_modified(since + Duration::from_secs(1)).unwrap();
+        File::create(disc_fp.join("TRACK_04.flac")).unwrap().set_modified(since + Duration::from_secs(1)).unwrap();
+
+        let expected: Vec<PathBuf> = {
+            Some(disc_fp.clone()).into_iter()
+                .chain(vec!["TRACK_01.flac", "TRACK_02.flac", "TRACK_03.flac", "TRACK_04.flac"].into_iter().map(|n| disc_fp.join(n)))
+                .collect()
+        };
+        assert_eq!(expected, media_lib.items_modified_since(since).unwrap());
+
+        // Without a persistent cache, items are taken to have been added when they were created.
+        assert_eq!(vec![disc_fp.join("TRACK_04.flac")], media_lib.items_added_since(since).unwrap());
+
+        let built = PersistentCache::build(&mut LookupContext::new(&media_lib), &mut ProgressCounts::default()).unwrap();
+        built.save(tp).unwrap();
+        assert_eq!(vec![disc_fp.join("TRACK_04.flac")], media_lib.items_added_since(since).unwrap());
+
+        // Items that no build has seen yet are still found.
+        // Creation times cannot be set, so wait out the time resolution of the file system on both sides.
+        sleep(Duration::from_millis(10));
+        let after_build = SystemTime::now();
+        sleep(Duration::from_millis(10));
+        File::create(disc_fp.join("TRACK_05.flac")).unwrap().set_modified(after_build + Duration::from_secs(1)).unwrap();
+        assert_eq!(vec![disc_fp.join("TRACK_05.flac")], media_lib.items_added_since(after_build).unwrap());
+
+        // Rebuilding keeps the times that items were first seen.
+        let mut rebuilt = PersistentCache::build(&mut LookupContext::new(&media_lib), &mut ProgressCounts::default()).unwrap();
+        rebuilt.carry_over_first_seen(&built);
+        rebuilt.save(tp).unwrap();
+        assert_eq!(built.first_seen(tp, disc_fp.join("TRACK_04.flac")), rebuilt.first_seen(tp, disc_fp.join("TRACK_04.flac")));
+        assert_eq!(vec![disc_fp.join("TRACK_04.flac"), disc_fp.join("TRACK_05.flac")], media_lib.items_added_since(since).unwrap());
+    }
+
+    #[test]
+    fn test_walk_items() {
+        let (temp_media_root, media_lib) = default_setup("test_walk_items");
+        let tp = temp_media_root.path();
+        let album_fp = tp.join("ALBUM_03");
+
+        let mut steps = vec![];
+        let mut entered = vec![];
+
+        media_lib.walk_items(&album_fp, |step| {
+            match step {
+                WalkStep::EnterDir(dir_path) => entered.push(dir_path.to_path_buf()),
+                WalkStep::Item { path, is_dir } if path.parent() == Some(album_fp.as_path()) => steps.push((path.to_path_buf(), is_dir)),
+                _ => {},
+            }
+
+            // Everything inside of the first disc is skipped.
+            Ok(step != WalkStep::Item { path: &album_fp.join("DISC_01"), is_dir: true })
+        }).unwrap();
+
+        assert_eq!(vec![(album_fp.join("DISC_01"), true), (album_fp.join("DISC_02"), true)], steps);
+
+        let disc_fp = album_fp.join("DISC_02");
+        assert_eq!(vec![album_fp.clone(), disc_fp.clone(), disc_fp.join("TRACK_01"), disc_fp.join("TRACK_02")], entered);
+    }
+
+    #[test]
+    fn test_memory_fs() {
+        let mut memory_fs = MemoryFs::new();
+        memory_fs
+            .add_file("/taggu_memory/ALBUM/self.yml", "artist: lapix\n")
+            .add_file("/taggu_memory/ALBUM/item.yml", "- title: a\n- title: b\n")
+            .add_file("/taggu_memory/ALBUM/TRACK_01.flac", "")
+            .add_file("/taggu_memory/ALBUM/TRACK_02.flac", "")
+            .add_file("/taggu_memory/ALBUM/cover.jpg", "");
+
+        let meta_targets = vec![
+            (String::from("item.yml"), MetaTarget::Siblings),
+            (String::from("self.yml"), MetaTarget::Contains),
+        ];
+        let selection = Selection::Or(Box::new(Selection::Ext(String::from("flac"))), Box::new(Selection::IsDir));
+
+        let media_lib = LibraryBuilder::new("/taggu_memory", meta_targets.clone()).selection(selection).vfs(memory_fs).create().unwrap();
+        let album_fp = PathBuf::from("/taggu_memory/ALBUM");
+
+        assert_eq!(vec![album_fp.join("TRACK_01.flac"), album_fp.join("TRACK_02.flac")], media_lib.children_paths(&album_fp).unwrap());
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(MetaValue::Str(String::from("b"))), lookup_ctx.lookup_origin(album_fp.join("TRACK_02.flac"), "title").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("lapix"))), lookup_ctx.lookup(album_fp.join("TRACK_01.flac"), &LookupOptions::new("artist")).unwrap());
+
+        // Libraries that are not on the native file system can only be read.
+        assert!(media_lib.ensure_writable().is_err());
+
+        assert!(LibraryBuilder::new("/taggu_memory/MISSING", meta_targets.clone()).vfs(MemoryFs::new()).create().is_err());
+    }
+
+    #[test]
+    fn test_memory_fs_contains() {
+        let mut memory_fs = MemoryFs::new();
+        memory_fs
+            .add_file("/taggu_memory/ALBUM/self.yml", "title: album\n")
+            .add_file("/taggu_memory/ALBUM/DISC_01/self.yml", "title: disc\n")
+            .add_file("/taggu_memory/ALBUM/DISC_01/TRACK_01.flac", "");
+
+        let meta_targets = vec![(String::from("self.yml"), MetaTarget::Contains)];
+
+        let media_lib = LibraryBuilder::new("/taggu_memory", meta_targets).vfs(memory_fs).create().unwrap();
+        let album_fp = PathBuf::from("/taggu_memory/ALBUM");
+        let disc_fp = album_fp.join("DISC_01");
+
+        // Directories are found on the library's file system, not on disk.
+        assert_eq!(Some(disc_fp.clone()), media_lib.meta_target_dir_path(&MetaTarget::Contains, &disc_fp));
+        assert_eq!(None, media_lib.meta_target_dir_path(&MetaTarget::Contains, &disc_fp.join("TRACK_01.flac")));
+
+        assert_eq!(vec![album_fp.join("self.yml"), disc_fp.join("self.yml")], media_lib.meta_fps_in_tree(&album_fp).unwrap());
+        assert_eq!(vec![(disc_fp.clone(), btreemap![String::from("title") => metaval!("disc")])], media_lib.item_fps_from_meta_fp(disc_fp.join("self.yml")).unwrap());
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(metaval!("album")), lookup_ctx.lookup_origin(&album_fp, "title").unwrap());
+        assert_eq!(Some(metaval!("disc")), lookup_ctx.lookup_origin(&disc_fp, "title").unwrap());
+        assert_eq!(None, lookup_ctx.lookup_origin(disc_fp.join("TRACK_01.flac"), "title").unwrap());
+    }
+
+    #[test]
+    fn test_memory_fs_sort_and_cache() {
+        let mut memory_fs = MemoryFs::new();
+        memory_fs
+            .add_file("/taggu_memory/ALBUM/item.yml", "- title: a\n- title: b\n")
+            .add_file("/taggu_memory/ALBUM/TRACK_01.flac", "")
+            .add_file("/taggu_memory/ALBUM/TRACK_02.flac", "")
+            .set_modified("/taggu_memory/ALBUM/TRACK_01.flac", UNIX_EPOCH + Duration::from_secs(20))
+            .set_modified("/taggu_memory/ALBUM/TRACK_02.flac", UNIX_EPOCH + Duration::from_secs(10))
+            .set_modified("/taggu_memory/ALBUM/item.yml", UNIX_EPOCH + Duration::from_secs(30));
+
+        let meta_targets = vec![(String::from("item.yml"), MetaTarget::Siblings)];
+
+        let media_lib = {
+            LibraryBuilder::new("/taggu_memory", meta_targets)
+                .selection(Selection::Ext(String::from("flac")))
+                .sort_order(SortOrder::ModTime(TieBreaker::Name))
+                .caching(true)
+                .vfs(memory_fs)
+                .create()
+                .unwrap()
+        };
+        let album_fp = PathBuf::from("/taggu_memory/ALBUM");
+
+        // Modification times come from the library's file system.
+        assert_eq!(vec![album_fp.join("TRACK_02.flac"), album_fp.join("TRACK_01.flac")], media_lib.children_paths(&album_fp).unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("a"))), LookupContext::new(&media_lib).lookup_origin(album_fp.join("TRACK_02.flac"), "title").unwrap());
+
+        // Entries on it can be stamped, so they are cached.
+        media_lib.read_meta_file(album_fp.join("item.yml")).unwrap();
+        assert_eq!(1, media_lib.cache().unwrap().meta_file_count());
+        assert_eq!(1, media_lib.cache().unwrap().listing_count());
+    }
 
     // #[test]
     // fn test_item_fps_from_meta_fp() {
@@ -344,7 +2911,7 @@ mod tests {
     //                             .expect("Unable to create media library"); // Library::new_with_options(&tp, meta_targets_map, library_options_map).expect("Unable to create media library");
     //     let media_lib_seq = LibraryBuilder::new(&tp, meta_targets_seq)
     //                             .selection(selection.clone())
-    //                             .sort_order(SortOrder::ModTime)
+    //                             .sort_order(SortOrder::ModTime(TieBreaker::Name))
     //                             .create()
     //                             .expect("Unable to create media library"); // Library::new_with_options(&tp, meta_targets_seq, library_options_seq).expect("Unable to create media library");
 
@@ -0,0 +1,68 @@
+// Plugin fields are virtual fields whose values come from code that an embedding application registers with the library, e.g. to read BPM from an external database.
+// They are looked up under the plugin prefix followed by the name they were registered with, e.g. `__plugin:bpm`.
+// As with the other virtual fields, they are never read from or written to meta files.
+
+use std::path::Path;
+
+use library::Library;
+use metadata::MetaValue;
+
+/// The prefix that marks a field name as naming a plugin field.
+pub const PLUGIN_PREFIX: &str = "__plugin:";
+
+/// Computes the value of a plugin field for an item, or `None` if the item has no value for it.
+/// Providers are shared between clones of a library, and may be called from several threads at once.
+pub type FieldProvider = Fn(&Library, &Path) -> Option<MetaValue> + Send + Sync;
+
+/// Returns the name that a plugin field was registered with, if a field name is that of a plugin field.
+pub fn plugin_field_name(field_name: &str) -> Option<&str> {
+    if field_name.starts_with(PLUGIN_PREFIX) {
+        Some(&field_name[PLUGIN_PREFIX.len()..])
+    }
+    else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use metadata::MetaValue;
+    use lookup::LookupContext;
+    use lookup::options::LookupOptions;
+    use library::LibraryBuilder;
+    use fixtures::{create_temp_media_test_dir, fixture_library};
+
+    use super::plugin_field_name;
+
+    #[test]
+    fn test_plugin_fields() {
+        assert_eq!(Some("bpm"), plugin_field_name("__plugin:bpm"));
+        assert_eq!(None, plugin_field_name("bpm"));
+
+        let temp_media_root = create_temp_media_test_dir("test_plugin_fields");
+        let tp = temp_media_root.path();
+
+        let media_lib = fixture_library(tp).unwrap();
+        let mut builder = LibraryBuilder::new(tp, media_lib.meta_target_specs().to_vec());
+        builder
+            .selection(media_lib.selection().clone())
+            .field_provider("name_len", |_, p| p.file_name().map(|s| MetaValue::Str(s.to_string_lossy().len().to_string())))
+            .field_provider("album_only", |media_lib, p| {
+                if media_lib.is_item_dir(p) && p.ends_with("ALBUM_01") { Some(MetaValue::Str(String::from("album"))) } else { None }
+            });
+        let media_lib = builder.create().unwrap();
+
+        let album_fp = tp.join("ALBUM_01");
+        let track_fp = album_fp.join("DISC_01").join("TRACK_01.flac");
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        assert_eq!(Some(MetaValue::Str(String::from("13"))), lookup_ctx.lookup_origin(&track_fp, "__plugin:name_len").unwrap());
+        assert_eq!(None, lookup_ctx.lookup_origin(&track_fp, "__plugin:album_only").unwrap());
+        assert_eq!(None, lookup_ctx.lookup_origin(&track_fp, "__plugin:unknown").unwrap());
+
+        // Like other fields, plugin fields are inherited from the ancestors of items without a value of their own.
+        assert_eq!(Some(MetaValue::Str(String::from("album"))), lookup_ctx.lookup(&track_fp, &LookupOptions::new("__plugin:album_only")).unwrap());
+
+        assert_eq!(vec!["__plugin:album_only", "__plugin:name_len"], media_lib.plugin_field_names());
+    }
+}
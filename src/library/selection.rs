@@ -1,9 +1,9 @@
 use std::path::Path;
 use regex::Regex;
 use std::ffi::OsStr;
-use std::fs::DirEntry;
 
 use helpers::normalize;
+use vfs::{Vfs, VfsEntry, StdFs};
 use error::*;
 
 #[derive(Debug, Clone)]
@@ -22,11 +22,22 @@ pub enum Selection {
 
 impl Selection {
     pub fn is_selected_path<P: AsRef<Path>>(&self, abs_item_path: P) -> bool {
+        self.is_selected_path_with(&StdFs, abs_item_path)
+    }
+
+    /// Like `is_selected_path`, but checking the entry on a given file system.
+    pub fn is_selected_path_with<V: Vfs + ?Sized, P: AsRef<Path>>(&self, vfs: &V, abs_item_path: P) -> bool {
         let abs_item_path = normalize(abs_item_path.as_ref());
 
-        if !abs_item_path.exists() {
-            return false
+        match vfs.metadata(&abs_item_path) {
+            Ok(md) => self.is_selected_entry(&abs_item_path, md.is_dir),
+            Err(_) => false,
         }
+    }
+
+    /// Like `is_selected_path`, but for an entry that is known to exist without checking the file system, e.g. an entry in an archive.
+    pub fn is_selected_entry<P: AsRef<Path>>(&self, abs_item_path: P, is_dir: bool) -> bool {
+        let abs_item_path = abs_item_path.as_ref();
 
         match *self {
             Selection::Ext(ref e_ext) => abs_item_path.extension() == Some(&OsStr::new(e_ext)),
@@ -36,36 +47,37 @@ impl Selection {
                     .and_then(|f| f.to_str())
                     .map_or(false, |f| r_exp.is_match(f))
             },
-            Selection::IsFile => abs_item_path.is_file(),
-            Selection::IsDir => abs_item_path.is_dir(),
-            Selection::And(ref sel_a, ref sel_b) => sel_a.is_selected_path(&abs_item_path)
-                && sel_b.is_selected_path(&abs_item_path),
-            Selection::Or(ref sel_a, ref sel_b) => sel_a.is_selected_path(&abs_item_path)
-                || sel_b.is_selected_path(&abs_item_path),
-            Selection::Xor(ref sel_a, ref sel_b) => sel_a.is_selected_path(&abs_item_path)
-                ^ sel_b.is_selected_path(&abs_item_path),
-            Selection::Not(ref sel) => !sel.is_selected_path(&abs_item_path),
+            Selection::IsFile => !is_dir,
+            Selection::IsDir => is_dir,
+            Selection::And(ref sel_a, ref sel_b) => sel_a.is_selected_entry(abs_item_path, is_dir)
+                && sel_b.is_selected_entry(abs_item_path, is_dir),
+            Selection::Or(ref sel_a, ref sel_b) => sel_a.is_selected_entry(abs_item_path, is_dir)
+                || sel_b.is_selected_entry(abs_item_path, is_dir),
+            Selection::Xor(ref sel_a, ref sel_b) => sel_a.is_selected_entry(abs_item_path, is_dir)
+                ^ sel_b.is_selected_entry(abs_item_path, is_dir),
+            Selection::Not(ref sel) => !sel.is_selected_entry(abs_item_path, is_dir),
             Selection::True => true,
             Selection::False => false,
         }
     }
 
-    pub fn selected_entries_in_dir<P: AsRef<Path>>(&self, abs_dir_path: P) -> Result<Vec<DirEntry>> {
-        let abs_dir_path = normalize(abs_dir_path.as_ref());
+    /// Returns the selected entries of a directory, sorted by path, so that the order does not depend on the file system.
+    pub fn selected_entries_in_dir<P: AsRef<Path>>(&self, abs_dir_path: P) -> Result<Vec<VfsEntry>> {
+        self.selected_entries_in_dir_with(&StdFs, abs_dir_path)
+    }
 
-        let mut sel_entries: Vec<DirEntry> = vec![];
+    /// Like `selected_entries_in_dir`, but listing the directory on a given file system.
+    pub fn selected_entries_in_dir_with<V: Vfs + ?Sized, P: AsRef<Path>>(&self, vfs: &V, abs_dir_path: P) -> Result<Vec<VfsEntry>> {
+        let abs_dir_path = normalize(abs_dir_path.as_ref());
 
-        let dir_entries = abs_dir_path.read_dir()?;
+        let mut sel_entries: Vec<VfsEntry> = {
+            vfs.read_dir(&abs_dir_path)?
+                .into_iter()
+                .filter(|e| self.is_selected_entry(&e.path, e.is_dir))
+                .collect()
+        };
 
-        for dir_entry in dir_entries {
-            if let Ok(dir_entry) = dir_entry {
-                if self.is_selected_path(dir_entry.path()) {
-                    sel_entries.push(dir_entry);
-                }
-            } else {
-                // TODO: Figure out what to do here.
-            }
-        }
+        sel_entries.sort_by(|a, b| a.path.cmp(&b.path));
 
         Ok(sel_entries)
     }
@@ -81,6 +93,8 @@ mod tests {
     use tempdir::TempDir;
     use regex::Regex;
 
+    use vfs::MemoryFs;
+
     use super::Selection;
 
     #[test]
@@ -159,11 +173,42 @@ mod tests {
 
         // Run the tests.
         for (selection, true_indices) in selections_and_true_indices {
-            for (index, &(ref abs_path, _)) in paths_and_flags.iter().enumerate() {
+            for (index, &(ref abs_path, is_dir)) in paths_and_flags.iter().enumerate() {
                 let expected = true_indices.contains(&index);
                 let produced = selection.is_selected_path(&abs_path);
                 assert_eq!(expected, produced);
+
+                // Entries that are known to exist are selected the same way.
+                assert_eq!(expected, selection.is_selected_entry(&abs_path, is_dir));
             }
         }
+
+        // Entries come sorted by path, whatever order the file system lists them in.
+        let mut expected: Vec<PathBuf> = paths_and_flags.iter().map(|&(ref path, _)| path.clone()).collect();
+        expected.sort();
+        let produced: Vec<PathBuf> = Selection::True.selected_entries_in_dir(tp).unwrap().into_iter().map(|e| e.path).collect();
+        assert_eq!(expected, produced);
+    }
+
+    #[test]
+    fn test_selected_entries_in_dir_with() {
+        let mut memory_fs = MemoryFs::new();
+        memory_fs
+            .add_file("/lib/b.flac", "")
+            .add_file("/lib/a.flac", "")
+            .add_file("/lib/cover.jpg", "")
+            .add_dir("/lib/sub.flac")
+            .add_file("/lib/sub.flac/c.flac", "");
+
+        let selection = Selection::And(Box::new(Selection::IsFile), Box::new(Selection::Ext("flac".to_string())));
+
+        assert!(selection.is_selected_path_with(&memory_fs, "/lib/a.flac"));
+        assert!(!selection.is_selected_path_with(&memory_fs, "/lib/sub.flac"));
+        assert!(!selection.is_selected_path_with(&memory_fs, "/lib/missing.flac"));
+
+        let produced: Vec<PathBuf> = selection.selected_entries_in_dir_with(&memory_fs, "/lib").unwrap().into_iter().map(|e| e.path).collect();
+        assert_eq!(vec![PathBuf::from("/lib/a.flac"), PathBuf::from("/lib/b.flac")], produced);
+
+        assert!(selection.selected_entries_in_dir_with(&memory_fs, "/lib/a.flac").is_err());
     }
 }
@@ -1,26 +1,244 @@
-use std::path::Path;
-use std::time::SystemTime;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::sync::{Arc, OnceLock};
+use std::process;
 
-#[derive(Copy, Clone)]
+use vfs::{Vfs, StdFs};
+use error::*;
+
+/// How to order two items that compare as equal under the primary sort order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TieBreaker {
+    /// Fall back to comparing item file names.
+    Name,
+    /// Leave tied items as equal; their relative order is then unspecified.
+    Equal,
+}
+
+/// Whether directories and files are kept apart when sorting, e.g. to list all discs of an album before its loose tracks.
+/// Grouping is applied first, and the sort order then orders the items within each group.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GroupOrder {
+    DirsFirst,
+    FilesFirst,
+    Mixed,
+}
+
+impl GroupOrder {
+    pub fn group_cmp(&self, is_dir_a: bool, is_dir_b: bool) -> Ordering {
+        match *self {
+            GroupOrder::DirsFirst => is_dir_b.cmp(&is_dir_a),
+            GroupOrder::FilesFirst => is_dir_a.cmp(&is_dir_b),
+            GroupOrder::Mixed => Ordering::Equal,
+        }
+    }
+
+    /// Sorts paths along with whether each is a directory, by group and then by the sort order.
+    pub fn sort_paths(&self, sort_order: &SortOrder, paths: &mut Vec<(PathBuf, bool)>) {
+        self.sort_paths_with(&StdFs, sort_order, paths)
+    }
+
+    /// Like `sort_paths`, but reading modification times from a given file system.
+    pub fn sort_paths_with<V: Vfs + ?Sized>(&self, vfs: &V, sort_order: &SortOrder, paths: &mut Vec<(PathBuf, bool)>) {
+        paths.sort_unstable_by(|&(ref path_a, is_dir_a), &(ref path_b, is_dir_b)| {
+            self.group_cmp(is_dir_a, is_dir_b).then_with(|| sort_order.path_sort_cmp_with(vfs, path_a, path_b))
+        });
+    }
+}
+
+/// A locale to sort names by, e.g. "ja" or "de-AT".
+/// Collation is only available when built with the 'collation' feature.
+#[derive(Clone)]
+pub struct LocaleSpec {
+    tag: String,
+    ignore_case: bool,
+    collator: Arc<collation::Collator>,
+}
+
+impl LocaleSpec {
+    pub fn new<S: Into<String>>(tag: S, ignore_case: bool) -> Result<Self> {
+        let tag = tag.into();
+        let collator = collation::Collator::new(&tag, ignore_case)?;
+
+        Ok(LocaleSpec { tag, ignore_case, collator: Arc::new(collator) })
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn ignore_case(&self) -> bool {
+        self.ignore_case
+    }
+
+    /// Names that collate as equal (e.g. differing only in case, when ignoring case) fall back to byte order, so that sorting stays stable.
+    fn compare(&self, name_a: &str, name_b: &str) -> Ordering {
+        self.collator.compare(name_a, name_b).then_with(|| name_a.cmp(name_b))
+    }
+}
+
+impl PartialEq for LocaleSpec {
+    fn eq(&self, other: &Self) -> bool {
+        self.tag == other.tag && self.ignore_case == other.ignore_case
+    }
+}
+
+impl Eq for LocaleSpec {}
+
+impl Debug for LocaleSpec {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("LocaleSpec").field("tag", &self.tag).field("ignore_case", &self.ignore_case).finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum SortOrder {
     Name,
-    ModTime,
+    /// Like `Name`, but ignoring case; names differing only in case fall back to byte order.
+    NameNoCase,
+    /// Sorts names as expected by readers of a locale, e.g. accented letters next to their base letters.
+    NameLocale(LocaleSpec),
+    ModTime(TieBreaker),
+    /// Shuffles names, e.g. for playlists or sampling; the same seed always gives the same order.
+    /// Without a seed, one is picked once per process, so that listings within a single run still agree.
+    Random(Option<u64>),
 }
 
 impl SortOrder {
     pub fn path_sort_cmp<P: AsRef<Path>>(&self, abs_item_path_a: P, abs_item_path_b: P) -> Ordering {
+        self.path_sort_cmp_with(&StdFs, abs_item_path_a, abs_item_path_b)
+    }
+
+    /// Like `path_sort_cmp`, but reading modification times from a given file system.
+    pub fn path_sort_cmp_with<V: Vfs + ?Sized, P: AsRef<Path>>(&self, vfs: &V, abs_item_path_a: P, abs_item_path_b: P) -> Ordering {
         let abs_item_path_a = abs_item_path_a.as_ref();
         let abs_item_path_b = abs_item_path_b.as_ref();
 
         match *self {
-            SortOrder::Name => abs_item_path_a.file_name().cmp(&abs_item_path_b.file_name()),
-            SortOrder::ModTime => SortOrder::get_mtime(abs_item_path_a).cmp(&SortOrder::get_mtime(abs_item_path_b)),
+            SortOrder::Name => SortOrder::name_cmp(abs_item_path_a, abs_item_path_b),
+            SortOrder::NameNoCase => {
+                let (name_a, name_b) = SortOrder::names(abs_item_path_a, abs_item_path_b);
+                name_a.to_lowercase().cmp(&name_b.to_lowercase()).then_with(|| name_a.cmp(&name_b))
+            },
+            SortOrder::NameLocale(ref locale_spec) => {
+                let (name_a, name_b) = SortOrder::names(abs_item_path_a, abs_item_path_b);
+                locale_spec.compare(&name_a, &name_b)
+            },
+            SortOrder::ModTime(tie_breaker) => {
+                let primary = SortOrder::get_mtime_with(vfs, abs_item_path_a).cmp(&SortOrder::get_mtime_with(vfs, abs_item_path_b));
+
+                // Files copied in bulk (e.g. with cp or rsync) often share mtimes, so ties are common.
+                match (primary, tie_breaker) {
+                    (Ordering::Equal, TieBreaker::Name) => SortOrder::name_cmp(abs_item_path_a, abs_item_path_b),
+                    _ => primary,
+                }
+            },
+            SortOrder::Random(opt_seed) => {
+                let seed = opt_seed.unwrap_or_else(SortOrder::process_seed);
+
+                SortOrder::shuffle_key(seed, abs_item_path_a).cmp(&SortOrder::shuffle_key(seed, abs_item_path_b))
+                    .then_with(|| SortOrder::name_cmp(abs_item_path_a, abs_item_path_b))
+            },
         }
     }
 
+    /// Hashes a file name together with a seed, so that sorting by the result shuffles names.
+    /// This uses FNV-1a followed by the SplitMix64 finalizer rather than the standard hasher, whose output may change between Rust releases.
+    fn shuffle_key(seed: u64, abs_item_path: &Path) -> u64 {
+        let name = abs_item_path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+        let mut h: u64 = 0xCBF2_9CE4_8422_2325;
+        for b in name.bytes() {
+            h ^= b as u64;
+            h = h.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+
+        let mut z = h ^ seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn process_seed() -> u64 {
+        static PROCESS_SEED: OnceLock<u64> = OnceLock::new();
+
+        *PROCESS_SEED.get_or_init(|| {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default();
+            nanos ^ ((process::id() as u64) << 32)
+        })
+    }
+
+    fn name_cmp(abs_item_path_a: &Path, abs_item_path_b: &Path) -> Ordering {
+        abs_item_path_a.file_name().cmp(&abs_item_path_b.file_name())
+    }
+
+    /// Returns the file names of two paths as text; names that are not valid UTF-8 are converted lossily.
+    fn names(abs_item_path_a: &Path, abs_item_path_b: &Path) -> (String, String) {
+        let name = |p: &Path| p.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+        (name(abs_item_path_a), name(abs_item_path_b))
+    }
+
+    #[cfg(test)]
     fn get_mtime<P: AsRef<Path>>(abs_path: P) -> Option<SystemTime> {
-        abs_path.as_ref().metadata().and_then(|m| m.modified()).ok()
+        SortOrder::get_mtime_with(&StdFs, abs_path)
+    }
+
+    fn get_mtime_with<V: Vfs + ?Sized, P: AsRef<Path>>(vfs: &V, abs_path: P) -> Option<SystemTime> {
+        vfs.metadata(abs_path.as_ref()).ok().and_then(|md| md.modified)
+    }
+}
+
+#[cfg(feature = "collation")]
+mod collation {
+    use std::cmp::Ordering;
+
+    use icu_collator::{Collator as IcuCollator, CollatorOptions, Strength};
+    use icu_locid::Locale;
+
+    use error::*;
+
+    pub struct Collator(IcuCollator);
+
+    impl Collator {
+        pub fn new(tag: &str, ignore_case: bool) -> Result<Self> {
+            let locale: Locale = tag.parse().map_err(|_| ErrorKind::InvalidLocale(tag.to_string()))?;
+
+            let mut options = CollatorOptions::new();
+            if ignore_case {
+                // Secondary strength still tells accents apart, but not case.
+                options.strength = Some(Strength::Secondary);
+            }
+
+            let collator = IcuCollator::try_new(&(&locale).into(), options).map_err(|_| ErrorKind::InvalidLocale(tag.to_string()))?;
+
+            Ok(Collator(collator))
+        }
+
+        pub fn compare(&self, a: &str, b: &str) -> Ordering {
+            self.0.compare(a, b)
+        }
+    }
+}
+
+#[cfg(not(feature = "collation"))]
+mod collation {
+    use std::cmp::Ordering;
+
+    use error::*;
+
+    pub struct Collator;
+
+    impl Collator {
+        pub fn new(_tag: &str, _ignore_case: bool) -> Result<Self> {
+            bail!(ErrorKind::CollationNotSupported)
+        }
+
+        pub fn compare(&self, a: &str, b: &str) -> Ordering {
+            a.cmp(b)
+        }
     }
 }
 
@@ -29,9 +247,13 @@ mod tests {
     use tempdir::TempDir;
     use std::fs::{File, DirBuilder};
     use std::thread::sleep;
-    use std::time::Duration;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use std::cmp::Ordering;
+    use std::path::PathBuf;
 
-    use super::SortOrder;
+    use vfs::MemoryFs;
+
+    use super::{SortOrder, TieBreaker, LocaleSpec, GroupOrder};
 
     #[test]
     fn test_path_sort_cmp() {
@@ -54,7 +276,7 @@ mod tests {
         }
 
         // Test sorting by mod time.
-        let sort_order = SortOrder::ModTime;
+        let sort_order = SortOrder::ModTime(TieBreaker::Name);
 
         for (o_i, o_val) in fps.iter().enumerate() {
             for (i_i, i_val) in fps.iter().enumerate() {
@@ -72,6 +294,162 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_path_sort_cmp_no_case() {
+        let sort_order = SortOrder::NameNoCase;
+
+        assert_eq!(Ordering::Less, sort_order.path_sort_cmp("/a/apple", "/a/Banana"));
+        assert_eq!(Ordering::Greater, sort_order.path_sort_cmp("/a/banana", "/a/Apple"));
+
+        // Names differing only in case still have a fixed order.
+        assert_eq!(Ordering::Less, sort_order.path_sort_cmp("/a/Apple", "/a/apple"));
+        assert_eq!(Ordering::Equal, sort_order.path_sort_cmp("/a/apple", "/a/apple"));
+    }
+
+    #[cfg(feature = "collation")]
+    #[test]
+    fn test_path_sort_cmp_locale() {
+        let sort_order = SortOrder::NameLocale(LocaleSpec::new("de", false).unwrap());
+
+        // Accented letters sort next to their base letters, not after 'z'.
+        assert_eq!(Ordering::Less, sort_order.path_sort_cmp("/a/\u{c4}pfel", "/a/Birnen"));
+        assert_eq!(Ordering::Greater, SortOrder::Name.path_sort_cmp("/a/\u{c4}pfel", "/a/Birnen"));
+
+        let sort_order = SortOrder::NameLocale(LocaleSpec::new("de", true).unwrap());
+        assert_eq!(Ordering::Less, sort_order.path_sort_cmp("/a/apfel", "/a/Birnen"));
+        assert_eq!(Ordering::Less, sort_order.path_sort_cmp("/a/Apfel", "/a/apfel"));
+
+        assert!(LocaleSpec::new("not a locale!", false).is_err());
+    }
+
+    #[cfg(not(feature = "collation"))]
+    #[test]
+    fn test_locale_spec_not_supported() {
+        assert!(LocaleSpec::new("de", false).is_err());
+    }
+
+    #[test]
+    fn test_path_sort_cmp_tie_breaker() {
+        // Create temp directory.
+        let temp = TempDir::new("").unwrap();
+        let tp = temp.path();
+
+        let fps = vec![
+            tp.join("file_b"),
+            tp.join("file_a"),
+            tp.join("file_c"),
+        ];
+
+        // Give all of the files the same mod time.
+        let mtime = SystemTime::now();
+
+        for fp in &fps {
+            let f = File::create(fp).expect(&format!(r#"Unable to create file "{:?}""#, fp));
+            f.set_modified(mtime).expect(&format!(r#"Unable to set mod time of file "{:?}""#, fp));
+        }
+
+        let sort_order = SortOrder::ModTime(TieBreaker::Name);
+
+        for o_val in fps.iter() {
+            for i_val in fps.iter() {
+                assert_eq!(o_val.file_name().cmp(&i_val.file_name()), sort_order.path_sort_cmp(o_val, i_val));
+            }
+        }
+
+        let sort_order = SortOrder::ModTime(TieBreaker::Equal);
+
+        for o_val in fps.iter() {
+            for i_val in fps.iter() {
+                assert_eq!(Ordering::Equal, sort_order.path_sort_cmp(o_val, i_val));
+            }
+        }
+    }
+
+    #[test]
+    fn test_path_sort_cmp_random() {
+        let names: Vec<PathBuf> = (0..20).map(|i| PathBuf::from(format!("/a/TRACK_{:02}.flac", i))).collect();
+
+        let shuffled = |sort_order: &SortOrder| {
+            let mut paths = names.clone();
+            paths.sort_by(|a, b| sort_order.path_sort_cmp(a, b));
+            paths
+        };
+
+        // The same seed gives the same order, no matter the order the paths started in.
+        let seeded = shuffled(&SortOrder::Random(Some(7)));
+        let mut reversed = names.clone();
+        reversed.reverse();
+        reversed.sort_by(|a, b| SortOrder::Random(Some(7)).path_sort_cmp(a, b));
+        assert_eq!(seeded, reversed);
+
+        // Every path is still there, but not in name order, and a different seed gives a different order.
+        let mut resorted = seeded.clone();
+        resorted.sort();
+        assert_eq!(names, resorted);
+        assert_ne!(names, seeded);
+        assert_ne!(seeded, shuffled(&SortOrder::Random(Some(8))));
+
+        // Without a seed, the order is still the same within a run.
+        assert_eq!(shuffled(&SortOrder::Random(None)), shuffled(&SortOrder::Random(None)));
+        assert_eq!(Ordering::Equal, SortOrder::Random(None).path_sort_cmp("/a/x", "/b/x"));
+    }
+
+    #[test]
+    fn test_path_sort_cmp_with() {
+        let mut memory_fs = MemoryFs::new();
+        memory_fs
+            .add_file("/a/new", "")
+            .add_file("/a/old", "")
+            .add_file("/a/same", "")
+            .add_file("/a/unknown", "")
+            .set_modified("/a/new", UNIX_EPOCH + Duration::from_secs(20))
+            .set_modified("/a/old", UNIX_EPOCH + Duration::from_secs(10))
+            .set_modified("/a/same", UNIX_EPOCH + Duration::from_secs(20));
+
+        let sort_order = SortOrder::ModTime(TieBreaker::Name);
+        assert_eq!(Ordering::Less, sort_order.path_sort_cmp_with(&memory_fs, "/a/old", "/a/new"));
+        assert_eq!(Ordering::Less, sort_order.path_sort_cmp_with(&memory_fs, "/a/new", "/a/same"));
+
+        // Items without a modification time sort first.
+        assert_eq!(Ordering::Less, sort_order.path_sort_cmp_with(&memory_fs, "/a/unknown", "/a/old"));
+
+        let mut paths = vec![
+            (PathBuf::from("/a/same"), false),
+            (PathBuf::from("/a/new"), false),
+            (PathBuf::from("/a/unknown"), false),
+            (PathBuf::from("/a/old"), false),
+        ];
+        GroupOrder::Mixed.sort_paths_with(&memory_fs, &sort_order, &mut paths);
+        assert_eq!(
+            vec![PathBuf::from("/a/unknown"), PathBuf::from("/a/old"), PathBuf::from("/a/new"), PathBuf::from("/a/same")],
+            paths.into_iter().map(|(p, _)| p).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_sort_paths() {
+        let paths = vec![
+            (PathBuf::from("/a/TRACK_02.flac"), false),
+            (PathBuf::from("/a/DISC_02"), true),
+            (PathBuf::from("/a/TRACK_01.flac"), false),
+            (PathBuf::from("/a/DISC_01"), true),
+        ];
+
+        let sorted_names = |group_order: GroupOrder| {
+            let mut paths = paths.clone();
+            group_order.sort_paths(&SortOrder::Name, &mut paths);
+            paths.into_iter().map(|(p, _)| p.file_name().unwrap().to_string_lossy().into_owned()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec!["DISC_01", "DISC_02", "TRACK_01.flac", "TRACK_02.flac"], sorted_names(GroupOrder::DirsFirst));
+        assert_eq!(vec!["TRACK_01.flac", "TRACK_02.flac", "DISC_01", "DISC_02"], sorted_names(GroupOrder::FilesFirst));
+        assert_eq!(vec!["DISC_01", "DISC_02", "TRACK_01.flac", "TRACK_02.flac"], sorted_names(GroupOrder::Mixed));
+
+        let mut paths = vec![(PathBuf::from("/a/b.flac"), false), (PathBuf::from("/a/C"), true), (PathBuf::from("/a/a.flac"), false)];
+        GroupOrder::Mixed.sort_paths(&SortOrder::Name, &mut paths);
+        assert_eq!(vec![PathBuf::from("/a/C"), PathBuf::from("/a/a.flac"), PathBuf::from("/a/b.flac")], paths.into_iter().map(|(p, _)| p).collect::<Vec<_>>());
+    }
+
     #[test]
     fn test_get_mtime() {
         // Create temp directory.
@@ -0,0 +1,136 @@
+// Structural fields are virtual fields computed from where an item sits in the directory tree, instead of from any meta file.
+// They make it possible to build e.g. "Disc 2, Track 5" without writing disc and track numbers by hand.
+// Positions are one-based, and follow the selection and sort order of the library.
+
+use std::path::{Path, PathBuf};
+
+use library::Library;
+use metadata::MetaValue;
+use helpers::normalize;
+use error::*;
+
+/// The position of an item among its siblings.
+pub const INDEX_KEY: &str = "__index";
+
+/// The position of the parent directory of an item among its sibling directories.
+pub const DISC_INDEX_KEY: &str = "__disc_index";
+
+/// The top-level directory of the collection that contains an item, e.g. `ALBUM_03` for `ALBUM_03/DISC_02/TRACK_01`.
+pub const ALBUM_DIR_KEY: &str = "__album_dir";
+
+/// How many levels below the root of its collection an item is, where the root itself is at depth zero.
+pub const DEPTH_KEY: &str = "__depth";
+
+pub const STRUCTURAL_KEYS: &[&str] = &[
+    INDEX_KEY,
+    DISC_INDEX_KEY,
+    ALBUM_DIR_KEY,
+    DEPTH_KEY,
+];
+
+pub fn is_structural_key<S: AsRef<str>>(key: S) -> bool {
+    let key = key.as_ref();
+
+    STRUCTURAL_KEYS.iter().any(|k| *k == key)
+}
+
+/// Finds the one-based position of an item among the entries of its parent directory, considering only entries that pass a filter.
+fn position_in_parent<F>(media_lib: &Library, abs_item_path: &Path, filter: F) -> Result<Option<usize>>
+where F: Fn(&Path) -> bool,
+{
+    let parent = match abs_item_path.parent() {
+        Some(parent) => parent,
+        None => return Ok(None),
+    };
+
+    let siblings = media_lib.sorted_children(parent)?;
+
+    Ok(siblings.iter().filter(|p| filter(p)).position(|p| p == abs_item_path).map(|i| i + 1))
+}
+
+/// Computes a structural field for an item.
+/// Returns `None` if the field is not structural, or if it has no value for this item (e.g. the collection root has no album directory).
+pub fn structural_field<P, S>(media_lib: &Library, abs_item_path: P, field_name: S) -> Result<Option<MetaValue>>
+where P: AsRef<Path>,
+      S: AsRef<str>,
+{
+    let abs_item_path = normalize(abs_item_path.as_ref());
+    let field_name = field_name.as_ref();
+
+    if !is_structural_key(field_name) {
+        return Ok(None);
+    }
+
+    let collection_root = media_lib.collection_root(&abs_item_path)?;
+
+    // The collection root is not an item of its own collection, so it has no position.
+    if abs_item_path == collection_root {
+        return Ok(match field_name {
+            DEPTH_KEY => Some(MetaValue::Str(String::from("0"))),
+            _ => None,
+        });
+    }
+
+    let relative_path = abs_item_path.strip_prefix(&collection_root).chain_err(|| ErrorKind::InvalidSubPath(abs_item_path.clone(), collection_root.clone()))?;
+    let depth = relative_path.components().count();
+
+    let found = match field_name {
+        INDEX_KEY => {
+            position_in_parent(media_lib, &abs_item_path, |_| true)?.map(|i| i.to_string())
+        },
+        DISC_INDEX_KEY => {
+            // Items directly inside the collection root are not on a disc.
+            match abs_item_path.parent() {
+                Some(parent) if depth >= 2 => position_in_parent(media_lib, parent, Path::is_dir)?.map(|i| i.to_string()),
+                _ => None,
+            }
+        },
+        ALBUM_DIR_KEY => {
+            relative_path.components().next().map(|c| {
+                let album_dir: PathBuf = collection_root.join(c.as_os_str());
+                album_dir.to_string_lossy().into_owned()
+            })
+        },
+        DEPTH_KEY => Some(depth.to_string()),
+        _ => None,
+    };
+
+    Ok(found.map(MetaValue::Str))
+}
+
+#[cfg(test)]
+mod tests {
+    use metadata::MetaValue;
+    use fixtures::default_setup;
+
+    use super::{structural_field, INDEX_KEY, DISC_INDEX_KEY, ALBUM_DIR_KEY, DEPTH_KEY};
+
+    #[test]
+    fn test_structural_field() {
+        let (temp_media_root, media_lib) = default_setup("test_structural_field");
+        let tp = temp_media_root.path();
+
+        let str_val = |s: &str| Some(MetaValue::Str(s.to_string()));
+        let field = |p: &::std::path::Path, f: &str| structural_field(&media_lib, p, f).expect("Unable to get structural field");
+
+        let track_fp = tp.join("ALBUM_03").join("DISC_02").join("TRACK_01");
+        assert_eq!(str_val("2"), field(&track_fp, DISC_INDEX_KEY));
+        assert_eq!(str_val(&tp.join("ALBUM_03").to_string_lossy()), field(&track_fp, ALBUM_DIR_KEY));
+        assert_eq!(str_val("3"), field(&track_fp, DEPTH_KEY));
+
+        let album_fp = tp.join("ALBUM_03");
+        assert_eq!(None, field(&album_fp, DISC_INDEX_KEY));
+        assert_eq!(str_val(&album_fp.to_string_lossy()), field(&album_fp, ALBUM_DIR_KEY));
+        assert_eq!(str_val("1"), field(&album_fp, DEPTH_KEY));
+
+        assert_eq!(str_val("0"), field(tp, DEPTH_KEY));
+        assert_eq!(None, field(tp, ALBUM_DIR_KEY));
+        assert_eq!(None, field(tp, INDEX_KEY));
+
+        assert_eq!(str_val("3"), field(&tp.join("ALBUM_03").join("DISC_02").join("TRACK_03.flac"), INDEX_KEY));
+        assert_eq!(str_val("3"), field(&album_fp, INDEX_KEY));
+
+        assert_eq!(None, field(&track_fp, "__not_structural"));
+        assert_eq!(None, field(&track_fp, "title"));
+    }
+}
@@ -0,0 +1,404 @@
+// Expressions compute a value for an item from metadata, e.g. to fill in album-level fields from the metadata of the tracks.
+// An expression is one of:
+// - a field path, looked up on the item with inheritance (e.g. `artist` or `credits.composer`)
+// - `children`, the child items themselves
+// - `children.` followed by a field path, collected from the children of the item (e.g. `children.duration`)
+// - a quoted string, with `\"` and `\\` as escapes (e.g. `", "`)
+// - a function applied to other expressions (e.g. `sum(children.duration)`), one of `sum`, `count`, `join`, `first` and `sort_name`
+
+use std::path::Path;
+
+use metadata::MetaValue;
+use lookup::LookupContext;
+use lookup::options::{LookupOptions, LookupOptionsBuilder, LookupDirection};
+use error::*;
+
+const CHILDREN_NAME: &str = "children";
+const CHILDREN_PREFIX: &str = "children.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    /// Adds up all numbers.
+    Sum,
+    /// Counts all values.
+    Count,
+    /// Joins all values into a single string, using a separator.
+    Join,
+    /// Takes the first value.
+    First,
+    /// Derives the sort names of all values, e.g. `Beatles, The` from `The Beatles`, using the sort name rules of the lookup context.
+    SortName,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "sum" => Some(Function::Sum),
+            "count" => Some(Function::Count),
+            "join" => Some(Function::Join),
+            "first" => Some(Function::First),
+            "sort_name" => Some(Function::SortName),
+            _ => None,
+        }
+    }
+
+    fn arity(&self) -> usize {
+        match *self {
+            Function::Sum | Function::Count | Function::First | Function::SortName => 1,
+            Function::Join => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Field(String),
+    Children,
+    ChildrenField(String),
+    Literal(String),
+    Call(Function, Vec<Expr>),
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Expr> {
+        let mut parser = Parser { chars: s.chars().collect(), pos: 0 };
+
+        let expr = parser.expr().chain_err(|| ErrorKind::InvalidExpression(s.to_string()))?;
+
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            return Err(Error::from(format!("unexpected trailing input at position {}", parser.pos))).chain_err(|| ErrorKind::InvalidExpression(s.to_string()));
+        }
+
+        Ok(expr)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(found) if found == c => {
+                self.pos += 1;
+                Ok(())
+            },
+            _ => bail!("expected '{}' at position {}", c, self.pos),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('"') => self.literal(),
+            Some(_) => {
+                let name = self.name();
+                ensure!(!name.is_empty(), format!("expected an expression at position {}", self.pos));
+
+                self.skip_whitespace();
+
+                if self.peek() == Some('(') {
+                    self.call(&name)
+                }
+                else if name == CHILDREN_NAME {
+                    Ok(Expr::Children)
+                }
+                else if name.starts_with(CHILDREN_PREFIX) {
+                    Ok(Expr::ChildrenField(name[CHILDREN_PREFIX.len()..].to_string()))
+                }
+                else {
+                    Ok(Expr::Field(name))
+                }
+            },
+            None => bail!("unexpected end of input"),
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == '(' || c == ')' || c == ',' || c == '"' {
+                break;
+            }
+
+            name.push(c);
+            self.pos += 1;
+        }
+
+        name
+    }
+
+    fn literal(&mut self) -> Result<Expr> {
+        self.expect('"')?;
+
+        let mut s = String::new();
+
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(Expr::Literal(s));
+                },
+                Some('\\') => {
+                    match self.chars.get(self.pos + 1).cloned() {
+                        Some(c) if c == '"' || c == '\\' => {
+                            s.push(c);
+                            self.pos += 2;
+                        },
+                        _ => bail!("invalid escape at position {}", self.pos),
+                    }
+                },
+                Some(c) => {
+                    s.push(c);
+                    self.pos += 1;
+                },
+                None => bail!("unclosed string"),
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str) -> Result<Expr> {
+        let function = Function::from_name(name).ok_or_else(|| Error::from(format!("unknown function: '{}'", name)))?;
+
+        self.expect('(')?;
+
+        let mut args = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some(')') {
+            self.pos += 1;
+        }
+        else {
+            loop {
+                args.push(self.expr()?);
+
+                self.skip_whitespace();
+                match self.peek() {
+                    Some(',') => { self.pos += 1; },
+                    Some(')') => {
+                        self.pos += 1;
+                        break;
+                    },
+                    _ => bail!("expected ',' or ')' at position {}", self.pos),
+                }
+            }
+        }
+
+        ensure!(args.len() == function.arity(), format!("'{}' takes {} argument(s), found {}", name, function.arity(), args.len()));
+
+        Ok(Expr::Call(function, args))
+    }
+}
+
+/// Flattens nested sequences into a list of scalar values, skipping nils.
+fn flatten(mv: MetaValue, results: &mut Vec<MetaValue>) {
+    match mv {
+        MetaValue::Nil => {},
+        MetaValue::Seq(mvs) => {
+            for mv in mvs {
+                flatten(mv, results);
+            }
+        },
+        mv => { results.push(mv); },
+    }
+}
+
+fn flattened(opt_mv: Option<MetaValue>) -> Vec<MetaValue> {
+    let mut results = vec![];
+
+    if let Some(mv) = opt_mv {
+        flatten(mv, &mut results);
+    }
+
+    results
+}
+
+fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    }
+    else {
+        format!("{}", n)
+    }
+}
+
+fn as_text(mv: &MetaValue) -> Result<String> {
+    match *mv {
+        MetaValue::Str(ref s) => Ok(s.clone()),
+        _ => bail!("expected a string value, found: {:?}", mv),
+    }
+}
+
+/// Evaluates an expression for an item.
+pub fn evaluate(lookup_ctx: &mut LookupContext, abs_item_path: &Path, expr: &Expr) -> Result<Option<MetaValue>> {
+    match *expr {
+        Expr::Literal(ref s) => Ok(Some(MetaValue::Str(s.clone()))),
+        Expr::Field(ref field_path) => lookup_ctx.lookup(abs_item_path, &LookupOptions::new(field_path)),
+        Expr::ChildrenField(ref field_path) => {
+            let options = LookupOptionsBuilder::new(field_path).direction(LookupDirection::Children).create();
+
+            lookup_ctx.lookup(abs_item_path, &options)
+        },
+        Expr::Children => {
+            if !lookup_ctx.media_lib().is_item_dir(abs_item_path) {
+                return Ok(Some(MetaValue::Seq(vec![])));
+            }
+
+            let mut children = vec![];
+
+            for child_path in lookup_ctx.media_lib.children_paths(abs_item_path)? {
+                if !lookup_ctx.is_ignored(&child_path)? {
+                    children.push(MetaValue::Str(child_path.to_string_lossy().into_owned()));
+                }
+            }
+
+            Ok(Some(MetaValue::Seq(children)))
+        },
+        Expr::Call(function, ref args) => {
+            let mut arg_values = vec![];
+
+            for arg in args {
+                arg_values.push(evaluate(lookup_ctx, abs_item_path, arg)?);
+            }
+
+            let mut arg_values = arg_values.into_iter();
+            let values = flattened(arg_values.next().and_then(|v| v));
+
+            match function {
+                Function::Sum => {
+                    let mut total = 0.0;
+
+                    for mv in &values {
+                        let text = as_text(mv)?;
+                        let n: f64 = text.trim().parse().chain_err(|| format!("cannot sum non-numeric value: '{}'", text))?;
+                        total += n;
+                    }
+
+                    Ok(Some(MetaValue::Str(format_number(total))))
+                },
+                Function::Count => Ok(Some(MetaValue::Str(values.len().to_string()))),
+                Function::Join => {
+                    let separator = match arg_values.next().and_then(|v| v) {
+                        Some(ref mv) => as_text(mv)?,
+                        None => String::new(),
+                    };
+
+                    let texts = values.iter().map(as_text).collect::<Result<Vec<_>>>()?;
+
+                    Ok(Some(MetaValue::Str(texts.join(&separator))))
+                },
+                Function::First => Ok(values.into_iter().next()),
+                Function::SortName => {
+                    let mut sort_names = vec![];
+
+                    for mv in &values {
+                        sort_names.push(MetaValue::Str(lookup_ctx.sort_name_rules().sort_name(as_text(mv)?)));
+                    }
+
+                    Ok(match sort_names.len() {
+                        0 => None,
+                        1 => sort_names.pop(),
+                        _ => Some(MetaValue::Seq(sort_names)),
+                    })
+                },
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use metadata::MetaValue;
+    use lookup::LookupContext;
+    use sort_name::SortNameRules;
+    use fixtures::default_setup;
+
+    use super::{Expr, Function};
+
+    #[test]
+    fn test_parse() {
+        let field = |s: &str| Expr::Field(s.to_string());
+        let literal = |s: &str| Expr::Literal(s.to_string());
+
+        let inputs_and_expected = vec![
+            ("artist", field("artist")),
+            ("credits.composer", field("credits.composer")),
+            ("children", Expr::Children),
+            ("count(children)", Expr::Call(Function::Count, vec![Expr::Children])),
+            ("sum( children.duration )", Expr::Call(Function::Sum, vec![Expr::ChildrenField(String::from("duration"))])),
+            (r#"join(artist, ", ")"#, Expr::Call(Function::Join, vec![field("artist"), literal(", ")])),
+            (r#""say \"hi\"""#, literal("say \"hi\"")),
+            ("first(join(children.title, \"/\"))", Expr::Call(Function::First, vec![
+                Expr::Call(Function::Join, vec![Expr::ChildrenField(String::from("title")), literal("/")]),
+            ])),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, Expr::parse(input).unwrap());
+        }
+
+        let invalid_inputs = vec![
+            "",
+            "sum(",
+            "sum(a, b)",
+            "join(a)",
+            "average(a)",
+            "\"unclosed",
+            "count(a) b",
+        ];
+
+        for input in invalid_inputs {
+            assert!(Expr::parse(input).is_err(), "expected error for: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let (temp_media_root, media_lib) = default_setup("test_evaluate");
+        let tp = temp_media_root.path();
+
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        let mut f = File::create(disc_fp.join("item.yml")).unwrap();
+        writeln!(f, "- {{duration: '60', artist: [A, B]}}\n- {{duration: '90.5'}}\n- {{duration: '30', title: bad}}").unwrap();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        let str_val = |s: &str| Some(MetaValue::Str(s.to_string()));
+
+        assert_eq!(str_val("180.5"), lookup_ctx.evaluate(&disc_fp, "sum(children.duration)").unwrap());
+        assert_eq!(str_val("3"), lookup_ctx.evaluate(&disc_fp, "count(children)").unwrap());
+        assert_eq!(str_val("3"), lookup_ctx.evaluate(&disc_fp, "count(children.duration)").unwrap());
+        assert_eq!(str_val("60"), lookup_ctx.evaluate(&disc_fp, "first(children.duration)").unwrap());
+        assert_eq!(str_val("A, B"), lookup_ctx.evaluate(disc_fp.join("TRACK_01.flac"), r#"join(artist, ", ")"#).unwrap());
+        assert_eq!(str_val("self_val"), lookup_ctx.evaluate(&disc_fp, "self_key").unwrap());
+        assert_eq!(None, lookup_ctx.evaluate(&disc_fp, "first(missing)").unwrap());
+        assert_eq!(str_val("0"), lookup_ctx.evaluate(&disc_fp, "sum(missing)").unwrap());
+
+        lookup_ctx.set_sort_name_rules(SortNameRules::default());
+        assert_eq!(str_val("bad"), lookup_ctx.evaluate(&disc_fp, "sort_name(children.title)").unwrap());
+        assert_eq!(str_val("Band, The"), lookup_ctx.evaluate(&disc_fp, r#"sort_name("The Band")"#).unwrap());
+        assert_eq!(None, lookup_ctx.evaluate(&disc_fp, "sort_name(missing)").unwrap());
+
+        assert!(lookup_ctx.evaluate(&disc_fp, "sum(children.title)").is_err());
+        assert!(lookup_ctx.evaluate(&disc_fp, "count(").is_err());
+    }
+}
@@ -0,0 +1,1934 @@
+pub mod trace;
+pub mod options;
+pub mod resolved;
+pub mod expr;
+pub mod query;
+pub mod persist;
+
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::env;
+
+use glob;
+
+use library::{Library, TargetPolicy};
+use helpers::{normalize, FileStamp};
+use metadata::{MetaValue, MetaBlock};
+use metadata::keys::{namespace_fields, is_reserved_key, is_ignored_block, get_field};
+use metadata::subst::substitute;
+use ontology::Ontology;
+use sort_name::SortNameRules;
+use progress::Progress;
+use error::*;
+
+use self::trace::{LookupTrace, TraceDecision};
+use self::options::{LookupOptions, LookupDirection, MergeStrategy};
+use self::resolved::{ResolvedBlock, FieldSource};
+use self::expr::{Expr, evaluate};
+use self::query::{Query, QueryRow, run_query};
+
+/// Blocks are shared, so that handing them out from the cache does not copy any metadata.
+/// Ordered by path, so that anything built by walking a cache, e.g. a persisted cache, comes out the same on every run.
+pub type MetadataCache = BTreeMap<PathBuf, Arc<MetaBlock>>;
+pub type MetaFileCache = BTreeMap<PathBuf, MetadataCache>;
+
+trait LabelExtractor {
+    fn extract_label<S: AsRef<str>>(&self, item_file_name: S) -> String;
+}
+
+pub type LookupResult = Result<Option<MetaValue>>;
+
+const LIB_VAR_PREFIX: &str = "lib.";
+
+/// Counts of how often cached meta files could be reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+pub struct LookupContext<'a> {
+    media_lib: &'a Library,
+    cache: MetaFileCache,
+    stamps: HashMap<PathBuf, FileStamp>,
+    auto_verify: bool,
+    capacity: Option<usize>,
+    last_used: HashMap<PathBuf, u64>,
+    tick: u64,
+    stats: CacheStats,
+    trace: Option<LookupTrace>,
+    ontology: Ontology,
+    sort_name_rules: SortNameRules,
+}
+
+impl<'a> LookupContext<'a> {
+    pub fn new(media_lib: &'a Library) -> LookupContext<'a> {
+        LookupContext {
+            media_lib,
+            cache: btreemap![],
+            stamps: hashmap![],
+            auto_verify: false,
+            capacity: None,
+            last_used: hashmap![],
+            tick: 0,
+            stats: CacheStats::default(),
+            trace: None,
+            ontology: Ontology::new(),
+            sort_name_rules: SortNameRules::new(),
+        }
+    }
+
+    pub fn media_lib(&self) -> &'a Library {
+        self.media_lib
+    }
+
+    /// Creates a context that keeps at most a given number of meta files cached, evicting the least recently used ones first.
+    /// A capacity of zero is treated as one, since the meta file being read always needs to stay cached.
+    pub fn with_capacity(media_lib: &'a Library, capacity: usize) -> LookupContext<'a> {
+        let mut lookup_ctx = LookupContext::new(media_lib);
+        lookup_ctx.set_capacity(Some(capacity));
+        lookup_ctx
+    }
+
+    /// Changes the maximum number of cached meta files, evicting entries if needed.
+    pub fn set_capacity(&mut self, opt_capacity: Option<usize>) {
+        self.capacity = opt_capacity.map(|c| c.max(1));
+        self.evict_to_capacity();
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Sets the hierarchies of terms that queries consult, e.g. so that a parent genre matches items tagged with its subgenres.
+    pub fn set_ontology(&mut self, ontology: Ontology) {
+        self.ontology = ontology;
+    }
+
+    pub fn ontology(&self) -> &Ontology {
+        &self.ontology
+    }
+
+    /// Sets how sort fields that are not set are derived, e.g. `artist_sort` from `artist`.
+    pub fn set_sort_name_rules(&mut self, sort_name_rules: SortNameRules) {
+        self.sort_name_rules = sort_name_rules;
+    }
+
+    pub fn sort_name_rules(&self) -> &SortNameRules {
+        &self.sort_name_rules
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    fn touch(&mut self, meta_fp: &Path) {
+        self.tick += 1;
+        self.last_used.insert(meta_fp.to_path_buf(), self.tick);
+    }
+
+    /// Evicts the least recently used meta files until the cache fits its capacity.
+    fn evict_to_capacity(&mut self) {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => return,
+        };
+
+        while self.cache.len() > capacity {
+            let opt_oldest = self.last_used.iter().min_by_key(|&(_, tick)| *tick).map(|(meta_fp, _)| meta_fp.clone());
+
+            match opt_oldest {
+                Some(oldest) => {
+                    self.cache.remove(&oldest);
+                    self.last_used.remove(&oldest);
+                    self.stamps.remove(&oldest);
+                    self.stats.evictions += 1;
+                },
+                None => break,
+            }
+        }
+    }
+
+    /// Returns true if a cached meta file has changed (or been removed) since it was cached.
+    fn is_stale(&self, meta_fp: &Path) -> bool {
+        match self.stamps.get(meta_fp) {
+            Some(stamp) => FileStamp::read_with(self.media_lib.vfs(), meta_fp).as_ref() != Some(stamp),
+            None => true,
+        }
+    }
+
+    /// Compares every cached meta file against the file on disk, by modification time and size.
+    /// Returns the paths of the meta files that have changed or been removed since they were cached, sorted.
+    pub fn verify(&self) -> Vec<PathBuf> {
+        let mut stale: Vec<PathBuf> = self.cache.keys().filter(|meta_fp| self.is_stale(meta_fp)).cloned().collect();
+        stale.sort();
+        stale
+    }
+
+    /// Sets whether cached meta files are checked against the disk whenever they are used, and re-read if they have changed.
+    /// This is disabled by default, since it costs a file system call per access.
+    pub fn set_auto_verify(&mut self, auto_verify: bool) {
+        self.auto_verify = auto_verify;
+    }
+
+    pub fn auto_verify(&self) -> bool {
+        self.auto_verify
+    }
+
+    /// Starts recording every meta file consulted by subsequent lookups.
+    pub fn enable_trace(&mut self) {
+        if self.trace.is_none() {
+            self.trace = Some(LookupTrace::new());
+        }
+    }
+
+    pub fn disable_trace(&mut self) {
+        self.trace = None;
+    }
+
+    pub fn trace(&self) -> Option<&LookupTrace> {
+        self.trace.as_ref()
+    }
+
+    /// Returns the steps recorded so far, and starts a fresh trace if tracing is enabled.
+    pub fn take_trace(&mut self) -> Option<LookupTrace> {
+        self.trace.as_mut().map(|t| {
+            let taken = t.clone();
+            t.clear();
+            taken
+        })
+    }
+
+    pub fn lookup_origin<P: AsRef<Path>, S: AsRef<str>>(
+        &mut self,
+        abs_item_path: P,
+        field_name: S,
+        ) -> LookupResult
+    {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+        let field_name = field_name.as_ref();
+
+        // Virtual fields are provided by the library, and do not come from meta files.
+        if let Some(val) = self.virtual_field(&abs_item_path, field_name)? {
+            return Ok(Some(val));
+        }
+
+        // Get meta file paths from item path.
+        let meta_file_paths = self.media_lib.meta_fps_from_item_fp(&abs_item_path)?;
+
+        self.lookup_meta_files(&abs_item_path, &meta_file_paths, field_name, true)
+    }
+
+    fn virtual_field(&self, abs_item_path: &Path, field_name: &str) -> LookupResult {
+        if is_reserved_key(field_name) {
+            self.media_lib.virtual_field(abs_item_path, field_name)
+        }
+        else {
+            Ok(None)
+        }
+    }
+
+    /// Looks up a field in the given meta files for an item, in order, before optionally falling back to any other meta sources.
+    fn lookup_meta_files(&mut self, abs_item_path: &Path, meta_file_paths: &[PathBuf], field_name: &str, fallback_sources: bool) -> LookupResult {
+        for meta_file_path in meta_file_paths {
+            // Ensure meta file path is cached.
+            self.cache_meta_file(meta_file_path, false)?;
+
+            let opt_block = {
+                self.cache.get(meta_file_path)
+                    .and_then(|mc| mc.get(abs_item_path))
+            };
+
+            let field_result = opt_block.and_then(|mb| get_field(mb, field_name, self.media_lib.fold_field_case())).cloned();
+
+            if let Some(ref mut trace) = self.trace {
+                let decision = match (opt_block, &field_result) {
+                    (None, _) => TraceDecision::NoBlock,
+                    (Some(_), &None) => TraceDecision::FieldMissing,
+                    (Some(_), &Some(_)) => TraceDecision::Found,
+                };
+
+                trace.record(abs_item_path, meta_file_path, field_name, decision);
+            }
+
+            match field_result {
+                Some(val) => { return Ok(Some(val)); },
+                // Only the first meta file with a block for the item counts, if the library says so.
+                None if opt_block.is_some() && self.media_lib.target_policy() == TargetPolicy::First => { break; },
+                None => { continue; }
+            }
+        }
+
+        // Fall back to any other meta sources.
+        if fallback_sources {
+            self.media_lib.source_field(abs_item_path, field_name)
+        }
+        else {
+            Ok(None)
+        }
+    }
+
+    /// Looks up a field at a single level, trying the field name and then each label.
+    /// Local fields are skipped if the level is an ancestor of the item being looked up.
+    fn lookup_level(&mut self, abs_item_path: &Path, meta_file_paths: &[PathBuf], options: &LookupOptions, inherited: bool) -> LookupResult {
+        for field_name in options.field_names() {
+            if inherited && self.media_lib.is_local_field(field_name) {
+                continue;
+            }
+
+            let found = match self.virtual_field(abs_item_path, field_name)? {
+                Some(val) => Some(val),
+                None => self.lookup_meta_files(abs_item_path, meta_file_paths, field_name, options.fallback_sources())?,
+            };
+
+            if let Some(val) = found.and_then(|val| options.extract(val)) {
+                return Ok(Some(val));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the meta blocks that count for an item under the target policy of the library, in meta target order, along with their meta files.
+    fn item_blocks(&mut self, abs_item_path: &Path) -> Result<Vec<(PathBuf, Arc<MetaBlock>)>> {
+        let mut results = vec![];
+
+        for meta_file_path in self.media_lib.meta_fps_from_item_fp(abs_item_path)? {
+            self.cache_meta_file(&meta_file_path, false)?;
+
+            let opt_block = {
+                self.cache.get(&meta_file_path)
+                    .and_then(|mc| mc.get(abs_item_path))
+                    .map(Arc::clone)
+            };
+
+            if let Some(mb) = opt_block {
+                results.push((meta_file_path, mb));
+            }
+        }
+
+        Ok(self.media_lib.effective_blocks(results))
+    }
+
+    /// Returns the meta blocks that directly describe an item, in meta target order.
+    /// The blocks are shared with the cache, so no metadata is copied.
+    pub fn origin_blocks<P: AsRef<Path>>(&mut self, abs_item_path: P) -> Result<Vec<Arc<MetaBlock>>> {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+
+        Ok(self.item_blocks(&abs_item_path)?.into_iter().map(|(_, mb)| mb).collect())
+    }
+
+    /// Returns true if any meta block that directly describes an item marks it as ignored.
+    /// Ignored items are left out of children lookups and exports.
+    pub fn is_ignored<P: AsRef<Path>>(&mut self, abs_item_path: P) -> Result<bool> {
+        Ok(self.origin_blocks(abs_item_path)?.iter().any(|mb| is_ignored_block(mb)))
+    }
+
+    /// Merges the meta blocks that directly describe an item into a single block.
+    /// Where meta files disagree, the one with the highest precedence (i.e. earliest in meta target order) wins.
+    pub fn merged_origin_block<P: AsRef<Path>>(&mut self, abs_item_path: P) -> Result<MetaBlock> {
+        let mut merged = MetaBlock::new();
+
+        // Apply the lowest precedence blocks first, so that higher precedence blocks overwrite them.
+        for mb in self.origin_blocks(abs_item_path)?.into_iter().rev() {
+            merged.extend(mb.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+
+        Ok(merged)
+    }
+
+    /// Resolves every field of an item, including inherited ones, keeping track of the meta file that provided each field.
+    /// As with lookups, the item itself is consulted first, and then its ancestors, nearest first, skipping their local fields.
+    pub fn resolve_block<P: AsRef<Path>>(&mut self, abs_item_path: P) -> Result<ResolvedBlock> {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+
+        let mut search_paths = vec![abs_item_path.clone()];
+        search_paths.extend(self.media_lib.ancestor_paths(&abs_item_path, None)?);
+
+        let mut resolved = ResolvedBlock::new();
+
+        for (depth, search_path) in search_paths.into_iter().enumerate() {
+            for (meta_file_path, mb) in self.item_blocks(&search_path)? {
+                let meta_target = self.media_lib.meta_target_of(&meta_file_path)?;
+
+                let media_lib = self.media_lib;
+                let is_inherited_field = |k: &str| depth == 0 || !media_lib.is_local_field(k);
+
+                for (field_name, val) in mb.iter().filter(|&(k, _)| !is_reserved_key(k) && is_inherited_field(k)) {
+                    let source = FieldSource { meta_path: meta_file_path.clone(), meta_target: meta_target.clone(), depth };
+                    resolved.insert_if_absent(field_name.clone(), val.clone(), source);
+                }
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Collects all fields in a namespace from the meta blocks that directly describe an item, keyed by local name.
+    /// If multiple meta files provide the same field, the first one (in meta target order) wins.
+    pub fn lookup_origin_namespace<P: AsRef<Path>, S: AsRef<str>>(
+        &mut self,
+        abs_item_path: P,
+        namespace: S,
+        ) -> Result<BTreeMap<String, MetaValue>>
+    {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+        let namespace = namespace.as_ref();
+
+        let mut results: BTreeMap<String, MetaValue> = btreemap![];
+
+        for (_, mb) in self.item_blocks(&abs_item_path)? {
+            for (local_name, val) in namespace_fields(&mb, namespace) {
+                results.entry(local_name.to_string()).or_insert_with(|| val.clone());
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Collects all fields from the meta blocks that directly describe an item whose names match a glob pattern.
+    /// If multiple meta files provide the same field, the first one (in meta target order) wins.
+    pub fn lookup_matching_fields<P: AsRef<Path>, S: AsRef<str>>(
+        &mut self,
+        abs_item_path: P,
+        field_pattern: S,
+        ) -> Result<BTreeMap<String, MetaValue>>
+    {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+        let field_pattern = field_pattern.as_ref();
+
+        let pattern = glob::Pattern::new(field_pattern)
+            .chain_err(|| ErrorKind::InvalidFieldPattern(field_pattern.to_string()))?;
+
+        let mut results: BTreeMap<String, MetaValue> = btreemap![];
+
+        for (_, mb) in self.item_blocks(&abs_item_path)? {
+            for (field_name, val) in mb.iter().filter(|&(k, _)| pattern.matches(k)) {
+                results.entry(field_name.clone()).or_insert_with(|| val.clone());
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub fn lookup_parents<P: AsRef<Path>, S: AsRef<str>>(
+        &mut self,
+        abs_item_path: P,
+        field_name: S,
+        ) -> LookupResult
+    {
+        self.lookup_parents_until(abs_item_path, field_name, None)
+    }
+
+    /// Looks up a field in the ancestors of an item, nearest first.
+    /// The search includes the library root, unless a closer ancestor to stop at (inclusive) is given.
+    pub fn lookup_parents_until<P: AsRef<Path>, S: AsRef<str>>(
+        &mut self,
+        abs_item_path: P,
+        field_name: S,
+        opt_stop_path: Option<&Path>,
+        ) -> LookupResult
+    {
+        let field_name = field_name.as_ref();
+
+        // Local fields are never inherited.
+        if self.media_lib.is_local_field(field_name) {
+            return Ok(None);
+        }
+
+        for ancestor_path in self.media_lib.ancestor_paths(abs_item_path, opt_stop_path)? {
+            match self.lookup_origin(&ancestor_path, field_name)? {
+                Some(results) => { return Ok(Some(results)); },
+                None => {},
+            }
+        }
+
+        // No error, but value was not found.
+        Ok(None)
+    }
+
+    /// Looks up a field for an item as described by the options.
+    /// By default, this looks at the item itself and then falls back to its ancestors, nearest first.
+    /// For nested field paths, a field only counts as found at a level if the entire path resolves there.
+    pub fn lookup<P: AsRef<Path>>(&mut self, abs_item_path: P, options: &LookupOptions) -> LookupResult {
+        let mut found = self.lookup_raw(abs_item_path.as_ref(), options)?;
+
+        // Sort fields that are not set are derived from the fields that they sort, e.g. `artist_sort` from `artist`.
+        if found.is_none() {
+            let opt_source_field = self.sort_name_rules.source_field(options.field_name()).map(String::from);
+
+            if let Some(source_field) = opt_source_field {
+                let source_options = options.for_field(source_field);
+
+                found = self.lookup_raw(abs_item_path.as_ref(), &source_options)?
+                    .map(|mv| options.flatten(self.sort_name_rules.sort_value(&mv)));
+            }
+        }
+
+        match found {
+            Some(val) if options.substitute_vars() => {
+                let val = substitute(val, &|var_name: &str| self.var_value(var_name))?;
+                Ok(Some(val))
+            },
+            found => Ok(found),
+        }
+    }
+
+    /// Gives the value of a substitution variable.
+    /// Variables starting with `lib.` describe the library; all others are read from the environment.
+    fn var_value(&self, var_name: &str) -> Option<String> {
+        match var_name {
+            "lib.root" => Some(self.media_lib.root_dir().to_string_lossy().into_owned()),
+            _ if var_name.starts_with(LIB_VAR_PREFIX) => None,
+            _ => env::var(var_name).ok(),
+        }
+    }
+
+    fn lookup_raw(&mut self, abs_item_path: &Path, options: &LookupOptions) -> LookupResult {
+        let abs_item_path = normalize(abs_item_path);
+
+        if options.trace() {
+            self.enable_trace();
+        }
+
+        let max_depth = options.max_depth().unwrap_or(usize::max_value());
+
+        let search_paths = match options.direction() {
+            LookupDirection::Origin => vec![abs_item_path.clone()],
+            LookupDirection::Parents => {
+                self.media_lib.ancestor_paths(&abs_item_path, None)?.into_iter().take(max_depth).collect()
+            },
+            LookupDirection::Inherit => {
+                let mut search_paths = vec![abs_item_path.clone()];
+                search_paths.extend(self.media_lib.ancestor_paths(&abs_item_path, None)?.into_iter().take(max_depth));
+                search_paths
+            },
+            LookupDirection::Children => {
+                return self.lookup_children_with(&abs_item_path, options, max_depth);
+            },
+        };
+
+        let mut found = vec![];
+
+        for search_path in search_paths {
+            let meta_file_paths = self.media_lib.meta_fps_from_item_fp(&search_path)?;
+
+            let inherited = search_path != abs_item_path;
+
+            if let Some(val) = self.lookup_level(&search_path, &meta_file_paths, options, inherited)? {
+                found.push(val);
+
+                // Farther levels cannot change the result.
+                if options.merge_strategy() == MergeStrategy::First {
+                    break;
+                }
+            }
+        }
+
+        Ok(options.merge(found))
+    }
+
+    /// Evaluates an expression for an item, e.g. `sum(children.duration)` or `join(artist, ", ")`.
+    /// See the `expr` module for the syntax.
+    pub fn evaluate<P: AsRef<Path>, S: AsRef<str>>(&mut self, abs_item_path: P, expr_str: S) -> LookupResult {
+        let abs_item_path = normalize(abs_item_path.as_ref());
+        let expr = Expr::parse(expr_str.as_ref())?;
+
+        evaluate(self, &abs_item_path, &expr)
+    }
+
+    /// Finds the items in a directory and below it that match a query, see `query::run_query`.
+    pub fn query<P: AsRef<Path>, S: AsRef<str>>(&mut self, abs_dir_path: P, query_str: S) -> Result<Vec<QueryRow>> {
+        let query = Query::parse(query_str.as_ref())?;
+
+        run_query(self, abs_dir_path, &query)
+    }
+
+    pub fn lookup_children<P: AsRef<Path>, S: AsRef<str>>(
+        &mut self,
+        abs_item_path: P,
+        field_name: S,
+        ) -> LookupResult
+    {
+        let curr_item_path = normalize(abs_item_path.as_ref());
+
+        self.lookup_children_with(&curr_item_path, &LookupOptions::exact(field_name.as_ref()), usize::max_value())
+    }
+
+    /// Collects a field from the descendants of an item, going at most a given number of levels deep.
+    fn lookup_children_with(&mut self, curr_item_path: &Path, options: &LookupOptions, max_depth: usize) -> LookupResult {
+        // A non-directory has no children; this is a leaf (and a base case).
+        // The same goes for when the maximum depth has been reached.
+        if !self.media_lib.is_item_dir(curr_item_path) || max_depth == 0 {
+            return Ok(None);
+        }
+
+        let mut agg_results: Vec<MetaValue> = vec![];
+
+        // println!("Calling lookup_children for: {:?}", curr_item_path);
+
+        let children = self.media_lib.children_paths(curr_item_path)?;
+
+        // Siblings mostly share the same meta files, so parse each one once for the whole directory up front.
+        let mut children_meta_fps: Vec<Vec<PathBuf>> = Vec::with_capacity(children.len());
+        let mut unique_meta_fps: Vec<PathBuf> = vec![];
+        let mut seen_meta_fps: HashSet<PathBuf> = hashset![];
+
+        for child_abs_item_path in &children {
+            let meta_fps = self.media_lib.meta_fps_from_item_fp(child_abs_item_path)?;
+
+            for meta_fp in &meta_fps {
+                if seen_meta_fps.insert(meta_fp.clone()) {
+                    unique_meta_fps.push(meta_fp.clone());
+                }
+            }
+
+            children_meta_fps.push(meta_fps);
+        }
+
+        self.cache_meta_files(&unique_meta_fps, false)?;
+
+        // Look at the metadata for each child contained in this directory, in the expected order.
+        for (child_abs_item_path, meta_fps) in children.into_iter().zip(children_meta_fps) {
+            // Ignored items leave no hole in the aggregation, and their descendants are skipped too.
+            if self.is_ignored(&child_abs_item_path)? {
+                continue;
+            }
+
+            // println!("Checking child: {:?}", child_abs_item_path);
+            // TODO: Do we want to short circuit on error here?
+            let child_results = self.lookup_level(&child_abs_item_path, &meta_fps, options, false)?;
+
+            match child_results {
+                Some(ref child_values) => {
+                    // println!("Found result: {:?}", child_results.clone());
+                    // Found the value, add it to the results and do not recurse further on this path.
+                    agg_results.push(child_values.clone());
+                },
+                None => {
+                    // println!("Not found here, trying subchildren");
+                    // Recurse down this path.
+                    // Note that this will produce a list.
+                    let sub_result = self.lookup_children_with(&child_abs_item_path, options, max_depth - 1)?;
+
+                    match sub_result {
+                        Some(sub_values) => { agg_results.push(sub_values); },
+                        None => {
+                            // println!("Not found at all");
+                            // TODO: Do nothing, or return null here?
+                            // Do nothing, this is a hole in the aggregation.
+                        },
+                    }
+                }
+            }
+        }
+
+        // TODO: If an enpty list would be returned, would it be better to return None?
+        Ok(Some(MetaValue::Seq(agg_results)))
+    }
+
+    pub fn cache_meta_files<I, P>(&mut self, meta_fps: I, force: bool) -> Result<()>
+    where I: IntoIterator<Item = P>,
+          P: AsRef<Path>,
+    {
+        for meta_fp in meta_fps.into_iter() {
+            let meta_fp = meta_fp.as_ref();
+
+            // Check if the entry is already cached (and still current, if verifying), and skip if cache request is not forced.
+            let is_current = self.cache.contains_key(meta_fp) && !(self.auto_verify && self.is_stale(meta_fp));
+
+            if !force && is_current {
+                self.stats.hits += 1;
+                self.touch(meta_fp);
+                continue;
+            }
+
+            self.stats.misses += 1;
+
+            // Remove the old entry from the cache.
+            // TODO: Create .remove_cached_meta_file().
+            let _ = self.cache.remove(meta_fp);
+
+            // Stamp before reading, so that a change made while reading is caught by the next check.
+            let opt_stamp = FileStamp::read_with(self.media_lib.vfs(), meta_fp);
+
+            // Temporary metadata cache, filled in below.
+            let mut temp: MetadataCache = btreemap![];
+
+            for (item_fp, meta_block) in self.media_lib.item_fps_from_meta_fp(meta_fp)? {
+                temp.insert(item_fp, Arc::new(meta_block));
+            }
+
+            self.cache.insert(meta_fp.to_path_buf(), temp);
+
+            if let Some(stamp) = opt_stamp {
+                self.stamps.insert(meta_fp.to_path_buf(), stamp);
+            }
+
+            self.touch(meta_fp);
+            self.evict_to_capacity();
+        }
+
+        Ok(())
+    }
+
+    pub fn cache_meta_file<P: AsRef<Path>>(&mut self, meta_fp: P, force: bool) -> Result<()> {
+        self.cache_meta_files(&[meta_fp], force)
+    }
+
+    pub fn cache_item_files<I, P>(&mut self, item_fps: I, force: bool) -> Result<()>
+    where I: IntoIterator<Item = P>,
+          P: AsRef<Path>,
+    {
+        for item_fp in item_fps.into_iter() {
+            let item_fp = item_fp.as_ref();
+
+            // Get the meta files that could provide info for this item.
+            // TODO: Remove duplicates.
+            let mut meta_fps = self.media_lib.meta_fps_from_item_fp(&item_fp)?;
+
+            self.cache_meta_files(&meta_fps, force)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn cache_item_file<P: AsRef<Path>>(&mut self, item_fp: P, force: bool) -> Result<()> {
+        self.cache_item_files(&[item_fp], force)
+    }
+
+    /// Caches the meta files that could describe an item, reporting each one that had to be parsed.
+    pub fn cache_item_file_with_progress<P: AsRef<Path>, G: Progress>(&mut self, item_fp: P, progress: &mut G) -> Result<()> {
+        for meta_fp in self.media_lib.meta_fps_from_item_fp(item_fp.as_ref())? {
+            if !self.cache.contains_key(&meta_fp) {
+                self.cache_meta_file(&meta_fp, false)?;
+                progress.meta_file_parsed(&meta_fp);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.last_used.clear();
+        self.stamps.clear();
+    }
+
+    pub fn clear_meta_files<I, P>(&mut self, meta_fps: I) -> Result<()>
+    where I: IntoIterator<Item = P>,
+          P: AsRef<Path>,
+    {
+        for meta_fp in meta_fps.into_iter() {
+            let meta_fp = meta_fp.as_ref();
+            let _ = self.cache.remove(meta_fp);
+            let _ = self.last_used.remove(meta_fp);
+            let _ = self.stamps.remove(meta_fp);
+        }
+
+        Ok(())
+    }
+
+    pub fn clear_meta_file<P: AsRef<Path>>(&mut self, meta_fp: P) -> Result<()> {
+        self.clear_meta_files(&[meta_fp])
+    }
+
+    /// Clears the cached meta files directly inside a directory, e.g. after items were added to or removed from it, which changes the items that their blocks are matched up with.
+    pub fn clear_meta_files_in_dir<P: AsRef<Path>>(&mut self, abs_dir_path: P) -> Result<()> {
+        let abs_dir_path = normalize(abs_dir_path.as_ref());
+        let meta_fps: Vec<PathBuf> = self.cache.keys().filter(|p| p.parent() == Some(abs_dir_path.as_path())).cloned().collect();
+
+        self.clear_meta_files(meta_fps)
+    }
+
+    // pub fn clear_item_files<I, P>(&mut self, item_fps: I) -> Result<()>
+    // where I: IntoIterator<Item = P>,
+    //       P: AsRef<Path>,
+    // {
+    //     for item_fp in item_fps.into_iter() {
+    //         let item_fp = item_fp.as_ref();
+
+    //         // Get the meta files that could provide info for this item.
+    //         // TODO: Remove duplicates.
+    //         let mut meta_fps = self.media_lib.meta_fps_from_item_fp(&item_fp)?;
+
+    //         self.clear_meta_files(&meta_fps)?;
+    //     }
+
+    //     Ok(())
+    // }
+
+    // pub fn clear_item_file<P: AsRef<Path>>(&mut self, item_fp: P) -> Result<()> {
+    //     self.clear_item_files(&[item_fp])
+    // }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use super::{LookupContext, MetaFileCache, CacheStats};
+    use super::options::{LookupOptions, LookupOptionsBuilder, LookupDirection, MergeStrategy};
+    use super::trace::{LookupTrace, TraceDecision};
+    use std::fs::{File, OpenOptions};
+    use std::io::Write;
+    use std::env;
+
+    use metadata::{MetaKey, MetaValue, MetaTarget};
+    use library::{LibraryBuilder, TargetPolicy};
+    use library::selection::Selection;
+    use library::assets::{AssetRule, AssetScope};
+    use sort_name::SortNameRules;
+    use fixtures::{default_setup, create_temp_media_test_dir};
+
+    fn extract_all_meta_fps(raw_cache: &MetaFileCache) -> HashSet<PathBuf> {
+        raw_cache.keys().into_iter().cloned().collect()
+    }
+
+    fn extract_all_item_fps(raw_cache: &MetaFileCache) -> HashSet<PathBuf> {
+        raw_cache
+            .values()
+            .into_iter()
+            .flat_map(|item_fp_to_mb| item_fp_to_mb.keys())
+            .cloned()
+            .collect()
+    }
+
+    fn extract_sub_item_fps<P: AsRef<Path>>(raw_cache: &MetaFileCache, meta_fp: P) -> HashSet<PathBuf> {
+        let meta_fp = meta_fp.as_ref();
+
+        raw_cache
+            .get(meta_fp)
+            .expect("key not found in cache")
+            .keys()
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    // enum EqualityTarget {
+    //     AllMetas,
+    //     AllItems,
+    //     SubItems(PathBuf),
+    // }
+
+    // fn test_fp_set_equality(expected_fps: HashSet<PathBuf>, raw_cache: &MetaFileCache, target: EqualityTarget) {
+    //     let produced_fps = match target {
+    //         EqualityTarget::AllMetas => extract_all_meta_fps(raw_cache),
+    //         EqualityTarget::AllItems => extract_all_item_fps(raw_cache),
+    //         EqualityTarget::SubItems(ref meta_fp) => extract_sub_item_fps(raw_cache, &meta_fp),
+    //     };
+
+    //     assert_eq!(expected_fps, produced_fps);
+    // }
+
+    #[test]
+    fn test_new() {
+        let (_, media_lib) = default_setup("test_new");
+
+        let lookup_ctx = LookupContext::new(&media_lib);
+
+        assert!(lookup_ctx.cache.is_empty());
+    }
+
+    #[test]
+    fn test_lookup_origin() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_origin");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+        let expected = Some(MetaValue::Str("const_val".to_string()));
+        let produced = lookup_ctx.lookup_origin(&item_fp, "const_key").expect("Unable to perform lookup");
+        assert_eq!(expected, produced);
+
+        let expected_meta_fps = hashset![
+            // The item file will never be searched!
+            // tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("self.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        lookup_ctx.clear();
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+        let expected = Some(MetaValue::Str("item_val".to_string()));
+        let produced = lookup_ctx.lookup_origin(&item_fp, "item_key").expect("Unable to perform lookup");
+        assert_eq!(expected, produced);
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("item.yml"),
+            // This gets cached and checked first, but does not contain the field.
+            tp.join("ALBUM_01").join("DISC_01").join("self.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        lookup_ctx.clear();
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+        let expected = None;
+        let produced = lookup_ctx.lookup_origin(&item_fp, "NON_EXISTENT_FIELD").expect("Unable to perform lookup");
+        assert_eq!(expected, produced);
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("self.yml"),
+            // The sibling meta file inside of the directory is checked last, since it can describe the directory under the null key.
+            tp.join("ALBUM_01").join("DISC_01").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+    }
+
+    #[test]
+    fn test_lookup_parents() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_parents");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+        let expected = Some(MetaValue::Str("const_val".to_string()));
+        let produced = lookup_ctx.lookup_parents(&item_fp, "const_key").expect("Unable to perform lookup");
+        assert_eq!(expected, produced);
+
+        let expected_meta_fps = hashset![
+            // The item file will never be searched!
+            // tp.join("item.yml"),
+            tp.join("ALBUM_01").join("self.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        lookup_ctx.clear();
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+        let expected = Some(MetaValue::Str("item_val".to_string()));
+        let produced = lookup_ctx.lookup_parents(&item_fp, "item_key").expect("Unable to perform lookup");
+        assert_eq!(expected, produced);
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("self.yml"),
+            tp.join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        lookup_ctx.clear();
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+        let expected = None;
+        let produced = lookup_ctx.lookup_parents(&item_fp, "NON_EXISTENT_FIELD").expect("Unable to perform lookup");
+        assert_eq!(expected, produced);
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("self.yml"),
+            tp.join("item.yml"),
+            // The sibling meta file inside of the parent is checked last, since it can describe the parent under the null key.
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("self.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+    }
+
+    #[test]
+    fn test_lookup_parents_until() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_parents_until");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        // Root-level values are reachable from any depth.
+        let expected = Some(MetaValue::Str("ROOT_self_val".to_string()));
+        for item_fp in vec![tp.join("ALBUM_04.flac"), tp.join("ALBUM_01"), tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac")] {
+            let produced = lookup_ctx.lookup_parents(&item_fp, "ROOT_self_key").expect("Unable to perform lookup");
+            assert_eq!(expected, produced);
+        }
+
+        // The root itself has no parents.
+        let produced = lookup_ctx.lookup_parents(&tp, "ROOT_self_key").expect("Unable to perform lookup");
+        assert_eq!(None, produced);
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+        let stop_fp = tp.join("ALBUM_01");
+
+        let expected = Some(MetaValue::Str("ALBUM_01_self_val".to_string()));
+        let produced = lookup_ctx.lookup_parents_until(&item_fp, "ALBUM_01_self_key", Some(&stop_fp)).expect("Unable to perform lookup");
+        assert_eq!(expected, produced);
+
+        let produced = lookup_ctx.lookup_parents_until(&item_fp, "ROOT_self_key", Some(&stop_fp)).expect("Unable to perform lookup");
+        assert_eq!(None, produced);
+
+        assert!(lookup_ctx.lookup_parents_until(&item_fp, "ROOT_self_key", Some(&tp.join("ALBUM_02"))).is_err());
+    }
+
+    #[test]
+    fn test_lookup_nested() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_nested");
+        let tp = temp_media_root.path();
+
+        let mut f = OpenOptions::new().append(true).open(tp.join("ALBUM_01").join("self.yml")).expect("Unable to open meta file");
+        writeln!(f, "credits:\n  composer: Album Composer").expect("Unable to write meta file");
+        let mut f = OpenOptions::new().append(true).open(tp.join("self.yml")).expect("Unable to open meta file");
+        writeln!(f, "credits:\n  composer: Root Composer\n  lyricist: Root Lyricist").expect("Unable to write meta file");
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+
+        let inputs_and_expected = vec![
+            ("credits.composer", Some(MetaValue::Str("Album Composer".to_string()))),
+            // Nested paths that do not fully resolve keep searching upwards.
+            ("credits.lyricist", Some(MetaValue::Str("Root Lyricist".to_string()))),
+            ("credits.arranger", None),
+            ("const_key.nested", None),
+            ("TRACK_01_item_key", Some(MetaValue::Str("TRACK_01_item_val".to_string()))),
+            ("ROOT_self_key", Some(MetaValue::Str("ROOT_self_val".to_string()))),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = lookup_ctx.lookup(&item_fp, &LookupOptions::new(input)).expect("Unable to perform lookup");
+            assert_eq!(expected, produced);
+        }
+    }
+
+    #[test]
+    fn test_origin_blocks() {
+        let (temp_media_root, media_lib) = default_setup("test_origin_blocks");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+        let produced = lookup_ctx.origin_blocks(&item_fp).expect("Unable to get blocks");
+        assert_eq!(2, produced.len());
+        assert_eq!(Some(&MetaValue::Str("DISC_01_self_val".to_string())), produced[0].get("DISC_01_self_key"));
+        assert_eq!(Some(&MetaValue::Str("DISC_01_item_val".to_string())), produced[1].get("DISC_01_item_key"));
+
+        // Later calls hand out the same blocks, instead of copies.
+        let produced_again = lookup_ctx.origin_blocks(&item_fp).expect("Unable to get blocks");
+        assert!(produced.iter().zip(&produced_again).all(|(a, b)| Arc::ptr_eq(a, b)));
+
+        // Forcing a re-cache replaces the blocks, but does not affect the ones already handed out.
+        lookup_ctx.cache_item_file(&item_fp, true).expect("Unable to cache item file");
+        let produced_again = lookup_ctx.origin_blocks(&item_fp).expect("Unable to get blocks");
+        assert!(!Arc::ptr_eq(&produced[0], &produced_again[0]));
+        assert_eq!(produced, produced_again);
+
+        // Only its own self meta file describes the library root.
+        let produced = lookup_ctx.origin_blocks(tp).expect("Unable to get blocks");
+        assert_eq!(1, produced.len());
+    }
+
+    #[test]
+    fn test_merged_origin_block() {
+        let temp_media_root = create_temp_media_test_dir("test_merged_origin_block");
+        let tp = temp_media_root.path();
+
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        let mut f = File::create(disc_fp.join("item.local.yml")).unwrap();
+        writeln!(f, "TRACK_01.flac:\n  item_key: local_val\n  local_key: local_only").unwrap();
+
+        // Local overrides are listed first, so that they take precedence over the shared meta files.
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.local.yml"), MetaTarget::Siblings),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs).selection(Selection::Ext(String::from("flac"))).create().expect("Unable to create media library");
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let track_fp = disc_fp.join("TRACK_01.flac");
+
+        let merged = lookup_ctx.merged_origin_block(&track_fp).expect("Unable to merge blocks");
+        assert_eq!(Some(&str_val("local_val")), merged.get("item_key"));
+        assert_eq!(Some(&str_val("local_only")), merged.get("local_key"));
+        assert_eq!(Some(&str_val("TRACK_01_item_val")), merged.get("TRACK_01_item_key"));
+
+        // Lookups agree with the merged block.
+        assert_eq!(Some(str_val("local_val")), lookup_ctx.lookup_origin(&track_fp, "item_key").unwrap());
+        assert_eq!(Some(str_val("item_val")), lookup_ctx.lookup_origin(disc_fp.join("TRACK_02.flac"), "item_key").unwrap());
+    }
+
+    #[test]
+    fn test_resolve_block() {
+        let (temp_media_root, media_lib) = default_setup("test_resolve_block");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let track_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+
+        let resolved = lookup_ctx.resolve_block(&track_fp).expect("Unable to resolve block");
+
+        // Nearer levels win.
+        assert_eq!(Some(&str_val("item_val")), resolved.value("item_key"));
+        let source = resolved.source("item_key").unwrap();
+        assert_eq!(tp.join("ALBUM_01").join("DISC_01").join("item.yml"), source.meta_path);
+        assert_eq!(MetaTarget::Siblings, source.meta_target);
+        assert_eq!(0, source.depth);
+
+        // Inherited fields remember how far away they came from.
+        let source = resolved.source("ALBUM_01_self_key").unwrap();
+        assert_eq!(tp.join("ALBUM_01").join("self.yml"), source.meta_path);
+        assert_eq!(MetaTarget::Contains, source.meta_target);
+        assert_eq!(2, source.depth);
+
+        assert_eq!(Some(&str_val("ROOT_self_val")), resolved.value("ROOT_self_key"));
+
+        // Resolved values agree with lookups.
+        for (field_name, rf) in resolved.iter() {
+            assert_eq!(Some(rf.value.clone()), lookup_ctx.lookup(&track_fp, &LookupOptions::exact(field_name.as_str())).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_lookup_substitute_vars() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_substitute_vars");
+        let tp = temp_media_root.path();
+
+        let mut f = File::create(tp.join("ALBUM_01").join("self.yml")).unwrap();
+        writeln!(f, "cover: ${{lib.root}}/covers/01.jpg\nowner: ${{TAGGU_TEST_OWNER}}\nbroken: ${{lib.missing}}").unwrap();
+        env::set_var("TAGGU_TEST_OWNER", "Owner");
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        // Substitution is opt-in.
+        assert_eq!(Some(str_val("${lib.root}/covers/01.jpg")), lookup_ctx.lookup(&item_fp, &LookupOptions::new("cover")).unwrap());
+
+        let options = |field_name: &str| LookupOptionsBuilder::new(field_name).substitute_vars(true).create();
+        let expected_cover = format!("{}/covers/01.jpg", media_lib.root_dir().to_string_lossy());
+        assert_eq!(Some(MetaValue::Str(expected_cover)), lookup_ctx.lookup(&item_fp, &options("cover")).unwrap());
+        assert_eq!(Some(str_val("Owner")), lookup_ctx.lookup(&item_fp, &options("owner")).unwrap());
+        assert!(lookup_ctx.lookup(&item_fp, &options("broken")).is_err());
+    }
+
+    #[test]
+    fn test_lookup_children_ignored() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_children_ignored");
+        let tp = temp_media_root.path();
+
+        let mut f = File::create(tp.join("ALBUM_01").join("DISC_01").join("item.yml")).unwrap();
+        writeln!(f, "- title: One\n- title: Two\n  __ignore: true\n- title: Three").unwrap();
+        let mut f = File::create(tp.join("ALBUM_01").join("DISC_02").join("self.yml")).unwrap();
+        writeln!(f, "__ignore: true").unwrap();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        assert!(lookup_ctx.is_ignored(tp.join("ALBUM_01").join("DISC_01").join("TRACK_02.flac")).unwrap());
+        assert!(lookup_ctx.is_ignored(tp.join("ALBUM_01").join("DISC_02")).unwrap());
+        assert!(!lookup_ctx.is_ignored(tp.join("ALBUM_01").join("DISC_01")).unwrap());
+
+        // The ignored disc does not contribute its tracks either.
+        let expected = MetaValue::Seq(vec![
+            MetaValue::Seq(vec![str_val("One"), str_val("Three")]),
+        ]);
+        assert_eq!(Some(expected), lookup_ctx.lookup_children(tp.join("ALBUM_01"), "title").unwrap());
+    }
+
+    #[test]
+    fn test_lookup_children_ignored_elsewhere() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_children_ignored_elsewhere");
+        let tp = temp_media_root.path();
+
+        // The first disc is ignored in item.yml, while the field comes from its self.yml, which has precedence.
+        let mut f = File::create(tp.join("ALBUM_01").join("item.yml")).unwrap();
+        writeln!(f, "- __ignore: true\n- {{}}").unwrap();
+
+        let album_fp = tp.join("ALBUM_01");
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(metaval!(["self_val"])), lookup_ctx.lookup_children(&album_fp, "self_key").unwrap());
+
+        // The result does not depend on which meta files happen to be cached already.
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(metaval!("self_val")), lookup_ctx.lookup_origin(album_fp.join("DISC_01"), "self_key").unwrap());
+        assert_eq!(Some(metaval!(["self_val"])), lookup_ctx.lookup_children(&album_fp, "self_key").unwrap());
+    }
+
+    #[test]
+    fn test_lookup_fold_field_case() {
+        let temp_media_root = create_temp_media_test_dir("test_lookup_fold_field_case");
+        let tp = temp_media_root.path();
+
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        let mut f = File::create(disc_fp.join("self.yml")).unwrap();
+        writeln!(f, "Artist: Someone\nself_key: self_val").unwrap();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let exact_lib = LibraryBuilder::new(tp, meta_target_specs.clone()).create().expect("Unable to create media library");
+        let folded_lib = LibraryBuilder::new(tp, meta_target_specs).fold_field_case(true).create().expect("Unable to create media library");
+
+        let mut lookup_ctx = LookupContext::new(&exact_lib);
+        assert_eq!(None, lookup_ctx.lookup_origin(&disc_fp, "artist").unwrap());
+
+        let mut lookup_ctx = LookupContext::new(&folded_lib);
+        assert_eq!(Some(MetaValue::Str(String::from("Someone"))), lookup_ctx.lookup_origin(&disc_fp, "artist").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("Someone"))), lookup_ctx.lookup_origin(&disc_fp, "ARTIST").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("self_val"))), lookup_ctx.lookup_origin(&disc_fp, "Self_Key").unwrap());
+
+        // The original casing is kept in the blocks themselves.
+        let merged = lookup_ctx.merged_origin_block(&disc_fp).expect("Unable to merge blocks");
+        assert!(merged.contains_key("Artist"));
+        assert!(!merged.contains_key("artist"));
+    }
+
+    #[test]
+    fn test_lookup_local_fields() {
+        let temp_media_root = create_temp_media_test_dir("test_lookup_local_fields");
+        let tp = temp_media_root.path();
+
+        let album_fp = tp.join("ALBUM_01");
+        let disc_fp = album_fp.join("DISC_01");
+        let mut f = File::create(album_fp.join("self.yml")).unwrap();
+        writeln!(f, "Album_Title: Album\nalbum_id: 1234\ncomment: Shared").unwrap();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs.clone())
+            .local_field("album_*")
+            .fold_field_case(true)
+            .create()
+            .expect("Unable to create media library");
+
+        assert!(media_lib.is_local_field("album_id"));
+        assert!(media_lib.is_local_field("ALBUM_ID"));
+        assert!(!media_lib.is_local_field("comment"));
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        // Local fields are found on the item they are set on, but are not inherited.
+        assert_eq!(Some(MetaValue::Str(String::from("Album"))), lookup_ctx.lookup(&album_fp, &LookupOptions::new("album_title")).unwrap());
+        assert_eq!(None, lookup_ctx.lookup(&disc_fp, &LookupOptions::new("album_title")).unwrap());
+        assert_eq!(None, lookup_ctx.lookup_parents(&disc_fp, "album_id").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("Shared"))), lookup_ctx.lookup(&disc_fp, &LookupOptions::new("comment")).unwrap());
+
+        let resolved = lookup_ctx.resolve_block(&disc_fp).expect("Unable to resolve block");
+        assert!(resolved.get("comment").is_some());
+        assert!(resolved.get("album_id").is_none());
+
+        // Rule: local field patterns must be valid.
+        assert!(LibraryBuilder::new(tp, meta_target_specs).local_field("album_[").create().is_err());
+    }
+
+    #[test]
+    fn test_lookup_target_policy() {
+        let temp_media_root = create_temp_media_test_dir("test_lookup_target_policy");
+        let tp = temp_media_root.path();
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let merge_lib = LibraryBuilder::new(tp, meta_target_specs.clone()).create().expect("Unable to create media library");
+        let first_lib = LibraryBuilder::new(tp, meta_target_specs).target_policy(TargetPolicy::First).create().expect("Unable to create media library");
+
+        // Both meta files describe the disc, so merging finds fields from either one.
+        let mut lookup_ctx = LookupContext::new(&merge_lib);
+        assert_eq!(Some(MetaValue::Str(String::from("item_val"))), lookup_ctx.lookup_origin(&disc_fp, "item_key").unwrap());
+        assert_eq!(2, lookup_ctx.origin_blocks(&disc_fp).unwrap().len());
+
+        // Only the first meta file counts otherwise, even for fields that it does not set.
+        let mut lookup_ctx = LookupContext::new(&first_lib);
+        assert_eq!(Some(MetaValue::Str(String::from("const_val"))), lookup_ctx.lookup_origin(&disc_fp, "const_key").unwrap());
+        assert_eq!(None, lookup_ctx.lookup_origin(&disc_fp, "item_key").unwrap());
+        assert_eq!(1, lookup_ctx.origin_blocks(&disc_fp).unwrap().len());
+        assert!(lookup_ctx.resolve_block(&disc_fp).unwrap().source("item_key").map_or(true, |s| !s.is_origin()));
+
+        let fields = first_lib.fields_for_children(tp.join("ALBUM_01"), &["const_key", "item_key"]).unwrap();
+        let (_, ref disc_fields) = fields[0];
+        assert_eq!(Some(&None), disc_fields.get("item_key"));
+    }
+
+    #[test]
+    fn test_lookup_nested_items() {
+        let temp_media_root = create_temp_media_test_dir("test_lookup_nested_items");
+        let tp = temp_media_root.path();
+
+        let disc_fp = tp.join("ALBUM_03").join("DISC_02");
+        let mut f = File::create(disc_fp.join("item.yml")).unwrap();
+        writeln!(f, "- item_key: track_val\n  __items:\n    - nested_key: first\n    - nested_key: second\n- item_key: track_val").unwrap();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let selection = Selection::Or(
+            Box::new(Selection::Ext(String::from("flac"))),
+            Box::new(Selection::IsDir),
+        );
+        let flat_lib = LibraryBuilder::new(tp, meta_target_specs.clone()).selection(selection.clone()).create().expect("Unable to create media library");
+        let nested_lib = LibraryBuilder::new(tp, meta_target_specs).selection(selection).nested_items(true).create().expect("Unable to create media library");
+
+        let subtrack_fp = disc_fp.join("TRACK_01").join("SUBTRACK_02.flac");
+
+        let mut lookup_ctx = LookupContext::new(&flat_lib);
+        assert_eq!(None, lookup_ctx.lookup_origin(&subtrack_fp, "nested_key").unwrap());
+
+        let mut lookup_ctx = LookupContext::new(&nested_lib);
+        assert_eq!(Some(MetaValue::Str(String::from("second"))), lookup_ctx.lookup_origin(&subtrack_fp, "nested_key").unwrap());
+
+        // Meta files nearer to the item still take precedence.
+        assert_eq!(Some(MetaValue::Str(String::from("item_val"))), lookup_ctx.lookup_origin(&subtrack_fp, "item_key").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("track_val"))), lookup_ctx.lookup_origin(disc_fp.join("TRACK_01"), "item_key").unwrap());
+    }
+
+    #[test]
+    fn test_verify() {
+        let (temp_media_root, media_lib) = default_setup("test_verify");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+
+        assert_eq!(Some(MetaValue::Str(String::from("self_val"))), lookup_ctx.lookup_origin(&disc_fp, "self_key").unwrap());
+        assert!(lookup_ctx.verify().is_empty());
+
+        let mut f = File::create(disc_fp.join("self.yml")).unwrap();
+        writeln!(f, "self_key: changed_val").unwrap();
+
+        assert_eq!(vec![disc_fp.join("self.yml")], lookup_ctx.verify());
+
+        // Without auto-verification, the stale entry keeps being used.
+        assert_eq!(Some(MetaValue::Str(String::from("self_val"))), lookup_ctx.lookup_origin(&disc_fp, "self_key").unwrap());
+
+        lookup_ctx.set_auto_verify(true);
+        assert_eq!(Some(MetaValue::Str(String::from("changed_val"))), lookup_ctx.lookup_origin(&disc_fp, "self_key").unwrap());
+        assert!(lookup_ctx.verify().is_empty());
+
+        // Removed meta files are stale too.
+        // The self key is found before the sibling meta file of the disc is read, so look up a key that only it has, to get it cached.
+        assert_eq!(Some(MetaValue::Str(String::from("DISC_01_item_val"))), lookup_ctx.lookup_origin(&disc_fp, "DISC_01_item_key").unwrap());
+        ::std::fs::remove_file(tp.join("ALBUM_01").join("item.yml")).unwrap();
+        assert_eq!(vec![tp.join("ALBUM_01").join("item.yml")], lookup_ctx.verify());
+    }
+
+    #[test]
+    fn test_lookup_matching_fields() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_matching_fields");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+
+        let inputs_and_expected = vec![
+            ("DISC_01_*", btreemap![
+                "DISC_01_item_key".to_string() => str_val("DISC_01_item_val"),
+                "DISC_01_self_key".to_string() => str_val("DISC_01_self_val"),
+            ]),
+            ("*_key", btreemap![
+                "DISC_01_item_key".to_string() => str_val("DISC_01_item_val"),
+                "DISC_01_self_key".to_string() => str_val("DISC_01_self_val"),
+                "const_key".to_string() => str_val("const_val"),
+                "item_key".to_string() => str_val("item_val"),
+                "self_key".to_string() => str_val("self_val"),
+            ]),
+            ("self_key", btreemap![
+                "self_key".to_string() => str_val("self_val"),
+            ]),
+            // Fields from ancestors are not included.
+            ("ALBUM_01_*", btreemap![]),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = lookup_ctx.lookup_matching_fields(&item_fp, input).expect("Unable to perform lookup");
+            assert_eq!(expected, produced);
+        }
+
+        assert!(lookup_ctx.lookup_matching_fields(&item_fp, "[DISC").is_err());
+    }
+
+    #[test]
+    fn test_lookup_with_options() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_with_options");
+        let tp = temp_media_root.path();
+
+        let mut f = OpenOptions::new().append(true).open(tp.join("ALBUM_01").join("self.yml")).expect("Unable to open meta file");
+        writeln!(f, "credits:\n  composer: Album Composer").expect("Unable to write meta file");
+        let mut f = OpenOptions::new().append(true).open(tp.join("self.yml")).expect("Unable to open meta file");
+        writeln!(f, "credits:\n  composer: Root Composer\n  lyricist: Root Lyricist").expect("Unable to write meta file");
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let item_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+
+        let inputs_and_expected = vec![
+            (LookupOptionsBuilder::new("const_key").direction(LookupDirection::Origin).create(), Some(str_val("const_val"))),
+            (LookupOptionsBuilder::new("self_key").direction(LookupDirection::Origin).create(), None),
+            (LookupOptionsBuilder::new("ROOT_self_key").direction(LookupDirection::Parents).create(), Some(str_val("ROOT_self_val"))),
+            (LookupOptionsBuilder::new("ROOT_self_key").direction(LookupDirection::Parents).max_depth(2).create(), None),
+            (LookupOptionsBuilder::new("TRACK_01_item_key").direction(LookupDirection::Parents).create(), None),
+            (LookupOptionsBuilder::new("NON_EXISTENT_FIELD").label("TRACK_01_item_key").create(), Some(str_val("TRACK_01_item_val"))),
+            (
+                LookupOptionsBuilder::new("self_key").merge_strategy(MergeStrategy::Collect).create(),
+                Some(MetaValue::Seq(vec![str_val("self_val"), str_val("self_val"), str_val("self_val")])),
+            ),
+            (
+                LookupOptionsBuilder::new("self_key").merge_strategy(MergeStrategy::Collect).max_depth(1).join_seq(", ").create(),
+                Some(str_val("self_val")),
+            ),
+            (
+                LookupOptionsBuilder::new("credits").merge_strategy(MergeStrategy::MergeMaps).create(),
+                Some(MetaValue::Map(btreemap![
+                    MetaKey::Str("composer".to_string()) => str_val("Album Composer"),
+                    MetaKey::Str("lyricist".to_string()) => str_val("Root Lyricist"),
+                ])),
+            ),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = lookup_ctx.lookup(&item_fp, &input).expect("Unable to perform lookup");
+            assert_eq!(expected, produced);
+        }
+
+        // Looking in children can be limited in depth.
+        let album_fp = tp.join("ALBUM_01");
+        let options = LookupOptionsBuilder::new("TRACK_01_item_key").direction(LookupDirection::Children).create();
+        let expected = Some(MetaValue::Seq(vec![
+            MetaValue::Seq(vec![str_val("TRACK_01_item_val")]),
+            MetaValue::Seq(vec![str_val("TRACK_01_item_val")]),
+        ]));
+        assert_eq!(expected, lookup_ctx.lookup(&album_fp, &options).expect("Unable to perform lookup"));
+
+        let options = LookupOptionsBuilder::new("TRACK_01_item_key").direction(LookupDirection::Children).max_depth(1).create();
+        assert_eq!(Some(MetaValue::Seq(vec![])), lookup_ctx.lookup(&album_fp, &options).expect("Unable to perform lookup"));
+
+        // Options can turn on tracing.
+        assert!(lookup_ctx.trace().is_none());
+        let options = LookupOptionsBuilder::new("const_key").trace(true).create();
+        lookup_ctx.lookup(&item_fp, &options).expect("Unable to perform lookup");
+        assert!(lookup_ctx.trace().is_some());
+    }
+
+    #[test]
+    fn test_lookup_trace() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_trace");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        // Tracing is opt-in.
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+        lookup_ctx.lookup_parents(&item_fp, "item_key").expect("Unable to perform lookup");
+        assert!(lookup_ctx.trace().is_none());
+
+        lookup_ctx.enable_trace();
+
+        lookup_ctx.lookup_parents(&item_fp, "item_key").expect("Unable to perform lookup");
+
+        let expected = vec![
+            (tp.join("ALBUM_01"), tp.join("ALBUM_01").join("self.yml"), TraceDecision::FieldMissing),
+            (tp.join("ALBUM_01"), tp.join("item.yml"), TraceDecision::Found),
+        ];
+        let produced: Vec<_> = lookup_ctx.take_trace().expect("Trace was not enabled")
+            .steps()
+            .iter()
+            .map(|s| (s.item_path.clone(), s.meta_path.clone(), s.decision))
+            .collect();
+        assert_eq!(expected, produced);
+
+        // Taking the trace leaves an empty trace behind.
+        assert_eq!(Some(true), lookup_ctx.trace().map(LookupTrace::is_empty));
+
+        lookup_ctx.lookup_origin(&item_fp, "NON_EXISTENT_FIELD").expect("Unable to perform lookup");
+
+        let expected = vec![
+            (tp.join("ALBUM_01").join("DISC_01").join("self.yml"), TraceDecision::FieldMissing),
+            (tp.join("ALBUM_01").join("item.yml"), TraceDecision::FieldMissing),
+            // The sibling meta file inside of the directory has no block under the null key.
+            (tp.join("ALBUM_01").join("DISC_01").join("item.yml"), TraceDecision::NoBlock),
+        ];
+        let produced: Vec<_> = lookup_ctx.take_trace().expect("Trace was not enabled")
+            .steps()
+            .iter()
+            .map(|s| (s.meta_path.clone(), s.decision))
+            .collect();
+        assert_eq!(expected, produced);
+
+        lookup_ctx.disable_trace();
+        assert!(lookup_ctx.take_trace().is_none());
+    }
+
+    #[test]
+    fn test_lookup_virtual_asset_fields() {
+        let temp_media_root = create_temp_media_test_dir("test_lookup_virtual_asset_fields");
+        let tp = temp_media_root.path();
+
+        File::create(tp.join("ALBUM_01").join("cover.jpg")).expect("Unable to create asset file");
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let selection = Selection::Or(
+            Box::new(Selection::Ext(String::from("flac"))),
+            Box::new(Selection::IsDir),
+        );
+        let media_lib = {
+            LibraryBuilder::new(tp, meta_target_specs)
+                .selection(selection)
+                .asset_rule(AssetRule::new("cover", Selection::Ext(String::from("jpg")), AssetScope::Dir))
+                .create()
+                .expect("Unable to create media library")
+        };
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let album_fp = tp.join("ALBUM_01");
+        let expected = Some(MetaValue::Str(album_fp.join("cover.jpg").to_string_lossy().into_owned()));
+        assert_eq!(expected, lookup_ctx.lookup_origin(&album_fp, "__cover_path").expect("Unable to perform lookup"));
+
+        // Virtual fields are inherited like any other field.
+        let item_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+        assert_eq!(None, lookup_ctx.lookup_origin(&item_fp, "__cover_path").expect("Unable to perform lookup"));
+        assert_eq!(expected, lookup_ctx.lookup_parents(&item_fp, "__cover_path").expect("Unable to perform lookup"));
+
+        assert_eq!(None, lookup_ctx.lookup_origin(tp.join("ALBUM_02"), "__cover_path").expect("Unable to perform lookup"));
+        assert_eq!(None, lookup_ctx.lookup_origin(&album_fp, "__booklet_path").expect("Unable to perform lookup"));
+    }
+
+    #[test]
+    fn test_lookup_children() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_children");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let item_fp = tp.join("ALBUM_01");
+        let expected = Some(MetaValue::Seq(vec![
+            MetaValue::Str("const_val".to_string()),
+            MetaValue::Str("const_val".to_string()),
+        ]));
+        let produced = lookup_ctx.lookup_children(&item_fp, "const_key").expect("Unable to perform lookup");
+        assert_eq!(expected, produced);
+
+        let expected_meta_fps = hashset![
+            // Note that every meta file that describes a child is accessed, even where the field is found in self.yml.
+            // This is so that ignored children are found, whichever meta file marks them.
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("self.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_02").join("self.yml"),
+            tp.join("ALBUM_01").join("DISC_02").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        lookup_ctx.clear();
+
+        let item_fp = tp.join("ALBUM_01");
+        let expected = Some(MetaValue::Seq(vec![
+            MetaValue::Seq(vec![
+                MetaValue::Str("TRACK_01_item_val".to_string()),
+            ]),
+            MetaValue::Seq(vec![
+                MetaValue::Str("TRACK_01_item_val".to_string()),
+            ]),
+        ]));
+        let produced = lookup_ctx.lookup_children(&item_fp, "TRACK_01_item_key").expect("Unable to perform lookup");
+        assert_eq!(expected, produced);
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("self.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_02").join("self.yml"),
+            tp.join("ALBUM_01").join("DISC_02").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+    }
+
+    #[test]
+    fn test_lookup_derived_sort_field() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_derived_sort_field");
+        let tp = temp_media_root.path();
+
+        let mut f = File::create(tp.join("ALBUM_01").join("self.yml")).unwrap();
+        writeln!(f, "artist: The Beatles").unwrap();
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        let mut f = File::create(disc_fp.join("item.yml")).unwrap();
+        writeln!(f, "- artist_sort: Lennon, John\n- artist: [The Who, Queen]").unwrap();
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        // Nothing is derived without rules.
+        assert_eq!(None, lookup_ctx.lookup(disc_fp.join("TRACK_03.flac"), &LookupOptions::new("artist_sort")).unwrap());
+
+        lookup_ctx.set_sort_name_rules(SortNameRules::default());
+
+        // Set sort fields win, and derived ones follow the inheritance of the fields that they sort.
+        assert_eq!(Some(str_val("Lennon, John")), lookup_ctx.lookup(disc_fp.join("TRACK_01.flac"), &LookupOptions::new("artist_sort")).unwrap());
+        assert_eq!(Some(MetaValue::Seq(vec![str_val("Who, The"), str_val("Queen")])), lookup_ctx.lookup(disc_fp.join("TRACK_02.flac"), &LookupOptions::new("artist_sort")).unwrap());
+        assert_eq!(Some(str_val("Beatles, The")), lookup_ctx.lookup(disc_fp.join("TRACK_03.flac"), &LookupOptions::new("artist_sort")).unwrap());
+        assert_eq!(None, lookup_ctx.lookup(tp.join("ALBUM_02"), &LookupOptions::new("artist_sort")).unwrap());
+
+        // Sequences are joined after their sort names are derived.
+        let options = LookupOptionsBuilder::new("artist_sort").join_seq("; ").create();
+        assert_eq!(Some(str_val("Who, The; Queen")), lookup_ctx.lookup(disc_fp.join("TRACK_02.flac"), &options).unwrap());
+    }
+
+    #[test]
+    fn test_lookup_children_parses_once() {
+        let (temp_media_root, media_lib) = default_setup("test_lookup_children_parses_once");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        lookup_ctx.lookup_children(tp.join("ALBUM_01"), "NON_EXISTENT_FIELD").expect("Unable to perform lookup");
+
+        // Each of the self and item meta files below the album is parsed exactly once.
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("self.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_02").join("self.yml"),
+            tp.join("ALBUM_01").join("DISC_02").join("item.yml"),
+        ];
+        assert_eq!(expected_meta_fps, extract_all_meta_fps(&lookup_ctx.cache));
+        assert_eq!(5, lookup_ctx.stats().misses);
+    }
+
+    #[test]
+    fn test_cache_meta_file() {
+        let (temp_media_root, media_lib) = default_setup("test_cache_meta_file");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let meta_fp = tp.join("ALBUM_01").join("item.yml");
+        lookup_ctx.cache_meta_file(&meta_fp, false).expect("Unable to cache meta file");
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        let expected_item_fps = hashset![
+            tp.join("ALBUM_01").join("DISC_01"),
+            tp.join("ALBUM_01").join("DISC_02"),
+        ];
+        let produced_item_fps: HashSet<PathBuf> = extract_sub_item_fps(&lookup_ctx.cache, &meta_fp);
+        assert_eq!(expected_item_fps, produced_item_fps);
+
+        let meta_fp = tp.join("ALBUM_01").join("item.yml");
+        lookup_ctx.cache_meta_file(&meta_fp, false).expect("Unable to cache meta file");
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        let expected_item_fps = hashset![
+            tp.join("ALBUM_01").join("DISC_01"),
+            tp.join("ALBUM_01").join("DISC_02"),
+        ];
+        let produced_item_fps: HashSet<PathBuf> = extract_sub_item_fps(&lookup_ctx.cache, &meta_fp);
+        assert_eq!(expected_item_fps, produced_item_fps);
+
+        let meta_fp = tp.join("ALBUM_01").join("self.yml");
+        lookup_ctx.cache_meta_file(&meta_fp, false).expect("Unable to cache meta file");
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_01").join("self.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        let expected_item_fps = hashset![
+            tp.join("ALBUM_01"),
+        ];
+        let produced_item_fps: HashSet<PathBuf> = extract_sub_item_fps(&lookup_ctx.cache, &meta_fp);
+        assert_eq!(expected_item_fps, produced_item_fps);
+    }
+
+    #[test]
+    fn test_cache_item_file() {
+        let (temp_media_root, media_lib) = default_setup("test_cache_item_file");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+        lookup_ctx.cache_item_file(&item_fp, false).expect("Unable to cache item file");
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("self.yml"),
+            // The sibling meta file inside of the directory can describe it under the null key.
+            tp.join("ALBUM_01").join("DISC_01").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        let expected_item_fps = hashset![
+            tp.join("ALBUM_01").join("DISC_01"),
+            // All item files pointed to by the item's meta files are cached.
+            tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac"),
+            tp.join("ALBUM_01").join("DISC_01").join("TRACK_02.flac"),
+            tp.join("ALBUM_01").join("DISC_01").join("TRACK_03.flac"),
+            tp.join("ALBUM_01").join("DISC_02"),
+        ];
+        let produced_item_fps: HashSet<PathBuf> = extract_all_item_fps(&lookup_ctx.cache);
+        assert_eq!(expected_item_fps, produced_item_fps);
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_02");
+        lookup_ctx.cache_item_file(&item_fp, false).expect("Unable to cache item file");
+
+        let expected_meta_fps = hashset![
+            // This should already be present from the first lookup.
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("self.yml"),
+            tp.join("ALBUM_01").join("DISC_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_02").join("self.yml"),
+            tp.join("ALBUM_01").join("DISC_02").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        let expected_item_fps = hashset![
+            tp.join("ALBUM_01").join("DISC_01"),
+            tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac"),
+            tp.join("ALBUM_01").join("DISC_01").join("TRACK_02.flac"),
+            tp.join("ALBUM_01").join("DISC_01").join("TRACK_03.flac"),
+            tp.join("ALBUM_01").join("DISC_02"),
+            tp.join("ALBUM_01").join("DISC_02").join("TRACK_01.flac"),
+            tp.join("ALBUM_01").join("DISC_02").join("TRACK_02.flac"),
+            tp.join("ALBUM_01").join("DISC_02").join("TRACK_03.flac"),
+        ];
+        let produced_item_fps: HashSet<PathBuf> = extract_all_item_fps(&lookup_ctx.cache);
+        assert_eq!(expected_item_fps, produced_item_fps);
+
+        // The meta file of the track was already cached along with its directory, so nothing changes.
+        let item_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+        lookup_ctx.cache_item_file(&item_fp, false).expect("Unable to cache item file");
+
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        let produced_item_fps: HashSet<PathBuf> = extract_all_item_fps(&lookup_ctx.cache);
+        assert_eq!(expected_item_fps, produced_item_fps);
+    }
+
+    #[test]
+    fn test_cache_capacity() {
+        let (temp_media_root, media_lib) = default_setup("test_cache_capacity");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::with_capacity(&media_lib, 2);
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01");
+        lookup_ctx.lookup_origin(&item_fp, "item_key").expect("Unable to perform lookup");
+        assert_eq!(CacheStats { hits: 0, misses: 2, evictions: 0 }, lookup_ctx.stats());
+
+        // The shared item meta file is reused, and the least recently used self meta file is evicted.
+        let item_fp = tp.join("ALBUM_01").join("DISC_02");
+        let produced = lookup_ctx.lookup_origin(&item_fp, "item_key").expect("Unable to perform lookup");
+        assert_eq!(Some(MetaValue::Str("item_val".to_string())), produced);
+        assert_eq!(CacheStats { hits: 1, misses: 3, evictions: 1 }, lookup_ctx.stats());
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_01").join("DISC_02").join("self.yml"),
+        ];
+        assert_eq!(expected_meta_fps, extract_all_meta_fps(&lookup_ctx.cache));
+
+        // Shrinking the capacity evicts immediately.
+        lookup_ctx.set_capacity(Some(0));
+        assert_eq!(Some(1), lookup_ctx.capacity());
+        assert_eq!(hashset![tp.join("ALBUM_01").join("item.yml")], extract_all_meta_fps(&lookup_ctx.cache));
+
+        lookup_ctx.reset_stats();
+        assert_eq!(CacheStats::default(), lookup_ctx.stats());
+    }
+
+    #[test]
+    fn test_clear() {
+        let (temp_media_root, media_lib) = default_setup("test_clear");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        lookup_ctx.cache_item_file(tp.join("ALBUM_01"), false).expect("Unable to cache item file");
+        lookup_ctx.cache_item_file(tp.join("ALBUM_02"), false).expect("Unable to cache item file");
+        lookup_ctx.cache_item_file(tp.join("ALBUM_03"), false).expect("Unable to cache item file");
+        lookup_ctx.cache_item_file(tp.join("ALBUM_05"), false).expect("Unable to cache item file");
+
+        let expected_meta_fps = hashset![
+            tp.join("item.yml"),
+            tp.join("ALBUM_01").join("self.yml"),
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_02").join("self.yml"),
+            tp.join("ALBUM_02").join("item.yml"),
+            tp.join("ALBUM_03").join("self.yml"),
+            tp.join("ALBUM_03").join("item.yml"),
+            tp.join("ALBUM_05").join("self.yml"),
+            tp.join("ALBUM_05").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        lookup_ctx.clear();
+
+        let expected_meta_fps = hashset![];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+    }
+
+    #[test]
+    fn test_clear_meta_file() {
+        let (temp_media_root, media_lib) = default_setup("test_clear_meta_file");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        lookup_ctx.cache_item_file(tp.join("ALBUM_01"), false).expect("Unable to cache item file");
+        lookup_ctx.cache_item_file(tp.join("ALBUM_02"), false).expect("Unable to cache item file");
+        lookup_ctx.cache_item_file(tp.join("ALBUM_03"), false).expect("Unable to cache item file");
+        lookup_ctx.cache_item_file(tp.join("ALBUM_05"), false).expect("Unable to cache item file");
+
+        let expected_meta_fps = hashset![
+            tp.join("item.yml"),
+            tp.join("ALBUM_01").join("self.yml"),
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_02").join("self.yml"),
+            tp.join("ALBUM_02").join("item.yml"),
+            tp.join("ALBUM_03").join("self.yml"),
+            tp.join("ALBUM_03").join("item.yml"),
+            tp.join("ALBUM_05").join("self.yml"),
+            tp.join("ALBUM_05").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        let expected_item_fps = hashset![
+            tp.join("ALBUM_01"),
+            tp.join("ALBUM_02"),
+            tp.join("ALBUM_03"),
+            tp.join("ALBUM_04.flac"),
+            tp.join("ALBUM_05"),
+            // The sibling meta files inside of the albums point to all of their children.
+            tp.join("ALBUM_01").join("DISC_01"),
+            tp.join("ALBUM_01").join("DISC_02"),
+            tp.join("ALBUM_02").join("DISC_01"),
+            tp.join("ALBUM_02").join("TRACK_01.flac"),
+            tp.join("ALBUM_02").join("TRACK_02.flac"),
+            tp.join("ALBUM_02").join("TRACK_03.flac"),
+            tp.join("ALBUM_03").join("DISC_01"),
+            tp.join("ALBUM_03").join("DISC_02"),
+            tp.join("ALBUM_05").join("DISC_01"),
+            tp.join("ALBUM_05").join("DISC_02"),
+            tp.join("ALBUM_05").join("TRACK_01.flac"),
+            tp.join("ALBUM_05").join("TRACK_02.flac"),
+            tp.join("ALBUM_05").join("TRACK_03.flac"),
+        ];
+        let produced_item_fps: HashSet<PathBuf> = extract_all_item_fps(&lookup_ctx.cache);
+        assert_eq!(expected_item_fps, produced_item_fps);
+
+        lookup_ctx.clear_meta_file(tp.join("item.yml")).expect("Unable to clear cache");
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("self.yml"),
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_02").join("self.yml"),
+            tp.join("ALBUM_02").join("item.yml"),
+            tp.join("ALBUM_03").join("self.yml"),
+            tp.join("ALBUM_03").join("item.yml"),
+            tp.join("ALBUM_05").join("self.yml"),
+            tp.join("ALBUM_05").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        let expected_item_fps = hashset![
+            tp.join("ALBUM_01"),
+            tp.join("ALBUM_02"),
+            tp.join("ALBUM_03"),
+            tp.join("ALBUM_05"),
+            tp.join("ALBUM_01").join("DISC_01"),
+            tp.join("ALBUM_01").join("DISC_02"),
+            tp.join("ALBUM_02").join("DISC_01"),
+            tp.join("ALBUM_02").join("TRACK_01.flac"),
+            tp.join("ALBUM_02").join("TRACK_02.flac"),
+            tp.join("ALBUM_02").join("TRACK_03.flac"),
+            tp.join("ALBUM_03").join("DISC_01"),
+            tp.join("ALBUM_03").join("DISC_02"),
+            tp.join("ALBUM_05").join("DISC_01"),
+            tp.join("ALBUM_05").join("DISC_02"),
+            tp.join("ALBUM_05").join("TRACK_01.flac"),
+            tp.join("ALBUM_05").join("TRACK_02.flac"),
+            tp.join("ALBUM_05").join("TRACK_03.flac"),
+        ];
+        let produced_item_fps: HashSet<PathBuf> = extract_all_item_fps(&lookup_ctx.cache);
+        assert_eq!(expected_item_fps, produced_item_fps);
+
+        lookup_ctx.clear_meta_file(tp.join("ALBUM_01").join("self.yml")).expect("Unable to clear cache");
+
+        let expected_meta_fps = hashset![
+            tp.join("ALBUM_01").join("item.yml"),
+            tp.join("ALBUM_02").join("self.yml"),
+            tp.join("ALBUM_02").join("item.yml"),
+            tp.join("ALBUM_03").join("self.yml"),
+            tp.join("ALBUM_03").join("item.yml"),
+            tp.join("ALBUM_05").join("self.yml"),
+            tp.join("ALBUM_05").join("item.yml"),
+        ];
+        let produced_meta_fps: HashSet<PathBuf> = extract_all_meta_fps(&lookup_ctx.cache);
+        assert_eq!(expected_meta_fps, produced_meta_fps);
+
+        let expected_item_fps = hashset![
+            tp.join("ALBUM_02"),
+            tp.join("ALBUM_03"),
+            tp.join("ALBUM_05"),
+            tp.join("ALBUM_01").join("DISC_01"),
+            tp.join("ALBUM_01").join("DISC_02"),
+            tp.join("ALBUM_02").join("DISC_01"),
+            tp.join("ALBUM_02").join("TRACK_01.flac"),
+            tp.join("ALBUM_02").join("TRACK_02.flac"),
+            tp.join("ALBUM_02").join("TRACK_03.flac"),
+            tp.join("ALBUM_03").join("DISC_01"),
+            tp.join("ALBUM_03").join("DISC_02"),
+            tp.join("ALBUM_05").join("DISC_01"),
+            tp.join("ALBUM_05").join("DISC_02"),
+            tp.join("ALBUM_05").join("TRACK_01.flac"),
+            tp.join("ALBUM_05").join("TRACK_02.flac"),
+            tp.join("ALBUM_05").join("TRACK_03.flac"),
+        ];
+        let produced_item_fps: HashSet<PathBuf> = extract_all_item_fps(&lookup_ctx.cache);
+        assert_eq!(expected_item_fps, produced_item_fps);
+    }
+}
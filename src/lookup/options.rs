@@ -0,0 +1,408 @@
+use metadata::{MetaKey, MetaValue};
+
+const FIELD_PATH_SEPARATOR: char = '.';
+
+/// Where to look for a field, relative to an item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupDirection {
+    /// Only the item itself.
+    Origin,
+    /// Only the ancestors of the item, nearest first.
+    Parents,
+    /// The item itself, and then its ancestors, nearest first.
+    Inherit,
+    /// The descendants of the item, collected into a sequence in sort order.
+    Children,
+}
+
+/// How values found at more than one level are combined, when looking in more than one level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// The nearest value wins.
+    First,
+    /// Every found value is collected into a sequence, nearest first.
+    Collect,
+    /// Mappings are merged, with keys from nearer levels winning.
+    /// If the nearest value is not a mapping, it wins outright, and farther values that are not mappings are ignored.
+    MergeMaps,
+}
+
+/// Describes what to look up for an item, and how.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LookupOptions {
+    field_name: String,
+    sub_keys: Vec<String>,
+    labels: Vec<String>,
+    direction: LookupDirection,
+    merge_strategy: MergeStrategy,
+    max_depth: Option<usize>,
+    fallback_sources: bool,
+    trace: bool,
+    seq_separator: Option<String>,
+    substitute_vars: bool,
+}
+
+impl LookupOptions {
+    fn from_parts(field_name: String, sub_keys: Vec<String>) -> Self {
+        LookupOptions {
+            field_name,
+            sub_keys,
+            labels: vec![],
+            direction: LookupDirection::Inherit,
+            merge_strategy: MergeStrategy::First,
+            max_depth: None,
+            fallback_sources: true,
+            trace: false,
+            seq_separator: None,
+            substitute_vars: false,
+        }
+    }
+
+    /// Creates options from a dotted field path, e.g. `credits.composer`.
+    /// The first part is the field name, and the remaining parts are keys into nested mappings.
+    pub fn new<S: AsRef<str>>(field_path: S) -> Self {
+        let mut parts = field_path.as_ref().split(FIELD_PATH_SEPARATOR).map(String::from);
+        let field_name = parts.next().unwrap_or_default();
+
+        LookupOptions::from_parts(field_name, parts.collect())
+    }
+
+    /// Creates options for a field name that is used as-is, even if it contains dots.
+    pub fn exact<S: Into<String>>(field_name: S) -> Self {
+        LookupOptions::from_parts(field_name.into(), vec![])
+    }
+
+    pub fn field_name(&self) -> &str {
+        &self.field_name
+    }
+
+    /// Creates options that look up another field in the same way, without any labels, and without joining sequences.
+    pub fn for_field<S: Into<String>>(&self, field_name: S) -> Self {
+        LookupOptions {
+            field_name: field_name.into(),
+            labels: vec![],
+            seq_separator: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn sub_keys(&self) -> &[String] {
+        &self.sub_keys
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// The field name, followed by any labels, in the order they are tried.
+    pub fn field_names(&self) -> Vec<&str> {
+        let mut field_names = vec![self.field_name.as_str()];
+        field_names.extend(self.labels.iter().map(String::as_str));
+        field_names
+    }
+
+    pub fn direction(&self) -> LookupDirection {
+        self.direction
+    }
+
+    pub fn merge_strategy(&self) -> MergeStrategy {
+        self.merge_strategy
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    pub fn fallback_sources(&self) -> bool {
+        self.fallback_sources
+    }
+
+    pub fn trace(&self) -> bool {
+        self.trace
+    }
+
+    /// Joins a found sequence of strings into a single string, e.g. with `"; "`.
+    /// Sequences containing anything other than strings are left as-is.
+    pub fn join_seq<S: Into<String>>(mut self, separator: S) -> Self {
+        self.seq_separator = Some(separator.into());
+        self
+    }
+
+    pub fn seq_separator(&self) -> Option<&str> {
+        self.seq_separator.as_ref().map(String::as_str)
+    }
+
+    pub fn substitute_vars(&self) -> bool {
+        self.substitute_vars
+    }
+
+    /// Follows the sub keys into a field value, returning `None` if any key is missing or the value is not a mapping.
+    pub fn extract(&self, mv: MetaValue) -> Option<MetaValue> {
+        let mut curr = mv;
+
+        for sub_key in &self.sub_keys {
+            curr = match curr {
+                MetaValue::Map(mut map) => map.remove(&MetaKey::Str(sub_key.clone()))?,
+                _ => return None,
+            };
+        }
+
+        Some(self.flatten(curr))
+    }
+
+    /// Combines the values found at each level, nearest first, according to the merge strategy.
+    pub fn merge(&self, found: Vec<MetaValue>) -> Option<MetaValue> {
+        if found.is_empty() {
+            return None;
+        }
+
+        match self.merge_strategy {
+            MergeStrategy::First => found.into_iter().next(),
+            MergeStrategy::Collect => Some(self.flatten(MetaValue::Seq(found))),
+            MergeStrategy::MergeMaps => {
+                let mut found = found.into_iter();
+                let mut merged = found.next()?;
+
+                if let MetaValue::Map(ref mut merged_map) = merged {
+                    for mv in found {
+                        if let MetaValue::Map(map) = mv {
+                            for (mk, mv) in map {
+                                merged_map.entry(mk).or_insert(mv);
+                            }
+                        }
+                    }
+                }
+
+                Some(merged)
+            },
+        }
+    }
+
+    /// Joins a sequence of strings into a single string, if the options have a separator for it.
+    pub fn flatten(&self, mv: MetaValue) -> MetaValue {
+        let separator = match self.seq_separator {
+            Some(ref separator) => separator,
+            None => return mv,
+        };
+
+        match mv {
+            MetaValue::Seq(mvs) => {
+                let mut strs: Vec<String> = Vec::with_capacity(mvs.len());
+
+                for mv in &mvs {
+                    match *mv {
+                        MetaValue::Str(ref s) => strs.push(s.clone()),
+                        _ => return MetaValue::Seq(mvs),
+                    }
+                }
+
+                MetaValue::Str(strs.join(separator))
+            },
+            mv => mv,
+        }
+    }
+}
+
+pub struct LookupOptionsBuilder {
+    options: LookupOptions,
+}
+
+impl LookupOptionsBuilder {
+    /// Starts from a dotted field path, as with `LookupOptions::new`.
+    pub fn new<S: AsRef<str>>(field_path: S) -> Self {
+        LookupOptionsBuilder { options: LookupOptions::new(field_path) }
+    }
+
+    /// Starts from a field name that is used as-is, as with `LookupOptions::exact`.
+    pub fn exact<S: Into<String>>(field_name: S) -> Self {
+        LookupOptionsBuilder { options: LookupOptions::exact(field_name) }
+    }
+
+    /// Adds another name for the field, tried at each level after the field name and any earlier labels.
+    pub fn label<S: Into<String>>(&mut self, label: S) -> &mut Self {
+        self.options.labels.push(label.into());
+        self
+    }
+
+    pub fn direction(&mut self, direction: LookupDirection) -> &mut Self {
+        self.options.direction = direction;
+        self
+    }
+
+    pub fn merge_strategy(&mut self, merge_strategy: MergeStrategy) -> &mut Self {
+        self.options.merge_strategy = merge_strategy;
+        self
+    }
+
+    /// Limits how many levels of ancestors (or descendants, when looking in children) are searched.
+    pub fn max_depth(&mut self, max_depth: usize) -> &mut Self {
+        self.options.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Sets whether meta sources other than meta files (e.g. cue sheets) are consulted. This is enabled by default.
+    pub fn fallback_sources(&mut self, fallback_sources: bool) -> &mut Self {
+        self.options.fallback_sources = fallback_sources;
+        self
+    }
+
+    /// Sets whether the lookup context starts tracing before the lookup.
+    pub fn trace(&mut self, trace: bool) -> &mut Self {
+        self.options.trace = trace;
+        self
+    }
+
+    pub fn join_seq<S: Into<String>>(&mut self, separator: S) -> &mut Self {
+        self.options.seq_separator = Some(separator.into());
+        self
+    }
+
+    /// Sets whether `${name}` variables in found strings are replaced, e.g. `${lib.root}` or environment variables.
+    /// This is disabled by default.
+    pub fn substitute_vars(&mut self, substitute_vars: bool) -> &mut Self {
+        self.options.substitute_vars = substitute_vars;
+        self
+    }
+
+    pub fn create(&self) -> LookupOptions {
+        self.options.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use metadata::{MetaKey, MetaValue};
+
+    use super::{LookupOptions, LookupOptionsBuilder, LookupDirection, MergeStrategy};
+
+    #[test]
+    fn test_new() {
+        let inputs_and_expected = vec![
+            ("title", ("title", vec![])),
+            ("credits.composer", ("credits", vec!["composer"])),
+            ("ids.mb.release", ("ids", vec!["mb", "release"])),
+            ("", ("", vec![])),
+        ];
+
+        for (input, (expected_field_name, expected_sub_keys)) in inputs_and_expected {
+            let produced = LookupOptions::new(input);
+            assert_eq!(expected_field_name, produced.field_name());
+            assert_eq!(expected_sub_keys, produced.sub_keys());
+        }
+
+        let produced = LookupOptions::exact("file.flac");
+        assert_eq!("file.flac", produced.field_name());
+        assert!(produced.sub_keys().is_empty());
+    }
+
+    #[test]
+    fn test_extract() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let credits = MetaValue::Map(btreemap![
+            MetaKey::Str("composer".to_string()) => str_val("Composer"),
+            MetaKey::Str("ids".to_string()) => MetaValue::Map(btreemap![
+                MetaKey::Str("mb".to_string()) => str_val("1234"),
+            ]),
+            MetaKey::Nil => str_val("default"),
+        ]);
+
+        let inputs_and_expected = vec![
+            ("credits", Some(credits.clone())),
+            ("credits.composer", Some(str_val("Composer"))),
+            ("credits.ids.mb", Some(str_val("1234"))),
+            ("credits.lyricist", None),
+            ("credits.composer.name", None),
+            ("credits.ids.mb.extra", None),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = LookupOptions::new(input).extract(credits.clone());
+            assert_eq!(expected, produced);
+        }
+    }
+
+    #[test]
+    fn test_join_seq() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let artists = MetaValue::Seq(vec![str_val("Artist A"), str_val("Artist B")]);
+        let mixed = MetaValue::Seq(vec![str_val("Artist A"), MetaValue::Seq(vec![str_val("Artist B")])]);
+
+        let inputs_and_expected = vec![
+            (artists.clone(), str_val("Artist A; Artist B")),
+            (MetaValue::Seq(vec![]), str_val("")),
+            (mixed.clone(), mixed.clone()),
+            (str_val("Artist A"), str_val("Artist A")),
+            (MetaValue::Nil, MetaValue::Nil),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = LookupOptions::new("artists").join_seq("; ").extract(input);
+            assert_eq!(Some(expected), produced);
+        }
+
+        // Without a separator, sequences are not flattened.
+        let produced = LookupOptions::new("artists").extract(artists.clone());
+        assert_eq!(Some(artists), produced);
+    }
+
+    #[test]
+    fn test_builder() {
+        let produced = LookupOptionsBuilder::new("credits.composer").create();
+        assert_eq!(LookupOptions::new("credits.composer"), produced);
+        assert_eq!(LookupDirection::Inherit, produced.direction());
+        assert_eq!(MergeStrategy::First, produced.merge_strategy());
+        assert!(produced.fallback_sources());
+        assert!(!produced.trace());
+
+        let produced = LookupOptionsBuilder::exact("album_artist")
+            .label("albumartist")
+            .label("artist")
+            .direction(LookupDirection::Parents)
+            .merge_strategy(MergeStrategy::Collect)
+            .max_depth(2)
+            .fallback_sources(false)
+            .trace(true)
+            .join_seq("; ")
+            .create();
+        assert_eq!(vec!["album_artist", "albumartist", "artist"], produced.field_names());
+        assert_eq!(LookupDirection::Parents, produced.direction());
+        assert_eq!(MergeStrategy::Collect, produced.merge_strategy());
+        assert_eq!(Some(2), produced.max_depth());
+        assert!(!produced.fallback_sources());
+        assert!(produced.trace());
+        assert_eq!(Some("; "), produced.seq_separator());
+    }
+
+    #[test]
+    fn test_merge() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let near = MetaValue::Map(btreemap![
+            MetaKey::Str("composer".to_string()) => str_val("Near Composer"),
+        ]);
+        let far = MetaValue::Map(btreemap![
+            MetaKey::Str("composer".to_string()) => str_val("Far Composer"),
+            MetaKey::Str("lyricist".to_string()) => str_val("Far Lyricist"),
+        ]);
+        let found = vec![near.clone(), str_val("Ignored"), far.clone()];
+
+        let merge_with = |merge_strategy| LookupOptionsBuilder::new("credits").merge_strategy(merge_strategy).create();
+
+        assert_eq!(Some(near.clone()), merge_with(MergeStrategy::First).merge(found.clone()));
+        assert_eq!(Some(MetaValue::Seq(found.clone())), merge_with(MergeStrategy::Collect).merge(found.clone()));
+        assert_eq!(
+            Some(MetaValue::Map(btreemap![
+                MetaKey::Str("composer".to_string()) => str_val("Near Composer"),
+                MetaKey::Str("lyricist".to_string()) => str_val("Far Lyricist"),
+            ])),
+            merge_with(MergeStrategy::MergeMaps).merge(found.clone()),
+        );
+        assert_eq!(Some(str_val("Ignored")), merge_with(MergeStrategy::MergeMaps).merge(vec![str_val("Ignored"), far.clone()]));
+        assert_eq!(None, merge_with(MergeStrategy::Collect).merge(vec![]));
+
+        let options = LookupOptionsBuilder::new("artists").merge_strategy(MergeStrategy::Collect).join_seq("; ").create();
+        assert_eq!(Some(str_val("A; B")), options.merge(vec![str_val("A"), str_val("B")]));
+    }
+}
@@ -0,0 +1,448 @@
+// The persistent cache keeps parsed meta files between runs, in a YAML file in the library root.
+// Entries remember the modification time and size of their meta file, so that stale entries are never used.
+// Paths are stored relative to the library root, so that the cache survives the library being moved.
+// The cache also remembers when each item was first seen by a build, which is what tells newly added items apart from old ones.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
+
+use library::WalkStep;
+use metadata::MetaBlock;
+use yaml::{read_yaml_file, write_yaml_file, yaml_as_meta_block, meta_block_as_yaml};
+use progress::Progress;
+use helpers::{normalize, FileStamp};
+use vfs::{Vfs, StdFs};
+use error::*;
+
+use super::{LookupContext, MetadataCache};
+
+pub const CACHE_FILE_NAME: &str = "taggu_cache.yml";
+
+/// Bumped whenever the layout of the cache file changes, so that old cache files are discarded instead of misread.
+const CACHE_FORMAT_VERSION: i64 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+struct PersistedEntry {
+    stamp: FileStamp,
+    blocks: BTreeMap<PathBuf, MetaBlock>,
+}
+
+/// Parsed meta files, keyed by their paths relative to the library root.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PersistentCache {
+    entries: BTreeMap<PathBuf, PersistedEntry>,
+    /// When each item was first seen, keyed by its path relative to the library root.
+    first_seen: BTreeMap<PathBuf, SystemTime>,
+    built_at: Option<SystemTime>,
+}
+
+/// A summary of a persistent cache, compared against the meta files currently on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PersistentCacheStats {
+    pub meta_files: usize,
+    pub items: usize,
+    pub stale: usize,
+    pub size_bytes: u64,
+}
+
+impl PersistentCacheStats {
+    /// The fraction of cached meta files that could be used as-is, from 0.0 to 1.0.
+    pub fn hit_rate(&self) -> f64 {
+        if self.meta_files == 0 {
+            0.0
+        }
+        else {
+            (self.meta_files - self.stale) as f64 / self.meta_files as f64
+        }
+    }
+}
+
+/// Times are written as seconds and nanoseconds since the Unix epoch, e.g. `1700000000.000000000`.
+fn time_as_yaml(opt_time: Option<SystemTime>) -> Yaml {
+    match opt_time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        Some(d) => Yaml::String(format!("{}.{:09}", d.as_secs(), d.subsec_nanos())),
+        None => Yaml::Null,
+    }
+}
+
+fn yaml_as_time(y: &Yaml) -> Option<SystemTime> {
+    let s = y.as_str()?;
+    let mut parts = s.splitn(2, '.');
+    let secs: u64 = parts.next()?.parse().ok()?;
+    let nanos: u32 = parts.next()?.parse().ok()?;
+
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+fn stamp_as_yaml(stamp: &FileStamp) -> Yaml {
+    let mut hsh = Hash::new();
+
+    hsh.insert(Yaml::String(String::from("modified")), time_as_yaml(stamp.modified));
+    hsh.insert(Yaml::String(String::from("len")), Yaml::Integer(stamp.len as i64));
+
+    Yaml::Hash(hsh)
+}
+
+fn yaml_as_stamp(y: &Yaml) -> Option<FileStamp> {
+    let modified = match y["modified"] {
+        Yaml::Null => None,
+        ref modified_y => Some(yaml_as_time(modified_y)?),
+    };
+
+    let len = y["len"].as_i64()?;
+
+    Some(FileStamp { modified, len: len as u64 })
+}
+
+/// Guesses when an item was added to a library from when its file was created, or failing that, last modified.
+/// An item that a build did not see must have been added after it, so the guess is never earlier than that build.
+fn guess_added_time<V: Vfs + ?Sized>(vfs: &V, abs_item_path: &Path, opt_built_at: Option<SystemTime>) -> Option<SystemTime> {
+    let md = vfs.metadata(abs_item_path).ok()?;
+    let file_time = md.created.or(md.modified)?;
+
+    Some(match opt_built_at {
+        Some(built_at) if built_at > file_time => built_at,
+        _ => file_time,
+    })
+}
+
+impl PersistentCache {
+    pub fn new() -> Self {
+        PersistentCache::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The number of item blocks stored, across all meta files.
+    pub fn item_count(&self) -> usize {
+        self.entries.values().map(|e| e.blocks.len()).sum()
+    }
+
+    /// Collects every cached meta file in a lookup context.
+    pub fn from_context(lookup_ctx: &LookupContext) -> Self {
+        let root_dir = lookup_ctx.media_lib.root_dir();
+        let mut entries = BTreeMap::new();
+
+        for (meta_fp, metadata_cache) in &lookup_ctx.cache {
+            // Entries that could not be stamped can never be checked for staleness, so they are not kept.
+            let stamp = match lookup_ctx.stamps.get(meta_fp) {
+                Some(stamp) => *stamp,
+                None => continue,
+            };
+
+            let rel_meta_fp = match meta_fp.strip_prefix(root_dir) {
+                Ok(p) => p.to_path_buf(),
+                Err(_) => continue,
+            };
+
+            let blocks = {
+                metadata_cache.iter()
+                    .filter_map(|(item_fp, mb)| item_fp.strip_prefix(root_dir).ok().map(|p| (p.to_path_buf(), (**mb).clone())))
+                    .collect()
+            };
+
+            entries.insert(rel_meta_fp, PersistedEntry { stamp, blocks });
+        }
+
+        PersistentCache { entries, ..PersistentCache::default() }
+    }
+
+    /// Parses every meta file in a library into a lookup context, and collects the results.
+    /// Every item is recorded as first seen when its file was created; use `carry_over_first_seen` to keep the times from an earlier build instead.
+    pub fn build<G: Progress>(lookup_ctx: &mut LookupContext, progress: &mut G) -> Result<Self> {
+        let media_lib = lookup_ctx.media_lib;
+        let root_dir = media_lib.root_dir();
+        let built_at = SystemTime::now();
+        let mut first_seen = BTreeMap::new();
+
+        lookup_ctx.cache_item_file_with_progress(media_lib.root_dir(), progress)?;
+
+        media_lib.walk_items(root_dir, |step| {
+            if let WalkStep::Item { path, .. } = step {
+                progress.item_scanned(path);
+                lookup_ctx.cache_item_file_with_progress(path, progress)?;
+
+                if let (Ok(rel_item_fp), Some(added_time)) = (path.strip_prefix(root_dir), guess_added_time(media_lib.vfs(), path, None)) {
+                    first_seen.insert(rel_item_fp.to_path_buf(), added_time);
+                }
+            }
+
+            Ok(true)
+        })?;
+
+        let mut cache = PersistentCache::from_context(lookup_ctx);
+        cache.first_seen = first_seen;
+        cache.built_at = Some(built_at);
+
+        Ok(cache)
+    }
+
+    /// Keeps the first seen times of items from an earlier cache, e.g. the one on disk before rebuilding it.
+    /// Items that the earlier cache did not see are recorded as first seen no earlier than when it was built.
+    pub fn carry_over_first_seen(&mut self, previous: &PersistentCache) {
+        for (rel_item_fp, first_seen) in self.first_seen.iter_mut() {
+            match previous.first_seen.get(rel_item_fp) {
+                Some(previous_first_seen) => *first_seen = *previous_first_seen,
+                None => {
+                    if let Some(previous_built_at) = previous.built_at {
+                        if previous_built_at > *first_seen {
+                            *first_seen = previous_built_at;
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// Returns when an item was first seen by a build of this cache.
+    /// For items that no build has seen, this is guessed from the item's file, see `guess_added_time`.
+    pub fn first_seen<P: AsRef<Path>, Q: AsRef<Path>>(&self, root_dir: P, abs_item_path: Q) -> Option<SystemTime> {
+        self.first_seen_with(&StdFs, root_dir, abs_item_path)
+    }
+
+    /// Like `first_seen`, but guessing from the item's file on a given file system.
+    pub fn first_seen_with<V: Vfs + ?Sized, P: AsRef<Path>, Q: AsRef<Path>>(&self, vfs: &V, root_dir: P, abs_item_path: Q) -> Option<SystemTime> {
+        let abs_item_path = abs_item_path.as_ref();
+
+        let recorded = {
+            abs_item_path.strip_prefix(root_dir.as_ref()).ok()
+                .and_then(|rel_item_fp| self.first_seen.get(rel_item_fp))
+                .cloned()
+        };
+
+        recorded.or_else(|| guess_added_time(vfs, abs_item_path, self.built_at))
+    }
+
+    /// Loads the entries that are still current into a lookup context, so that their meta files do not need to be parsed again.
+    /// Returns the number of entries loaded.
+    pub fn seed_context(&self, lookup_ctx: &mut LookupContext) -> usize {
+        let root_dir = lookup_ctx.media_lib.root_dir().to_path_buf();
+        let mut seeded = 0;
+
+        for (rel_meta_fp, entry) in &self.entries {
+            let meta_fp = normalize(root_dir.join(rel_meta_fp));
+
+            if FileStamp::read_with(lookup_ctx.media_lib.vfs(), &meta_fp).as_ref() != Some(&entry.stamp) {
+                continue;
+            }
+
+            let metadata_cache: MetadataCache = {
+                entry.blocks.iter()
+                    .map(|(rel_item_fp, mb)| (normalize(root_dir.join(rel_item_fp)), Arc::new(mb.clone())))
+                    .collect()
+            };
+
+            lookup_ctx.cache.insert(meta_fp.clone(), metadata_cache);
+            lookup_ctx.stamps.insert(meta_fp.clone(), entry.stamp);
+            lookup_ctx.touch(&meta_fp);
+
+            seeded += 1;
+        }
+
+        lookup_ctx.evict_to_capacity();
+
+        seeded
+    }
+
+    /// Returns the absolute paths of the meta files that have changed or been removed since they were cached, sorted.
+    pub fn stale_paths<P: AsRef<Path>>(&self, root_dir: P) -> Vec<PathBuf> {
+        let root_dir = root_dir.as_ref();
+
+        self.entries.iter()
+            .map(|(rel_meta_fp, entry)| (normalize(root_dir.join(rel_meta_fp)), entry))
+            .filter(|&(ref meta_fp, entry)| FileStamp::read(meta_fp).as_ref() != Some(&entry.stamp))
+            .map(|(meta_fp, _)| meta_fp)
+            .collect()
+    }
+
+    pub fn stats<P: AsRef<Path>>(&self, root_dir: P) -> PersistentCacheStats {
+        let root_dir = root_dir.as_ref();
+
+        PersistentCacheStats {
+            meta_files: self.len(),
+            items: self.item_count(),
+            stale: self.stale_paths(root_dir).len(),
+            size_bytes: fs::metadata(root_dir.join(CACHE_FILE_NAME)).map(|md| md.len()).unwrap_or(0),
+        }
+    }
+
+    pub fn as_yaml(&self) -> Yaml {
+        let mut entries_hsh = Hash::new();
+
+        for (rel_meta_fp, entry) in &self.entries {
+            let mut blocks_hsh = Hash::new();
+
+            for (rel_item_fp, mb) in &entry.blocks {
+                blocks_hsh.insert(Yaml::String(rel_item_fp.to_string_lossy().into_owned()), meta_block_as_yaml(mb));
+            }
+
+            let mut entry_hsh = Hash::new();
+            entry_hsh.insert(Yaml::String(String::from("stamp")), stamp_as_yaml(&entry.stamp));
+            entry_hsh.insert(Yaml::String(String::from("items")), Yaml::Hash(blocks_hsh));
+
+            entries_hsh.insert(Yaml::String(rel_meta_fp.to_string_lossy().into_owned()), Yaml::Hash(entry_hsh));
+        }
+
+        let mut first_seen_hsh = Hash::new();
+
+        for (rel_item_fp, first_seen) in &self.first_seen {
+            first_seen_hsh.insert(Yaml::String(rel_item_fp.to_string_lossy().into_owned()), time_as_yaml(Some(*first_seen)));
+        }
+
+        let mut hsh = Hash::new();
+        hsh.insert(Yaml::String(String::from("version")), Yaml::Integer(CACHE_FORMAT_VERSION));
+        hsh.insert(Yaml::String(String::from("built_at")), time_as_yaml(self.built_at));
+        hsh.insert(Yaml::String(String::from("meta_files")), Yaml::Hash(entries_hsh));
+        hsh.insert(Yaml::String(String::from("first_seen")), Yaml::Hash(first_seen_hsh));
+
+        Yaml::Hash(hsh)
+    }
+
+    /// Reads a cache from YAML.
+    /// Caches written by a different version are treated as empty, and any malformed entries are skipped.
+    pub fn from_yaml(y: &Yaml) -> Self {
+        let mut cache = PersistentCache::new();
+
+        if y["version"].as_i64() != Some(CACHE_FORMAT_VERSION) {
+            return cache;
+        }
+
+        cache.built_at = yaml_as_time(&y["built_at"]);
+
+        if let Some(entries_hsh) = y["meta_files"].as_hash() {
+            for (rel_meta_fp_y, entry_y) in entries_hsh {
+                let opt_entry = (|| {
+                    let rel_meta_fp = PathBuf::from(rel_meta_fp_y.as_str()?);
+                    let stamp = yaml_as_stamp(&entry_y["stamp"])?;
+                    let mut blocks = BTreeMap::new();
+
+                    for (rel_item_fp_y, mb_y) in entry_y["items"].as_hash()? {
+                        blocks.insert(PathBuf::from(rel_item_fp_y.as_str()?), yaml_as_meta_block(mb_y)?);
+                    }
+
+                    Some((rel_meta_fp, PersistedEntry { stamp, blocks }))
+                })();
+
+                match opt_entry {
+                    Some((rel_meta_fp, entry)) => { cache.entries.insert(rel_meta_fp, entry); },
+                    None => { warn!("skipping malformed cache entry"); },
+                }
+            }
+        }
+
+        if let Some(first_seen_hsh) = y["first_seen"].as_hash() {
+            for (rel_item_fp_y, first_seen_y) in first_seen_hsh {
+                match (rel_item_fp_y.as_str(), yaml_as_time(first_seen_y)) {
+                    (Some(rel_item_fp), Some(first_seen)) => { cache.first_seen.insert(PathBuf::from(rel_item_fp), first_seen); },
+                    _ => { warn!("skipping malformed first seen time"); },
+                }
+            }
+        }
+
+        cache
+    }
+
+    /// Loads the cache file in a library root, or an empty cache if there is none.
+    pub fn load<P: AsRef<Path>>(root_dir: P) -> Result<Self> {
+        let cache_fp = root_dir.as_ref().join(CACHE_FILE_NAME);
+
+        if !cache_fp.is_file() {
+            return Ok(PersistentCache::new());
+        }
+
+        let y = read_yaml_file(&cache_fp).chain_err(|| format!("unable to read cache file: '{}'", cache_fp.to_string_lossy()))?;
+
+        Ok(PersistentCache::from_yaml(&y))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, root_dir: P) -> Result<()> {
+        write_yaml_file(root_dir.as_ref().join(CACHE_FILE_NAME), &self.as_yaml())
+    }
+
+    /// Removes the cache file in a library root.
+    /// Returns true if there was one to remove.
+    pub fn clear<P: AsRef<Path>>(root_dir: P) -> Result<bool> {
+        let cache_fp = root_dir.as_ref().join(CACHE_FILE_NAME);
+
+        if !cache_fp.is_file() {
+            return Ok(false);
+        }
+
+        fs::remove_file(&cache_fp)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use metadata::MetaValue;
+    use lookup::LookupContext;
+    use progress::ProgressCounts;
+    use fixtures::default_setup;
+
+    use super::{PersistentCache, CACHE_FILE_NAME};
+
+    #[test]
+    fn test_persistent_cache() {
+        let (temp_media_root, media_lib) = default_setup("test_persistent_cache");
+        let tp = temp_media_root.path();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        let mut progress = ProgressCounts::default();
+
+        let built = PersistentCache::build(&mut lookup_ctx, &mut progress).expect("Unable to build cache");
+        assert!(!built.is_empty());
+        assert_eq!(built.len(), progress.meta_files_parsed);
+        assert!(built.stale_paths(tp).is_empty());
+
+        // The cache survives a round trip through its file.
+        built.save(tp).expect("Unable to save cache");
+        let loaded = PersistentCache::load(tp).expect("Unable to load cache");
+        assert_eq!(built, loaded);
+
+        let stats = loaded.stats(tp);
+        assert_eq!(built.len(), stats.meta_files);
+        assert_eq!(built.item_count(), stats.items);
+        assert_eq!(0, stats.stale);
+        assert!(stats.size_bytes > 0);
+        assert_eq!(1.0, stats.hit_rate());
+
+        // Changed meta files are reported, and not loaded into lookup contexts.
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        let mut f = File::create(disc_fp.join("self.yml")).unwrap();
+        writeln!(f, "self_key: changed_val").unwrap();
+
+        assert_eq!(vec![disc_fp.join("self.yml")], loaded.stale_paths(tp));
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(loaded.len() - 1, loaded.seed_context(&mut lookup_ctx));
+
+        assert_eq!(Some(MetaValue::Str(String::from("self_val"))), lookup_ctx.lookup_origin(tp.join("ALBUM_01"), "self_key").unwrap());
+        assert_eq!(Some(MetaValue::Str(String::from("changed_val"))), lookup_ctx.lookup_origin(&disc_fp, "self_key").unwrap());
+
+        // Seeded meta files are reused instead of parsed.
+        let stats = lookup_ctx.stats();
+        assert_eq!(1, stats.misses);
+
+        assert!(PersistentCache::clear(tp).expect("Unable to clear cache"));
+        assert!(!tp.join(CACHE_FILE_NAME).exists());
+        assert!(!PersistentCache::clear(tp).expect("Unable to clear cache"));
+        assert!(PersistentCache::load(tp).expect("Unable to load cache").is_empty());
+    }
+}
@@ -0,0 +1,646 @@
+// Queries select the items of a library by their metadata, e.g. to build a playlist or to show a table of items.
+// A query is a condition, followed by an optional ordering and an optional projection, in either order:
+//     genre ~ "psy*" && year >= 2020 ORDER BY year DESC, title SELECT artist, title
+// A condition is one of:
+// - a field path on its own, which holds if the item has a value for it (e.g. `artist`)
+// - a field path compared to a value, with `==`, `!=`, `<`, `<=`, `>`, `>=`, or `~` and `!~` for glob patterns (e.g. `year >= 2020`)
+// - `!` followed by a condition, or conditions combined with `&&` and `||`, grouped with parentheses
+// Values are bare words (e.g. `2020`) or quoted strings, with `\"` and `\\` as escapes (e.g. `"Aphex Twin"`).
+// Fields are looked up with inheritance, and for fields with several values, a comparison holds if it holds for any of them.
+// For fields with a hierarchy in the ontology of the lookup context, `==` and `!=` also match narrower terms, e.g. `genre == electronic` matches `psytrance`.
+// Without a condition, every item matches.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use glob;
+use yaml_rust::Yaml;
+
+use metadata::MetaValue;
+use library::WalkStep;
+use lookup::LookupContext;
+use lookup::options::LookupOptions;
+use helpers::normalize;
+use error::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Matches a glob pattern, without regard to case.
+    Glob,
+    NotGlob,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// Holds for every item.
+    All,
+    /// Holds if the item has a value for the field.
+    Has(String),
+    Compare(String, CompareOp, String),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey {
+    pub field_path: String,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub condition: Condition,
+    /// Items are sorted by each key in turn, and are left in walk order otherwise.
+    pub order_by: Vec<SortKey>,
+    /// The fields to return for each item.
+    pub projection: Vec<String>,
+}
+
+impl Query {
+    pub fn parse(s: &str) -> Result<Query> {
+        let tokens = tokenize(s).chain_err(|| ErrorKind::InvalidQuery(s.to_string()))?;
+        let mut parser = Parser { tokens, pos: 0 };
+
+        parser.query().chain_err(|| ErrorKind::InvalidQuery(s.to_string()))
+    }
+}
+
+/// Reads named queries, e.g. for smart playlists, from a mapping of names to query strings:
+///     recent_psy: genre ~ "psy*" && year >= 2020 ORDER BY year DESC
+/// Every query is parsed up front, so that a mistake in any of them is found right away.
+pub fn read_saved_queries(y: &Yaml) -> Result<BTreeMap<String, Query>> {
+    let mut saved_queries = BTreeMap::new();
+
+    match *y {
+        Yaml::Hash(ref hsh) => {
+            for (name_y, query_y) in hsh {
+                match (name_y.as_str(), query_y.as_str()) {
+                    (Some(name), Some(query_str)) => {
+                        let query = Query::parse(query_str).chain_err(|| format!("invalid saved query: '{}'", name))?;
+                        saved_queries.insert(name.to_string(), query);
+                    },
+                    _ => bail!("saved queries must map names to query strings"),
+                }
+            }
+        },
+        Yaml::Null => {},
+        _ => bail!("saved queries must be a mapping of names to query strings"),
+    }
+
+    Ok(saved_queries)
+}
+
+/// An item that matched a query, along with the values of the projected fields, in projection order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryRow {
+    pub item_path: PathBuf,
+    pub fields: Vec<(String, Option<MetaValue>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Symbol(&'static str),
+}
+
+// Longer symbols come first, so that e.g. `!=` is not read as `!` followed by `=`.
+const SYMBOLS: &[&str] = &["==", "!=", "!~", "<=", ">=", "&&", "||", "<", ">", "~", "!", "(", ")", ","];
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = vec![];
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        if let Some(symbol) = SYMBOLS.iter().find(|sym| sym.chars().enumerate().all(|(i, sc)| chars.get(pos + i) == Some(&sc))) {
+            tokens.push(Token::Symbol(symbol));
+            pos += symbol.chars().count();
+            continue;
+        }
+
+        if c == '"' {
+            let mut text = String::new();
+            pos += 1;
+
+            loop {
+                match chars.get(pos).cloned() {
+                    Some('"') => {
+                        pos += 1;
+                        break;
+                    },
+                    Some('\\') => {
+                        match chars.get(pos + 1).cloned() {
+                            Some(c) if c == '"' || c == '\\' => {
+                                text.push(c);
+                                pos += 2;
+                            },
+                            _ => bail!("invalid escape at position {}", pos),
+                        }
+                    },
+                    Some(c) => {
+                        text.push(c);
+                        pos += 1;
+                    },
+                    None => bail!("unclosed string"),
+                }
+            }
+
+            tokens.push(Token::Quoted(text));
+            continue;
+        }
+
+        let mut word = String::new();
+
+        while let Some(&c) = chars.get(pos) {
+            if c.is_whitespace() || c == '"' || "()!=~<>&|,".contains(c) {
+                break;
+            }
+
+            word.push(c);
+            pos += 1;
+        }
+
+        ensure!(!word.is_empty(), format!("unexpected character at position {}: '{}'", pos, c));
+
+        tokens.push(Token::Word(word));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Symbol(s)) => s == symbol,
+            _ => false,
+        }
+    }
+
+    /// Keywords are not case sensitive, so `ORDER BY` and `order by` are the same.
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Word(ref w)) => w.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        ensure!(self.is_keyword(keyword), format!("expected '{}' at token {}", keyword, self.pos));
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn query(&mut self) -> Result<Query> {
+        let condition = if self.peek().is_none() || self.is_keyword("order") || self.is_keyword("select") {
+            Condition::All
+        }
+        else {
+            self.or()?
+        };
+
+        let mut opt_order_by = None;
+        let mut opt_projection = None;
+
+        while self.peek().is_some() {
+            if self.is_keyword("order") && opt_order_by.is_none() {
+                self.pos += 1;
+                self.expect_keyword("by")?;
+                opt_order_by = Some(self.sort_keys()?);
+            }
+            else if self.is_keyword("select") && opt_projection.is_none() {
+                self.pos += 1;
+                opt_projection = Some(self.field_list()?);
+            }
+            else {
+                bail!("unexpected token {}: {:?}", self.pos, self.tokens[self.pos]);
+            }
+        }
+
+        Ok(Query {
+            condition,
+            order_by: opt_order_by.unwrap_or_default(),
+            projection: opt_projection.unwrap_or_default(),
+        })
+    }
+
+    fn or(&mut self) -> Result<Condition> {
+        let mut cond = self.and()?;
+
+        while self.is_symbol("||") {
+            self.pos += 1;
+            cond = Condition::Or(Box::new(cond), Box::new(self.and()?));
+        }
+
+        Ok(cond)
+    }
+
+    fn and(&mut self) -> Result<Condition> {
+        let mut cond = self.unary()?;
+
+        while self.is_symbol("&&") {
+            self.pos += 1;
+            cond = Condition::And(Box::new(cond), Box::new(self.unary()?));
+        }
+
+        Ok(cond)
+    }
+
+    fn unary(&mut self) -> Result<Condition> {
+        if self.is_symbol("!") {
+            self.pos += 1;
+            return Ok(Condition::Not(Box::new(self.unary()?)));
+        }
+
+        if self.is_symbol("(") {
+            self.pos += 1;
+            let cond = self.or()?;
+
+            ensure!(self.is_symbol(")"), format!("expected ')' at token {}", self.pos));
+            self.pos += 1;
+
+            return Ok(cond);
+        }
+
+        let field_path = self.field_path()?;
+
+        let op = match self.peek() {
+            Some(&Token::Symbol("==")) => CompareOp::Eq,
+            Some(&Token::Symbol("!=")) => CompareOp::Ne,
+            Some(&Token::Symbol("<")) => CompareOp::Lt,
+            Some(&Token::Symbol("<=")) => CompareOp::Le,
+            Some(&Token::Symbol(">")) => CompareOp::Gt,
+            Some(&Token::Symbol(">=")) => CompareOp::Ge,
+            Some(&Token::Symbol("~")) => CompareOp::Glob,
+            Some(&Token::Symbol("!~")) => CompareOp::NotGlob,
+            _ => return Ok(Condition::Has(field_path)),
+        };
+        self.pos += 1;
+
+        let value = match self.peek().cloned() {
+            Some(Token::Word(w)) => w,
+            Some(Token::Quoted(q)) => q,
+            _ => bail!("expected a value at token {}", self.pos),
+        };
+        self.pos += 1;
+
+        if op == CompareOp::Glob || op == CompareOp::NotGlob {
+            glob::Pattern::new(&value).chain_err(|| format!("invalid glob pattern: '{}'", value))?;
+        }
+
+        Ok(Condition::Compare(field_path, op, value))
+    }
+
+    fn field_path(&mut self) -> Result<String> {
+        match self.peek().cloned() {
+            Some(Token::Word(ref w)) if !is_keyword(w) => {
+                self.pos += 1;
+                Ok(w.clone())
+            },
+            _ => bail!("expected a field at token {}", self.pos),
+        }
+    }
+
+    fn sort_keys(&mut self) -> Result<Vec<SortKey>> {
+        let mut sort_keys = vec![];
+
+        loop {
+            let field_path = self.field_path()?;
+            let mut descending = false;
+
+            if self.is_keyword("desc") {
+                self.pos += 1;
+                descending = true;
+            }
+            else if self.is_keyword("asc") {
+                self.pos += 1;
+            }
+
+            sort_keys.push(SortKey { field_path, descending });
+
+            if !self.is_symbol(",") {
+                return Ok(sort_keys);
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn field_list(&mut self) -> Result<Vec<String>> {
+        let mut field_paths = vec![self.field_path()?];
+
+        while self.is_symbol(",") {
+            self.pos += 1;
+            field_paths.push(self.field_path()?);
+        }
+
+        Ok(field_paths)
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    ["order", "by", "select", "asc", "desc"].iter().any(|k| word.eq_ignore_ascii_case(k))
+}
+
+/// Flattens a value into its scalar strings, skipping nils and mappings.
+fn flatten_strs(mv: &MetaValue, strs: &mut Vec<String>) {
+    match *mv {
+        MetaValue::Str(ref s) => strs.push(s.clone()),
+        MetaValue::Seq(ref mvs) => {
+            for mv in mvs {
+                flatten_strs(mv, strs);
+            }
+        },
+        MetaValue::Nil | MetaValue::Map(_) => {},
+    }
+}
+
+fn field_strs(lookup_ctx: &mut LookupContext, abs_item_path: &Path, field_path: &str) -> Result<Vec<String>> {
+    let mut strs = vec![];
+
+    if let Some(mv) = lookup_ctx.lookup(abs_item_path, &LookupOptions::new(field_path))? {
+        flatten_strs(&mv, &mut strs);
+    }
+
+    Ok(strs)
+}
+
+fn as_number(s: &str) -> Option<f64> {
+    s.trim().parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+/// Compares values as numbers if they both are numbers, and as strings otherwise.
+fn compare_values(a: &str, b: &str) -> Ordering {
+    match (as_number(a), as_number(b)) {
+        (Some(na), Some(nb)) => na.partial_cmp(&nb).unwrap_or(Ordering::Equal),
+        _ => a.cmp(b),
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, lookup_ctx: &mut LookupContext, abs_item_path: &Path) -> Result<bool> {
+        match *self {
+            Condition::All => Ok(true),
+            Condition::Has(ref field_path) => Ok(!field_strs(lookup_ctx, abs_item_path, field_path)?.is_empty()),
+            Condition::Compare(ref field_path, op, ref value) => {
+                let strs = field_strs(lookup_ctx, abs_item_path, field_path)?;
+                let value_mv = MetaValue::Str(value.clone());
+                let match_options = glob::MatchOptions { case_sensitive: false, ..glob::MatchOptions::new() };
+
+                let any = |pred: &Fn(&str) -> bool| strs.iter().any(|s| pred(s));
+
+                // For fields with a hierarchy of terms, a value also equals any of its broader terms.
+                let ontology = lookup_ctx.ontology();
+                let has_hierarchy = ontology.has_field(field_path);
+                let equals = |s: &str| {
+                    MetaValue::Str(s.to_string()).eq_coerced(&value_mv) || (has_hierarchy && ontology.is_a(field_path.as_str(), s, value.as_str()))
+                };
+
+                Ok(match op {
+                    CompareOp::Eq => any(&equals),
+                    CompareOp::Ne => !any(&equals),
+                    CompareOp::Lt => any(&|s| compare_values(s, value) == Ordering::Less),
+                    CompareOp::Le => any(&|s| compare_values(s, value) != Ordering::Greater),
+                    CompareOp::Gt => any(&|s| compare_values(s, value) == Ordering::Greater),
+                    CompareOp::Ge => any(&|s| compare_values(s, value) != Ordering::Less),
+                    CompareOp::Glob | CompareOp::NotGlob => {
+                        let pattern = glob::Pattern::new(value).chain_err(|| format!("invalid glob pattern: '{}'", value))?;
+                        let found = any(&|s| pattern.matches_with(s, &match_options));
+
+                        if op == CompareOp::Glob { found } else { !found }
+                    },
+                })
+            },
+            Condition::Not(ref cond) => Ok(!cond.matches(lookup_ctx, abs_item_path)?),
+            Condition::And(ref a, ref b) => Ok(a.matches(lookup_ctx, abs_item_path)? && b.matches(lookup_ctx, abs_item_path)?),
+            Condition::Or(ref a, ref b) => Ok(a.matches(lookup_ctx, abs_item_path)? || b.matches(lookup_ctx, abs_item_path)?),
+        }
+    }
+}
+
+/// Compares the sort values of two items for one key, where items without a value always come last.
+fn compare_sort_values(a: &Option<String>, b: &Option<String>, descending: bool) -> Ordering {
+    match (a.as_ref(), b.as_ref()) {
+        (Some(va), Some(vb)) => {
+            let ordering = compare_values(va, vb);
+            if descending { ordering.reverse() } else { ordering }
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Finds the non-directory items in a directory and its selected subdirectories that match a query, sorted and projected as it says.
+/// Items marked as ignored are left out, along with everything inside them.
+/// Items that sort the same stay in walk order, and items are sorted by the first value of a field with several values.
+pub fn run_query<P: AsRef<Path>>(lookup_ctx: &mut LookupContext, abs_dir_path: P, query: &Query) -> Result<Vec<QueryRow>> {
+    let abs_dir_path = normalize(abs_dir_path.as_ref());
+    let media_lib = lookup_ctx.media_lib;
+
+    // Rule: dir path must be a directory.
+    ensure!(media_lib.vfs().is_dir(&abs_dir_path), ErrorKind::NotADirectory(abs_dir_path.clone()));
+
+    let mut matched: Vec<(PathBuf, Vec<Option<String>>)> = vec![];
+
+    media_lib.walk_items(&abs_dir_path, |step| {
+        let (item_path, is_dir) = match step {
+            WalkStep::Item { path, is_dir } => (path, is_dir),
+            _ => return Ok(true),
+        };
+
+        if lookup_ctx.is_ignored(item_path)? {
+            return Ok(false);
+        }
+
+        if !is_dir && query.condition.matches(lookup_ctx, item_path)? {
+            let mut sort_values = vec![];
+
+            for sort_key in &query.order_by {
+                sort_values.push(field_strs(lookup_ctx, item_path, &sort_key.field_path)?.into_iter().next());
+            }
+
+            matched.push((item_path.to_path_buf(), sort_values));
+        }
+
+        Ok(true)
+    })?;
+
+    // The sort is stable, so ties keep their walk order.
+    matched.sort_by(|&(_, ref a), &(_, ref b)| {
+        query.order_by.iter().enumerate()
+            .map(|(i, sort_key)| compare_sort_values(&a[i], &b[i], sort_key.descending))
+            .find(|&o| o != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let mut rows = vec![];
+
+    for (item_path, _) in matched {
+        let mut fields = vec![];
+
+        for field_path in &query.projection {
+            fields.push((field_path.clone(), lookup_ctx.lookup(&item_path, &LookupOptions::new(field_path))?));
+        }
+
+        rows.push(QueryRow { item_path, fields });
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use yaml_rust::YamlLoader;
+
+    use metadata::MetaValue;
+    use lookup::LookupContext;
+    use ontology::Ontology;
+    use fixtures::default_setup;
+
+    use super::{Query, Condition, CompareOp, SortKey, read_saved_queries};
+
+    #[test]
+    fn test_parse() {
+        let has = |s: &str| Condition::Has(s.to_string());
+        let cmp = |f: &str, op: CompareOp, v: &str| Condition::Compare(f.to_string(), op, v.to_string());
+        let key = |f: &str, descending: bool| SortKey { field_path: f.to_string(), descending };
+
+        let query = Query::parse(r#"genre ~ "psy*" && year >= 2020"#).unwrap();
+        assert_eq!(Condition::And(Box::new(cmp("genre", CompareOp::Glob, "psy*")), Box::new(cmp("year", CompareOp::Ge, "2020"))), query.condition);
+        assert!(query.order_by.is_empty());
+        assert!(query.projection.is_empty());
+
+        let query = Query::parse("!(artist || credits.composer != \"A \\\"B\\\"\") ORDER BY year desc, title select artist, title").unwrap();
+        assert_eq!(Condition::Not(Box::new(Condition::Or(
+            Box::new(has("artist")),
+            Box::new(cmp("credits.composer", CompareOp::Ne, "A \"B\"")),
+        ))), query.condition);
+        assert_eq!(vec![key("year", true), key("title", false)], query.order_by);
+        assert_eq!(vec![String::from("artist"), String::from("title")], query.projection);
+
+        // Without a condition, everything matches, and the clauses can come in any order.
+        let query = Query::parse("SELECT title ORDER BY track ASC").unwrap();
+        assert_eq!(Condition::All, query.condition);
+        assert_eq!(vec![key("track", false)], query.order_by);
+        assert_eq!(Condition::All, Query::parse("").unwrap().condition);
+
+        // `&&` binds tighter than `||`.
+        let query = Query::parse("a || b && c").unwrap();
+        assert_eq!(Condition::Or(Box::new(has("a")), Box::new(Condition::And(Box::new(has("b")), Box::new(has("c"))))), query.condition);
+
+        let invalid_inputs = vec![
+            "year >=",
+            "(a",
+            "a b",
+            "a == \"unclosed",
+            "ORDER year",
+            "SELECT",
+            "SELECT a SELECT b",
+            "a ~ \"[\"",
+            "a & b",
+        ];
+
+        for input in invalid_inputs {
+            assert!(Query::parse(input).is_err(), "expected error for: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_run_query() {
+        let (temp_media_root, media_lib) = default_setup("test_run_query");
+        let tp = temp_media_root.path();
+
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        let mut f = File::create(disc_fp.join("item.yml")).unwrap();
+        writeln!(f, "- {{title: B, year: '2021', genre: Psytrance, track: '1'}}").unwrap();
+        writeln!(f, "- {{title: A, year: '2019', genre: [Ambient, Psybient], track: '2'}}").unwrap();
+        writeln!(f, "- {{title: C, year: '2021.0', genre: House, track: '10'}}").unwrap();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        let track_fp = |n: &str| disc_fp.join(format!("TRACK_{}.flac", n));
+        let paths = |rows: Vec<super::QueryRow>| rows.into_iter().map(|r| r.item_path).collect::<Vec<_>>();
+
+        // Globs ignore case and match any value, and numbers compare as numbers.
+        let rows = lookup_ctx.query(tp, r#"genre ~ "psy*" ORDER BY year DESC"#).unwrap();
+        assert_eq!(vec![track_fp("01"), track_fp("02")], paths(rows));
+
+        let rows = lookup_ctx.query(tp, "year == 2021 ORDER BY track DESC SELECT title, missing").unwrap();
+        assert_eq!(vec![track_fp("03"), track_fp("01")], paths(rows.clone()));
+        assert_eq!(vec![
+            (String::from("title"), Some(MetaValue::Str(String::from("C")))),
+            (String::from("missing"), None),
+        ], rows[0].fields);
+
+        // Items without a sort value come last, whatever the direction.
+        let rows = lookup_ctx.query(&tp.join("ALBUM_01"), "ORDER BY title DESC").unwrap();
+        assert_eq!(6, rows.len());
+        assert_eq!(vec![track_fp("03"), track_fp("01"), track_fp("02")], paths(rows.clone())[..3].to_vec());
+        assert_eq!(tp.join("ALBUM_01").join("DISC_02").join("TRACK_01.flac"), rows[3].item_path);
+
+        // Inherited fields are matched too.
+        assert_eq!(6, lookup_ctx.query(&tp.join("ALBUM_01"), "ALBUM_01_self_key && !missing").unwrap().len());
+
+        // With an ontology, broader terms match narrower ones.
+        let mut ontology = Ontology::new();
+        ontology
+            .term("genre", "Psytrance", &["Trance"])
+            .term("genre", "trance", &["electronic"])
+            .term("genre", "psybient", &["ambient"]);
+        lookup_ctx.set_ontology(ontology);
+
+        assert_eq!(vec![track_fp("01")], paths(lookup_ctx.query(tp, "genre == electronic").unwrap()));
+        assert_eq!(vec![track_fp("02")], paths(lookup_ctx.query(tp, "genre == AMBIENT").unwrap()));
+        assert_eq!(vec![track_fp("02"), track_fp("03")], paths(lookup_ctx.query(&disc_fp, "genre != trance").unwrap()));
+
+        assert!(lookup_ctx.query(track_fp("01"), "title").is_err());
+        assert!(lookup_ctx.query(tp, "title ==").is_err());
+    }
+
+    #[test]
+    fn test_read_saved_queries() {
+        let text = "recent_psy: genre ~ \"psy*\" && year >= 2020\nby_title: ORDER BY title\n";
+        let y = &YamlLoader::load_from_str(text).unwrap()[0];
+
+        let saved_queries = read_saved_queries(y).unwrap();
+        assert_eq!(vec!["by_title", "recent_psy"], saved_queries.keys().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(Query::parse("genre ~ \"psy*\" && year >= 2020").unwrap(), saved_queries["recent_psy"]);
+
+        let invalid_inputs = vec![
+            "broken: year >=",
+            "nested: {a: b}",
+            "- a",
+        ];
+
+        for input in invalid_inputs {
+            let y = &YamlLoader::load_from_str(input).unwrap()[0];
+            assert!(read_saved_queries(y).is_err(), "expected error for: {}", input);
+        }
+    }
+}
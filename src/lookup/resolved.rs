@@ -0,0 +1,131 @@
+// This module provides resolved blocks, which remember where each of their fields came from.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Iter;
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::path::{Path, PathBuf};
+
+use metadata::{MetaBlock, MetaTarget, MetaValue};
+
+/// Where a resolved field was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSource {
+    pub meta_path: PathBuf,
+    pub meta_target: MetaTarget,
+    /// How many levels above the item the field was found, where zero is the item itself.
+    pub depth: usize,
+}
+
+impl FieldSource {
+    /// Returns true if the field was found in a block describing the item itself, instead of being inherited.
+    pub fn is_origin(&self) -> bool {
+        self.depth == 0
+    }
+}
+
+impl Display for FieldSource {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{} ({:?}, depth {})", self.meta_path.to_string_lossy(), self.meta_target, self.depth)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedField {
+    pub value: MetaValue,
+    pub source: FieldSource,
+}
+
+/// The fields of an item after inheritance, each along with the meta file that provided it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ResolvedBlock {
+    fields: BTreeMap<String, ResolvedField>,
+}
+
+impl ResolvedBlock {
+    pub fn new() -> Self {
+        ResolvedBlock { fields: BTreeMap::new() }
+    }
+
+    /// Adds a field, unless a field with the same name was already resolved.
+    /// Returns true if the field was added.
+    pub fn insert_if_absent<S: Into<String>>(&mut self, field_name: S, value: MetaValue, source: FieldSource) -> bool {
+        let field_name = field_name.into();
+
+        if self.fields.contains_key(&field_name) {
+            return false;
+        }
+
+        self.fields.insert(field_name, ResolvedField { value, source });
+        true
+    }
+
+    pub fn get<S: AsRef<str>>(&self, field_name: S) -> Option<&ResolvedField> {
+        self.fields.get(field_name.as_ref())
+    }
+
+    pub fn value<S: AsRef<str>>(&self, field_name: S) -> Option<&MetaValue> {
+        self.get(field_name).map(|rf| &rf.value)
+    }
+
+    pub fn source<S: AsRef<str>>(&self, field_name: S) -> Option<&FieldSource> {
+        self.get(field_name).map(|rf| &rf.source)
+    }
+
+    pub fn iter(&self) -> Iter<String, ResolvedField> {
+        self.fields.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the fields that came from a given meta file, e.g. to write back only the values that belong there.
+    pub fn fields_from<P: AsRef<Path>>(&self, meta_path: P) -> MetaBlock {
+        let meta_path = meta_path.as_ref();
+
+        self.fields.iter()
+            .filter(|&(_, rf)| rf.source.meta_path == meta_path)
+            .map(|(k, rf)| (k.clone(), rf.value.clone()))
+            .collect()
+    }
+
+    /// Drops the source information, leaving a plain block.
+    pub fn into_meta_block(self) -> MetaBlock {
+        self.fields.into_iter().map(|(k, rf)| (k, rf.value)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use metadata::{MetaTarget, MetaValue};
+
+    use super::{ResolvedBlock, FieldSource};
+
+    #[test]
+    fn test_resolved_block() {
+        let source = |p: &str, depth: usize| FieldSource { meta_path: PathBuf::from(p), meta_target: MetaTarget::Contains, depth };
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let mut rb = ResolvedBlock::new();
+        assert!(rb.insert_if_absent("title", str_val("Near"), source("/a/self.yml", 0)));
+        assert!(!rb.insert_if_absent("title", str_val("Far"), source("/self.yml", 1)));
+        assert!(rb.insert_if_absent("album", str_val("Album"), source("/self.yml", 1)));
+
+        assert_eq!(Some(&str_val("Near")), rb.value("title"));
+        assert!(rb.source("title").unwrap().is_origin());
+        assert!(!rb.source("album").unwrap().is_origin());
+        assert_eq!(btreemap![String::from("album") => str_val("Album")], rb.fields_from("/self.yml"));
+        assert_eq!("/self.yml (Contains, depth 1)", format!("{}", rb.source("album").unwrap()));
+
+        assert_eq!(btreemap![
+            String::from("album") => str_val("Album"),
+            String::from("title") => str_val("Near"),
+        ], rb.into_meta_block());
+    }
+}
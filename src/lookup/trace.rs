@@ -0,0 +1,86 @@
+// This module provides a record of the steps taken while performing lookups, useful for debugging inheritance.
+
+use std::path::{Path, PathBuf};
+use std::fmt::{Formatter, Result as FmtResult, Display};
+
+/// The outcome of consulting a single meta file for a field.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TraceDecision {
+    /// The meta file provided a block for the item, and the block contained the field.
+    Found,
+    /// The meta file provided a block for the item, but the block did not contain the field.
+    FieldMissing,
+    /// The meta file did not provide a block for the item.
+    NoBlock,
+}
+
+impl Display for TraceDecision {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            TraceDecision::Found => write!(f, "found"),
+            TraceDecision::FieldMissing => write!(f, "field missing"),
+            TraceDecision::NoBlock => write!(f, "no block"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TraceStep {
+    pub item_path: PathBuf,
+    pub meta_path: PathBuf,
+    pub field_name: String,
+    pub decision: TraceDecision,
+}
+
+/// Ordered list of the meta files consulted during one or more lookups, along with the outcome of each.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct LookupTrace {
+    steps: Vec<TraceStep>,
+}
+
+impl LookupTrace {
+    pub fn new() -> Self {
+        LookupTrace { steps: vec![] }
+    }
+
+    pub fn record<P, Q, S>(&mut self, item_path: P, meta_path: Q, field_name: S, decision: TraceDecision)
+    where P: AsRef<Path>,
+          Q: AsRef<Path>,
+          S: AsRef<str>,
+    {
+        self.steps.push(TraceStep {
+            item_path: item_path.as_ref().to_path_buf(),
+            meta_path: meta_path.as_ref().to_path_buf(),
+            field_name: field_name.as_ref().to_string(),
+            decision,
+        });
+    }
+
+    pub fn steps(&self) -> &[TraceStep] {
+        &self.steps
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+}
+
+impl Display for LookupTrace {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        for (i, step) in self.steps.iter().enumerate() {
+            writeln!(f, "{:>3}. {}: '{}' in '{}' (item '{}')",
+                i + 1,
+                step.decision,
+                step.field_name,
+                step.meta_path.to_string_lossy(),
+                step.item_path.to_string_lossy(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+// Measures loudness as described in ITU-R BS.1770 (and EBU R128), for computing ReplayGain 2.0 values.
+// Samples are fed in as they are decoded, so that entire tracks never need to be held in memory.
+
+use std::f64::consts::PI;
+
+/// The loudness that ReplayGain 2.0 adjusts tracks towards, in LUFS.
+pub const REFERENCE_LOUDNESS: f64 = -18.0;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE_OFFSET: f64 = -10.0;
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+
+/// A second order IIR filter, in direct form I.
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad { b, a, x: [0.0; 2], y: [0.0; 2] }
+    }
+
+    fn process(&mut self, x0: f64) -> f64 {
+        let y0 = self.b[0] * x0 + self.b[1] * self.x[0] + self.b[2] * self.x[1] - self.a[1] * self.y[0] - self.a[2] * self.y[1];
+
+        self.x = [x0, self.x[0]];
+        self.y = [y0, self.y[0]];
+
+        y0
+    }
+}
+
+/// Creates the two stages of the K-weighting filter for a sample rate.
+fn k_weighting_filters(sample_rate: u32) -> (Biquad, Biquad) {
+    let rate = f64::from(sample_rate);
+
+    // High shelf, modelling the acoustic effects of the head.
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+
+    let shelf = Biquad::new(
+        [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // High pass.
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    (shelf, high_pass)
+}
+
+fn power_to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// The result of measuring a stream of samples.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Measurement {
+    /// The mean power of each 400ms gating block.
+    pub block_powers: Vec<f64>,
+    /// The largest absolute sample value, where full scale is 1.0.
+    pub peak: f64,
+}
+
+impl Measurement {
+    /// Combines measurements, e.g. of the tracks of an album.
+    pub fn combine<'a, I: IntoIterator<Item = &'a Measurement>>(measurements: I) -> Measurement {
+        let mut combined = Measurement::default();
+
+        for m in measurements {
+            combined.block_powers.extend_from_slice(&m.block_powers);
+            combined.peak = combined.peak.max(m.peak);
+        }
+
+        combined
+    }
+
+    /// Returns the gated integrated loudness in LUFS, or `None` if everything is too quiet to measure.
+    pub fn integrated_loudness(&self) -> Option<f64> {
+        let abs_gated: Vec<f64> = self.block_powers.iter().cloned().filter(|&p| power_to_lufs(p) > ABSOLUTE_GATE).collect();
+
+        if abs_gated.is_empty() {
+            return None;
+        }
+
+        let relative_gate = power_to_lufs(abs_gated.iter().sum::<f64>() / abs_gated.len() as f64) + RELATIVE_GATE_OFFSET;
+
+        let rel_gated: Vec<f64> = abs_gated.into_iter().filter(|&p| power_to_lufs(p) > relative_gate).collect();
+
+        if rel_gated.is_empty() {
+            return None;
+        }
+
+        Some(power_to_lufs(rel_gated.iter().sum::<f64>() / rel_gated.len() as f64))
+    }
+
+    /// Returns the ReplayGain 2.0 gain in dB.
+    pub fn gain(&self) -> Option<f64> {
+        self.integrated_loudness().map(|l| REFERENCE_LOUDNESS - l)
+    }
+}
+
+/// Measures the loudness of a stream of interleaved samples.
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<(Biquad, Biquad)>,
+    sub_block_len: usize,
+    sub_block_pos: usize,
+    sub_block_sum: f64,
+    sub_block_powers: Vec<f64>,
+    measurement: Measurement,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        LoudnessMeter {
+            channels,
+            filters: (0..channels).map(|_| k_weighting_filters(sample_rate)).collect(),
+            // Gating blocks are 400ms long, and overlap by 75%, so work in 100ms pieces.
+            sub_block_len: (sample_rate as usize / 10).max(1),
+            sub_block_pos: 0,
+            sub_block_sum: 0.0,
+            sub_block_powers: vec![],
+            measurement: Measurement::default(),
+        }
+    }
+
+    /// Adds a single frame of samples (one per channel), where full scale is 1.0.
+    // TODO: Surround channels should be weighted differently, and the LFE channel ignored.
+    pub fn push_frame(&mut self, frame: &[f64]) {
+        debug_assert_eq!(self.channels, frame.len());
+
+        for (sample, &mut (ref mut shelf, ref mut high_pass)) in frame.iter().zip(self.filters.iter_mut()) {
+            self.measurement.peak = self.measurement.peak.max(sample.abs());
+
+            let filtered = high_pass.process(shelf.process(*sample));
+            self.sub_block_sum += filtered * filtered;
+        }
+
+        self.sub_block_pos += 1;
+
+        if self.sub_block_pos == self.sub_block_len {
+            self.sub_block_powers.push(self.sub_block_sum / self.sub_block_len as f64);
+            self.sub_block_pos = 0;
+            self.sub_block_sum = 0.0;
+
+            let n = self.sub_block_powers.len();
+
+            if n >= SUB_BLOCKS_PER_BLOCK {
+                let block_power = self.sub_block_powers[n - SUB_BLOCKS_PER_BLOCK..].iter().sum::<f64>() / SUB_BLOCKS_PER_BLOCK as f64;
+                self.measurement.block_powers.push(block_power);
+            }
+        }
+    }
+
+    /// Adds interleaved samples; any trailing partial frame is ignored.
+    pub fn push_interleaved(&mut self, samples: &[f64]) {
+        for frame in samples.chunks(self.channels) {
+            if frame.len() == self.channels {
+                self.push_frame(frame);
+            }
+        }
+    }
+
+    /// Finishes measuring; a trailing partial gating block is not included.
+    pub fn finish(self) -> Measurement {
+        self.measurement
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::{LoudnessMeter, Measurement};
+
+    fn sine_measurement(amplitude: f64, freq: f64, seconds: usize) -> Measurement {
+        let sample_rate = 48000;
+        let mut meter = LoudnessMeter::new(2, sample_rate);
+
+        for i in 0..(sample_rate as usize * seconds) {
+            let s = amplitude * (2.0 * PI * freq * i as f64 / f64::from(sample_rate)).sin();
+            meter.push_frame(&[s, s]);
+        }
+
+        meter.finish()
+    }
+
+    #[test]
+    fn test_loudness_meter() {
+        // A full scale 997Hz sine in both stereo channels measures as 0 LUFS.
+        let m = sine_measurement(1.0, 997.0, 2);
+        let loudness = m.integrated_loudness().expect("Unable to measure loudness");
+        assert!(loudness.abs() < 0.1, "unexpected loudness: {}", loudness);
+        assert!((m.gain().unwrap() + 18.0).abs() < 0.1);
+        assert!((m.peak - 1.0).abs() < 1e-3);
+
+        // Halving the amplitude lowers the loudness by ~6dB.
+        let m = sine_measurement(0.5, 997.0, 2);
+        let loudness = m.integrated_loudness().expect("Unable to measure loudness");
+        assert!((loudness + 6.02).abs() < 0.1, "unexpected loudness: {}", loudness);
+
+        // Silence cannot be measured.
+        let m = sine_measurement(0.0, 997.0, 2);
+        assert_eq!(None, m.integrated_loudness());
+        assert_eq!(0.0, m.peak);
+
+        // Combining a measurement with silence does not change its loudness, due to gating.
+        let loud = sine_measurement(1.0, 997.0, 2);
+        let quiet = sine_measurement(0.0, 997.0, 2);
+        let combined = Measurement::combine(&[loud.clone(), quiet]);
+        assert_eq!(loud.integrated_loudness(), combined.integrated_loudness());
+    }
+}
@@ -0,0 +1,15 @@
+// Macros shared across the crate. This module must be declared before any module that uses them.
+
+/// Builds a `MetaValue` from a YAML-like literal, e.g. `metaval!({"title" => "Title", "artists" => ["A", "B"]})`.
+/// `~` is a null value; any other expression must be a single token tree (wrap it in parentheses if needed) that converts into a `MetaValue`.
+macro_rules! metaval {
+    (~) => { $crate::metadata::MetaValue::Nil };
+    ([ $($elem:tt),* $(,)* ]) => { $crate::metadata::MetaValue::Seq(vec![ $( metaval!($elem) ),* ]) };
+    ({ $($key:expr => $val:tt),* $(,)* }) => {{
+        #[allow(unused_mut)]
+        let mut map = ::std::collections::BTreeMap::new();
+        $( map.insert($crate::metadata::MetaKey::from($key), metaval!($val)); )*
+        $crate::metadata::MetaValue::Map(map)
+    }};
+    ($other:expr) => { $crate::metadata::MetaValue::from($other) };
+}
@@ -1,6 +1,7 @@
 #![feature(generators, generator_trait)]
 #![feature(type_ascription)]
 #![feature(entry_or_default)]
+#![recursion_limit = "256"]
 
 extern crate tempdir;
 extern crate regex;
@@ -8,18 +9,76 @@ extern crate yaml_rust;
 #[macro_use] extern crate maplit;
 #[macro_use] extern crate log;
 extern crate glob;
+extern crate env_logger;
 #[macro_use] extern crate error_chain;
+#[cfg(feature = "replaygain")] extern crate claxon;
+#[cfg(feature = "async")] extern crate tokio;
+#[cfg(feature = "archives")] extern crate zip;
+#[cfg(feature = "collation")] extern crate icu_collator;
+#[cfg(feature = "collation")] extern crate icu_locid;
+#[cfg(feature = "tui")] extern crate crossterm;
 
+#[macro_use] mod macros;
 mod library;
 mod helpers;
+mod vfs;
 mod yaml;
+mod roundtrip;
 mod metadata;
 mod plexer;
 mod lookup;
+mod schema;
+mod ontology;
+mod scaffold;
+mod writer;
+mod tags;
+mod sync;
+mod multivalue;
+mod sort_name;
+mod naming;
+mod retag;
+mod tracklist;
+mod typos;
+mod beets;
+mod itunes;
+mod mpd;
+mod progress;
+mod plan;
+mod json;
+mod completion;
+mod playlist;
+mod compare;
+mod undo;
+mod redact;
+mod refactor;
+mod health;
+mod git;
+mod watch;
+mod diagnostics;
+#[cfg(feature = "replaygain")] mod loudness;
+#[cfg(feature = "async")] mod nonblocking;
+#[cfg(feature = "lastfm")] mod lastfm;
+#[cfg(feature = "discogs")] mod discogs;
+#[cfg(feature = "tui")] mod tui;
 mod error;
-mod test_helpers;
+#[cfg(any(test, feature = "fixtures"))] pub mod fixtures;
 // mod resolver;
 mod generator;
+mod cli;
+
+use std::env;
+use std::process;
 
 fn main() {
+    env_logger::init().expect("Unable to initialize logger");
+
+    if let Err(ref e) = cli::run(env::args().skip(1)) {
+        eprintln!("error: {}", e);
+
+        for cause in e.iter().skip(1) {
+            eprintln!("caused by: {}", cause);
+        }
+
+        process::exit(1);
+    }
 }
@@ -0,0 +1,222 @@
+// This module defines conventions for field names found in meta blocks.
+// Field names starting with the reserved prefix are set aside for use by taggu itself.
+// Field names of the form `namespace:name` are grouped by their namespace (e.g. `mb:release_id`).
+
+use std::collections::BTreeMap;
+
+use metadata::{MetaBlock, MetaValue};
+
+pub const RESERVED_PREFIX: &str = "__";
+pub const NAMESPACE_SEPARATOR: char = ':';
+
+/// Pins a block in a sequence meta file to a specific item name, regardless of its position.
+pub const MATCH_KEY: &str = "__match";
+
+/// Marks an item as excluded from aggregation and exports when set to true, without touching the file system or the selection.
+pub const IGNORE_KEY: &str = "__ignore";
+
+/// Holds a sequence of blocks for the children of a directory item, so that nested items can be described in the same meta file.
+/// Only used if the library has nested items enabled.
+pub const ITEMS_KEY: &str = "__items";
+
+/// Holds fields that are filled in for every item block in the same meta file that does not set them itself.
+/// In a mapping meta file, it is used as an item name; in a sequence meta file, it is the only field of a block of its own.
+pub const DEFAULT_KEY: &str = "__default";
+
+/// Names the item that a block is meant for, so that plexing fails instead of describing the wrong item if the directory has changed.
+pub const EXPECT_NAME_KEY: &str = "__expect_name";
+
+/// Like `__expect_name`, but checks the contents of the item, as hashed by `helpers::content_hash`.
+pub const EXPECT_HASH_KEY: &str = "__expect_hash";
+
+/// Lists the titles of the tracks that a directory should hold, in order, so that missing, extra and misordered items can be found.
+/// Entries may also be blocks with a `title` field, e.g. as imported from a release.
+pub const TRACKLIST_KEY: &str = "__tracklist";
+
+/// Holds a personal rating of an item, as a number from 0 to `RATING_MAX`.
+pub const RATING_KEY: &str = "rating";
+
+pub const RATING_MAX: u8 = 5;
+
+/// Counts how many times an item has been played, as a whole number.
+pub const PLAY_COUNT_KEY: &str = "playcount";
+
+/// Holds when an item was last played, as an ISO 8601 timestamp in UTC, e.g. `2020-01-31T12:34:00Z`.
+pub const LAST_PLAYED_KEY: &str = "lastplayed";
+
+/// Reserved field names that have a defined meaning to taggu, and are allowed to appear in meta files.
+pub const KNOWN_RESERVED_KEYS: &[&str] = &[
+    MATCH_KEY,
+    IGNORE_KEY,
+    ITEMS_KEY,
+    DEFAULT_KEY,
+    EXPECT_NAME_KEY,
+    EXPECT_HASH_KEY,
+    TRACKLIST_KEY,
+];
+
+pub fn is_reserved_key<S: AsRef<str>>(key: S) -> bool {
+    key.as_ref().starts_with(RESERVED_PREFIX)
+}
+
+pub fn is_known_reserved_key<S: AsRef<str>>(key: S) -> bool {
+    let key = key.as_ref();
+
+    KNOWN_RESERVED_KEYS.iter().any(|k| *k == key)
+}
+
+/// Returns true if a field name from user data may be stored in a meta block.
+/// Reserved field names are only allowed if taggu knows what to do with them.
+pub fn is_allowed_user_key<S: AsRef<str>>(key: S) -> bool {
+    let key = key.as_ref();
+
+    !is_reserved_key(key) || is_known_reserved_key(key)
+}
+
+/// Returns true if a meta block marks its item as ignored.
+pub fn is_ignored_block(meta_block: &MetaBlock) -> bool {
+    match meta_block.get(IGNORE_KEY) {
+        Some(&MetaValue::Str(ref s)) => s == "true",
+        _ => false,
+    }
+}
+
+/// Returns true if two field names are the same, ignoring case.
+pub fn field_names_match_folded(a: &str, b: &str) -> bool {
+    a == b || a.to_lowercase() == b.to_lowercase()
+}
+
+/// Gets a field from a meta block, optionally ignoring the case of field names.
+/// An exact match is always preferred; otherwise, the first field in name order that matches when ignoring case is used.
+pub fn get_field<'a>(meta_block: &'a MetaBlock, field_name: &str, fold_case: bool) -> Option<&'a MetaValue> {
+    match meta_block.get(field_name) {
+        Some(mv) => Some(mv),
+        None if fold_case => {
+            meta_block.iter()
+                .find(|&(k, _)| field_names_match_folded(k, field_name))
+                .map(|(_, mv)| mv)
+        },
+        None => None,
+    }
+}
+
+/// Splits a field name into its namespace (if any) and its local name.
+pub fn split_namespace(key: &str) -> (Option<&str>, &str) {
+    match key.find(NAMESPACE_SEPARATOR) {
+        // An empty namespace is not considered a namespace.
+        Some(0) | None => (None, key),
+        Some(i) => (Some(&key[..i]), &key[(i + NAMESPACE_SEPARATOR.len_utf8())..]),
+    }
+}
+
+/// Collects all fields in a meta block that belong to a namespace, keyed by their local names.
+pub fn namespace_fields<'a, S: AsRef<str>>(meta_block: &'a MetaBlock, namespace: S) -> BTreeMap<&'a str, &'a MetaValue> {
+    let namespace = namespace.as_ref();
+
+    meta_block
+        .iter()
+        .filter_map(|(k, v)| {
+            match split_namespace(k) {
+                (Some(ns), local) if ns == namespace => Some((local, v)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use metadata::{MetaBlock, MetaValue};
+
+    use super::{
+        MATCH_KEY,
+        IGNORE_KEY,
+        is_reserved_key,
+        is_ignored_block,
+        get_field,
+        is_allowed_user_key,
+        split_namespace,
+        namespace_fields,
+    };
+
+    #[test]
+    fn test_is_allowed_user_key() {
+        let inputs_and_expected = vec![
+            ("title", true),
+            ("_title", true),
+            ("title__", true),
+            ("mb:release_id", true),
+            ("__title", false),
+            ("__", false),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(!expected, is_reserved_key(input));
+            assert_eq!(expected, is_allowed_user_key(input));
+        }
+
+        // Known reserved keys are allowed.
+        assert!(is_reserved_key(MATCH_KEY));
+        assert!(is_allowed_user_key(MATCH_KEY));
+        assert!(is_allowed_user_key(IGNORE_KEY));
+    }
+
+    #[test]
+    fn test_is_ignored_block() {
+        let block = |mv: MetaValue| -> MetaBlock { btreemap![String::from(IGNORE_KEY) => mv] };
+
+        assert!(is_ignored_block(&block(MetaValue::Str(String::from("true")))));
+        assert!(!is_ignored_block(&block(MetaValue::Str(String::from("false")))));
+        assert!(!is_ignored_block(&block(MetaValue::Nil)));
+        assert!(!is_ignored_block(&MetaBlock::new()));
+    }
+
+    #[test]
+    fn test_get_field() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let block: MetaBlock = btreemap![
+            String::from("Artist") => str_val("Upper"),
+            String::from("artist") => str_val("Lower"),
+            String::from("Title") => str_val("Title"),
+        ];
+
+        assert_eq!(Some(&str_val("Lower")), get_field(&block, "artist", true));
+        assert_eq!(Some(&str_val("Upper")), get_field(&block, "ARTIST", true));
+        assert_eq!(Some(&str_val("Title")), get_field(&block, "title", true));
+        assert_eq!(None, get_field(&block, "title", false));
+        assert_eq!(None, get_field(&block, "album", true));
+    }
+
+    #[test]
+    fn test_split_namespace() {
+        let inputs_and_expected = vec![
+            ("title", (None, "title")),
+            ("mb:release_id", (Some("mb"), "release_id")),
+            ("mb:", (Some("mb"), "")),
+            (":title", (None, ":title")),
+            ("a:b:c", (Some("a"), "b:c")),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, split_namespace(input));
+        }
+    }
+
+    #[test]
+    fn test_namespace_fields() {
+        let mb: MetaBlock = btreemap![
+            String::from("title") => MetaValue::Str(String::from("Black Mamba")),
+            String::from("mb:release_id") => MetaValue::Str(String::from("1234")),
+            String::from("mb:artist_id") => MetaValue::Str(String::from("5678")),
+            String::from("discogs:release_id") => MetaValue::Str(String::from("9012")),
+        ];
+
+        let expected = btreemap![
+            "release_id" => &mb["mb:release_id"],
+            "artist_id" => &mb["mb:artist_id"],
+        ];
+        assert_eq!(expected, namespace_fields(&mb, "mb"));
+
+        assert!(namespace_fields(&mb, "acoustid").is_empty());
+    }
+}
@@ -1,21 +1,28 @@
 pub mod reader;
+pub mod keys;
+pub mod source;
+pub mod pretty;
+pub mod subst;
 
 use std::path::{Path, PathBuf};
-use std::collections::{BTreeMap, HashMap};
-use std::fs::DirEntry;
+use std::collections::BTreeMap;
 
 use metadata::reader::MetaReader;
-use library::sort_order::SortOrder;
+use library::sort_order::{SortOrder, GroupOrder};
 use library::selection::Selection;
+use vfs::{Vfs, StdFs};
 use error::*;
 use generator::GenConverter;
 
 pub type MetaBlock = BTreeMap<String, MetaValue>;
 pub type MetaBlockSeq = Vec<MetaBlock>;
-pub type MetaBlockMap = HashMap<String, MetaBlock>;
+/// Blocks keyed by item name.
+/// The block under the `Nil` key (`~` in YAML) describes the working directory itself, instead of one of the items in it.
+/// Ordered, so that blocks are plexed, aligned and emitted in the same order on every run, sorted by key with `Nil` first.
+pub type MetaBlockMap = BTreeMap<MetaKey, MetaBlock>;
 
-/// Mapping of item file paths to their complete metadata blocks.
-pub type PathMetaListing = HashMap<PathBuf, MetaBlock>;
+/// Mapping of item file paths to their complete metadata blocks, sorted by path.
+pub type PathMetaListing = BTreeMap<PathBuf, MetaBlock>;
 
 /// Represents the different metadata formats/layouts found among all types of meta targets.
 pub enum MetaFormat {
@@ -24,13 +31,71 @@ pub enum MetaFormat {
     Map(MetaBlockMap),
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone)]
 pub enum MetaTarget {
     Contains,
     Siblings,
+    /// Describes a single item, in a meta file next to it that is named after it plus a suffix, e.g. `TRACK_01.flac.yml` for the suffix `yml`.
+    /// Sidecar meta files are found by their suffix, so the meta file name of a sidecar spec is only used to refer to the spec.
+    Sidecar { suffix: String },
+}
+
+/// Returns the path of the sidecar meta file for an item, given the suffix of its sidecar target.
+pub fn sidecar_path<P: AsRef<Path>>(item_path: P, suffix: &str) -> Option<PathBuf> {
+    let item_path = item_path.as_ref();
+    let item_file_name = item_path.file_name()?.to_str()?;
+
+    Some(item_path.with_file_name(format!("{}.{}", item_file_name, suffix)))
+}
+
+/// Returns the path of the item that a sidecar meta file describes, if the meta file has the suffix.
+pub fn sidecar_item_path<P: AsRef<Path>>(meta_path: P, suffix: &str) -> Option<PathBuf> {
+    let meta_path = meta_path.as_ref();
+    let meta_file_name = meta_path.file_name()?.to_str()?;
+
+    if meta_file_name.len() <= suffix.len() + 1 || !meta_file_name.ends_with(suffix) {
+        return None;
+    }
+
+    let item_file_name = &meta_file_name[..meta_file_name.len() - suffix.len()];
+
+    if !item_file_name.ends_with('.') {
+        return None;
+    }
+
+    Some(meta_path.with_file_name(&item_file_name[..item_file_name.len() - 1]))
 }
 
 impl MetaTarget {
+    /// Returns the suffix of sidecar meta files, if this is a sidecar target.
+    pub fn sidecar_suffix(&self) -> Option<&str> {
+        match *self {
+            MetaTarget::Sidecar { ref suffix } => Some(suffix),
+            _ => None,
+        }
+    }
+
+    /// Returns the directory in which a meta file of this target type would be found for a given item path.
+    pub fn target_dir_path<P: AsRef<Path>>(&self, item_path: P) -> Option<PathBuf> {
+        self.target_dir_path_with(&StdFs, item_path)
+    }
+
+    /// Like `target_dir_path`, but checking the item on a given file system.
+    pub fn target_dir_path_with<V: Vfs + ?Sized, P: AsRef<Path>>(&self, vfs: &V, item_path: P) -> Option<PathBuf> {
+        let item_path = item_path.as_ref();
+
+        match *self {
+            MetaTarget::Contains => {
+                if vfs.is_dir(item_path) {
+                    Some(item_path.to_path_buf())
+                } else {
+                    None
+                }
+            },
+            MetaTarget::Siblings | MetaTarget::Sidecar { .. } => item_path.parent().map(Path::to_path_buf),
+        }
+    }
+
     pub fn get_target_meta_path<P: AsRef<Path>>(&self, item_path: P) -> Result<PathBuf> {
         let item_path: &Path = item_path.as_ref();
 
@@ -47,7 +112,8 @@ impl MetaTarget {
                     Some(item_path_parent) => item_path_parent.join("taggu_item.yml"),
                     None => bail!(ErrorKind::CappedAtRoot),
                 }
-            }
+            },
+            MetaTarget::Sidecar { ref suffix } => sidecar_path(item_path, suffix).ok_or(ErrorKind::CappedAtRoot)?,
         };
 
         ensure!(meta_path.exists(), ErrorKind::DoesNotExist(meta_path.to_path_buf()));
@@ -63,9 +129,10 @@ impl MetaTarget {
         match *self {
             MetaTarget::Contains => {},
             MetaTarget::Siblings => {},
+            MetaTarget::Sidecar { .. } => {},
         }
 
-        Ok(hashmap![])
+        Ok(btreemap![])
     }
 }
 
@@ -79,24 +146,24 @@ pub enum Metadata {
 }
 
 impl Metadata {
-    fn get_relevant_dir_entries<P: AsRef<Path>>(working_dir_path: P, selection: &Selection, opt_sort_order: Option<SortOrder>) -> Result<Vec<DirEntry>> {
+    fn get_relevant_paths<P: AsRef<Path>>(working_dir_path: P, selection: &Selection, opt_sort: Option<(&SortOrder, GroupOrder)>) -> Result<Vec<PathBuf>> {
         let working_dir_path = working_dir_path.as_ref();
 
-        let mut dir_entries = selection.selected_entries_in_dir(working_dir_path)?;
-
-        if let Some(sort_order) = opt_sort_order {
-            dir_entries.sort_unstable_by(|a, b| sort_order.path_sort_cmp(a.path(), b.path()));
-        }
+        let dir_entries = selection.selected_entries_in_dir(working_dir_path)?;
 
-        Ok(dir_entries)
-    }
+        match opt_sort {
+            Some((sort_order, group_order)) => {
+                let mut paths: Vec<_> = dir_entries.into_iter().map(|e| (e.path, e.is_dir)).collect();
+                group_order.sort_paths(sort_order, &mut paths);
 
-    fn get_relevant_paths<P: AsRef<Path>>(working_dir_path: P, selection: &Selection, opt_sort_order: Option<SortOrder>) -> Result<Vec<PathBuf>> {
-        Ok(Metadata::get_relevant_dir_entries(working_dir_path, selection, opt_sort_order)?.iter().map(|e| e.path()).collect())
+                Ok(paths.into_iter().map(|(path, _)| path).collect())
+            },
+            None => Ok(dir_entries.into_iter().map(|e| e.path).collect()),
+        }
     }
 
-    fn get_relevant_names<P: AsRef<Path>>(working_dir_path: P, selection: &Selection, opt_sort_order: Option<SortOrder>) -> Result<Vec<String>> {
-        Ok(Metadata::get_relevant_paths(working_dir_path, selection, opt_sort_order)?
+    fn get_relevant_names<P: AsRef<Path>>(working_dir_path: P, selection: &Selection, opt_sort: Option<(&SortOrder, GroupOrder)>) -> Result<Vec<String>> {
+        Ok(Metadata::get_relevant_paths(working_dir_path, selection, opt_sort)?
             .iter()
             .filter_map(|p| p.file_name())
             .map(|o_str| o_str.to_string_lossy().to_string())
@@ -107,13 +174,15 @@ impl Metadata {
         &self,
         working_dir_path: P,
         selection: &Selection,
-        sort_order: SortOrder,
+        sort_order: &SortOrder,
+        group_order: GroupOrder,
         ) -> Result<Vec<String>>
     {
         match *self {
             Metadata::Contains(_) => Ok(vec![]),
-            Metadata::SiblingsSeq(_) => Metadata::get_relevant_names(working_dir_path, selection, Some(sort_order)),
-            Metadata::SiblingsMap(_) => Metadata::get_relevant_names(working_dir_path, selection, None),
+            Metadata::SiblingsSeq(_) => Metadata::get_relevant_names(working_dir_path, selection, Some((sort_order, group_order))),
+            // Mappings are matched by name, but are sorted too so that positional keys can be resolved.
+            Metadata::SiblingsMap(_) => Metadata::get_relevant_names(working_dir_path, selection, Some((sort_order, group_order))),
         }
     }
 }
@@ -137,6 +206,18 @@ impl MetaKey {
     }
 }
 
+impl<'a> From<&'a str> for MetaKey {
+    fn from(s: &'a str) -> Self {
+        MetaKey::Str(s.to_string())
+    }
+}
+
+impl From<String> for MetaKey {
+    fn from(s: String) -> Self {
+        MetaKey::Str(s)
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Hash)]
 pub enum MetaValue {
     Nil,
@@ -185,6 +266,67 @@ impl MetaValue {
 
         GenConverter::gen_to_iter(closure)
     }
+
+    /// Compares two values the way a person reading the meta files would, so that e.g. diffs and conflict reports do not flag values that only differ in form.
+    /// On top of plain equality:
+    /// - strings that both read as the same number are equal, e.g. `1`, `01` and `1.0`, since YAML integers and tag values are both read as strings;
+    /// - a sequence with a single value is equal to that value on its own;
+    /// - in mappings, a key with a `Nil` value is equal to the key being missing, as with `eq_coerced_opt`.
+    pub fn eq_coerced(&self, other: &MetaValue) -> bool {
+        match (self, other) {
+            (&MetaValue::Nil, &MetaValue::Nil) => true,
+            (&MetaValue::Str(ref a), &MetaValue::Str(ref b)) => {
+                a == b || match (parse_number(a), parse_number(b)) {
+                    (Some(na), Some(nb)) => na == nb,
+                    _ => false,
+                }
+            },
+            (&MetaValue::Seq(ref a), &MetaValue::Seq(ref b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(mva, mvb)| mva.eq_coerced(mvb))
+            },
+            (&MetaValue::Seq(ref a), b) if a.len() == 1 => a[0].eq_coerced(b),
+            (a, &MetaValue::Seq(ref b)) if b.len() == 1 => a.eq_coerced(&b[0]),
+            (&MetaValue::Map(ref a), &MetaValue::Map(ref b)) => {
+                a.keys().chain(b.keys()).all(|mk| MetaValue::eq_coerced_opt(a.get(mk), b.get(mk)))
+            },
+            _ => false,
+        }
+    }
+
+    /// Like `eq_coerced`, for values that may be missing, e.g. the values of a field in two blocks.
+    /// A missing value is equal to `Nil`, since a field set to `~` and an unset field both have no value.
+    pub fn eq_coerced_opt(a: Option<&MetaValue>, b: Option<&MetaValue>) -> bool {
+        a.unwrap_or(&MetaValue::Nil).eq_coerced(b.unwrap_or(&MetaValue::Nil))
+    }
+}
+
+/// Reads a string as a finite number, for comparing values loosely.
+fn parse_number(s: &str) -> Option<f64> {
+    s.trim().parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+impl<'a> From<&'a str> for MetaValue {
+    fn from(s: &'a str) -> Self {
+        MetaValue::Str(s.to_string())
+    }
+}
+
+impl From<String> for MetaValue {
+    fn from(s: String) -> Self {
+        MetaValue::Str(s)
+    }
+}
+
+impl<T: Into<MetaValue>> From<Vec<T>> for MetaValue {
+    fn from(v: Vec<T>) -> Self {
+        MetaValue::Seq(v.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<K: Into<MetaKey> + Ord, V: Into<MetaValue>> From<BTreeMap<K, V>> for MetaValue {
+    fn from(m: BTreeMap<K, V>) -> Self {
+        MetaValue::Map(m.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
@@ -196,11 +338,34 @@ pub enum MappingIterScheme {
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
     use super::{
+        MetaKey,
         MetaValue,
+        MetaTarget,
         MappingIterScheme,
+        sidecar_path,
+        sidecar_item_path,
     };
 
+    #[test]
+    fn test_sidecar_paths() {
+        let item_path = PathBuf::from("/music/ALBUM/TRACK_01.flac");
+        let meta_path = PathBuf::from("/music/ALBUM/TRACK_01.flac.meta.yml");
+
+        assert_eq!(Some(meta_path.clone()), sidecar_path(&item_path, "meta.yml"));
+        assert_eq!(Some(item_path), sidecar_item_path(&meta_path, "meta.yml"));
+
+        assert_eq!(None, sidecar_item_path("/music/ALBUM/meta.yml", "meta.yml"));
+        assert_eq!(None, sidecar_item_path("/music/ALBUM/.meta.yml", "meta.yml"));
+        assert_eq!(None, sidecar_item_path("/music/ALBUM/TRACK_01.flacmeta.yml", "meta.yml"));
+        assert_eq!(None, sidecar_item_path("/music/ALBUM/item.yml", "meta.yml"));
+
+        assert_eq!(Some("meta.yml"), MetaTarget::Sidecar { suffix: String::from("meta.yml") }.sidecar_suffix());
+        assert_eq!(None, MetaTarget::Siblings.sidecar_suffix());
+    }
+
     #[test]
     fn test_meta_value_flatten() {
         let str_sample_a = "Goldfish".to_string();
@@ -221,4 +386,80 @@ mod tests {
             assert_eq!(expected, produced);
         }
     }
+
+    #[test]
+    fn test_meta_value_eq_coerced() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        assert!(str_val("1").eq_coerced(&str_val("1")));
+        assert!(str_val("1").eq_coerced(&str_val("01")));
+        assert!(str_val("1").eq_coerced(&str_val("1.0")));
+        assert!(!str_val("1").eq_coerced(&str_val("2")));
+        assert!(!str_val("Title").eq_coerced(&str_val("title")));
+        assert!(!str_val("inf").eq_coerced(&str_val("Infinity")));
+
+        assert!(MetaValue::from(vec!["A"]).eq_coerced(&str_val("A")));
+        assert!(str_val("3").eq_coerced(&MetaValue::from(vec!["03"])));
+        assert!(!MetaValue::from(vec!["A", "B"]).eq_coerced(&str_val("A")));
+        assert!(MetaValue::from(vec!["1", "2"]).eq_coerced(&MetaValue::from(vec!["01", "02"])));
+        assert!(!MetaValue::from(vec!["1", "2"]).eq_coerced(&MetaValue::from(vec!["2", "1"])));
+
+        assert!(MetaValue::Nil.eq_coerced(&MetaValue::Nil));
+        assert!(!MetaValue::Nil.eq_coerced(&str_val("")));
+        assert!(MetaValue::eq_coerced_opt(None, Some(&MetaValue::Nil)));
+        assert!(MetaValue::eq_coerced_opt(None, None));
+        assert!(!MetaValue::eq_coerced_opt(None, Some(&str_val("A"))));
+
+        let map_a = MetaValue::Map(btreemap![
+            MetaKey::from("composer") => str_val("Composer"),
+            MetaKey::from("year") => str_val("1999"),
+        ]);
+        let map_b = MetaValue::Map(btreemap![
+            MetaKey::from("composer") => MetaValue::from(vec!["Composer"]),
+            MetaKey::from("year") => str_val("1999.0"),
+            MetaKey::from("lyricist") => MetaValue::Nil,
+        ]);
+        assert!(map_a.eq_coerced(&map_b));
+        assert!(map_b.eq_coerced(&map_a));
+        assert!(!map_a.eq_coerced(&MetaValue::Map(btreemap![MetaKey::from("composer") => str_val("Composer")])));
+    }
+
+    #[test]
+    fn test_meta_value_from() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        assert_eq!(str_val("Title"), MetaValue::from("Title"));
+        assert_eq!(str_val("Title"), MetaValue::from(String::from("Title")));
+        assert_eq!(MetaValue::Seq(vec![str_val("A"), str_val("B")]), MetaValue::from(vec!["A", "B"]));
+        assert_eq!(MetaValue::Seq(vec![MetaValue::Seq(vec![str_val("A")])]), MetaValue::from(vec![vec!["A"]]));
+
+        let expected = MetaValue::Map(btreemap![MetaKey::Str(String::from("composer")) => str_val("Composer")]);
+        assert_eq!(expected, MetaValue::from(btreemap!["composer" => "Composer"]));
+    }
+
+    #[test]
+    fn test_metaval() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let artist = String::from("Artist");
+
+        let expected = MetaValue::Map(btreemap![
+            MetaKey::Str(String::from("title")) => str_val("Title"),
+            MetaKey::Str(String::from("artists")) => MetaValue::Seq(vec![str_val("Artist"), str_val("Other")]),
+            MetaKey::Str(String::from("credits")) => MetaValue::Map(btreemap![
+                MetaKey::Str(String::from("composer")) => MetaValue::Nil,
+            ]),
+            MetaKey::Str(String::from("tracks")) => MetaValue::Seq(vec![]),
+        ]);
+
+        let produced = metaval!({
+            "title" => "Title",
+            "artists" => [(artist.clone()), "Other"],
+            "credits" => {"composer" => ~},
+            "tracks" => [],
+        });
+
+        assert_eq!(expected, produced);
+        assert_eq!(MetaValue::Nil, metaval!(~));
+        assert_eq!(str_val("Artist"), metaval!(artist));
+    }
 }
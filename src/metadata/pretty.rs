@@ -0,0 +1,202 @@
+// Human-readable, YAML-like rendering of metadata, for use in command line output.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use metadata::{Metadata, MetaBlock, MetaKey, MetaValue};
+
+const INDENT_STEP: usize = 2;
+
+/// Characters that would change the meaning of a plain YAML scalar if they started it.
+const SPECIAL_LEADING_CHARS: &[char] = &['-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%', '@', '`'];
+
+pub trait PrettyPrint {
+    /// Renders this value as YAML-like text, with every line of a multi-line rendering indented by the given number of spaces.
+    /// Single-line renderings are never indented, so that they can follow a key on the same line.
+    fn to_pretty_string(&self, indent: usize) -> String;
+}
+
+fn needs_quotes(s: &str) -> bool {
+    s.is_empty()
+        || s.starts_with(SPECIAL_LEADING_CHARS)
+        || s.trim() != s
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.contains('\n')
+        || ["~", "null", "true", "false"].contains(&s)
+}
+
+fn pretty_scalar(s: &str) -> String {
+    if needs_quotes(s) {
+        format!("{:?}", s)
+    }
+    else {
+        s.to_string()
+    }
+}
+
+fn is_multi_line(mv: &MetaValue) -> bool {
+    match *mv {
+        MetaValue::Seq(ref mvs) => !mvs.is_empty(),
+        MetaValue::Map(ref map) => !map.is_empty(),
+        _ => false,
+    }
+}
+
+/// Renders a single `key: value` entry, putting multi-line values on the following lines.
+pub fn pretty_entry<K: Display>(key: K, mv: &MetaValue, indent: usize) -> String {
+    let pad = " ".repeat(indent);
+
+    if is_multi_line(mv) {
+        format!("{}{}:\n{}", pad, key, mv.to_pretty_string(indent + INDENT_STEP))
+    }
+    else {
+        format!("{}{}: {}", pad, key, mv.to_pretty_string(indent))
+    }
+}
+
+/// Renders a sequence item, putting the first line of a multi-line value on the same line as the dash.
+fn pretty_seq_item(mv: &MetaValue, indent: usize) -> String {
+    let pad = " ".repeat(indent);
+
+    if is_multi_line(mv) {
+        let nested = mv.to_pretty_string(indent + INDENT_STEP);
+        format!("{}- {}", pad, &nested[indent + INDENT_STEP..])
+    }
+    else {
+        format!("{}- {}", pad, mv.to_pretty_string(indent))
+    }
+}
+
+impl PrettyPrint for MetaKey {
+    fn to_pretty_string(&self, _indent: usize) -> String {
+        match *self {
+            MetaKey::Nil => String::from("~"),
+            MetaKey::Str(ref s) => pretty_scalar(s),
+        }
+    }
+}
+
+impl PrettyPrint for MetaValue {
+    fn to_pretty_string(&self, indent: usize) -> String {
+        match *self {
+            MetaValue::Nil => String::from("~"),
+            MetaValue::Str(ref s) => pretty_scalar(s),
+            MetaValue::Seq(ref mvs) if mvs.is_empty() => String::from("[]"),
+            MetaValue::Seq(ref mvs) => {
+                mvs.iter().map(|mv| pretty_seq_item(mv, indent)).collect::<Vec<_>>().join("\n")
+            },
+            MetaValue::Map(ref map) if map.is_empty() => String::from("{}"),
+            MetaValue::Map(ref map) => {
+                map.iter().map(|(mk, mv)| pretty_entry(mk.to_pretty_string(0), mv, indent)).collect::<Vec<_>>().join("\n")
+            },
+        }
+    }
+}
+
+impl PrettyPrint for MetaBlock {
+    fn to_pretty_string(&self, indent: usize) -> String {
+        if self.is_empty() {
+            return String::from("{}");
+        }
+
+        self.iter().map(|(field_name, mv)| pretty_entry(pretty_scalar(field_name), mv, indent)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl PrettyPrint for Metadata {
+    fn to_pretty_string(&self, indent: usize) -> String {
+        // Blocks are rendered the same way as the equivalent meta values.
+        let block_as_value = |mb: &MetaBlock| {
+            MetaValue::Map(mb.iter().map(|(k, v)| (MetaKey::Str(k.clone()), v.clone())).collect())
+        };
+
+        match *self {
+            Metadata::Contains(ref mb) => mb.to_pretty_string(indent),
+            Metadata::SiblingsSeq(ref mb_seq) => {
+                MetaValue::Seq(mb_seq.iter().map(block_as_value).collect()).to_pretty_string(indent)
+            },
+            Metadata::SiblingsMap(ref mb_map) => {
+                MetaValue::Map(mb_map.iter().map(|(k, mb)| (k.clone(), block_as_value(mb))).collect()).to_pretty_string(indent)
+            },
+        }
+    }
+}
+
+impl Display for MetaKey {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.to_pretty_string(0))
+    }
+}
+
+impl Display for MetaValue {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.to_pretty_string(0))
+    }
+}
+
+impl Display for Metadata {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.to_pretty_string(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use metadata::{Metadata, MetaBlock, MetaKey, MetaValue};
+
+    use super::{PrettyPrint, pretty_entry};
+
+    fn str_val(s: &str) -> MetaValue {
+        MetaValue::Str(s.to_string())
+    }
+
+    #[test]
+    fn test_meta_value_to_pretty_string() {
+        let nested = MetaValue::Map(btreemap![
+            MetaKey::Str(String::from("composer")) => str_val("Composer"),
+            MetaKey::Str(String::from("performers")) => MetaValue::Seq(vec![str_val("A"), str_val("B")]),
+            MetaKey::Nil => MetaValue::Nil,
+        ]);
+
+        let inputs_and_expected = vec![
+            (MetaValue::Nil, "~"),
+            (str_val("Title"), "Title"),
+            (str_val(""), "\"\""),
+            (str_val("- Title"), "\"- Title\""),
+            (str_val("a: b"), "\"a: b\""),
+            (str_val("true"), "\"true\""),
+            (MetaValue::Seq(vec![]), "[]"),
+            (MetaValue::Map(btreemap![]), "{}"),
+            (MetaValue::Seq(vec![str_val("A"), MetaValue::Nil]), "- A\n- ~"),
+            (nested.clone(), "~: ~\ncomposer: Composer\nperformers:\n  - A\n  - B"),
+            (MetaValue::Seq(vec![nested.clone(), MetaValue::Seq(vec![str_val("C")])]), "- ~: ~\n  composer: Composer\n  performers:\n    - A\n    - B\n- - C"),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, input.to_pretty_string(0));
+            assert_eq!(expected, format!("{}", input));
+        }
+
+        assert_eq!("    - A\n    - B", MetaValue::Seq(vec![str_val("A"), str_val("B")]).to_pretty_string(4));
+        assert_eq!("title: Title", pretty_entry("title", &str_val("Title"), 0));
+        assert_eq!("  artists:\n    - A", pretty_entry("artists", &MetaValue::Seq(vec![str_val("A")]), 2));
+    }
+
+    #[test]
+    fn test_metadata_to_pretty_string() {
+        let mut mb = MetaBlock::new();
+        mb.insert(String::from("title"), str_val("Title"));
+        mb.insert(String::from("artist"), str_val("Artist"));
+
+        let inputs_and_expected = vec![
+            (Metadata::Contains(mb.clone()), "artist: Artist\ntitle: Title"),
+            (Metadata::Contains(MetaBlock::new()), "{}"),
+            (Metadata::SiblingsSeq(vec![mb.clone(), MetaBlock::new()]), "- artist: Artist\n  title: Title\n- {}"),
+            (Metadata::SiblingsMap(btreemap![MetaKey::from("b.flac") => mb.clone(), MetaKey::from("a.flac") => MetaBlock::new()]), "a.flac: {}\nb.flac:\n  artist: Artist\n  title: Title"),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, format!("{}", input));
+        }
+    }
+}
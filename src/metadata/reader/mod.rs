@@ -1,21 +1,69 @@
 pub mod yaml;
 
 use std::path::Path;
-use std::fs::File;
-use std::io::Read;
+use std::fmt::{Formatter, Result as FmtResult, Display};
 
-use metadata::{Metadata, MetaTarget, PathMetaListing};
+use metadata::{Metadata, MetaKey, MetaTarget, PathMetaListing};
+use vfs::{Vfs, StdFs};
 use error::*;
 
+/// Identifies a single block within a meta file.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BlockLocation {
+    /// The block that makes up the entire file.
+    Whole,
+    /// A block in a sequence, by index.
+    Index(usize),
+    /// A block in a mapping, by item name.
+    Key(String),
+    /// The block in a mapping under the null key, which describes the working directory.
+    Nil,
+}
+
+impl BlockLocation {
+    /// Returns the location of the block under a key of a mapping.
+    pub fn for_key(mk: &MetaKey) -> Self {
+        match *mk {
+            MetaKey::Nil => BlockLocation::Nil,
+            MetaKey::Str(ref s) => BlockLocation::Key(s.clone()),
+        }
+    }
+}
+
+impl Display for BlockLocation {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            BlockLocation::Whole => write!(f, "whole file"),
+            BlockLocation::Index(i) => write!(f, "block #{}", i),
+            BlockLocation::Key(ref k) => write!(f, "block '{}'", k),
+            BlockLocation::Nil => write!(f, "working directory block"),
+        }
+    }
+}
+
+/// A problem found while leniently reading metadata, which caused a block to be skipped.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReadDiagnostic {
+    pub location: BlockLocation,
+    pub message: String,
+}
+
+impl Display for ReadDiagnostic {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
 pub trait MetaReader {
     fn from_str<S: AsRef<str>>(s: S, mt: MetaTarget) -> Result<PathMetaListing>;
 
     fn from_file<P: AsRef<Path>>(p: P, mt: MetaTarget) -> Result<PathMetaListing> {
-        let p = p.as_ref();
-        let mut f = File::open(p)?;
+        Self::from_vfs_file(&StdFs, p, mt)
+    }
 
-        let mut buffer = String::new();
-        f.read_to_string(&mut buffer)?;
+    /// Like `from_file`, but reading the file from a given file system.
+    fn from_vfs_file<V: Vfs + ?Sized, P: AsRef<Path>>(vfs: &V, p: P, mt: MetaTarget) -> Result<PathMetaListing> {
+        let buffer = vfs.read_to_string(p.as_ref())?;
 
         Self::from_str(buffer, mt).chain_err(|| "unable to parse text")
     }
@@ -2,7 +2,7 @@ use std::collections::BTreeMap;
 
 use yaml_rust::{Yaml, YamlLoader};
 
-use metadata::reader::MetaReader;
+use metadata::reader::{MetaReader, ReadDiagnostic, BlockLocation};
 use metadata::{
     Metadata,
     MetaBlock,
@@ -13,6 +13,7 @@ use metadata::{
     MetaTarget,
     PathMetaListing,
 };
+use metadata::keys::is_allowed_user_key;
 use error::*;
 
 pub struct YamlMetaReader;
@@ -30,7 +31,21 @@ impl MetaReader for YamlMetaReader {
         let yaml_doc = &yaml_docs[0];
 
         // yaml_as_metadata(yaml_doc, mt)
-        Ok(hashmap![])
+        Ok(btreemap![])
+    }
+}
+
+impl YamlMetaReader {
+    /// Reads metadata, skipping over any individual blocks that cannot be parsed instead of failing outright.
+    /// A diagnostic is recorded for each skipped block.
+    /// Errors are still returned if the text is not valid YAML, or is not shaped like metadata for the meta target.
+    pub fn from_str_lenient<S: AsRef<str>>(s: S, mt: MetaTarget) -> Result<(Metadata, Vec<ReadDiagnostic>)> {
+        let s = s.as_ref();
+        let yaml_docs: Vec<Yaml> = YamlLoader::load_from_str(s)?;
+
+        ensure!(yaml_docs.len() >= 1, "empty YAML document");
+
+        yaml_as_metadata_lenient(&yaml_docs[0], mt)
     }
 }
 
@@ -100,6 +115,9 @@ fn yaml_as_meta_block(y: &Yaml) -> Result<MetaBlock> {
             // Values can be any meta value.
             for (key_y, val_y) in hsh {
                 let key = yaml_as_string(&key_y)?;
+
+                ensure!(is_allowed_user_key(&key), ErrorKind::ReservedKey(key));
+
                 let val = yaml_as_meta_value(&val_y)?;
 
                 mb.insert(key, val);
@@ -136,7 +154,8 @@ pub fn yaml_as_meta_block_map(y: &Yaml) -> Result<MetaBlockMap> {
             let mut item_map = MetaBlockMap::new();
 
             for (key_y, val_y) in hsh {
-                let key = yaml_as_string(&key_y)?;
+                // A null key gives the block for the working directory.
+                let key = yaml_as_meta_key(&key_y)?;
 
                 // TODO: Check that key is a valid item name!
 
@@ -153,7 +172,7 @@ pub fn yaml_as_meta_block_map(y: &Yaml) -> Result<MetaBlockMap> {
 
 pub fn yaml_as_metadata(y: &Yaml, meta_target: MetaTarget) -> Result<Metadata> {
     match meta_target {
-        MetaTarget::Contains => {
+        MetaTarget::Contains | MetaTarget::Sidecar { .. } => {
             yaml_as_meta_block(y).map(|m| Metadata::Contains(m))
         },
         MetaTarget::Siblings => {
@@ -163,12 +182,81 @@ pub fn yaml_as_metadata(y: &Yaml, meta_target: MetaTarget) -> Result<Metadata> {
     }
 }
 
+/// Converts to sequenced item-metadata, replacing unparseable blocks with empty ones.
+/// Empty blocks are used (instead of dropping the block) so that later blocks still line up with their items.
+pub fn yaml_as_meta_block_seq_lenient(y: &Yaml) -> Result<(MetaBlockSeq, Vec<ReadDiagnostic>)> {
+    match y {
+        &Yaml::Array(ref arr) => {
+            let mut item_seq = MetaBlockSeq::new();
+            let mut diagnostics = vec![];
+
+            for (i, val_y) in arr.iter().enumerate() {
+                match yaml_as_meta_block(&val_y) {
+                    Ok(mb) => { item_seq.push(mb); },
+                    Err(err) => {
+                        diagnostics.push(ReadDiagnostic { location: BlockLocation::Index(i), message: err.to_string() });
+                        item_seq.push(MetaBlock::new());
+                    },
+                }
+            }
+
+            Ok((item_seq, diagnostics))
+        },
+        _ => bail!("cannot convert YAML to meta block sequence"),
+    }
+}
+
+/// Converts to mapped item-metadata, dropping entries whose keys or blocks are unparseable.
+pub fn yaml_as_meta_block_map_lenient(y: &Yaml) -> Result<(MetaBlockMap, Vec<ReadDiagnostic>)> {
+    match y {
+        &Yaml::Hash(ref hsh) => {
+            let mut item_map = MetaBlockMap::new();
+            let mut diagnostics = vec![];
+
+            for (i, (key_y, val_y)) in hsh.iter().enumerate() {
+                let key = match yaml_as_meta_key(&key_y) {
+                    Ok(key) => key,
+                    Err(err) => {
+                        diagnostics.push(ReadDiagnostic { location: BlockLocation::Index(i), message: err.to_string() });
+                        continue;
+                    },
+                };
+
+                match yaml_as_meta_block(&val_y) {
+                    Ok(mb) => { item_map.insert(key, mb); },
+                    Err(err) => {
+                        diagnostics.push(ReadDiagnostic { location: BlockLocation::for_key(&key), message: err.to_string() });
+                    },
+                }
+            }
+
+            Ok((item_map, diagnostics))
+        },
+        _ => bail!("cannot convert YAML to meta block mapping"),
+    }
+}
+
+pub fn yaml_as_metadata_lenient(y: &Yaml, meta_target: MetaTarget) -> Result<(Metadata, Vec<ReadDiagnostic>)> {
+    match meta_target {
+        MetaTarget::Contains | MetaTarget::Sidecar { .. } => {
+            // There is only one block, so there is nothing to recover if it is bad.
+            yaml_as_meta_block(y).map(|m| (Metadata::Contains(m), vec![]))
+        },
+        MetaTarget::Siblings => {
+            yaml_as_meta_block_seq_lenient(y).map(|(m, d)| (Metadata::SiblingsSeq(m), d))
+                .or(yaml_as_meta_block_map_lenient(y).map(|(m, d)| (Metadata::SiblingsMap(m), d)))
+        },
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use metadata::{MetaBlock, MetaKey, MetaValue};
+    use metadata::{MetaBlock, MetaKey, MetaValue, MetaTarget, Metadata};
+    use metadata::reader::{BlockLocation, ReadDiagnostic};
     use yaml_rust::{YamlLoader};
 
     use super::{
+        YamlMetaReader,
         yaml_as_string,
         yaml_as_meta_key,
         yaml_as_meta_value,
@@ -426,6 +514,9 @@ mod tests {
             ("{key_a: val_a, [skipped_key, skipped_key]: skipped_val}", None),
             ("{key_a: val_a, {skipped_key_key: skipped_key_val}: skipped_val}", None),
             ("{key_a: val_a, ~: skipped_val}", None),
+
+            // Reserved field names
+            ("{key_a: val_a, __key_b: val_b}", None),
             // ("{key_a: val_a, [skipped_key, skipped_key]: skipped_val}", {
             //     let mut mb = MetaBlock::new();
             //     mb.insert("key_a".to_string(), MetaValue::Str("val_a".to_string()));
@@ -449,4 +540,44 @@ mod tests {
             assert_eq!(expected, produced);
         }
     }
+
+    #[test]
+    fn test_from_str_lenient() {
+        let seq_input = "- title: Black Mamba\n- title: [bad, {nested: ~}]\n  artist: {[bad_key]: val}\n- title: What Is This?";
+
+        let (md, diagnostics) = YamlMetaReader::from_str_lenient(seq_input, MetaTarget::Siblings).expect("Unable to read metadata");
+
+        // The bad block is replaced with an empty block, so later blocks stay aligned.
+        match md {
+            Metadata::SiblingsSeq(mb_seq) => {
+                assert_eq!(3, mb_seq.len());
+                assert_eq!(Some(&MetaValue::Str("Black Mamba".to_string())), mb_seq[0].get("title"));
+                assert!(mb_seq[1].is_empty());
+                assert_eq!(Some(&MetaValue::Str("What Is This?".to_string())), mb_seq[2].get("title"));
+            },
+            _ => panic!("unexpected metadata format"),
+        }
+
+        assert_eq!(vec![BlockLocation::Index(1)], diagnostics.iter().map(|d| d.location.clone()).collect::<Vec<_>>());
+
+        let map_input = "item_a.flac:\n  title: Black Mamba\nitem_b.flac:\n  __bad_key: val\nitem_c.flac: not a block";
+
+        let (md, diagnostics) = YamlMetaReader::from_str_lenient(map_input, MetaTarget::Siblings).expect("Unable to read metadata");
+
+        match md {
+            Metadata::SiblingsMap(mb_map) => {
+                assert_eq!(1, mb_map.len());
+                assert!(mb_map.contains_key(&MetaKey::from("item_a.flac")));
+            },
+            _ => panic!("unexpected metadata format"),
+        }
+
+        let produced: Vec<BlockLocation> = diagnostics.into_iter().map(|d: ReadDiagnostic| d.location).collect();
+        assert_eq!(vec![BlockLocation::Key("item_b.flac".to_string()), BlockLocation::Key("item_c.flac".to_string())], produced);
+
+        // Structural problems are still errors.
+        assert!(YamlMetaReader::from_str_lenient("just a string", MetaTarget::Siblings).is_err());
+        assert!(YamlMetaReader::from_str_lenient("[unclosed", MetaTarget::Siblings).is_err());
+        assert!(YamlMetaReader::from_str_lenient("__bad_key: val", MetaTarget::Contains).is_err());
+    }
 }
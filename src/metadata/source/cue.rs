@@ -0,0 +1,263 @@
+// Reads cue sheets, which describe the tracks of a single-file album rip.
+// A cue sheet named after an audio file (e.g. `album.cue` for `album.flac`) provides metadata for that file.
+// Album-level commands become fields of the file's block, and each track becomes a mapping in the `tracks` field.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use metadata::{MetaBlock, MetaBlockSeq, MetaKey, MetaValue};
+use metadata::source::MetaSource;
+use error::*;
+
+const CUE_EXT: &str = "cue";
+const TRACKS_FIELD: &str = "tracks";
+
+/// The contents of a cue sheet.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CueSheet {
+    pub album: MetaBlock,
+    pub tracks: MetaBlockSeq,
+}
+
+/// Splits a cue sheet line into tokens, keeping quoted strings together.
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut curr = String::new();
+    let mut in_quotes = false;
+    let mut has_token = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => { in_quotes = !in_quotes; has_token = true; },
+            c if c.is_whitespace() && !in_quotes => {
+                if has_token {
+                    tokens.push(curr.clone());
+                    curr.clear();
+                    has_token = false;
+                }
+            },
+            c => { curr.push(c); has_token = true; },
+        }
+    }
+
+    if has_token {
+        tokens.push(curr);
+    }
+
+    tokens
+}
+
+fn str_val<S: Into<String>>(s: S) -> MetaValue {
+    MetaValue::Str(s.into())
+}
+
+pub fn parse_cue_str<S: AsRef<str>>(s: S) -> Result<CueSheet> {
+    let mut cue_sheet = CueSheet::default();
+    let mut curr_file: Option<String> = None;
+
+    for (line_num, line) in s.as_ref().lines().enumerate() {
+        let tokens = tokenize(line);
+
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (command.to_uppercase(), args),
+            None => continue,
+        };
+
+        // Commands that come before the first track describe the whole album.
+        let block = match cue_sheet.tracks.last_mut() {
+            Some(track_block) => track_block,
+            None => &mut cue_sheet.album,
+        };
+
+        match (command.as_str(), args) {
+            ("REM", args) if args.len() >= 2 => {
+                block.insert(args[0].to_lowercase(), str_val(args[1..].join(" ")));
+            },
+            ("TITLE", &[ref val]) | ("PERFORMER", &[ref val]) | ("SONGWRITER", &[ref val]) | ("ISRC", &[ref val]) | ("CATALOG", &[ref val]) => {
+                block.insert(command.to_lowercase(), str_val(val.as_str()));
+            },
+            ("FILE", args) if !args.is_empty() => {
+                curr_file = Some(args[0].clone());
+
+                if cue_sheet.tracks.is_empty() {
+                    cue_sheet.album.insert(String::from("file"), str_val(args[0].as_str()));
+                }
+            },
+            ("TRACK", args) if !args.is_empty() => {
+                let mut track_block = MetaBlock::new();
+                track_block.insert(String::from("track_num"), str_val(args[0].as_str()));
+
+                // Only note the file for tracks that are not in the album's file.
+                if let Some(ref file) = curr_file {
+                    if cue_sheet.album.get("file") != Some(&str_val(file.as_str())) {
+                        track_block.insert(String::from("file"), str_val(file.as_str()));
+                    }
+                }
+
+                cue_sheet.tracks.push(track_block);
+            },
+            ("INDEX", &[ref index_num, ref timestamp]) => {
+                let entry = block.entry(String::from("indexes")).or_insert_with(|| MetaValue::Map(BTreeMap::new()));
+
+                if let MetaValue::Map(ref mut indexes) = *entry {
+                    indexes.insert(MetaKey::Str(index_num.clone()), str_val(timestamp.as_str()));
+                }
+            },
+            ("FLAGS", _) | ("PREGAP", _) | ("POSTGAP", _) | ("CDTEXTFILE", _) => {},
+            _ => bail!("invalid cue sheet command on line {}: '{}'", line_num + 1, line.trim()),
+        }
+    }
+
+    Ok(cue_sheet)
+}
+
+pub fn read_cue_file<P: AsRef<Path>>(cue_fp: P) -> Result<CueSheet> {
+    let mut f = File::open(cue_fp)?;
+
+    let mut buffer = String::new();
+    f.read_to_string(&mut buffer)?;
+
+    parse_cue_str(buffer)
+}
+
+/// Provides metadata for audio files from cue sheets with the same file stem.
+pub struct CueMetaSource;
+
+impl MetaSource for CueMetaSource {
+    fn name(&self) -> &str {
+        "cue"
+    }
+
+    fn block_for_item(&self, abs_item_path: &Path) -> Result<Option<MetaBlock>> {
+        if !abs_item_path.is_file() || abs_item_path.extension().map_or(false, |e| e == CUE_EXT) {
+            return Ok(None);
+        }
+
+        let cue_fp = abs_item_path.with_extension(CUE_EXT);
+
+        if !cue_fp.is_file() {
+            return Ok(None);
+        }
+
+        let cue_sheet = read_cue_file(&cue_fp).chain_err(|| format!("unable to read cue sheet: '{}'", cue_fp.to_string_lossy()))?;
+
+        let mut mb = cue_sheet.album;
+
+        let tracks = {
+            cue_sheet.tracks
+                .into_iter()
+                .map(|track_block| MetaValue::Map(track_block.into_iter().map(|(k, v)| (MetaKey::Str(k), v)).collect()))
+                .collect()
+        };
+        mb.insert(String::from(TRACKS_FIELD), MetaValue::Seq(tracks));
+
+        Ok(Some(mb))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use metadata::{MetaKey, MetaValue};
+    use metadata::source::MetaSource;
+
+    use super::{tokenize, parse_cue_str, CueMetaSource};
+
+    const CUE_TEXT: &str = r#"REM GENRE "Progressive Rock"
+REM DATE 1973
+PERFORMER "Some Band"
+TITLE "Some Album"
+FILE "album.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "First Song"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Second Song"
+    PERFORMER "Guest Artist"
+    INDEX 00 04:10:20
+    INDEX 01 04:12:00
+"#;
+
+    #[test]
+    fn test_tokenize() {
+        let inputs_and_expected = vec![
+            ("", vec![]),
+            ("TRACK 01 AUDIO", vec!["TRACK", "01", "AUDIO"]),
+            (r#"  TITLE "Quoted  Title" "#, vec!["TITLE", "Quoted  Title"]),
+            (r#"TITLE """#, vec!["TITLE", ""]),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = tokenize(input);
+            assert_eq!(expected, produced);
+        }
+    }
+
+    #[test]
+    fn test_parse_cue_str() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let cue_sheet = parse_cue_str(CUE_TEXT).expect("Unable to parse cue sheet");
+
+        let expected_album = btreemap![
+            String::from("genre") => str_val("Progressive Rock"),
+            String::from("date") => str_val("1973"),
+            String::from("performer") => str_val("Some Band"),
+            String::from("title") => str_val("Some Album"),
+            String::from("file") => str_val("album.flac"),
+        ];
+        assert_eq!(expected_album, cue_sheet.album);
+
+        let expected_tracks = vec![
+            btreemap![
+                String::from("track_num") => str_val("01"),
+                String::from("title") => str_val("First Song"),
+                String::from("indexes") => MetaValue::Map(btreemap![
+                    MetaKey::Str(String::from("01")) => str_val("00:00:00"),
+                ]),
+            ],
+            btreemap![
+                String::from("track_num") => str_val("02"),
+                String::from("title") => str_val("Second Song"),
+                String::from("performer") => str_val("Guest Artist"),
+                String::from("indexes") => MetaValue::Map(btreemap![
+                    MetaKey::Str(String::from("00")) => str_val("04:10:20"),
+                    MetaKey::Str(String::from("01")) => str_val("04:12:00"),
+                ]),
+            ],
+        ];
+        assert_eq!(expected_tracks, cue_sheet.tracks);
+
+        assert!(parse_cue_str("NONSENSE here").is_err());
+    }
+
+    #[test]
+    fn test_cue_meta_source() {
+        let temp = TempDir::new("test_cue_meta_source").unwrap();
+        let tp = temp.path();
+
+        File::create(tp.join("album.flac")).unwrap();
+        File::create(tp.join("other.flac")).unwrap();
+        let mut f = File::create(tp.join("album.cue")).unwrap();
+        f.write_all(CUE_TEXT.as_bytes()).unwrap();
+
+        let source = CueMetaSource;
+
+        let mb = source.block_for_item(&tp.join("album.flac")).expect("Unable to read source").expect("No block found");
+        assert_eq!(Some(&MetaValue::Str(String::from("Some Album"))), mb.get("title"));
+
+        match mb.get("tracks") {
+            Some(&MetaValue::Seq(ref tracks)) => { assert_eq!(2, tracks.len()); },
+            _ => panic!("tracks not found"),
+        }
+
+        assert_eq!(None, source.block_for_item(&tp.join("other.flac")).expect("Unable to read source"));
+        assert_eq!(None, source.block_for_item(&tp.join("album.cue")).expect("Unable to read source"));
+    }
+}
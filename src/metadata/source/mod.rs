@@ -0,0 +1,23 @@
+// Meta sources provide metadata for items from somewhere other than taggu's own meta files (e.g. cue sheets).
+// Values from meta files always take precedence over values from meta sources.
+
+#[cfg(feature = "cue")] pub mod cue;
+#[cfg(feature = "replaygain")] pub mod replaygain;
+
+use std::path::Path;
+
+use metadata::MetaBlock;
+use error::*;
+
+pub trait MetaSource {
+    /// A short, human-readable name for this source, used in diagnostics.
+    fn name(&self) -> &str;
+
+    /// Returns false if this source never provides a field, so that expensive sources can be skipped.
+    fn provides_field(&self, _field_name: &str) -> bool {
+        true
+    }
+
+    /// Returns the meta block that this source provides for an item, if any.
+    fn block_for_item(&self, abs_item_path: &Path) -> Result<Option<MetaBlock>>;
+}
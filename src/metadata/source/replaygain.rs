@@ -0,0 +1,261 @@
+// Computes ReplayGain 2.0 values by decoding FLAC audio, and provides them as fields.
+// Album values are computed over all of the FLAC files in the same directory as the item.
+// TODO: Albums that span multiple directories (e.g. one per disc) are treated as separate albums.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use claxon::FlacReader;
+
+use loudness::{LoudnessMeter, Measurement};
+use metadata::{MetaBlock, MetaValue};
+use metadata::source::MetaSource;
+use error::*;
+
+pub const TRACK_GAIN_FIELD: &str = "replaygain_track_gain";
+pub const TRACK_PEAK_FIELD: &str = "replaygain_track_peak";
+pub const ALBUM_GAIN_FIELD: &str = "replaygain_album_gain";
+pub const ALBUM_PEAK_FIELD: &str = "replaygain_album_peak";
+
+const FIELDS: &[&str] = &[TRACK_GAIN_FIELD, TRACK_PEAK_FIELD, ALBUM_GAIN_FIELD, ALBUM_PEAK_FIELD];
+const FLAC_EXT: &str = "flac";
+
+fn is_flac_file(path: &Path) -> bool {
+    path.is_file() && path.extension().and_then(|e| e.to_str()).map_or(false, |e| e.eq_ignore_ascii_case(FLAC_EXT))
+}
+
+pub fn measure_flac_file<P: AsRef<Path>>(path: P) -> Result<Measurement> {
+    let path = path.as_ref();
+
+    let mut reader = FlacReader::open(path).map_err(|e| format!("unable to open FLAC file: {}", e))?;
+    let info = reader.streaminfo();
+
+    let channels = info.channels as usize;
+    let scale = f64::from(1u32 << (info.bits_per_sample - 1));
+
+    let mut meter = LoudnessMeter::new(channels, info.sample_rate);
+    let mut frame: Vec<f64> = Vec::with_capacity(channels);
+
+    for sample in reader.samples() {
+        let sample = sample.map_err(|e| format!("unable to decode FLAC file: {}", e))?;
+        frame.push(f64::from(sample) / scale);
+
+        if frame.len() == channels {
+            meter.push_frame(&frame);
+            frame.clear();
+        }
+    }
+
+    Ok(meter.finish())
+}
+
+fn format_gain(gain: Option<f64>) -> MetaValue {
+    match gain {
+        Some(gain) => MetaValue::Str(format!("{:.2} dB", gain)),
+        // Silent audio cannot be measured.
+        None => MetaValue::Nil,
+    }
+}
+
+fn format_peak(peak: f64) -> MetaValue {
+    MetaValue::Str(format!("{:.6}", peak))
+}
+
+/// Provides ReplayGain fields for FLAC files.
+/// Measurements are cached by path and modification time, since decoding is slow.
+#[derive(Default)]
+pub struct ReplayGainSource {
+    cache: Mutex<HashMap<(PathBuf, SystemTime), Measurement>>,
+}
+
+impl ReplayGainSource {
+    pub fn new() -> Self {
+        ReplayGainSource::default()
+    }
+
+    fn measure(&self, path: &Path) -> Result<Measurement> {
+        let key = (path.to_path_buf(), path.metadata()?.modified()?);
+
+        if let Some(m) = self.cache.lock().map_err(|_| "replaygain cache is poisoned")?.get(&key) {
+            return Ok(m.clone());
+        }
+
+        let m = measure_flac_file(path).chain_err(|| format!("unable to measure loudness: '{}'", path.to_string_lossy()))?;
+
+        self.cache.lock().map_err(|_| "replaygain cache is poisoned")?.insert(key, m.clone());
+
+        Ok(m)
+    }
+}
+
+impl MetaSource for ReplayGainSource {
+    fn name(&self) -> &str {
+        "replaygain"
+    }
+
+    fn provides_field(&self, field_name: &str) -> bool {
+        FIELDS.contains(&field_name)
+    }
+
+    fn block_for_item(&self, abs_item_path: &Path) -> Result<Option<MetaBlock>> {
+        if !is_flac_file(abs_item_path) {
+            return Ok(None);
+        }
+
+        let track = self.measure(abs_item_path)?;
+
+        let mut album_paths: Vec<PathBuf> = vec![];
+
+        if let Some(dir_path) = abs_item_path.parent() {
+            for entry in dir_path.read_dir()? {
+                let entry_path = entry?.path();
+
+                if is_flac_file(&entry_path) {
+                    album_paths.push(entry_path);
+                }
+            }
+        }
+
+        album_paths.sort();
+
+        let mut album_measurements = vec![];
+
+        for album_path in &album_paths {
+            album_measurements.push(self.measure(album_path)?);
+        }
+
+        let album = Measurement::combine(&album_measurements);
+
+        Ok(Some(btreemap![
+            String::from(TRACK_GAIN_FIELD) => format_gain(track.gain()),
+            String::from(TRACK_PEAK_FIELD) => format_peak(track.peak),
+            String::from(ALBUM_GAIN_FIELD) => format_gain(album.gain()),
+            String::from(ALBUM_PEAK_FIELD) => format_peak(album.peak),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+
+    use tempdir::TempDir;
+
+    use metadata::MetaValue;
+    use metadata::source::MetaSource;
+
+    use super::{ReplayGainSource, TRACK_GAIN_FIELD, TRACK_PEAK_FIELD, ALBUM_GAIN_FIELD, ALBUM_PEAK_FIELD};
+
+    const SAMPLE_RATE: u32 = 48000;
+    const BLOCK_SIZE: usize = 4096;
+
+    fn crc8(data: &[u8]) -> u8 {
+        let mut crc = 0u8;
+
+        for &byte in data {
+            crc ^= byte;
+
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+            }
+        }
+
+        crc
+    }
+
+    fn crc16(data: &[u8]) -> u16 {
+        let mut crc = 0u16;
+
+        for &byte in data {
+            crc ^= u16::from(byte) << 8;
+
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+            }
+        }
+
+        crc
+    }
+
+    /// Writes a stereo, 16-bit, 48kHz FLAC file containing a sine wave, using uncompressed (verbatim) subframes.
+    fn write_sine_flac(path: &Path, amplitude: f64, num_blocks: usize) {
+        let total_samples = (BLOCK_SIZE * num_blocks) as u64;
+
+        let mut raw: Vec<u8> = b"fLaC".to_vec();
+
+        // Stream info block, which is also the last metadata block.
+        raw.extend_from_slice(&[0x80, 0x00, 0x00, 34]);
+        raw.extend_from_slice(&[(BLOCK_SIZE >> 8) as u8, BLOCK_SIZE as u8, (BLOCK_SIZE >> 8) as u8, BLOCK_SIZE as u8]);
+        raw.extend_from_slice(&[0u8; 6]);
+        let packed: u64 = u64::from(SAMPLE_RATE) << 44 | 1 << 41 | 15 << 36 | total_samples;
+        for i in (0..8).rev() {
+            raw.push((packed >> (i * 8)) as u8);
+        }
+        raw.extend_from_slice(&[0u8; 16]);
+
+        for frame_num in 0..num_blocks {
+            // Fixed block size, 16-bit block size at end of header, 48kHz, independent stereo, 16 bits per sample.
+            let mut frame = vec![0xFF, 0xF8, 0x7A, 0x18, frame_num as u8, ((BLOCK_SIZE - 1) >> 8) as u8, (BLOCK_SIZE - 1) as u8];
+            let header_crc = crc8(&frame);
+            frame.push(header_crc);
+
+            for _ in 0..2 {
+                // Verbatim subframe.
+                frame.push(0x02);
+
+                for i in 0..BLOCK_SIZE {
+                    let t = (frame_num * BLOCK_SIZE + i) as f64 / f64::from(SAMPLE_RATE);
+                    let s = (amplitude * 32767.0 * (2.0 * PI * 997.0 * t).sin()) as i16;
+                    frame.extend_from_slice(&[(s >> 8) as u8, s as u8]);
+                }
+            }
+
+            let frame_crc = crc16(&frame);
+            frame.extend_from_slice(&[(frame_crc >> 8) as u8, frame_crc as u8]);
+
+            raw.extend_from_slice(&frame);
+        }
+
+        File::create(path).unwrap().write_all(&raw).unwrap();
+    }
+
+    fn parse_db(mv: Option<&MetaValue>) -> f64 {
+        match mv {
+            Some(&MetaValue::Str(ref s)) => s.trim_end_matches(" dB").parse().unwrap(),
+            _ => panic!("unexpected gain value: {:?}", mv),
+        }
+    }
+
+    #[test]
+    fn test_replaygain_source() {
+        let temp = TempDir::new("test_replaygain_source").unwrap();
+        let tp = temp.path();
+
+        // About a second of audio each.
+        write_sine_flac(&tp.join("loud.flac"), 1.0, 12);
+        write_sine_flac(&tp.join("quiet.flac"), 0.5, 12);
+        File::create(tp.join("notes.txt")).unwrap();
+
+        let source = ReplayGainSource::new();
+
+        assert!(source.provides_field(TRACK_GAIN_FIELD));
+        assert!(!source.provides_field("title"));
+
+        let loud = source.block_for_item(&tp.join("loud.flac")).expect("Unable to read source").expect("No block found");
+        let quiet = source.block_for_item(&tp.join("quiet.flac")).expect("Unable to read source").expect("No block found");
+
+        assert!((parse_db(loud.get(TRACK_GAIN_FIELD)) + 18.0).abs() < 0.1);
+        assert!((parse_db(quiet.get(TRACK_GAIN_FIELD)) + 12.0).abs() < 0.1);
+
+        // Album values are shared.
+        assert_eq!(loud.get(ALBUM_GAIN_FIELD), quiet.get(ALBUM_GAIN_FIELD));
+        assert_eq!(loud.get(ALBUM_PEAK_FIELD), loud.get(TRACK_PEAK_FIELD));
+
+        assert_eq!(None, source.block_for_item(&tp.join("notes.txt")).expect("Unable to read source"));
+    }
+}
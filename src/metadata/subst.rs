@@ -0,0 +1,111 @@
+// Substitution of `${name}` variables in meta values, so that meta files can refer to values that differ between machines.
+// A literal `${` can be written as `$${`.
+
+use metadata::MetaValue;
+use error::*;
+
+const VAR_START: &str = "${";
+const VAR_END: char = '}';
+const ESCAPED_VAR_START: &str = "$${";
+
+/// Replaces every variable in a string, using a function that gives the value of a variable by name.
+pub fn substitute_str<F>(s: &str, var_value: &F) -> Result<String>
+where F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(i) = rest.find('$') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if rest.starts_with(ESCAPED_VAR_START) {
+            result.push_str(VAR_START);
+            rest = &rest[ESCAPED_VAR_START.len()..];
+        }
+        else if rest.starts_with(VAR_START) {
+            let after_start = &rest[VAR_START.len()..];
+            let end = after_start.find(VAR_END).ok_or_else(|| ErrorKind::UnclosedVariable(s.to_string()))?;
+            let var_name = &after_start[..end];
+
+            let val = var_value(var_name).ok_or_else(|| ErrorKind::UndefinedVariable(var_name.to_string()))?;
+            result.push_str(&val);
+
+            rest = &after_start[end + 1..];
+        }
+        else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Replaces every variable in the strings of a meta value, including nested ones.
+/// Mapping keys are left alone.
+pub fn substitute<F>(mv: MetaValue, var_value: &F) -> Result<MetaValue>
+where F: Fn(&str) -> Option<String>,
+{
+    Ok(match mv {
+        MetaValue::Nil => MetaValue::Nil,
+        MetaValue::Str(s) => MetaValue::Str(substitute_str(&s, var_value)?),
+        MetaValue::Seq(mvs) => {
+            MetaValue::Seq(mvs.into_iter().map(|mv| substitute(mv, var_value)).collect::<Result<_>>()?)
+        },
+        MetaValue::Map(map) => {
+            MetaValue::Map(map.into_iter().map(|(mk, mv)| substitute(mv, var_value).map(|mv| (mk, mv))).collect::<Result<_>>()?)
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use metadata::{MetaKey, MetaValue};
+
+    use super::{substitute, substitute_str};
+
+    fn var_value(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some(String::from("/home/user")),
+            "lib.root" => Some(String::from("/music")),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_substitute_str() {
+        let inputs_and_expected = vec![
+            ("plain", "plain"),
+            ("${lib.root}/covers", "/music/covers"),
+            ("${HOME} and ${lib.root}", "/home/user and /music"),
+            ("costs $5", "costs $5"),
+            ("$${HOME}", "${HOME}"),
+            ("$$${HOME}", "$${HOME}"),
+            ("", ""),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, substitute_str(input, &var_value).unwrap());
+        }
+
+        assert!(substitute_str("${MISSING}", &var_value).is_err());
+        assert!(substitute_str("${HOME", &var_value).is_err());
+    }
+
+    #[test]
+    fn test_substitute() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let input = MetaValue::Map(btreemap![
+            MetaKey::Str(String::from("${HOME}")) => MetaValue::Seq(vec![str_val("${lib.root}"), MetaValue::Nil]),
+        ]);
+        let expected = MetaValue::Map(btreemap![
+            MetaKey::Str(String::from("${HOME}")) => MetaValue::Seq(vec![str_val("/music"), MetaValue::Nil]),
+        ]);
+
+        assert_eq!(expected, substitute(input, &var_value).unwrap());
+    }
+}
@@ -0,0 +1,181 @@
+// This module exports resolved metadata as MPD stickers, so that an MPD server can see taggu fields without retagging files.
+
+use std::path::Path;
+
+use library::{Library, WalkStep};
+use lookup::LookupContext;
+use lookup::options::LookupOptions;
+use metadata::MetaValue;
+use helpers::normalize;
+use progress::Progress;
+use error::*;
+
+const STICKER_SEQ_SEPARATOR: &str = "; ";
+
+/// Mirrors the table that MPD creates in its sticker database.
+const STICKER_SCHEMA_SQL: &str = "\
+CREATE TABLE IF NOT EXISTS sticker(type VARCHAR NOT NULL, uri VARCHAR NOT NULL, name VARCHAR NOT NULL, value VARCHAR NOT NULL);
+CREATE UNIQUE INDEX IF NOT EXISTS sticker_unique ON sticker(type, uri, name);
+";
+
+/// A single song sticker, keyed by the song's URI relative to the MPD music directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sticker {
+    pub uri: String,
+    pub name: String,
+    pub value: String,
+}
+
+fn sql_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// MPD expects URIs relative to its music directory, which is assumed to be the library root, and always uses forward slashes.
+fn song_uri(media_lib: &Library, abs_item_path: &Path) -> Result<String> {
+    let rel_path = abs_item_path.strip_prefix(media_lib.root_dir())
+        .map_err(|_| ErrorKind::InvalidSubPath(abs_item_path.to_path_buf(), media_lib.root_dir().to_path_buf()))?;
+
+    let parts: Vec<String> = rel_path.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+
+    Ok(parts.join("/"))
+}
+
+/// Resolves the given fields for a single song, including values inherited from ancestors.
+/// Fields that are missing, null, or cannot be stored as a single string (e.g. mappings) are skipped.
+fn song_stickers<S: AsRef<str>>(
+    lookup_ctx: &mut LookupContext,
+    media_lib: &Library,
+    abs_item_path: &Path,
+    field_names: &[S],
+    ) -> Result<Vec<Sticker>>
+{
+    let uri = song_uri(media_lib, abs_item_path)?;
+    let mut stickers = vec![];
+
+    for field_name in field_names {
+        let field_name = field_name.as_ref();
+        let options = LookupOptions::exact(field_name).join_seq(STICKER_SEQ_SEPARATOR);
+
+        match lookup_ctx.lookup(abs_item_path, &options)? {
+            Some(MetaValue::Str(value)) => {
+                stickers.push(Sticker { uri: uri.clone(), name: field_name.to_string(), value });
+            },
+            Some(MetaValue::Nil) | None => {},
+            Some(_) => { warn!("skipping field that cannot be stored as a sticker: '{}'", field_name); },
+        }
+    }
+
+    Ok(stickers)
+}
+
+/// Collects stickers for every non-directory item in a directory and its selected subdirectories, in walk order.
+/// Items marked as ignored are left out.
+pub fn collect_stickers<P: AsRef<Path>, S: AsRef<str>, G: Progress>(
+    media_lib: &Library,
+    abs_dir_path: P,
+    field_names: &[S],
+    progress: &mut G,
+    ) -> Result<Vec<Sticker>>
+{
+    let abs_dir_path = normalize(abs_dir_path.as_ref());
+
+    // Rule: dir path must be a directory.
+    ensure!(media_lib.vfs().is_dir(&abs_dir_path), ErrorKind::NotADirectory(abs_dir_path.clone()));
+
+    let mut lookup_ctx = LookupContext::new(media_lib);
+    let mut stickers = vec![];
+
+    media_lib.walk_items(&abs_dir_path, |step| {
+        match step {
+            WalkStep::EnterDir(dir_path) => lookup_ctx.cache_item_file_with_progress(dir_path, progress)?,
+            WalkStep::Item { path, is_dir } => {
+                // Ignored items are left out, along with everything inside them.
+                if lookup_ctx.is_ignored(path)? {
+                    return Ok(false);
+                }
+
+                if !is_dir {
+                    lookup_ctx.cache_item_file_with_progress(path, progress)?;
+                    progress.item_scanned(path);
+
+                    stickers.extend(song_stickers(&mut lookup_ctx, media_lib, path, field_names)?);
+                }
+            },
+            WalkStep::LeaveDir(_) => {},
+        }
+
+        Ok(true)
+    })?;
+
+    Ok(stickers)
+}
+
+/// Renders stickers as an SQL script that can be applied to an MPD sticker database, e.g. with `sqlite3 sticker.sql < script`.
+/// Existing stickers with the same URI and name are replaced; MPD should not be running while the script is applied.
+pub fn stickers_as_sql(stickers: &[Sticker]) -> String {
+    let mut sql = String::from(STICKER_SCHEMA_SQL);
+    sql.push_str("BEGIN TRANSACTION;\n");
+
+    for sticker in stickers {
+        sql.push_str(&format!(
+            "INSERT OR REPLACE INTO sticker(type, uri, name, value) VALUES('song', {}, {}, {});\n",
+            sql_quote(&sticker.uri),
+            sql_quote(&sticker.name),
+            sql_quote(&sticker.value),
+        ));
+    }
+
+    sql.push_str("COMMIT;\n");
+    sql
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use fixtures::default_setup;
+    use progress::{NoProgress, ProgressCounts};
+
+    use super::{Sticker, collect_stickers, stickers_as_sql};
+
+    #[test]
+    fn test_collect_stickers() {
+        let (temp_media_root, media_lib) = default_setup("test_collect_stickers");
+        let tp = temp_media_root.path();
+
+        let mut f = File::create(tp.join("ALBUM_01").join("self.yml")).unwrap();
+        writeln!(f, "artist:\n  - Artist A\n  - Artist B\ncredits:\n  composer: Composer\nempty: ~").unwrap();
+
+        let sticker = |uri: &str, name: &str, value: &str| Sticker { uri: uri.to_string(), name: name.to_string(), value: value.to_string() };
+
+        let mut f = File::create(tp.join("ALBUM_01").join("DISC_02").join("item.yml")).unwrap();
+        writeln!(f, "- TRACK_01_item_key: TRACK_01_item_val\n- TRACK_02_item_key: TRACK_02_item_val\n- __ignore: true").unwrap();
+
+        let field_names = ["artist", "credits", "empty", "TRACK_02_item_key"];
+        let mut progress = ProgressCounts::default();
+        let produced = collect_stickers(&media_lib, tp.join("ALBUM_01").join("DISC_02"), &field_names, &mut progress).expect("Unable to collect stickers");
+
+        let expected = vec![
+            sticker("ALBUM_01/DISC_02/TRACK_01.flac", "artist", "Artist A; Artist B"),
+            sticker("ALBUM_01/DISC_02/TRACK_02.flac", "artist", "Artist A; Artist B"),
+            sticker("ALBUM_01/DISC_02/TRACK_02.flac", "TRACK_02_item_key", "TRACK_02_item_val"),
+        ];
+        assert_eq!(expected, produced);
+        assert_eq!(2, progress.items_scanned);
+
+        assert!(collect_stickers(&media_lib, tp.join("ALBUM_04.flac"), &field_names, &mut NoProgress).is_err());
+    }
+
+    #[test]
+    fn test_stickers_as_sql() {
+        let stickers = vec![
+            Sticker { uri: String::from("A/B.flac"), name: String::from("title"), value: String::from("It's") },
+        ];
+
+        let produced = stickers_as_sql(&stickers);
+
+        assert!(produced.starts_with("CREATE TABLE IF NOT EXISTS sticker("));
+        assert!(produced.contains("BEGIN TRANSACTION;\nINSERT OR REPLACE INTO sticker(type, uri, name, value) VALUES('song', 'A/B.flac', 'title', 'It''s');\nCOMMIT;\n"));
+    }
+}
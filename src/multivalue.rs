@@ -0,0 +1,223 @@
+// This module splits strings that join several values with a delimiter (e.g. `A feat. B`, or `Rock; Jazz`) into sequences of values.
+// Embedded tags often hold several artists or genres in a single string, while meta files are better off with a sequence of them.
+
+use yaml_rust::Yaml;
+
+use metadata::MetaValue;
+use error::*;
+
+/// The delimiters that several values of a field may be joined with, per field.
+/// Delimiters are matched without regard to ASCII case, so `feat.` also splits `A Feat. B`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitRules {
+    rules: Vec<(String, Vec<String>)>,
+}
+
+impl Default for SplitRules {
+    fn default() -> Self {
+        let artist_delimiters = &["; ", " feat. ", " ft. ", " featuring "];
+
+        let mut split_rules = SplitRules::new();
+        split_rules
+            .rule("artist", artist_delimiters)
+            .rule("album_artist", artist_delimiters)
+            .rule("composer", &["; "])
+            .rule("genre", &["; ", " / "]);
+        split_rules
+    }
+}
+
+impl SplitRules {
+    pub fn new() -> Self {
+        SplitRules { rules: vec![] }
+    }
+
+    /// Sets the delimiters of a field, replacing any existing rule for that field.
+    pub fn rule<S: Into<String>>(&mut self, field_name: S, delimiters: &[&str]) -> &mut Self {
+        let field_name = field_name.into();
+
+        self.rules.retain(|&(ref f, _)| *f != field_name);
+        self.rules.push((field_name, delimiters.iter().map(|d| d.to_string()).collect()));
+        self
+    }
+
+    /// Reads a mapping of field names to delimiters, or sequences of them, adding to (or overriding) the existing rules.
+    /// A field mapped to an empty sequence is never split.
+    pub fn extend_from_yaml(&mut self, y: &Yaml) -> Result<()> {
+        match *y {
+            Yaml::Hash(ref hsh) => {
+                for (field_name_y, delimiters_y) in hsh {
+                    let field_name = field_name_y.as_str().ok_or("split rules must map field names to delimiters")?;
+
+                    let delimiters: Vec<&str> = match *delimiters_y {
+                        Yaml::String(ref delimiter) => vec![delimiter.as_str()],
+                        Yaml::Array(ref arr) => arr.iter().map(Yaml::as_str).collect::<Option<_>>()
+                            .ok_or_else(|| format!("delimiters of field '{}' must be strings", field_name))?,
+                        _ => bail!("delimiters of field '{}' must be a string or a sequence of strings", field_name),
+                    };
+
+                    // Rule: delimiters must not be empty.
+                    ensure!(delimiters.iter().all(|d| !d.is_empty()), format!("delimiters of field '{}' must not be empty", field_name));
+
+                    self.rule(field_name, &delimiters);
+                }
+
+                Ok(())
+            },
+            Yaml::Null => Ok(()),
+            _ => bail!("split rules must be a mapping of field names to delimiters"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.iter().all(|&(_, ref delimiters)| delimiters.is_empty())
+    }
+
+    /// Returns the names of the fields with delimiters, in rule order.
+    pub fn fields(&self) -> Vec<&str> {
+        self.rules.iter().filter(|&&(_, ref delimiters)| !delimiters.is_empty()).map(|&(ref f, _)| f.as_str()).collect()
+    }
+
+    pub fn delimiters_for<S: AsRef<str>>(&self, field_name: S) -> &[String] {
+        let field_name = field_name.as_ref();
+
+        self.rules.iter().find(|&&(ref f, _)| f == field_name).map_or(&[][..], |&(_, ref delimiters)| delimiters.as_slice())
+    }
+
+    /// Splits each of several values of a field, keeping their order.
+    pub fn split_values<S: AsRef<str>>(&self, field_name: S, vals: &[String]) -> Vec<String> {
+        let delimiters = self.delimiters_for(field_name);
+
+        vals.iter().flat_map(|s| split_str(s, delimiters)).collect()
+    }
+
+    /// Splits the strings in a value of a field, turning a string into a sequence if it holds several values.
+    /// Sequences have each of their strings split in place, and anything else is left as is.
+    pub fn normalize<S: AsRef<str>>(&self, field_name: S, mv: &MetaValue) -> MetaValue {
+        let delimiters = self.delimiters_for(field_name);
+
+        match *mv {
+            MetaValue::Str(ref s) => {
+                let mut parts = split_str(s, delimiters);
+
+                if parts.len() == 1 { MetaValue::Str(parts.remove(0)) }
+                else { MetaValue::Seq(parts.into_iter().map(MetaValue::Str).collect()) }
+            },
+            MetaValue::Seq(ref mvs) => {
+                MetaValue::Seq(mvs.iter().flat_map(|mv| match *mv {
+                    MetaValue::Str(ref s) => split_str(s, delimiters).into_iter().map(MetaValue::Str).collect(),
+                    _ => vec![mv.clone()],
+                }).collect())
+            },
+            MetaValue::Nil | MetaValue::Map(_) => mv.clone(),
+        }
+    }
+}
+
+/// Finds the earliest match of any of the delimiters in a string, along with the length of the delimiter.
+/// Longer delimiters win at the same position, so that e.g. ` / ` is preferred over `/`.
+fn find_delimiter(s: &str, delimiters: &[String]) -> Option<(usize, usize)> {
+    let bytes = s.as_bytes();
+
+    (0..bytes.len())
+        .filter_map(|i| {
+            delimiters.iter()
+                .map(|d| d.as_bytes())
+                .filter(|d| bytes[i..].len() >= d.len() && bytes[i..i + d.len()].eq_ignore_ascii_case(d))
+                .map(|d| (i, d.len()))
+                .max_by_key(|&(_, len)| len)
+        })
+        .next()
+}
+
+/// Splits a string at every match of any of the delimiters, trimming the parts and leaving out empty ones.
+/// A string that has no parts left is kept as it is.
+pub fn split_str(s: &str, delimiters: &[String]) -> Vec<String> {
+    let mut parts = vec![];
+    let mut rest = s;
+
+    // Delimiters may be empty when given in code, and an empty delimiter would match everywhere.
+    let delimiters: Vec<String> = delimiters.iter().filter(|d| !d.is_empty()).cloned().collect();
+
+    while let Some((start, len)) = find_delimiter(rest, &delimiters) {
+        parts.push(rest[..start].trim().to_string());
+        rest = &rest[start + len..];
+    }
+
+    parts.push(rest.trim().to_string());
+    parts.retain(|p| !p.is_empty());
+
+    if parts.is_empty() {
+        return vec![s.to_string()];
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+
+    use metadata::MetaValue;
+
+    use super::{SplitRules, split_str};
+
+    #[test]
+    fn test_split_str() {
+        let delimiters = vec![String::from("; "), String::from(" feat. "), String::from("/"), String::from(" / ")];
+
+        let inputs_and_expected = vec![
+            ("A", vec!["A"]),
+            ("A; B", vec!["A", "B"]),
+            ("A FEAT. B; C", vec!["A", "B", "C"]),
+            ("AC / DC", vec!["AC", "DC"]),
+            ("A; ; B; ", vec!["A", "B"]),
+            ("Café feat. Ñu", vec!["Café", "Ñu"]),
+            ("; ", vec!["; "]),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, split_str(input, &delimiters), "unexpected parts for: {}", input);
+        }
+
+        assert_eq!(vec!["A; B"], split_str("A; B", &[]));
+    }
+
+    #[test]
+    fn test_split_rules() {
+        let text = "genre: [', ']\nartist: ' & '\ncomposer: []\n";
+        let y = &YamlLoader::load_from_str(text).unwrap()[0];
+
+        let mut split_rules = SplitRules::default();
+        split_rules.extend_from_yaml(y).unwrap();
+
+        assert_eq!(vec!["album_artist", "genre", "artist"], split_rules.fields());
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        // Rules from YAML replace the built-in ones for the same fields.
+        assert_eq!(MetaValue::Seq(vec![str_val("A"), str_val("B")]), split_rules.normalize("artist", &str_val("A & B")));
+        assert_eq!(str_val("A feat. B"), split_rules.normalize("artist", &str_val("A feat. B")));
+        assert_eq!(str_val("A; B"), split_rules.normalize("composer", &str_val("A; B")));
+        assert_eq!(str_val("A; B"), split_rules.normalize("title", &str_val("A; B")));
+
+        assert_eq!(
+            MetaValue::Seq(vec![str_val("Rock"), str_val("Jazz"), str_val("Funk"), MetaValue::Nil]),
+            split_rules.normalize("genre", &MetaValue::Seq(vec![str_val("Rock, Jazz"), str_val("Funk"), MetaValue::Nil])),
+        );
+
+        assert_eq!(vec!["A", "B", "C"], split_rules.split_values("album_artist", &[String::from("A feat. B"), String::from("C")]));
+
+        let invalid_inputs = vec![
+            "- genre",
+            "genre: {a: b}",
+            "genre: [1, {a: b}]",
+            "genre: ''",
+        ];
+
+        for input in invalid_inputs {
+            let y = &YamlLoader::load_from_str(input).unwrap()[0];
+            assert!(SplitRules::new().extend_from_yaml(y).is_err(), "expected error for: {}", input);
+        }
+    }
+}
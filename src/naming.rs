@@ -0,0 +1,300 @@
+// This module checks the file names of items against templates filled in from their metadata, e.g. `{track_num:02}. {title}.flac`, so that naming stays consistent.
+// A template is literal text with fields in braces, where a field is a field path, optionally followed by a colon and a width to pad the value to.
+// Widths starting with `0` pad with zeros, e.g. `{track_num:02}` gives `01`, and other widths pad with spaces; `{{` and `}}` are literal braces.
+// Fields are looked up with inheritance, and fields with several values are joined with `, `.
+
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::path::{Path, PathBuf};
+
+use glob;
+use yaml_rust::Yaml;
+
+use library::WalkStep;
+use metadata::MetaValue;
+use lookup::LookupContext;
+use lookup::options::LookupOptions;
+use helpers::normalize;
+use error::*;
+
+const SEQ_SEPARATOR: &str = ", ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Literal(String),
+    /// A field path, along with the width to pad its value to, and whether to pad with zeros.
+    Field(String, usize, bool),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl NameTemplate {
+    pub fn parse(s: &str) -> Result<NameTemplate> {
+        parse_parts(s).map(|parts| NameTemplate { parts }).chain_err(|| ErrorKind::InvalidNameTemplate(s.to_string()))
+    }
+
+    /// Fills in the template for an item, or returns `None` if the item has no value for one of its fields.
+    /// Path separators in values are replaced with underscores, so that a value cannot move a file into another directory.
+    pub fn render<P: AsRef<Path>>(&self, lookup_ctx: &mut LookupContext, abs_item_path: P) -> Result<Option<String>> {
+        let abs_item_path = abs_item_path.as_ref();
+        let mut name = String::new();
+
+        for part in &self.parts {
+            match *part {
+                TemplatePart::Literal(ref s) => name.push_str(s),
+                TemplatePart::Field(ref field_path, width, zero_pad) => {
+                    let options = LookupOptions::new(field_path).join_seq(SEQ_SEPARATOR);
+
+                    let value = match lookup_ctx.lookup(abs_item_path, &options)? {
+                        Some(MetaValue::Str(s)) => s.replace(|c| c == '/' || c == '\\', "_"),
+                        _ => return Ok(None),
+                    };
+
+                    let pad = if zero_pad { '0' } else { ' ' };
+
+                    for _ in value.chars().count()..width {
+                        name.push(pad);
+                    }
+
+                    name.push_str(&value);
+                },
+            }
+        }
+
+        Ok(Some(name))
+    }
+}
+
+fn parse_parts(s: &str) -> Result<Vec<TemplatePart>> {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            },
+            '{' => {
+                let mut field = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => field.push(c),
+                        None => bail!("unclosed field"),
+                    }
+                }
+
+                let (field_path, width, zero_pad) = match field.find(':') {
+                    Some(i) => {
+                        let spec = field[i + 1..].trim();
+                        let width = spec.parse::<usize>().chain_err(|| format!("invalid width: '{}'", spec))?;
+
+                        (field[..i].trim(), width, spec.starts_with('0'))
+                    },
+                    None => (field.trim(), 0, false),
+                };
+
+                ensure!(!field_path.is_empty(), "empty field");
+
+                if !literal.is_empty() {
+                    parts.push(TemplatePart::Literal(literal.split_off(0)));
+                }
+
+                parts.push(TemplatePart::Field(field_path.to_string(), width, zero_pad));
+            },
+            '}' => bail!("unmatched '}}'"),
+            c => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        parts.push(TemplatePart::Literal(literal));
+    }
+
+    Ok(parts)
+}
+
+/// Templates for the file names of items, keyed by patterns of file names, e.g. `*.flac`.
+/// The first template whose pattern matches an item's name applies; items that match no pattern are not checked.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NamingRules {
+    rules: Vec<(glob::Pattern, NameTemplate)>,
+}
+
+impl NamingRules {
+    pub fn new() -> Self {
+        NamingRules::default()
+    }
+
+    pub fn rule<S: AsRef<str>>(&mut self, name_pattern: S, template: NameTemplate) -> Result<&mut Self> {
+        let name_pattern = name_pattern.as_ref();
+        let pattern = glob::Pattern::new(name_pattern).chain_err(|| format!("invalid name pattern: '{}'", name_pattern))?;
+
+        self.rules.push((pattern, template));
+        Ok(self)
+    }
+
+    /// Adds rules from a mapping of name patterns to templates, e.g. `'*.flac': '{track_num:02}. {title}.flac'`.
+    pub fn extend_from_yaml(&mut self, y: &Yaml) -> Result<()> {
+        match *y {
+            Yaml::Hash(ref hsh) => {
+                for (name_pattern_y, template_y) in hsh {
+                    match (name_pattern_y.as_str(), template_y.as_str()) {
+                        (Some(name_pattern), Some(template)) => { self.rule(name_pattern, NameTemplate::parse(template)?)?; },
+                        _ => bail!("naming rules must map name patterns to templates"),
+                    }
+                }
+
+                Ok(())
+            },
+            Yaml::Null => Ok(()),
+            _ => bail!("naming rules must be a mapping of name patterns to templates"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn template_for<S: AsRef<str>>(&self, item_name: S) -> Option<&NameTemplate> {
+        let item_name = item_name.as_ref();
+
+        self.rules.iter().find(|&&(ref pattern, _)| pattern.matches(item_name)).map(|&(_, ref template)| template)
+    }
+}
+
+/// An item whose file name differs from the name its template gives it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameMismatch {
+    pub item_path: PathBuf,
+    pub expected_name: String,
+}
+
+impl NameMismatch {
+    /// The path that the item would be renamed to.
+    pub fn expected_path(&self) -> PathBuf {
+        self.item_path.with_file_name(&self.expected_name)
+    }
+}
+
+impl Display for NameMismatch {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}: file name does not match its template, expected '{}'", self.item_path.to_string_lossy(), self.expected_name)
+    }
+}
+
+/// Checks the names of the non-directory items in a directory and its selected subdirectories against naming rules, in walk order.
+/// Items marked as ignored are left out, along with everything inside them, and items without a value for a field of their template are skipped.
+pub fn check_names<P: AsRef<Path>>(lookup_ctx: &mut LookupContext, abs_dir_path: P, naming_rules: &NamingRules) -> Result<Vec<NameMismatch>> {
+    let abs_dir_path = normalize(abs_dir_path.as_ref());
+    let media_lib = lookup_ctx.media_lib();
+
+    // Rule: dir path must be a directory.
+    ensure!(media_lib.vfs().is_dir(&abs_dir_path), ErrorKind::NotADirectory(abs_dir_path.clone()));
+
+    let mut mismatches = vec![];
+
+    media_lib.walk_items(&abs_dir_path, |step| {
+        let (item_path, is_dir) = match step {
+            WalkStep::Item { path, is_dir } => (path, is_dir),
+            _ => return Ok(true),
+        };
+
+        if lookup_ctx.is_ignored(item_path)? {
+            return Ok(false);
+        }
+
+        if is_dir {
+            return Ok(true);
+        }
+
+        let item_name = item_path.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+        let opt_expected_name = match naming_rules.template_for(&item_name) {
+            Some(template) => template.render(lookup_ctx, item_path)?,
+            None => None,
+        };
+
+        if let Some(expected_name) = opt_expected_name {
+            if expected_name != item_name {
+                mismatches.push(NameMismatch { item_path: item_path.to_path_buf(), expected_name });
+            }
+        }
+
+        Ok(true)
+    })?;
+
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use lookup::LookupContext;
+    use fixtures::default_setup;
+
+    use super::{NameTemplate, NamingRules, TemplatePart, check_names};
+
+    #[test]
+    fn test_parse() {
+        let literal = |s: &str| TemplatePart::Literal(s.to_string());
+        let field = |s: &str, width: usize, zero_pad: bool| TemplatePart::Field(s.to_string(), width, zero_pad);
+
+        let template = NameTemplate::parse("{track_num:02}. {title}.flac").unwrap();
+        assert_eq!(vec![field("track_num", 2, true), literal(". "), field("title", 0, false), literal(".flac")], template.parts);
+
+        let template = NameTemplate::parse("{{{ credits.composer :3 }}}").unwrap();
+        assert_eq!(vec![literal("{"), field("credits.composer", 3, false), literal("}")], template.parts);
+
+        let invalid_inputs = vec![
+            "{title",
+            "title}",
+            "{}",
+            "{:02}",
+            "{title:x}",
+        ];
+
+        for input in invalid_inputs {
+            assert!(NameTemplate::parse(input).is_err(), "expected error for: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_check_names() {
+        let (temp_media_root, media_lib) = default_setup("test_check_names");
+        let tp = temp_media_root.path();
+
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        let mut f = File::create(disc_fp.join("item.yml")).unwrap();
+        writeln!(f, "- {{track_num: '1', title: A/B}}\n- {{track_num: '12', title: B}}\n- {{title: C}}").unwrap();
+
+        let mut naming_rules = NamingRules::new();
+        naming_rules.rule("TRACK_*.flac", NameTemplate::parse("TRACK_{track_num:02}.flac").unwrap()).unwrap();
+        naming_rules.rule("*.flac", NameTemplate::parse("{title}.flac").unwrap()).unwrap();
+
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let template = NameTemplate::parse("{track_num:3} {title}").unwrap();
+        assert_eq!(Some(String::from("  1 A_B")), template.render(&mut lookup_ctx, disc_fp.join("TRACK_01.flac")).unwrap());
+        assert_eq!(None, template.render(&mut lookup_ctx, disc_fp.join("TRACK_03.flac")).unwrap());
+
+        // Only the first matching template applies, and items without a value for one of its fields are skipped.
+        let mismatches = check_names(&mut lookup_ctx, &disc_fp, &naming_rules).unwrap();
+        assert_eq!(1, mismatches.len());
+        assert_eq!(disc_fp.join("TRACK_02.flac"), mismatches[0].item_path);
+        assert_eq!(disc_fp.join("TRACK_12.flac"), mismatches[0].expected_path());
+
+        assert!(check_names(&mut lookup_ctx, disc_fp.join("TRACK_01.flac"), &naming_rules).is_err());
+    }
+}
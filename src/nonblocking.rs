@@ -0,0 +1,144 @@
+// Async variants of the library operations that can take a long time, for embedding taggu in async hosts such as servers.
+// The work itself still uses blocking file system calls, so it is run on the blocking thread pool of the tokio runtime,
+// which keeps the async worker threads free while large directories are scanned.
+// Every function here must be called from within a tokio runtime.
+// Libraries are cheap to clone, and libraries created with caching enabled share their cache with the copies handed to these tasks.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::task::{self, JoinHandle};
+
+use library::Library;
+use lookup::LookupContext;
+use lookup::options::LookupOptions;
+use lookup::query::{Query, QueryRow, run_query};
+use lookup::resolved::ResolvedBlock;
+use lookup::persist::PersistentCache;
+use metadata::MetaValue;
+use progress::NoProgress;
+use error::*;
+
+/// The result of an operation running on the blocking thread pool.
+/// A task that panics resolves to an error instead of panicking the task that awaits it.
+#[derive(Debug)]
+pub struct Blocking<T> {
+    handle: JoinHandle<Result<T>>,
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.handle).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(Error::from(format!("background task failed: {}", e)))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Runs a blocking operation on the blocking thread pool.
+pub fn spawn<T, F>(f: F) -> Blocking<T>
+where T: Send + 'static,
+      F: FnOnce() -> Result<T> + Send + 'static,
+{
+    Blocking { handle: task::spawn_blocking(f) }
+}
+
+/// Looks up a field for an item, see `LookupContext::lookup`.
+pub fn lookup<P: AsRef<Path>>(media_lib: &Library, abs_item_path: P, options: &LookupOptions) -> Blocking<Option<MetaValue>> {
+    let media_lib = media_lib.clone();
+    let abs_item_path = abs_item_path.as_ref().to_path_buf();
+    let options = options.clone();
+
+    spawn(move || LookupContext::new(&media_lib).lookup(&abs_item_path, &options))
+}
+
+/// Finds the fields of an item whose names match a glob pattern, see `LookupContext::lookup_matching_fields`.
+pub fn search_fields<P: AsRef<Path>, S: AsRef<str>>(media_lib: &Library, abs_item_path: P, field_pattern: S) -> Blocking<BTreeMap<String, MetaValue>> {
+    let media_lib = media_lib.clone();
+    let abs_item_path = abs_item_path.as_ref().to_path_buf();
+    let field_pattern = field_pattern.as_ref().to_string();
+
+    spawn(move || LookupContext::new(&media_lib).lookup_matching_fields(&abs_item_path, &field_pattern))
+}
+
+/// Finds the items in a directory and below it that match a query, sorted and projected as it says, see `query::run_query`.
+pub fn query<P: AsRef<Path>>(media_lib: &Library, abs_dir_path: P, query: &Query) -> Blocking<Vec<QueryRow>> {
+    let media_lib = media_lib.clone();
+    let abs_dir_path = abs_dir_path.as_ref().to_path_buf();
+    let query = query.clone();
+
+    spawn(move || run_query(&mut LookupContext::new(&media_lib), &abs_dir_path, &query))
+}
+
+/// Resolves all fields of an item along with where they came from, see `LookupContext::resolve_block`.
+pub fn resolve_block<P: AsRef<Path>>(media_lib: &Library, abs_item_path: P) -> Blocking<ResolvedBlock> {
+    let media_lib = media_lib.clone();
+    let abs_item_path = abs_item_path.as_ref().to_path_buf();
+
+    spawn(move || LookupContext::new(&media_lib).resolve_block(&abs_item_path))
+}
+
+/// Lists the selected entries of a directory in sort order, see `Library::children_paths`.
+pub fn children_paths<P: AsRef<Path>>(media_lib: &Library, abs_dir_path: P) -> Blocking<Vec<PathBuf>> {
+    let media_lib = media_lib.clone();
+    let abs_dir_path = abs_dir_path.as_ref().to_path_buf();
+
+    spawn(move || media_lib.children_paths(&abs_dir_path))
+}
+
+/// Parses every meta file in the library into a persistent cache, without saving it, see `PersistentCache::build`.
+pub fn build_cache(media_lib: &Library) -> Blocking<PersistentCache> {
+    let media_lib = media_lib.clone();
+
+    spawn(move || PersistentCache::build(&mut LookupContext::new(&media_lib), &mut NoProgress))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::runtime::Builder;
+
+    use metadata::MetaValue;
+    use lookup::options::LookupOptions;
+    use lookup::query::Query;
+    use fixtures::default_setup;
+
+    use super::{lookup, search_fields, children_paths, build_cache, spawn};
+
+    #[test]
+    fn test_nonblocking() {
+        let (temp_media_root, media_lib) = default_setup("test_nonblocking");
+        let tp = temp_media_root.path();
+        let track_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+
+        let runtime = Builder::new_current_thread().build().expect("Unable to create runtime");
+
+        // Tasks are spawned as soon as they are created, so that needs to happen inside the runtime.
+        let _guard = runtime.enter();
+
+        let found = runtime.block_on(lookup(&media_lib, &track_fp, &LookupOptions::new("self_key"))).unwrap();
+        assert_eq!(Some(MetaValue::Str(String::from("self_val"))), found);
+
+        let matches = runtime.block_on(search_fields(&media_lib, &track_fp, "item_*")).unwrap();
+        assert!(matches.contains_key("item_key"));
+
+        let children = runtime.block_on(children_paths(&media_lib, tp.join("ALBUM_01").join("DISC_01"))).unwrap();
+        assert_eq!(3, children.len());
+
+        let query = Query::parse("TRACK_02_item_key ORDER BY TRACK_02_item_key DESC").unwrap();
+        let rows = runtime.block_on(super::query(&media_lib, tp.join("ALBUM_01"), &query)).unwrap();
+        assert_eq!(2, rows.len());
+
+        let cache = runtime.block_on(build_cache(&media_lib)).unwrap();
+        assert!(cache.len() > 0);
+
+        // Panics in the background become errors.
+        let result: ::error::Result<()> = runtime.block_on(spawn(|| panic!("boom")));
+        assert!(result.is_err());
+    }
+}
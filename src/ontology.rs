@@ -0,0 +1,200 @@
+// An ontology arranges the values of a field into a hierarchy of terms, e.g. genres and their parent genres:
+//     genre:
+//       psytrance: trance
+//       trance: electronic
+//       ambient: [electronic, chillout]
+//       electronic: ~
+// A term may have one parent, several, or none at all.
+// Queries consult it so that `genre == electronic` matches items tagged with any narrower genre, and validation flags terms that it does not know.
+// Terms are compared without regard to case.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use yaml_rust::Yaml;
+
+use yaml::read_yaml_file;
+use error::*;
+
+/// The hierarchies of terms for fields, keyed by field name and then by the case folded term.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Ontology {
+    hierarchies: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+impl Ontology {
+    pub fn new() -> Self {
+        Ontology::default()
+    }
+
+    /// Adds a term for a field, along with its direct parents.
+    /// Parents do not need to be added as terms of their own, they are known terms either way.
+    pub fn term<S: AsRef<str>>(&mut self, field_name: S, term: S, parents: &[&str]) -> &mut Self {
+        let hierarchy = self.hierarchies.entry(field_name.as_ref().to_string()).or_insert_with(BTreeMap::new);
+
+        for parent in parents {
+            hierarchy.entry(fold(parent)).or_insert_with(Vec::new);
+        }
+
+        hierarchy.entry(fold(term.as_ref())).or_insert_with(Vec::new).extend(parents.iter().map(|p| fold(p)));
+        self
+    }
+
+    /// Reads an ontology from a mapping of field names to mappings of terms to their parents.
+    /// Cycles of terms are rejected, since a term cannot be narrower than itself.
+    pub fn from_yaml(y: &Yaml) -> Result<Self> {
+        let hsh = match *y {
+            Yaml::Hash(ref hsh) => hsh,
+            Yaml::Null => return Ok(Ontology::new()),
+            _ => bail!("ontology must be a mapping of field names to terms"),
+        };
+
+        let mut ontology = Ontology::new();
+
+        for (field_name_y, terms_y) in hsh {
+            let field_name = field_name_y.as_str().ok_or("ontology field names must be strings")?;
+
+            let terms_hsh = match *terms_y {
+                Yaml::Hash(ref terms_hsh) => terms_hsh,
+                _ => bail!("terms of field '{}' must be a mapping of terms to parents", field_name),
+            };
+
+            for (term_y, parents_y) in terms_hsh {
+                let term = term_y.as_str().ok_or_else(|| format!("terms of field '{}' must be strings", field_name))?;
+
+                let parents: Vec<&str> = match *parents_y {
+                    Yaml::Null => vec![],
+                    Yaml::String(ref parent) => vec![parent.as_str()],
+                    Yaml::Array(ref arr) => arr.iter().map(Yaml::as_str).collect::<Option<_>>()
+                        .ok_or_else(|| format!("parents of term '{}' must be strings", term))?,
+                    _ => bail!("parents of term '{}' must be a string or a sequence of strings", term),
+                };
+
+                ontology.term(field_name, term, &parents);
+            }
+
+            if let Some(term) = ontology.find_cycle(field_name) {
+                bail!("term '{}' of field '{}' is its own ancestor", term, field_name);
+            }
+        }
+
+        Ok(ontology)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hierarchies.is_empty()
+    }
+
+    /// Returns the names of the fields that have a hierarchy, in sorted order.
+    pub fn fields(&self) -> Vec<&str> {
+        self.hierarchies.keys().map(String::as_str).collect()
+    }
+
+    pub fn has_field<S: AsRef<str>>(&self, field_name: S) -> bool {
+        self.hierarchies.contains_key(field_name.as_ref())
+    }
+
+    /// Returns the case folded terms of a field, in sorted order.
+    pub fn terms<S: AsRef<str>>(&self, field_name: S) -> Vec<&str> {
+        self.hierarchies.get(field_name.as_ref()).map_or_else(Vec::new, |h| h.keys().map(String::as_str).collect())
+    }
+
+    pub fn is_known<S: AsRef<str>>(&self, field_name: S, term: S) -> bool {
+        self.hierarchies.get(field_name.as_ref()).map_or(false, |h| h.contains_key(&fold(term.as_ref())))
+    }
+
+    /// Returns the case folded ancestors of a term, nearest first, without repeats.
+    pub fn ancestors<S: AsRef<str>>(&self, field_name: S, term: S) -> Vec<String> {
+        let mut ancestors: Vec<String> = vec![];
+
+        if let Some(hierarchy) = self.hierarchies.get(field_name.as_ref()) {
+            let mut frontier = vec![fold(term.as_ref())];
+
+            while !frontier.is_empty() {
+                let mut next_frontier = vec![];
+
+                for t in frontier {
+                    for parent in hierarchy.get(&t).into_iter().flat_map(|ps| ps.iter()) {
+                        if !ancestors.contains(parent) {
+                            ancestors.push(parent.clone());
+                            next_frontier.push(parent.clone());
+                        }
+                    }
+                }
+
+                frontier = next_frontier;
+            }
+        }
+
+        ancestors
+    }
+
+    /// Returns true if a term is the same as another term or narrower than it, e.g. `psytrance` is an `electronic` genre.
+    pub fn is_a<S: AsRef<str>>(&self, field_name: S, term: S, ancestor: S) -> bool {
+        let ancestor = fold(ancestor.as_ref());
+
+        fold(term.as_ref()) == ancestor || self.ancestors(field_name.as_ref(), term.as_ref()).contains(&ancestor)
+    }
+
+    /// Finds a term of a field that is its own ancestor, if there is one.
+    fn find_cycle(&self, field_name: &str) -> Option<String> {
+        self.terms(field_name).into_iter()
+            .find(|&t| self.ancestors(field_name, t).iter().any(|a| a == t))
+            .map(String::from)
+    }
+}
+
+/// Folds the case of a term, so that it can be compared with the terms of an ontology.
+pub fn fold(term: &str) -> String {
+    term.trim().to_lowercase()
+}
+
+pub fn read_ontology_file<P: AsRef<Path>>(ontology_fp: P) -> Result<Ontology> {
+    let yaml_data = read_yaml_file(ontology_fp)?;
+
+    Ontology::from_yaml(&yaml_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+
+    use super::Ontology;
+
+    #[test]
+    fn test_from_yaml() {
+        let text = "genre:\n  psytrance: trance\n  Trance: Electronic\n  ambient: [electronic, chillout]\n  electronic: ~\n";
+        let y = &YamlLoader::load_from_str(text).unwrap()[0];
+
+        let ontology = Ontology::from_yaml(y).unwrap();
+
+        assert_eq!(vec!["genre"], ontology.fields());
+        assert_eq!(vec!["ambient", "chillout", "electronic", "psytrance", "trance"], ontology.terms("genre"));
+        assert_eq!(vec!["trance", "electronic"], ontology.ancestors("genre", "Psytrance"));
+
+        // Matching is transitive and ignores case, but only goes up the hierarchy.
+        assert!(ontology.is_a("genre", "Psytrance", "electronic"));
+        assert!(ontology.is_a("genre", "ambient", "CHILLOUT"));
+        assert!(ontology.is_a("genre", "unlisted", "Unlisted"));
+        assert!(!ontology.is_a("genre", "electronic", "psytrance"));
+        assert!(!ontology.is_a("mood", "psytrance", "electronic"));
+
+        assert!(ontology.is_known("genre", "Chillout"));
+        assert!(!ontology.is_known("genre", "polka"));
+        assert!(!ontology.is_known("mood", "trance"));
+
+        let invalid_inputs = vec![
+            "- genre",
+            "genre: [rock]",
+            "genre: {rock: {a: b}}",
+            "genre: {rock: [1, {a: b}]}",
+            "genre: {a: b, b: c, c: a}",
+            "genre: {a: a}",
+        ];
+
+        for input in invalid_inputs {
+            let y = &YamlLoader::load_from_str(input).unwrap()[0];
+            assert!(Ontology::from_yaml(y).is_err(), "expected error for: {}", input);
+        }
+    }
+}
@@ -0,0 +1,394 @@
+// Mutating operations describe their changes as a write plan, which can be shown or diffed before anything touches the disk.
+
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use tags::{self, TagMap};
+use library::archive::split_archive_path;
+use yaml::{read_yaml_file, write_yaml_file, yaml_as_text};
+use roundtrip::patch_yaml_text;
+use helpers::write_file_atomic;
+use error::*;
+
+/// A single pending write.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteOp {
+    /// Writes a meta file, creating it if it does not exist.
+    Yaml(PathBuf, Yaml),
+    /// Writes a meta file as exact text, for edits that keep the existing formatting and comments.
+    Text(PathBuf, String),
+    /// Replaces all of the embedded tags of an item.
+    Tags(PathBuf, TagMap),
+    /// Removes a meta file, e.g. after its blocks were moved to other meta files.
+    Remove(PathBuf),
+}
+
+impl WriteOp {
+    pub fn path(&self) -> &Path {
+        match *self {
+            WriteOp::Yaml(ref p, _) => p,
+            WriteOp::Text(ref p, _) => p,
+            WriteOp::Tags(ref p, _) => p,
+            WriteOp::Remove(ref p) => p,
+        }
+    }
+
+    /// Renders the current contents on disk, using empty text for files that do not exist yet.
+    fn old_text(&self) -> Result<String> {
+        match *self {
+            WriteOp::Yaml(ref p, _) | WriteOp::Text(ref p, _) | WriteOp::Remove(ref p) => {
+                if !p.is_file() {
+                    return Ok(String::new());
+                }
+
+                let mut buffer = String::new();
+                File::open(p)?.read_to_string(&mut buffer)?;
+                Ok(buffer)
+            },
+            WriteOp::Tags(ref p, _) => Ok(tags_as_text(&tags::read_tags(p)?)),
+        }
+    }
+
+    /// Renders the contents that will be written.
+    /// Existing meta files are edited in place, so that comments and formatting are kept for the parts that do not change.
+    fn new_text(&self) -> Result<String> {
+        match *self {
+            WriteOp::Yaml(ref p, ref y) => {
+                if p.is_file() { patch_yaml_text(&self.old_text()?, y) } else { yaml_as_text(y) }
+            },
+            WriteOp::Text(_, ref text) => Ok(text.clone()),
+            WriteOp::Tags(_, ref tags) => Ok(tags_as_text(tags)),
+            WriteOp::Remove(_) => Ok(String::new()),
+        }
+    }
+
+    fn execute(&self) -> Result<()> {
+        // Rule: archives are read-only.
+        if let Some((archive_fp, inner_path)) = split_archive_path(self.path()) {
+            ensure!(inner_path.as_os_str().is_empty(), ErrorKind::ReadOnlyArchive(archive_fp));
+        }
+
+        // A meta file in an overlay can be the first file in its mirrored directory.
+        match *self {
+            WriteOp::Yaml(ref p, _) | WriteOp::Text(ref p, _) => {
+                if let Some(parent_dir_path) = p.parent() {
+                    fs::create_dir_all(parent_dir_path)?;
+                }
+            },
+            _ => {},
+        }
+
+        match *self {
+            WriteOp::Yaml(ref p, ref y) => write_yaml_file(p, y),
+            WriteOp::Text(ref p, ref text) => write_file_atomic(p, text.as_bytes()),
+            WriteOp::Tags(ref p, ref tags) => tags::write_tags(p, tags),
+            WriteOp::Remove(ref p) => Ok(fs::remove_file(p)?),
+        }
+    }
+}
+
+impl Display for WriteOp {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            WriteOp::Yaml(ref p, _) | WriteOp::Text(ref p, _) => write!(f, "write meta file: {}", p.to_string_lossy()),
+            WriteOp::Tags(ref p, _) => write!(f, "write tags: {}", p.to_string_lossy()),
+            WriteOp::Remove(ref p) => write!(f, "remove meta file: {}", p.to_string_lossy()),
+        }
+    }
+}
+
+fn tags_as_text(tags: &TagMap) -> String {
+    let mut text = String::new();
+
+    for (tag_name, vals) in tags {
+        for val in vals {
+            text.push_str(&format!("{}={}\n", tag_name, val));
+        }
+    }
+
+    text
+}
+
+/// Lists the lines that were removed (prefixed with '-') and added (prefixed with '+'), in order.
+fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    // Lengths of the longest common subsequences of the suffixes of both sides.
+    let mut lcs = vec![vec![0usize; new_lines.len() + 1]; old_lines.len() + 1];
+
+    for i in (0..old_lines.len()).rev() {
+        for j in (0..new_lines.len()).rev() {
+            lcs[i][j] = if old_lines[i] == new_lines[j] {
+                lcs[i + 1][j + 1] + 1
+            }
+            else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = vec![];
+    let (mut i, mut j) = (0, 0);
+
+    while i < old_lines.len() || j < new_lines.len() {
+        if i < old_lines.len() && j < new_lines.len() && old_lines[i] == new_lines[j] {
+            i += 1;
+            j += 1;
+        }
+        else if i < old_lines.len() && (j == new_lines.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(format!("-{}", old_lines[i]));
+            i += 1;
+        }
+        else {
+            diff.push(format!("+{}", new_lines[j]));
+            j += 1;
+        }
+    }
+
+    diff
+}
+
+/// An ordered set of pending writes, with at most one write per path.
+/// Operations that read files they may have already planned to write should read through the plan, so that they see their own changes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WritePlan {
+    ops: Vec<WriteOp>,
+}
+
+impl WritePlan {
+    pub fn new() -> Self {
+        WritePlan { ops: vec![] }
+    }
+
+    pub fn ops(&self) -> &[WriteOp] {
+        &self.ops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn paths(&self) -> Vec<&Path> {
+        self.ops.iter().map(WriteOp::path).collect()
+    }
+
+    /// Adds a write, replacing any earlier write to the same path while keeping its position.
+    pub fn push(&mut self, op: WriteOp) -> &mut Self {
+        match self.ops.iter().position(|o| o.path() == op.path()) {
+            Some(i) => { self.ops[i] = op; },
+            None => { self.ops.push(op); },
+        }
+
+        self
+    }
+
+    pub fn write_yaml<P: Into<PathBuf>>(&mut self, yaml_fp: P, y: Yaml) -> &mut Self {
+        self.push(WriteOp::Yaml(yaml_fp.into(), y))
+    }
+
+    pub fn write_text<P: Into<PathBuf>, S: Into<String>>(&mut self, path: P, text: S) -> &mut Self {
+        self.push(WriteOp::Text(path.into(), text.into()))
+    }
+
+    pub fn write_tags<P: Into<PathBuf>>(&mut self, path: P, tags: TagMap) -> &mut Self {
+        self.push(WriteOp::Tags(path.into(), tags))
+    }
+
+    pub fn remove<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.push(WriteOp::Remove(path.into()))
+    }
+
+    /// Returns true if the file exists on disk and is not going to be removed, or is going to be written.
+    pub fn file_exists<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = path.as_ref();
+
+        match self.ops.iter().find(|o| o.path() == path) {
+            Some(&WriteOp::Remove(_)) => false,
+            Some(_) => true,
+            None => path.is_file(),
+        }
+    }
+
+    /// Reads a YAML file, as it will be after this plan is executed.
+    pub fn read_yaml<P: AsRef<Path>>(&self, yaml_fp: P) -> Result<Yaml> {
+        let yaml_fp = yaml_fp.as_ref();
+
+        for op in &self.ops {
+            match *op {
+                WriteOp::Yaml(ref p, ref y) if p == yaml_fp => return Ok(y.clone()),
+                WriteOp::Text(ref p, ref text) if p == yaml_fp => {
+                    return YamlLoader::load_from_str(text)?.into_iter().next().ok_or_else(|| ErrorKind::EmptyMetaFile(p.clone()).into());
+                },
+                WriteOp::Remove(ref p) if p == yaml_fp => bail!(ErrorKind::DoesNotExist(p.clone())),
+                _ => {},
+            }
+        }
+
+        read_yaml_file(yaml_fp)
+    }
+
+    /// Reads a meta file as text, as it will be after this plan is executed.
+    /// Files that are not changed by the plan are read as they are, so that their comments and formatting can be carried over, e.g. to a new path.
+    pub fn read_text<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        let path = path.as_ref();
+
+        match self.ops.iter().find(|o| o.path() == path) {
+            Some(&WriteOp::Remove(ref p)) => bail!(ErrorKind::DoesNotExist(p.clone())),
+            Some(op) => op.new_text(),
+            None => {
+                let mut buffer = String::new();
+                File::open(path)?.read_to_string(&mut buffer)?;
+                Ok(buffer)
+            },
+        }
+    }
+
+    /// Reads the embedded tags of an item, as they will be after this plan is executed.
+    pub fn read_tags<P: AsRef<Path>>(&self, path: P) -> Result<TagMap> {
+        let path = path.as_ref();
+
+        for op in &self.ops {
+            if let WriteOp::Tags(ref p, ref tags) = *op {
+                if p == path {
+                    return Ok(tags.clone());
+                }
+            }
+        }
+
+        tags::read_tags(path)
+    }
+
+    /// Lists the writes that would change a file, each along with its removed and added lines.
+    pub fn changes(&self) -> Result<Vec<(&WriteOp, Vec<String>)>> {
+        let mut changes = vec![];
+
+        for op in &self.ops {
+            let lines = diff_lines(&op.old_text()?, &op.new_text()?);
+
+            if !lines.is_empty() {
+                changes.push((op, lines));
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Describes the changes that executing this plan would make to each file, compared to what is on disk now.
+    /// Writes that would not change a file are left out.
+    pub fn diff(&self) -> Result<String> {
+        let mut text = String::new();
+
+        for (op, lines) in self.changes()? {
+            text.push_str(&format!("{}\n", op));
+
+            for line in lines {
+                text.push_str(&format!("    {}\n", line));
+            }
+        }
+
+        Ok(text)
+    }
+
+    /// Performs every write, in order.
+    /// Returns the paths that were written.
+    pub fn execute(self) -> Result<Vec<PathBuf>> {
+        let mut written = vec![];
+
+        for op in self.ops {
+            op.execute().chain_err(|| format!("unable to {}", op))?;
+            written.push(op.path().to_path_buf());
+        }
+
+        Ok(written)
+    }
+}
+
+impl Display for WritePlan {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        for op in &self.ops {
+            writeln!(f, "{}", op)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use tempdir::TempDir;
+    use yaml_rust::Yaml;
+
+    use super::{WritePlan, diff_lines};
+
+    #[test]
+    fn test_diff_lines() {
+        assert!(diff_lines("a\nb\n", "a\nb\n").is_empty());
+        assert_eq!(vec!["+a", "+b"], diff_lines("", "a\nb"));
+        assert_eq!(vec!["-b", "+c", "+d"], diff_lines("a\nb\ne", "a\nc\nd\ne"));
+    }
+
+    #[test]
+    fn test_write_plan() {
+        let temp = TempDir::new("test_write_plan").unwrap();
+        let tp = temp.path();
+
+        let mut f = File::create(tp.join("a.yml")).unwrap();
+        writeln!(f, "title: Old").unwrap();
+
+        let mut plan = WritePlan::new();
+        assert!(plan.is_empty());
+
+        plan
+            .write_yaml(tp.join("a.yml"), Yaml::String(String::from("first")))
+            .write_yaml(tp.join("b.yml"), Yaml::String(String::from("new")))
+            .write_yaml(tp.join("a.yml"), Yaml::String(String::from("second")));
+
+        // Later writes to the same path replace earlier ones.
+        assert_eq!(vec![tp.join("a.yml"), tp.join("b.yml")], plan.paths());
+        assert_eq!(Yaml::String(String::from("second")), plan.read_yaml(tp.join("a.yml")).unwrap());
+        assert!(plan.file_exists(tp.join("b.yml")));
+        assert!(!tp.join("b.yml").exists());
+
+        let diff = plan.diff().unwrap();
+        assert!(diff.contains("    -title: Old\n    +---\n    +second\n"));
+        assert!(diff.contains("    +---\n    +new\n"));
+        assert_eq!(2, plan.changes().unwrap().len());
+
+        assert_eq!(format!("write meta file: {}\nwrite meta file: {}\n", tp.join("a.yml").to_string_lossy(), tp.join("b.yml").to_string_lossy()), format!("{}", plan));
+
+        let written = plan.execute().expect("Unable to execute plan");
+        assert_eq!(vec![tp.join("a.yml"), tp.join("b.yml")], written);
+        assert!(tp.join("b.yml").is_file());
+
+        // Nothing is left to change.
+        let mut plan = WritePlan::new();
+        plan.write_yaml(tp.join("b.yml"), Yaml::String(String::from("new")));
+        assert_eq!("", plan.diff().unwrap());
+
+        // Archives are read-only.
+        File::create(tp.join("ALBUM.zip")).unwrap();
+        let mut plan = WritePlan::new();
+        plan.write_text(tp.join("ALBUM.zip").join("item.yml"), "- title: New\n");
+        assert!(plan.execute().is_err());
+
+        // Removed files no longer exist as far as the plan is concerned.
+        let mut plan = WritePlan::new();
+        plan.remove(tp.join("a.yml"));
+        assert!(!plan.file_exists(tp.join("a.yml")));
+        assert!(plan.read_yaml(tp.join("a.yml")).is_err());
+        assert!(plan.read_text(tp.join("a.yml")).is_err());
+        assert_eq!("---\nnew\n", plan.read_text(tp.join("b.yml")).unwrap());
+        assert!(plan.diff().unwrap().contains("remove meta file: "));
+
+        plan.execute().expect("Unable to execute plan");
+        assert!(!tp.join("a.yml").exists());
+    }
+}
@@ -0,0 +1,116 @@
+// This module renders the results of queries as playlists, so that saved queries can be used as smart playlists.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use lookup::query::QueryRow;
+use metadata::MetaValue;
+use error::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    /// An extended M3U playlist.
+    M3u,
+    /// A plain list of paths, one per line.
+    Paths,
+}
+
+impl FromStr for PlaylistFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "m3u" => Ok(PlaylistFormat::M3u),
+            "paths" => Ok(PlaylistFormat::Paths),
+            _ => bail!("unknown playlist format: '{}', expected m3u or paths", s),
+        }
+    }
+}
+
+/// Renders a value as a single line for an M3U title, leaving out values that are not strings.
+fn title_text(mv: &MetaValue) -> Option<String> {
+    match *mv {
+        MetaValue::Str(ref s) => Some(s.replace('\n', " ")),
+        MetaValue::Seq(ref mvs) => {
+            let parts: Vec<String> = mvs.iter().filter_map(title_text).collect();
+            if parts.is_empty() { None } else { Some(parts.join(", ")) }
+        },
+        _ => None,
+    }
+}
+
+/// Renders the items of a query as a playlist, in query order.
+/// If a base directory is given, paths inside it are written relative to it, e.g. to the directory holding the playlist file.
+/// In M3U playlists, the fields projected by the query become the title of each entry, joined with " - ".
+pub fn render_playlist(rows: &[QueryRow], format: PlaylistFormat, opt_base_dir: Option<&Path>) -> String {
+    let mut text = String::new();
+
+    if format == PlaylistFormat::M3u {
+        text.push_str("#EXTM3U\n");
+    }
+
+    for row in rows {
+        let item_path = match opt_base_dir.and_then(|base_dir| row.item_path.strip_prefix(base_dir).ok()) {
+            Some(rel_path) => rel_path,
+            None => row.item_path.as_path(),
+        };
+
+        if format == PlaylistFormat::M3u {
+            let titles: Vec<String> = row.fields.iter()
+                .filter_map(|&(_, ref opt_mv)| opt_mv.as_ref().and_then(title_text))
+                .collect();
+
+            if !titles.is_empty() {
+                text.push_str(&format!("#EXTINF:-1,{}\n", titles.join(" - ")));
+            }
+        }
+
+        text.push_str(&item_path.to_string_lossy());
+        text.push('\n');
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use lookup::query::QueryRow;
+    use metadata::MetaValue;
+
+    use super::{PlaylistFormat, render_playlist};
+
+    #[test]
+    fn test_render_playlist() {
+        assert_eq!(PlaylistFormat::Paths, "paths".parse().unwrap());
+        assert!("pls".parse::<PlaylistFormat>().is_err());
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let rows = vec![
+            QueryRow {
+                item_path: PathBuf::from("/music/ALBUM/TRACK_01.flac"),
+                fields: vec![
+                    (String::from("artist"), Some(MetaValue::Seq(vec![str_val("A"), str_val("B")]))),
+                    (String::from("title"), Some(str_val("One"))),
+                ],
+            },
+            QueryRow {
+                item_path: PathBuf::from("/elsewhere/TRACK_02.flac"),
+                fields: vec![
+                    (String::from("artist"), None),
+                    (String::from("title"), None),
+                ],
+            },
+        ];
+
+        let expected = "#EXTM3U\n#EXTINF:-1,A, B - One\nALBUM/TRACK_01.flac\n/elsewhere/TRACK_02.flac\n";
+        assert_eq!(expected, render_playlist(&rows, PlaylistFormat::M3u, Some(Path::new("/music"))));
+
+        let expected = "/music/ALBUM/TRACK_01.flac\n/elsewhere/TRACK_02.flac\n";
+        assert_eq!(expected, render_playlist(&rows, PlaylistFormat::Paths, None));
+
+        assert_eq!("#EXTM3U\n", render_playlist(&[], PlaylistFormat::M3u, None));
+    }
+}
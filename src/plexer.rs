@@ -1,19 +1,47 @@
 // This module provides an interface to "match up" media items with metadata blocks.
 
+use std::borrow::Cow;
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::ptr;
 use std::path::{Path, PathBuf};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
-use library::sort_order::SortOrder;
+use library::sort_order::{SortOrder, GroupOrder};
 use library::selection::Selection;
 use metadata::{
     MetaBlock,
     MetaBlockSeq,
     MetaBlockMap,
     Metadata,
+    MetaKey,
+    MetaValue,
 };
-use helpers::{is_valid_item_name, fuzzy_name_match};
+use metadata::keys::{MATCH_KEY, ITEMS_KEY, DEFAULT_KEY, EXPECT_NAME_KEY, EXPECT_HASH_KEY};
+use helpers::{is_valid_item_name, fuzzy_name_match, content_hash};
+use vfs::{Vfs, StdFs};
 use error::*;
 
+/// Starts a key in a mapping meta file that refers to an item by its (one-based) position in sort order, e.g. `#3`.
+const INDEX_KEY_PREFIX: char = '#';
+
+/// Parses a positional key into a zero-based index.
+fn parse_index_key(key: &str) -> Option<usize> {
+    if !key.starts_with(INDEX_KEY_PREFIX) {
+        return None;
+    }
+
+    let digits = &key[INDEX_KEY_PREFIX.len_utf8()..];
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    match digits.parse::<usize>() {
+        Ok(n) if n >= 1 => Some(n - 1),
+        _ => None,
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum PlexTarget {
     WorkingDir,
@@ -49,13 +77,214 @@ pub fn multiplex<'a, P: AsRef<Path>>(
     metadata: &'a Metadata,
     working_dir_path: P,
     selection: &Selection,
-    sort_order: SortOrder,
+    sort_order: &SortOrder,
+    group_order: GroupOrder,
     use_fuzzy_match: bool,
     ) -> Result<Vec<PlexRecord<'a>>>
 {
-    let item_file_names: Vec<_> = metadata.source_item_names(working_dir_path, selection, sort_order)?;
+    let working_dir_path = working_dir_path.as_ref();
+    let item_file_names: Vec<_> = metadata.source_item_names(working_dir_path, selection, sort_order, group_order)?;
+
+    let plex_records = plex(metadata, &item_file_names, use_fuzzy_match);
+    verify_expectations(&plex_records, working_dir_path, true)?;
+
+    Ok(plex_records)
+}
+
+/// Like `multiplex`, but with the names of the items in the working directory already listed, in sort order.
+/// This is for items that are not listed straight from disk, e.g. the entries of an archive, so expected hashes are not checked.
+pub fn multiplex_with_names<'a, S: AsRef<str>>(metadata: &'a Metadata, working_dir_path: &Path, item_file_names: &[S], use_fuzzy_match: bool) -> Result<Vec<PlexRecord<'a>>> {
+    let plex_records = plex(metadata, item_file_names, use_fuzzy_match);
+    verify_expectations(&plex_records, working_dir_path, false)?;
+
+    Ok(plex_records)
+}
+
+/// Checks that the items that blocks were matched with are the ones the blocks expect, by name or by contents.
+/// This catches metadata that would otherwise describe the wrong item, e.g. after an item was added to a directory that is described by a sequence.
+fn verify_expectations(plex_records: &[PlexRecord], working_dir_path: &Path, check_hashes: bool) -> Result<()> {
+    for &(ref plex_target, mb) in plex_records {
+        let item_file_name = match *plex_target {
+            PlexTarget::SubItem(ref s) => s,
+            PlexTarget::WorkingDir => continue,
+        };
+
+        let item_path = working_dir_path.join(item_file_name);
+
+        match mb.get(EXPECT_NAME_KEY) {
+            Some(&MetaValue::Str(ref expected)) => {
+                ensure!(expected == item_file_name, ErrorKind::UnexpectedItemName(item_path.clone(), expected.clone()));
+            },
+            Some(_) => { warn!("invalid value for '{}', expected a string", EXPECT_NAME_KEY); },
+            None => {},
+        }
+
+        match mb.get(EXPECT_HASH_KEY) {
+            Some(&MetaValue::Str(ref expected)) if check_hashes => {
+                let found = content_hash(&item_path).chain_err(|| format!("unable to hash item: '{}'", item_path.to_string_lossy()))?;
+                ensure!(found.eq_ignore_ascii_case(expected), ErrorKind::UnexpectedItemHash(item_path.clone(), expected.clone(), found));
+            },
+            Some(&MetaValue::Str(_)) => { warn!("unable to check '{}' for item: '{}'", EXPECT_HASH_KEY, item_path.to_string_lossy()); },
+            Some(_) => { warn!("invalid value for '{}', expected a string", EXPECT_HASH_KEY); },
+            None => {},
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts a mapping found under a reserved key into a meta block, skipping fields that are not named by strings.
+fn map_as_block(map: &BTreeMap<MetaKey, MetaValue>, reserved_key: &str) -> MetaBlock {
+    let mut mb = MetaBlock::new();
+
+    for (mk, mv) in map {
+        match *mk {
+            MetaKey::Str(ref s) => { mb.insert(s.clone(), mv.clone()); },
+            MetaKey::Nil => { warn!("invalid field name in '{}', expected a string", reserved_key); },
+        }
+    }
+
+    mb
+}
 
-    Ok(plex(metadata, &item_file_names, use_fuzzy_match))
+/// Returns true if a block in a sequence holds the default fields, instead of describing an item.
+fn is_default_block(meta_block: &MetaBlock) -> bool {
+    meta_block.contains_key(DEFAULT_KEY)
+}
+
+/// Finds the default fields for the item blocks in metadata, if any.
+pub fn default_block(metadata: &Metadata) -> Option<Cow<MetaBlock>> {
+    match *metadata {
+        Metadata::Contains(_) => None,
+        Metadata::SiblingsMap(ref mb_map) => mb_map.get(&MetaKey::from(DEFAULT_KEY)).map(Cow::Borrowed),
+        Metadata::SiblingsSeq(ref mb_seq) => {
+            let mut default_blocks = mb_seq.iter().filter(|mb| is_default_block(mb));
+
+            let mb = default_blocks.next()?;
+
+            if default_blocks.next().is_some() {
+                warn!("multiple '{}' blocks found, only the first is used", DEFAULT_KEY);
+            }
+
+            if mb.len() > 1 {
+                warn!("fields next to '{}' are ignored", DEFAULT_KEY);
+            }
+
+            match mb.get(DEFAULT_KEY) {
+                Some(&MetaValue::Map(ref map)) => Some(Cow::Owned(map_as_block(map, DEFAULT_KEY))),
+                _ => {
+                    warn!("invalid value for '{}', expected a mapping", DEFAULT_KEY);
+                    None
+                },
+            }
+        },
+    }
+}
+
+/// Fills in the fields that an item block does not set itself from the default fields.
+/// Reserved fields that only make sense for a single block (e.g. `__match` or `__expect_name`) are never filled in.
+pub fn apply_defaults<'a>(meta_block: &'a MetaBlock, opt_default_block: Option<&MetaBlock>) -> Cow<'a, MetaBlock> {
+    let default_block = match opt_default_block {
+        Some(default_block) if !default_block.is_empty() => default_block,
+        _ => return Cow::Borrowed(meta_block),
+    };
+
+    let mut merged = meta_block.clone();
+
+    for (field_name, mv) in default_block {
+        if field_name == MATCH_KEY || field_name == ITEMS_KEY || field_name == EXPECT_NAME_KEY || field_name == EXPECT_HASH_KEY {
+            continue;
+        }
+
+        merged.entry(field_name.clone()).or_insert_with(|| mv.clone());
+    }
+
+    Cow::Owned(merged)
+}
+
+/// Extracts the nested sequence of blocks for the children of an item, if its block has one.
+fn nested_metadata(meta_block: &MetaBlock) -> Option<Metadata> {
+    let mvs = match meta_block.get(ITEMS_KEY) {
+        Some(&MetaValue::Seq(ref mvs)) => mvs,
+        Some(_) => {
+            warn!("invalid value for '{}', expected a sequence", ITEMS_KEY);
+            return None;
+        },
+        None => return None,
+    };
+
+    let mut mb_seq: MetaBlockSeq = vec![];
+
+    for mv in mvs {
+        let mb = match *mv {
+            MetaValue::Map(ref map) => map_as_block(map, ITEMS_KEY),
+            // Keep an empty block, so that the following blocks still line up with their items.
+            _ => {
+                warn!("invalid block in '{}', expected a mapping", ITEMS_KEY);
+                MetaBlock::new()
+            },
+        };
+
+        mb_seq.push(mb);
+    }
+
+    Some(Metadata::SiblingsSeq(mb_seq))
+}
+
+/// Like `multiplex`, but also descends into the nested `__items` sequences of directory items, in one pass.
+/// Default fields are filled in for each level from that level's own `__default` block.
+/// Blocks from the meta file itself are borrowed if they have no defaults to fill in, while nested blocks are owned.
+/// Each item is followed by the items nested inside it, in order.
+/// As with `multiplex`, items are read from the native file system; a library on any other goes through `multiplex_nested_with`.
+pub fn multiplex_nested<'a, P: AsRef<Path>>(
+    metadata: &'a Metadata,
+    working_dir_path: P,
+    selection: &Selection,
+    sort_order: &SortOrder,
+    group_order: GroupOrder,
+    use_fuzzy_match: bool,
+    ) -> Result<Vec<(PathBuf, Cow<'a, MetaBlock>)>>
+{
+    multiplex_nested_with(
+        metadata,
+        working_dir_path.as_ref(),
+        &|md, dir_path| multiplex(md, dir_path, selection, sort_order, group_order, use_fuzzy_match),
+        &|p| StdFs.is_dir(p),
+    )
+}
+
+/// Like `multiplex_nested`, but with how each level is plexed, and how directory items are recognized, supplied by the caller.
+pub fn multiplex_nested_with<'a, F, D>(
+    metadata: &'a Metadata,
+    working_dir_path: &Path,
+    plex_level: &F,
+    is_dir: &D,
+    ) -> Result<Vec<(PathBuf, Cow<'a, MetaBlock>)>>
+where F: for<'m> Fn(&'m Metadata, &Path) -> Result<Vec<PlexRecord<'m>>>,
+      D: Fn(&Path) -> bool,
+{
+    let mut results = vec![];
+    let opt_default_block = default_block(metadata);
+
+    for (plex_target, mb) in plex_level(metadata, working_dir_path)? {
+        let item_path = plex_target.resolve(working_dir_path);
+        let opt_nested = nested_metadata(mb);
+
+        results.push((item_path.clone(), apply_defaults(mb, opt_default_block.as_ref().map(|d| &**d))));
+
+        if let Some(nested) = opt_nested {
+            if !is_dir(&item_path) {
+                warn!("nested items found for an item that is not a directory: '{}'", item_path.to_string_lossy());
+                continue;
+            }
+
+            for (nested_item_path, nested_mb) in multiplex_nested_with(&nested, &item_path, plex_level, is_dir)? {
+                results.push((nested_item_path, Cow::Owned(nested_mb.into_owned())));
+            }
+        }
+    }
+
+    Ok(results)
 }
 
 fn plex<'a, I, J>(metadata: &Metadata, item_file_names: I, use_fuzzy_match: bool) -> Vec<PlexRecord>
@@ -64,7 +293,7 @@ where I: IntoIterator<Item = &'a J>,
 {
     match *metadata {
         Metadata::Contains(ref mb) => plex_singular(&mb),
-        Metadata::SiblingsSeq(ref mb_seq) => plex_multiple_seq(mb_seq, item_file_names),
+        Metadata::SiblingsSeq(ref mb_seq) => plex_multiple_seq(mb_seq, item_file_names, use_fuzzy_match),
         Metadata::SiblingsMap(ref mb_map) => plex_multiple_map(mb_map, item_file_names, use_fuzzy_match),
     }
 }
@@ -73,7 +302,7 @@ fn plex_singular(meta_block: &MetaBlock) -> Vec<PlexRecord> {
     vec![(PlexTarget::WorkingDir, meta_block)]
 }
 
-fn plex_multiple_seq<'a, I, J>(meta_block_seq: &MetaBlockSeq, item_file_names: I) -> Vec<PlexRecord>
+fn plex_multiple_seq<'a, I, J>(meta_block_seq: &MetaBlockSeq, item_file_names: I, use_fuzzy_match: bool) -> Vec<PlexRecord>
 where I: IntoIterator<Item = &'a J>,
       J: AsRef<str> + 'a
 {
@@ -82,19 +311,88 @@ where I: IntoIterator<Item = &'a J>,
 
     // Metadata is a sequence of meta blocks.
     // Each should correspond one-to-one with a valid item in the working dir.
-    let item_file_names: Vec<_> = item_file_names.into_iter().collect();
+    let item_file_names: Vec<&str> = item_file_names.into_iter().map(AsRef::as_ref).collect();
+
+    // Blocks that are pinned to an item name claim their items first.
+    let mut claimed_item_file_names: HashSet<&str> = HashSet::new();
+    let mut positional_blocks: Vec<&MetaBlock> = vec![];
+
+    for mb in meta_block_seq {
+        // The default block does not describe an item, so it does not take up a position.
+        if is_default_block(mb) {
+            continue;
+        }
+
+        let search_name_string = match mb.get(MATCH_KEY) {
+            Some(&MetaValue::Str(ref s)) => s,
+            Some(_) => {
+                warn!("invalid value for '{}', expected a string", MATCH_KEY);
+                continue;
+            },
+            None => {
+                positional_blocks.push(mb);
+                continue;
+            },
+        };
+
+        let remaining_item_file_names: Vec<&str> = {
+            item_file_names
+                .iter()
+                .cloned()
+                .filter(|n| !claimed_item_file_names.contains(n))
+                .collect()
+        };
+
+        let needle = if remaining_item_file_names.contains(&search_name_string.as_str()) {
+            search_name_string.to_string()
+        } else if use_fuzzy_match {
+            match fuzzy_name_match(search_name_string.as_str(), &remaining_item_file_names) {
+                Ok(matched_name) => matched_name.to_string(),
+                Err(_) => {
+                    warn!("unable to match item name: '{}'", search_name_string);
+                    continue;
+                },
+            }
+        } else {
+            warn!("unexpected item name: '{}'", search_name_string);
+            continue;
+        };
+
+        if let Some(found_name) = item_file_names.iter().find(|n| **n == needle.as_str()) {
+            claimed_item_file_names.insert(found_name);
+        }
+
+        results.push((PlexTarget::SubItem(needle), mb));
+    }
+
+    // The remaining blocks are associated with the unclaimed items in order.
+    let positional_item_file_names: Vec<&str> = {
+        item_file_names
+            .iter()
+            .cloned()
+            .filter(|n| !claimed_item_file_names.contains(n))
+            .collect()
+    };
 
-    if meta_block_seq.len() > item_file_names.len() {
-        warn!("excess metadata definitions found: {}", meta_block_seq.len() - item_file_names.len());
+    if positional_blocks.len() > positional_item_file_names.len() {
+        warn!("excess metadata definitions found: {}", positional_blocks.len() - positional_item_file_names.len());
     }
-    else if meta_block_seq.len() < item_file_names.len() {
-        warn!("excess item entries found: {}", item_file_names.len() - meta_block_seq.len());
+    else if positional_blocks.len() < positional_item_file_names.len() {
+        warn!("excess item entries found: {}", positional_item_file_names.len() - positional_blocks.len());
     }
 
-    for (item_file_name, mb) in item_file_names.iter().zip(meta_block_seq) {
-        results.push((PlexTarget::SubItem(item_file_name.as_ref().to_string()), mb));
+    for (item_file_name, mb) in positional_item_file_names.iter().zip(positional_blocks) {
+        results.push((PlexTarget::SubItem(item_file_name.to_string()), mb));
     }
 
+    // Report results in item order, regardless of how they were matched.
+    results.sort_by_key(|&(ref plex_target, _)| {
+        match *plex_target {
+            PlexTarget::SubItem(ref s) => item_file_names.iter().position(|n| n == s),
+            PlexTarget::WorkingDir => None,
+        }
+    });
+
     results
 }
 
@@ -107,9 +405,32 @@ where I: IntoIterator<Item = &'a J>,
 
     // Metadata is a mapping of item file names to meta blocks.
     // Collect a mutable set of the expected item names.
-    let mut remaining_item_file_names: HashSet<&str> = item_file_names.into_iter().map(AsRef::as_ref).collect();
+    let item_file_names: Vec<&str> = item_file_names.into_iter().map(AsRef::as_ref).collect();
+    let mut remaining_item_file_names: HashSet<&str> = item_file_names.iter().cloned().collect();
+
+    // Positional keys are matched after all named keys, so that an item is never matched by position if it is also named.
+    // An item that is actually named like a positional key is matched by name.
+    let mut index_entries: Vec<(&String, usize, &MetaBlock)> = vec![];
+
+    for (meta_key, mb) in meta_block_map {
+        let search_name_string = match *meta_key {
+            // The block under the null key describes the working directory itself.
+            MetaKey::Nil => {
+                results.push((PlexTarget::WorkingDir, mb));
+                continue;
+            },
+            // The default block does not describe an item.
+            MetaKey::Str(ref s) if s == DEFAULT_KEY => continue,
+            MetaKey::Str(ref s) => s,
+        };
+
+        if !item_file_names.contains(&search_name_string.as_str()) {
+            if let Some(index) = parse_index_key(search_name_string) {
+                index_entries.push((search_name_string, index, mb));
+                continue;
+            }
+        }
 
-    for (search_name_string, mb) in meta_block_map {
         // Check if the item name is valid.
         if !is_valid_item_name(&search_name_string) {
             warn!("invalid item name: '{}'", search_name_string);
@@ -135,6 +456,19 @@ where I: IntoIterator<Item = &'a J>,
         results.push((PlexTarget::SubItem(needle), mb));
     }
 
+    // Sort, so that the outcome does not depend on the order of the mapping.
+    index_entries.sort_by_key(|&(_, index, _)| index);
+
+    for (index_key, index, mb) in index_entries {
+        match item_file_names.get(index) {
+            Some(item_file_name) if remaining_item_file_names.remove(item_file_name) => {
+                results.push((PlexTarget::SubItem(item_file_name.to_string()), mb));
+            },
+            Some(item_file_name) => { warn!("item already has metadata, ignoring positional key: '{}' ('{}')", index_key, item_file_name); },
+            None => { warn!("positional key is out of range: '{}'", index_key); },
+        }
+    }
+
     // Warn if any names remain in the set.
     if remaining_item_file_names.len() > 0 {
         warn!("excess item entries found: {}", remaining_item_file_names.len());
@@ -143,6 +477,116 @@ where I: IntoIterator<Item = &'a J>,
     results
 }
 
+/// How a block of metadata and an item of its working directory line up, as found by `align`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Alignment {
+    /// The block describes the item, given as (block label, item name).
+    Matched(String, String),
+    /// The item is not described by any block.
+    MissingBlock(String),
+    /// The block does not describe any item.
+    ExcessBlock(String),
+}
+
+/// Names a block of metadata for reports: the key for mappings, and the one-based position for sequences.
+fn block_label(position: usize, meta_block: &MetaBlock) -> String {
+    match meta_block.get(MATCH_KEY) {
+        Some(&MetaValue::Str(ref s)) => format!("block {} ({}: '{}')", position + 1, MATCH_KEY, s),
+        _ => format!("block {}", position + 1),
+    }
+}
+
+/// Lines up the blocks of metadata with the items of its working directory, given the results of plexing them.
+/// Items are listed in sort order, each with the block that describes it, followed by the blocks that describe no item, in file order.
+/// Meta files that describe their own directory have nothing to line up, and neither do the blocks for the working directory or the default fields.
+pub fn align<'a, S: AsRef<str>>(metadata: &Metadata, item_file_names: &[S], plex_records: &[PlexRecord<'a>]) -> Vec<Alignment> {
+    let labeled_blocks: Vec<(String, &MetaBlock)> = match *metadata {
+        Metadata::Contains(_) => return vec![],
+        Metadata::SiblingsSeq(ref mb_seq) => {
+            mb_seq.iter().enumerate()
+                .filter(|&(_, mb)| !is_default_block(mb))
+                .map(|(i, mb)| (block_label(i, mb), mb))
+                .collect()
+        },
+        Metadata::SiblingsMap(ref mb_map) => {
+            mb_map.iter()
+                .filter_map(|(mk, mb)| match *mk {
+                    MetaKey::Str(ref s) if s != DEFAULT_KEY => Some((s.clone(), mb)),
+                    _ => None,
+                })
+                .collect()
+        },
+    };
+
+    // Blocks are told apart by address, since equal blocks can describe different items.
+    let label_of = |mb: &MetaBlock| labeled_blocks.iter().find(|&&(_, b)| ptr::eq(b, mb)).map(|&(ref label, _)| label.clone());
+
+    let mut alignments = vec![];
+
+    for item_file_name in item_file_names {
+        let item_file_name = item_file_name.as_ref();
+
+        let opt_label = plex_records.iter()
+            .find(|&&(ref plex_target, _)| match *plex_target {
+                PlexTarget::SubItem(ref s) => s == item_file_name,
+                PlexTarget::WorkingDir => false,
+            })
+            .and_then(|&(_, mb)| label_of(mb));
+
+        alignments.push(match opt_label {
+            Some(label) => Alignment::Matched(label, item_file_name.to_string()),
+            None => Alignment::MissingBlock(item_file_name.to_string()),
+        });
+    }
+
+    for &(ref label, mb) in &labeled_blocks {
+        if !plex_records.iter().any(|&(_, b)| ptr::eq(b, mb)) {
+            alignments.push(Alignment::ExcessBlock(label.clone()));
+        }
+    }
+
+    alignments
+}
+
+/// The result of lining up the blocks of a meta file with the items they are meant to describe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlexCheckReport {
+    pub meta_path: PathBuf,
+    pub alignments: Vec<Alignment>,
+}
+
+impl PlexCheckReport {
+    /// Returns true if every item has a block, and every block has an item.
+    pub fn is_consistent(&self) -> bool {
+        self.alignments.iter().all(|a| match *a {
+            Alignment::Matched(..) => true,
+            _ => false,
+        })
+    }
+}
+
+impl Display for PlexCheckReport {
+    /// Shows blocks next to the items they describe, like a diff: blocks without an item are marked with '-', and items without a block with '+'.
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let width = self.alignments.iter().map(|a| match *a {
+            Alignment::Matched(ref label, _) => label.chars().count(),
+            _ => 0,
+        }).max().unwrap_or(0);
+
+        write!(f, "{}", self.meta_path.to_string_lossy())?;
+
+        for alignment in &self.alignments {
+            match *alignment {
+                Alignment::Matched(ref label, ref item_name) => write!(f, "\n  {:w$}  {}", label, item_name, w = width)?,
+                Alignment::MissingBlock(ref item_name) => write!(f, "\n+ {:w$}  {}", "", item_name, w = width)?,
+                Alignment::ExcessBlock(ref label) => write!(f, "\n- {}", label)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
@@ -151,14 +595,28 @@ mod tests {
         plex_singular,
         plex_multiple_seq,
         plex_multiple_map,
+        parse_index_key,
+        multiplex,
+        multiplex_nested,
+        plex,
+        default_block,
+        apply_defaults,
+        align,
         PlexTarget,
+        Alignment,
+        PlexCheckReport,
     };
     use metadata::{
         MetaBlock,
         MetaBlockSeq,
         MetaBlockMap,
+        Metadata,
+        MetaKey,
         MetaValue,
     };
+    use metadata::keys::{MATCH_KEY, ITEMS_KEY, DEFAULT_KEY, EXPECT_NAME_KEY, EXPECT_HASH_KEY};
+    use fixtures::default_setup;
+    use error::ErrorKind;
 
     #[test]
     fn test_plex_singular() {
@@ -202,26 +660,67 @@ mod tests {
             (PlexTarget::SubItem(names[1].to_string()), &mb_seq[1]),
             (PlexTarget::SubItem(names[2].to_string()), &mb_seq[2]),
         ];
-        let produced = plex_multiple_seq(&mb_seq, &names);
+        let produced = plex_multiple_seq(&mb_seq, &names, true);
+
+        assert_eq!(expected, produced);
+    }
+
+    #[test]
+    fn test_plex_multiple_seq_pinned() {
+        let mb_seq: MetaBlockSeq = vec![
+            btreemap![
+                String::from("title") => MetaValue::Str(String::from("Jupiter Junction")),
+                String::from(MATCH_KEY) => MetaValue::Str(String::from("TRACK03")),
+            ],
+            btreemap![
+                String::from("title") => MetaValue::Str(String::from("I'm Falling Love With You")),
+            ],
+            btreemap![
+                String::from("title") => MetaValue::Str(String::from("Floating Disk")),
+            ],
+            btreemap![
+                String::from("title") => MetaValue::Str(String::from("Unmatched")),
+                String::from(MATCH_KEY) => MetaValue::Str(String::from("TRACK09")),
+            ],
+        ];
+
+        let names: Vec<&str> = vec!["TRACK01.flac", "TRACK02.flac", "TRACK03.flac"];
+
+        // Pinned blocks claim their items, the rest are matched up in order.
+        let expected = vec![
+            (PlexTarget::SubItem(names[0].to_string()), &mb_seq[1]),
+            (PlexTarget::SubItem(names[1].to_string()), &mb_seq[2]),
+            (PlexTarget::SubItem(names[2].to_string()), &mb_seq[0]),
+        ];
+        let produced = plex_multiple_seq(&mb_seq, &names, true);
+
+        assert_eq!(expected, produced);
+
+        // Without fuzzy matching, pinned names must match exactly.
+        let expected = vec![
+            (PlexTarget::SubItem(names[0].to_string()), &mb_seq[1]),
+            (PlexTarget::SubItem(names[1].to_string()), &mb_seq[2]),
+        ];
+        let produced = plex_multiple_seq(&mb_seq, &names, false);
 
         assert_eq!(expected, produced);
     }
 
     #[test]
     fn test_plex_multiple_map() {
-        let mb_map: MetaBlockMap = hashmap![
-            String::from("TRACK01.flac") => btreemap![
+        let mb_map: MetaBlockMap = btreemap![
+            MetaKey::from("TRACK01.flac") => btreemap![
                 String::from("artist") => MetaValue::Seq(vec![
                     MetaValue::Str(String::from("MK")),
                     MetaValue::Str(String::from("Kanae Asaba")),
                 ]),
                 String::from("title") => MetaValue::Str(String::from("I'm Falling Love With You")),
             ],
-            String::from("TRACK02.flac") => btreemap![
+            MetaKey::from("TRACK02.flac") => btreemap![
                 String::from("artist") => MetaValue::Str(String::from("Taishi")),
                 String::from("title") => MetaValue::Str(String::from("Floating Disk")),
             ],
-            String::from("TRACK03.flac") => btreemap![
+            MetaKey::from("TRACK03.flac") => btreemap![
                 String::from("artist") => MetaValue::Str(String::from("Nhato")),
                 String::from("title") => MetaValue::Str(String::from("Jupiter Junction")),
             ],
@@ -230,12 +729,255 @@ mod tests {
         let names: Vec<&str> = vec!["TRACK01.flac", "TRACK02.flac", "TRACK03.flac"];
 
         let expected = hashset![
-            (PlexTarget::SubItem(names[1].to_string()), &mb_map["TRACK02.flac"]),
-            (PlexTarget::SubItem(names[0].to_string()), &mb_map["TRACK01.flac"]),
-            (PlexTarget::SubItem(names[2].to_string()), &mb_map["TRACK03.flac"]),
+            (PlexTarget::SubItem(names[1].to_string()), &mb_map[&MetaKey::from("TRACK02.flac")]),
+            (PlexTarget::SubItem(names[0].to_string()), &mb_map[&MetaKey::from("TRACK01.flac")]),
+            (PlexTarget::SubItem(names[2].to_string()), &mb_map[&MetaKey::from("TRACK03.flac")]),
         ];
         let produced: HashSet<_> = plex_multiple_map(&mb_map, &names, true).into_iter().collect();
 
         assert_eq!(expected, produced);
     }
+
+    #[test]
+    fn test_plex_multiple_map_nil_key() {
+        let block = |title: &str| -> MetaBlock { btreemap![String::from("title") => MetaValue::Str(title.to_string())] };
+
+        let mb_map: MetaBlockMap = btreemap![
+            MetaKey::Nil => block("Album"),
+            MetaKey::from("TRACK01.flac") => block("Track"),
+        ];
+
+        let names: Vec<&str> = vec!["TRACK01.flac"];
+
+        let expected = hashset![
+            (PlexTarget::WorkingDir, &mb_map[&MetaKey::Nil]),
+            (PlexTarget::SubItem(names[0].to_string()), &mb_map[&MetaKey::from("TRACK01.flac")]),
+        ];
+        let produced: HashSet<_> = plex_multiple_map(&mb_map, &names, false).into_iter().collect();
+
+        assert_eq!(expected, produced);
+    }
+
+    #[test]
+    fn test_default_block() {
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let names: Vec<&str> = vec!["TRACK01.flac", "TRACK02.flac"];
+
+        let defaults = btreemap![
+            MetaKey::from("album") => str_val("Album"),
+            MetaKey::from("title") => str_val("Untitled"),
+        ];
+        let mb_seq: MetaBlockSeq = vec![
+            btreemap![String::from("title") => str_val("Track 1")],
+            btreemap![String::from(DEFAULT_KEY) => MetaValue::Map(defaults)],
+            btreemap![String::from("title") => str_val("Track 2")],
+        ];
+        let md = Metadata::SiblingsSeq(mb_seq);
+
+        // The default block does not take up a position.
+        let produced = plex(&md, &names, false);
+        assert_eq!(2, produced.len());
+        assert_eq!(PlexTarget::SubItem(names[1].to_string()), produced[1].0);
+
+        let expected: MetaBlock = btreemap![String::from("album") => str_val("Album"), String::from("title") => str_val("Untitled")];
+        let found = default_block(&md).expect("Unable to find default block");
+        assert_eq!(expected, *found);
+
+        // Fields set by an item win over the default fields.
+        let merged = apply_defaults(produced[1].1, Some(&*found));
+        assert_eq!(Some(&str_val("Album")), merged.get("album"));
+        assert_eq!(Some(&str_val("Track 2")), merged.get("title"));
+
+        let mb_map: MetaBlockMap = btreemap![
+            MetaKey::from(DEFAULT_KEY) => btreemap![
+                String::from("album") => str_val("Album"),
+                String::from(MATCH_KEY) => str_val("TRACK02.flac"),
+            ],
+            MetaKey::from("TRACK01.flac") => btreemap![String::from("title") => str_val("Track 1")],
+        ];
+        let md = Metadata::SiblingsMap(mb_map);
+
+        let produced = plex(&md, &names, false);
+        assert_eq!(1, produced.len());
+        assert_eq!(PlexTarget::SubItem(names[0].to_string()), produced[0].0);
+
+        let found = default_block(&md).expect("Unable to find default block");
+        let merged = apply_defaults(produced[0].1, Some(&*found));
+        assert_eq!(Some(&str_val("Album")), merged.get("album"));
+        assert!(!merged.contains_key(MATCH_KEY));
+
+        assert!(default_block(&Metadata::Contains(MetaBlock::new())).is_none());
+    }
+
+    #[test]
+    fn test_parse_index_key() {
+        let inputs_and_expected = vec![
+            ("#1", Some(0)),
+            ("#12", Some(11)),
+            ("#0", None),
+            ("#", None),
+            ("#+1", None),
+            ("#1a", None),
+            ("1", None),
+            ("TRACK01.flac", None),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, parse_index_key(input));
+        }
+    }
+
+    #[test]
+    fn test_plex_multiple_map_index_keys() {
+        let block = |title: &str| -> MetaBlock { btreemap![String::from("title") => MetaValue::Str(title.to_string())] };
+
+        let mb_map: MetaBlockMap = btreemap![
+            MetaKey::from("#3") => block("Third"),
+            MetaKey::from("TRACK01.flac") => block("Named"),
+            MetaKey::from("#1") => block("Ignored"),
+            MetaKey::from("#9") => block("Out Of Range"),
+            MetaKey::from("#2") => block("Literal"),
+        ];
+
+        // An item that is literally named like a positional key is matched by name.
+        let names: Vec<&str> = vec!["TRACK01.flac", "TRACK02.flac", "TRACK03.flac", "#2"];
+
+        let expected = hashset![
+            (PlexTarget::SubItem(names[0].to_string()), &mb_map[&MetaKey::from("TRACK01.flac")]),
+            (PlexTarget::SubItem(names[2].to_string()), &mb_map[&MetaKey::from("#3")]),
+            (PlexTarget::SubItem(names[3].to_string()), &mb_map[&MetaKey::from("#2")]),
+        ];
+        let produced: HashSet<_> = plex_multiple_map(&mb_map, &names, false).into_iter().collect();
+
+        assert_eq!(expected, produced);
+    }
+
+    #[test]
+    fn test_align() {
+        let title_block = |title: &str| btreemap![String::from("title") => MetaValue::Str(String::from(title))];
+
+        let mut pinned_block = title_block("Unmatched");
+        pinned_block.insert(String::from(MATCH_KEY), MetaValue::Str(String::from("TRACK09")));
+
+        let metadata = Metadata::SiblingsSeq(vec![title_block("Track 1"), pinned_block, title_block("Track 2")]);
+        let names: Vec<&str> = vec!["TRACK01.flac", "TRACK02.flac", "TRACK03.flac"];
+
+        let plex_records = plex(&metadata, &names, false);
+
+        let expected = vec![
+            Alignment::Matched(String::from("block 1"), String::from("TRACK01.flac")),
+            Alignment::Matched(String::from("block 3"), String::from("TRACK02.flac")),
+            Alignment::MissingBlock(String::from("TRACK03.flac")),
+            Alignment::ExcessBlock(String::from("block 2 (__match: 'TRACK09')")),
+        ];
+        let produced = align(&metadata, &names, &plex_records);
+        assert_eq!(expected, produced);
+
+        let report = PlexCheckReport { meta_path: "item.yml".into(), alignments: produced };
+        assert!(!report.is_consistent());
+
+        let expected = "item.yml\n  block 1  TRACK01.flac\n  block 3  TRACK02.flac\n+          TRACK03.flac\n- block 2 (__match: 'TRACK09')";
+        assert_eq!(expected, report.to_string());
+
+        // Mappings are labeled by their keys.
+        let metadata = Metadata::SiblingsMap(btreemap![
+            MetaKey::from("TRACK01.flac") => title_block("Track 1"),
+            MetaKey::from("TRACK02.flac") => title_block("Track 2"),
+        ]);
+        let names: Vec<&str> = vec!["TRACK01.flac", "TRACK02.flac"];
+
+        let plex_records = plex(&metadata, &names, false);
+        let report = PlexCheckReport { meta_path: "item.yml".into(), alignments: align(&metadata, &names, &plex_records) };
+        assert!(report.is_consistent());
+        assert_eq!(Alignment::Matched(String::from("TRACK02.flac"), String::from("TRACK02.flac")), report.alignments[1]);
+
+        // Meta files that describe their own directory have nothing to line up.
+        let metadata = Metadata::Contains(title_block("Album"));
+        assert!(align(&metadata, &names, &plex(&metadata, &names, false)).is_empty());
+    }
+
+    #[test]
+    fn test_multiplex_expectations() {
+        let (temp_media_root, media_lib) = default_setup("test_multiplex_expectations");
+        let album_dir = temp_media_root.path().join("ALBUM_02");
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let check = |metadata: &Metadata| {
+            multiplex(metadata, &album_dir, media_lib.selection(), media_lib.sort_order(), media_lib.group_order(), false).map(|records| records.len())
+        };
+
+        // The media files of the test library are empty.
+        let metadata = Metadata::SiblingsSeq(vec![
+            btreemap![String::from(EXPECT_NAME_KEY) => str_val("DISC_01")],
+            btreemap![
+                String::from(EXPECT_NAME_KEY) => str_val("TRACK_01.flac"),
+                String::from(EXPECT_HASH_KEY) => str_val("CBF29CE484222325"),
+            ],
+        ]);
+        assert_eq!(2, check(&metadata).unwrap());
+
+        // Blocks that would describe a different item than expected are an error.
+        let metadata = Metadata::SiblingsSeq(vec![
+            btreemap![String::from(EXPECT_NAME_KEY) => str_val("TRACK_01.flac")],
+        ]);
+        match *check(&metadata).unwrap_err().kind() {
+            ErrorKind::UnexpectedItemName(ref item_path, ref expected) => {
+                assert_eq!(&album_dir.join("DISC_01"), item_path);
+                assert_eq!("TRACK_01.flac", expected);
+            },
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+
+        let metadata = Metadata::SiblingsSeq(vec![
+            btreemap![],
+            btreemap![String::from(EXPECT_HASH_KEY) => str_val("0000000000000000")],
+        ]);
+        match *check(&metadata).unwrap_err().kind() {
+            ErrorKind::UnexpectedItemHash(ref item_path, _, ref found) => {
+                assert_eq!(&album_dir.join("TRACK_01.flac"), item_path);
+                assert_eq!("cbf29ce484222325", found);
+            },
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn test_multiplex_nested() {
+        let (temp_media_root, media_lib) = default_setup("test_multiplex_nested");
+        let tp = temp_media_root.path();
+        let disc_fp = tp.join("ALBUM_03").join("DISC_02");
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let nested_block = |s: &str| MetaValue::Map(btreemap![MetaKey::Str(String::from("title")) => str_val(s)]);
+
+        let metadata = Metadata::SiblingsSeq(vec![
+            btreemap![
+                String::from("title") => str_val("Track 1"),
+                String::from(ITEMS_KEY) => MetaValue::Seq(vec![nested_block("Part 1"), nested_block("Part 2")]),
+            ],
+            btreemap![String::from("title") => str_val("Track 2")],
+            // Files cannot have nested items.
+            btreemap![
+                String::from("title") => str_val("Track 3"),
+                String::from(ITEMS_KEY) => MetaValue::Seq(vec![nested_block("Ignored")]),
+            ],
+        ]);
+
+        let produced: Vec<_> = {
+            multiplex_nested(&metadata, &disc_fp, media_lib.selection(), media_lib.sort_order(), media_lib.group_order(), false)
+                .expect("Unable to plex nested items")
+                .into_iter()
+                .map(|(item_path, mb)| (item_path, mb.get("title").cloned()))
+                .collect()
+        };
+        let expected = vec![
+            (disc_fp.join("TRACK_01"), Some(str_val("Track 1"))),
+            (disc_fp.join("TRACK_01").join("SUBTRACK_01.flac"), Some(str_val("Part 1"))),
+            (disc_fp.join("TRACK_01").join("SUBTRACK_02.flac"), Some(str_val("Part 2"))),
+            (disc_fp.join("TRACK_02"), Some(str_val("Track 2"))),
+            (disc_fp.join("TRACK_03.flac"), Some(str_val("Track 3"))),
+        ];
+
+        assert_eq!(expected, produced);
+    }
 }
@@ -0,0 +1,39 @@
+// Progress reporting for operations that walk large parts of a library, so that callers can show status.
+
+use std::path::{Path, PathBuf};
+
+/// Receives updates from long-running operations.
+/// All methods do nothing by default, so implementors only need to handle the updates they care about.
+pub trait Progress {
+    /// Called when an operation starts working on an item.
+    fn item_scanned(&mut self, _abs_item_path: &Path) {}
+
+    /// Called after a meta file has been read and parsed.
+    fn meta_file_parsed(&mut self, _abs_meta_path: &Path) {}
+}
+
+/// Ignores all updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {}
+
+/// Keeps running totals of all updates, along with the most recent path seen.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProgressCounts {
+    pub items_scanned: usize,
+    pub meta_files_parsed: usize,
+    pub current_path: Option<PathBuf>,
+}
+
+impl Progress for ProgressCounts {
+    fn item_scanned(&mut self, abs_item_path: &Path) {
+        self.items_scanned += 1;
+        self.current_path = Some(abs_item_path.to_path_buf());
+    }
+
+    fn meta_file_parsed(&mut self, abs_meta_path: &Path) {
+        self.meta_files_parsed += 1;
+        self.current_path = Some(abs_meta_path.to_path_buf());
+    }
+}
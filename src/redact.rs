@@ -0,0 +1,165 @@
+// This module redacts sensitive fields (e.g. purchase info or personal ratings) from exported metadata, so that listings of a library can be shared publicly.
+
+use std::str::FromStr;
+
+use glob;
+use yaml_rust::Yaml;
+
+use metadata::MetaValue;
+use helpers::{fnv1a, FNV_OFFSET_BASIS};
+use error::*;
+
+/// The key in a redaction mapping that holds the salt for hashed values, rather than a field pattern.
+const SALT_KEY: &str = "__salt";
+
+/// What happens to a sensitive field on export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedactAction {
+    /// Leaves the field out.
+    Remove,
+    /// Replaces each string in the value with a hash of it, so that equal values can still be matched up without being shown.
+    Hash,
+}
+
+impl FromStr for RedactAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "remove" => Ok(RedactAction::Remove),
+            "hash" => Ok(RedactAction::Hash),
+            _ => bail!("unknown redaction action: '{}', expected remove or hash", s),
+        }
+    }
+}
+
+/// Rules for redacting fields on export, as field name patterns along with what to do with matching fields.
+/// The first matching rule applies.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Redaction {
+    rules: Vec<(glob::Pattern, RedactAction)>,
+    salt: String,
+}
+
+impl Redaction {
+    pub fn new() -> Self {
+        Redaction::default()
+    }
+
+    pub fn rule<S: AsRef<str>>(&mut self, field_pattern: S, action: RedactAction) -> Result<&mut Self> {
+        let field_pattern = field_pattern.as_ref();
+        let pattern = glob::Pattern::new(field_pattern).chain_err(|| ErrorKind::InvalidFieldPattern(field_pattern.to_string()))?;
+
+        self.rules.push((pattern, action));
+        Ok(self)
+    }
+
+    /// Hashes are not cryptographic, so short values (e.g. ratings from 1 to 5) can be guessed unless a secret salt is mixed in.
+    pub fn salt<S: Into<String>>(&mut self, salt: S) -> &mut Self {
+        self.salt = salt.into();
+        self
+    }
+
+    /// Adds rules from a mapping of field patterns to actions, e.g. `purchase_*: remove`, along with an optional salt under `__salt`.
+    pub fn extend_from_yaml(&mut self, y: &Yaml) -> Result<()> {
+        match *y {
+            Yaml::Hash(ref hsh) => {
+                for (field_pattern_y, action_y) in hsh {
+                    match (field_pattern_y.as_str(), action_y.as_str()) {
+                        (Some(SALT_KEY), Some(salt)) => { self.salt(salt); },
+                        (Some(field_pattern), Some(action)) => { self.rule(field_pattern, action.parse()?)?; },
+                        _ => bail!("redactions must map field patterns to actions"),
+                    }
+                }
+
+                Ok(())
+            },
+            Yaml::Null => Ok(()),
+            _ => bail!("redactions must be a mapping of field patterns to actions"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn action_for<S: AsRef<str>>(&self, field_name: S) -> Option<RedactAction> {
+        let field_name = field_name.as_ref();
+
+        self.rules.iter().find(|&&(ref pattern, _)| pattern.matches(field_name)).map(|&(_, action)| action)
+    }
+
+    /// Returns the value to export for a field, or `None` if the field should be left out.
+    pub fn apply<S: AsRef<str>>(&self, field_name: S, mv: MetaValue) -> Option<MetaValue> {
+        match self.action_for(field_name) {
+            None => Some(mv),
+            Some(RedactAction::Remove) => None,
+            Some(RedactAction::Hash) => Some(self.hash_value(mv)),
+        }
+    }
+
+    /// Hashes every string in a value, keeping its shape (and the keys of mappings).
+    fn hash_value(&self, mv: MetaValue) -> MetaValue {
+        match mv {
+            MetaValue::Nil => MetaValue::Nil,
+            MetaValue::Str(s) => {
+                let hash = fnv1a(fnv1a(FNV_OFFSET_BASIS, self.salt.as_bytes()), s.as_bytes());
+                MetaValue::Str(format!("hash:{:016x}", hash))
+            },
+            MetaValue::Seq(mvs) => MetaValue::Seq(mvs.into_iter().map(|mv| self.hash_value(mv)).collect()),
+            MetaValue::Map(map) => MetaValue::Map(map.into_iter().map(|(k, mv)| (k, self.hash_value(mv))).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+
+    use metadata::MetaValue;
+
+    use super::{Redaction, RedactAction};
+
+    #[test]
+    fn test_redaction() {
+        let text = "purchase_*: remove\nrating: hash\n__salt: secret\n";
+        let y = &YamlLoader::load_from_str(text).unwrap()[0];
+
+        let mut redaction = Redaction::new();
+        redaction.extend_from_yaml(y).unwrap();
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        assert_eq!(Some(RedactAction::Remove), redaction.action_for("purchase_price"));
+        assert_eq!(None, redaction.apply("purchase_date", str_val("2020-01-01")));
+        assert_eq!(Some(str_val("Title")), redaction.apply("title", str_val("Title")));
+
+        // Equal values hash the same, and different values and salts do not.
+        let hashed = redaction.apply("rating", str_val("5")).unwrap();
+        assert_eq!(Some(hashed.clone()), redaction.apply("rating", str_val("5")));
+        assert_ne!(Some(hashed.clone()), redaction.apply("rating", str_val("4")));
+        assert_ne!(Some(hashed.clone()), Redaction::new().rule("rating", RedactAction::Hash).unwrap().apply("rating", str_val("5")));
+
+        match hashed {
+            MetaValue::Str(ref s) => assert!(s.starts_with("hash:") && s.len() == 21),
+            _ => panic!("expected a string"),
+        }
+
+        // Sequences keep their shape.
+        match redaction.apply("rating", MetaValue::Seq(vec![str_val("5"), MetaValue::Nil])) {
+            Some(MetaValue::Seq(ref mvs)) => assert_eq!(vec![hashed.clone(), MetaValue::Nil], *mvs),
+            other => panic!("unexpected value: {:?}", other),
+        }
+
+        let invalid_inputs = vec![
+            "rating: scramble",
+            "- rating",
+            "'[': remove",
+        ];
+
+        for input in invalid_inputs {
+            let y = &YamlLoader::load_from_str(input).unwrap()[0];
+            assert!(Redaction::new().extend_from_yaml(y).is_err(), "expected error for: {}", input);
+        }
+    }
+}
@@ -0,0 +1,548 @@
+// This module provides library-wide refactorings of meta files, such as renaming a field everywhere it is used.
+// Changes are added to a write plan, so that they can be previewed before anything is written.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use regex::{self, Captures, Regex};
+use yaml_rust::{Yaml, YamlLoader};
+use yaml_rust::yaml::Hash;
+
+use library::Library;
+use metadata::{MetaTarget, sidecar_path};
+use metadata::keys::{is_reserved_key, ITEMS_KEY, MATCH_KEY, EXPECT_NAME_KEY};
+use multivalue::{SplitRules, split_str};
+use plan::WritePlan;
+use yaml::meta_block_as_yaml;
+use error::*;
+
+/// Calls a function on a block and on any nested item blocks inside it.
+/// Returns true if the function changed any of them.
+fn visit_block<F>(block: &mut Yaml, f: &mut F) -> Result<bool>
+where F: FnMut(&mut Hash) -> Result<bool>,
+{
+    let hash = match *block {
+        Yaml::Hash(ref mut hash) => hash,
+        _ => return Ok(false),
+    };
+
+    let mut changed = false;
+
+    // Nested item blocks are blocks in their own right.
+    if let Some(&mut Yaml::Array(ref mut nested_blocks)) = hash.get_mut(&Yaml::String(ITEMS_KEY.to_string())) {
+        for nested_block in nested_blocks {
+            changed |= visit_block(nested_block, f)?;
+        }
+    }
+
+    changed |= f(hash)?;
+
+    Ok(changed)
+}
+
+/// Calls a function on every block of a meta file document.
+/// Returns true if the function changed any of them.
+fn visit_blocks<F>(doc: &mut Yaml, meta_target: MetaTarget, f: &mut F) -> Result<bool>
+where F: FnMut(&mut Hash) -> Result<bool>,
+{
+    match meta_target {
+        MetaTarget::Contains | MetaTarget::Sidecar { .. } => visit_block(doc, f),
+        MetaTarget::Siblings => {
+            let mut changed = false;
+
+            match *doc {
+                Yaml::Array(ref mut blocks) => {
+                    for block in blocks {
+                        changed |= visit_block(block, f)?;
+                    }
+                },
+                Yaml::Hash(ref mut named_blocks) => {
+                    for (_, block) in named_blocks.iter_mut() {
+                        changed |= visit_block(block, f)?;
+                    }
+                },
+                _ => {},
+            }
+
+            Ok(changed)
+        },
+    }
+}
+
+/// Renames a key in a single block, keeping its position.
+/// Returns true if the key was found.
+fn rename_in_block(hash: &mut Hash, old_name: &str, new_name: &str, meta_fp: &Path) -> Result<bool> {
+    let old_key = Yaml::String(old_name.to_string());
+    let new_key = Yaml::String(new_name.to_string());
+
+    if !hash.contains_key(&old_key) {
+        return Ok(false);
+    }
+
+    ensure!(!hash.contains_key(&new_key), format!("cannot rename field '{}' to '{}', since a block in '{}' already has both", old_name, new_name, meta_fp.to_string_lossy()));
+
+    let entries: Vec<(Yaml, Yaml)> = hash.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    let mut renamed_hash = Hash::new();
+
+    for (k, v) in entries {
+        let k = if k == old_key { new_key.clone() } else { k };
+        renamed_hash.insert(k, v);
+    }
+
+    *hash = renamed_hash;
+
+    Ok(true)
+}
+
+/// Replaces all matches of a regex in the strings of a value, including strings inside sequences and mappings.
+/// Returns the number of strings that were changed.
+fn replace_in_value(value: &mut Yaml, regex: &Regex, replacement: &str) -> usize {
+    match *value {
+        Yaml::String(ref mut s) => {
+            let replaced = regex.replace_all(s, replacement).into_owned();
+
+            if replaced == *s {
+                0
+            }
+            else {
+                *s = replaced;
+                1
+            }
+        },
+        Yaml::Array(ref mut values) => values.iter_mut().map(|v| replace_in_value(v, regex, replacement)).sum(),
+        Yaml::Hash(ref mut hash) => hash.iter_mut().map(|(_, v)| replace_in_value(v, regex, replacement)).sum(),
+        _ => 0,
+    }
+}
+
+/// Splits the strings in a value that join several values, turning a string into a sequence if it holds several values.
+/// Returns the number of strings that were split.
+fn split_in_value(value: &mut Yaml, delimiters: &[String]) -> usize {
+    match *value {
+        Yaml::String(ref s) => {
+            let parts = split_str(s, delimiters);
+
+            if parts.len() == 1 {
+                return 0;
+            }
+
+            *value = Yaml::Array(parts.into_iter().map(Yaml::String).collect());
+            1
+        },
+        Yaml::Array(ref mut values) => {
+            let mut count = 0;
+            let mut split_values = vec![];
+
+            for v in values.drain(..) {
+                match v {
+                    Yaml::String(ref s) => {
+                        let parts = split_str(s, delimiters);
+
+                        if parts.len() > 1 {
+                            count += 1;
+                        }
+
+                        split_values.extend(parts.into_iter().map(Yaml::String));
+                    },
+                    v => split_values.push(v),
+                }
+            }
+
+            *values = split_values;
+            count
+        },
+        _ => 0,
+    }
+}
+
+/// Renames keys directly in the text of a meta file, so that comments and formatting are kept.
+/// This only looks at the text, so the result must be checked before it is used.
+fn rename_in_text(text: &str, old_name: &str, new_name: &str) -> Result<String> {
+    let old_name = regex::escape(old_name);
+    let key_pattern = format!(r#"(?:{0}|"{0}"|'{0}')"#, old_name);
+
+    // Keys in block style, which may follow the dashes of sequence entries.
+    let block_regex = Regex::new(&format!(r"(?m)^((?:[ \t]*-[ \t]+)*[ \t]*){}([ \t]*:(?:[ \t]|$))", key_pattern)).chain_err(|| "unable to compile key pattern")?;
+
+    // Keys in flow style, e.g. `{title: A, artist: B}`.
+    let flow_regex = Regex::new(&format!(r"([{{,][ \t]*){}([ \t]*:)", key_pattern)).chain_err(|| "unable to compile key pattern")?;
+
+    let replacer = |caps: &Captures| format!("{}{}{}", &caps[1], new_name, &caps[2]);
+
+    let text = block_regex.replace_all(text, replacer).into_owned();
+    let text = flow_regex.replace_all(&text, replacer).into_owned();
+
+    Ok(text)
+}
+
+/// Renames a field in every meta file in the library.
+/// Where possible, only the keys themselves are changed in the text of each file, and everything else is kept as is.
+/// Files where that cannot be done safely (e.g. because the new name would need quoting) are rewritten in full instead.
+/// Returns the paths of the meta files that contain the field.
+pub fn rename_field(media_lib: &Library, old_name: &str, new_name: &str, plan: &mut WritePlan) -> Result<Vec<PathBuf>> {
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    // Rule: neither name may be reserved, since reserved fields have a meaning of their own.
+    ensure!(!is_reserved_key(old_name), format!("not a user field name: '{}'", old_name));
+    ensure!(!is_reserved_key(new_name), format!("not a user field name: '{}'", new_name));
+    ensure!(old_name != new_name, "old and new field names are the same");
+
+    let mut touched = vec![];
+
+    for meta_fp in media_lib.meta_fps_in_tree(media_lib.root_dir())? {
+        let meta_target = media_lib.meta_target_of(&meta_fp)?;
+
+        // Read through the plan, so that earlier refactorings in the same plan are kept.
+        let mut doc = plan.read_yaml(&meta_fp)?;
+
+        if !visit_blocks(&mut doc, meta_target, &mut |hash: &mut Hash| rename_in_block(hash, old_name, new_name, &meta_fp))? {
+            continue;
+        }
+
+        let old_text = match plan.ops().iter().find(|op| op.path() == meta_fp.as_path()) {
+            Some(_) => None,
+            None => {
+                let mut buffer = String::new();
+                File::open(&meta_fp)?.read_to_string(&mut buffer)?;
+                Some(buffer)
+            },
+        };
+
+        let opt_new_text = match old_text {
+            Some(ref old_text) => {
+                let new_text = rename_in_text(old_text, old_name, new_name)?;
+                let matches = YamlLoader::load_from_str(&new_text).ok().and_then(|docs| docs.into_iter().next()) == Some(doc.clone());
+
+                if matches { Some(new_text) } else { None }
+            },
+            None => None,
+        };
+
+        match opt_new_text {
+            Some(new_text) => { plan.write_text(&meta_fp, new_text); },
+            None => {
+                warn!("unable to keep formatting while renaming field in meta file, rewriting it in full: '{}'", meta_fp.to_string_lossy());
+                plan.write_yaml(&meta_fp, doc);
+            },
+        }
+
+        touched.push(meta_fp);
+    }
+
+    Ok(touched)
+}
+
+/// Replaces all matches of a regex in the values of a field, in every meta file in the library.
+/// The replacement can refer to capture groups, e.g. `$1`.
+/// Meta files with changes are edited in place, keeping comments and formatting outside of the changed values.
+/// Returns the paths of the meta files that were changed, along with how many values were changed in each.
+pub fn replace_values(media_lib: &Library, field_name: &str, regex: &Regex, replacement: &str, plan: &mut WritePlan) -> Result<Vec<(PathBuf, usize)>> {
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    let field_key = Yaml::String(field_name.to_string());
+    let mut touched = vec![];
+
+    for meta_fp in media_lib.meta_fps_in_tree(media_lib.root_dir())? {
+        let meta_target = media_lib.meta_target_of(&meta_fp)?;
+        let mut doc = plan.read_yaml(&meta_fp)?;
+        let mut count = 0;
+
+        visit_blocks(&mut doc, meta_target, &mut |hash: &mut Hash| {
+            let changed = hash.get_mut(&field_key).map_or(0, |v| replace_in_value(v, regex, replacement));
+            count += changed;
+            Ok(changed > 0)
+        })?;
+
+        if count > 0 {
+            plan.write_yaml(&meta_fp, doc);
+            touched.push((meta_fp, count));
+        }
+    }
+
+    Ok(touched)
+}
+
+/// Splits the values of fields that join several values with a delimiter (e.g. `artist: A feat. B`) into sequences, in every meta file in the library.
+/// Only the fields with split rules are changed, or only the given field if there is one.
+/// Returns the paths of the meta files that were changed, along with how many values were split in each.
+pub fn split_values(media_lib: &Library, split_rules: &SplitRules, opt_field_name: Option<&str>, plan: &mut WritePlan) -> Result<Vec<(PathBuf, usize)>> {
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    let field_names = match opt_field_name {
+        Some(field_name) => {
+            // Rule: the field must have a split rule.
+            ensure!(!split_rules.delimiters_for(field_name).is_empty(), format!("no split rule for field: '{}'", field_name));
+            vec![field_name]
+        },
+        None => split_rules.fields(),
+    };
+
+    let mut touched = vec![];
+
+    for meta_fp in media_lib.meta_fps_in_tree(media_lib.root_dir())? {
+        let meta_target = media_lib.meta_target_of(&meta_fp)?;
+        let mut doc = plan.read_yaml(&meta_fp)?;
+        let mut count = 0;
+
+        visit_blocks(&mut doc, meta_target, &mut |hash: &mut Hash| {
+            let mut changed = 0;
+
+            for field_name in &field_names {
+                if let Some(v) = hash.get_mut(&Yaml::String(field_name.to_string())) {
+                    changed += split_in_value(v, split_rules.delimiters_for(field_name));
+                }
+            }
+
+            count += changed;
+            Ok(changed > 0)
+        })?;
+
+        if count > 0 {
+            plan.write_yaml(&meta_fp, doc);
+            touched.push((meta_fp, count));
+        }
+    }
+
+    Ok(touched)
+}
+
+/// Splits a sibling meta file into one sidecar meta file per item, for directories where a single meta file has become unwieldy.
+/// Each item gets its own block, with defaults applied and matching keys removed, since a sidecar is matched up by its name.
+/// The sibling meta file is removed once it has been split.
+/// Returns the paths of the sidecar meta files that were created.
+pub fn split_into_sidecars<P: AsRef<Path>>(media_lib: &Library, abs_meta_path: P, plan: &mut WritePlan) -> Result<Vec<PathBuf>> {
+    let abs_meta_path = abs_meta_path.as_ref();
+
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    let suffix = match media_lib.meta_target_specs().iter().filter_map(|&(_, ref t)| t.sidecar_suffix()).next() {
+        Some(suffix) => suffix,
+        None => bail!("library has no sidecar meta target"),
+    };
+
+    // Rule: only meta files that describe sibling items can be split.
+    ensure!(media_lib.meta_target_of(abs_meta_path)? == MetaTarget::Siblings, format!("not a sibling meta file: '{}'", abs_meta_path.to_string_lossy()));
+
+    let mut created = vec![];
+
+    for (item_path, mut mb) in media_lib.item_fps_from_meta_fp(abs_meta_path)? {
+        mb.remove(MATCH_KEY);
+        mb.remove(EXPECT_NAME_KEY);
+
+        // Nested items are yielded on their own, and get sidecars of their own.
+        if media_lib.nested_items() {
+            mb.remove(ITEMS_KEY);
+        }
+
+        let sidecar_fp = sidecar_path(&item_path, suffix).ok_or_else(|| ErrorKind::NotAFile(item_path.clone()))?;
+
+        // Rule: existing sidecars are never overwritten.
+        ensure!(!plan.file_exists(&sidecar_fp), format!("sidecar meta file already exists: '{}'", sidecar_fp.to_string_lossy()));
+
+        plan.write_yaml(&sidecar_fp, meta_block_as_yaml(&mb));
+        created.push(sidecar_fp);
+    }
+
+    plan.remove(abs_meta_path);
+
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
+
+    use regex::Regex;
+
+    use library::LibraryBuilder;
+    use library::selection::Selection;
+    use lookup::LookupContext;
+    use metadata::{MetaTarget, MetaValue};
+    use multivalue::SplitRules;
+    use plan::WritePlan;
+    use yaml::read_yaml_file;
+    use fixtures::{create_temp_media_test_dir, default_setup};
+
+    use super::{rename_field, rename_in_text, replace_values, split_values, split_into_sidecars};
+
+    #[test]
+    fn test_rename_in_text() {
+        let inputs_and_expected = vec![
+            ("title: A\n", "name: A\n"),
+            ("# the title\n- title: A\n  subtitle: B\n- 'title': C\n", "# the title\n- name: A\n  subtitle: B\n- name: C\n"),
+            ("- {title: A, \"title\" : B}\n", "- {name: A, name : B}\n"),
+            ("title:\n  - title: x\n", "name:\n  - name: x\n"),
+            ("entitled: A\ntitle_2: B\n", "entitled: A\ntitle_2: B\n"),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, rename_in_text(input, "title", "name").unwrap());
+        }
+    }
+
+    #[test]
+    fn test_rename_field() {
+        let (temp_media_root, media_lib) = default_setup("test_rename_field");
+        let tp = temp_media_root.path();
+
+        // Comments should survive the rename.
+        let self_fp = tp.join("ALBUM_01").join("self.yml");
+        let mut f = File::create(&self_fp).unwrap();
+        writeln!(f, "# album\nconst_key: const_val\nself_key: self_val").unwrap();
+
+        let mut plan = WritePlan::new();
+        let touched = rename_field(&media_lib, "const_key", "fixed_key", &mut plan).unwrap();
+
+        assert!(touched.contains(&self_fp));
+        assert!(touched.contains(&tp.join("ALBUM_01").join("item.yml")));
+        assert_eq!(touched.len(), plan.ops().len());
+
+        plan.execute().unwrap();
+
+        let mut text = String::new();
+        File::open(&self_fp).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!("# album\nfixed_key: const_val\nself_key: self_val\n", text);
+
+        for meta_fp in touched {
+            let doc = read_yaml_file(&meta_fp).unwrap();
+            let text = format!("{:?}", doc);
+            assert!(text.contains("fixed_key") && !text.contains("const_key"));
+        }
+
+        // Renaming to a field that a block already has would lose data.
+        let mut plan = WritePlan::new();
+        assert!(rename_field(&media_lib, "fixed_key", "self_key", &mut plan).is_err());
+
+        let mut plan = WritePlan::new();
+        assert!(rename_field(&media_lib, "fixed_key", "__match", &mut plan).is_err());
+        assert!(rename_field(&media_lib, "__match", "other_key", &mut plan).is_err());
+        assert!(rename_field(&media_lib, "missing_key", "other_key", &mut plan).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replace_values() {
+        let (temp_media_root, media_lib) = default_setup("test_replace_values");
+        let tp = temp_media_root.path();
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01").join("item.yml");
+        let mut f = File::create(&item_fp).unwrap();
+        writeln!(f, "- artist: A feat. B\n- artist: [C feat. D, E]\n  title: X feat. Y\n- title: Z").unwrap();
+
+        let regex = Regex::new(r"feat\.").unwrap();
+
+        let mut plan = WritePlan::new();
+        let touched = replace_values(&media_lib, "artist", &regex, "ft.", &mut plan).unwrap();
+        assert_eq!(vec![(item_fp.clone(), 2)], touched);
+
+        // Nothing is written until the plan is executed.
+        let diff = plan.diff().unwrap();
+        assert!(diff.contains("-- artist: A feat. B"));
+        assert!(diff.contains("+- artist: A ft. B"));
+
+        plan.execute().unwrap();
+
+        let text = format!("{:?}", read_yaml_file(&item_fp).unwrap());
+        assert!(text.contains("C ft. D") && text.contains("X feat. Y"));
+
+        let regex = Regex::new(r"^(\w+) ft\. (\w+)$").unwrap();
+        let mut plan = WritePlan::new();
+        replace_values(&media_lib, "artist", &regex, "$2 & $1", &mut plan).unwrap();
+        assert!(format!("{:?}", plan.read_yaml(&item_fp).unwrap()).contains("B & A"));
+
+        let mut plan = WritePlan::new();
+        assert!(replace_values(&media_lib, "missing", &regex, "x", &mut plan).unwrap().is_empty());
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn test_split_values() {
+        let (temp_media_root, media_lib) = default_setup("test_split_values");
+        let tp = temp_media_root.path();
+
+        let item_fp = tp.join("ALBUM_01").join("DISC_01").join("item.yml");
+        let mut f = File::create(&item_fp).unwrap();
+        writeln!(f, "- artist: A feat. B\n  genre: Rock; Jazz\n- artist: [C feat. D, E]\n  title: X feat. Y\n- artist: F").unwrap();
+
+        let mut plan = WritePlan::new();
+        let touched = split_values(&media_lib, &SplitRules::default(), Some("artist"), &mut plan).unwrap();
+        assert_eq!(vec![(item_fp.clone(), 2)], touched);
+
+        plan.execute().unwrap();
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        let track_fp = |n: &str| tp.join("ALBUM_01").join("DISC_01").join(format!("TRACK_{}.flac", n));
+        assert_eq!(Some(MetaValue::Seq(vec![str_val("A"), str_val("B")])), lookup_ctx.lookup_origin(track_fp("01"), "artist").unwrap());
+        assert_eq!(Some(MetaValue::Seq(vec![str_val("C"), str_val("D"), str_val("E")])), lookup_ctx.lookup_origin(track_fp("02"), "artist").unwrap());
+        assert_eq!(Some(str_val("X feat. Y")), lookup_ctx.lookup_origin(track_fp("02"), "title").unwrap());
+        assert_eq!(Some(str_val("Rock; Jazz")), lookup_ctx.lookup_origin(track_fp("01"), "genre").unwrap());
+
+        // Without a field, every field with a split rule is split.
+        let mut plan = WritePlan::new();
+        assert_eq!(vec![(item_fp.clone(), 1)], split_values(&media_lib, &SplitRules::default(), None, &mut plan).unwrap());
+
+        let mut plan = WritePlan::new();
+        assert!(split_values(&media_lib, &SplitRules::default(), Some("title"), &mut plan).is_err());
+    }
+
+    #[test]
+    fn test_split_into_sidecars() {
+        let temp_media_root = create_temp_media_test_dir("test_split_into_sidecars");
+        let tp = temp_media_root.path();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+            (String::from("sidecar"), MetaTarget::Sidecar { suffix: String::from("meta.yml") }),
+        ];
+        let selection = Selection::Or(
+            Box::new(Selection::Ext(String::from("flac"))),
+            Box::new(Selection::IsDir),
+        );
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs).selection(selection).create().unwrap();
+
+        let album_dir = tp.join("ALBUM_02");
+        let item_fp = album_dir.join("item.yml");
+        let before = media_lib.item_fps_from_meta_fp(&item_fp).unwrap();
+
+        let mut plan = WritePlan::new();
+        let created = split_into_sidecars(&media_lib, &item_fp, &mut plan).unwrap();
+        assert_eq!(vec![
+            album_dir.join("DISC_01.meta.yml"),
+            album_dir.join("TRACK_01.flac.meta.yml"),
+            album_dir.join("TRACK_02.flac.meta.yml"),
+            album_dir.join("TRACK_03.flac.meta.yml"),
+        ], created);
+
+        // The sibling meta file counts as gone once its removal is planned.
+        assert!(plan.file_exists(&created[0]));
+        assert!(!plan.file_exists(&item_fp));
+
+        plan.execute().unwrap();
+        assert!(!item_fp.exists());
+
+        // Each item is still described by the same block, now from its own sidecar.
+        for ((item_path, mb), sidecar_fp) in before.into_iter().zip(&created) {
+            assert_eq!(vec![(item_path.clone(), mb)], media_lib.item_fps_from_meta_fp(sidecar_fp).unwrap());
+            assert!(media_lib.meta_fps_from_item_fp(&item_path).unwrap().contains(sidecar_fp));
+        }
+
+        let (track_fp, mb) = media_lib.item_fps_from_meta_fp(&created[2]).unwrap().remove(0);
+        assert_eq!(album_dir.join("TRACK_02.flac"), track_fp);
+        assert_eq!(Some(&MetaValue::Str(String::from("TRACK_02_item_val"))), mb.get("TRACK_02_item_key"));
+
+        // Only sibling meta files can be split, and only in libraries with a sidecar target.
+        let mut plan = WritePlan::new();
+        assert!(split_into_sidecars(&media_lib, album_dir.join("self.yml"), &mut plan).is_err());
+
+        let (temp_media_root, media_lib) = default_setup("test_split_into_sidecars_none");
+        let mut plan = WritePlan::new();
+        assert!(split_into_sidecars(&media_lib, temp_media_root.path().join("ALBUM_01").join("item.yml"), &mut plan).is_err());
+    }
+}
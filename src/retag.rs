@@ -0,0 +1,291 @@
+// This module applies batches of field changes listed in a CSV file, e.g. one exported from a spreadsheet:
+//     path,query,field,value
+//     ALBUM_01/TRACK_01.flac,,title,Intro
+//     ,artist == "Various",album_artist,Various Artists
+// Each row sets a field of a single item, or of every item that a query matches, and an empty value removes the field.
+// Values are split into sequences by the split rules for their field, and checked against the schema before anything is written.
+
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::path::{Path, PathBuf};
+
+use metadata::{MetaBlock, MetaValue};
+use metadata::keys::field_names_match_folded;
+use lookup::LookupContext;
+use lookup::query::{Query, run_query};
+use multivalue::SplitRules;
+use schema::{Schema, ViolationKind};
+use writer::plan_edit_item_block;
+use plan::WritePlan;
+use helpers::{normalize, split_csv_line};
+use error::*;
+
+/// The items that a row of changes applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetagTarget {
+    /// The path of an item, which may be relative.
+    Path(PathBuf),
+    Query(String),
+}
+
+/// A single change, read from a row of a CSV file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetagRow {
+    pub line_num: usize,
+    pub target: RetagTarget,
+    pub field_name: String,
+    /// The new value of the field, or `None` to remove it.
+    pub value: Option<String>,
+}
+
+/// The positions of the columns of a changes CSV.
+struct CsvColumns {
+    path: Option<usize>,
+    query: Option<usize>,
+    field: usize,
+    value: usize,
+}
+
+impl CsvColumns {
+    fn from_header(header: &[String]) -> Option<Self> {
+        let find = |name: &str| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+
+        let columns = CsvColumns { path: find("path"), query: find("query"), field: find("field")?, value: find("value")? };
+
+        if columns.path.is_none() && columns.query.is_none() { None } else { Some(columns) }
+    }
+}
+
+/// Reads the rows of a changes CSV, which starts with a header naming its `path` and/or `query`, `field` and `value` columns.
+/// Each row needs either a path or a query, but not both.
+pub fn read_retag_csv(text: &str) -> Result<Vec<RetagRow>> {
+    let mut lines = text.lines().map(|l| l.trim_end_matches('\r')).enumerate().filter(|&(_, line)| !line.trim().is_empty());
+
+    let columns = lines.next()
+        .and_then(|(_, line)| CsvColumns::from_header(&split_csv_line(line)))
+        .ok_or("changes must start with a header naming the path or query, field and value columns")?;
+
+    let mut rows = vec![];
+
+    for (i, line) in lines {
+        let fields = split_csv_line(line);
+        let line_num = i + 1;
+
+        let column = |opt_col: Option<usize>| opt_col.and_then(|col| fields.get(col)).map(|f| f.trim()).filter(|f| !f.is_empty());
+
+        let target = match (column(columns.path), column(columns.query)) {
+            (Some(path), None) => RetagTarget::Path(PathBuf::from(path)),
+            (None, Some(query)) => RetagTarget::Query(query.to_string()),
+            _ => bail!("line {} must have either a path or a query", line_num),
+        };
+
+        let field_name = column(Some(columns.field)).ok_or_else(|| format!("line {} is missing a field", line_num))?.to_string();
+
+        rows.push(RetagRow { line_num, target, field_name, value: column(Some(columns.value)).map(String::from) });
+    }
+
+    Ok(rows)
+}
+
+/// A value that would break the schema once a change is applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetagViolation {
+    pub item_path: PathBuf,
+    pub field_name: String,
+    pub kind: ViolationKind,
+}
+
+impl Display for RetagViolation {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}: {}: {}", self.item_path.to_string_lossy(), self.field_name, self.kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RetagReport {
+    /// The items that were edited, in the order they first appear in the rows, along with how many fields of each were changed.
+    pub edited: Vec<(PathBuf, usize)>,
+    /// The line numbers of rows with a query that matched no items.
+    pub unmatched: Vec<usize>,
+    pub violations: Vec<RetagViolation>,
+}
+
+/// Plans the changes in a batch of rows, with relative paths taken to be relative to a base directory.
+/// All changes to an item are made in a single edit, in row order, so that later rows win.
+/// Paths that are not items of the library are errors, while queries that match nothing are reported.
+/// Only the fields that are changed are checked against the schema; if any of them break it, nothing is added to the plan.
+pub fn plan_retag<P: AsRef<Path>>(
+    lookup_ctx: &mut LookupContext,
+    rows: &[RetagRow],
+    abs_base_dir: P,
+    schema: &Schema,
+    split_rules: &SplitRules,
+    plan: &mut WritePlan,
+) -> Result<RetagReport>
+{
+    let abs_base_dir = abs_base_dir.as_ref();
+    let media_lib = lookup_ctx.media_lib();
+    let root_dir = media_lib.root_dir().to_path_buf();
+
+    let mut report = RetagReport::default();
+    let mut changes_by_item: Vec<(PathBuf, Vec<(String, Option<MetaValue>)>)> = vec![];
+
+    for row in rows {
+        let item_paths = match row.target {
+            RetagTarget::Path(ref path) => {
+                let item_path = normalize(abs_base_dir.join(path));
+
+                // Rule: paths must point to existing items of the library.
+                ensure!(
+                    media_lib.is_proper_sub_path(&item_path) && item_path.exists(),
+                    format!("line {}: not an item of the library: '{}'", row.line_num, path.to_string_lossy())
+                );
+
+                vec![item_path]
+            },
+            RetagTarget::Query(ref query_str) => {
+                let query = Query::parse(query_str).chain_err(|| format!("line {}: invalid query", row.line_num))?;
+                let item_paths: Vec<PathBuf> = run_query(lookup_ctx, &root_dir, &query)?.into_iter().map(|r| r.item_path).collect();
+
+                if item_paths.is_empty() {
+                    report.unmatched.push(row.line_num);
+                }
+
+                item_paths
+            },
+        };
+
+        let opt_mv = row.value.as_ref().map(|v| split_rules.normalize(&row.field_name, &MetaValue::Str(v.clone())));
+
+        for item_path in item_paths {
+            let change = (row.field_name.clone(), opt_mv.clone());
+
+            match changes_by_item.iter().position(|&(ref p, _)| *p == item_path) {
+                Some(i) => changes_by_item[i].1.push(change),
+                None => changes_by_item.push((item_path, vec![change])),
+            }
+        }
+    }
+
+    // Edits are planned on a copy of the plan, which is only kept if every edit passes the schema.
+    let mut retag_plan = plan.clone();
+    let fold_case = media_lib.fold_field_case();
+
+    for (item_path, changes) in changes_by_item {
+        let mut block_violations = vec![];
+
+        plan_edit_item_block(media_lib, &mut retag_plan, &item_path, |mb: &mut MetaBlock| {
+            for &(ref field_name, ref opt_mv) in &changes {
+                match *opt_mv {
+                    Some(ref mv) => { mb.insert(field_name.clone(), mv.clone()); },
+                    None => { mb.remove(field_name); },
+                }
+            }
+
+            block_violations = schema.check_block_with_case(mb, fold_case);
+        })?;
+
+        // Values that were already there are left to lint, so that a batch is not held up by them.
+        for (field_name, kind) in block_violations {
+            let changed = changes.iter().any(|&(ref f, _)| *f == field_name || (fold_case && field_names_match_folded(f, &field_name)));
+
+            if changed {
+                report.violations.push(RetagViolation { item_path: item_path.clone(), field_name, kind });
+            }
+        }
+
+        report.edited.push((item_path, changes.len()));
+    }
+
+    if report.violations.is_empty() {
+        *plan = retag_plan;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use lookup::LookupContext;
+    use metadata::MetaValue;
+    use multivalue::SplitRules;
+    use schema::{Schema, FieldSpec, FieldConstraint, ViolationKind};
+    use plan::WritePlan;
+    use fixtures::default_setup;
+
+    use super::{RetagRow, RetagTarget, read_retag_csv, plan_retag};
+
+    #[test]
+    fn test_read_retag_csv() {
+        let text = "Field,Path,Value,Query\r\ntitle,ALBUM_01/TRACK_01.flac,\"Intro, Part 1\",\n\ncomment,,,\"artist == \"\"A\"\"\"\n";
+
+        let rows = read_retag_csv(text).unwrap();
+        assert_eq!(vec![
+            RetagRow { line_num: 2, target: RetagTarget::Path(PathBuf::from("ALBUM_01/TRACK_01.flac")), field_name: String::from("title"), value: Some(String::from("Intro, Part 1")) },
+            RetagRow { line_num: 4, target: RetagTarget::Query(String::from("artist == \"A\"")), field_name: String::from("comment"), value: None },
+        ], rows);
+
+        let invalid_inputs = vec![
+            "path,field\nA.flac,title\n",
+            "field,value\ntitle,A\n",
+            "path,query,field,value\nA.flac,title == \"A\",title,B\n",
+            "path,query,field,value\n,,title,B\n",
+            "path,field,value\nA.flac,,B\n",
+        ];
+
+        for input in invalid_inputs {
+            assert!(read_retag_csv(input).is_err(), "expected error for: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_plan_retag() {
+        let (temp_media_root, media_lib) = default_setup("test_plan_retag");
+        let tp = temp_media_root.path();
+
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        let text = "path,query,field,value\n\
+            ALBUM_01/DISC_01/TRACK_01.flac,,genre,Rock; Jazz\n\
+            ,TRACK_02_item_key == \"TRACK_02_item_val\",title,Second\n\
+            ALBUM_01/DISC_01/TRACK_01.flac,,genre,Funk\n\
+            ALBUM_01/DISC_01/TRACK_01.flac,,item_key,\n\
+            ,title == \"Nothing\",title,Never\n";
+        let rows = read_retag_csv(text).unwrap();
+
+        let mut schema = Schema::new();
+        schema.field("rating", FieldSpec::new().constraint(FieldConstraint::Range(Some(0.0), Some(5.0))));
+
+        let mut plan = WritePlan::new();
+        let report = plan_retag(&mut LookupContext::new(&media_lib), &rows, tp, &schema, &SplitRules::default(), &mut plan).unwrap();
+        assert_eq!(vec![6], report.unmatched);
+        assert!(report.violations.is_empty());
+        assert!(report.edited.iter().any(|&(ref p, n)| *p == disc_fp.join("TRACK_01.flac") && n == 3));
+        plan.execute().unwrap();
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let origin = |path: PathBuf, field_name: &str| LookupContext::new(&media_lib).lookup_origin(path, field_name).unwrap();
+        assert_eq!(Some(str_val("Funk")), origin(disc_fp.join("TRACK_01.flac"), "genre"));
+        assert_eq!(None, origin(disc_fp.join("TRACK_01.flac"), "item_key"));
+        assert_eq!(Some(str_val("Second")), origin(disc_fp.join("TRACK_02.flac"), "title"));
+
+        // Values are split by the split rules for their field.
+        let rows = read_retag_csv("path,field,value\nALBUM_01/DISC_01/TRACK_03.flac,genre,Rock; Jazz\n").unwrap();
+        let mut plan = WritePlan::new();
+        plan_retag(&mut LookupContext::new(&media_lib), &rows, tp, &schema, &SplitRules::default(), &mut plan).unwrap();
+        plan.execute().unwrap();
+        assert_eq!(Some(MetaValue::Seq(vec![str_val("Rock"), str_val("Jazz")])), origin(disc_fp.join("TRACK_03.flac"), "genre"));
+
+        // A change that breaks the schema leaves the plan as it was.
+        let rows = read_retag_csv("path,field,value\nALBUM_01/DISC_01/TRACK_01.flac,rating,9\nALBUM_01/DISC_01/TRACK_02.flac,title,B\n").unwrap();
+        let mut plan = WritePlan::new();
+        let report = plan_retag(&mut LookupContext::new(&media_lib), &rows, tp, &schema, &SplitRules::default(), &mut plan).unwrap();
+        assert_eq!(1, report.violations.len());
+        assert_eq!(ViolationKind::OutOfRange(String::from("9")), report.violations[0].kind);
+        assert!(plan.is_empty());
+
+        // Paths must be items of the library.
+        let rows = read_retag_csv("path,field,value\nALBUM_01/MISSING.flac,title,A\n").unwrap();
+        assert!(plan_retag(&mut LookupContext::new(&media_lib), &rows, tp, &schema, &SplitRules::default(), &mut WritePlan::new()).is_err());
+    }
+}
@@ -0,0 +1,661 @@
+// This module edits the text of YAML documents in place, so that rewriting a meta file only touches the parts that changed.
+// Comments, blank lines, key order and quoting are kept for everything else, which keeps diffs small for meta files under version control.
+// Only block style mappings and sequences are edited in place; anything else that changed (e.g. a flow style mapping) is rendered again as a whole.
+
+use yaml_rust::{Yaml, YamlLoader, YamlEmitter};
+
+use yaml::yaml_as_text;
+use error::*;
+
+/// How far nested blocks are indented when they are rendered from scratch.
+const INDENT_STEP: usize = 2;
+
+/// Returns true if two YAML documents hold the same data, ignoring the order of keys in mappings.
+pub fn same_data(a: &Yaml, b: &Yaml) -> bool {
+    match (a, b) {
+        (&Yaml::Hash(ref ha), &Yaml::Hash(ref hb)) => {
+            ha.len() == hb.len() && ha.iter().all(|(k, va)| hb.get(k).map_or(false, |vb| same_data(va, vb)))
+        },
+        (&Yaml::Array(ref aa), &Yaml::Array(ref ab)) => {
+            aa.len() == ab.len() && aa.iter().zip(ab).all(|(va, vb)| same_data(va, vb))
+        },
+        _ => a == b,
+    }
+}
+
+/// A value rendered on its own, either on a single line or as lines of a block with no indentation.
+enum Rendered {
+    Inline(String),
+    Block(Vec<String>),
+}
+
+fn render(y: &Yaml) -> Result<Rendered> {
+    let mut buffer = String::new();
+    YamlEmitter::new(&mut buffer).dump(y).map_err(|e| format!("unable to emit YAML: {:?}", e))?;
+
+    // The emitter always starts with a document start marker on its own line.
+    let body = buffer.trim_start_matches("---\n");
+
+    match *y {
+        Yaml::Hash(ref h) if !h.is_empty() => Ok(Rendered::Block(body.lines().map(String::from).collect())),
+        Yaml::Array(ref a) if !a.is_empty() => Ok(Rendered::Block(body.lines().map(String::from).collect())),
+        _ => Ok(Rendered::Inline(body.to_string())),
+    }
+}
+
+fn indented(indent: usize, s: &str) -> String {
+    format!("{}{}", " ".repeat(indent), s)
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+/// Returns true for lines that hold no data, i.e. blank lines and comments.
+fn is_filler(line: &str) -> bool {
+    let trimmed = line.trim();
+
+    trimmed.is_empty() || trimmed.starts_with('#')
+}
+
+/// Returns true if a line (after its indentation) starts a sequence entry.
+fn is_dash(content: &str) -> bool {
+    content == "-" || content.starts_with("- ")
+}
+
+/// Finds where a trailing comment starts in a line, including the whitespace before it.
+fn comment_start(line: &str) -> usize {
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut prev: Option<char> = None;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '\'' if !in_double => { in_single = !in_single; },
+            '"' if !in_single && prev != Some('\\') => { in_double = !in_double; },
+            '#' if !in_single && !in_double && prev.map_or(true, char::is_whitespace) => {
+                return line[..i].trim_end().len();
+            },
+            _ => {},
+        }
+
+        prev = Some(c);
+    }
+
+    line.trim_end().len()
+}
+
+/// Finds the colon that ends the key of a block mapping entry, given the line after its indentation.
+/// Returns `None` for anything that is not a simple key, e.g. complex keys or flow collections.
+fn key_colon(content: &str) -> Option<usize> {
+    let is_colon_at = |i: usize| content[i..].starts_with(':') && content[i + 1..].chars().next().map_or(true, |c| c == ' ');
+
+    let first = content.chars().next()?;
+
+    let key_end = match first {
+        '"' | '\'' => {
+            let mut escaped = false;
+            let mut close = None;
+            let mut chars = content.char_indices().skip(1).peekable();
+
+            while let Some((i, c)) = chars.next() {
+                if first == '"' && c == '\\' && !escaped {
+                    escaped = true;
+                    continue;
+                }
+
+                if c == first && !escaped {
+                    // Single quotes are escaped by doubling them.
+                    if first == '\'' && chars.peek().map(|&(_, n)| n) == Some('\'') {
+                        chars.next();
+                        continue;
+                    }
+
+                    close = Some(i + 1);
+                    break;
+                }
+
+                escaped = false;
+            }
+
+            close?
+        },
+        '-' if is_dash(content) => return None,
+        '?' | '{' | '[' | '&' | '*' | '!' | '|' | '>' | '%' | '@' | '`' | '#' => return None,
+        _ => {
+            let i = (0..content.len()).filter(|&i| content.is_char_boundary(i)).find(|&i| is_colon_at(i) || content[i..].starts_with(" #"))?;
+            return if is_colon_at(i) { Some(i) } else { None };
+        },
+    };
+
+    let colon = key_end + (content.len() - key_end - content[key_end..].trim_start_matches(' ').len());
+
+    if is_colon_at(colon) { Some(colon) } else { None }
+}
+
+/// Where a key of a block mapping is in YAML text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySpan {
+    /// The line of the key, counting from 0.
+    pub line: usize,
+    /// The byte offsets of the key in its line, including any quotes.
+    pub start: usize,
+    pub end: usize,
+    pub key: Yaml,
+    /// The value text on the same line as the key, without any trailing comment.
+    pub value_text: String,
+}
+
+/// Finds the keys of block mappings in YAML text, at any depth, in text order.
+/// This also finds keys of mappings that start on the same line as a sequence dash.
+pub fn find_keys(text: &str) -> Vec<KeySpan> {
+    let mut spans = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        if is_filler(line) {
+            continue;
+        }
+
+        let mut start = indent_of(line);
+
+        while is_dash(&line[start..]) {
+            let after_dash = &line[start + 1..];
+            start += 1 + (after_dash.len() - after_dash.trim_start_matches(' ').len());
+        }
+
+        let content = &line[start..];
+
+        let colon = match key_colon(content) {
+            Some(colon) => colon,
+            None => continue,
+        };
+
+        let key_text = content[..colon].trim_end();
+
+        let key = match YamlLoader::load_from_str(key_text).ok().and_then(|docs| docs.into_iter().next()) {
+            Some(key) => key,
+            None => Yaml::Null,
+        };
+
+        let value_start = start + colon + 1;
+        let value_end = comment_start(line).max(value_start);
+
+        spans.push(KeySpan {
+            line: i,
+            start,
+            end: start + key_text.len(),
+            key,
+            value_text: line[value_start..value_end].trim().to_string(),
+        });
+    }
+
+    spans
+}
+
+/// A single entry of a block mapping or sequence, as found in the text.
+struct Entry {
+    /// The key, for mapping entries.
+    key: Option<Yaml>,
+    /// The line that the entry starts on.
+    line: usize,
+    /// One past the last line holding data for the entry.
+    end: usize,
+    /// Where the value starts on the first line, after the key or dash.
+    value_col: usize,
+}
+
+impl Entry {
+    /// The value text on the first line of the entry, without any trailing comment.
+    fn inline_value<'l>(&self, lines: &'l [String]) -> &'l str {
+        let line = &lines[self.line];
+        let end = comment_start(line).max(self.value_col);
+
+        &line[self.value_col..end]
+    }
+}
+
+/// Splits the lines of a block mapping or sequence with a given indentation into entries.
+/// Returns `None` if the lines are not laid out as expected.
+fn find_entries(lines: &[String], start: usize, end: usize, indent: usize, is_seq: bool) -> Option<Vec<Entry>> {
+    let mut entries: Vec<Entry> = vec![];
+
+    for i in start..end {
+        let line = &lines[i];
+
+        if is_filler(line) {
+            continue;
+        }
+
+        let line_indent = indent_of(line);
+        let content = &line[line_indent..];
+
+        if line_indent > indent {
+            entries.last_mut()?.end = i + 1;
+            continue;
+        }
+
+        if line_indent < indent {
+            return None;
+        }
+
+        if is_seq {
+            if !is_dash(content) {
+                return None;
+            }
+
+            let after_dash = &content[1..];
+            let value_col = line_indent + 1 + (after_dash.len() - after_dash.trim_start_matches(' ').len());
+
+            entries.push(Entry { key: None, line: i, end: i + 1, value_col });
+        }
+        else {
+            // Sequences may sit at the same indentation as the key that holds them.
+            if is_dash(content) {
+                match entries.last_mut() {
+                    Some(ref mut entry) if entry.inline_value(lines).trim().is_empty() => {
+                        entry.end = i + 1;
+                        continue;
+                    },
+                    _ => return None,
+                }
+            }
+
+            let colon = key_colon(content)?;
+            let key = YamlLoader::load_from_str(content[..colon].trim_end()).ok()?.into_iter().next().unwrap_or(Yaml::Null);
+            let after_colon = &content[colon + 1..];
+            let value_col = line_indent + colon + 1 + (after_colon.len() - after_colon.trim_start_matches(' ').len());
+
+            entries.push(Entry { key: Some(key), line: i, end: i + 1, value_col });
+        }
+    }
+
+    Some(entries)
+}
+
+/// Finds the indentation of the first line holding data in a range.
+fn first_indent(lines: &[String], start: usize, end: usize) -> Option<usize> {
+    (start..end).map(|i| &lines[i]).find(|l| !is_filler(l)).map(|l| indent_of(l))
+}
+
+/// Renders a scalar to replace an inline value, keeping the quoting style of the old value where that is safe.
+fn render_scalar(y: &Yaml, old_value: &str) -> Result<Option<String>> {
+    if let Yaml::String(ref s) = *y {
+        let is_simple = !s.contains(|c: char| c.is_control());
+
+        if old_value.starts_with('\'') && is_simple {
+            return Ok(Some(format!("'{}'", s.replace('\'', "''"))));
+        }
+
+        if old_value.starts_with('"') && is_simple {
+            return Ok(Some(format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))));
+        }
+    }
+
+    match render(y)? {
+        Rendered::Inline(s) => Ok(Some(s)),
+        Rendered::Block(_) => Ok(None),
+    }
+}
+
+/// Renders a mapping entry from scratch.
+fn render_map_entry(key_text: &str, y: &Yaml, indent: usize) -> Result<Vec<String>> {
+    match render(y)? {
+        Rendered::Inline(s) => Ok(vec![indented(indent, &format!("{}: {}", key_text, s))]),
+        Rendered::Block(block_lines) => {
+            let mut rendered = vec![indented(indent, &format!("{}:", key_text))];
+            rendered.extend(block_lines.iter().map(|l| indented(indent + INDENT_STEP, l)));
+            Ok(rendered)
+        },
+    }
+}
+
+/// Renders a sequence entry from scratch.
+fn render_seq_entry(y: &Yaml, indent: usize) -> Result<Vec<String>> {
+    match render(y)? {
+        Rendered::Inline(s) => Ok(vec![indented(indent, &format!("- {}", s))]),
+        Rendered::Block(block_lines) => {
+            Ok(block_lines.iter().enumerate().map(|(i, l)| {
+                if i == 0 { indented(indent, &format!("- {}", l)) } else { indented(indent + INDENT_STEP, l) }
+            }).collect())
+        },
+    }
+}
+
+/// Edits the value of a single entry in place, returning the new lines for the entry.
+/// Returns `None` if the entry needs to be rendered from scratch instead.
+fn patch_entry(lines: &[String], entry: &Entry, old: &Yaml, new: &Yaml) -> Result<Option<Vec<String>>> {
+    let old_value = entry.inline_value(lines).trim();
+    let is_collection = |y: &Yaml| match *y { Yaml::Hash(_) | Yaml::Array(_) => true, _ => false };
+
+    // Scalars on a single line are replaced, keeping any trailing comment.
+    if !old_value.is_empty() && entry.end == entry.line + 1 && !is_collection(old) && !is_collection(new) {
+        let line = &lines[entry.line];
+
+        return Ok(render_scalar(new, old_value)?.map(|s| {
+            let comment = &line[comment_start(line).max(entry.value_col)..];
+            vec![format!("{}{}{}", &line[..entry.value_col], s, comment)]
+        }));
+    }
+
+    // Block collections on the following lines are edited recursively.
+    if old_value.is_empty() && entry.end > entry.line + 1 {
+        let indent = match first_indent(lines, entry.line + 1, entry.end) {
+            Some(indent) => indent,
+            None => return Ok(None),
+        };
+
+        if let Some(patched) = patch_node(lines, entry.line + 1, entry.end, indent, old, new)? {
+            let mut result = vec![lines[entry.line].clone()];
+            result.extend(patched);
+            return Ok(Some(result));
+        }
+
+        return Ok(None);
+    }
+
+    // A mapping that starts on the same line as a sequence dash is edited as if the dash were indentation.
+    if entry.key.is_none() && !old_value.is_empty() {
+        if let (&Yaml::Hash(_), &Yaml::Hash(_)) = (old, new) {
+            let first_line = &lines[entry.line];
+            let dash_prefix = &first_line[..entry.value_col];
+
+            let mut shifted: Vec<String> = vec![indented(entry.value_col, &first_line[entry.value_col..])];
+            shifted.extend(lines[entry.line + 1..entry.end].iter().cloned());
+
+            let patched = match patch_node(&shifted, 0, shifted.len(), entry.value_col, old, new)? {
+                Some(patched) => patched,
+                None => return Ok(None),
+            };
+
+            return Ok(Some(match patched.split_first() {
+                Some((first, rest)) if indent_of(first) >= entry.value_col => {
+                    let mut result = vec![format!("{}{}", dash_prefix, &first[entry.value_col..])];
+                    result.extend(rest.iter().cloned());
+                    result
+                },
+                Some(_) => {
+                    let mut result = vec![dash_prefix.trim_end().to_string()];
+                    result.extend(patched);
+                    result
+                },
+                None => vec![format!("{}{{}}", dash_prefix)],
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Edits the lines of a block mapping, returning the new lines for the range.
+fn patch_map(lines: &[String], start: usize, end: usize, indent: usize, old: &Yaml, new: &Yaml) -> Result<Option<Vec<String>>> {
+    let (old_hash, new_hash) = match (old, new) {
+        (&Yaml::Hash(ref o), &Yaml::Hash(ref n)) => (o, n),
+        _ => return Ok(None),
+    };
+
+    let entries = match find_entries(lines, start, end, indent, false) {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+
+    if entries.len() != old_hash.len() || entries.is_empty() {
+        return Ok(None);
+    }
+
+    let mut result = vec![];
+    let mut cursor = start;
+
+    for entry in &entries {
+        let key = entry.key.as_ref().expect("mapping entries have keys");
+        let old_value = match old_hash.get(key) {
+            Some(old_value) => old_value,
+            None => return Ok(None),
+        };
+
+        result.extend(lines[cursor..entry.line].iter().cloned());
+        cursor = entry.end;
+
+        let new_value = match new_hash.get(key) {
+            Some(new_value) => new_value,
+            // Removed entries are dropped, along with the lines of their values.
+            None => continue,
+        };
+
+        if same_data(old_value, new_value) {
+            result.extend(lines[entry.line..entry.end].iter().cloned());
+            continue;
+        }
+
+        match patch_entry(lines, entry, old_value, new_value)? {
+            Some(patched) => result.extend(patched),
+            None => {
+                let line = &lines[entry.line];
+                let content = &line[indent..];
+                let key_text = content[..key_colon(content).expect("entry lines have keys")].trim_end();
+
+                result.extend(render_map_entry(key_text, new_value, indent)?);
+            },
+        }
+    }
+
+    // New entries go after the last existing one, before any trailing comments.
+    for (key, new_value) in new_hash.iter().filter(|&(k, _)| !old_hash.contains_key(k)) {
+        let key_text = match render(key)? {
+            Rendered::Inline(s) => s,
+            Rendered::Block(_) => return Ok(None),
+        };
+
+        result.extend(render_map_entry(&key_text, new_value, indent)?);
+    }
+
+    result.extend(lines[cursor..end].iter().cloned());
+
+    Ok(Some(result))
+}
+
+/// Edits the lines of a block sequence, returning the new lines for the range.
+/// Entries are compared by position, so entries that were added or removed in the middle change the entries after them.
+fn patch_seq(lines: &[String], start: usize, end: usize, indent: usize, old: &Yaml, new: &Yaml) -> Result<Option<Vec<String>>> {
+    let (old_arr, new_arr) = match (old, new) {
+        (&Yaml::Array(ref o), &Yaml::Array(ref n)) => (o, n),
+        _ => return Ok(None),
+    };
+
+    let entries = match find_entries(lines, start, end, indent, true) {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+
+    if entries.len() != old_arr.len() || entries.is_empty() {
+        return Ok(None);
+    }
+
+    let mut result = vec![];
+    let mut cursor = start;
+
+    for ((entry, old_value), opt_new_value) in entries.iter().zip(old_arr).zip(new_arr.iter().map(Some).chain(::std::iter::repeat(None))) {
+        result.extend(lines[cursor..entry.line].iter().cloned());
+        cursor = entry.end;
+
+        let new_value = match opt_new_value {
+            Some(new_value) => new_value,
+            None => continue,
+        };
+
+        if same_data(old_value, new_value) {
+            result.extend(lines[entry.line..entry.end].iter().cloned());
+            continue;
+        }
+
+        match patch_entry(lines, entry, old_value, new_value)? {
+            Some(patched) => result.extend(patched),
+            None => result.extend(render_seq_entry(new_value, indent)?),
+        }
+    }
+
+    for new_value in new_arr.iter().skip(old_arr.len()) {
+        result.extend(render_seq_entry(new_value, indent)?);
+    }
+
+    result.extend(lines[cursor..end].iter().cloned());
+
+    Ok(Some(result))
+}
+
+fn patch_node(lines: &[String], start: usize, end: usize, indent: usize, old: &Yaml, new: &Yaml) -> Result<Option<Vec<String>>> {
+    match (old, new) {
+        (&Yaml::Hash(_), &Yaml::Hash(_)) => patch_map(lines, start, end, indent, old, new),
+        (&Yaml::Array(_), &Yaml::Array(_)) => patch_seq(lines, start, end, indent, old, new),
+        _ => Ok(None),
+    }
+}
+
+/// Finds the lines of the first document in YAML text, skipping an explicit document start marker.
+fn document_range(lines: &[String]) -> (usize, usize) {
+    let is_marker = |l: &String| l.trim_end() == "---" || l.trim_end() == "...";
+
+    let start = match lines.iter().position(|l| !is_filler(l)) {
+        Some(i) if lines[i].trim_end() == "---" => i + 1,
+        _ => 0,
+    };
+
+    let end = lines[start..].iter().position(is_marker).map_or(lines.len(), |i| start + i);
+
+    (start, end)
+}
+
+/// Rewrites YAML text so that it holds a new document, keeping the text of everything that did not change.
+/// Text that cannot be edited in place, or that does not hold valid YAML, is replaced by the new document rendered from scratch.
+pub fn patch_yaml_text(old_text: &str, new_doc: &Yaml) -> Result<String> {
+    let old_doc = match YamlLoader::load_from_str(old_text).ok().and_then(|docs| docs.into_iter().next()) {
+        Some(old_doc) => old_doc,
+        None => return yaml_as_text(new_doc),
+    };
+
+    if same_data(&old_doc, new_doc) {
+        return Ok(old_text.to_string());
+    }
+
+    let lines: Vec<String> = old_text.lines().map(String::from).collect();
+    let (start, end) = document_range(&lines);
+
+    let opt_patched = match first_indent(&lines, start, end) {
+        Some(indent) => patch_node(&lines, start, end, indent, &old_doc, new_doc)?,
+        None => None,
+    };
+
+    if let Some(patched) = opt_patched {
+        let mut new_lines: Vec<String> = lines[..start].to_vec();
+        new_lines.extend(patched);
+        new_lines.extend(lines[end..].iter().cloned());
+
+        let mut new_text = new_lines.join("\n");
+        new_text.push('\n');
+
+        // Only keep the edit if it reads back as the new document.
+        let reread = YamlLoader::load_from_str(&new_text).ok().and_then(|docs| docs.into_iter().next());
+
+        if reread.map_or(false, |y| same_data(&y, new_doc)) {
+            return Ok(new_text);
+        }
+
+        warn!("unable to edit YAML in place, rendering it from scratch");
+    }
+
+    yaml_as_text(new_doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::{Yaml, YamlLoader};
+
+    use super::{patch_yaml_text, same_data, key_colon, comment_start, find_keys};
+
+    fn load(text: &str) -> Yaml {
+        YamlLoader::load_from_str(text).unwrap().into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn test_key_colon() {
+        assert_eq!(Some(5), key_colon("title: A"));
+        assert_eq!(Some(5), key_colon("title:"));
+        assert_eq!(Some(8), key_colon("\"a: b\"  : A"));
+        assert_eq!(Some(7), key_colon("'it''s': A"));
+        assert_eq!(Some(1), key_colon("~: A"));
+        assert_eq!(None, key_colon("- title: A"));
+        assert_eq!(None, key_colon("{title: A}"));
+        assert_eq!(None, key_colon("url:http"));
+
+        assert_eq!(8, comment_start("title: A # note"));
+        assert_eq!(13, comment_start("title: 'A #1'"));
+    }
+
+    #[test]
+    fn test_find_keys() {
+        let text = "# Album\ntitle: A  # note\n- artist: 'B'\n  '~': x\n- - nested: y\nnot a key\n";
+
+        let produced: Vec<_> = find_keys(text).into_iter().map(|s| (s.line, s.start, s.end, s.key, s.value_text)).collect();
+        let expected = vec![
+            (1, 0, 5, Yaml::String(String::from("title")), String::from("A")),
+            (2, 2, 8, Yaml::String(String::from("artist")), String::from("'B'")),
+            (3, 2, 5, Yaml::String(String::from("~")), String::from("x")),
+            (4, 4, 10, Yaml::String(String::from("nested")), String::from("y")),
+        ];
+        assert_eq!(expected, produced);
+    }
+
+    #[test]
+    fn test_patch_yaml_text() {
+        // Each case is the old text, the new data, and the expected new text.
+        let inputs_and_expected = vec![
+            // Values are replaced in place, keeping comments, key order and quoting.
+            (
+                "# Album\ntitle: Old  # note\nartist: 'A'\n",
+                "artist: A\ntitle: New",
+                "# Album\ntitle: New  # note\nartist: 'A'\n",
+            ),
+            (
+                "title: 'Old'\n",
+                "title: \"It's new\"",
+                "title: 'It''s new'\n",
+            ),
+            // Fields are added after the last field of their block, and removed along with their values.
+            (
+                "- title: A\n  # keep me\n- title: B\n  artists:\n    - X\n    - Y\n",
+                "- title: A\n- title: B\n  genre: Rock",
+                "- title: A\n  # keep me\n- title: B\n  genre: Rock\n",
+            ),
+            // Blocks are added at the end of sequences.
+            (
+                "---\n- title: A\n",
+                "- title: A\n- title: B",
+                "---\n- title: A\n- title: B\n",
+            ),
+            // Nested blocks are edited recursively.
+            (
+                "DISC_01:\n  # first disc\n  __items:\n  - title: One\n  - title: Two\n",
+                "DISC_01:\n  __items:\n  - title: One\n  - title: Deux",
+                "DISC_01:\n  # first disc\n  __items:\n  - title: One\n  - title: Deux\n",
+            ),
+            // Flow style is rendered again as a whole.
+            (
+                "artists: [A, B]  # both\ntitle: T\n",
+                "artists: [A, C]\ntitle: T",
+                "artists:\n  - A\n  - C\ntitle: T\n",
+            ),
+        ];
+
+        for (old_text, new_data, expected) in inputs_and_expected {
+            let produced = patch_yaml_text(old_text, &load(new_data)).unwrap();
+            assert_eq!(expected, produced);
+            assert!(same_data(&load(new_data), &load(&produced)));
+        }
+
+        // Unchanged data keeps the text exactly.
+        assert_eq!("title:   A\n", patch_yaml_text("title:   A\n", &load("title: A")).unwrap());
+
+        // Text that does not fit the new data at all is rendered from scratch.
+        assert_eq!("---\n- title: A\n", patch_yaml_text("title: A\n", &load("- title: A")).unwrap());
+        assert_eq!("---\ntitle: A\n", patch_yaml_text("[unclosed", &load("title: A")).unwrap());
+    }
+}
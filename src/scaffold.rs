@@ -0,0 +1,181 @@
+// Generates skeleton meta files for items that do not have any metadata yet.
+
+use std::path::{Path, PathBuf};
+
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
+
+use library::{Library, is_meta_file_pattern};
+use metadata::MetaTarget;
+use schema::Schema;
+use plan::WritePlan;
+use helpers::normalize;
+use error::*;
+
+/// Creates a block with every required field of the schema set to null, ready to be filled in.
+fn skeleton_block(schema: &Schema) -> Yaml {
+    let mut hsh = Hash::new();
+
+    for field_name in schema.required_fields() {
+        hsh.insert(Yaml::String(field_name.to_string()), Yaml::Null);
+    }
+
+    Yaml::Hash(hsh)
+}
+
+fn skeleton_yaml(media_lib: &Library, schema: &Schema, abs_dir_path: &Path, meta_target: &MetaTarget) -> Result<Option<Yaml>> {
+    let yaml = match *meta_target {
+        MetaTarget::Contains => skeleton_block(schema),
+        // Sidecars are created one per item, when an item is first edited.
+        MetaTarget::Sidecar { .. } => return Ok(None),
+        MetaTarget::Siblings => {
+            let children = media_lib.children_paths(abs_dir_path)?;
+
+            if children.is_empty() {
+                return Ok(None);
+            }
+
+            // Use a mapping instead of a sequence, so that the file stays valid if items get renamed or re-sorted.
+            let mut hsh = Hash::new();
+
+            for child_path in children {
+                if let Some(name) = child_path.file_name().and_then(|s| s.to_str()) {
+                    hsh.insert(Yaml::String(name.to_string()), skeleton_block(schema));
+                }
+            }
+
+            Yaml::Hash(hsh)
+        },
+    };
+
+    Ok(Some(yaml))
+}
+
+/// Walks a directory and its selected subdirectories, planning the creation of any meta files that do not already exist.
+/// Existing meta files are never modified.
+/// Returns the paths of the meta files to be created, in walk order.
+pub fn scaffold_dir<P: AsRef<Path>>(media_lib: &Library, schema: &Schema, abs_dir_path: P, plan: &mut WritePlan) -> Result<Vec<PathBuf>> {
+    let abs_dir_path = normalize(abs_dir_path.as_ref());
+
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    // Rule: dir path must be in the library.
+    ensure!(abs_dir_path.starts_with(media_lib.root_dir()), ErrorKind::InvalidSubPath(abs_dir_path.clone(), media_lib.root_dir().to_path_buf()));
+
+    // Rule: dir path must be a directory.
+    ensure!(abs_dir_path.is_dir(), ErrorKind::NotADirectory(abs_dir_path.clone()));
+
+    let mut created = vec![];
+
+    scaffold_dir_helper(media_lib, schema, &abs_dir_path, plan, &mut created)?;
+
+    Ok(created)
+}
+
+fn scaffold_dir_helper(media_lib: &Library, schema: &Schema, abs_dir_path: &Path, plan: &mut WritePlan, created: &mut Vec<PathBuf>) -> Result<()> {
+    // Directories that only lead into the scope of a scoped library are walked through, but left alone.
+    let meta_target_specs = if media_lib.is_in_scope(abs_dir_path) { media_lib.meta_target_specs() } else { &[] };
+
+    for &(ref meta_fn, ref meta_target) in meta_target_specs {
+        // Patterns do not name a single file to create.
+        if is_meta_file_pattern(meta_fn) {
+            continue;
+        }
+
+        let meta_fp = abs_dir_path.join(meta_fn);
+
+        if meta_fp.exists() || plan.file_exists(&meta_fp) {
+            continue;
+        }
+
+        if let Some(yaml) = skeleton_yaml(media_lib, schema, abs_dir_path, meta_target)? {
+            plan.write_yaml(meta_fp.clone(), yaml);
+
+            created.push(meta_fp);
+        }
+    }
+
+    for child_path in media_lib.walk_children_paths(abs_dir_path)? {
+        if child_path.is_dir() {
+            scaffold_dir_helper(media_lib, schema, &child_path, plan, created)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{DirBuilder, File};
+
+    use tempdir::TempDir;
+
+    use library::LibraryBuilder;
+    use library::selection::Selection;
+    use lookup::LookupContext;
+    use metadata::{MetaTarget, MetaValue};
+    use schema::{Schema, FieldSpec};
+    use plan::WritePlan;
+
+    use super::scaffold_dir;
+
+    #[test]
+    fn test_scaffold_dir() {
+        let temp = TempDir::new("test_scaffold_dir").unwrap();
+        let tp = temp.path();
+
+        let db = DirBuilder::new();
+        db.create(tp.join("ALBUM")).unwrap();
+        File::create(tp.join("ALBUM").join("TRACK_01.flac")).unwrap();
+        File::create(tp.join("ALBUM").join("TRACK_02.flac")).unwrap();
+        db.create(tp.join("EMPTY")).unwrap();
+
+        // An already-tagged directory should be left alone.
+        File::create(tp.join("self.yml")).unwrap();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let selection = Selection::Or(
+            Box::new(Selection::Ext(String::from("flac"))),
+            Box::new(Selection::IsDir),
+        );
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs).selection(selection).create().expect("Unable to create media library");
+
+        let mut schema = Schema::new();
+        schema
+            .field("title", FieldSpec::new().required(true))
+            .field("comment", FieldSpec::new());
+
+        let expected = vec![
+            tp.join("item.yml"),
+            tp.join("ALBUM").join("self.yml"),
+            tp.join("ALBUM").join("item.yml"),
+            tp.join("EMPTY").join("self.yml"),
+        ];
+        let mut plan = WritePlan::new();
+        let produced = scaffold_dir(&media_lib, &schema, tp, &mut plan).expect("Unable to scaffold");
+        assert_eq!(expected, produced);
+        assert!(!tp.join("item.yml").exists());
+
+        assert_eq!(expected, plan.execute().expect("Unable to execute plan"));
+
+        // Pre-existing files are untouched.
+        assert_eq!(0, tp.join("self.yml").metadata().unwrap().len());
+
+        // Generated files are readable, and provide empty values for required fields.
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        let track_fp = tp.join("ALBUM").join("TRACK_02.flac");
+        assert_eq!(Some(MetaValue::Nil), lookup_ctx.lookup_origin(&track_fp, "title").expect("Unable to look up field"));
+        assert_eq!(None, lookup_ctx.lookup_origin(&track_fp, "comment").expect("Unable to look up field"));
+
+        // Running again creates nothing new.
+        let mut plan = WritePlan::new();
+        assert!(scaffold_dir(&media_lib, &schema, tp, &mut plan).expect("Unable to scaffold").is_empty());
+        assert!(plan.is_empty());
+
+        assert!(scaffold_dir(&media_lib, &schema, tp.join("ALBUM").join("TRACK_01.flac"), &mut plan).is_err());
+    }
+}
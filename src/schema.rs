@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use yaml_rust::Yaml;
+
+use metadata::{MetaBlock, MetaValue};
+use metadata::keys::{get_field, RATING_KEY, RATING_MAX, PLAY_COUNT_KEY, LAST_PLAYED_KEY};
+use ontology::{Ontology, fold};
+use yaml::read_yaml_file;
+use error::*;
+
+/// A restriction on the values that a field may take.
+/// Constraints are checked against each string in a field value; sequences are checked element-wise.
+#[derive(Debug, Clone)]
+pub enum FieldConstraint {
+    /// Value must be one of a controlled vocabulary, e.g. a list of genres.
+    OneOf(Vec<String>),
+    /// Value must fully match a regex pattern.
+    Pattern(Regex),
+    /// Value must be numeric, and within the (inclusive) bounds, if any.
+    Range(Option<f64>, Option<f64>),
+    /// Value must be one of the terms of an ontology, without regard to case.
+    KnownTerm(BTreeSet<String>),
+}
+
+impl FieldConstraint {
+    fn check_str(&self, s: &str) -> Option<ViolationKind> {
+        match *self {
+            FieldConstraint::OneOf(ref allowed) => {
+                if allowed.iter().any(|a| a == s) { None }
+                else { Some(ViolationKind::NotAllowed(s.to_string())) }
+            },
+            FieldConstraint::Pattern(ref r_exp) => {
+                let full_match = r_exp.find(s).map_or(false, |m| m.start() == 0 && m.end() == s.len());
+
+                if full_match { None }
+                else { Some(ViolationKind::PatternMismatch(s.to_string())) }
+            },
+            FieldConstraint::Range(opt_min, opt_max) => {
+                match s.trim().parse::<f64>() {
+                    Err(_) => Some(ViolationKind::NotNumeric(s.to_string())),
+                    Ok(n) => {
+                        let too_low = opt_min.map_or(false, |min| n < min);
+                        let too_high = opt_max.map_or(false, |max| n > max);
+
+                        if too_low || too_high { Some(ViolationKind::OutOfRange(s.to_string())) }
+                        else { None }
+                    },
+                }
+            },
+            FieldConstraint::KnownTerm(ref terms) => {
+                if terms.contains(&fold(s)) { None }
+                else { Some(ViolationKind::UnknownTerm(s.to_string())) }
+            },
+        }
+    }
+
+    fn check_value(&self, mv: &MetaValue, violations: &mut Vec<ViolationKind>) {
+        match *mv {
+            // Nil means the value is explicitly absent, so there is nothing to constrain.
+            MetaValue::Nil => {},
+            MetaValue::Str(ref s) => { violations.extend(self.check_str(s)); },
+            MetaValue::Seq(ref mvs) => {
+                for mv in mvs {
+                    self.check_value(mv, violations);
+                }
+            },
+            MetaValue::Map(_) => { violations.push(ViolationKind::UnexpectedMapping); },
+        }
+    }
+}
+
+/// The expectations for a single field.
+#[derive(Debug, Clone, Default)]
+pub struct FieldSpec {
+    pub required: bool,
+    /// Whether the field belongs only to the item it is set on, and is never inherited from ancestors.
+    pub local: bool,
+    pub constraints: Vec<FieldConstraint>,
+}
+
+impl FieldSpec {
+    pub fn new() -> Self {
+        FieldSpec::default()
+    }
+
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    pub fn local(mut self, local: bool) -> Self {
+        self.local = local;
+        self
+    }
+
+    pub fn constraint(mut self, constraint: FieldConstraint) -> Self {
+        self.constraints.push(constraint);
+        self
+    }
+}
+
+/// Describes the fields that items in a library are expected to have.
+/// Fields not mentioned in the schema are left unchecked.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    fields: BTreeMap<String, FieldSpec>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Schema::default()
+    }
+
+    pub fn field<S: Into<String>>(&mut self, field_name: S, field_spec: FieldSpec) -> &mut Self {
+        self.fields.insert(field_name.into(), field_spec);
+        self
+    }
+
+    /// The specs of the fields that taggu itself knows about, i.e. ratings and listening stats.
+    /// Both are local, since an item does not share the listening stats of its album.
+    pub fn builtin() -> Self {
+        let mut schema = Schema::new();
+        schema
+            .field(RATING_KEY, FieldSpec::new().local(true).constraint(FieldConstraint::Range(Some(0.0), Some(f64::from(RATING_MAX)))))
+            .field(PLAY_COUNT_KEY, FieldSpec::new().local(true).constraint(FieldConstraint::Pattern(Regex::new(r"\d+").unwrap())))
+            .field(LAST_PLAYED_KEY, FieldSpec::new().local(true).constraint(FieldConstraint::Pattern(Regex::new(r"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z").unwrap())))
+        ;
+        schema
+    }
+
+    /// Adds the field specs of another schema, replacing any specs for the same fields.
+    pub fn extend(&mut self, other: Schema) -> &mut Self {
+        self.fields.extend(other.fields);
+        self
+    }
+
+    /// Adds a constraint to a field, on top of any it has already.
+    pub fn constrain<S: Into<String>>(&mut self, field_name: S, constraint: FieldConstraint) -> &mut Self {
+        self.fields.entry(field_name.into()).or_insert_with(FieldSpec::new).constraints.push(constraint);
+        self
+    }
+
+    /// Constrains each field that has a hierarchy in an ontology to the terms of that hierarchy, so that unknown terms (e.g. misspelled genres) are flagged.
+    pub fn constrain_to_ontology(&mut self, ontology: &Ontology) -> &mut Self {
+        for field_name in ontology.fields() {
+            let terms = ontology.terms(field_name).into_iter().map(String::from).collect();
+            self.constrain(field_name, FieldConstraint::KnownTerm(terms));
+        }
+
+        self
+    }
+
+    /// Reads a schema from a YAML mapping of field names to field specs, for example:
+    ///
+    /// ```yaml
+    /// title:
+    ///   local: true
+    /// genre:
+    ///   required: true
+    ///   one_of: [Rock, Jazz]
+    /// date:
+    ///   pattern: '\d{4}(-\d{2}-\d{2})?'
+    /// track_num:
+    ///   min: 1
+    ///   max: 99
+    /// ```
+    pub fn from_yaml(y: &Yaml) -> Result<Self> {
+        let hsh = match *y {
+            Yaml::Hash(ref hsh) => hsh,
+            Yaml::Null => return Ok(Schema::new()),
+            _ => bail!("schema must be a mapping of field names to field specs"),
+        };
+
+        let mut schema = Schema::new();
+
+        for (field_name_y, field_spec_y) in hsh {
+            let field_name = field_name_y.as_str().ok_or("schema field names must be strings")?;
+            let field_spec = yaml_as_field_spec(field_spec_y).chain_err(|| format!("invalid spec for field '{}'", field_name))?;
+
+            schema.field(field_name, field_spec);
+        }
+
+        Ok(schema)
+    }
+
+    pub fn fields(&self) -> &BTreeMap<String, FieldSpec> {
+        &self.fields
+    }
+
+    /// Returns the names of the required fields, in sorted order.
+    pub fn required_fields(&self) -> Vec<&str> {
+        self.fields.iter().filter(|&(_, fs)| fs.required).map(|(f, _)| f.as_str()).collect()
+    }
+
+    /// Returns the names of the local fields, in sorted order.
+    pub fn local_fields(&self) -> Vec<&str> {
+        self.fields.iter().filter(|&(_, fs)| fs.local).map(|(f, _)| f.as_str()).collect()
+    }
+
+    /// Checks the values in a single meta block against field constraints.
+    /// Required fields are not checked here, since they may be provided by another meta file.
+    pub fn check_block(&self, mb: &MetaBlock) -> Vec<(String, ViolationKind)> {
+        self.check_block_with_case(mb, false)
+    }
+
+    /// Checks the values in a single meta block against field constraints, optionally ignoring the case of field names.
+    pub fn check_block_with_case(&self, mb: &MetaBlock, fold_case: bool) -> Vec<(String, ViolationKind)> {
+        let mut results = vec![];
+
+        for (field_name, field_spec) in &self.fields {
+            if let Some(mv) = get_field(mb, field_name, fold_case) {
+                let mut violations = vec![];
+
+                for constraint in &field_spec.constraints {
+                    constraint.check_value(mv, &mut violations);
+                }
+
+                results.extend(violations.into_iter().map(|v| (field_name.clone(), v)));
+            }
+        }
+
+        results
+    }
+}
+
+pub fn read_schema_file<P: AsRef<Path>>(schema_fp: P) -> Result<Schema> {
+    let yaml_data = read_yaml_file(schema_fp)?;
+
+    Schema::from_yaml(&yaml_data)
+}
+
+fn yaml_as_bound(y: &Yaml) -> Result<Option<f64>> {
+    match *y {
+        Yaml::BadValue => Ok(None),
+        Yaml::Integer(i) => Ok(Some(i as f64)),
+        Yaml::Real(_) => Ok(y.as_f64()),
+        _ => bail!("numeric bounds must be numbers"),
+    }
+}
+
+fn yaml_as_field_spec(y: &Yaml) -> Result<FieldSpec> {
+    let mut field_spec = FieldSpec::new();
+
+    match *y {
+        // A bare field name is an unconstrained, optional field.
+        Yaml::Null => return Ok(field_spec),
+        Yaml::Hash(_) => {},
+        _ => bail!("field spec must be a mapping"),
+    }
+
+    match y["required"] {
+        Yaml::BadValue => {},
+        Yaml::Boolean(b) => { field_spec = field_spec.required(b); },
+        _ => bail!("'required' must be a boolean"),
+    }
+
+    match y["local"] {
+        Yaml::BadValue => {},
+        Yaml::Boolean(b) => { field_spec = field_spec.local(b); },
+        _ => bail!("'local' must be a boolean"),
+    }
+
+    match y["one_of"] {
+        Yaml::BadValue => {},
+        Yaml::Array(ref arr) => {
+            let mut allowed = vec![];
+
+            for val_y in arr {
+                match *val_y {
+                    Yaml::String(ref s) => allowed.push(s.clone()),
+                    Yaml::Integer(i) => allowed.push(i.to_string()),
+                    Yaml::Real(ref r) => allowed.push(r.clone()),
+                    _ => bail!("'one_of' values must be scalars"),
+                }
+            }
+
+            field_spec = field_spec.constraint(FieldConstraint::OneOf(allowed));
+        },
+        _ => bail!("'one_of' must be a sequence"),
+    }
+
+    match y["pattern"] {
+        Yaml::BadValue => {},
+        Yaml::String(ref s) => {
+            let r_exp = Regex::new(s).chain_err(|| format!("invalid pattern: '{}'", s))?;
+            field_spec = field_spec.constraint(FieldConstraint::Pattern(r_exp));
+        },
+        _ => bail!("'pattern' must be a string"),
+    }
+
+    let opt_min = yaml_as_bound(&y["min"])?;
+    let opt_max = yaml_as_bound(&y["max"])?;
+
+    if opt_min.is_some() || opt_max.is_some() {
+        field_spec = field_spec.constraint(FieldConstraint::Range(opt_min, opt_max));
+    }
+
+    Ok(field_spec)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ViolationKind {
+    Missing,
+    NotAllowed(String),
+    PatternMismatch(String),
+    NotNumeric(String),
+    OutOfRange(String),
+    UnknownTerm(String),
+    UnexpectedMapping,
+}
+
+impl Display for ViolationKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            ViolationKind::Missing => write!(f, "required field is missing"),
+            ViolationKind::NotAllowed(ref s) => write!(f, "value is not an allowed value: '{}'", s),
+            ViolationKind::PatternMismatch(ref s) => write!(f, "value does not match pattern: '{}'", s),
+            ViolationKind::NotNumeric(ref s) => write!(f, "value is not numeric: '{}'", s),
+            ViolationKind::OutOfRange(ref s) => write!(f, "value is out of range: '{}'", s),
+            ViolationKind::UnknownTerm(ref s) => write!(f, "value is not a known term: '{}'", s),
+            ViolationKind::UnexpectedMapping => write!(f, "value is a mapping"),
+        }
+    }
+}
+
+/// A schema violation found in a library.
+/// Missing required fields are not tied to a single meta file, so they have no meta file path.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SchemaViolation {
+    pub item_path: PathBuf,
+    pub meta_path: Option<PathBuf>,
+    pub field_name: String,
+    pub kind: ViolationKind,
+}
+
+impl Display for SchemaViolation {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}: {}: {}", self.item_path.to_string_lossy(), self.field_name, self.kind)?;
+
+        if let Some(ref meta_path) = self.meta_path {
+            write!(f, " (in '{}')", meta_path.to_string_lossy())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use yaml_rust::YamlLoader;
+
+    use metadata::{MetaBlock, MetaValue};
+    use ontology::Ontology;
+
+    use super::{Schema, FieldSpec, FieldConstraint, ViolationKind};
+
+    #[test]
+    fn test_builtin() {
+        let mut schema = Schema::builtin();
+        assert_eq!(vec!["lastplayed", "playcount", "rating"], schema.local_fields());
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let mb: MetaBlock = btreemap![
+            "rating".to_string() => str_val("4.5"),
+            "playcount".to_string() => str_val("12"),
+        ];
+        assert!(schema.check_block(&mb).is_empty());
+
+        let mb: MetaBlock = btreemap![
+            "rating".to_string() => str_val("6"),
+            "playcount".to_string() => str_val("-1"),
+        ];
+        assert_eq!(2, schema.check_block(&mb).len());
+
+        // A schema file can give the fields other specs, e.g. ratings out of 10.
+        let mut overrides = Schema::new();
+        overrides.field("rating", FieldSpec::new().local(true).constraint(FieldConstraint::Range(Some(0.0), Some(10.0))));
+        schema.extend(overrides);
+        assert_eq!(1, schema.check_block(&mb).len());
+    }
+
+    #[test]
+    fn test_constrain_to_ontology() {
+        let mut ontology = Ontology::new();
+        ontology.term("genre", "Psytrance", &["Trance"]);
+
+        let mut schema = Schema::new();
+        schema.field("genre", FieldSpec::new().required(true));
+        schema.constrain_to_ontology(&ontology);
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        // Existing specs are kept, and terms are matched without regard to case.
+        let mb: MetaBlock = btreemap![
+            "genre".to_string() => MetaValue::Seq(vec![str_val("trance"), str_val("PSYTRANCE"), str_val("Psytrnace")]),
+        ];
+        assert_eq!(vec![("genre".to_string(), ViolationKind::UnknownTerm("Psytrnace".to_string()))], schema.check_block(&mb));
+        assert_eq!(vec!["genre"], schema.required_fields());
+    }
+
+    #[test]
+    fn test_check_block() {
+        let mut schema = Schema::new();
+        schema
+            .field("genre", FieldSpec::new().constraint(FieldConstraint::OneOf(vec!["Rock".to_string(), "Jazz".to_string()])))
+            .field("date", FieldSpec::new().required(true).constraint(FieldConstraint::Pattern(Regex::new(r"\d{4}").unwrap())))
+            .field("track_num", FieldSpec::new().constraint(FieldConstraint::Range(Some(1.0), Some(99.0))));
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let inputs_and_expected: Vec<(MetaBlock, Vec<(String, ViolationKind)>)> = vec![
+            (
+                btreemap![],
+                vec![],
+            ),
+            (
+                btreemap![
+                    "genre".to_string() => str_val("Rock"),
+                    "date".to_string() => str_val("2018"),
+                    "track_num".to_string() => str_val("12"),
+                    "unchecked".to_string() => str_val("anything"),
+                ],
+                vec![],
+            ),
+            (
+                btreemap![
+                    "genre".to_string() => MetaValue::Seq(vec![str_val("Jazz"), str_val("Polka")]),
+                ],
+                vec![("genre".to_string(), ViolationKind::NotAllowed("Polka".to_string()))],
+            ),
+            (
+                btreemap![
+                    "date".to_string() => str_val("2018-01-01"),
+                    "track_num".to_string() => str_val("100"),
+                ],
+                vec![
+                    ("date".to_string(), ViolationKind::PatternMismatch("2018-01-01".to_string())),
+                    ("track_num".to_string(), ViolationKind::OutOfRange("100".to_string())),
+                ],
+            ),
+            (
+                btreemap![
+                    "genre".to_string() => MetaValue::Nil,
+                    "track_num".to_string() => str_val("one"),
+                ],
+                vec![("track_num".to_string(), ViolationKind::NotNumeric("one".to_string()))],
+            ),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = schema.check_block(&input);
+            assert_eq!(expected, produced);
+        }
+
+        assert_eq!(vec!["date"], schema.required_fields());
+    }
+
+    #[test]
+    fn test_from_yaml() {
+        let text = "genre:\n  required: true\n  one_of: [Rock, Jazz]\ndate:\n  pattern: '\\d{4}'\ntrack_num:\n  min: 1\n  max: 99.5\ncomment:\ntitle:\n  local: true";
+        let yaml = YamlLoader::load_from_str(text).unwrap().pop().unwrap();
+
+        let schema = Schema::from_yaml(&yaml).expect("Unable to read schema");
+
+        assert_eq!(vec!["comment", "date", "genre", "title", "track_num"], schema.fields().keys().map(|k| k.as_str()).collect::<Vec<_>>());
+        assert_eq!(vec!["genre"], schema.required_fields());
+        assert_eq!(vec!["title"], schema.local_fields());
+
+        let mb = btreemap![
+            "genre".to_string() => MetaValue::Str("Polka".to_string()),
+            "date".to_string() => MetaValue::Str("2018".to_string()),
+            "track_num".to_string() => MetaValue::Str("99.6".to_string()),
+            "comment".to_string() => MetaValue::Str("anything".to_string()),
+        ];
+        let expected = vec![
+            ("genre".to_string(), ViolationKind::NotAllowed("Polka".to_string())),
+            ("track_num".to_string(), ViolationKind::OutOfRange("99.6".to_string())),
+        ];
+        assert_eq!(expected, schema.check_block(&mb));
+
+        let bad_texts = vec![
+            "- genre",
+            "genre: [required]",
+            "genre:\n  required: yes please",
+            "genre:\n  pattern: '('",
+            "genre:\n  min: low",
+            "genre:\n  local: 1",
+        ];
+
+        for bad_text in bad_texts {
+            let yaml = YamlLoader::load_from_str(bad_text).unwrap().pop().unwrap();
+            assert!(Schema::from_yaml(&yaml).is_err());
+        }
+    }
+}
@@ -0,0 +1,304 @@
+// This module derives sort names from names, e.g. `Beatles, The` from `The Beatles`, so that sort fields do not need to be filled in by hand.
+// A sort field that is not set (e.g. `artist_sort`) is derived from the field that it sorts (e.g. `artist`) when it is looked up.
+// Leading articles are moved to the end, after names have been passed through any transliterators, e.g. to spell out letters of other scripts.
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::sync::Arc;
+
+use yaml_rust::Yaml;
+
+use metadata::MetaValue;
+use error::*;
+
+/// Rewrites names before their sort names are derived, e.g. to spell out the letters of a script or locale in Latin letters.
+/// Embedding applications can add their own, e.g. one backed by a transliteration library.
+pub trait Transliterator {
+    /// Returns the rewritten name, or `None` to leave it as it is.
+    fn transliterate(&self, name: &str) -> Option<String>;
+}
+
+/// Replaces single characters with strings, e.g. `Ø` with `O` or `ß` with `ss`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CharTable {
+    table: BTreeMap<char, String>,
+}
+
+impl CharTable {
+    pub fn new() -> Self {
+        CharTable::default()
+    }
+
+    pub fn map<S: Into<String>>(&mut self, c: char, replacement: S) -> &mut Self {
+        self.table.insert(c, replacement.into());
+        self
+    }
+}
+
+impl Transliterator for CharTable {
+    fn transliterate(&self, name: &str) -> Option<String> {
+        if !name.chars().any(|c| self.table.contains_key(&c)) {
+            return None;
+        }
+
+        let mut transliterated = String::new();
+
+        for c in name.chars() {
+            match self.table.get(&c) {
+                Some(replacement) => transliterated.push_str(replacement),
+                None => transliterated.push(c),
+            }
+        }
+
+        Some(transliterated)
+    }
+}
+
+/// How sort names are derived, and which sort fields are derived from which fields.
+#[derive(Clone)]
+pub struct SortNameRules {
+    articles: Vec<String>,
+    derived_fields: Vec<(String, String)>,
+    transliterators: Vec<Arc<Transliterator + Send + Sync>>,
+}
+
+impl Debug for SortNameRules {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("SortNameRules")
+            .field("articles", &self.articles)
+            .field("derived_fields", &self.derived_fields)
+            .field("transliterators", &self.transliterators.len())
+            .finish()
+    }
+}
+
+impl Default for SortNameRules {
+    fn default() -> Self {
+        let mut sort_name_rules = SortNameRules::new();
+        sort_name_rules
+            .articles(&["The", "A", "An"])
+            .derive("artist_sort", "artist")
+            .derive("album_artist_sort", "album_artist")
+            .derive("composer_sort", "composer");
+        sort_name_rules
+    }
+}
+
+impl SortNameRules {
+    /// Creates rules that derive no sort fields.
+    pub fn new() -> Self {
+        SortNameRules {
+            articles: vec![],
+            derived_fields: vec![],
+            transliterators: vec![],
+        }
+    }
+
+    /// Sets the articles that are moved to the end of names, replacing any existing ones.
+    /// An article ending in an apostrophe (e.g. `L'`) does not need a space after it.
+    pub fn articles(&mut self, articles: &[&str]) -> &mut Self {
+        self.articles = articles.iter().map(|a| a.to_string()).collect();
+        self
+    }
+
+    /// Derives a sort field from another field, replacing any existing rule for the sort field.
+    pub fn derive<S: Into<String>, T: Into<String>>(&mut self, sort_field_name: S, field_name: T) -> &mut Self {
+        let sort_field_name = sort_field_name.into();
+
+        self.derived_fields.retain(|&(ref f, _)| *f != sort_field_name);
+        self.derived_fields.push((sort_field_name, field_name.into()));
+        self
+    }
+
+    /// Adds a transliterator, which is applied after the ones added before it.
+    pub fn transliterator<T: Transliterator + Send + Sync + 'static>(&mut self, transliterator: T) -> &mut Self {
+        self.transliterators.push(Arc::new(transliterator));
+        self
+    }
+
+    /// Reads rules from a mapping, adding to (or overriding) the existing rules:
+    ///
+    /// ```yaml
+    /// articles: [The, A, An, Die, "L'"]
+    /// transliterate: {Ø: O, ß: ss}
+    /// fields: {title_sort: title}
+    /// ```
+    pub fn extend_from_yaml(&mut self, y: &Yaml) -> Result<()> {
+        match *y {
+            Yaml::Hash(_) => {},
+            Yaml::Null => return Ok(()),
+            _ => bail!("sort name rules must be a mapping"),
+        }
+
+        match y["articles"] {
+            Yaml::BadValue => {},
+            Yaml::Array(ref arr) => {
+                let articles: Vec<&str> = arr.iter().map(Yaml::as_str).collect::<Option<_>>().ok_or("'articles' must be strings")?;
+
+                // Rule: articles must not be empty.
+                ensure!(articles.iter().all(|a| !a.trim().is_empty()), "'articles' must not be empty strings");
+
+                self.articles(&articles);
+            },
+            _ => bail!("'articles' must be a sequence"),
+        }
+
+        match y["transliterate"] {
+            Yaml::BadValue => {},
+            Yaml::Hash(ref hsh) => {
+                let mut char_table = CharTable::new();
+
+                for (c_y, replacement_y) in hsh {
+                    let mut chars = c_y.as_str().unwrap_or_default().chars();
+
+                    match (chars.next(), chars.next(), replacement_y.as_str()) {
+                        (Some(c), None, Some(replacement)) => { char_table.map(c, replacement); },
+                        _ => bail!("'transliterate' must map single characters to strings"),
+                    }
+                }
+
+                self.transliterator(char_table);
+            },
+            _ => bail!("'transliterate' must be a mapping"),
+        }
+
+        match y["fields"] {
+            Yaml::BadValue => {},
+            Yaml::Hash(ref hsh) => {
+                for (sort_field_name_y, field_name_y) in hsh {
+                    match (sort_field_name_y.as_str(), field_name_y.as_str()) {
+                        (Some(sort_field_name), Some(field_name)) => { self.derive(sort_field_name, field_name); },
+                        _ => bail!("'fields' must map sort field names to field names"),
+                    }
+                }
+            },
+            _ => bail!("'fields' must be a mapping"),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the field that a sort field is derived from, if it is derived at all.
+    pub fn source_field<S: AsRef<str>>(&self, sort_field_name: S) -> Option<&str> {
+        let sort_field_name = sort_field_name.as_ref();
+
+        self.derived_fields.iter().find(|&&(ref f, _)| f == sort_field_name).map(|&(_, ref f)| f.as_str())
+    }
+
+    /// Derives the sort name of a name, e.g. `Beatles, The` from `The Beatles`.
+    /// The article keeps the case it has in the name, and a name that is only an article is left as it is.
+    pub fn sort_name<S: AsRef<str>>(&self, name: S) -> String {
+        let mut name = name.as_ref().trim().to_string();
+
+        for transliterator in &self.transliterators {
+            if let Some(transliterated) = transliterator.transliterate(&name) {
+                name = transliterated;
+            }
+        }
+
+        for article in &self.articles {
+            let prefix = match name.get(..article.len()) {
+                Some(prefix) if prefix.to_lowercase() == article.to_lowercase() => prefix,
+                _ => continue,
+            };
+
+            let rest = &name[article.len()..];
+            let separated = article.ends_with('\'') || rest.starts_with(char::is_whitespace);
+
+            if separated && !rest.trim().is_empty() {
+                return format!("{}, {}", rest.trim_start(), prefix);
+            }
+        }
+
+        name
+    }
+
+    /// Derives the sort names of the strings in a value, keeping its shape.
+    pub fn sort_value(&self, mv: &MetaValue) -> MetaValue {
+        match *mv {
+            MetaValue::Str(ref s) => MetaValue::Str(self.sort_name(s)),
+            MetaValue::Seq(ref mvs) => MetaValue::Seq(mvs.iter().map(|mv| self.sort_value(mv)).collect()),
+            MetaValue::Nil | MetaValue::Map(_) => mv.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+
+    use metadata::MetaValue;
+
+    use super::{SortNameRules, Transliterator};
+
+    struct Upper;
+
+    impl Transliterator for Upper {
+        fn transliterate(&self, name: &str) -> Option<String> {
+            Some(name.to_uppercase())
+        }
+    }
+
+    #[test]
+    fn test_sort_name() {
+        let sort_name_rules = SortNameRules::default();
+
+        let inputs_and_expected = vec![
+            ("The Beatles", "Beatles, The"),
+            ("the  pretenders ", "pretenders, the"),
+            ("A Tribe Called Quest", "Tribe Called Quest, A"),
+            ("Theatre of Tragedy", "Theatre of Tragedy"),
+            ("Beatles, The", "Beatles, The"),
+            ("The", "The"),
+            ("Aphex Twin", "Aphex Twin"),
+            ("Ängel", "Ängel"),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, sort_name_rules.sort_name(input), "unexpected sort name for: {}", input);
+        }
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        assert_eq!(
+            MetaValue::Seq(vec![str_val("Who, The"), MetaValue::Nil]),
+            sort_name_rules.sort_value(&MetaValue::Seq(vec![str_val("The Who"), MetaValue::Nil])),
+        );
+
+        // Transliterators run in order, before articles are moved.
+        let mut sort_name_rules = SortNameRules::new();
+        sort_name_rules.articles(&["THE"]).transliterator(Upper);
+        assert_eq!("WHO, THE", sort_name_rules.sort_name("The Who"));
+    }
+
+    #[test]
+    fn test_extend_from_yaml() {
+        let text = "articles: [Die, \"L'\"]\ntransliterate: {Ä: Ae, ß: ss}\nfields: {title_sort: title, artist_sort: performer}\n";
+        let y = &YamlLoader::load_from_str(text).unwrap()[0];
+
+        let mut sort_name_rules = SortNameRules::default();
+        sort_name_rules.extend_from_yaml(y).unwrap();
+
+        assert_eq!("Aerzte, Die", sort_name_rules.sort_name("Die Ärzte"));
+        assert_eq!("Arc~en~Ciel, L'", sort_name_rules.sort_name("L'Arc~en~Ciel"));
+        assert_eq!("The Strasse", sort_name_rules.sort_name("The Straße"));
+
+        assert_eq!(Some("title"), sort_name_rules.source_field("title_sort"));
+        assert_eq!(Some("performer"), sort_name_rules.source_field("artist_sort"));
+        assert_eq!(Some("composer"), sort_name_rules.source_field("composer_sort"));
+        assert_eq!(None, sort_name_rules.source_field("artist"));
+
+        let invalid_inputs = vec![
+            "- articles",
+            "articles: The",
+            "articles: ['']",
+            "transliterate: {ab: c}",
+            "transliterate: {a: [b]}",
+            "fields: [title_sort]",
+        ];
+
+        for input in invalid_inputs {
+            let y = &YamlLoader::load_from_str(input).unwrap()[0];
+            assert!(SortNameRules::new().extend_from_yaml(y).is_err(), "expected error for: {}", input);
+        }
+    }
+}
@@ -0,0 +1,472 @@
+// This module reconciles YAML metadata with the tags embedded in audio files.
+
+use std::collections::BTreeMap;
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use yaml_rust::Yaml;
+
+use library::{Library, WalkStep};
+use lookup::LookupContext;
+use lookup::options::LookupOptions;
+use metadata::MetaValue;
+use multivalue::SplitRules;
+use tags::{self, TagMap, meta_value_as_tag_values, tag_values_as_meta_value};
+use writer::plan_edit_item_block;
+use plan::WritePlan;
+use helpers::normalize;
+use progress::Progress;
+use error::*;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SyncDirection {
+    /// YAML values overwrite tag values.
+    YamlToTags,
+    /// Tag values overwrite YAML values.
+    TagsToYaml,
+    /// Values missing on one side are copied from the other; differing values are left alone.
+    Merge,
+}
+
+impl FromStr for SyncDirection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "yaml-to-tags" => Ok(SyncDirection::YamlToTags),
+            "tags-to-yaml" => Ok(SyncDirection::TagsToYaml),
+            "merge" => Ok(SyncDirection::Merge),
+            _ => bail!("unknown sync direction: '{}'", s),
+        }
+    }
+}
+
+/// Pairs of YAML field names and the embedded tag names that they correspond to.
+/// Tag values are split by the split rules of their fields as they are read, so that e.g. an `ARTIST` tag of `A feat. B` becomes two artists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldMapping {
+    pairs: Vec<(String, String)>,
+    split_rules: SplitRules,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        let mut mapping = FieldMapping::new();
+        mapping
+            .map("title", "TITLE")
+            .map("artist", "ARTIST")
+            .map("album", "ALBUM")
+            .map("album_artist", "ALBUMARTIST")
+            .map("date", "DATE")
+            .map("genre", "GENRE")
+            .map("track_num", "TRACKNUMBER")
+            .map("disc_num", "DISCNUMBER")
+            .map("replaygain_track_gain", "REPLAYGAIN_TRACK_GAIN")
+            .map("replaygain_track_peak", "REPLAYGAIN_TRACK_PEAK")
+            .map("replaygain_album_gain", "REPLAYGAIN_ALBUM_GAIN")
+            .map("replaygain_album_peak", "REPLAYGAIN_ALBUM_PEAK")
+            .split_rules(SplitRules::default());
+        mapping
+    }
+}
+
+impl FieldMapping {
+    pub fn new() -> Self {
+        FieldMapping { pairs: vec![], split_rules: SplitRules::new() }
+    }
+
+    pub fn split_rules(&mut self, split_rules: SplitRules) -> &mut Self {
+        self.split_rules = split_rules;
+        self
+    }
+
+    /// Maps a field to a tag, replacing any existing mapping for that field.
+    /// Tag names are case-insensitive.
+    pub fn map<S: Into<String>, T: AsRef<str>>(&mut self, field_name: S, tag_name: T) -> &mut Self {
+        let field_name = field_name.into();
+        let tag_name = tag_name.as_ref().to_uppercase();
+
+        self.pairs.retain(|&(ref f, _)| *f != field_name);
+        self.pairs.push((field_name, tag_name));
+        self
+    }
+
+    /// Reads a mapping of field names to tag names, adding to (or overriding) the existing mappings.
+    pub fn extend_from_yaml(&mut self, y: &Yaml) -> Result<()> {
+        match *y {
+            Yaml::Hash(ref hsh) => {
+                for (field_name_y, tag_name_y) in hsh {
+                    match (field_name_y.as_str(), tag_name_y.as_str()) {
+                        (Some(field_name), Some(tag_name)) => { self.map(field_name, tag_name); },
+                        _ => bail!("field mappings must map strings to strings"),
+                    }
+                }
+
+                Ok(())
+            },
+            Yaml::Null => Ok(()),
+            _ => bail!("field mapping must be a mapping of field names to tag names"),
+        }
+    }
+
+    pub fn pairs(&self) -> &[(String, String)] {
+        &self.pairs
+    }
+}
+
+/// A field whose YAML and tag values differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncConflict {
+    pub field_name: String,
+    pub tag_name: String,
+    pub yaml_values: Vec<String>,
+    pub tag_values: Vec<String>,
+}
+
+impl Display for SyncConflict {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{} <-> {}: yaml {:?}, tags {:?}", self.field_name, self.tag_name, self.yaml_values, self.tag_values)
+    }
+}
+
+/// The changes needed to bring a single item in sync.
+/// In directional modes, conflicts are resolved by overwriting, but are still reported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncReport {
+    pub item_path: PathBuf,
+    pub yaml_updates: BTreeMap<String, MetaValue>,
+    pub tag_updates: TagMap,
+    pub conflicts: Vec<SyncConflict>,
+}
+
+impl SyncReport {
+    pub fn is_in_sync(&self) -> bool {
+        self.yaml_updates.is_empty() && self.tag_updates.is_empty() && self.conflicts.is_empty()
+    }
+}
+
+/// Works out the changes needed to sync an item, without touching the disk.
+/// Fields whose values cannot be stored as tags (e.g. mappings) are skipped.
+pub fn plan_sync<P: AsRef<Path>>(
+    abs_item_path: P,
+    yaml_fields: &BTreeMap<String, MetaValue>,
+    tags: &TagMap,
+    mapping: &FieldMapping,
+    direction: SyncDirection,
+    ) -> SyncReport
+{
+    let mut report = SyncReport {
+        item_path: abs_item_path.as_ref().to_path_buf(),
+        yaml_updates: BTreeMap::new(),
+        tag_updates: TagMap::new(),
+        conflicts: vec![],
+    };
+
+    for &(ref field_name, ref tag_name) in mapping.pairs() {
+        let opt_yaml_vals = match yaml_fields.get(field_name) {
+            Some(mv) => {
+                let opt_vals = meta_value_as_tag_values(mv);
+
+                if opt_vals.is_none() && *mv != MetaValue::Nil {
+                    warn!("skipping field that cannot be stored as a tag: '{}'", field_name);
+                    continue;
+                }
+
+                opt_vals
+            },
+            None => None,
+        };
+        let opt_tag_vals = tags.get(tag_name).filter(|vals| !vals.is_empty()).map(|vals| mapping.split_rules.split_values(field_name, vals));
+
+        match (opt_yaml_vals, opt_tag_vals) {
+            (None, None) => {},
+            (Some(yaml_vals), None) => {
+                if direction != SyncDirection::TagsToYaml {
+                    report.tag_updates.insert(tag_name.clone(), yaml_vals);
+                }
+            },
+            (None, Some(tag_vals)) => {
+                if direction != SyncDirection::YamlToTags {
+                    report.yaml_updates.insert(field_name.clone(), tag_values_as_meta_value(&tag_vals));
+                }
+            },
+            (Some(yaml_vals), Some(tag_vals)) => {
+                // Values that only differ in form, e.g. a track number of `1` against `01`, are not a conflict.
+                if tag_values_as_meta_value(&yaml_vals).eq_coerced(&tag_values_as_meta_value(&tag_vals)) {
+                    continue;
+                }
+
+                match direction {
+                    SyncDirection::YamlToTags => { report.tag_updates.insert(tag_name.clone(), yaml_vals.clone()); },
+                    SyncDirection::TagsToYaml => { report.yaml_updates.insert(field_name.clone(), tag_values_as_meta_value(&tag_vals)); },
+                    SyncDirection::Merge => {},
+                }
+
+                report.conflicts.push(SyncConflict {
+                    field_name: field_name.clone(),
+                    tag_name: tag_name.clone(),
+                    yaml_values: yaml_vals,
+                    tag_values: tag_vals,
+                });
+            },
+        }
+    }
+
+    report
+}
+
+/// Works out the changes needed to sync a single item, and adds the writes to a plan.
+/// YAML values include those inherited from ancestors; YAML updates are always written to the item's own block.
+pub fn sync_item<P: AsRef<Path>>(
+    lookup_ctx: &mut LookupContext,
+    media_lib: &Library,
+    abs_item_path: P,
+    mapping: &FieldMapping,
+    direction: SyncDirection,
+    plan: &mut WritePlan,
+    ) -> Result<SyncReport>
+{
+    let abs_item_path = normalize(abs_item_path.as_ref());
+
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    let mut yaml_fields = BTreeMap::new();
+
+    for &(ref field_name, _) in mapping.pairs() {
+        // Mapped field names are used as-is, since updates are written back under the same name.
+        let found = lookup_ctx.lookup(&abs_item_path, &LookupOptions::exact(field_name.as_str()))?;
+
+        if let Some(val) = found {
+            yaml_fields.insert(field_name.clone(), val);
+        }
+    }
+
+    let mut item_tags = plan.read_tags(&abs_item_path)?;
+
+    let report = plan_sync(&abs_item_path, &yaml_fields, &item_tags, mapping, direction);
+
+    for conflict in &report.conflicts {
+        for hook in media_lib.hooks() {
+            hook.conflict(&abs_item_path, conflict);
+        }
+    }
+
+    if !report.tag_updates.is_empty() {
+        item_tags.extend(report.tag_updates.clone());
+        plan.write_tags(abs_item_path.clone(), item_tags);
+    }
+
+    if !report.yaml_updates.is_empty() {
+        let yaml_updates = report.yaml_updates.clone();
+        plan_edit_item_block(media_lib, plan, &abs_item_path, |mb| mb.extend(yaml_updates))?;
+    }
+
+    Ok(report)
+}
+
+/// Works out the changes needed to sync every taggable item in a directory and its selected subdirectories, in walk order.
+/// The writes are added to a plan, which can be shown as a dry run or executed.
+/// Items whose tag format is not supported are skipped.
+pub fn sync_dir<P: AsRef<Path>, G: Progress>(
+    media_lib: &Library,
+    abs_dir_path: P,
+    mapping: &FieldMapping,
+    direction: SyncDirection,
+    plan: &mut WritePlan,
+    progress: &mut G,
+    ) -> Result<Vec<SyncReport>>
+{
+    let abs_dir_path = normalize(abs_dir_path.as_ref());
+
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    // Rule: dir path must be a directory.
+    ensure!(abs_dir_path.is_dir(), ErrorKind::NotADirectory(abs_dir_path.clone()));
+
+    let mut reports = vec![];
+    let mut lookup_ctx = LookupContext::new(media_lib);
+
+    media_lib.walk_items(&abs_dir_path, |step| {
+        match step {
+            WalkStep::EnterDir(dir_path) => {
+                // YAML updates only ever go to blocks of files, which are not inherited by other directories, so planned writes do not affect lookups.
+                lookup_ctx = LookupContext::new(media_lib);
+                lookup_ctx.cache_item_file_with_progress(dir_path, progress)?;
+            },
+            WalkStep::Item { path, is_dir } => {
+                if !is_dir && tags::is_supported(path) {
+                    lookup_ctx.cache_item_file_with_progress(path, progress)?;
+                    progress.item_scanned(path);
+
+                    reports.push(sync_item(&mut lookup_ctx, media_lib, path, mapping, direction, plan)?);
+                }
+            },
+            WalkStep::LeaveDir(_) => {},
+        }
+
+        Ok(true)
+    })?;
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+
+    use tempdir::TempDir;
+
+    use library::LibraryBuilder;
+    use library::selection::Selection;
+    use lookup::LookupContext;
+    use metadata::{MetaTarget, MetaValue};
+    use multivalue::SplitRules;
+    use tags::{read_tags, write_tags};
+    use progress::{NoProgress, ProgressCounts};
+    use plan::WritePlan;
+
+    use super::{FieldMapping, SyncDirection, SyncConflict, plan_sync, sync_dir};
+
+    #[test]
+    fn test_plan_sync() {
+        let mut mapping = FieldMapping::new();
+        mapping
+            .map("title", "title")
+            .map("artist", "ARTIST")
+            .map("album", "ALBUM")
+            .map("track", "TRACKNUMBER");
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        let yaml_fields = btreemap![
+            String::from("title") => str_val("Yaml Title"),
+            String::from("album") => str_val("Same Album"),
+            String::from("track") => str_val("1"),
+        ];
+        let tags = btreemap![
+            String::from("TITLE") => vec![String::from("Tag Title")],
+            String::from("ALBUM") => vec![String::from("Same Album")],
+            String::from("ARTIST") => vec![String::from("Artist A"), String::from("Artist B")],
+            // Only differs in form from the YAML value, so it is neither a conflict nor an update.
+            String::from("TRACKNUMBER") => vec![String::from("01")],
+        ];
+
+        let conflict = SyncConflict {
+            field_name: String::from("title"),
+            tag_name: String::from("TITLE"),
+            yaml_values: vec![String::from("Yaml Title")],
+            tag_values: vec![String::from("Tag Title")],
+        };
+
+        let report = plan_sync("item", &yaml_fields, &tags, &mapping, SyncDirection::YamlToTags);
+        assert_eq!(btreemap![String::from("TITLE") => vec![String::from("Yaml Title")]], report.tag_updates);
+        assert!(report.yaml_updates.is_empty());
+        assert_eq!(vec![conflict.clone()], report.conflicts);
+
+        let report = plan_sync("item", &yaml_fields, &tags, &mapping, SyncDirection::TagsToYaml);
+        assert!(report.tag_updates.is_empty());
+        assert_eq!(btreemap![
+            String::from("title") => str_val("Tag Title"),
+            String::from("artist") => MetaValue::Seq(vec![str_val("Artist A"), str_val("Artist B")]),
+        ], report.yaml_updates);
+        assert_eq!(vec![conflict.clone()], report.conflicts);
+
+        let report = plan_sync("item", &yaml_fields, &tags, &mapping, SyncDirection::Merge);
+        assert!(report.tag_updates.is_empty());
+        assert_eq!(btreemap![
+            String::from("artist") => MetaValue::Seq(vec![str_val("Artist A"), str_val("Artist B")]),
+        ], report.yaml_updates);
+        assert_eq!(vec![conflict.clone()], report.conflicts);
+
+        // Tag values are split by the split rules of their fields.
+        let mut split_rules = SplitRules::new();
+        split_rules.rule("artist", &["; "]);
+        mapping.split_rules(split_rules);
+
+        let yaml_fields = btreemap![String::from("title") => MetaValue::Seq(vec![str_val("A; B")])];
+        let tags = btreemap![
+            String::from("TITLE") => vec![String::from("A; B")],
+            String::from("ARTIST") => vec![String::from("Artist A; Artist B")],
+        ];
+
+        let report = plan_sync("item", &yaml_fields, &tags, &mapping, SyncDirection::TagsToYaml);
+        assert_eq!(btreemap![
+            String::from("artist") => MetaValue::Seq(vec![str_val("Artist A"), str_val("Artist B")]),
+        ], report.yaml_updates);
+        assert!(report.conflicts.is_empty());
+    }
+
+    fn create_flac_file(path: &Path) {
+        let mut f = File::create(path).unwrap();
+        f.write_all(b"fLaC").unwrap();
+        f.write_all(&[0x80, 0x00, 0x00, 34]).unwrap();
+        f.write_all(&[0u8; 34]).unwrap();
+    }
+
+    #[test]
+    fn test_sync_dir() {
+        let temp = TempDir::new("test_sync_dir").unwrap();
+        let tp = temp.path();
+
+        create_flac_file(&tp.join("TRACK_01.flac"));
+        create_flac_file(&tp.join("TRACK_02.flac"));
+
+        let mut f = File::create(tp.join("self.yml")).unwrap();
+        writeln!(f, "album: Album").unwrap();
+        let mut f = File::create(tp.join("item.yml")).unwrap();
+        writeln!(f, "- title: Title 1\n- album_artist: Artist").unwrap();
+
+        write_tags(tp.join("TRACK_02.flac"), &btreemap![String::from("TITLE") => vec![String::from("Title 2")]]).unwrap();
+
+        let meta_target_specs = vec![
+            (String::from("self.yml"), MetaTarget::Contains),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let selection = Selection::Ext(String::from("flac"));
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs).selection(selection).create().expect("Unable to create media library");
+
+        let mapping = FieldMapping::default();
+
+        // Planning does not change anything.
+        let mut progress = ProgressCounts::default();
+        let mut plan = WritePlan::new();
+        let reports = sync_dir(&media_lib, tp, &mapping, SyncDirection::Merge, &mut plan, &mut progress).expect("Unable to sync");
+        assert_eq!(2, reports.len());
+        assert!(reports.iter().all(|r| r.conflicts.is_empty()));
+        assert_eq!(2, progress.items_scanned);
+        assert_eq!(2, progress.meta_files_parsed);
+        assert!(read_tags(tp.join("TRACK_01.flac")).unwrap().is_empty());
+
+        assert_eq!(vec![tp.join("TRACK_01.flac"), tp.join("TRACK_02.flac"), tp.join("item.yml")], plan.paths());
+        plan.execute().expect("Unable to execute plan");
+
+        // Inherited fields are written to tags.
+        let expected = btreemap![
+            String::from("ALBUM") => vec![String::from("Album")],
+            String::from("TITLE") => vec![String::from("Title 1")],
+        ];
+        assert_eq!(expected, read_tags(tp.join("TRACK_01.flac")).unwrap());
+
+        let expected = btreemap![
+            String::from("ALBUM") => vec![String::from("Album")],
+            String::from("ALBUMARTIST") => vec![String::from("Artist")],
+            String::from("TITLE") => vec![String::from("Title 2")],
+        ];
+        assert_eq!(expected, read_tags(tp.join("TRACK_02.flac")).unwrap());
+
+        // Tag values are written to YAML.
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(MetaValue::Str(String::from("Title 2"))), lookup_ctx.lookup_origin(tp.join("TRACK_02.flac"), "title").unwrap());
+
+        // Everything is now in sync.
+        let mut plan = WritePlan::new();
+        let reports = sync_dir(&media_lib, tp, &mapping, SyncDirection::Merge, &mut plan, &mut NoProgress).expect("Unable to sync");
+        assert!(reports.iter().all(|r| r.is_in_sync()));
+        assert!(plan.is_empty());
+    }
+}
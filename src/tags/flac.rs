@@ -0,0 +1,242 @@
+// Reads and writes the Vorbis comment block of FLAC files.
+// See https://xiph.org/flac/format.html for a description of the format.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use tags::TagMap;
+use error::*;
+
+const FLAC_MAGIC: &[u8] = b"fLaC";
+const BLOCK_TYPE_STREAMINFO: u8 = 0;
+const BLOCK_TYPE_VORBIS_COMMENT: u8 = 4;
+const DEFAULT_VENDOR: &str = "taggu";
+
+/// A raw metadata block, along with its type.
+struct FlacBlock {
+    block_type: u8,
+    data: Vec<u8>,
+}
+
+/// The metadata blocks of a FLAC file, and the audio frames that follow them.
+struct FlacFile {
+    blocks: Vec<FlacBlock>,
+    audio: Vec<u8>,
+}
+
+fn read_u32_le(data: &[u8], pos: &mut usize) -> Result<u32> {
+    ensure!(*pos + 4 <= data.len(), "unexpected end of Vorbis comment block");
+
+    let bytes = &data[*pos..*pos + 4];
+    *pos += 4;
+
+    Ok(u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16 | u32::from(bytes[3]) << 24)
+}
+
+fn read_str(data: &[u8], pos: &mut usize) -> Result<String> {
+    let len = read_u32_le(data, pos)? as usize;
+    ensure!(*pos + len <= data.len(), "unexpected end of Vorbis comment block");
+
+    let s = String::from_utf8_lossy(&data[*pos..*pos + len]).into_owned();
+    *pos += len;
+
+    Ok(s)
+}
+
+fn write_u32_le(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]);
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32_le(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn parse_flac(raw: Vec<u8>) -> Result<FlacFile> {
+    ensure!(raw.starts_with(FLAC_MAGIC), "not a FLAC file");
+
+    let mut pos = FLAC_MAGIC.len();
+    let mut blocks = vec![];
+
+    loop {
+        ensure!(pos + 4 <= raw.len(), "unexpected end of FLAC metadata");
+
+        let header = &raw[pos..pos + 4];
+        let is_last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7f;
+        let len = (header[1] as usize) << 16 | (header[2] as usize) << 8 | header[3] as usize;
+        pos += 4;
+
+        ensure!(pos + len <= raw.len(), "unexpected end of FLAC metadata");
+
+        blocks.push(FlacBlock { block_type, data: raw[pos..pos + len].to_vec() });
+        pos += len;
+
+        if is_last {
+            break;
+        }
+    }
+
+    Ok(FlacFile { blocks, audio: raw[pos..].to_vec() })
+}
+
+/// Splits a Vorbis comment block into its vendor string and comments.
+/// Comment names are case-insensitive, and are normalized to upper case.
+fn parse_vorbis_comments(data: &[u8]) -> Result<(String, TagMap)> {
+    let mut pos = 0;
+    let vendor = read_str(data, &mut pos)?;
+    let count = read_u32_le(data, &mut pos)?;
+
+    let mut tags = TagMap::new();
+
+    for _ in 0..count {
+        let comment = read_str(data, &mut pos)?;
+
+        match comment.find('=') {
+            Some(i) => {
+                tags.entry(comment[..i].to_uppercase()).or_insert_with(Vec::new).push(comment[i + 1..].to_string());
+            },
+            None => { warn!("skipping malformed Vorbis comment: '{}'", comment); },
+        }
+    }
+
+    Ok((vendor, tags))
+}
+
+fn build_vorbis_comments(vendor: &str, tags: &TagMap) -> Vec<u8> {
+    let mut data = vec![];
+    write_str(&mut data, vendor);
+
+    let comments: Vec<String> = {
+        tags.iter()
+            .flat_map(|(name, vals)| vals.iter().map(move |v| format!("{}={}", name, v)))
+            .collect()
+    };
+
+    write_u32_le(&mut data, comments.len() as u32);
+
+    for comment in &comments {
+        write_str(&mut data, comment);
+    }
+
+    data
+}
+
+fn read_raw<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let mut f = File::open(path)?;
+    let mut raw = vec![];
+    f.read_to_end(&mut raw)?;
+
+    Ok(raw)
+}
+
+pub fn read_tags<P: AsRef<Path>>(path: P) -> Result<TagMap> {
+    let flac = parse_flac(read_raw(path)?)?;
+
+    match flac.blocks.iter().find(|b| b.block_type == BLOCK_TYPE_VORBIS_COMMENT) {
+        Some(block) => parse_vorbis_comments(&block.data).map(|(_, tags)| tags),
+        None => Ok(TagMap::new()),
+    }
+}
+
+/// Replaces all of the Vorbis comments in a FLAC file.
+/// The file is rewritten in full, leaving all other metadata blocks and the audio data untouched.
+pub fn write_tags<P: AsRef<Path>>(path: P, tags: &TagMap) -> Result<()> {
+    let path = path.as_ref();
+    let mut flac = parse_flac(read_raw(path)?)?;
+
+    let vendor = match flac.blocks.iter().find(|b| b.block_type == BLOCK_TYPE_VORBIS_COMMENT) {
+        Some(block) => parse_vorbis_comments(&block.data)?.0,
+        None => DEFAULT_VENDOR.to_string(),
+    };
+
+    let new_block = FlacBlock { block_type: BLOCK_TYPE_VORBIS_COMMENT, data: build_vorbis_comments(&vendor, tags) };
+    ensure!(new_block.data.len() < 1 << 24, "Vorbis comment block is too large");
+
+    match flac.blocks.iter().position(|b| b.block_type == BLOCK_TYPE_VORBIS_COMMENT) {
+        Some(i) => { flac.blocks[i] = new_block; },
+        None => {
+            // The stream info block must always come first.
+            let i = if flac.blocks.first().map_or(false, |b| b.block_type == BLOCK_TYPE_STREAMINFO) { 1 } else { 0 };
+            flac.blocks.insert(i, new_block);
+        },
+    }
+
+    let mut raw = FLAC_MAGIC.to_vec();
+
+    for (i, block) in flac.blocks.iter().enumerate() {
+        let last_flag = if i + 1 == flac.blocks.len() { 0x80 } else { 0x00 };
+        let len = block.data.len();
+
+        raw.extend_from_slice(&[last_flag | block.block_type, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+        raw.extend_from_slice(&block.data);
+    }
+
+    raw.extend_from_slice(&flac.audio);
+
+    // Write to a temporary file first, so that a failed write does not leave a truncated audio file.
+    let temp_path = path.with_extension("taggu_tmp");
+    {
+        let mut f = File::create(&temp_path)?;
+        f.write_all(&raw)?;
+    }
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
+
+    use tempdir::TempDir;
+
+    use super::{read_tags, write_tags};
+
+    /// Creates a minimal FLAC file, with an empty stream info block and some fake audio data.
+    fn create_flac_file(path: &::std::path::Path, audio: &[u8]) {
+        let mut f = File::create(path).unwrap();
+        f.write_all(b"fLaC").unwrap();
+        f.write_all(&[0x80, 0x00, 0x00, 34]).unwrap();
+        f.write_all(&[0u8; 34]).unwrap();
+        f.write_all(audio).unwrap();
+    }
+
+    #[test]
+    fn test_read_write_tags() {
+        let temp = TempDir::new("test_read_write_tags").unwrap();
+        let fp = temp.path().join("track.flac");
+        let audio = b"not really audio";
+
+        create_flac_file(&fp, audio);
+
+        assert!(read_tags(&fp).expect("Unable to read tags").is_empty());
+
+        let tags = btreemap![
+            String::from("ARTIST") => vec![String::from("Artist A"), String::from("Artist B")],
+            String::from("TITLE") => vec![String::from("Title = Something")],
+        ];
+        write_tags(&fp, &tags).expect("Unable to write tags");
+        assert_eq!(tags, read_tags(&fp).expect("Unable to read tags"));
+
+        // Rewriting with fewer tags shrinks the block.
+        let tags = btreemap![
+            String::from("TITLE") => vec![String::from("Short")],
+        ];
+        write_tags(&fp, &tags).expect("Unable to write tags");
+        assert_eq!(tags, read_tags(&fp).expect("Unable to read tags"));
+
+        // Audio data is preserved.
+        let mut raw = vec![];
+        File::open(&fp).unwrap().read_to_end(&mut raw).unwrap();
+        assert!(raw.ends_with(audio));
+
+        // Non-FLAC files are rejected.
+        let bad_fp = temp.path().join("bad.flac");
+        File::create(&bad_fp).unwrap();
+        assert!(read_tags(&bad_fp).is_err());
+        assert!(write_tags(&bad_fp, &tags).is_err());
+    }
+}
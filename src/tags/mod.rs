@@ -0,0 +1,69 @@
+// This module provides access to tags embedded in audio files.
+
+pub mod flac;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use metadata::MetaValue;
+use error::*;
+
+/// Embedded tags, mapping (upper case) tag names to their values.
+/// Tags may have multiple values.
+pub type TagMap = BTreeMap<String, Vec<String>>;
+
+fn has_ext(path: &Path, ext: &str) -> bool {
+    path.extension().and_then(|e| e.to_str()).map_or(false, |e| e.eq_ignore_ascii_case(ext))
+}
+
+/// Returns true if embedded tags can be read from and written to this file.
+pub fn is_supported<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+
+    path.is_file() && has_ext(path, "flac")
+}
+
+pub fn read_tags<P: AsRef<Path>>(path: P) -> Result<TagMap> {
+    let path = path.as_ref();
+
+    ensure!(is_supported(path), "unsupported tag format: '{}'", path.to_string_lossy());
+
+    flac::read_tags(path).chain_err(|| format!("unable to read tags: '{}'", path.to_string_lossy()))
+}
+
+pub fn write_tags<P: AsRef<Path>>(path: P, tags: &TagMap) -> Result<()> {
+    let path = path.as_ref();
+
+    ensure!(is_supported(path), "unsupported tag format: '{}'", path.to_string_lossy());
+
+    flac::write_tags(path, tags).chain_err(|| format!("unable to write tags: '{}'", path.to_string_lossy()))
+}
+
+/// Converts a meta value into tag values.
+/// Only strings and sequences of strings can be stored as tags; nil values are treated as absent.
+pub fn meta_value_as_tag_values(mv: &MetaValue) -> Option<Vec<String>> {
+    match *mv {
+        MetaValue::Nil => None,
+        MetaValue::Str(ref s) => Some(vec![s.clone()]),
+        MetaValue::Seq(ref mvs) => {
+            let mut vals = vec![];
+
+            for mv in mvs {
+                match *mv {
+                    MetaValue::Str(ref s) => vals.push(s.clone()),
+                    _ => return None,
+                }
+            }
+
+            Some(vals)
+        },
+        MetaValue::Map(_) => None,
+    }
+}
+
+pub fn tag_values_as_meta_value(vals: &[String]) -> MetaValue {
+    match vals.len() {
+        1 => MetaValue::Str(vals[0].clone()),
+        _ => MetaValue::Seq(vals.iter().cloned().map(MetaValue::Str).collect()),
+    }
+}
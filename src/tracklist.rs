@@ -0,0 +1,268 @@
+// This module checks that directories hold the tracks that their tracklists say they should, in the right order.
+// A tracklist is declared in the `__tracklist` field of a directory itself, as a sequence of track titles:
+//     __tracklist: [Intro, First Song, Outro]
+// The items of the directory are matched up with its tracklist by their titles, compared without regard to case.
+
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::path::{Path, PathBuf};
+
+use library::WalkStep;
+use lookup::LookupContext;
+use lookup::options::LookupOptions;
+use metadata::{MetaKey, MetaValue};
+use metadata::keys::TRACKLIST_KEY;
+use helpers::normalize;
+use error::*;
+
+const TITLE_FIELD: &str = "title";
+
+/// A way in which the items of a directory differ from its tracklist.
+/// Positions are counted from one, as they would be printed on a release.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackIssue {
+    /// A track on the tracklist that no item has the title of.
+    Missing(usize, String),
+    /// An item whose title is not on the tracklist, or that has no title at all.
+    Extra(PathBuf),
+    /// An item that comes before or after where its track is on the tracklist.
+    Misordered(PathBuf, usize),
+}
+
+/// The issues found with the items of a directory that has a tracklist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracklistReport {
+    pub dir_path: PathBuf,
+    pub issues: Vec<TrackIssue>,
+}
+
+impl Display for TracklistReport {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}: items do not match the tracklist", self.dir_path.to_string_lossy())?;
+
+        for issue in &self.issues {
+            match *issue {
+                TrackIssue::Missing(position, ref title) => write!(f, "\n    missing: {}. {}", position, title)?,
+                TrackIssue::Extra(ref item_path) => write!(f, "\n    extra: {}", item_name(item_path))?,
+                TrackIssue::Misordered(ref item_path, position) => write!(f, "\n    out of order: {}, expected at {}", item_name(item_path), position)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn item_name(item_path: &Path) -> String {
+    item_path.file_name().map_or_else(|| item_path.to_string_lossy().into_owned(), |s| s.to_string_lossy().into_owned())
+}
+
+fn fold_title(title: &str) -> String {
+    title.trim().to_lowercase()
+}
+
+/// Reads the titles of the tracks on a tracklist, which may be strings or blocks with a `title` field.
+pub fn read_tracklist(mv: &MetaValue) -> Result<Vec<String>> {
+    let mvs = match *mv {
+        MetaValue::Seq(ref mvs) => mvs,
+        _ => bail!("'{}' must be a sequence of track titles", TRACKLIST_KEY),
+    };
+
+    mvs.iter()
+        .map(|mv| match *mv {
+            MetaValue::Str(ref title) => Ok(title.clone()),
+            MetaValue::Map(ref map) => match map.get(&MetaKey::Str(TITLE_FIELD.to_string())) {
+                Some(&MetaValue::Str(ref title)) => Ok(title.clone()),
+                _ => bail!("tracks in '{}' must have a title", TRACKLIST_KEY),
+            },
+            _ => bail!("'{}' must be a sequence of track titles", TRACKLIST_KEY),
+        })
+        .collect()
+}
+
+/// Finds the positions of the longest increasing run (not necessarily contiguous) within a sequence.
+fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    // For each value, the length of the longest run ending with it, and the value before it in that run.
+    let mut lengths: Vec<usize> = vec![];
+    let mut previous: Vec<Option<usize>> = vec![];
+
+    for (i, &value) in values.iter().enumerate() {
+        let best = (0..i).filter(|&j| values[j] < value).max_by_key(|&j| lengths[j]);
+
+        lengths.push(best.map_or(1, |j| lengths[j] + 1));
+        previous.push(best);
+    }
+
+    let mut run = vec![];
+    let mut opt_i = (0..values.len()).max_by_key(|&i| (lengths[i], usize::max_value() - i));
+
+    while let Some(i) = opt_i {
+        run.push(i);
+        opt_i = previous[i];
+    }
+
+    run.reverse();
+    run
+}
+
+/// Compares the items of a directory, in order, with its tracklist.
+/// Each track is matched with the first item with its title that has not been matched yet, so tracks with the same title are matched in order.
+/// Of the matched items, the fewest possible are reported as misordered, such that the rest are in tracklist order.
+pub fn compare_tracklist(tracklist: &[String], items: &[(PathBuf, Option<String>)]) -> Vec<TrackIssue> {
+    let mut matched_tracks = vec![false; tracklist.len()];
+    let mut matched_items: Vec<(usize, usize)> = vec![];
+    let mut issues = vec![];
+
+    for (item_index, &(ref item_path, ref opt_title)) in items.iter().enumerate() {
+        let opt_track_index = opt_title.as_ref().and_then(|title| {
+            let title = fold_title(title);
+            (0..tracklist.len()).find(|&t| !matched_tracks[t] && fold_title(&tracklist[t]) == title)
+        });
+
+        match opt_track_index {
+            Some(t) => {
+                matched_tracks[t] = true;
+                matched_items.push((item_index, t));
+            },
+            None => issues.push(TrackIssue::Extra(item_path.clone())),
+        }
+    }
+
+    let track_indices: Vec<usize> = matched_items.iter().map(|&(_, t)| t).collect();
+    let in_order = longest_increasing(&track_indices);
+
+    for (k, &(item_index, t)) in matched_items.iter().enumerate() {
+        if !in_order.contains(&k) {
+            issues.push(TrackIssue::Misordered(items[item_index].0.clone(), t + 1));
+        }
+    }
+
+    for (t, title) in tracklist.iter().enumerate() {
+        if !matched_tracks[t] {
+            issues.push(TrackIssue::Missing(t + 1, title.clone()));
+        }
+    }
+
+    issues
+}
+
+/// Checks every directory with a tracklist in a directory tree, including the directory itself, in walk order.
+/// Items marked as ignored are left out, and directories without issues are not reported.
+pub fn check_tracklists<P: AsRef<Path>>(lookup_ctx: &mut LookupContext, abs_dir_path: P) -> Result<Vec<TracklistReport>> {
+    let abs_dir_path = normalize(abs_dir_path.as_ref());
+    let media_lib = lookup_ctx.media_lib();
+
+    // Rule: dir path must be a directory.
+    ensure!(media_lib.vfs().is_dir(&abs_dir_path), ErrorKind::NotADirectory(abs_dir_path.clone()));
+
+    let title_options = LookupOptions::new(TITLE_FIELD).join_seq(", ");
+    let mut reports = vec![];
+    let mut items = vec![];
+
+    media_lib.walk_items(&abs_dir_path, |step| {
+        match step {
+            WalkStep::EnterDir(_) => items.clear(),
+            WalkStep::Item { path, .. } => {
+                if lookup_ctx.is_ignored(path)? {
+                    return Ok(false);
+                }
+
+                let opt_title = match lookup_ctx.lookup(path, &title_options)? {
+                    Some(MetaValue::Str(title)) => Some(title),
+                    _ => None,
+                };
+
+                items.push((path.to_path_buf(), opt_title));
+            },
+            WalkStep::LeaveDir(dir_path) => {
+                // The tracklist belongs to the directory itself, and is not inherited by directories below it.
+                let opt_tracklist = lookup_ctx.merged_origin_block(dir_path)?.get(TRACKLIST_KEY).cloned();
+
+                if let Some(tracklist_mv) = opt_tracklist {
+                    let tracklist = read_tracklist(&tracklist_mv).chain_err(|| format!("invalid tracklist: '{}'", dir_path.to_string_lossy()))?;
+                    let issues = compare_tracklist(&tracklist, &items);
+
+                    if !issues.is_empty() {
+                        reports.push(TracklistReport { dir_path: dir_path.to_path_buf(), issues });
+                    }
+                }
+            },
+        }
+
+        Ok(true)
+    })?;
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use lookup::LookupContext;
+    use metadata::{MetaKey, MetaValue};
+    use fixtures::default_setup;
+
+    use super::{TrackIssue, read_tracklist, compare_tracklist, check_tracklists};
+
+    #[test]
+    fn test_compare_tracklist() {
+        let tracklist: Vec<String> = vec!["A", "B", "C", "D", "A"].into_iter().map(String::from).collect();
+        let item = |name: &str, opt_title: Option<&str>| (PathBuf::from(name), opt_title.map(String::from));
+
+        let items = vec![item("1", Some("a")), item("2", Some("B")), item("3", Some("C")), item("4", Some("D")), item("5", Some("A"))];
+        assert_eq!(Vec::<TrackIssue>::new(), compare_tracklist(&tracklist, &items));
+
+        // A track moved to the front is the one reported, rather than every track it moved ahead of.
+        let items = vec![item("4", Some("D")), item("1", Some("A")), item("2", Some("B")), item("3", Some("C")), item("x", None), item("y", Some("Z"))];
+        assert_eq!(
+            vec![
+                TrackIssue::Extra(PathBuf::from("x")),
+                TrackIssue::Extra(PathBuf::from("y")),
+                TrackIssue::Misordered(PathBuf::from("4"), 4),
+                TrackIssue::Missing(5, String::from("A")),
+            ],
+            compare_tracklist(&tracklist, &items)
+        );
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+        let track = MetaValue::Map(btreemap![MetaKey::Str(String::from("title")) => str_val("B")]);
+        assert_eq!(vec!["A", "B"], read_tracklist(&MetaValue::Seq(vec![str_val("A"), track])).unwrap());
+        assert!(read_tracklist(&str_val("A")).is_err());
+        assert!(read_tracklist(&MetaValue::Seq(vec![MetaValue::Map(btreemap![])])).is_err());
+    }
+
+    #[test]
+    fn test_check_tracklists() {
+        let (temp_media_root, media_lib) = default_setup("test_check_tracklists");
+        let tp = temp_media_root.path();
+
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+        let mut f = File::create(disc_fp.join("item.yml")).unwrap();
+        writeln!(f, "- title: One\n- title: Three\n- title: Two").unwrap();
+
+        fs::write(disc_fp.join("self.yml"), "__tracklist: [One, Two, Three, Four]\n").unwrap();
+        fs::write(tp.join("ALBUM_01").join("DISC_02").join("self.yml"), "__tracklist: []\n").unwrap();
+
+        let reports = check_tracklists(&mut LookupContext::new(&media_lib), tp).unwrap();
+        assert_eq!(2, reports.len());
+
+        assert_eq!(disc_fp, reports[0].dir_path);
+        assert_eq!(
+            vec![
+                TrackIssue::Misordered(disc_fp.join("TRACK_03.flac"), 2),
+                TrackIssue::Missing(4, String::from("Four")),
+            ],
+            reports[0].issues
+        );
+
+        // Items without a title are extra.
+        let disc_02_fp = tp.join("ALBUM_01").join("DISC_02");
+        assert_eq!(disc_02_fp, reports[1].dir_path);
+        assert_eq!(3, reports[1].issues.len());
+        assert_eq!(TrackIssue::Extra(disc_02_fp.join("TRACK_01.flac")), reports[1].issues[0]);
+
+        fs::write(disc_fp.join("self.yml"), "__tracklist: One\n").unwrap();
+        assert!(check_tracklists(&mut LookupContext::new(&media_lib), &disc_fp).is_err());
+    }
+}
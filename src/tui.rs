@@ -0,0 +1,532 @@
+// This module provides a terminal browser for a library, which shows its tree along with the resolved metadata of the selected item.
+// Fields can be edited in place, and the selected item is linted against the schema as it changes.
+// The state of the browser is kept apart from drawing it, so that everything but the terminal itself goes through the usual library APIs.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crossterm::{ExecutableCommand, QueueableCommand};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+
+use lookup::LookupContext;
+use lookup::resolved::ResolvedField;
+use metadata::{MetaBlock, MetaValue};
+use multivalue::SplitRules;
+use schema::{Schema, ViolationKind};
+use writer::plan_edit_item_block;
+use plan::WritePlan;
+use undo::UndoJournal;
+use error::*;
+
+const HELP_TEXT: &str = "j/k: move  enter/l: open  h: back  tab: switch pane  e: edit  a: add  d: delete  q: quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    Items,
+    Fields,
+}
+
+/// What a line of input typed at the prompt is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PromptAction {
+    /// Sets the value of an existing field.
+    Edit(String),
+    /// Adds a field, typed as `<field>=<value>`.
+    Add,
+}
+
+/// The state of the browser: the directory being shown, the selections in its panes, and the metadata of the selected item.
+pub struct Browser<'a> {
+    lookup_ctx: LookupContext<'a>,
+    schema: Schema,
+    split_rules: SplitRules,
+    dir_path: PathBuf,
+    entries: Vec<PathBuf>,
+    selected: usize,
+    fields: Vec<(String, ResolvedField)>,
+    selected_field: usize,
+    pane: Pane,
+    lints: Vec<String>,
+    message: Option<String>,
+}
+
+impl<'a> Browser<'a> {
+    /// Creates a browser showing the root directory of a library.
+    pub fn new(lookup_ctx: LookupContext<'a>, schema: Schema, split_rules: SplitRules) -> Result<Browser<'a>> {
+        let root_dir = lookup_ctx.media_lib().root_dir().to_path_buf();
+
+        let mut browser = Browser {
+            lookup_ctx,
+            schema,
+            split_rules,
+            dir_path: root_dir.clone(),
+            entries: vec![],
+            selected: 0,
+            fields: vec![],
+            selected_field: 0,
+            pane: Pane::Items,
+            lints: vec![],
+            message: None,
+        };
+
+        browser.open_dir(root_dir, None)?;
+        Ok(browser)
+    }
+
+    pub fn dir_path(&self) -> &Path {
+        &self.dir_path
+    }
+
+    pub fn entries(&self) -> &[PathBuf] {
+        &self.entries
+    }
+
+    pub fn selected_item(&self) -> Option<&Path> {
+        self.entries.get(self.selected).map(PathBuf::as_path)
+    }
+
+    pub fn fields(&self) -> &[(String, ResolvedField)] {
+        &self.fields
+    }
+
+    pub fn selected_field(&self) -> Option<&str> {
+        self.fields.get(self.selected_field).map(|&(ref f, _)| f.as_str())
+    }
+
+    pub fn pane(&self) -> Pane {
+        self.pane
+    }
+
+    pub fn lints(&self) -> &[String] {
+        &self.lints
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_ref().map(String::as_str)
+    }
+
+    /// Shows a directory, selecting one of its entries if given, and otherwise the first one.
+    /// Items marked as ignored are left out, as they are in walks over the library.
+    fn open_dir(&mut self, dir_path: PathBuf, opt_select: Option<&Path>) -> Result<()> {
+        let media_lib = self.lookup_ctx.media_lib();
+        let mut entries = vec![];
+
+        for child_path in media_lib.walk_children_paths(&dir_path)? {
+            if !self.lookup_ctx.is_ignored(&child_path)? {
+                entries.push(child_path);
+            }
+        }
+
+        self.selected = opt_select.and_then(|p| entries.iter().position(|e| e == p)).unwrap_or(0);
+        self.entries = entries;
+        self.dir_path = dir_path;
+        self.pane = Pane::Items;
+
+        self.refresh_item()
+    }
+
+    /// Resolves the metadata of the selected item again, and lints it.
+    fn refresh_item(&mut self) -> Result<()> {
+        self.fields.clear();
+        self.lints.clear();
+
+        let item_path = match self.entries.get(self.selected) {
+            Some(item_path) => item_path.clone(),
+            None => {
+                self.selected_field = 0;
+                return Ok(());
+            },
+        };
+
+        let resolved = self.lookup_ctx.resolve_block(&item_path)?;
+        self.fields = resolved.iter().map(|(f, rf)| (f.clone(), rf.clone())).collect();
+        self.selected_field = self.selected_field.min(self.fields.len().saturating_sub(1));
+
+        let fold_case = self.lookup_ctx.media_lib().fold_field_case();
+        let origin_block = self.lookup_ctx.merged_origin_block(&item_path)?;
+
+        for (field_name, kind) in self.schema.check_block_with_case(&origin_block, fold_case) {
+            self.lints.push(format!("{}: {}", field_name, kind));
+        }
+
+        for field_name in self.schema.required_fields() {
+            if resolved.get(field_name).is_none() {
+                self.lints.push(format!("{}: {}", field_name, ViolationKind::Missing));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the selection in the focused pane up or down, stopping at either end.
+    pub fn move_selection(&mut self, delta: isize) -> Result<()> {
+        match self.pane {
+            Pane::Items => {
+                let last = self.entries.len().saturating_sub(1) as isize;
+                let selected = (self.selected as isize + delta).max(0).min(last) as usize;
+
+                if selected != self.selected {
+                    self.selected = selected;
+                    self.selected_field = 0;
+                    self.refresh_item()?;
+                }
+            },
+            Pane::Fields => {
+                let last = self.fields.len().saturating_sub(1) as isize;
+                self.selected_field = (self.selected_field as isize + delta).max(0).min(last) as usize;
+            },
+        }
+
+        Ok(())
+    }
+
+    pub fn switch_pane(&mut self) {
+        self.pane = match self.pane {
+            Pane::Items if !self.fields.is_empty() => Pane::Fields,
+            _ => Pane::Items,
+        };
+    }
+
+    /// Opens the selected item, if it is a directory.
+    pub fn enter(&mut self) -> Result<()> {
+        match self.selected_item().map(Path::to_path_buf) {
+            Some(ref item_path) if item_path.is_dir() => self.open_dir(item_path.clone(), None),
+            _ => Ok(()),
+        }
+    }
+
+    /// Goes up to the parent directory, with the directory that was left selected, but never above the library root.
+    pub fn leave(&mut self) -> Result<()> {
+        if self.dir_path == self.lookup_ctx.media_lib().root_dir() {
+            return Ok(());
+        }
+
+        let dir_path = self.dir_path.clone();
+
+        match dir_path.parent() {
+            Some(parent_path) => self.open_dir(parent_path.to_path_buf(), Some(&dir_path)),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets a field of the selected item, or removes it if no value is given, recording the change for undo.
+    /// Values are split into sequences by the split rules for their field.
+    /// Only fields in the item's own block can be removed, since an inherited field would still be inherited.
+    pub fn set_field(&mut self, field_name: &str, opt_value: Option<&str>, undo_journal: &UndoJournal) -> Result<()> {
+        let item_path = match self.selected_item() {
+            Some(item_path) => item_path.to_path_buf(),
+            None => return Ok(()),
+        };
+
+        if opt_value.is_none() {
+            let is_origin = self.fields.iter().any(|&(ref f, ref rf)| f == field_name && rf.source.is_origin());
+
+            if !is_origin {
+                self.message = Some(format!("'{}' is inherited, and can only be removed where it is set", field_name));
+                return Ok(());
+            }
+        }
+
+        let opt_mv = opt_value.map(|v| self.split_rules.normalize(field_name, &MetaValue::Str(v.to_string())));
+
+        let mut plan = WritePlan::new();
+        let meta_fp = plan_edit_item_block(self.lookup_ctx.media_lib(), &mut plan, &item_path, |mb: &mut MetaBlock| {
+            match opt_mv {
+                Some(mv) => { mb.insert(field_name.to_string(), mv); },
+                None => { mb.remove(field_name); },
+            }
+        })?;
+
+        undo_journal.record("tui", &[], &plan)?;
+        plan.execute()?;
+
+        // The edited meta file may be cached, along with blocks inherited from it.
+        self.lookup_ctx.clear();
+
+        self.message = Some(format!("wrote: {}", meta_fp.to_string_lossy()));
+        self.refresh_item()
+    }
+}
+
+/// Shortens a string to fit a number of columns, counting characters.
+fn fit(s: &str, width: usize) -> String {
+    if width == 0 {
+        return String::new();
+    }
+
+    if s.chars().count() <= width {
+        return s.to_string();
+    }
+
+    let mut fitted: String = s.chars().take(width - 1).collect();
+    fitted.push('~');
+    fitted
+}
+
+fn value_text(mv: &MetaValue) -> String {
+    match *mv {
+        MetaValue::Nil => String::from("~"),
+        MetaValue::Str(ref s) => s.clone(),
+        MetaValue::Seq(ref mvs) => mvs.iter().map(value_text).collect::<Vec<_>>().join("; "),
+        MetaValue::Map(_) => mv.to_string().replace('\n', " "),
+    }
+}
+
+/// The lines of the fields pane, where inherited fields are marked with the meta file they come from.
+fn field_lines(browser: &Browser, root_dir: &Path) -> Vec<String> {
+    browser.fields().iter().map(|&(ref field_name, ref rf)| {
+        if rf.source.is_origin() {
+            format!("{}: {}", field_name, value_text(&rf.value))
+        }
+        else {
+            let meta_path = rf.source.meta_path.strip_prefix(root_dir).unwrap_or(&rf.source.meta_path);
+            format!("{}: {}  (from {})", field_name, value_text(&rf.value), meta_path.to_string_lossy())
+        }
+    }).collect()
+}
+
+fn draw<W: Write>(out: &mut W, browser: &Browser, root_dir: &Path, opt_prompt: Option<&str>) -> Result<()> {
+    let (width, height) = terminal::size()?;
+    let (width, height) = (width as usize, height as usize);
+
+    // The items pane takes a third of the width, and the lints and status line take the bottom of the screen.
+    let items_width = (width / 3).max(1);
+    let fields_width = width.saturating_sub(items_width + 1);
+    let lint_rows = browser.lints().len().min(height / 4);
+    let pane_rows = height.saturating_sub(lint_rows + 3);
+
+    out.queue(Clear(ClearType::All))?;
+
+    let rel_dir_path = browser.dir_path().strip_prefix(root_dir).unwrap_or(browser.dir_path());
+    out.queue(MoveTo(0, 0))?.queue(SetAttribute(Attribute::Bold))?
+        .queue(Print(fit(&format!("/{}", rel_dir_path.to_string_lossy()), width)))?
+        .queue(SetAttribute(Attribute::Reset))?;
+
+    // Scroll the panes so that their selections stay in view.
+    let item_offset = (browser.selected + 1).saturating_sub(pane_rows);
+
+    for (row, entry) in browser.entries().iter().enumerate().skip(item_offset).take(pane_rows) {
+        let mut name = entry.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+        if entry.is_dir() {
+            name.push('/');
+        }
+
+        let selected = row == browser.selected;
+        let marker = if selected && browser.pane() == Pane::Items { "> " } else if selected { "* " } else { "  " };
+
+        out.queue(MoveTo(0, (row - item_offset + 1) as u16))?.queue(Print(fit(&format!("{}{}", marker, name), items_width)))?;
+    }
+
+    let field_offset = (browser.selected_field + 1).saturating_sub(pane_rows);
+
+    for (row, line) in field_lines(browser, root_dir).into_iter().enumerate().skip(field_offset).take(pane_rows) {
+        let marker = if row == browser.selected_field && browser.pane() == Pane::Fields { "> " } else { "  " };
+
+        out.queue(MoveTo((items_width + 1) as u16, (row - field_offset + 1) as u16))?.queue(Print(fit(&format!("{}{}", marker, line), fields_width)))?;
+    }
+
+    for (i, lint) in browser.lints().iter().take(lint_rows).enumerate() {
+        out.queue(MoveTo(0, (pane_rows + 1 + i) as u16))?.queue(Print(fit(&format!("! {}", lint), width)))?;
+    }
+
+    let status = match opt_prompt {
+        Some(prompt) => prompt.to_string(),
+        None => browser.message().unwrap_or(HELP_TEXT).to_string(),
+    };
+
+    out.queue(MoveTo(0, height.saturating_sub(1) as u16))?.queue(Print(fit(&status, width)))?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Reads a line of input at the bottom of the screen, returning `None` if it is cancelled with escape.
+fn read_line<W: Write>(out: &mut W, browser: &Browser, root_dir: &Path, label: &str, initial: &str) -> Result<Option<String>> {
+    let mut buffer = initial.to_string();
+
+    loop {
+        draw(out, browser, root_dir, Some(&format!("{}: {}_", label, buffer)))?;
+
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Enter => return Ok(Some(buffer)),
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Backspace => { buffer.pop(); },
+                KeyCode::Char(c) => { buffer.push(c); },
+                _ => {},
+            }
+        }
+    }
+}
+
+fn handle_prompt(browser: &mut Browser, action: PromptAction, input: &str, undo_journal: &UndoJournal) -> Result<()> {
+    let (field_name, value) = match action {
+        PromptAction::Edit(field_name) => (field_name, input.to_string()),
+        PromptAction::Add => {
+            match input.find('=') {
+                Some(i) if !input[..i].trim().is_empty() => (input[..i].trim().to_string(), input[i + 1..].to_string()),
+                _ => {
+                    browser.message = Some(String::from("fields are added as <field>=<value>"));
+                    return Ok(());
+                },
+            }
+        },
+    };
+
+    // An empty value removes the field, as it does for apply.
+    let value = value.trim();
+    browser.set_field(&field_name, if value.is_empty() { None } else { Some(value) }, undo_journal)
+}
+
+fn run_loop<W: Write>(out: &mut W, browser: &mut Browser, root_dir: &Path, undo_journal: &UndoJournal) -> Result<()> {
+    loop {
+        draw(out, browser, root_dir, None)?;
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+
+        browser.message = None;
+
+        let opt_prompt = match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('j') | KeyCode::Down => { browser.move_selection(1)?; None },
+            KeyCode::Char('k') | KeyCode::Up => { browser.move_selection(-1)?; None },
+            KeyCode::Char('l') | KeyCode::Enter | KeyCode::Right => { browser.enter()?; None },
+            KeyCode::Char('h') | KeyCode::Backspace | KeyCode::Left => { browser.leave()?; None },
+            KeyCode::Tab => { browser.switch_pane(); None },
+            KeyCode::Char('a') if browser.selected_item().is_some() => Some((PromptAction::Add, String::from("add <field>=<value>"), String::new())),
+            KeyCode::Char('e') if browser.pane() == Pane::Fields => {
+                browser.selected_field().map(String::from).map(|field_name| {
+                    let initial = browser.fields()[browser.selected_field].1.value.clone();
+                    (PromptAction::Edit(field_name.clone()), format!("set {}", field_name), value_text(&initial))
+                })
+            },
+            KeyCode::Char('d') if browser.pane() == Pane::Fields => {
+                if let Some(field_name) = browser.selected_field().map(String::from) {
+                    report(browser, |b| b.set_field(&field_name, None, undo_journal));
+                }
+                None
+            },
+            _ => None,
+        };
+
+        if let Some((action, label, initial)) = opt_prompt {
+            if let Some(input) = read_line(out, browser, root_dir, &label, &initial)? {
+                report(browser, |b| handle_prompt(b, action, &input, undo_journal));
+            }
+        }
+    }
+}
+
+/// Shows the error of a failed edit in the status line, instead of leaving the browser.
+fn report<F>(browser: &mut Browser, f: F)
+where F: FnOnce(&mut Browser) -> Result<()>,
+{
+    if let Err(e) = f(browser) {
+        browser.message = Some(format!("error: {}", e));
+    }
+}
+
+/// Runs the browser in the terminal until it is quit, putting the terminal back as it was afterwards.
+pub fn run(lookup_ctx: LookupContext, schema: Schema, split_rules: SplitRules, undo_journal: &UndoJournal) -> Result<()> {
+    let root_dir = lookup_ctx.media_lib().root_dir().to_path_buf();
+    let mut browser = Browser::new(lookup_ctx, schema, split_rules)?;
+    let mut out = io::stdout();
+
+    terminal::enable_raw_mode()?;
+    out.execute(EnterAlternateScreen)?.execute(Hide)?;
+
+    let result = run_loop(&mut out, &mut browser, &root_dir, undo_journal);
+
+    // The terminal is put back even if the browser failed, so that the error can be read.
+    out.execute(Show)?.execute(LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use lookup::LookupContext;
+    use metadata::MetaValue;
+    use multivalue::SplitRules;
+    use schema::{Schema, FieldSpec};
+    use undo::UndoJournal;
+    use fixtures::default_setup;
+
+    use super::{Browser, Pane, fit};
+
+    #[test]
+    fn test_browser() {
+        let (temp_media_root, media_lib) = default_setup("test_browser");
+        let tp = temp_media_root.path();
+
+        let mut schema = Schema::new();
+        schema.field("title", FieldSpec::new().required(true));
+
+        let mut browser = Browser::new(LookupContext::new(&media_lib), schema, SplitRules::default()).unwrap();
+        assert_eq!(tp, browser.dir_path());
+        assert_eq!(Some(tp.join("ALBUM_01").as_path()), browser.selected_item());
+        assert_eq!(vec!["title: required field is missing"], browser.lints());
+
+        // Going up stops at the library root, and coming back out of a directory selects it.
+        browser.leave().unwrap();
+        assert_eq!(tp, browser.dir_path());
+        browser.enter().unwrap();
+        browser.move_selection(5).unwrap();
+        assert_eq!(tp.join("ALBUM_01"), browser.dir_path());
+        assert_eq!(Some(tp.join("ALBUM_01").join("DISC_02").as_path()), browser.selected_item());
+        browser.leave().unwrap();
+        assert_eq!(Some(tp.join("ALBUM_01").as_path()), browser.selected_item());
+
+        browser.enter().unwrap();
+        browser.enter().unwrap();
+        assert_eq!(Some(tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac").as_path()), browser.selected_item());
+
+        let field = |b: &Browser, name: &str| b.fields().iter().find(|&&(ref f, _)| f == name).map(|&(_, ref rf)| (rf.value.clone(), rf.source.is_origin()));
+        assert_eq!(Some((MetaValue::Str(String::from("item_val")), true)), field(&browser, "item_key"));
+        assert_eq!(Some((MetaValue::Str(String::from("self_val")), false)), field(&browser, "self_key"));
+
+        let undo_journal = UndoJournal::new(tp);
+        browser.set_field("title", Some("Song; Other"), &undo_journal).unwrap();
+        assert_eq!(Some((MetaValue::Str(String::from("Song; Other")), true)), field(&browser, "title"));
+        assert!(browser.lints().is_empty());
+
+        browser.set_field("genre", Some("Rock; Jazz"), &undo_journal).unwrap();
+        assert_eq!(Some((MetaValue::Seq(vec![MetaValue::Str(String::from("Rock")), MetaValue::Str(String::from("Jazz"))]), true)), field(&browser, "genre"));
+
+        // Removing a field of the item itself uncovers the value that it inherits from the disc.
+        browser.set_field("item_key", None, &undo_journal).unwrap();
+        assert_eq!(Some((MetaValue::Str(String::from("item_val")), false)), field(&browser, "item_key"));
+
+        // Inherited fields are not removed.
+        browser.set_field("self_key", None, &undo_journal).unwrap();
+        assert!(field(&browser, "self_key").is_some());
+        assert!(browser.message().unwrap().contains("inherited"));
+
+        assert_eq!(3, undo_journal.batches().unwrap().len());
+
+        browser.switch_pane();
+        assert_eq!(Pane::Fields, browser.pane());
+        browser.move_selection(100).unwrap();
+        assert_eq!(Some(browser.fields().last().unwrap().0.as_str()), browser.selected_field());
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!("abc", fit("abc", 3));
+        assert_eq!("ab~", fit("abcd", 3));
+        assert_eq!("~", fit("abcd", 1));
+        assert_eq!("", fit("abcd", 0));
+    }
+}
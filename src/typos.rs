@@ -0,0 +1,209 @@
+// This module hunts for typos among the values of a field, by finding values that are used in the library and look almost the same,
+// e.g. "psytrance" and "psy-trance", or "Aphex Twin" and "aphex twin".
+// Values are compared without regard to case, and then by the number of characters that would need to be inserted, removed or replaced to turn one into the other.
+// Values that only differ in their digits, e.g. "Disc 1" and "Disc 2", are told apart on purpose, and are never reported.
+
+use std::fmt::{Formatter, Result as FmtResult, Display};
+use std::path::PathBuf;
+
+use library::Library;
+use error::*;
+
+/// The fields checked for typos when no others are asked for.
+pub const DEFAULT_TYPO_FIELDS: &[&str] = &["artist", "album_artist", "composer", "genre"];
+
+/// A value of a field, along with how many items use it, and the meta files it is used in, sorted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueUse {
+    pub value: String,
+    pub item_count: usize,
+    pub meta_fps: Vec<PathBuf>,
+}
+
+/// Values of a field that look almost the same, most used first.
+/// The most used value is likely the intended one, and the others typos of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimilarValues {
+    pub field_name: String,
+    pub values: Vec<ValueUse>,
+}
+
+impl Display for SimilarValues {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}: similar values, possibly typos of '{}'", self.field_name, self.values[0].value)?;
+
+        for (i, value_use) in self.values.iter().enumerate() {
+            let items = if value_use.item_count == 1 { "item" } else { "items" };
+            write!(f, "\n    '{}' ({} {})", value_use.value, value_use.item_count, items)?;
+
+            // The meta files of the likely intended value are not the ones that need fixing.
+            if i > 0 {
+                for meta_fp in &value_use.meta_fps {
+                    write!(f, "\n        {}", meta_fp.to_string_lossy())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts the characters that need to be inserted, removed or replaced to turn one string into another.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    // Only the previous row of the table is kept.
+    let mut prev_row: Vec<usize> = (0..b.len() + 1).collect();
+
+    for (i, ca) in a.iter().enumerate() {
+        let mut row = vec![i + 1];
+
+        for (j, cb) in b.iter().enumerate() {
+            let replace = prev_row[j] + if ca == cb { 0 } else { 1 };
+            row.push(replace.min(prev_row[j + 1] + 1).min(row[j] + 1));
+        }
+
+        prev_row = row;
+    }
+
+    prev_row[b.len()]
+}
+
+/// How many edits a value of a length can differ by and still look like a typo, where shorter values allow fewer.
+fn max_edits(len: usize) -> usize {
+    match len {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Returns true if two different values look so much alike that one is probably a typo of the other.
+pub fn is_similar(value_a: &str, value_b: &str) -> bool {
+    let folded_a: Vec<char> = value_a.trim().to_lowercase().chars().collect();
+    let folded_b: Vec<char> = value_b.trim().to_lowercase().chars().collect();
+
+    if folded_a == folded_b {
+        return true;
+    }
+
+    let digits = |cs: &[char]| -> Vec<char> { cs.iter().cloned().filter(|c| c.is_numeric()).collect() };
+
+    if digits(&folded_a) != digits(&folded_b) {
+        return false;
+    }
+
+    let max = max_edits(folded_a.len().min(folded_b.len()));
+    let len_diff = if folded_a.len() > folded_b.len() { folded_a.len() - folded_b.len() } else { folded_b.len() - folded_a.len() };
+
+    // The lengths alone rule out most pairs, without working out the distance.
+    max > 0 && len_diff <= max && edit_distance(&folded_a, &folded_b) <= max
+}
+
+/// Finds the groups of similar values of a field in a library, sorted by their most used value.
+/// Values are grouped with every value they are similar to, and with the values those are similar to in turn.
+pub fn find_similar_values(media_lib: &Library, field_name: &str) -> Result<Vec<SimilarValues>> {
+    let histogram = media_lib.field_histogram(field_name)?;
+    let mut value_meta_fps = media_lib.field_value_meta_fps(field_name)?;
+
+    let values: Vec<&String> = histogram.keys().collect();
+
+    // Each value starts out in a group of its own, and groups are merged as similar values are found.
+    let mut group_of: Vec<usize> = (0..values.len()).collect();
+
+    fn find_group(group_of: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+
+        while group_of[root] != root {
+            root = group_of[root];
+        }
+
+        group_of[i] = root;
+        root
+    }
+
+    for i in 0..values.len() {
+        for j in (i + 1)..values.len() {
+            if is_similar(values[i], values[j]) {
+                let (group_i, group_j) = (find_group(&mut group_of, i), find_group(&mut group_of, j));
+                group_of[group_j] = group_i;
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<ValueUse>> = vec![vec![]; values.len()];
+
+    for (i, value) in values.iter().enumerate() {
+        let group = find_group(&mut group_of, i);
+
+        groups[group].push(ValueUse {
+            value: value.to_string(),
+            item_count: histogram[*value],
+            meta_fps: value_meta_fps.remove(*value).map(|fps| fps.into_iter().collect()).unwrap_or_default(),
+        });
+    }
+
+    let mut reports: Vec<SimilarValues> = {
+        groups.into_iter()
+            .filter(|group| group.len() > 1)
+            .map(|mut group| {
+                group.sort_by(|a, b| b.item_count.cmp(&a.item_count).then_with(|| a.value.cmp(&b.value)));
+                SimilarValues { field_name: field_name.to_string(), values: group }
+            })
+            .collect()
+    };
+
+    reports.sort_by(|a, b| a.values[0].value.cmp(&b.values[0].value));
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use fixtures::default_setup;
+
+    use super::{is_similar, edit_distance, find_similar_values};
+
+    #[test]
+    fn test_is_similar() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(3, edit_distance(&chars("kitten"), &chars("sitting")));
+        assert_eq!(0, edit_distance(&chars(""), &chars("")));
+        assert_eq!(2, edit_distance(&chars("ab"), &chars("")));
+
+        assert!(is_similar("psytrance", "psy-trance"));
+        assert!(is_similar("Aphex Twin", "aphex twin "));
+        assert!(is_similar("Bjork", "Björk"));
+        assert!(is_similar("EP", "ep"));
+        assert!(!is_similar("EP", "LP"));
+        assert!(!is_similar("Disc 1", "Disc 2"));
+        assert!(!is_similar("trance", "psytrance"));
+        assert!(!is_similar("Rock", "Pop"));
+    }
+
+    #[test]
+    fn test_find_similar_values() {
+        let (temp_media_root, media_lib) = default_setup("test_find_similar_values");
+        let tp = temp_media_root.path();
+        let album_dir = tp.join("ALBUM_01");
+        let disc_dir = album_dir.join("DISC_01");
+
+        fs::write(album_dir.join("item.yml"), "- genre: psytrance\n- genre: [psy-trance, Ambient]\n").unwrap();
+        fs::write(disc_dir.join("item.yml"), "- genre: psytrance\n- genre: Psy trance\n- genre: [ambient, Rock]\n").unwrap();
+
+        let reports = find_similar_values(&media_lib, "genre").unwrap();
+        assert_eq!(2, reports.len());
+
+        // Ties in use are broken by value.
+        let values: Vec<(&str, usize)> = reports[0].values.iter().map(|v| (v.value.as_str(), v.item_count)).collect();
+        assert_eq!(vec![("Ambient", 1), ("ambient", 1)], values);
+
+        // Values that differ in case as well as spelling are grouped too.
+        let values: Vec<(&str, usize)> = reports[1].values.iter().map(|v| (v.value.as_str(), v.item_count)).collect();
+        assert_eq!(vec![("psytrance", 2), ("Psy trance", 1), ("psy-trance", 1)], values);
+        assert_eq!(vec![disc_dir.join("item.yml")], reports[1].values[1].meta_fps);
+        assert!(reports[1].to_string().starts_with("genre: similar values, possibly typos of 'psytrance'"));
+
+        assert!(find_similar_values(&media_lib, "artist").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,338 @@
+// This module keeps a journal of the changes made by mutating commands, so that a bad bulk edit can be undone.
+// Each command that writes anything records a batch: copies of the files it is about to change, the embedded tags it is about to replace,
+// and the items it is about to move. Undoing the last batch puts all of those back, and then drops the batch.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
+
+use plan::{WritePlan, WriteOp};
+use tags::{self, TagMap};
+use yaml::{read_yaml_file, write_yaml_file, yaml_as_string};
+use error::*;
+
+/// The directory in the library root that holds the journal.
+pub const UNDO_DIR_NAME: &str = ".taggu_undo";
+
+const MANIFEST_FILE_NAME: &str = "batch.yml";
+
+/// How many batches are kept; the oldest ones are dropped when a new batch is recorded.
+const MAX_BATCHES: usize = 20;
+
+/// A single change that can be reversed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JournalEntry {
+    /// A file that was written or removed, along with the name of its copy in the batch, or `None` if it did not exist yet.
+    File { path: PathBuf, backup: Option<String> },
+    /// The embedded tags of an item, as they were before they were replaced.
+    Tags { path: PathBuf, tags: TagMap },
+    /// An item that was moved or renamed.
+    Move { from: PathBuf, to: PathBuf },
+}
+
+impl JournalEntry {
+    fn as_yaml(&self) -> Yaml {
+        let path_y = |p: &Path| Yaml::String(p.to_string_lossy().into_owned());
+
+        let pairs = match *self {
+            JournalEntry::File { ref path, ref backup } => vec![
+                ("kind", Yaml::String(String::from("file"))),
+                ("path", path_y(path)),
+                ("backup", backup.clone().map_or(Yaml::Null, Yaml::String)),
+            ],
+            JournalEntry::Tags { ref path, ref tags } => {
+                let mut tags_hsh = Hash::new();
+
+                for (tag_name, vals) in tags {
+                    tags_hsh.insert(Yaml::String(tag_name.clone()), Yaml::Array(vals.iter().cloned().map(Yaml::String).collect()));
+                }
+
+                vec![
+                    ("kind", Yaml::String(String::from("tags"))),
+                    ("path", path_y(path)),
+                    ("tags", Yaml::Hash(tags_hsh)),
+                ]
+            },
+            JournalEntry::Move { ref from, ref to } => vec![
+                ("kind", Yaml::String(String::from("move"))),
+                ("from", path_y(from)),
+                ("to", path_y(to)),
+            ],
+        };
+
+        Yaml::Hash(pairs.into_iter().map(|(k, v)| (Yaml::String(k.to_string()), v)).collect())
+    }
+
+    fn from_yaml(y: &Yaml) -> Option<JournalEntry> {
+        let path = |key: &str| y[key].as_str().map(PathBuf::from);
+
+        match y["kind"].as_str()? {
+            "file" => Some(JournalEntry::File { path: path("path")?, backup: y["backup"].as_str().map(String::from) }),
+            "tags" => {
+                let mut tags = TagMap::new();
+
+                for (tag_name_y, vals_y) in y["tags"].as_hash()? {
+                    let vals = vals_y.as_vec()?.iter().filter_map(yaml_as_string).collect();
+                    tags.insert(yaml_as_string(tag_name_y)?, vals);
+                }
+
+                Some(JournalEntry::Tags { path: path("path")?, tags })
+            },
+            "move" => Some(JournalEntry::Move { from: path("from")?, to: path("to")? }),
+            _ => None,
+        }
+    }
+}
+
+/// The changes made by a single command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndoBatch {
+    pub batch_dir_path: PathBuf,
+    pub command: String,
+    /// When the batch was recorded, in seconds since the Unix epoch.
+    pub created: u64,
+    /// Entries in the order the changes were made, so they are undone in reverse.
+    pub entries: Vec<JournalEntry>,
+}
+
+impl UndoBatch {
+    fn read<P: AsRef<Path>>(batch_dir_path: P) -> Result<UndoBatch> {
+        let batch_dir_path = batch_dir_path.as_ref();
+        let manifest_fp = batch_dir_path.join(MANIFEST_FILE_NAME);
+        let y = read_yaml_file(&manifest_fp)?;
+
+        let invalid = || format!("invalid undo journal: '{}'", manifest_fp.to_string_lossy());
+
+        let command = y["command"].as_str().ok_or_else(invalid)?.to_string();
+        let created = y["created"].as_i64().unwrap_or(0) as u64;
+        let entries = y["entries"].as_vec().ok_or_else(invalid)?.iter()
+            .map(|entry_y| JournalEntry::from_yaml(entry_y).ok_or_else(invalid))
+            .collect::<::std::result::Result<Vec<_>, _>>()?;
+
+        Ok(UndoBatch { batch_dir_path: batch_dir_path.to_path_buf(), command, created, entries })
+    }
+
+    /// Puts back every change in this batch, last change first.
+    fn restore(&self) -> Result<()> {
+        for entry in self.entries.iter().rev() {
+            match *entry {
+                JournalEntry::File { ref path, backup: Some(ref backup) } => {
+                    if let Some(parent_dir_path) = path.parent() {
+                        fs::create_dir_all(parent_dir_path)?;
+                    }
+
+                    fs::copy(self.batch_dir_path.join(backup), path)?;
+                },
+                JournalEntry::File { ref path, backup: None } => {
+                    if path.is_file() {
+                        fs::remove_file(path)?;
+                    }
+                },
+                JournalEntry::Tags { ref path, ref tags } => tags::write_tags(path, tags)?,
+                JournalEntry::Move { ref from, ref to } => {
+                    // Rule: an item that took the old place in the meantime is never overwritten.
+                    ensure!(!from.exists(), ErrorKind::AlreadyExists(from.clone()));
+
+                    fs::rename(to, from)?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The journal of changes for a library, kept in a directory of numbered batches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndoJournal {
+    journal_dir_path: PathBuf,
+}
+
+impl UndoJournal {
+    /// Uses the journal in a directory, which is usually the library root (or the overlay root, for a library with one).
+    pub fn new<P: AsRef<Path>>(root_dir: P) -> Self {
+        UndoJournal { journal_dir_path: root_dir.as_ref().join(UNDO_DIR_NAME) }
+    }
+
+    /// Lists the directories of the recorded batches, oldest first.
+    fn batch_dir_paths(&self) -> Result<Vec<PathBuf>> {
+        if !self.journal_dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut numbered = vec![];
+
+        for entry in fs::read_dir(&self.journal_dir_path)? {
+            let path = entry?.path();
+
+            if let Some(n) = path.file_name().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok()) {
+                numbered.push((n, path));
+            }
+        }
+
+        numbered.sort();
+
+        Ok(numbered.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Lists the recorded batches, newest first.
+    pub fn batches(&self) -> Result<Vec<UndoBatch>> {
+        self.batch_dir_paths()?.iter().rev().map(UndoBatch::read).collect()
+    }
+
+    /// Records what the moves and the writes of a plan are about to change, before they are made.
+    /// The moves are taken to happen before the plan is executed.
+    /// Returns the directory of the new batch.
+    pub fn record<S: Into<String>>(&self, command: S, moves: &[(PathBuf, PathBuf)], plan: &WritePlan) -> Result<PathBuf> {
+        let batch_dir_paths = self.batch_dir_paths()?;
+
+        let next_n = batch_dir_paths.last()
+            .and_then(|p| p.file_name().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok()))
+            .map_or(1, |n| n + 1);
+
+        let batch_dir_path = self.journal_dir_path.join(format!("{:06}", next_n));
+        fs::create_dir_all(&batch_dir_path)?;
+
+        let mut entries: Vec<JournalEntry> = moves.iter()
+            .map(|&(ref from, ref to)| JournalEntry::Move { from: from.clone(), to: to.clone() })
+            .collect();
+
+        for (i, op) in plan.ops().iter().enumerate() {
+            let entry = match *op {
+                WriteOp::Tags(ref path, _) => JournalEntry::Tags { path: path.clone(), tags: tags::read_tags(path)? },
+                _ => {
+                    let path = op.path();
+
+                    let backup = if path.is_file() {
+                        let backup = format!("{:04}", i);
+                        fs::copy(path, batch_dir_path.join(&backup))?;
+                        Some(backup)
+                    }
+                    else {
+                        None
+                    };
+
+                    JournalEntry::File { path: path.to_path_buf(), backup }
+                },
+            };
+
+            entries.push(entry);
+        }
+
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        let mut manifest = Hash::new();
+        manifest.insert(Yaml::String(String::from("command")), Yaml::String(command.into()));
+        manifest.insert(Yaml::String(String::from("created")), Yaml::Integer(created as i64));
+        manifest.insert(Yaml::String(String::from("entries")), Yaml::Array(entries.iter().map(JournalEntry::as_yaml).collect()));
+
+        // The manifest goes last, so that a batch that could not be recorded in full is never undone.
+        write_yaml_file(batch_dir_path.join(MANIFEST_FILE_NAME), &Yaml::Hash(manifest))?;
+
+        let batch_count = batch_dir_paths.len() + 1;
+
+        if batch_count > MAX_BATCHES {
+            for old_batch_dir_path in batch_dir_paths.iter().take(batch_count - MAX_BATCHES) {
+                fs::remove_dir_all(old_batch_dir_path)?;
+            }
+        }
+
+        Ok(batch_dir_path)
+    }
+
+    /// Undoes the most recent batch, and removes it from the journal, so that the batch before it is next.
+    /// Batches that were not recorded in full are skipped over and removed.
+    /// Returns the batch that was undone, or `None` if the journal is empty.
+    pub fn undo_last(&self) -> Result<Option<UndoBatch>> {
+        for batch_dir_path in self.batch_dir_paths()?.into_iter().rev() {
+            if !batch_dir_path.join(MANIFEST_FILE_NAME).is_file() {
+                fs::remove_dir_all(&batch_dir_path)?;
+                continue;
+            }
+
+            let batch = UndoBatch::read(&batch_dir_path)?;
+            batch.restore().chain_err(|| format!("unable to undo '{}'", batch.command))?;
+            fs::remove_dir_all(&batch_dir_path)?;
+
+            return Ok(Some(batch));
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+
+    use tempdir::TempDir;
+
+    use plan::WritePlan;
+
+    use super::{UndoJournal, JournalEntry, MAX_BATCHES};
+
+    fn read_text(path: &::std::path::Path) -> String {
+        let mut text = String::new();
+        File::open(path).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn test_undo_journal() {
+        let temp = TempDir::new("test_undo_journal").unwrap();
+        let tp = temp.path();
+
+        let mut f = File::create(tp.join("a.yml")).unwrap();
+        write!(f, "# kept\ntitle: Old\n").unwrap();
+        File::create(tp.join("b.yml")).unwrap();
+        File::create(tp.join("old.flac")).unwrap();
+
+        let journal = UndoJournal::new(tp);
+        assert_eq!(None, journal.undo_last().unwrap());
+
+        // A batch that rewrites one file, removes another and creates a third, after moving an item.
+        let mut plan = WritePlan::new();
+        plan.write_text(tp.join("a.yml"), "title: New\n").remove(tp.join("b.yml")).write_text(tp.join("sub").join("c.yml"), "title: C\n");
+
+        journal.record("refactor", &[(tp.join("old.flac"), tp.join("new.flac"))], &plan).unwrap();
+        fs::rename(tp.join("old.flac"), tp.join("new.flac")).unwrap();
+        plan.execute().unwrap();
+
+        let batches = journal.batches().unwrap();
+        assert_eq!(1, batches.len());
+        assert_eq!("refactor", batches[0].command);
+        assert_eq!(JournalEntry::Move { from: tp.join("old.flac"), to: tp.join("new.flac") }, batches[0].entries[0]);
+        assert_eq!(4, batches[0].entries.len());
+
+        let undone = journal.undo_last().unwrap().expect("Nothing was undone");
+        assert_eq!("refactor", undone.command);
+        assert_eq!("# kept\ntitle: Old\n", read_text(&tp.join("a.yml")));
+        assert!(tp.join("b.yml").is_file());
+        assert!(!tp.join("sub").join("c.yml").exists());
+        assert!(tp.join("old.flac").is_file());
+        assert!(!tp.join("new.flac").exists());
+
+        // The undone batch is gone from the journal.
+        assert!(journal.batches().unwrap().is_empty());
+        assert_eq!(None, journal.undo_last().unwrap());
+
+        // Only the most recent batches are kept, and the newest is undone first.
+        for i in 0..(MAX_BATCHES + 2) {
+            let mut plan = WritePlan::new();
+            plan.write_text(tp.join("a.yml"), format!("title: {}\n", i));
+            journal.record(format!("edit {}", i), &[], &plan).unwrap();
+            plan.execute().unwrap();
+        }
+
+        let batches = journal.batches().unwrap();
+        assert_eq!(MAX_BATCHES, batches.len());
+        assert_eq!(format!("edit {}", MAX_BATCHES + 1), batches[0].command);
+
+        journal.undo_last().unwrap();
+        assert_eq!(format!("title: {}\n", MAX_BATCHES), read_text(&tp.join("a.yml")));
+    }
+}
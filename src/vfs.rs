@@ -0,0 +1,271 @@
+// This module abstracts the file system that a library reads its items and meta files from, so that the core logic can run against something other than the disk.
+// `StdFs` is the native file system, and `MemoryFs` is a tree kept in memory, e.g. for running in a browser under WebAssembly, where there is no file system to read, or in tests that should not depend on the disk.
+// Paths are absolute, as they are everywhere else in a library.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Cursor};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use helpers::normalize;
+
+/// What a file system knows about one of its entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VfsMetadata {
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub created: Option<SystemTime>,
+}
+
+/// An entry in a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// Read access to a file system.
+pub trait Vfs {
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata>;
+
+    /// Lists the entries of a directory, in no particular order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsEntry>>;
+
+    /// Opens a file for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<Read>>;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let mut text = String::new();
+        self.open(path)?.read_to_string(&mut text)?;
+
+        Ok(text)
+    }
+
+    /// Resolves a path to the absolute path of the entry it leads to, which must exist.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Returns true if this is the native file system, which everything that writes (edits, moves, tags) works with directly.
+    fn is_native(&self) -> bool {
+        false
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.metadata(path).map(|md| md.is_dir).unwrap_or(false)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.metadata(path).map(|md| !md.is_dir).unwrap_or(false)
+    }
+}
+
+/// The native file system, through `std::fs`.
+/// Symlinks are followed, as with `Path::is_dir` and friends.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdFs;
+
+impl Vfs for StdFs {
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        let md = fs::metadata(path)?;
+
+        Ok(VfsMetadata { is_dir: md.is_dir(), len: md.len(), modified: md.modified().ok(), created: md.created().ok() })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsEntry>> {
+        let mut entries = vec![];
+
+        for entry in path.read_dir()? {
+            let path = entry?.path();
+            let is_dir = path.is_dir();
+
+            entries.push(VfsEntry { path, is_dir });
+        }
+
+        Ok(entries)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<Read>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MemoryNode {
+    Dir,
+    File(String),
+}
+
+/// A file system held in memory, built up by adding files and directories to it.
+/// Entries have no modification times unless they are given one, no creation times, and there are no symlinks.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    nodes: BTreeMap<PathBuf, MemoryNode>,
+    modified: BTreeMap<PathBuf, SystemTime>,
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no such entry: '{}'", path.to_string_lossy()))
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        MemoryFs::default()
+    }
+
+    fn add_parent_dirs(&mut self, path: &Path) {
+        for ancestor in path.ancestors().skip(1) {
+            self.nodes.insert(ancestor.to_path_buf(), MemoryNode::Dir);
+        }
+    }
+
+    /// Adds a directory, along with any directories above it that are missing.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        let path = normalize(path.as_ref());
+
+        self.add_parent_dirs(&path);
+        self.nodes.insert(path, MemoryNode::Dir);
+        self
+    }
+
+    /// Adds a file with the given contents, along with any directories above it that are missing.
+    /// An existing file at the same path is replaced.
+    pub fn add_file<P: AsRef<Path>, S: Into<String>>(&mut self, path: P, contents: S) -> &mut Self {
+        let path = normalize(path.as_ref());
+
+        self.add_parent_dirs(&path);
+        self.nodes.insert(path, MemoryNode::File(contents.into()));
+        self
+    }
+
+    /// Sets the modification time of an entry, e.g. for sorting by it.
+    /// Nothing is set if there is no such entry.
+    pub fn set_modified<P: AsRef<Path>>(&mut self, path: P, modified: SystemTime) -> &mut Self {
+        let path = normalize(path.as_ref());
+
+        if self.nodes.contains_key(&path) {
+            self.modified.insert(path, modified);
+        }
+
+        self
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        let path = normalize(path);
+        let modified = self.modified.get(&path).cloned();
+
+        match self.nodes.get(&path) {
+            Some(&MemoryNode::Dir) => Ok(VfsMetadata { is_dir: true, len: 0, modified, created: None }),
+            Some(&MemoryNode::File(ref contents)) => Ok(VfsMetadata { is_dir: false, len: contents.len() as u64, modified, created: None }),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsEntry>> {
+        let path = normalize(path);
+
+        if self.nodes.get(&path) != Some(&MemoryNode::Dir) {
+            return Err(not_found(&path));
+        }
+
+        // Entries below a directory sort right after it, so the scan can stop at the first path outside of it.
+        let entries = {
+            self.nodes.range(path.clone()..)
+                .skip(1)
+                .take_while(|&(p, _)| p.starts_with(&path))
+                .filter(|&(p, _)| p.parent() == Some(path.as_path()))
+                .map(|(p, node)| VfsEntry { path: p.clone(), is_dir: *node == MemoryNode::Dir })
+                .collect()
+        };
+
+        Ok(entries)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<Read>> {
+        self.read_to_string(path).map(|contents| Box::new(Cursor::new(contents.into_bytes())) as Box<Read>)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        match self.nodes.get(&normalize(path)) {
+            Some(&MemoryNode::File(ref contents)) => Ok(contents.clone()),
+            Some(&MemoryNode::Dir) => Err(io::Error::new(io::ErrorKind::Other, format!("is a directory: '{}'", path.to_string_lossy()))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = normalize(path);
+
+        if self.nodes.contains_key(&path) { Ok(path) } else { Err(not_found(&path)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{Vfs, MemoryFs, VfsEntry};
+
+    #[test]
+    fn test_memory_fs() {
+        let mut memory_fs = MemoryFs::new();
+        memory_fs
+            .add_file("/lib/ALBUM/item.yml", "- title: a\n")
+            .add_file("/lib/ALBUM/TRACK_01.flac", "")
+            .add_dir("/lib/ALBUM/DISC")
+            .add_file("/lib/ALBUM_2/TRACK_01.flac", "");
+
+        assert!(memory_fs.is_dir(Path::new("/lib")));
+        assert!(memory_fs.is_file(Path::new("/lib/ALBUM/item.yml")));
+        assert!(!memory_fs.exists(Path::new("/lib/ALBUM/TRACK_02.flac")));
+        assert_eq!(11, memory_fs.metadata(Path::new("/lib/ALBUM/item.yml")).unwrap().len);
+
+        let mut entries = memory_fs.read_dir(Path::new("/lib/ALBUM")).unwrap();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(
+            vec![
+                VfsEntry { path: Path::new("/lib/ALBUM/DISC").to_path_buf(), is_dir: true },
+                VfsEntry { path: Path::new("/lib/ALBUM/TRACK_01.flac").to_path_buf(), is_dir: false },
+                VfsEntry { path: Path::new("/lib/ALBUM/item.yml").to_path_buf(), is_dir: false },
+            ],
+            entries
+        );
+        assert!(memory_fs.read_dir(Path::new("/lib/ALBUM/item.yml")).is_err());
+
+        assert_eq!("- title: a\n", memory_fs.read_to_string(Path::new("/lib/ALBUM/DISC/../item.yml")).unwrap());
+        assert!(memory_fs.read_to_string(Path::new("/lib/ALBUM")).is_err());
+
+        let mut bytes = vec![];
+        memory_fs.open(Path::new("/lib/ALBUM/item.yml")).unwrap().read_to_end(&mut bytes).unwrap();
+        assert_eq!(b"- title: a\n".to_vec(), bytes);
+
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        memory_fs.set_modified("/lib/ALBUM/TRACK_01.flac", mtime).set_modified("/lib/MISSING", mtime);
+        assert_eq!(Some(mtime), memory_fs.metadata(Path::new("/lib/ALBUM/TRACK_01.flac")).unwrap().modified);
+        assert_eq!(None, memory_fs.metadata(Path::new("/lib/ALBUM/item.yml")).unwrap().modified);
+        assert!(!memory_fs.exists(Path::new("/lib/MISSING")));
+
+        assert_eq!(Path::new("/lib/ALBUM"), memory_fs.canonicalize(Path::new("/lib/./ALBUM/DISC/..")).unwrap());
+        assert!(memory_fs.canonicalize(Path::new("/elsewhere")).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+// Notices changes to the meta files of a library while it is being edited, e.g. to keep diagnostics in an editor up to date.
+// Changes are found by polling: every poll walks the library, and compares the stamps of the meta files and directories with the ones seen last time.
+// Polling needs no change notifications from the operating system, so it also works on network mounts and other file systems that do not send them.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use library::{Library, WalkStep};
+use lookup::LookupContext;
+use helpers::FileStamp;
+use error::*;
+
+/// A change found by a poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// A meta file was added, changed or removed.
+    MetaFile(PathBuf),
+    /// Entries were added to or removed from a directory, which can change the items that the blocks of its meta files are matched up with.
+    Dir(PathBuf),
+}
+
+impl WatchEvent {
+    pub fn path(&self) -> &Path {
+        match *self {
+            WatchEvent::MetaFile(ref p) | WatchEvent::Dir(ref p) => p,
+        }
+    }
+}
+
+type Stamps = BTreeMap<PathBuf, Option<FileStamp>>;
+
+/// Returns the paths whose stamps differ between two polls, including paths only seen by one of them, sorted.
+fn changed_paths(old: &Stamps, new: &Stamps) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = new.iter()
+        .filter(|&(p, stamp)| old.get(p) != Some(stamp))
+        .map(|(p, _)| p.clone())
+        .collect();
+
+    changed.extend(old.keys().filter(|p| !new.contains_key(*p)).cloned());
+    changed.sort();
+    changed
+}
+
+/// Keeps track of the meta files and directories of a library, to tell which of them changed between polls.
+#[derive(Debug, Default)]
+pub struct MetaFileWatcher {
+    stamps: Stamps,
+    dir_stamps: Stamps,
+}
+
+impl MetaFileWatcher {
+    /// Creates a watcher that has not seen any meta files yet, so that the first poll returns all of them.
+    pub fn new() -> Self {
+        MetaFileWatcher { stamps: BTreeMap::new(), dir_stamps: BTreeMap::new() }
+    }
+
+    /// Returns the meta files seen by the last poll, sorted by path.
+    pub fn meta_fps(&self) -> Vec<PathBuf> {
+        self.stamps.keys().cloned().collect()
+    }
+
+    /// Returns the meta files that were added, changed or removed since the last poll, sorted by path.
+    pub fn poll(&mut self, media_lib: &Library) -> Result<Vec<PathBuf>> {
+        let events = self.poll_events(media_lib)?;
+
+        Ok(events.into_iter().filter_map(|e| match e { WatchEvent::MetaFile(p) => Some(p), WatchEvent::Dir(_) => None }).collect())
+    }
+
+    /// Returns what changed since the last poll: meta files first, then directories, each sorted by path.
+    /// Like meta files, every directory counts as changed on the first poll.
+    pub fn poll_events(&mut self, media_lib: &Library) -> Result<Vec<WatchEvent>> {
+        let vfs = media_lib.vfs();
+        let mut stamps = BTreeMap::new();
+        let mut dir_stamps = BTreeMap::new();
+
+        media_lib.walk_items(media_lib.root_dir(), |step| {
+            if let WalkStep::EnterDir(dir_path) = step {
+                for &(ref meta_fn, ref meta_target) in media_lib.meta_target_specs() {
+                    for meta_fp in media_lib.meta_fps_for_spec(dir_path, meta_fn, meta_target)? {
+                        let stamp = FileStamp::read_with(vfs, &meta_fp);
+                        stamps.insert(meta_fp, stamp);
+                    }
+                }
+
+                dir_stamps.insert(dir_path.to_path_buf(), FileStamp::read_with(vfs, dir_path));
+            }
+
+            Ok(true)
+        })?;
+
+        let mut events: Vec<WatchEvent> = changed_paths(&self.stamps, &stamps).into_iter().map(WatchEvent::MetaFile).collect();
+        events.extend(changed_paths(&self.dir_stamps, &dir_stamps).into_iter().map(WatchEvent::Dir));
+
+        self.stamps = stamps;
+        self.dir_stamps = dir_stamps;
+
+        Ok(events)
+    }
+}
+
+/// Polls a library for changes at most once per interval.
+pub struct PollingWatcher {
+    watcher: MetaFileWatcher,
+    interval: Duration,
+    last_poll: Option<Instant>,
+}
+
+impl PollingWatcher {
+    pub fn new(interval: Duration) -> Self {
+        PollingWatcher { watcher: MetaFileWatcher::new(), interval, last_poll: None }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the meta files seen by the last poll, sorted by path.
+    pub fn meta_fps(&self) -> Vec<PathBuf> {
+        self.watcher.meta_fps()
+    }
+
+    /// Waits until the interval has passed since the last poll, and then polls.
+    /// The first poll happens right away.
+    pub fn next_events(&mut self, media_lib: &Library) -> Result<Vec<WatchEvent>> {
+        if let Some(last_poll) = self.last_poll {
+            let elapsed = last_poll.elapsed();
+
+            if elapsed < self.interval {
+                thread::sleep(self.interval - elapsed);
+            }
+        }
+
+        self.last_poll = Some(Instant::now());
+        self.watcher.poll_events(media_lib)
+    }
+}
+
+/// Drops whatever a lookup context has cached from the meta files that some events affect, so that later lookups read them again.
+pub fn invalidate(lookup_ctx: &mut LookupContext, events: &[WatchEvent]) -> Result<()> {
+    for event in events {
+        match *event {
+            WatchEvent::MetaFile(ref meta_fp) => lookup_ctx.clear_meta_file(meta_fp)?,
+            WatchEvent::Dir(ref dir_path) => lookup_ctx.clear_meta_files_in_dir(dir_path)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    use lookup::LookupContext;
+    use metadata::MetaValue;
+    use fixtures::default_setup;
+
+    use super::{MetaFileWatcher, PollingWatcher, WatchEvent, invalidate};
+
+    #[test]
+    fn test_poll() {
+        let (temp_media_root, media_lib) = default_setup("test_poll");
+        let tp = temp_media_root.path();
+
+        let mut watcher = MetaFileWatcher::new();
+
+        let first = watcher.poll(&media_lib).unwrap();
+        assert!(first.contains(&tp.join("self.yml")));
+        assert!(first.contains(&tp.join("ALBUM_01").join("item.yml")));
+
+        assert!(watcher.poll(&media_lib).unwrap().is_empty());
+
+        // Edited, added and removed meta files are all reported.
+        sleep(Duration::from_millis(10));
+        let mut f = File::create(tp.join("ALBUM_01").join("self.yml")).unwrap();
+        writeln!(f, "self_key: edited and longer").unwrap();
+        fs::remove_file(tp.join("ALBUM_02").join("item.yml")).unwrap();
+        fs::create_dir(tp.join("ALBUM_06")).unwrap();
+        File::create(tp.join("ALBUM_06").join("self.yml")).unwrap();
+        File::create(tp.join("ALBUM_03").join("notes.yml")).unwrap();
+        fs::remove_file(tp.join("ALBUM_03").join("DISC_01").join("self.yml")).unwrap();
+
+        let expected = vec![
+            tp.join("ALBUM_01").join("self.yml"),
+            tp.join("ALBUM_02").join("item.yml"),
+            tp.join("ALBUM_03").join("DISC_01").join("self.yml"),
+            tp.join("ALBUM_06").join("self.yml"),
+        ];
+        assert_eq!(expected, watcher.poll(&media_lib).unwrap());
+        assert!(watcher.poll(&media_lib).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_polling_watcher() {
+        let (temp_media_root, media_lib) = default_setup("test_polling_watcher");
+        let tp = temp_media_root.path();
+        let disc_fp = tp.join("ALBUM_01").join("DISC_01");
+
+        let mut watcher = PollingWatcher::new(Duration::from_millis(50));
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+
+        let started = Instant::now();
+        let first = watcher.next_events(&media_lib).unwrap();
+        assert!(first.contains(&WatchEvent::MetaFile(disc_fp.join("item.yml"))));
+        assert!(first.contains(&WatchEvent::Dir(disc_fp.clone())));
+
+        let item_val = |lookup_ctx: &mut LookupContext| lookup_ctx.lookup_origin(disc_fp.join("TRACK_02.flac"), "TRACK_02_item_key").unwrap();
+        assert_eq!(Some(MetaValue::Str(String::from("TRACK_02_item_val"))), item_val(&mut lookup_ctx));
+
+        // Adding an item to the front changes which block each item is matched up with, without touching any meta file.
+        sleep(Duration::from_millis(10));
+        File::create(disc_fp.join("TRACK_00.flac")).unwrap();
+
+        // Polls are spaced out by at least the interval.
+        let events = watcher.next_events(&media_lib).unwrap();
+        assert!(started.elapsed() >= watcher.interval());
+        assert_eq!(vec![WatchEvent::Dir(disc_fp.clone())], events);
+
+        // Until the events are applied, the lookup context still has the old matching cached.
+        assert_eq!(Some(MetaValue::Str(String::from("TRACK_02_item_val"))), item_val(&mut lookup_ctx));
+        invalidate(&mut lookup_ctx, &events).unwrap();
+        assert_eq!(None, item_val(&mut lookup_ctx));
+
+        assert!(watcher.next_events(&media_lib).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,520 @@
+// This module provides the ability to edit the metadata of a single item, and write the changes back to its meta file.
+
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
+
+use library::{Library, is_meta_file_pattern};
+use metadata::{Metadata, MetaBlock, MetaTarget, MetaValue, sidecar_path};
+use metadata::keys::{MATCH_KEY, EXPECT_NAME_KEY};
+use metadata::reader::BlockLocation;
+use helpers::normalize;
+use yaml::{yaml_as_metadata, yaml_as_string, meta_block_as_yaml};
+use plan::WritePlan;
+use error::*;
+
+/// Finds where the block for an item lives in a meta file's metadata, if the meta file describes the item at all.
+fn locate_item_block(media_lib: &Library, md: &Metadata, working_dir_path: &Path, meta_fn: &str, abs_item_path: &Path) -> Result<Option<(BlockLocation, MetaBlock)>> {
+    let plex_results = media_lib.multiplex_items(md, working_dir_path, meta_fn)?;
+
+    for (plex_target, mb) in plex_results {
+        if plex_target.resolve(working_dir_path) != abs_item_path {
+            continue;
+        }
+
+        // Plexing hands back references into the metadata, so the block can be found again by identity.
+        let opt_location = match *md {
+            Metadata::Contains(_) => Some(BlockLocation::Whole),
+            Metadata::SiblingsSeq(ref mb_seq) => {
+                mb_seq.iter().position(|b| ptr::eq(b, mb)).map(BlockLocation::Index)
+            },
+            Metadata::SiblingsMap(ref mb_map) => {
+                mb_map.iter().find(|&(_, b)| ptr::eq(b, mb)).map(|(k, _)| BlockLocation::for_key(k))
+            },
+        };
+
+        return Ok(opt_location.map(|loc| (loc, mb.clone())));
+    }
+
+    Ok(None)
+}
+
+/// Replaces the block at a location within a YAML document.
+fn replace_yaml_block(yaml: &mut Yaml, location: &BlockLocation, block_yaml: Yaml) -> Result<()> {
+    match (location, yaml) {
+        (&BlockLocation::Whole, yaml) => { *yaml = block_yaml; },
+        (&BlockLocation::Index(i), &mut Yaml::Array(ref mut arr)) => {
+            let slot = arr.get_mut(i).ok_or(ErrorKind::InvalidMetadata)?;
+            *slot = block_yaml;
+        },
+        (&BlockLocation::Key(ref k), &mut Yaml::Hash(ref mut hsh)) => {
+            // Keys may have been written as something other than a string (e.g. a number), so compare converted keys.
+            let opt_key_y = hsh.keys().find(|key_y| yaml_as_string(key_y).as_ref() == Some(k)).cloned();
+            let key_y = opt_key_y.unwrap_or_else(|| Yaml::String(k.clone()));
+
+            hsh.insert(key_y, block_yaml);
+        },
+        (&BlockLocation::Nil, &mut Yaml::Hash(ref mut hsh)) => { hsh.insert(Yaml::Null, block_yaml); },
+        _ => Err(ErrorKind::InvalidMetadata)?,
+    }
+
+    Ok(())
+}
+
+/// Removes the block at a location within a YAML document of sibling blocks.
+fn remove_yaml_block(yaml: &mut Yaml, location: &BlockLocation) -> Result<()> {
+    match (location, yaml) {
+        (&BlockLocation::Index(i), &mut Yaml::Array(ref mut arr)) => {
+            ensure!(i < arr.len(), ErrorKind::InvalidMetadata);
+            arr.remove(i);
+        },
+        (&BlockLocation::Key(ref k), &mut Yaml::Hash(ref mut hsh)) => {
+            let key_y = hsh.keys().find(|key_y| yaml_as_string(key_y).as_ref() == Some(k)).cloned().ok_or(ErrorKind::InvalidMetadata)?;
+            hsh.remove(&key_y);
+        },
+        (&BlockLocation::Nil, &mut Yaml::Hash(ref mut hsh)) => { hsh.remove(&Yaml::Null); },
+        _ => Err(ErrorKind::InvalidMetadata)?,
+    }
+
+    Ok(())
+}
+
+/// Adds a new block for an item to an existing YAML document that does not yet describe it.
+fn insert_yaml_block(yaml: &mut Yaml, item_name: &str, mut mb: MetaBlock) -> Result<()> {
+    match *yaml {
+        Yaml::Hash(ref mut hsh) => {
+            hsh.insert(Yaml::String(item_name.to_string()), meta_block_as_yaml(&mb));
+        },
+        Yaml::Array(ref mut arr) => {
+            // Appending a block to a sequence would shift positional matching, so pin the new block to its item.
+            mb.insert(MATCH_KEY.to_string(), MetaValue::Str(item_name.to_string()));
+            arr.push(meta_block_as_yaml(&mb));
+        },
+        _ => Err(ErrorKind::InvalidMetadata)?,
+    }
+
+    Ok(())
+}
+
+/// Edits the meta block that describes an item, and writes the result back to disk.
+/// Returns the path of the meta file that was written.
+pub fn edit_item_block<P, F>(media_lib: &Library, abs_item_path: P, edit: F) -> Result<PathBuf>
+where P: AsRef<Path>,
+      F: FnOnce(&mut MetaBlock),
+{
+    let mut plan = WritePlan::new();
+    let meta_fp = plan_edit_item_block(media_lib, &mut plan, abs_item_path, edit)?;
+    plan.execute()?;
+
+    Ok(meta_fp)
+}
+
+/// Edits the meta block that describes an item, adding the write of the edited meta file to a plan.
+/// Meta files are read through the plan, so several edits to items in the same meta file can be planned together.
+/// The first meta file (in meta target order) that describes the item is edited.
+/// If no meta file describes the item, a block is added to (or a new file is created for) the first applicable meta target.
+/// For a library with an overlay, only meta files in the overlay are edited or created, and their blocks take precedence over the library's own.
+/// Returns the path of the meta file to be written.
+pub fn plan_edit_item_block<P, F>(media_lib: &Library, plan: &mut WritePlan, abs_item_path: P, edit: F) -> Result<PathBuf>
+where P: AsRef<Path>,
+      F: FnOnce(&mut MetaBlock),
+{
+    let abs_item_path = normalize(abs_item_path.as_ref());
+
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    // Rule: item path must be in the library.
+    ensure!(abs_item_path.starts_with(media_lib.root_dir()), ErrorKind::InvalidSubPath(abs_item_path.clone(), media_lib.root_dir().to_path_buf()));
+
+    // Rule: item path must exist.
+    ensure!(abs_item_path.exists(), ErrorKind::DoesNotExist(abs_item_path.clone()));
+
+    // Collect the meta files that could describe this item, in meta target order.
+    let mut candidates: Vec<(PathBuf, PathBuf, &str, MetaTarget)> = vec![];
+
+    for &(ref meta_fn, ref meta_target) in media_lib.meta_target_specs() {
+        // A sidecar describes its item as a whole, so it is matched up as if the item were its working directory.
+        if let Some(suffix) = meta_target.sidecar_suffix() {
+            if let Some(meta_fp) = sidecar_path(&abs_item_path, suffix) {
+                if media_lib.is_proper_sub_path(&meta_fp) {
+                    candidates.push((media_lib.writable_meta_path(&meta_fp), abs_item_path.clone(), meta_fn.as_str(), meta_target.clone()));
+                }
+            }
+
+            continue;
+        }
+
+        // A spec with a selection of its own only describes the items that it selects, so its meta files would not see a new block for any other item.
+        if let Some(selection) = media_lib.target_selection(meta_fn) {
+            if !selection.is_selected_entry(&abs_item_path, media_lib.is_item_dir(&abs_item_path)) {
+                continue;
+            }
+        }
+
+        if let Some(working_dir_path) = media_lib.meta_target_dir_path(meta_target, &abs_item_path) {
+            if !media_lib.is_proper_sub_path(&working_dir_path) {
+                continue;
+            }
+
+            // Patterns cannot name a new file, so only meta files that already exist are candidates for them.
+            if is_meta_file_pattern(meta_fn) {
+                for meta_fp in media_lib.meta_fps_in_dir(&working_dir_path, meta_fn)? {
+                    // With an overlay, the library's own meta files are left alone.
+                    if media_lib.writable_meta_path(&meta_fp) == meta_fp {
+                        candidates.push((meta_fp, working_dir_path.clone(), meta_fn.as_str(), meta_target.clone()));
+                    }
+                }
+            }
+            else {
+                candidates.push((media_lib.writable_meta_path(working_dir_path.join(meta_fn)), working_dir_path, meta_fn.as_str(), meta_target.clone()));
+            }
+        }
+    }
+
+    for &(ref meta_fp, ref working_dir_path, meta_fn, ref meta_target) in &candidates {
+        if !plan.file_exists(meta_fp) {
+            continue;
+        }
+
+        let mut yaml = plan.read_yaml(meta_fp)?;
+        let md = yaml_as_metadata(&yaml, meta_target).ok_or(ErrorKind::InvalidMetadata)?;
+
+        // Blocks that could not be read would throw off the positions of the blocks that could.
+        if let (&Metadata::SiblingsSeq(ref mb_seq), &Yaml::Array(ref arr)) = (&md, &yaml) {
+            ensure!(mb_seq.len() == arr.len(), "meta file contains unreadable blocks, refusing to edit: '{}'", meta_fp.to_string_lossy());
+        }
+
+        if let Some((location, mut mb)) = locate_item_block(media_lib, &md, working_dir_path, meta_fn, &abs_item_path)? {
+            edit(&mut mb);
+            replace_yaml_block(&mut yaml, &location, meta_block_as_yaml(&mb))?;
+            plan.write_yaml(meta_fp.clone(), yaml);
+
+            return Ok(meta_fp.clone());
+        }
+    }
+
+    let &(ref meta_fp, _, _, ref meta_target) = candidates.first().ok_or_else(|| format!("no meta target can describe item: '{}'", abs_item_path.to_string_lossy()))?;
+
+    let mut mb = MetaBlock::new();
+    edit(&mut mb);
+
+    let yaml = match *meta_target {
+        MetaTarget::Contains | MetaTarget::Sidecar { .. } => meta_block_as_yaml(&mb),
+        MetaTarget::Siblings => {
+            let item_name = abs_item_path.file_name().and_then(|s| s.to_str()).ok_or(ErrorKind::InvalidMetadata)?;
+
+            if plan.file_exists(meta_fp) {
+                let mut yaml = plan.read_yaml(meta_fp)?;
+                insert_yaml_block(&mut yaml, item_name, mb)?;
+                yaml
+            }
+            else {
+                let mut hsh = Hash::new();
+                hsh.insert(Yaml::String(item_name.to_string()), meta_block_as_yaml(&mb));
+                Yaml::Hash(hsh)
+            }
+        },
+    };
+
+    plan.write_yaml(meta_fp.clone(), yaml);
+
+    Ok(meta_fp.clone())
+}
+
+/// Plans moving the metadata of an item along with the item itself, before the item is moved.
+/// Blocks in sibling meta files are taken out of the meta files next to the item, and added to the meta files of the same name next to the destination;
+/// a sidecar meta file is moved to the sidecar path of the destination. Meta files that end up without any blocks are removed.
+/// Meta files inside a directory move along with it, so they are left alone.
+/// Returns the paths of the meta files to be written or removed.
+pub fn plan_move_item_meta<P, Q>(media_lib: &Library, plan: &mut WritePlan, abs_src_path: P, abs_dst_path: Q) -> Result<Vec<PathBuf>>
+where P: AsRef<Path>,
+      Q: AsRef<Path>,
+{
+    let abs_src_path = normalize(abs_src_path.as_ref());
+    let abs_dst_path = normalize(abs_dst_path.as_ref());
+
+    // Rule: library must be writable.
+    media_lib.ensure_writable()?;
+
+    // Rule: meta files in an overlay mirror the items of the library, which an overlay is there to leave alone.
+    ensure!(media_lib.overlay_root().is_none(), "items cannot be moved in a library with an overlay");
+
+    // Rule: nested blocks further up the tree are not moved, so they would be left behind.
+    ensure!(!media_lib.nested_items(), "items cannot be moved in a library with nested items");
+
+    let (src_dir_path, dst_dir_path, dst_name) = match (abs_src_path.parent(), abs_dst_path.parent(), abs_dst_path.file_name().and_then(|s| s.to_str())) {
+        (Some(src_dir_path), Some(dst_dir_path), Some(dst_name)) => (src_dir_path, dst_dir_path, dst_name),
+        _ => bail!(ErrorKind::InvalidSubPath(abs_dst_path.clone(), media_lib.root_dir().to_path_buf())),
+    };
+
+    let mut touched = vec![];
+
+    for &(ref meta_fn, ref meta_target) in media_lib.meta_target_specs() {
+        if let Some(suffix) = meta_target.sidecar_suffix() {
+            let src_meta_fp = sidecar_path(&abs_src_path, suffix).ok_or_else(|| ErrorKind::NotAFile(abs_src_path.clone()))?;
+
+            if !plan.file_exists(&src_meta_fp) {
+                continue;
+            }
+
+            let dst_meta_fp = sidecar_path(&abs_dst_path, suffix).ok_or_else(|| ErrorKind::NotAFile(abs_dst_path.clone()))?;
+
+            // Rule: existing sidecars are never overwritten.
+            ensure!(!plan.file_exists(&dst_meta_fp), format!("sidecar meta file already exists: '{}'", dst_meta_fp.to_string_lossy()));
+
+            // The text is carried over as is, so that comments and formatting are kept.
+            let text = plan.read_text(&src_meta_fp)?;
+            plan.remove(&src_meta_fp).write_text(&dst_meta_fp, text);
+
+            touched.push(src_meta_fp);
+            touched.push(dst_meta_fp);
+            continue;
+        }
+
+        if *meta_target != MetaTarget::Siblings {
+            continue;
+        }
+
+        for src_meta_fp in media_lib.meta_fps_in_dir(src_dir_path, meta_fn)? {
+            if !plan.file_exists(&src_meta_fp) {
+                continue;
+            }
+
+            let mut yaml = plan.read_yaml(&src_meta_fp)?;
+            let md = yaml_as_metadata(&yaml, meta_target).ok_or(ErrorKind::InvalidMetadata)?;
+
+            // Blocks that could not be read would throw off the positions of the blocks that could.
+            if let (&Metadata::SiblingsSeq(ref mb_seq), &Yaml::Array(ref arr)) = (&md, &yaml) {
+                ensure!(mb_seq.len() == arr.len(), "meta file contains unreadable blocks, refusing to edit: '{}'", src_meta_fp.to_string_lossy());
+            }
+
+            let (location, mut mb) = match locate_item_block(media_lib, &md, src_dir_path, meta_fn, &abs_src_path)? {
+                Some(found) => found,
+                None => continue,
+            };
+
+            // Taking the block out of a sequence keeps the later blocks lined up, since the item leaves the directory along with it.
+            remove_yaml_block(&mut yaml, &location)?;
+
+            let is_empty = match yaml {
+                Yaml::Array(ref arr) => arr.is_empty(),
+                Yaml::Hash(ref hsh) => hsh.is_empty(),
+                _ => false,
+            };
+
+            if is_empty {
+                plan.remove(&src_meta_fp);
+            }
+            else {
+                plan.write_yaml(&src_meta_fp, yaml);
+            }
+
+            // Matching keys name the item as it was, and the block is matched up by its new name from now on.
+            mb.remove(MATCH_KEY);
+            mb.remove(EXPECT_NAME_KEY);
+
+            let file_name = src_meta_fp.file_name().ok_or(ErrorKind::InvalidMetadata)?;
+            let dst_meta_fp = dst_dir_path.join(file_name);
+
+            let dst_yaml = if plan.file_exists(&dst_meta_fp) {
+                let mut dst_yaml = plan.read_yaml(&dst_meta_fp)?;
+
+                // Rule: blocks for the destination must not already exist.
+                if let Yaml::Hash(ref hsh) = dst_yaml {
+                    ensure!(
+                        !hsh.keys().any(|key_y| yaml_as_string(key_y).as_ref().map(String::as_str) == Some(dst_name)),
+                        "meta file already describes '{}': '{}'", dst_name, dst_meta_fp.to_string_lossy()
+                    );
+                }
+
+                insert_yaml_block(&mut dst_yaml, dst_name, mb)?;
+                dst_yaml
+            }
+            else {
+                let mut hsh = Hash::new();
+                hsh.insert(Yaml::String(dst_name.to_string()), meta_block_as_yaml(&mb));
+                Yaml::Hash(hsh)
+            };
+
+            plan.write_yaml(&dst_meta_fp, dst_yaml);
+
+            touched.push(src_meta_fp);
+            touched.push(dst_meta_fp);
+        }
+    }
+
+    touched.sort();
+    touched.dedup();
+
+    Ok(touched)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
+
+    use tempdir::TempDir;
+
+    use library::{Library, LibraryBuilder};
+    use library::selection::Selection;
+    use lookup::LookupContext;
+    use metadata::{MetaTarget, MetaValue};
+    use fixtures::{create_temp_media_test_dir, default_setup};
+    use plan::WritePlan;
+
+    use super::{edit_item_block, plan_edit_item_block, plan_move_item_meta};
+
+    #[test]
+    fn test_plan_move_item_meta_sidecar() {
+        let temp_media_root = create_temp_media_test_dir("test_plan_move_item_meta_sidecar");
+        let tp = temp_media_root.path();
+
+        let meta_target_specs = vec![
+            (String::from("sidecar"), MetaTarget::Sidecar { suffix: String::from("meta.yml") }),
+            (String::from("item.yml"), MetaTarget::Siblings),
+        ];
+        let selection = Selection::Or(
+            Box::new(Selection::Ext(String::from("flac"))),
+            Box::new(Selection::IsDir),
+        );
+        let media_lib = LibraryBuilder::new(tp, meta_target_specs).selection(selection).create().unwrap();
+
+        let album_dir = tp.join("ALBUM_02");
+        let sidecar_fp = album_dir.join("TRACK_01.flac.meta.yml");
+        let mut f = File::create(&sidecar_fp).unwrap();
+        write!(f, "# notes\ntitle: Sidecar\n").unwrap();
+
+        let mut plan = WritePlan::new();
+        let touched = plan_move_item_meta(&media_lib, &mut plan, album_dir.join("TRACK_01.flac"), tp.join("TRACK_01.flac")).expect("Unable to plan move");
+        assert!(touched.contains(&sidecar_fp));
+        assert!(touched.contains(&tp.join("TRACK_01.flac.meta.yml")));
+        assert!(touched.contains(&album_dir.join("item.yml")));
+        assert!(touched.contains(&tp.join("item.yml")));
+
+        // The sidecar is moved as text, so its comments are kept.
+        assert!(!plan.file_exists(&sidecar_fp));
+        assert_eq!("# notes\ntitle: Sidecar\n", plan.read_text(tp.join("TRACK_01.flac.meta.yml")).unwrap());
+
+        // Existing sidecars at the destination are never overwritten.
+        File::create(tp.join("ALBUM_04.flac.meta.yml")).unwrap();
+        File::create(album_dir.join("TRACK_02.flac.meta.yml")).unwrap();
+        let mut plan = WritePlan::new();
+        assert!(plan_move_item_meta(&media_lib, &mut plan, album_dir.join("TRACK_02.flac"), tp.join("ALBUM_04.flac")).is_err());
+    }
+
+    #[test]
+    fn test_edit_item_block_overlay() {
+        let (temp_media_root, media_lib) = default_setup("test_edit_item_block_overlay");
+        let tp = temp_media_root.path();
+        let temp_overlay_root = TempDir::new("test_edit_item_block_overlay_meta").unwrap();
+        let op = temp_overlay_root.path().canonicalize().unwrap();
+
+        let overlaid = Library::overlay(&media_lib, &op).expect("Unable to overlay media library");
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        // New blocks go to the mirrored meta file in the overlay, whose directories are created as needed.
+        let track_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+        let meta_fp = edit_item_block(&overlaid, &track_fp, |mb| { mb.insert("item_key".to_string(), str_val("overlaid")); }).expect("Unable to edit item");
+        assert_eq!(op.join("ALBUM_01").join("DISC_01").join("item.yml"), meta_fp);
+
+        let other_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_02.flac");
+        let meta_fp = edit_item_block(&overlaid, &other_fp, |mb| { mb.insert("item_key".to_string(), str_val("also_overlaid")); }).expect("Unable to edit item");
+        assert_eq!(op.join("ALBUM_01").join("DISC_01").join("item.yml"), meta_fp);
+
+        {
+            let mut lookup_ctx = LookupContext::new(&overlaid);
+            assert_eq!(Some(str_val("overlaid")), lookup_ctx.lookup_origin(&track_fp, "item_key").unwrap());
+            assert_eq!(Some(str_val("also_overlaid")), lookup_ctx.lookup_origin(&other_fp, "item_key").unwrap());
+            assert_eq!(Some(str_val("TRACK_01_item_val")), lookup_ctx.lookup_origin(&track_fp, "TRACK_01_item_key").unwrap());
+        }
+
+        // The library's own meta files are left alone.
+        let mut lookup_ctx = LookupContext::new(&media_lib);
+        assert_eq!(Some(str_val("item_val")), lookup_ctx.lookup_origin(&track_fp, "item_key").unwrap());
+    }
+
+    #[test]
+    fn test_edit_item_block() {
+        let (temp_media_root, media_lib) = default_setup("test_edit_item_block");
+        let tp = temp_media_root.path();
+
+        let str_val = |s: &str| MetaValue::Str(s.to_string());
+
+        // Directories are described by their own self meta file first.
+        let item_fp = tp.join("ALBUM_01").join("DISC_02");
+        let meta_fp = edit_item_block(&media_lib, &item_fp, |mb| { mb.insert("new_key".to_string(), str_val("new_val")); }).expect("Unable to edit item");
+        assert_eq!(tp.join("ALBUM_01").join("DISC_02").join("self.yml"), meta_fp);
+
+        // Items described by a sequence meta file.
+        let track_fp = tp.join("ALBUM_01").join("DISC_02").join("TRACK_02.flac");
+        let meta_fp = edit_item_block(&media_lib, &track_fp, |mb| { mb.insert("item_key".to_string(), str_val("changed")); }).expect("Unable to edit item");
+        assert_eq!(tp.join("ALBUM_01").join("DISC_02").join("item.yml"), meta_fp);
+
+        {
+            let mut lookup_ctx = LookupContext::new(&media_lib);
+            assert_eq!(Some(str_val("new_val")), lookup_ctx.lookup_origin(&item_fp, "new_key").unwrap());
+            assert_eq!(Some(str_val("changed")), lookup_ctx.lookup_origin(&track_fp, "item_key").unwrap());
+            assert_eq!(Some(str_val("TRACK_02_item_val")), lookup_ctx.lookup_origin(&track_fp, "TRACK_02_item_key").unwrap());
+
+            // Neighboring items are untouched.
+            let other_fp = tp.join("ALBUM_01").join("DISC_02").join("TRACK_03.flac");
+            assert_eq!(Some(str_val("item_val")), lookup_ctx.lookup_origin(&other_fp, "item_key").unwrap());
+        }
+
+        // A new item that is not yet described gets a pinned block appended to the sequence.
+        let new_fp = tp.join("ALBUM_01").join("DISC_02").join("TRACK_04.flac");
+        File::create(&new_fp).unwrap();
+        edit_item_block(&media_lib, &new_fp, |mb| { mb.insert("item_key".to_string(), str_val("brand_new")); }).expect("Unable to edit item");
+
+        {
+            let mut lookup_ctx = LookupContext::new(&media_lib);
+            assert_eq!(Some(str_val("brand_new")), lookup_ctx.lookup_origin(&new_fp, "item_key").unwrap());
+            assert_eq!(Some(str_val("TRACK_01_item_val")), lookup_ctx.lookup_origin(tp.join("ALBUM_01").join("DISC_02").join("TRACK_01.flac"), "TRACK_01_item_key").unwrap());
+        }
+
+        // Items described by a mapping meta file.
+        let mut f = File::create(tp.join("item.yml")).unwrap();
+        writeln!(f, "ALBUM_04.flac:\n  item_key: mapped").unwrap();
+        edit_item_block(&media_lib, tp.join("ALBUM_04.flac"), |mb| { mb.remove("item_key"); }).expect("Unable to edit item");
+
+        {
+            let mut lookup_ctx = LookupContext::new(&media_lib);
+            assert_eq!(None, lookup_ctx.lookup_origin(tp.join("ALBUM_04.flac"), "item_key").unwrap());
+        }
+
+        // Planned edits see earlier planned edits to the same meta file, and nothing is written until the plan is executed.
+        let track_01_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+        let track_02_fp = tp.join("ALBUM_01").join("DISC_01").join("TRACK_02.flac");
+        let mut plan = WritePlan::new();
+        plan_edit_item_block(&media_lib, &mut plan, &track_01_fp, |mb| { mb.insert("planned".to_string(), str_val("one")); }).expect("Unable to plan edit");
+        plan_edit_item_block(&media_lib, &mut plan, &track_02_fp, |mb| { mb.insert("planned".to_string(), str_val("two")); }).expect("Unable to plan edit");
+        assert_eq!(vec![tp.join("ALBUM_01").join("DISC_01").join("item.yml")], plan.paths());
+        assert_eq!(None, LookupContext::new(&media_lib).lookup_origin(&track_01_fp, "planned").unwrap());
+
+        plan.execute().expect("Unable to execute plan");
+
+        {
+            let mut lookup_ctx = LookupContext::new(&media_lib);
+            assert_eq!(Some(str_val("one")), lookup_ctx.lookup_origin(&track_01_fp, "planned").unwrap());
+            assert_eq!(Some(str_val("two")), lookup_ctx.lookup_origin(&track_02_fp, "planned").unwrap());
+        }
+
+        // Comments and formatting in edited meta files are kept.
+        let album_fp = tp.join("ALBUM_02");
+        let self_fp = album_fp.join("self.yml");
+        let mut f = File::create(&self_fp).unwrap();
+        write!(f, "# Album notes\nconst_key: 'const_val'  # shared\nself_key: self_val\n").unwrap();
+        edit_item_block(&media_lib, &album_fp, |mb| { mb.insert("self_key".to_string(), str_val("edited")); }).expect("Unable to edit item");
+
+        let mut text = String::new();
+        File::open(&self_fp).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!("# Album notes\nconst_key: 'const_val'  # shared\nself_key: edited\n", text);
+
+        // Paths outside the library are rejected.
+        assert!(edit_item_block(&media_lib, tp.join(".."), |_| {}).is_err());
+    }
+}
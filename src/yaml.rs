@@ -3,7 +3,8 @@ use std::io::Read;
 use std::path::Path;
 use std::collections::BTreeMap;
 
-use yaml_rust::{YamlLoader, Yaml};
+use yaml_rust::{YamlLoader, YamlEmitter, Yaml};
+use yaml_rust::yaml::Hash;
 
 use metadata::{
     Metadata,
@@ -14,6 +15,9 @@ use metadata::{
     MetaValue,
     MetaTarget,
 };
+use metadata::keys::is_allowed_user_key;
+use roundtrip::patch_yaml_text;
+use helpers::write_file_atomic;
 use error::*;
 
 pub fn read_yaml_file<P: AsRef<Path>>(yaml_fp: P) -> Result<Yaml> {
@@ -24,7 +28,14 @@ pub fn read_yaml_file<P: AsRef<Path>>(yaml_fp: P) -> Result<Yaml> {
     let mut buffer = String::new();
     f.read_to_string(&mut buffer)?;
 
-    let yaml_docs: Vec<Yaml> = YamlLoader::load_from_str(&buffer)?;
+    read_yaml_str(&buffer, yaml_fp)
+}
+
+/// Parses the first document of YAML text that was read from somewhere other than a plain file, e.g. an archive.
+/// The path is only used for error messages.
+pub fn read_yaml_str<P: AsRef<Path>>(text: &str, yaml_fp: P) -> Result<Yaml> {
+    let yaml_fp = yaml_fp.as_ref();
+    let yaml_docs: Vec<Yaml> = YamlLoader::load_from_str(text)?;
 
     if yaml_docs.len() < 1 {
         Err(ErrorKind::EmptyMetaFile(yaml_fp.to_path_buf()))?
@@ -33,7 +44,33 @@ pub fn read_yaml_file<P: AsRef<Path>>(yaml_fp: P) -> Result<Yaml> {
     Ok(yaml_docs[0].clone())
 }
 
-fn yaml_as_string(y: &Yaml) -> Option<String> {
+/// Renders a YAML document as the text that would be written to a file.
+pub fn yaml_as_text(y: &Yaml) -> Result<String> {
+    let mut buffer = String::new();
+    YamlEmitter::new(&mut buffer).dump(y).map_err(|e| format!("unable to emit YAML: {:?}", e))?;
+    buffer.push('\n');
+
+    Ok(buffer)
+}
+
+/// Writes a YAML document to a file on disk, replacing any existing contents.
+/// Existing files are edited in place, keeping comments and formatting for the parts that do not change.
+pub fn write_yaml_file<P: AsRef<Path>>(yaml_fp: P, y: &Yaml) -> Result<()> {
+    let yaml_fp = yaml_fp.as_ref();
+
+    let buffer = if yaml_fp.is_file() {
+        let mut old_text = String::new();
+        File::open(yaml_fp)?.read_to_string(&mut old_text)?;
+        patch_yaml_text(&old_text, y)?
+    }
+    else {
+        yaml_as_text(y)?
+    };
+
+    write_file_atomic(yaml_fp, buffer.as_bytes())
+}
+
+pub fn yaml_as_string(y: &Yaml) -> Option<String> {
     match y {
         &Yaml::Null => None,
         &Yaml::Array(_) => None,
@@ -97,7 +134,7 @@ fn yaml_as_meta_value(y: &Yaml) -> Option<MetaValue> {
     }
 }
 
-fn yaml_as_meta_block(y: &Yaml) -> Option<MetaBlock> {
+pub fn yaml_as_meta_block(y: &Yaml) -> Option<MetaBlock> {
     // Try to convert to a hash.
     match *y {
         Yaml::Hash(ref hsh) => {
@@ -110,6 +147,12 @@ fn yaml_as_meta_block(y: &Yaml) -> Option<MetaBlock> {
                 let maybe_val = yaml_as_meta_value(&val_y);
 
                 if let (Some(key), Some(val)) = (maybe_key, maybe_val) {
+                    // Reserved field names that taggu does not understand are skipped.
+                    if !is_allowed_user_key(&key) {
+                        warn!("skipping field with reserved name: '{}'", key);
+                        continue;
+                    }
+
                     mb.insert(key, val);
                 } else {
                     // TODO: Log that an unexpected value was found.
@@ -151,7 +194,8 @@ pub fn yaml_as_meta_block_map(y: &Yaml) -> Option<MetaBlockMap> {
             let mut item_map = MetaBlockMap::new();
 
             for (key_y, val_y) in hsh {
-                let maybe_key = yaml_as_string(&key_y);
+                // A null key gives the block for the working directory.
+                let maybe_key = yaml_as_meta_key(&key_y);
                 let maybe_val = yaml_as_meta_block(&val_y);
 
                 if let (Some(key), Some(val)) = (maybe_key, maybe_val) {
@@ -169,7 +213,7 @@ pub fn yaml_as_meta_block_map(y: &Yaml) -> Option<MetaBlockMap> {
 
 pub fn yaml_as_metadata(y: &Yaml, meta_target: &MetaTarget) -> Option<Metadata> {
     match *meta_target {
-        MetaTarget::Contains => {
+        MetaTarget::Contains | MetaTarget::Sidecar { .. } => {
             yaml_as_meta_block(y).map(|m| Metadata::Contains(m))
         },
         MetaTarget::Siblings => {
@@ -179,6 +223,40 @@ pub fn yaml_as_metadata(y: &Yaml, meta_target: &MetaTarget) -> Option<Metadata>
     }
 }
 
+pub fn meta_key_as_yaml(mk: &MetaKey) -> Yaml {
+    match *mk {
+        MetaKey::Nil => Yaml::Null,
+        MetaKey::Str(ref s) => Yaml::String(s.clone()),
+    }
+}
+
+pub fn meta_value_as_yaml(mv: &MetaValue) -> Yaml {
+    match *mv {
+        MetaValue::Nil => Yaml::Null,
+        MetaValue::Str(ref s) => Yaml::String(s.clone()),
+        MetaValue::Seq(ref mvs) => Yaml::Array(mvs.iter().map(meta_value_as_yaml).collect()),
+        MetaValue::Map(ref mvm) => {
+            let mut hsh = Hash::new();
+
+            for (mk, mv) in mvm {
+                hsh.insert(meta_key_as_yaml(mk), meta_value_as_yaml(mv));
+            }
+
+            Yaml::Hash(hsh)
+        },
+    }
+}
+
+pub fn meta_block_as_yaml(mb: &MetaBlock) -> Yaml {
+    let mut hsh = Hash::new();
+
+    for (field_name, mv) in mb {
+        hsh.insert(Yaml::String(field_name.clone()), meta_value_as_yaml(mv));
+    }
+
+    Yaml::Hash(hsh)
+}
+
 #[cfg(test)]
 mod tests {
     use metadata::{MetaBlock, MetaKey, MetaValue};
@@ -189,6 +267,7 @@ mod tests {
         yaml_as_meta_key,
         yaml_as_meta_value,
         yaml_as_meta_block,
+        meta_value_as_yaml,
     };
 
     #[test]
@@ -461,4 +540,23 @@ mod tests {
             assert_eq!(expected, produced);
         }
     }
+
+    #[test]
+    fn test_meta_value_as_yaml() {
+        let inputs = vec![
+            "~",
+            "foo",
+            "[foo, ~, [bar, baz]]",
+            "{foo: bar, ~: baz, nested: {key: [val]}}",
+        ];
+
+        for input in inputs {
+            let yaml = &YamlLoader::load_from_str(input).unwrap()[0];
+            let mv = yaml_as_meta_value(yaml).expect("Unable to convert YAML");
+
+            // Converting back and forth should be lossless.
+            let produced = yaml_as_meta_value(&meta_value_as_yaml(&mv));
+            assert_eq!(Some(mv), produced);
+        }
+    }
 }